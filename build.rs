@@ -45,6 +45,12 @@ fn main() {
     #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
     generate_protos();
 
+    // Binaries cannot be executed without a shim, so their tests are skipped on the nil backend.
+    println!("cargo:rerun-if-env-changed=ENARX_BACKEND");
+    if std::env::var("ENARX_BACKEND").as_deref() == Ok("nil") {
+        println!("cargo:rustc-cfg=enarx_backend_nil");
+    }
+
     #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
     if std::path::Path::new("/dev/sgx_enclave").exists()
         && std::fs::metadata("/dev/sgx_enclave")
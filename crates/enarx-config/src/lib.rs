@@ -8,7 +8,9 @@
 #![deny(clippy::all)]
 #![warn(rust_2018_idioms)]
 
-use std::{collections::HashMap, ops::Deref};
+use std::collections::{BTreeMap, HashMap};
+use std::net::Ipv6Addr;
+use std::ops::Deref;
 
 use serde::ser::SerializeStruct;
 use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
@@ -23,14 +25,77 @@ pub const CONFIG_TEMPLATE: &str = r#"## Configuration for a WASI application in
 #      "--argument2=foo"
 # ]
 
+## Function called repeatedly after `_initialize` for reactor modules, until it returns non-zero
+# handler = "handle"
+
 ## Steward
-# steward = "https://steward.example.com"
+# steward = "https://steward.example.com" # or a list of Stewards tried in order, e.g. ["https://a.example.com", "https://b.example.com"]
+# steward_retries = 2 # times all Stewards are tried again, if none of them is reachable
+# steward_backoff = 1 # seconds to wait before the first retry, doubled on every further retry
+# steward_required = true # whether startup fails, if no Steward issues a certificate, or the keep self-signs it instead
+# steward_handoff = false # whether the host may hand the certificate signing request off to be issued out of band
+# renewal_margin = 86400 # renew the certificate issued by the steward this many seconds before its expiry
+# steward_proxy = "http://proxy.example.com:3128" # HTTP proxy to reach the steward through
+# steward_hints = ["version", "technology", "config", "digest-algorithms"] # policy hints for the steward
+# secrets = "https://secrets.example.com/bundle" # secrets bundle fetched with the certificate issued by the steward
 
 ## Environment variables
+# env_host = ["FOO", "BAR_*"] # host environment variables passed to the application
 # [env]
 # VAR1 = "var1"
 # VAR2 = "var2"
 
+## Resource limits
+# [limits]
+# time = 3600         # maximum wall-clock execution time in seconds
+# cpu_time = 60       # maximum CPU time in seconds, time spent waiting does not count
+# memory = 1073741824 # maximum linear memory size in bytes
+# fuel = 10000000000  # maximum amount of fuel consumed by the application
+
+## Linear memory reservation, tuned for the backend by default
+# [memory]
+# static_maximum_size = 0                # maximum size of statically reserved memories in bytes
+# static_guard_size = 0                  # guard region after static memories in bytes
+# dynamic_guard_size = 0                 # guard region after dynamic memories in bytes
+# dynamic_reserved_for_growth = 16777216 # address space reserved for dynamic memory growth in bytes
+
+## Floating-point semantics
+# [float]
+# canonicalize_nans = true # replace NaN results with the canonical NaN for bit-identical results
+# simd = false             # reject modules using SIMD instructions
+
+## TLS policy of the `tls` and `wss` sockets
+# [tls]
+# versions = ["1.3"] # or versions = ["1.2", "1.3"]
+# cipher_suites = ["TLS13_AES_256_GCM_SHA384", "TLS13_AES_128_GCM_SHA256", "TLS13_CHACHA20_POLY1305_SHA256"]
+# kx_groups = ["X25519", "SECP384R1", "SECP256R1"]
+# client_auth = "none" # or client_auth = "optional" to verify certificates presented by clients
+
+## Hosts the application may send HTTPS requests to with the `wasi_experimental_http` interface
+# [network]
+# outgoing = ["api.example.com", "storage.example.com:8443"] # port 443 by default
+# allow_widening = true # allow the host to update the policy to allow more hosts at runtime
+
+## Entropy of the random number generator of the application
+# [entropy]
+# host = false # use the RDSEED and RDRAND instructions of the CPU only, never entropy provided by the host
+
+## Source of the system clock of the application, the clock of the host by default
+# [time]
+# source = "roughtime"                  # or source = "host", "monotonic", or "fixed" with `epoch` in seconds
+# server = "roughtime.example.com:2002" # Roughtime server authenticating the time
+# key = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=" # base64-encoded Ed25519 public key of the server
+
+## Status server answering health checks and reporting the identity of the keep over TLS
+# [status]
+# port = 8444
+# addr = "::" # address to bind to
+
+## Host directories preopened for development, only supported on the `nil` backend
+# [mount."/data"]
+# host = "/home/user/data" # directory of the host
+# writable = false         # read-only by default
+
 ## Pre-opened file descriptors
 [[files]]
 kind = "stdin"
@@ -41,12 +106,78 @@ kind = "stdout"
 [[files]]
 kind = "stderr"
 
+## Read-only directory of the other files of the package, list it right after the standard streams,
+## such that the C library of the workload finds it
+# [[files]]
+# kind = "package" # mounted at `/app` by default
+
+## Structured JSON log records of the written lines on the host
+# [[files]]
+# name = "LOG"
+# kind = "log"
+# target = "stderr" # or target = "stdout"
+
+## Event counters of the keep, readable at any time
+# [[files]]
+# kind = "stats"
+
+## Metrics of the keep in the Prometheus text format, readable at any time
+# [[files]]
+# kind = "metrics"
+
+## Fresh attestation evidence of the keep bound to the data written to it
+# [[files]]
+# kind = "attestation"
+
+## PEM-encoded certificate chain of the keep, leaf first, readable at any time
+# [[files]]
+# kind = "certs"
+
+## Keys derived for the label written to it, from the key of the keep or the sealing key of the platform
+# [[files]]
+# kind = "keys"
+# source = "keep" # or source = "platform"
+# labels = ["db", "backup/*"] # labels keys may be derived for, any by default
+# max_uses = 16 # maximum number of keys derived, unlimited by default
+
+## Random bytes of the random number generator seeded according to `entropy`
+# [[files]]
+# kind = "random" # named `/dev/urandom` by default
+
+## A secret of the bundle fetched from `secrets`, readable at any time
+# [[files]]
+# name = "/secrets/db"
+# kind = "secret"
+# secret = "db" # the name after `/secrets/` by default
+
+## Readiness and liveness of the workload reported to the host, set by writing to them and cleared by writing `0`
+# [[files]]
+# kind = "ready"
+# [[files]]
+# kind = "healthy"
+
+## Hash-chained audit trail of the written lines on the host, anchored with a signature of the keep
+# [[files]]
+# kind = "audit"
+# target = "stderr"     # or target = "stdout"
+# anchor_interval = 64  # number of entries after which the chain is anchored
+
 ## A listen socket
 # [[files]]
 # name = "LISTEN"
 # kind = "listen"
-# prot = "tls" # or prot = "tcp"
+# prot = "tls" # or prot = "tcp", prot = "wss" or prot = "https"
 # port = 12345
+# addr = "::"          # address to bind to
+# backlog = 128        # maximum length of the queue of pending connections
+# reuseaddr = true     # allow rebinding the address while closed connections linger
+# only_v6 = true       # accept only IPv6 connections on an IPv6 address
+# max_connections = 64 # maximum number of concurrently open connections
+# handshake_timeout = 10 # seconds, within which TLS and WebSocket handshakes must complete
+# idle_timeout = 300   # seconds, after which blocking reads and writes of connections fail
+# keepalive = 60       # seconds of idleness, after which TCP keepalive probes are sent
+# alpn = ["http/1.1"]  # application-layer protocols offered via ALPN
+# sni = "example.com"  # the only server name accepted for TLS connections
 
 ## An outgoing connected socket
 # [[files]]
@@ -55,6 +186,40 @@ kind = "stderr"
 # prot = "tls" # or prot = "tcp"
 # host = "127.0.0.1"
 # port = 23456
+# connect_timeout = 5 # seconds, within which connecting and the handshakes must complete
+# idle_timeout = 300  # seconds, after which blocking reads and writes fail
+# keepalive = 60      # seconds of idleness, after which TCP keepalive probes are sent
+# max_bytes = 1048576 # maximum number of bytes sent and received
+# proxy = "socks5://proxy.example.com:1080" # or proxy = "http://proxy.example.com:3128"
+
+## The TLS session of the connection accepted on `LISTEN` last, readable at any time
+# [[files]]
+# name = "LISTEN_SESSION"
+# kind = "session"
+# socket = "LISTEN"
+
+## An in-keep channel to the `api` service
+# [[files]]
+# name = "API"
+# kind = "channel"
+# peer = "api"
+
+## A service running next to `main.wasm` in the same keep
+# [services.api]
+# module = "api.wasm" # Wasm module in the root of the package
+# handler = "handle"  # function called repeatedly, if the module is a reactor
+# args = ["--verbose"]
+#
+# [services.api.env]
+# VAR1 = "var1"
+#
+# [[services.api.files]]
+# kind = "stderr"
+#
+# [[services.api.files]]
+# name = "MAIN"
+# kind = "channel"
+# peer = "main"
 "#;
 
 const fn default_port() -> u16 {
@@ -109,6 +274,93 @@ impl<'de> Deserialize<'de> for FileName {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+/// Pattern matching the names of host environment variables
+///
+/// A pattern is either a variable name, which matches exactly,
+/// or a prefix followed by a single trailing `*`, which matches all variables starting with it.
+/// A prefix must end with `_` following at least [`MIN_ENV_PREFIX`] characters, e.g. `APP_*`,
+/// such that a pattern cannot match large parts of the environment of the host, like `A*`.
+pub struct EnvPattern(String);
+
+/// Minimum length of the prefix of an [`EnvPattern`] before its trailing `_`
+pub const MIN_ENV_PREFIX: usize = 2;
+
+impl EnvPattern {
+    /// Returns whether the environment variable `name` matches the pattern.
+    pub fn matches(&self, name: &str) -> bool {
+        match self.0.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == self.0,
+        }
+    }
+}
+
+impl From<&str> for EnvPattern {
+    fn from(value: &str) -> Self {
+        Self(value.into())
+    }
+}
+
+impl Deref for EnvPattern {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<'de> Deserialize<'de> for EnvPattern {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let pattern = String::deserialize(deserializer)?;
+
+        let name = pattern.strip_suffix('*').unwrap_or(&pattern);
+        if name.is_empty() || name.contains(['*', '=', '\0']) {
+            return Err(D::Error::custom(format!(
+                "invalid `env_host` pattern `{pattern}`"
+            )));
+        }
+        if name.len() < pattern.len() {
+            match name.strip_suffix('_') {
+                Some(prefix) if prefix.len() >= MIN_ENV_PREFIX => {}
+                _ => {
+                    return Err(D::Error::custom(format!(
+                        "invalid `env_host` pattern `{pattern}`, a prefix must end with `_` following at least {MIN_ENV_PREFIX} characters"
+                    )))
+                }
+            }
+        }
+
+        Ok(Self(pattern))
+    }
+}
+
+/// Deserializes a single URL or a list of URLs.
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<Url>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(Url),
+        Many(Vec<Url>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer) {
+        Ok(OneOrMany::One(url)) => vec![url],
+        Ok(OneOrMany::Many(urls)) => urls,
+        Err(_) => {
+            return Err(D::Error::custom(
+                "`steward` must be a URL or a list of URLs",
+            ))
+        }
+    })
+}
+
 /// The configuration for an Enarx WASI application
 ///
 /// This struct can be used with any serde deserializer.
@@ -134,17 +386,118 @@ pub struct Config {
     #[serde(default)]
     pub env: HashMap<String, String>,
 
+    /// The host environment variables to provide to the application
+    ///
+    /// These take precedence over the variables in `env`.
+    #[serde(default)]
+    pub env_host: Vec<EnvPattern>,
+
     /// The arguments to provide to the application
     #[serde(default)]
     pub args: Vec<String>,
 
+    /// The function exported by a reactor module, which is called until it returns a non-zero value
+    ///
+    /// Reactor modules export `_initialize` instead of `_start`, which is called once before the
+    /// handler, such that the module can handle one event per call.
+    #[serde(default)]
+    pub handler: Option<String>,
+
     /// The array of pre-opened file descriptors
     #[serde(default)]
     pub files: Vec<File>,
 
-    /// An optional Steward URL
+    /// The URLs of the Stewards, which are tried in order until one issues a certificate
+    ///
+    /// A single URL can be specified as a string. The keep self-signs its certificate, if empty.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub steward: Vec<Url>,
+
+    /// Number of times all Stewards are tried again, if none of them could be reached, 0 by default
+    #[serde(default)]
+    pub steward_retries: Option<u32>,
+
+    /// Time in seconds to wait before the first retry, which is doubled on every further retry,
+    /// 1 by default
+    #[serde(default)]
+    pub steward_backoff: Option<u64>,
+
+    /// Whether the keep fails to start, if no Steward issues a certificate, `true` by default
+    ///
+    /// If `false`, the keep self-signs its certificate instead, unless a Steward rejected the
+    /// certificate signing request.
+    #[serde(default)]
+    pub steward_required: Option<bool>,
+
+    /// Whether the host may hand the certificate signing request off to be issued out of band,
+    /// instead of it being sent to the Stewards, `false` by default
+    #[serde(default)]
+    pub steward_handoff: Option<bool>,
+
+    /// Time in seconds before the expiry of the certificate issued by the Steward, at which it is renewed
+    #[serde(default)]
+    pub renewal_margin: Option<u64>,
+
+    /// An optional `http://` URL of the proxy to send the requests to the Steward through
+    #[serde(default)]
+    pub steward_proxy: Option<Url>,
+
+    /// The policy hints requested from the Steward in addition to the attestation report
+    ///
+    /// None by default, since older Stewards may reject certificate signing requests with unknown
+    /// extensions.
+    #[serde(default)]
+    pub steward_hints: Vec<StewardHint>,
+
+    /// An optional `https://` URL of the secrets bundle of the application
+    ///
+    /// The bundle is fetched after attestation with the certificate issued by the Steward as the
+    /// TLS client certificate, and its secrets are exposed to the application by `secret` files.
+    #[serde(default)]
+    pub secrets: Option<Url>,
+
+    /// The resource limits imposed on the application
+    #[serde(default)]
+    pub limits: Limits,
+
+    /// The linear memory reservation strategy overrides
+    #[serde(default)]
+    pub memory: Memory,
+
+    /// The floating-point semantics of the application
+    #[serde(default)]
+    pub float: Float,
+
+    /// The TLS policy of the `tls` and `wss` sockets
+    #[serde(default)]
+    pub tls: Tls,
+
+    /// The network policy of the HTTP client of the application
+    #[serde(default)]
+    pub network: Network,
+
+    /// The entropy sources of the random number generator of the application
+    #[serde(default)]
+    pub entropy: Entropy,
+
+    /// The source of the system clock of the application
+    #[serde(default)]
+    pub time: Time,
+
+    /// The status server of the keep, if any
+    #[serde(default)]
+    pub status: Option<Status>,
+
+    /// The directories of the host preopened at the paths of the application they are keyed by
+    ///
+    /// Mounted directories are not protected by the keep, so they are only supported on the `nil`
+    /// backend for development.
+    #[serde(default)]
+    pub mount: BTreeMap<String, Mount>,
+
+    /// The services running next to the main module in the same keep
     #[serde(default)]
-    pub steward: Option<Url>,
+    pub services: BTreeMap<String, Service>,
 }
 
 // TOML requires the `Vec`s to be serialized last, so manually implement `Serialize`
@@ -153,19 +506,88 @@ impl Serialize for Config {
     where
         S: Serializer,
     {
-        let mut s = serializer.serialize_struct("Config", 4)?;
+        let mut s = serializer.serialize_struct("Config", 24)?;
         if !self.args.is_empty() {
             s.serialize_field("args", &self.args).unwrap();
         }
-        if self.steward.is_some() {
-            s.serialize_field("steward", &self.steward).unwrap();
+        if self.handler.is_some() {
+            s.serialize_field("handler", &self.handler).unwrap();
+        }
+        if !self.env_host.is_empty() {
+            s.serialize_field("env_host", &self.env_host).unwrap();
+        }
+        match self.steward.as_slice() {
+            [] => {}
+            [url] => s.serialize_field("steward", url).unwrap(),
+            urls => s.serialize_field("steward", urls).unwrap(),
+        }
+        if self.steward_retries.is_some() {
+            s.serialize_field("steward_retries", &self.steward_retries)
+                .unwrap();
+        }
+        if self.steward_backoff.is_some() {
+            s.serialize_field("steward_backoff", &self.steward_backoff)
+                .unwrap();
+        }
+        if self.steward_required.is_some() {
+            s.serialize_field("steward_required", &self.steward_required)
+                .unwrap();
+        }
+        if self.steward_handoff.is_some() {
+            s.serialize_field("steward_handoff", &self.steward_handoff)
+                .unwrap();
+        }
+        if self.renewal_margin.is_some() {
+            s.serialize_field("renewal_margin", &self.renewal_margin)
+                .unwrap();
+        }
+        if self.steward_proxy.is_some() {
+            s.serialize_field("steward_proxy", &self.steward_proxy)
+                .unwrap();
+        }
+        if !self.steward_hints.is_empty() {
+            s.serialize_field("steward_hints", &self.steward_hints)
+                .unwrap();
+        }
+        if self.secrets.is_some() {
+            s.serialize_field("secrets", &self.secrets).unwrap();
         }
         if !self.env.is_empty() {
             s.serialize_field("env", &self.env).unwrap();
         }
+        if self.limits != Limits::default() {
+            s.serialize_field("limits", &self.limits).unwrap();
+        }
+        if self.memory != Memory::default() {
+            s.serialize_field("memory", &self.memory).unwrap();
+        }
+        if self.float != Float::default() {
+            s.serialize_field("float", &self.float).unwrap();
+        }
+        if self.tls != Tls::default() {
+            s.serialize_field("tls", &self.tls).unwrap();
+        }
+        if self.network != Network::default() {
+            s.serialize_field("network", &self.network).unwrap();
+        }
+        if self.entropy != Entropy::default() {
+            s.serialize_field("entropy", &self.entropy).unwrap();
+        }
+        if self.time != Time::default() {
+            s.serialize_field("time", &self.time).unwrap();
+        }
+        if self.status.is_some() {
+            s.serialize_field("status", &self.status).unwrap();
+        }
+        if !self.mount.is_empty() {
+            s.serialize_field("mount", &self.mount).unwrap();
+        }
         if !self.files.is_empty() {
             s.serialize_field("files", &self.files).unwrap();
         }
+        if !self.services.is_empty() {
+            s.serialize_field("services", &self.services).unwrap();
+        }
         s.end()
     }
 }
@@ -180,196 +602,2162 @@ impl Default for Config {
 
         Self {
             env: HashMap::new(),
+            env_host: vec![],
             args: vec![],
+            handler: None,
             files,
-            steward: None, // TODO: Default to a deployed Steward instance
+            steward: vec![], // TODO: Default to a deployed Steward instance
+            steward_retries: None,
+            steward_backoff: None,
+            steward_required: None,
+            steward_handoff: None,
+            renewal_margin: None,
+            steward_proxy: None,
+            steward_hints: vec![],
+            secrets: None,
+            limits: Limits::default(),
+            memory: Memory::default(),
+            float: Float::default(),
+            tls: Tls::default(),
+            network: Network::default(),
+            entropy: Entropy::default(),
+            time: Time::default(),
+            status: None,
+            mount: BTreeMap::new(),
+            services: BTreeMap::new(),
         }
     }
 }
 
-/// Parameters for a pre-opened file descriptor
+/// Name of the service running the `main.wasm` module of the package
+pub const MAIN_SERVICE: &str = "main";
+
+/// A service running next to the main module in the same keep
+///
+/// Every service has its own WASI context, i.e. its own arguments, environment
+/// variables and pre-opened file descriptors, and communicates with the other
+/// services of the keep over `kind = "channel"` file descriptors.
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
-#[serde(tag = "kind")]
-pub enum File {
-    /// File descriptor of `/dev/null`
-    #[serde(rename = "null")]
-    Null {
-        /// Name assigned to the file descriptor
-        name: Option<FileName>,
-    },
+#[serde(deny_unknown_fields)]
+pub struct Service {
+    /// The Wasm module of the service in the root of the package
+    pub module: String,
 
-    /// File descriptor of stdin
-    #[serde(rename = "stdin")]
-    Stdin {
-        /// Name assigned to the file descriptor
-        name: Option<FileName>,
-    },
+    /// The function exported by the reactor module of the service, which is called until it
+    /// returns a non-zero value
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handler: Option<String>,
 
-    /// File descriptor of stdout
-    #[serde(rename = "stdout")]
-    Stdout {
-        /// Name assigned to the file descriptor
-        name: Option<FileName>,
-    },
+    /// The arguments to provide to the service
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
 
-    /// File descriptor of stderr
-    #[serde(rename = "stderr")]
-    Stderr {
-        /// Name assigned to the file descriptor
-        name: Option<FileName>,
-    },
+    /// The environment variables to provide to the service
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
 
-    /// File descriptor of a TCP listen socket
-    #[serde(rename = "listen")]
-    Listen {
-        /// Name assigned to the file descriptor
-        name: FileName,
+    /// The array of pre-opened file descriptors of the service
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<File>,
+}
 
-        /// Address to listen on
-        #[serde(default = "default_addr")]
-        addr: String,
+/// Resource limits imposed on the application
+///
+/// Every limit is optional, an unset limit is not enforced.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    /// Maximum wall-clock execution time in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<u64>,
 
-        /// Port to listen on
-        #[serde(default = "default_port")]
-        port: u16,
+    /// Maximum CPU time in seconds
+    ///
+    /// Only the time the keep spends running counts, unlike for `time`, so a workload waiting
+    /// for I/O or sleeping is not stopped, while one spinning is stopped after the same amount
+    /// of work on every backend.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_time: Option<u64>,
 
-        /// Protocol to use
-        #[serde(default)]
-        prot: Protocol,
-    },
+    /// Maximum linear memory size in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<u64>,
 
-    /// File descriptor of a TCP stream socket
-    #[serde(rename = "connect")]
-    Connect {
-        /// Name assigned to the file descriptor
-        name: Option<FileName>,
+    /// Maximum amount of fuel the application may consume
+    ///
+    /// Every executed WebAssembly instruction consumes roughly one unit of fuel.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fuel: Option<u64>,
+}
 
-        /// Host address to connect to
-        host: String,
+/// Linear memory reservation strategy of the application
+///
+/// Every setting is optional, an unset setting is tuned for the backend the application runs on.
+/// See the `wasmtime::Config` methods of the same names for details.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Memory {
+    /// Maximum size in bytes of linear memories, which are reserved statically
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub static_maximum_size: Option<u64>,
 
-        /// Port to connect to
-        #[serde(default = "default_port")]
-        port: u16,
+    /// Size in bytes of the guard region following static linear memories
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub static_guard_size: Option<u64>,
 
-        /// Protocol to use
-        #[serde(default)]
-        prot: Protocol,
-    },
+    /// Size in bytes of the guard region following dynamic linear memories
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dynamic_guard_size: Option<u64>,
+
+    /// Size in bytes of the address space reserved for growth of dynamic linear memories
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dynamic_reserved_for_growth: Option<u64>,
 }
 
-impl File {
-    /// Get the name for a file descriptor
-    pub fn name(&self) -> &str {
-        match self {
-            Self::Null { name } => name.as_deref().unwrap_or("null"),
-            Self::Stdin { name } => name.as_deref().unwrap_or("stdin"),
-            Self::Stdout { name } => name.as_deref().unwrap_or("stdout"),
-            Self::Stderr { name } => name.as_deref().unwrap_or("stderr"),
-            Self::Listen { name, .. } => name,
-            Self::Connect { name, host, .. } => name.as_deref().unwrap_or(host),
-        }
-    }
+/// Floating-point semantics of the application
+///
+/// WebAssembly leaves only the bit patterns of NaN results and the relaxed SIMD instructions
+/// nondeterministic, every other floating-point result is defined by IEEE 754 and identical on all
+/// backends, in particular subnormal numbers are never flushed to zero.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Float {
+    /// Whether every NaN result is replaced with the canonical NaN, `false` by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canonicalize_nans: Option<bool>,
+
+    /// Whether SIMD instructions are accepted, `true` by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub simd: Option<bool>,
 }
 
-/// Protocol to use for a connection
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
-pub enum Protocol {
-    /// Transparently wrap the TCP connection with the TLS protocol
-    #[serde(rename = "tls")]
-    Tls,
+/// Network policy of the HTTP client of the application
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Network {
+    /// Hosts the application may send HTTPS requests to, optionally followed by `:` and the port,
+    /// which is 443 by default, or `*` for any host. IPv6 addresses are enclosed in brackets as in
+    /// URLs, e.g. `[2001:db8::1]:8443`.
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        deserialize_with = "outgoing"
+    )]
+    pub outgoing: Vec<String>,
 
-    /// Normal TCP connection
-    #[serde(rename = "tcp")]
-    Tcp,
+    /// Whether the host may update the policy at runtime to allow more than this one, `false` by
+    /// default
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allow_widening: bool,
 }
 
-impl Default for Protocol {
-    fn default() -> Self {
-        Self::Tls
+/// Parses an entry of [`Network::outgoing`] into the host and the port, if valid.
+///
+/// The brackets of an IPv6 address are removed and a bare IPv6 address is invalid, since its last
+/// group cannot be told apart from a port.
+fn destination(entry: &str) -> (&str, Option<u16>) {
+    let (host, port) = match entry.strip_prefix('[') {
+        Some(rest) => match rest.split_once(']') {
+            Some((addr, port)) if addr.parse::<Ipv6Addr>().is_ok() => (addr, port),
+            _ => return (entry, None),
+        },
+        None => entry.split_at(entry.find(':').unwrap_or(entry.len())),
+    };
+    match port {
+        "" => (host, Some(443)),
+        port => (
+            host,
+            port.strip_prefix(':').and_then(|port| port.parse().ok()),
+        ),
     }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+/// Returns whether `host` is the same as `allowed`, ignoring the case of names and the notation of
+/// IPv6 addresses.
+fn same_host(allowed: &str, host: &str) -> bool {
+    let host = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+    match (allowed.parse::<Ipv6Addr>(), host.parse::<Ipv6Addr>()) {
+        (Ok(allowed), Ok(host)) => allowed == host,
+        _ => allowed.eq_ignore_ascii_case(host),
+    }
+}
 
-    const CONFIG: &str = r#"
-        [[files]]
-        kind = "stdin"
+/// Deserializes [`Network::outgoing`], rejecting invalid entries.
+fn outgoing<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let outgoing = Vec::<String>::deserialize(deserializer)?;
+    for entry in &outgoing {
+        if entry != "*" && destination(entry).1.is_none() {
+            return Err(D::Error::custom(format!(
+                "invalid value for `outgoing` {entry:?}, expected a host, optionally followed by `:` and the port, with IPv6 addresses in brackets"
+            )));
+        }
+    }
+    Ok(outgoing)
+}
 
-        [[files]]
-        name = "X"
-        kind = "listen"
-        prot = "tcp"
-        port = 9000
+impl Network {
+    /// Returns whether the application may send requests to `port` of `host`.
+    pub fn allows(&self, host: &str, port: u16) -> bool {
+        self.outgoing.iter().any(|allowed| {
+            let (allowed_host, allowed_port) = destination(allowed);
+            allowed == "*" || (same_host(allowed_host, host) && allowed_port == Some(port))
+        })
+    }
 
-        [[files]]
-        kind = "stdout"
+    /// Returns whether the policy allows all requests allowed by `other`, i.e. replacing it by
+    /// `other` does not widen it.
+    pub fn includes(&self, other: &Network) -> bool {
+        other.outgoing.iter().all(|entry| match destination(entry) {
+            _ if entry == "*" => self.outgoing.iter().any(|allowed| allowed == "*"),
+            (host, Some(port)) => self.allows(host, port),
+            // An invalid entry allows nothing.
+            (_, None) => true,
+        })
+    }
+}
 
-        [[files]]
-        kind = "null"
+/// Status server of the keep
+///
+/// The server is run by the keep next to the application and answers health checks and requests
+/// for the identity of the keep over TLS with the certificate of the keep, such that load
+/// balancers can probe the keep without the cooperation of the application.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Status {
+    /// Address to bind to, `::` by default
+    #[serde(default = "default_addr")]
+    pub addr: String,
 
-        [[files]]
-        kind = "stderr"
+    /// Port to listen on
+    pub port: u16,
+}
 
+/// Entropy sources of the random number generator of the application
+///
+/// The generator is always seeded with the RDSEED and RDRAND instructions of the CPU, if
+/// available, which are executed inside of the keep.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Entropy {
+    /// Whether entropy provided by the host is mixed in, `true` by default
+    ///
+    /// The `nil` backend obtains it from the operating system of the host, while the shims of
+    /// the other backends never forward requests for entropy to the host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<bool>,
+}
+
+/// Source of the system clock of the application
+///
+/// The clock of the host is controlled by the host, so applications relying on the current time,
+/// e.g. to validate certificates or tokens, can choose a source they trust instead. Every source
+/// but `host` advances with the monotonic clock of the keep from the time it starts at.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "source", deny_unknown_fields)]
+pub enum Time {
+    /// The clock of the host
+    #[serde(rename = "host")]
+    Host,
+
+    /// A clock starting at the Unix epoch, which only measures the time since the start
+    #[serde(rename = "monotonic")]
+    Monotonic,
+
+    /// A clock starting at a fixed time
+    #[serde(rename = "fixed")]
+    Fixed {
+        /// Time the clock starts at in seconds since the Unix epoch
+        epoch: u64,
+    },
+
+    /// A clock starting at the time authenticated by a Roughtime server
+    #[serde(rename = "roughtime")]
+    Roughtime {
+        /// Address of the Roughtime server, e.g. `roughtime.example.com:2002`
+        server: String,
+
+        /// Base64-encoded Ed25519 public key of the Roughtime server
+        key: String,
+    },
+}
+
+impl Default for Time {
+    fn default() -> Self {
+        Self::Host
+    }
+}
+
+impl Time {
+    /// Returns the name of the source.
+    pub fn source(&self) -> &'static str {
+        match self {
+            Self::Host => "host",
+            Self::Monotonic => "monotonic",
+            Self::Fixed { .. } => "fixed",
+            Self::Roughtime { .. } => "roughtime",
+        }
+    }
+}
+
+/// Directory of the host preopened for the application
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mount {
+    /// Path of the directory on the host
+    pub host: String,
+
+    /// Whether the application may modify the directory, `false` by default
+    #[serde(default)]
+    pub writable: bool,
+}
+
+/// Policy hint requested from the Steward as an extension of the certificate signing request
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StewardHint {
+    /// Version of Enarx running the keep
+    #[serde(rename = "version")]
+    Version,
+
+    /// Backend technology of the keep
+    #[serde(rename = "technology")]
+    Technology,
+
+    /// SHA-256 digest of `Enarx.toml`
+    #[serde(rename = "config")]
+    Config,
+
+    /// Digest algorithms the keep computes the digests of the workload with
+    #[serde(rename = "digest-algorithms")]
+    DigestAlgorithms,
+}
+
+/// TLS protocol version
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TlsVersion {
+    /// TLS 1.2
+    #[serde(rename = "1.2")]
+    Tls12,
+
+    /// TLS 1.3
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+/// TLS cipher suite, named as in the IANA registry
+#[allow(non_camel_case_types, missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CipherSuite {
+    TLS13_AES_256_GCM_SHA384,
+    TLS13_AES_128_GCM_SHA256,
+    TLS13_CHACHA20_POLY1305_SHA256,
+    TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+    TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+    TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+    TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+    TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+    TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+}
+
+impl CipherSuite {
+    /// Returns the TLS protocol version the cipher suite is used with.
+    pub fn version(&self) -> TlsVersion {
+        match self {
+            Self::TLS13_AES_256_GCM_SHA384
+            | Self::TLS13_AES_128_GCM_SHA256
+            | Self::TLS13_CHACHA20_POLY1305_SHA256 => TlsVersion::Tls13,
+            _ => TlsVersion::Tls12,
+        }
+    }
+}
+
+/// TLS key exchange group
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KxGroup {
+    X25519,
+    SECP384R1,
+    SECP256R1,
+}
+
+/// Authentication of the clients of TLS connections accepted by the keep
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ClientAuth {
+    /// Clients are not asked for certificates
+    #[serde(rename = "none")]
+    None,
+
+    /// Clients are asked for certificates, which are verified, if presented, but clients without
+    /// one are accepted as well
+    #[serde(rename = "optional")]
+    Optional,
+}
+
+impl Default for ClientAuth {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+/// TLS policy of the application
+///
+/// Every setting is optional, an unset setting uses the Enarx defaults.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Tls {
+    /// Allowed TLS protocol versions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub versions: Option<Vec<TlsVersion>>,
+
+    /// Allowed cipher suites in order of preference
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cipher_suites: Option<Vec<CipherSuite>>,
+
+    /// Allowed key exchange groups in order of preference
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kx_groups: Option<Vec<KxGroup>>,
+
+    /// Authentication of the clients of accepted connections
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_auth: Option<ClientAuth>,
+}
+
+impl Tls {
+    /// Default TLS protocol versions
+    pub const DEFAULT_VERSIONS: &'static [TlsVersion] = &[TlsVersion::Tls13];
+
+    /// Default cipher suites
+    pub const DEFAULT_CIPHER_SUITES: &'static [CipherSuite] = &[
+        CipherSuite::TLS13_AES_256_GCM_SHA384,
+        CipherSuite::TLS13_AES_128_GCM_SHA256,
+        CipherSuite::TLS13_CHACHA20_POLY1305_SHA256,
+    ];
+
+    /// Default key exchange groups
+    pub const DEFAULT_KX_GROUPS: &'static [KxGroup] =
+        &[KxGroup::X25519, KxGroup::SECP384R1, KxGroup::SECP256R1];
+
+    /// Returns the allowed TLS protocol versions.
+    pub fn versions(&self) -> &[TlsVersion] {
+        self.versions.as_deref().unwrap_or(Self::DEFAULT_VERSIONS)
+    }
+
+    /// Returns the allowed cipher suites, which are used with one of the allowed protocol versions.
+    pub fn cipher_suites(&self) -> Vec<CipherSuite> {
+        self.cipher_suites
+            .as_deref()
+            .unwrap_or(Self::DEFAULT_CIPHER_SUITES)
+            .iter()
+            .filter(|suite| self.versions().contains(&suite.version()))
+            .copied()
+            .collect()
+    }
+
+    /// Returns the allowed key exchange groups.
+    pub fn kx_groups(&self) -> &[KxGroup] {
+        self.kx_groups.as_deref().unwrap_or(Self::DEFAULT_KX_GROUPS)
+    }
+
+    /// Returns the authentication of the clients of accepted connections.
+    pub fn client_auth(&self) -> ClientAuth {
+        self.client_auth.unwrap_or_default()
+    }
+
+    /// Checks that the policy allows establishing a connection with every allowed protocol version.
+    fn validate(&self) -> Result<(), String> {
+        if self.versions().is_empty() {
+            return Err("`tls.versions` must not be empty".into());
+        }
+        if self.kx_groups().is_empty() {
+            return Err("`tls.kx_groups` must not be empty".into());
+        }
+        let suites = self.cipher_suites();
+        for version in self.versions() {
+            if !suites.iter().any(|suite| suite.version() == *version) {
+                return Err(format!(
+                    "`tls.cipher_suites` contains no cipher suite for TLS version {}",
+                    match version {
+                        TlsVersion::Tls12 => "1.2",
+                        TlsVersion::Tls13 => "1.3",
+                    }
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'de> Deserialize<'de> for Tls {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Raw {
+            #[serde(default)]
+            versions: Option<Vec<TlsVersion>>,
+            #[serde(default)]
+            cipher_suites: Option<Vec<CipherSuite>>,
+            #[serde(default)]
+            kx_groups: Option<Vec<KxGroup>>,
+            #[serde(default)]
+            client_auth: Option<ClientAuth>,
+        }
+
+        let Raw {
+            versions,
+            cipher_suites,
+            kx_groups,
+            client_auth,
+        } = Raw::deserialize(deserializer)?;
+        let tls = Self {
+            versions,
+            cipher_suites,
+            kx_groups,
+            client_auth,
+        };
+        tls.validate().map_err(D::Error::custom)?;
+        Ok(tls)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+/// Application-layer protocol negotiated via ALPN
+///
+/// A protocol name must be between 1 and 255 bytes long.
+pub struct AlpnProtocol(String);
+
+impl From<&str> for AlpnProtocol {
+    fn from(value: &str) -> Self {
+        Self(value.into())
+    }
+}
+
+impl Deref for AlpnProtocol {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<'de> Deserialize<'de> for AlpnProtocol {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let protocol = String::deserialize(deserializer)?;
+
+        if protocol.is_empty() || protocol.len() > 255 {
+            return Err(D::Error::custom(format!(
+                "invalid ALPN protocol `{protocol}` must be between 1 and 255 bytes long"
+            )));
+        }
+
+        Ok(Self(protocol))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+/// DNS name of a server as sent via SNI
+///
+/// The name is stored in lowercase, since DNS names are case-insensitive.
+pub struct ServerName(String);
+
+impl From<&str> for ServerName {
+    fn from(value: &str) -> Self {
+        Self(value.to_ascii_lowercase())
+    }
+}
+
+impl Deref for ServerName {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<'de> Deserialize<'de> for ServerName {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let name = String::deserialize(deserializer)?;
+
+        let valid_label = |label: &str| {
+            (1..=63).contains(&label.len())
+                && label
+                    .bytes()
+                    .all(|c| c.is_ascii_alphanumeric() || c == b'-')
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+        };
+        // SNI carries no IP addresses, so a name must not end with a numeric label.
+        let numeric = |label: &str| label.bytes().all(|c| c.is_ascii_digit());
+        if name.len() > 253
+            || !name.split('.').all(valid_label)
+            || name.rsplit('.').next().map_or(true, numeric)
+        {
+            return Err(D::Error::custom(format!(
+                "invalid server name `{name}` must be a DNS name"
+            )));
+        }
+
+        Ok(name.as_str().into())
+    }
+}
+
+/// Parameters for a pre-opened file descriptor
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum File {
+    /// File descriptor of `/dev/null`
+    #[serde(rename = "null")]
+    Null {
+        /// Name assigned to the file descriptor
+        name: Option<FileName>,
+    },
+
+    /// File descriptor of stdin
+    #[serde(rename = "stdin")]
+    Stdin {
+        /// Name assigned to the file descriptor
+        name: Option<FileName>,
+    },
+
+    /// File descriptor of stdout
+    #[serde(rename = "stdout")]
+    Stdout {
+        /// Name assigned to the file descriptor
+        name: Option<FileName>,
+    },
+
+    /// File descriptor of stderr
+    #[serde(rename = "stderr")]
+    Stderr {
+        /// Name assigned to the file descriptor
+        name: Option<FileName>,
+    },
+
+    /// File descriptor, which emits every written line as a structured JSON log record on the host
+    #[serde(rename = "log")]
+    Log {
+        /// Name assigned to the file descriptor, also used as the stream name of the log records
+        name: Option<FileName>,
+
+        /// Host stream to emit the log records to
+        #[serde(default)]
+        target: LogTarget,
+
+        /// Absolute path of the file of the host to append the log records to, if `target` is
+        /// `file`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+    },
+
+    /// File descriptor, every read of which yields the event counters of the keep since its start
+    #[serde(rename = "stats")]
+    Stats {
+        /// Name assigned to the file descriptor
+        name: Option<FileName>,
+    },
+
+    /// File descriptor, every read of which yields the metrics of the keep in the Prometheus text format
+    #[serde(rename = "metrics")]
+    Metrics {
+        /// Name assigned to the file descriptor
+        name: Option<FileName>,
+    },
+
+    /// File descriptor, which yields fresh attestation evidence of the keep bound to the data written to it
+    #[serde(rename = "attestation")]
+    Attestation {
+        /// Name assigned to the file descriptor
+        name: Option<FileName>,
+    },
+
+    /// Read-only file descriptor, every read of which yields the current certificate chain of the
+    /// keep, PEM-encoded and leaf first
+    #[serde(rename = "certs")]
+    Certs {
+        /// Name assigned to the file descriptor
+        name: Option<FileName>,
+    },
+
+    /// File descriptor, which yields a 32-byte key derived from a secret of the keep for the
+    /// label written to it
+    #[serde(rename = "keys")]
+    Keys {
+        /// Name assigned to the file descriptor
+        name: Option<FileName>,
+
+        /// Secret the keys are derived from
+        #[serde(default)]
+        source: KeySource,
+
+        /// Labels, for which keys may be derived, any if empty
+        ///
+        /// A label ending with `*` allows all labels starting with the prefix before it.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        labels: Vec<String>,
+
+        /// Maximum number of keys derived, unlimited if unset
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_uses: Option<u64>,
+    },
+
+    /// Read-only file descriptor yielding random bytes of a generator seeded according to the
+    /// `entropy` section
+    #[serde(rename = "random")]
+    Random {
+        /// Name assigned to the file descriptor
+        name: Option<FileName>,
+    },
+
+    /// Read-only file descriptor, every read of which yields a secret of the bundle fetched from
+    /// the `secrets` URL
+    #[serde(rename = "secret")]
+    Secret {
+        /// Name assigned to the file descriptor
+        name: FileName,
+
+        /// Name of the secret in the bundle, the name of the file descriptor without the
+        /// `/secrets/` prefix by default
+        secret: Option<String>,
+    },
+
+    /// Read-only directory of the files of the package other than its modules and config
+    #[serde(rename = "package")]
+    Package {
+        /// Name assigned to the file descriptor, which is the path the directory is mounted at
+        name: Option<FileName>,
+    },
+
+    /// Write-only file descriptor, which the workload signals its readiness to the host with
+    #[serde(rename = "ready")]
+    Ready {
+        /// Name assigned to the file descriptor
+        name: Option<FileName>,
+    },
+
+    /// Write-only file descriptor, which the workload signals its liveness to the host with
+    #[serde(rename = "healthy")]
+    Healthy {
+        /// Name assigned to the file descriptor
+        name: Option<FileName>,
+    },
+
+    /// Append-only file descriptor, which hash-chains every written line and periodically anchors
+    /// the chain with a signature of the keep
+    #[serde(rename = "audit")]
+    Audit {
+        /// Name assigned to the file descriptor, also used as the stream name of the records
+        name: Option<FileName>,
+
+        /// Host stream to emit the records to
+        #[serde(default)]
+        target: LogTarget,
+
+        /// Absolute path of the file of the host to append the records to, if `target` is `file`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+
+        /// Number of entries after which the chain is anchored, 64 if not specified
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        anchor_interval: Option<u64>,
+    },
+
+    /// File descriptor of a TCP listen socket
+    #[serde(rename = "listen")]
+    Listen {
+        /// Name assigned to the file descriptor
+        name: FileName,
+
+        /// Address to listen on
+        #[serde(default = "default_addr")]
+        addr: String,
+
+        /// Port to listen on
+        #[serde(default = "default_port")]
+        port: u16,
+
+        /// Protocol to use
+        #[serde(default)]
+        prot: Protocol,
+
+        /// Maximum size of a WebSocket message in bytes for `prot = "wss"` or of the body of a
+        /// request for `prot = "https"`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_message_size: Option<usize>,
+
+        /// Origins allowed to open WebSocket connections for `prot = "wss"`, any if not specified
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        origins: Option<Vec<String>>,
+
+        /// Maximum length of the queue of pending connections
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        backlog: Option<u32>,
+
+        /// Whether to allow binding to an address still in use by closed connections, `SO_REUSEADDR`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reuseaddr: Option<bool>,
+
+        /// Whether an IPv6 `addr` only accepts IPv6 connections, `IPV6_V6ONLY`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        only_v6: Option<bool>,
+
+        /// Maximum number of concurrently open accepted connections, unlimited if not specified
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_connections: Option<usize>,
+
+        /// Time in seconds, within which the handshakes of an accepted connection must complete for `prot = "tls"`, `prot = "wss"` and `prot = "https"`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        handshake_timeout: Option<u64>,
+
+        /// Time in seconds, after which blocking reads and writes on an accepted connection fail, unlimited if not specified
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        idle_timeout: Option<u64>,
+
+        /// Time in seconds of idleness, after which TCP keepalive probes are sent on an accepted connection, none if not specified
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        keepalive: Option<u64>,
+
+        /// Application-layer protocols offered via ALPN for `prot = "tls"`, `prot = "wss"` and `prot = "https"` in order of preference
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        alpn: Option<Vec<AlpnProtocol>>,
+
+        /// The only server name accepted via SNI for `prot = "tls"`, `prot = "wss"` and `prot = "https"`, any if not specified
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sni: Option<ServerName>,
+    },
+
+    /// File descriptor of a TCP stream socket
+    #[serde(rename = "connect")]
+    Connect {
+        /// Name assigned to the file descriptor
+        name: Option<FileName>,
+
+        /// Host address to connect to
+        host: String,
+
+        /// Port to connect to
+        #[serde(default = "default_port")]
+        port: u16,
+
+        /// Protocol to use
+        #[serde(default)]
+        prot: Protocol,
+
+        /// Application-layer protocols offered via ALPN for `prot = "tls"` in order of preference
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        alpn: Option<Vec<AlpnProtocol>>,
+
+        /// Time in seconds, within which each connection attempt and the handshakes must complete, unlimited if not specified
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        connect_timeout: Option<u64>,
+
+        /// Time in seconds, after which blocking reads and writes fail, unlimited if not specified
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        idle_timeout: Option<u64>,
+
+        /// Time in seconds of idleness, after which TCP keepalive probes are sent, none if not specified
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        keepalive: Option<u64>,
+
+        /// Maximum number of bytes sent and received on the connection, unlimited if not specified
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_bytes: Option<u64>,
+
+        /// `socks5://` or `http://` URL of the proxy to connect through, a direct connection if not specified
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        proxy: Option<Url>,
+    },
+
+    /// Read-only file descriptor, every read of which yields the TLS session of the connection
+    /// established last on a socket with `prot = "tls"` in JSON
+    #[serde(rename = "session")]
+    Session {
+        /// Name assigned to the file descriptor
+        name: FileName,
+
+        /// Name of the `kind = "listen"` or `kind = "connect"` file of the socket
+        socket: FileName,
+    },
+
+    /// File descriptor of an in-keep channel to another service of the keep
+    #[serde(rename = "channel")]
+    Channel {
+        /// Name assigned to the file descriptor
+        name: Option<FileName>,
+
+        /// Service at the other end of the channel
+        peer: String,
+    },
+}
+
+impl File {
+    /// Get the name for a file descriptor
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Null { name } => name.as_deref().unwrap_or("null"),
+            Self::Stdin { name } => name.as_deref().unwrap_or("stdin"),
+            Self::Stdout { name } => name.as_deref().unwrap_or("stdout"),
+            Self::Stderr { name } => name.as_deref().unwrap_or("stderr"),
+            Self::Log { name, .. } => name.as_deref().unwrap_or("log"),
+            Self::Stats { name } => name.as_deref().unwrap_or("/proc/enarx/stats"),
+            Self::Metrics { name } => name.as_deref().unwrap_or("/proc/enarx/metrics"),
+            Self::Attestation { name } => name.as_deref().unwrap_or("/proc/enarx/attestation"),
+            Self::Certs { name } => name.as_deref().unwrap_or("/key/cert-chain.pem"),
+            Self::Keys { name, .. } => name.as_deref().unwrap_or("/key/derive"),
+            Self::Random { name } => name.as_deref().unwrap_or("/dev/urandom"),
+            Self::Secret { name, .. } => name,
+            Self::Package { name } => name.as_deref().unwrap_or("/app"),
+            Self::Ready { name } => name.as_deref().unwrap_or("/run/ready"),
+            Self::Healthy { name } => name.as_deref().unwrap_or("/run/healthy"),
+            Self::Audit { name, .. } => name.as_deref().unwrap_or("/attest/log"),
+            Self::Listen { name, .. } => name,
+            Self::Connect { name, host, .. } => name.as_deref().unwrap_or(host),
+            Self::Session { name, .. } => name,
+            Self::Channel { name, peer } => name.as_deref().unwrap_or(peer),
+        }
+    }
+
+    /// Get the path of the file of the host, which a `log` or `audit` file descriptor appends its
+    /// records to, if any
+    pub fn log_path(&self) -> Option<&str> {
+        match self {
+            Self::Log {
+                target: LogTarget::File,
+                path,
+                ..
+            }
+            | Self::Audit {
+                target: LogTarget::File,
+                path,
+                ..
+            } => path.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Get the kind of a file descriptor, as given in the config
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Null { .. } => "null",
+            Self::Stdin { .. } => "stdin",
+            Self::Stdout { .. } => "stdout",
+            Self::Stderr { .. } => "stderr",
+            Self::Log { .. } => "log",
+            Self::Stats { .. } => "stats",
+            Self::Metrics { .. } => "metrics",
+            Self::Attestation { .. } => "attestation",
+            Self::Certs { .. } => "certs",
+            Self::Keys { .. } => "keys",
+            Self::Random { .. } => "random",
+            Self::Secret { .. } => "secret",
+            Self::Package { .. } => "package",
+            Self::Ready { .. } => "ready",
+            Self::Healthy { .. } => "healthy",
+            Self::Audit { .. } => "audit",
+            Self::Listen { .. } => "listen",
+            Self::Connect { .. } => "connect",
+            Self::Session { .. } => "session",
+            Self::Channel { .. } => "channel",
+        }
+    }
+}
+
+/// Host stream to emit log records to
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogTarget {
+    /// Emit log records to the standard output of the host
+    #[serde(rename = "stdout")]
+    Stdout,
+
+    /// Emit log records to the standard error of the host
+    #[serde(rename = "stderr")]
+    Stderr,
+
+    /// Append log records to the file of the host at `path`, which the host opens for the keep
+    #[serde(rename = "file")]
+    File,
+}
+
+impl Default for LogTarget {
+    fn default() -> Self {
+        Self::Stderr
+    }
+}
+
+/// Secret keys are derived from
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeySource {
+    /// The private key of the keep, so the keys change with every start of the keep
+    #[serde(rename = "keep")]
+    Keep,
+
+    /// The sealing key of the platform, so the keys are the same for every keep of the same
+    /// workload on the same platform, available on the `sgx` and `sev` backends only
+    #[serde(rename = "platform")]
+    Platform,
+}
+
+impl Default for KeySource {
+    fn default() -> Self {
+        Self::Keep
+    }
+}
+
+/// Protocol to use for a connection
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Protocol {
+    /// Transparently wrap the TCP connection with the TLS protocol
+    #[serde(rename = "tls")]
+    Tls,
+
+    /// Normal TCP connection
+    #[serde(rename = "tcp")]
+    Tcp,
+
+    /// WebSocket messages over a TLS connection, framed by the host
+    #[serde(rename = "wss")]
+    Wss,
+
+    /// HTTP/1.1 requests over a TLS connection, each handled by a call of the
+    /// `handle_http_request` export of the module
+    #[serde(rename = "https")]
+    Https,
+}
+
+impl Default for Protocol {
+    fn default() -> Self {
+        Self::Tls
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        [[files]]
+        kind = "stdin"
+
+        [[files]]
+        name = "X"
+        kind = "listen"
+        prot = "tcp"
+        port = 9000
+
+        [[files]]
+        kind = "stdout"
+
+        [[files]]
+        kind = "null"
+
+        [[files]]
+        kind = "stderr"
+
+        [[files]]
+        kind = "connect"
+        host = "example.com"
+    "#;
+
+    #[test]
+    fn values() {
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
+
+        assert_eq!(
+            cfg.files,
+            vec![
+                File::Stdin { name: None },
+                File::Listen {
+                    name: "X".into(),
+                    port: 9000,
+                    prot: Protocol::Tcp,
+                    addr: default_addr(),
+                    max_message_size: None,
+                    origins: None,
+                    backlog: None,
+                    reuseaddr: None,
+                    only_v6: None,
+                    max_connections: None,
+                    handshake_timeout: None,
+                    idle_timeout: None,
+                    keepalive: None,
+                    alpn: None,
+                    sni: None,
+                },
+                File::Stdout { name: None },
+                File::Null { name: None },
+                File::Stderr { name: None },
+                File::Connect {
+                    name: None,
+                    port: default_port(),
+                    prot: Protocol::Tls,
+                    host: "example.com".into(),
+                    alpn: None,
+                    connect_timeout: None,
+                    idle_timeout: None,
+                    keepalive: None,
+                    max_bytes: None,
+                    proxy: None,
+                },
+            ]
+        );
+
+        let _cfg_str = toml::to_string(&cfg).unwrap();
+    }
+
+    #[test]
+    fn names() {
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
+
+        assert_eq!(
+            vec!["stdin", "X", "stdout", "null", "stderr", "example.com"],
+            cfg.files.iter().map(|f| f.name()).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["stdin", "listen", "stdout", "null", "stderr", "connect"],
+            cfg.files.iter().map(File::kind).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn invalid_name() {
+        const CONFIG: &str = r#"
+        [[files]]
+        name = "test:"
+        kind = "null"
+        "#;
+
+        let err = toml::from_str::<Config>(CONFIG).unwrap_err();
+        assert_eq!(err.line_col(), Some((1, 8)));
+        assert_eq!(
+            err.to_string(),
+            "invalid value for `name` contains ':' for key `files` at line 2 column 9"
+        );
+    }
+
+    #[test]
+    fn env_host() {
+        const CONFIG: &str = r#"
+        env_host = ["FOO", "BAR_*"]
+        "#;
+
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(cfg.env_host, vec!["FOO".into(), "BAR_*".into()]);
+
+        let matches = |name| cfg.env_host.iter().any(|p| p.matches(name));
+        assert!(matches("FOO"));
+        assert!(!matches("FOOBAR"));
+        assert!(matches("BAR_"));
+        assert!(matches("BAR_BAZ"));
+        assert!(!matches("BAR"));
+
+        let cfg: Config = toml::from_str(r#"env_host = ["AB_*"]"#).unwrap();
+        assert!(cfg.env_host[0].matches("AB_C"));
+
+        let cfg_str = toml::to_string(&cfg).unwrap();
+        assert_eq!(cfg, toml::from_str(&cfg_str).unwrap());
+
+        for pattern in ["", "*", "A*B", "A**", "A=B", "A*", "BAR*", "_*", "A_*"] {
+            let err = toml::from_str::<Config>(&format!("env_host = [\"{pattern}\"]"))
+                .unwrap_err()
+                .to_string();
+            assert!(err.starts_with(&format!("invalid `env_host` pattern `{pattern}`")));
+        }
+    }
+
+    #[test]
+    fn renewal_margin() {
+        const CONFIG: &str = r#"
+        steward = "https://steward.example.com"
+        renewal_margin = 3600
+        "#;
+
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(cfg.renewal_margin, Some(3600));
+
+        let cfg_str = toml::to_string(&cfg).unwrap();
+        assert_eq!(cfg, toml::from_str(&cfg_str).unwrap());
+
+        let cfg: Config = toml::from_str("").unwrap();
+        assert_eq!(cfg.renewal_margin, None);
+    }
+
+    #[test]
+    fn stewards() {
+        const CONFIG: &str = r#"
+        steward = ["https://a.example.com", "https://b.example.com"]
+        steward_retries = 2
+        steward_backoff = 5
+        steward_required = false
+        steward_handoff = true
+        "#;
+
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(
+            cfg.steward,
+            [
+                "https://a.example.com".parse::<Url>().unwrap(),
+                "https://b.example.com".parse().unwrap()
+            ]
+        );
+        assert_eq!(cfg.steward_retries, Some(2));
+        assert_eq!(cfg.steward_backoff, Some(5));
+        assert_eq!(cfg.steward_required, Some(false));
+        assert_eq!(cfg.steward_handoff, Some(true));
+
+        let cfg_str = toml::to_string(&cfg).unwrap();
+        assert_eq!(cfg, toml::from_str(&cfg_str).unwrap());
+
+        let cfg: Config = toml::from_str(r#"steward = "https://a.example.com""#).unwrap();
+        assert_eq!(cfg.steward.len(), 1);
+        let cfg_str = toml::to_string(&cfg).unwrap();
+        assert!(cfg_str.starts_with("steward = \"https://a.example.com/\""));
+
+        let cfg: Config = toml::from_str("").unwrap();
+        assert!(cfg.steward.is_empty());
+        assert!(toml::from_str::<Config>("steward = 1").is_err());
+    }
+
+    #[test]
+    fn steward_hints() {
+        const CONFIG: &str = r#"
+        steward = "https://steward.example.com"
+        steward_hints = ["version", "config", "digest-algorithms"]
+        "#;
+
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(
+            cfg.steward_hints,
+            [
+                StewardHint::Version,
+                StewardHint::Config,
+                StewardHint::DigestAlgorithms
+            ]
+        );
+
+        let cfg_str = toml::to_string(&cfg).unwrap();
+        assert_eq!(cfg, toml::from_str(&cfg_str).unwrap());
+
+        let cfg: Config = toml::from_str("").unwrap();
+        assert!(cfg.steward_hints.is_empty());
+        assert!(toml::from_str::<Config>(r#"steward_hints = ["unknown"]"#).is_err());
+    }
+
+    #[test]
+    fn handler() {
+        const CONFIG: &str = r#"
+        handler = "handle"
+
+        [services.api]
+        module = "api.wasm"
+        handler = "serve"
+        "#;
+
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(cfg.handler.as_deref(), Some("handle"));
+        assert_eq!(cfg.services["api"].handler.as_deref(), Some("serve"));
+
+        let cfg_str = toml::to_string(&cfg).unwrap();
+        assert_eq!(cfg, toml::from_str(&cfg_str).unwrap());
+
+        let cfg: Config = toml::from_str("").unwrap();
+        assert_eq!(cfg.handler, None);
+    }
+
+    #[test]
+    fn proxies() {
+        const CONFIG: &str = r#"
+        steward = "https://steward.example.com"
+        steward_proxy = "http://proxy.example.com:3128"
+
+        [[files]]
+        kind = "connect"
+        host = "example.com"
+        proxy = "socks5://proxy.example.com:1080"
+        "#;
+
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(
+            cfg.steward_proxy,
+            Some("http://proxy.example.com:3128".parse().unwrap())
+        );
+        assert_eq!(
+            cfg.files,
+            vec![File::Connect {
+                name: None,
+                host: "example.com".into(),
+                port: default_port(),
+                prot: Protocol::Tls,
+                alpn: None,
+                connect_timeout: None,
+                idle_timeout: None,
+                keepalive: None,
+                max_bytes: None,
+                proxy: Some("socks5://proxy.example.com:1080".parse().unwrap()),
+            }]
+        );
+
+        let cfg_str = toml::to_string(&cfg).unwrap();
+        assert_eq!(cfg, toml::from_str(&cfg_str).unwrap());
+    }
+
+    #[test]
+    fn limits() {
+        const CONFIG: &str = r#"
+        [limits]
+        time = 60
+        cpu_time = 10
+        fuel = 1000
+        "#;
+
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(
+            cfg.limits,
+            Limits {
+                time: Some(60),
+                cpu_time: Some(10),
+                memory: None,
+                fuel: Some(1000),
+            }
+        );
+
+        let cfg_str = toml::to_string(&cfg).unwrap();
+        assert_eq!(cfg, toml::from_str(&cfg_str).unwrap());
+
+        let cfg: Config = toml::from_str("").unwrap();
+        assert_eq!(cfg.limits, Limits::default());
+    }
+
+    #[test]
+    fn memory() {
+        const CONFIG: &str = r#"
+        [memory]
+        static_maximum_size = 0
+        dynamic_guard_size = 65536
+        "#;
+
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(
+            cfg.memory,
+            Memory {
+                static_maximum_size: Some(0),
+                static_guard_size: None,
+                dynamic_guard_size: Some(65536),
+                dynamic_reserved_for_growth: None,
+            }
+        );
+
+        let cfg_str = toml::to_string(&cfg).unwrap();
+        assert_eq!(cfg, toml::from_str(&cfg_str).unwrap());
+
+        assert!(toml::from_str::<Config>("[memory]\nguard = 0\n").is_err());
+    }
+
+    #[test]
+    fn float() {
+        const CONFIG: &str = r#"
+        [float]
+        canonicalize_nans = true
+        "#;
+
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(
+            cfg.float,
+            Float {
+                canonicalize_nans: Some(true),
+                simd: None,
+            }
+        );
+
+        let cfg_str = toml::to_string(&cfg).unwrap();
+        assert_eq!(cfg, toml::from_str(&cfg_str).unwrap());
+
+        assert!(toml::from_str::<Config>("[float]\nflush_to_zero = true\n").is_err());
+    }
+
+    #[test]
+    fn mount() {
+        const CONFIG: &str = r#"
+        [mount."/data"]
+        host = "/home/user/data"
+
+        [mount."/tmp"]
+        host = "/tmp/workload"
+        writable = true
+        "#;
+
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(
+            cfg.mount,
+            BTreeMap::from([
+                (
+                    "/data".into(),
+                    Mount {
+                        host: "/home/user/data".into(),
+                        writable: false,
+                    }
+                ),
+                (
+                    "/tmp".into(),
+                    Mount {
+                        host: "/tmp/workload".into(),
+                        writable: true,
+                    }
+                ),
+            ])
+        );
+
+        let cfg_str = toml::to_string(&cfg).unwrap();
+        assert_eq!(cfg, toml::from_str(&cfg_str).unwrap());
+
+        assert!(toml::from_str::<Config>("[mount.\"/data\"]\nwritable = true\n").is_err());
+    }
+
+    #[test]
+    fn network() {
+        let cfg: Config = toml::from_str("").unwrap();
+        assert!(!cfg.network.allows("api.example.com", 443));
+
+        const CONFIG: &str = r#"
+        [network]
+        outgoing = ["api.example.com", "storage.example.com:8443"]
+        "#;
+
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
+        assert!(cfg.network.allows("api.example.com", 443));
+        assert!(cfg.network.allows("API.example.com", 443));
+        assert!(!cfg.network.allows("api.example.com", 8443));
+        assert!(cfg.network.allows("storage.example.com", 8443));
+        assert!(!cfg.network.allows("storage.example.com", 443));
+        assert!(!cfg.network.allows("example.com", 443));
+
+        let cfg_str = toml::to_string(&cfg).unwrap();
+        assert_eq!(cfg, toml::from_str(&cfg_str).unwrap());
+
+        let any: Config = toml::from_str("[network]\noutgoing = [\"*\"]\n").unwrap();
+        assert!(any.network.allows("example.org", 8080));
+        assert!(!any.network.allow_widening);
+
+        // Only policies allowing less do not widen the policy.
+        let narrower = Network {
+            outgoing: vec!["storage.example.com:8443".into()],
+            ..Default::default()
+        };
+        assert!(cfg.network.includes(&narrower));
+        assert!(cfg.network.includes(&Network::default()));
+        assert!(!narrower.includes(&cfg.network));
+        assert!(!cfg.network.includes(&any.network));
+        assert!(any.network.includes(&cfg.network));
+
+        let cfg: Config = toml::from_str("[network]\nallow_widening = true\n").unwrap();
+        assert!(cfg.network.allow_widening);
+    }
+
+    #[test]
+    fn network_ipv6() {
+        const CONFIG: &str = r#"
+        [network]
+        outgoing = ["[2001:db8::1]", "[2001:DB8:0::2]:8443", "192.0.2.1:8080"]
+        "#;
+
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
+        // The hosts of URLs are bracketed, while resolved addresses are not.
+        assert!(cfg.network.allows("[2001:db8::1]", 443));
+        assert!(cfg.network.allows("2001:db8::1", 443));
+        assert!(!cfg.network.allows("[2001:db8::1]", 8443));
+        assert!(cfg.network.allows("[2001:db8::2]", 8443));
+        assert!(!cfg.network.allows("[2001:db8::2]", 443));
+        assert!(!cfg.network.allows("[2001:db8::3]", 443));
+        assert!(cfg.network.allows("192.0.2.1", 8080));
+
+        let cfg_str = toml::to_string(&cfg).unwrap();
+        assert_eq!(cfg, toml::from_str(&cfg_str).unwrap());
+
+        assert_eq!(destination("[::1]:8443"), ("::1", Some(8443)));
+        assert_eq!(destination("example.com"), ("example.com", Some(443)));
+        // Bare IPv6 addresses are ambiguous, e.g. `2001:db8::1:443`.
+        for entry in [
+            "2001:db8::1",
+            "2001:db8::1:443",
+            "[2001:db8::1",
+            "[2001:db8::1]443",
+            "[example.com]:443",
+            "example.com:",
+            "example.com:65536",
+        ] {
+            assert_eq!(destination(entry).1, None, "{entry}");
+            let cfg = format!("[network]\noutgoing = [{entry:?}]\n");
+            assert!(toml::from_str::<Config>(&cfg).is_err(), "{entry}");
+        }
+    }
+
+    #[test]
+    fn entropy() {
+        let cfg: Config = toml::from_str("").unwrap();
+        assert_eq!(cfg.entropy.host, None);
+
+        let cfg: Config = toml::from_str("[entropy]\nhost = false\n").unwrap();
+        assert_eq!(cfg.entropy.host, Some(false));
+
+        let cfg_str = toml::to_string(&cfg).unwrap();
+        assert_eq!(cfg, toml::from_str(&cfg_str).unwrap());
+
+        assert!(toml::from_str::<Config>("[entropy]\nrdrand = false\n").is_err());
+
+        let cfg: Config = toml::from_str("[[files]]\nkind = \"random\"\n").unwrap();
+        assert_eq!(cfg.files, vec![File::Random { name: None }]);
+        assert_eq!(cfg.files[0].name(), "/dev/urandom");
+    }
+
+    #[test]
+    fn status() {
+        let cfg: Config = toml::from_str("").unwrap();
+        assert_eq!(cfg.status, None);
+
+        let cfg: Config = toml::from_str("[status]\nport = 8444\n").unwrap();
+        assert_eq!(
+            cfg.status,
+            Some(Status {
+                addr: default_addr(),
+                port: 8444,
+            })
+        );
+
+        let cfg_str = toml::to_string(&cfg).unwrap();
+        assert_eq!(cfg, toml::from_str(&cfg_str).unwrap());
+
+        assert!(toml::from_str::<Config>("[status]\naddr = \"::1\"\n").is_err());
+    }
+
+    #[test]
+    fn time() {
+        let cfg: Config = toml::from_str("").unwrap();
+        assert_eq!(cfg.time, Time::Host);
+
+        const CONFIG: &str = r#"
+        [time]
+        source = "roughtime"
+        server = "roughtime.example.com:2002"
+        key = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+        "#;
+
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(
+            cfg.time,
+            Time::Roughtime {
+                server: "roughtime.example.com:2002".into(),
+                key: "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".into(),
+            }
+        );
+        assert_eq!(cfg.time.source(), "roughtime");
+
+        let cfg_str = toml::to_string(&cfg).unwrap();
+        assert_eq!(cfg, toml::from_str(&cfg_str).unwrap());
+
+        let cfg: Config =
+            toml::from_str("[time]\nsource = \"fixed\"\nepoch = 1672531200\n").unwrap();
+        assert_eq!(cfg.time, Time::Fixed { epoch: 1672531200 });
+
+        assert!(toml::from_str::<Config>("[time]\nsource = \"fixed\"\n").is_err());
+        assert!(toml::from_str::<Config>("[time]\nsource = \"ntp\"\n").is_err());
+    }
+
+    #[test]
+    fn log() {
+        const CONFIG: &str = r#"
+        [[files]]
+        kind = "log"
+
+        [[files]]
+        name = "stderr"
+        kind = "log"
+        target = "stdout"
+
+        [[files]]
+        name = "app"
+        kind = "log"
+        target = "file"
+        path = "/var/log/app.log"
+        "#;
+
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(
+            cfg.files,
+            vec![
+                File::Log {
+                    name: None,
+                    target: LogTarget::Stderr,
+                    path: None,
+                },
+                File::Log {
+                    name: Some("stderr".into()),
+                    target: LogTarget::Stdout,
+                    path: None,
+                },
+                File::Log {
+                    name: Some("app".into()),
+                    target: LogTarget::File,
+                    path: Some("/var/log/app.log".into()),
+                },
+            ]
+        );
+        assert_eq!(
+            vec!["log", "stderr", "app"],
+            cfg.files.iter().map(|f| f.name()).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![None, None, Some("/var/log/app.log")],
+            cfg.files.iter().map(|f| f.log_path()).collect::<Vec<_>>()
+        );
+
+        let cfg_str = toml::to_string(&cfg).unwrap();
+        assert_eq!(cfg, toml::from_str(&cfg_str).unwrap());
+    }
+
+    #[test]
+    fn stats() {
+        const CONFIG: &str = r#"
+        [[files]]
+        kind = "stats"
+
+        [[files]]
+        name = "STATS"
+        kind = "stats"
+        "#;
+
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(
+            cfg.files,
+            vec![
+                File::Stats { name: None },
+                File::Stats {
+                    name: Some("STATS".into())
+                },
+            ]
+        );
+        assert_eq!(
+            vec!["/proc/enarx/stats", "STATS"],
+            cfg.files.iter().map(|f| f.name()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn certs() {
+        const CONFIG: &str = r#"
         [[files]]
-        kind = "connect"
-        host = "example.com"
-    "#;
+        kind = "certs"
+
+        [[files]]
+        name = "CERTS"
+        kind = "certs"
+        "#;
+
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(
+            cfg.files,
+            vec![
+                File::Certs { name: None },
+                File::Certs {
+                    name: Some("CERTS".into())
+                },
+            ]
+        );
+        assert_eq!(
+            vec!["/key/cert-chain.pem", "CERTS"],
+            cfg.files.iter().map(|f| f.name()).collect::<Vec<_>>()
+        );
+    }
 
     #[test]
-    fn values() {
+    fn keys() {
+        const CONFIG: &str = r#"
+        [[files]]
+        kind = "keys"
+
+        [[files]]
+        name = "SEALED"
+        kind = "keys"
+        source = "platform"
+        labels = ["db", "backup/*"]
+        max_uses = 2
+        "#;
+
         let cfg: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(
+            cfg.files,
+            vec![
+                File::Keys {
+                    name: None,
+                    source: KeySource::Keep,
+                    labels: vec![],
+                    max_uses: None,
+                },
+                File::Keys {
+                    name: Some("SEALED".into()),
+                    source: KeySource::Platform,
+                    labels: vec!["db".into(), "backup/*".into()],
+                    max_uses: Some(2),
+                },
+            ]
+        );
+        let cfg_str = toml::to_string(&cfg).unwrap();
+        assert_eq!(cfg, toml::from_str(&cfg_str).unwrap());
+        assert_eq!(
+            vec!["/key/derive", "SEALED"],
+            cfg.files.iter().map(|f| f.name()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn package() {
+        const CONFIG: &str = r#"
+        [[files]]
+        kind = "package"
+
+        [[files]]
+        name = "/data"
+        kind = "package"
+        "#;
 
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
         assert_eq!(
             cfg.files,
             vec![
-                File::Stdin { name: None },
-                File::Listen {
-                    name: "X".into(),
-                    port: 9000,
-                    prot: Protocol::Tcp,
-                    addr: default_addr()
+                File::Package { name: None },
+                File::Package {
+                    name: Some("/data".into())
                 },
-                File::Stdout { name: None },
-                File::Null { name: None },
-                File::Stderr { name: None },
-                File::Connect {
-                    name: None,
-                    port: default_port(),
-                    prot: Protocol::Tls,
-                    host: "example.com".into(),
+            ]
+        );
+        assert_eq!(
+            vec!["/app", "/data"],
+            cfg.files.iter().map(|f| f.name()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn secrets() {
+        const CONFIG: &str = r#"
+        secrets = "https://secrets.example.com/bundle"
+
+        [[files]]
+        name = "/secrets/db"
+        kind = "secret"
+
+        [[files]]
+        name = "API_KEY"
+        kind = "secret"
+        secret = "api"
+        "#;
+
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(
+            cfg.secrets,
+            Some("https://secrets.example.com/bundle".parse().unwrap())
+        );
+        assert_eq!(
+            cfg.files,
+            vec![
+                File::Secret {
+                    name: "/secrets/db".into(),
+                    secret: None,
+                },
+                File::Secret {
+                    name: "API_KEY".into(),
+                    secret: Some("api".into()),
                 },
             ]
         );
+        assert_eq!(cfg.files[1].kind(), "secret");
+        assert!(toml::from_str::<Config>("[[files]]\nkind = \"secret\"").is_err());
+    }
 
-        let _cfg_str = toml::to_string(&cfg).unwrap();
+    #[test]
+    fn metrics() {
+        const CONFIG: &str = r#"
+        [[files]]
+        kind = "metrics"
+        "#;
+
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(cfg.files, vec![File::Metrics { name: None }]);
+        assert_eq!(cfg.files[0].name(), "/proc/enarx/metrics");
     }
 
     #[test]
-    fn names() {
+    fn attestation() {
+        const CONFIG: &str = r#"
+        [[files]]
+        kind = "attestation"
+
+        [[files]]
+        name = "ATTESTATION"
+        kind = "attestation"
+        "#;
+
         let cfg: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(
+            cfg.files,
+            vec![
+                File::Attestation { name: None },
+                File::Attestation {
+                    name: Some("ATTESTATION".into())
+                },
+            ]
+        );
+        assert_eq!(
+            vec!["/proc/enarx/attestation", "ATTESTATION"],
+            cfg.files.iter().map(|f| f.name()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn health() {
+        const CONFIG: &str = r#"
+        [[files]]
+        kind = "ready"
+
+        [[files]]
+        name = "HEALTHY"
+        kind = "healthy"
+        "#;
 
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
         assert_eq!(
-            vec!["stdin", "X", "stdout", "null", "stderr", "example.com"],
+            cfg.files,
+            vec![
+                File::Ready { name: None },
+                File::Healthy {
+                    name: Some("HEALTHY".into())
+                },
+            ]
+        );
+        assert_eq!(
+            vec!["/run/ready", "HEALTHY"],
             cfg.files.iter().map(|f| f.name()).collect::<Vec<_>>()
         );
     }
 
     #[test]
-    fn invalid_name() {
+    fn audit() {
         const CONFIG: &str = r#"
         [[files]]
-        name = "test:"
-        kind = "null"
+        kind = "audit"
+
+        [[files]]
+        name = "AUDIT"
+        kind = "audit"
+        target = "stdout"
+        anchor_interval = 10
         "#;
 
-        let err = toml::from_str::<Config>(CONFIG).unwrap_err();
-        assert_eq!(err.line_col(), Some((1, 8)));
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
         assert_eq!(
-            err.to_string(),
-            "invalid value for `name` contains ':' for key `files` at line 2 column 9"
+            cfg.files,
+            vec![
+                File::Audit {
+                    name: None,
+                    target: LogTarget::Stderr,
+                    path: None,
+                    anchor_interval: None,
+                },
+                File::Audit {
+                    name: Some("AUDIT".into()),
+                    target: LogTarget::Stdout,
+                    path: None,
+                    anchor_interval: Some(10),
+                },
+            ]
+        );
+        assert_eq!(
+            vec!["/attest/log", "AUDIT"],
+            cfg.files.iter().map(|f| f.name()).collect::<Vec<_>>()
+        );
+
+        let cfg_str = toml::to_string(&cfg).unwrap();
+        assert_eq!(cfg, toml::from_str(&cfg_str).unwrap());
+    }
+
+    #[test]
+    fn services() {
+        const CONFIG: &str = r#"
+        [[files]]
+        kind = "channel"
+        peer = "api"
+
+        [services.api]
+        module = "api.wasm"
+        args = ["--verbose"]
+
+        [services.api.env]
+        VAR = "var"
+
+        [[services.api.files]]
+        name = "MAIN"
+        kind = "channel"
+        peer = "main"
+        "#;
+
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(
+            cfg.files,
+            vec![File::Channel {
+                name: None,
+                peer: "api".into()
+            }]
+        );
+        assert_eq!(cfg.files[0].name(), "api");
+        assert_eq!(
+            cfg.services,
+            BTreeMap::from([(
+                "api".into(),
+                Service {
+                    module: "api.wasm".into(),
+                    handler: None,
+                    args: vec!["--verbose".into()],
+                    env: HashMap::from([("VAR".into(), "var".into())]),
+                    files: vec![File::Channel {
+                        name: Some("MAIN".into()),
+                        peer: MAIN_SERVICE.into()
+                    }],
+                }
+            )])
+        );
+
+        let cfg_str = toml::to_string(&cfg).unwrap();
+        assert_eq!(toml::from_str::<Config>(&cfg_str).unwrap(), cfg);
+
+        assert!(toml::from_str::<Config>("[services.api]\nargs = []").is_err());
+    }
+
+    #[test]
+    fn https() {
+        const CONFIG: &str = r#"
+        [[files]]
+        name = "API"
+        kind = "listen"
+        prot = "https"
+        port = 8443
+        max_message_size = 65536
+        "#;
+
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
+        match &cfg.files[..] {
+            [File::Listen {
+                prot: Protocol::Https,
+                max_message_size: Some(65536),
+                port: 8443,
+                ..
+            }] => {}
+            files => panic!("unexpected files {files:?}"),
+        }
+
+        let cfg_str = toml::to_string(&cfg).unwrap();
+        assert_eq!(cfg, toml::from_str(&cfg_str).unwrap());
+    }
+
+    #[test]
+    fn websocket() {
+        const CONFIG: &str = r#"
+        [[files]]
+        name = "WS"
+        kind = "listen"
+        prot = "wss"
+        max_message_size = 4096
+        origins = ["https://example.com"]
+        handshake_timeout = 5
+        "#;
+
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(
+            cfg.files,
+            vec![File::Listen {
+                name: "WS".into(),
+                addr: default_addr(),
+                port: default_port(),
+                prot: Protocol::Wss,
+                max_message_size: Some(4096),
+                origins: Some(vec!["https://example.com".into()]),
+                backlog: None,
+                reuseaddr: None,
+                only_v6: None,
+                max_connections: None,
+                handshake_timeout: Some(5),
+                idle_timeout: None,
+                keepalive: None,
+                alpn: None,
+                sni: None,
+            }]
+        );
+
+        let cfg_str = toml::to_string(&cfg).unwrap();
+        assert_eq!(cfg, toml::from_str(&cfg_str).unwrap());
+    }
+
+    #[test]
+    fn connections() {
+        const CONFIG: &str = r#"
+        [[files]]
+        name = "LISTEN"
+        kind = "listen"
+        prot = "tcp"
+        backlog = 16
+        reuseaddr = false
+        only_v6 = true
+        max_connections = 64
+        idle_timeout = 300
+        keepalive = 60
+
+        [[files]]
+        kind = "connect"
+        host = "example.com"
+        max_bytes = 1048576
+        connect_timeout = 5
+        idle_timeout = 300
+        keepalive = 60
+        "#;
+
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(
+            cfg.files,
+            vec![
+                File::Listen {
+                    name: "LISTEN".into(),
+                    addr: default_addr(),
+                    port: default_port(),
+                    prot: Protocol::Tcp,
+                    max_message_size: None,
+                    origins: None,
+                    backlog: Some(16),
+                    reuseaddr: Some(false),
+                    only_v6: Some(true),
+                    max_connections: Some(64),
+                    handshake_timeout: None,
+                    idle_timeout: Some(300),
+                    keepalive: Some(60),
+                    alpn: None,
+                    sni: None,
+                },
+                File::Connect {
+                    name: None,
+                    host: "example.com".into(),
+                    port: default_port(),
+                    prot: Protocol::Tls,
+                    alpn: None,
+                    connect_timeout: Some(5),
+                    idle_timeout: Some(300),
+                    keepalive: Some(60),
+                    max_bytes: Some(1048576),
+                    proxy: None,
+                },
+            ]
+        );
+
+        let cfg_str = toml::to_string(&cfg).unwrap();
+        assert_eq!(cfg, toml::from_str(&cfg_str).unwrap());
+    }
+
+    #[test]
+    fn tls() {
+        const CONFIG: &str = r#"
+        [tls]
+        versions = ["1.2", "1.3"]
+        cipher_suites = ["TLS13_AES_128_GCM_SHA256", "TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256"]
+
+        [[files]]
+        name = "LISTEN"
+        kind = "listen"
+        alpn = ["h2", "http/1.1"]
+        sni = "Example.com"
+
+        [[files]]
+        name = "LISTEN_SESSION"
+        kind = "session"
+        socket = "LISTEN"
+        "#;
+
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(cfg.tls.versions(), [TlsVersion::Tls12, TlsVersion::Tls13]);
+        assert_eq!(
+            cfg.tls.cipher_suites(),
+            [
+                CipherSuite::TLS13_AES_128_GCM_SHA256,
+                CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256
+            ]
+        );
+        assert_eq!(cfg.tls.kx_groups(), Tls::DEFAULT_KX_GROUPS);
+        match &cfg.files[0] {
+            File::Listen { alpn, sni, .. } => {
+                assert_eq!(alpn, &Some(vec!["h2".into(), "http/1.1".into()]));
+                assert_eq!(sni, &Some("example.com".into()));
+            }
+            file => panic!("unexpected file {file:?}"),
+        }
+        assert_eq!(
+            cfg.files[1],
+            File::Session {
+                name: "LISTEN_SESSION".into(),
+                socket: "LISTEN".into(),
+            }
+        );
+        assert_eq!(cfg.tls.client_auth(), ClientAuth::None);
+        let auth: Config = toml::from_str("[tls]\nclient_auth = \"optional\"\n").unwrap();
+        assert_eq!(auth.tls.client_auth(), ClientAuth::Optional);
+
+        let cfg_str = toml::to_string(&cfg).unwrap();
+        assert_eq!(cfg, toml::from_str(&cfg_str).unwrap());
+
+        // Only the suites of allowed versions are used.
+        let cfg: Config = toml::from_str(
+            "[tls]\ncipher_suites = [\"TLS13_AES_128_GCM_SHA256\", \"TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256\"]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            cfg.tls.cipher_suites(),
+            [CipherSuite::TLS13_AES_128_GCM_SHA256]
         );
+
+        for (config, err) in [
+            ("[tls]\nversions = []\n", "`tls.versions` must not be empty"),
+            (
+                "[tls]\nkx_groups = []\n",
+                "`tls.kx_groups` must not be empty",
+            ),
+            (
+                "[tls]\nversions = [\"1.2\"]\n",
+                "`tls.cipher_suites` contains no cipher suite for TLS version 1.2",
+            ),
+            ("[tls]\nversions = [\"1.0\"]\n", "unknown variant `1.0`"),
+            (
+                "[tls]\ncipher_suites = [\"NULL\"]\n",
+                "unknown variant `NULL`",
+            ),
+            (
+                "[[files]]\nkind = \"connect\"\nhost = \"example.com\"\nalpn = [\"\"]\n",
+                "invalid ALPN protocol `` must be between 1 and 255 bytes long",
+            ),
+            (
+                "[[files]]\nkind = \"listen\"\nsni = \"127.0.0.1\"\n",
+                "invalid server name `127.0.0.1`",
+            ),
+            (
+                "[[files]]\nkind = \"listen\"\nsni = \"foo..com\"\n",
+                "invalid server name `foo..com`",
+            ),
+        ] {
+            let e = toml::from_str::<Config>(config).unwrap_err().to_string();
+            assert!(e.starts_with(err), "`{e}` does not start with `{err}`");
+        }
     }
 
     #[test]
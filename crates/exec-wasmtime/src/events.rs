@@ -0,0 +1,179 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Lifecycle events of the keep reported to the host
+
+use std::io::{self, Write};
+
+use serde::{Deserialize, Serialize};
+use ureq::serde_json;
+use url::Url;
+
+#[cfg(unix)]
+use std::{fs::File, mem::ManuallyDrop, os::unix::io::FromRawFd, os::unix::io::RawFd};
+
+#[cfg(unix)]
+use once_cell::sync::OnceCell;
+#[cfg(unix)]
+use std::sync::Mutex;
+
+/// The file descriptor of the host, which the events are written to, if requested
+#[cfg(unix)]
+static EVENTS: OnceCell<Mutex<ManuallyDrop<File>>> = OnceCell::new();
+
+/// A lifecycle event of a keep
+///
+/// Events are written as single lines of JSON tagged by `event`, e.g. `{"event":"wasm-compiled"}`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    /// The backend of the keep was selected
+    BackendSelected {
+        /// Name of the backend
+        backend: String,
+        /// Backends preferred over the selected one, which were skipped by `--backend auto`
+        #[serde(default)]
+        skipped: Vec<Skipped>,
+    },
+
+    /// The keep was measured
+    KeepMeasured {
+        /// Hex-encoded measurement of the keep, if the backend measures keeps
+        measurement: Option<String>,
+    },
+
+    /// The package was fetched
+    PackageFetched {
+        /// URL of the package, if it is remote
+        url: Option<Url>,
+        /// Size of the WebAssembly module in bytes
+        size: usize,
+    },
+
+    /// The keep obtained its certificate
+    Attested {
+        /// Hex-encoded SHA-256 digest of the public key of the keep
+        identity: String,
+        /// Hex-encoded SHA-256 digest of the DER-encoded certificate of the keep
+        certificate: String,
+        /// URL of the Steward, which issued the certificate, or `None` if it is self-signed or was
+        /// issued out of band
+        steward: Option<Url>,
+        /// Hex-encoded serial number of the certificate of the keep
+        #[serde(default)]
+        serial: String,
+        /// Digest of the WebAssembly module, `sha256:` followed by the hex-encoded SHA-256 digest
+        #[serde(default)]
+        workload: String,
+    },
+
+    /// The WebAssembly module was compiled
+    WasmCompiled,
+
+    /// A listen socket was bound
+    ListeningOn {
+        /// Name of the file in `Enarx.toml`
+        name: String,
+        /// Address the socket is bound to
+        addr: String,
+        /// Port the socket is bound to
+        port: u16,
+    },
+
+    /// The workload was started, once all of its files were set up
+    Started {
+        /// Whether the workload signals its readiness via a `kind = "ready"` file
+        signals_ready: bool,
+    },
+
+    /// The workload signalled a change of its readiness via a `kind = "ready"` file
+    Ready {
+        /// Whether the workload is ready to serve
+        ready: bool,
+    },
+
+    /// The workload signalled a change of its liveness via a `kind = "healthy"` file
+    Healthy {
+        /// Whether the workload is healthy
+        healthy: bool,
+    },
+
+    /// The keep exited
+    Exited {
+        /// Exit code of the keep
+        code: i32,
+    },
+}
+
+/// A backend skipped when selecting the backend of the keep
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Skipped {
+    /// Name of the backend
+    pub backend: String,
+    /// Why the backend was skipped, e.g. `unavailable`
+    pub reason: String,
+}
+
+impl Event {
+    /// Writes the event to `out` as a single line of JSON.
+    pub fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut line = serde_json::to_vec(self)?;
+        line.push(b'\n');
+        out.write_all(&line)
+    }
+}
+
+/// Reports the events to the file descriptor `fd` of the host.
+///
+/// The FD is managed by the host, so it is never closed.
+#[cfg(unix)]
+pub(crate) fn init(fd: RawFd) {
+    let file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+    let _ = EVENTS.set(Mutex::new(file));
+}
+
+/// Reports `event` to the host, if requested.
+#[cfg(unix)]
+pub(crate) fn emit(event: Event) {
+    if let Some(out) = EVENTS.get() {
+        if let Err(e) = event.write_to(&mut **out.lock().unwrap()) {
+            log::warn!("failed to report event: {e}");
+        }
+    }
+}
+
+#[cfg(windows)]
+pub(crate) fn emit(_event: Event) {}
+
+#[cfg(test)]
+mod test {
+    use super::{Event, Skipped};
+
+    #[test]
+    fn lines() {
+        let mut out = vec![];
+        Event::BackendSelected {
+            backend: "kvm".into(),
+            skipped: vec![Skipped {
+                backend: "sev".into(),
+                reason: "unavailable".into(),
+            }],
+        }
+        .write_to(&mut out)
+        .unwrap();
+        Event::WasmCompiled.write_to(&mut out).unwrap();
+        Event::ListeningOn {
+            name: "web".into(),
+            addr: "::".into(),
+            port: 443,
+        }
+        .write_to(&mut out)
+        .unwrap();
+        Event::Ready { ready: true }.write_to(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"event\":\"backend-selected\",\"backend\":\"kvm\",\"skipped\":[{\"backend\":\"sev\",\"reason\":\"unavailable\"}]}\n\
+             {\"event\":\"wasm-compiled\"}\n\
+             {\"event\":\"listening-on\",\"name\":\"web\",\"addr\":\"::\",\"port\":443}\n\
+             {\"event\":\"ready\",\"ready\":true}\n"
+        );
+    }
+}
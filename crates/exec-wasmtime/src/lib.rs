@@ -6,13 +6,28 @@
 #![deny(clippy::all)]
 #![warn(rust_2018_idioms)]
 
+mod events;
 mod loader;
+mod metrics;
+#[cfg(unix)]
+mod reload;
+mod report;
+mod shutdown;
+#[cfg(unix)]
+mod trace;
 
 use drawbridge_client::types::TreeName;
+use enarx_config::{Config, File};
+pub use events::{Event, Skipped};
 use loader::Loader;
+pub use metrics::{Metrics, Traffic};
 use once_cell::sync::Lazy;
+use tracing::info_span;
 use url::Url;
 
+use std::collections::BTreeMap;
+use std::fmt;
+
 #[cfg(unix)]
 use std::os::unix::io::{FromRawFd, RawFd};
 
@@ -25,6 +40,69 @@ pub static PACKAGE_ENTRYPOINT: Lazy<TreeName> = Lazy::new(|| "main.wasm".parse()
 /// Name of package config file
 pub static PACKAGE_CONFIG: Lazy<TreeName> = Lazy::new(|| "Enarx.toml".parse().unwrap());
 
+/// Maximum size of a Wasm module in bytes, unless configured otherwise by the host
+pub const DEFAULT_MAX_WASM_SIZE: u64 = 100_000_000;
+
+/// Maximum total size of the data files of a package in bytes
+pub const MAX_PACKAGE_DATA_SIZE: u64 = 64_000_000;
+
+/// Returns whether the workload configured by `config` mounts the data files of its package.
+pub fn mounts_package_data(config: &Config) -> bool {
+    config
+        .files
+        .iter()
+        .chain(config.services.values().flat_map(|s| s.files.iter()))
+        .any(|file| matches!(file, File::Package { .. }))
+}
+
+/// Returns whether the file `name` at the top level of a package configured by `config` is a data
+/// file, i.e. neither one of its modules nor its config.
+pub fn is_package_data(config: &Config, name: &str) -> bool {
+    name != PACKAGE_ENTRYPOINT.as_str()
+        && name != PACKAGE_CONFIG.as_str()
+        && !config.services.values().any(|s| s.module == name)
+}
+
+/// Error returned, if the workload exits with a non-zero exit code
+///
+/// Callers are expected to exit with the same code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExitCode(pub i32);
+
+impl fmt::Display for ExitCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "workload exited with code {}", self.0)
+    }
+}
+
+impl std::error::Error for ExitCode {}
+
+/// Error returned, if the Steward refuses to issue a certificate for the keep
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StewardRejected {
+    /// HTTP status code of the response of the Steward
+    pub status: u16,
+
+    /// Body of the response of the Steward, which usually states the reason
+    pub reason: String,
+}
+
+impl fmt::Display for StewardRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "steward rejected the certificate signing request with status {}",
+            self.status
+        )?;
+        match self.reason.trim() {
+            "" => Ok(()),
+            reason => write!(f, ": {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for StewardRejected {}
+
 /// Package to execute
 #[cfg(unix)]
 #[derive(Debug, Deserialize, Serialize)]
@@ -39,6 +117,12 @@ pub enum Package {
         wasm: RawFd,
         /// Optional open config file descriptor
         conf: Option<RawFd>,
+        /// Open WASM module file descriptors of the services in the config keyed by service name
+        #[serde(default)]
+        modules: BTreeMap<String, RawFd>,
+        /// Open file descriptors of the data files of the package keyed by file name
+        #[serde(default)]
+        data: BTreeMap<String, RawFd>,
     },
 }
 
@@ -55,6 +139,10 @@ pub enum Package {
         wasm: std::fs::File,
         /// Optional open config file
         conf: Option<std::fs::File>,
+        /// Open WASM module files of the services in the config keyed by service name
+        modules: BTreeMap<String, std::fs::File>,
+        /// Open data files of the package keyed by file name
+        data: BTreeMap<String, std::fs::File>,
     },
 }
 
@@ -64,19 +152,151 @@ pub enum Package {
 #[cfg_attr(unix, derive(Deserialize, Serialize))]
 #[repr(C)]
 pub struct Args {
+    /// Whether to report [`Metrics`] to the host
+    #[cfg_attr(unix, serde(default))]
+    pub metrics: bool,
+
+    /// Whether to emit debug info for the workload, such that a debugger attached to the keep
+    /// can set breakpoints in its source code, only supported by debug builds
+    #[cfg_attr(unix, serde(default))]
+    pub debug: bool,
+
+    /// Whether the host allows the workload to preopen directories of the host with `[mount]` in
+    /// `Enarx.toml`, only supported on backends, which run the workload as a process of the host
+    #[cfg_attr(unix, serde(default))]
+    pub mounts: bool,
+
+    /// File descriptor of the host to report [`Event`]s to
+    #[cfg(unix)]
+    #[serde(default)]
+    pub events: Option<RawFd>,
+
+    /// File descriptor of the host to write the trace of the keep to as JSON lines
+    ///
+    /// The trace reveals what the workload does, so it is only written if requested.
+    #[cfg(unix)]
+    #[serde(default)]
+    pub trace: Option<RawFd>,
+
+    /// File descriptor of the host to write the signed execution report of the keep to on exit
+    #[cfg(unix)]
+    #[serde(default)]
+    pub report: Option<RawFd>,
+
+    /// File descriptor of the host, which releases the keep after attestation by writing a byte to it
+    ///
+    /// If set, the keep waits for the release before compiling and executing the workload.
+    #[cfg(unix)]
+    #[serde(default)]
+    pub hold: Option<RawFd>,
+
+    /// File descriptor of the host, which requests a graceful shutdown of the keep by writing the
+    /// number of the signal it received to it
+    #[cfg(unix)]
+    #[serde(default)]
+    pub shutdown: Option<RawFd>,
+
+    /// File descriptor of the host, which delivers updates of the `network` section of the config
+    /// by writing them to it as lines of JSON
+    #[cfg(unix)]
+    #[serde(default)]
+    pub network: Option<RawFd>,
+
+    /// File descriptor of the host to write the PEM-encoded certificate signing request of the
+    /// keep to, instead of sending it to the Steward
+    ///
+    /// The certificate chain issued for it out of band is read from `crt`.
+    #[cfg(unix)]
+    #[serde(default)]
+    pub csr: Option<RawFd>,
+
+    /// File descriptor of the host to read the certificate chain of the keep from until EOF, either
+    /// PEM-encoded leaf first or as a DER-encoded `PkiPath`
+    #[cfg(unix)]
+    #[serde(default)]
+    pub crt: Option<RawFd>,
+
+    /// File descriptor of the compiled main module sealed by a previous keep, which the host
+    /// cached
+    #[cfg(unix)]
+    #[serde(default)]
+    pub cached: Option<RawFd>,
+
+    /// File descriptor of the host to write the sealed compiled main module to
+    ///
+    /// If set, the main module is loaded from `cached`, if it is valid for the module and the
+    /// platform, and is otherwise compiled, sealed and written to this file descriptor.
+    #[cfg(unix)]
+    #[serde(default)]
+    pub cache: Option<RawFd>,
+
+    /// File descriptors of the listen sockets the host was passed by systemd socket activation
+    /// keyed by socket name
+    ///
+    /// A `kind = "listen"` file accepts connections on the socket named like it, instead of
+    /// binding a socket of its own.
+    #[cfg(unix)]
+    #[serde(default)]
+    pub listeners: BTreeMap<String, RawFd>,
+
+    /// File descriptors of the files of the host opened for appending, which the `kind = "log"`
+    /// and `kind = "audit"` files with `target = "file"` write their records to, keyed by path
+    #[cfg(unix)]
+    #[serde(default)]
+    pub logs: BTreeMap<String, RawFd>,
+
+    /// Maximum size of every Wasm module of the package in bytes, `100_000_000` if not specified
+    #[cfg_attr(unix, serde(default))]
+    pub max_wasm_size: Option<u64>,
+
+    /// Digest the main Wasm module of the package is pinned to, `sha256:` followed by the
+    /// hex-encoded SHA-256 digest of the module
+    ///
+    /// If set, the module is verified against it before compilation and the digest is used as
+    /// the common name of the self-signed certificate of the keep.
+    #[cfg_attr(unix, serde(default))]
+    pub digest: Option<String>,
+
     /// Package
     pub package: Package,
+
+    /// Environment variables of the host, of which only the ones allowed by `env_host` in
+    /// `Enarx.toml` are passed to the workload
+    #[cfg_attr(unix, serde(default))]
+    pub env: BTreeMap<String, String>,
+
+    /// [`Faults`] to inject into the workload, only supported on development backends
+    #[cfg_attr(unix, serde(default))]
+    pub faults: Option<Faults>,
+}
+
+/// Faults injected into the workload for testing its resilience
+///
+/// Only backends without hardware isolation, on which the workload is not attested, support
+/// fault injection.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(unix, derive(Deserialize, Serialize))]
+pub struct Faults {
+    /// Probability of a read or write on a network stream failing with `EAGAIN` or
+    /// `ECONNRESET`, or of accepting a connection failing with `EAGAIN`
+    pub error_rate: f64,
+
+    /// Maximum latency added to reads from network streams, in milliseconds
+    pub read_latency: u64,
+
+    /// Offset of the system clock, in seconds
+    pub clock_skew: i64,
 }
 
 /// Execute
 pub fn execute_with_args(args: Args) -> anyhow::Result<()> {
     // Step through the state machine.
     let configured = Loader::from(args);
-    let requested = configured.next()?;
-    let attested = requested.next()?;
-    let compiled = attested.next()?;
-    let connected = compiled.next()?;
-    let completed = connected.next()?;
+    let requested = info_span!("attest").in_scope(|| configured.next())?;
+    let attested = info_span!("fetch").in_scope(|| requested.next())?;
+    let compiled = info_span!("compile").in_scope(|| attested.next())?;
+    let connected = info_span!("connect").in_scope(|| compiled.next())?;
+    let completed = info_span!("run").in_scope(|| connected.next())?;
     drop(completed);
     Ok(())
 }
@@ -88,32 +308,54 @@ pub fn execute_with_args(args: Args) -> anyhow::Result<()> {
 pub fn execute() -> anyhow::Result<()> {
     use anyhow::Context;
     use std::io::Read;
-    use std::mem::forget;
+    use std::mem::ManuallyDrop;
     use std::os::unix::net::UnixStream;
 
     // This is the FD of a Unix socket on which the host will send the TOML-encoded execution arguments
     // and shutdown the write half of it immediately after.
+    // If requested, the metrics of the workload are written to the write half of the socket.
     // TODO: Use the write half of the socket to write logs/errors to the host
-    let mut host = unsafe { UnixStream::from_raw_fd(3) };
+    let mut host = ManuallyDrop::new(unsafe { UnixStream::from_raw_fd(3) });
 
     let mut args = String::new();
     host.read_to_string(&mut args)
         .context("failed to read arguments")?;
 
-    // The FD is managed by the host or its parent.
-    forget(host);
-
     let args = toml::from_str::<Args>(&args).context("failed to decode arguments")?;
 
-    execute_with_args(args)?;
+    if let Some(fd) = args.events {
+        events::init(fd);
+    }
+    if let Some(fd) = args.trace {
+        trace::init(fd);
+    }
+    if let Some(fd) = args.report {
+        report::init(fd);
+    }
+    if let Some(fd) = args.shutdown {
+        shutdown::init(fd);
+    }
+    if let Some(fd) = args.network {
+        reload::init(fd);
+    }
 
-    Ok(())
+    // The FD is managed by the host or its parent, so it is never closed.
+    let res = if args.metrics {
+        metrics::report_while(&mut *host, || execute_with_args(args))
+            .context("failed to report metrics")?
+    } else {
+        execute_with_args(args)
+    };
+    report::finish(&res);
+    res
 }
 
 #[cfg(test)]
 mod test {
     use crate::loader::Loader;
 
+    use enarx_config::{Config, Float, Limits, Memory};
+
     const NO_EXPORT_WAT: &str = r#"(module
       (memory (export "") 1)
     )"#;
@@ -122,6 +364,10 @@ mod test {
       (func (export "") (result i32) i32.const 1)
     )"#;
 
+    const LOOP_WAT: &str = r#"(module
+      (func (export "") (loop br 0))
+    )"#;
+
     const HELLO_WASI_WAT: &str = r#"(module
       (import "wasi_snapshot_preview1" "proc_exit"
         (func $__wasi_proc_exit (param i32)))
@@ -148,6 +394,42 @@ mod test {
       (data (i32.const 0) "Hello, world!\0a")
     )"#;
 
+    const REACTOR_WAT: &str = r#"(module
+      (global $calls (mut i32) (i32.const 0))
+      (func (export "_initialize")
+        (global.set $calls (i32.const 10))
+      )
+      (func (export "handle") (result i32)
+        (global.set $calls (i32.add (global.get $calls) (i32.const 1)))
+        (select (global.get $calls) (i32.const 0) (i32.eq (global.get $calls) (i32.const 13)))
+      )
+    )"#;
+
+    const SIMD_WAT: &str = r#"(module
+      (func (export "") (result i32)
+        (i32x4.extract_lane 0 (v128.const i32x4 1 2 3 4)))
+    )"#;
+
+    const THREADS_WAT: &str = r#"(module
+      (import "env" "memory" (memory 1 65536 shared))
+      (import "wasi" "thread-spawn" (func $thread_spawn (param i32) (result i32)))
+      (func (export "wasi_thread_start") (param $tid i32) (param $arg i32)
+        (i32.atomic.store (i32.const 0) (local.get $arg))
+      )
+      (func (export "_start") (result i32)
+        (local $spins i32)
+        (if (i32.lt_s (call $thread_spawn (i32.const 42)) (i32.const 1))
+          (then (return (i32.const -1))))
+        (local.set $spins (i32.const 1000000000))
+        (loop $wait
+          (if (i32.eqz (i32.atomic.load (i32.const 0)))
+            (then
+              (local.set $spins (i32.sub (local.get $spins) (i32.const 1)))
+              (br_if $wait (local.get $spins)))))
+        (i32.atomic.load (i32.const 0))
+      )
+    )"#;
+
     #[test]
     fn workload_run_return_1() {
         let bytes = wat::parse_str(RETURN_1_WAT).expect("error parsing wat");
@@ -180,4 +462,269 @@ mod test {
         // TODO/FIXME: we need a way to configure WASI stdout so we can capture
         // and check it here...
     }
+
+    #[test]
+    fn workload_run_fuel_limit() {
+        let bytes = wat::parse_str(LOOP_WAT).expect("error parsing wat");
+        let config = Config {
+            limits: Limits {
+                fuel: Some(1000),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        match Loader::run_with_config(&bytes, config) {
+            Err(..) => (),
+            _ => panic!("unexpected success"),
+        }
+    }
+
+    #[test]
+    fn workload_run_time_limit() {
+        let bytes = wat::parse_str(LOOP_WAT).expect("error parsing wat");
+        let config = Config {
+            limits: Limits {
+                time: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        match Loader::run_with_config(&bytes, config) {
+            Err(..) => (),
+            _ => panic!("unexpected success"),
+        }
+    }
+
+    #[test]
+    fn workload_run_cpu_time_limit() {
+        let bytes = wat::parse_str(LOOP_WAT).expect("error parsing wat");
+        let config = Config {
+            limits: Limits {
+                cpu_time: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let err = Loader::run_with_config(&bytes, config).expect_err("unexpected success");
+        assert_eq!(
+            err.to_string(),
+            "workload exceeded its CPU time limit of 1s"
+        );
+    }
+
+    #[test]
+    fn workload_run_memory() {
+        let bytes = wat::parse_str(RETURN_1_WAT).expect("error parsing wat");
+        let config = Config {
+            memory: Memory {
+                static_maximum_size: Some(1 << 20),
+                dynamic_reserved_for_growth: Some(0),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let results: Vec<i32> = Loader::run_with_config(&bytes, config)
+            .unwrap()
+            .iter()
+            .map(wasmtime::Val::unwrap_i32)
+            .collect();
+        assert_eq!(results, vec![1]);
+
+        let config = Config {
+            memory: Memory {
+                static_guard_size: Some(0),
+                dynamic_guard_size: Some(1 << 20),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        match Loader::run_with_config(&bytes, config) {
+            Err(..) => (),
+            _ => panic!("unexpected success"),
+        }
+    }
+
+    #[test]
+    fn workload_run_reactor() {
+        let bytes = wat::parse_str(REACTOR_WAT).expect("error parsing wat");
+        let config = Config {
+            handler: Some("handle".into()),
+            ..Default::default()
+        };
+        let results: Vec<i32> = Loader::run_with_config(&bytes, config)
+            .unwrap()
+            .iter()
+            .map(wasmtime::Val::unwrap_i32)
+            .collect();
+        assert_eq!(results, vec![13]);
+
+        // Without a handler, the reactor is only initialized.
+        assert!(Loader::run(&bytes).unwrap().is_empty());
+
+        for handler in ["_initialize", "missing"] {
+            let config = Config {
+                handler: Some(handler.into()),
+                ..Default::default()
+            };
+            assert!(Loader::run_with_config(&bytes, config).is_err());
+        }
+
+        let bytes = wat::parse_str(HELLO_WASI_WAT).expect("error parsing wat");
+        let config = Config {
+            handler: Some("_start".into()),
+            ..Default::default()
+        };
+        assert!(Loader::run_with_config(&bytes, config).is_err());
+    }
+
+    #[test]
+    fn workload_run_simd() {
+        let bytes = wat::parse_str(SIMD_WAT).expect("error parsing wat");
+        let results: Vec<i32> = Loader::run(&bytes)
+            .unwrap()
+            .iter()
+            .map(wasmtime::Val::unwrap_i32)
+            .collect();
+        assert_eq!(results, vec![1]);
+
+        let config = Config {
+            float: Float {
+                simd: Some(false),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        match Loader::run_with_config(&bytes, config) {
+            Err(..) => (),
+            _ => panic!("unexpected success"),
+        }
+    }
+
+    #[test]
+    fn workload_run_threads() {
+        let bytes = wat::parse_str(THREADS_WAT).expect("error parsing wat");
+
+        // Shared memories must fit within the static memory bound.
+        match Loader::run(&bytes) {
+            Err(..) => (),
+            _ => panic!("unexpected success"),
+        }
+
+        let config = Config {
+            memory: Memory {
+                static_maximum_size: Some(1 << 20),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let results: Vec<i32> = Loader::run_with_config(&bytes, config)
+            .unwrap()
+            .iter()
+            .map(wasmtime::Val::unwrap_i32)
+            .collect();
+        // The spawned thread stores its argument in the shared memory.
+        assert_eq!(results, vec![42]);
+    }
+
+    /// Returns a config running shared memories of up to 1 MiB with a `fuel` limit.
+    fn threads_config(fuel: Option<u64>) -> Config {
+        Config {
+            limits: Limits {
+                fuel,
+                ..Default::default()
+            },
+            memory: Memory {
+                static_maximum_size: Some(1 << 20),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn workload_run_threads_fuel() {
+        // Spawns a thread, if `$spawn`, and burns about 750000 units of fuel.
+        let wat = |spawn: bool| {
+            format!(
+                r#"(module
+                  (import "env" "memory" (memory 1 1 shared))
+                  (import "wasi" "thread-spawn" (func $thread_spawn (param i32) (result i32)))
+                  (func (export "wasi_thread_start") (param i32 i32))
+                  (func (export "_start") (result i32)
+                    (local $n i32)
+                    (if (i32.const {spawn})
+                      (then (drop (call $thread_spawn (i32.const 0)))))
+                    (local.set $n (i32.const 150000))
+                    (loop $burn
+                      (br_if $burn (local.tee $n (i32.sub (local.get $n) (i32.const 1)))))
+                    (i32.const 0)
+                  )
+                )"#,
+                spawn = spawn as i32,
+            )
+        };
+        let fuel = Some(1_000_000);
+
+        let bytes = wat::parse_str(wat(false)).expect("error parsing wat");
+        Loader::run_with_config(&bytes, threads_config(fuel)).unwrap();
+
+        // The spawned thread takes half of the fuel left, which is too little to finish.
+        let bytes = wat::parse_str(wat(true)).expect("error parsing wat");
+        match Loader::run_with_config(&bytes, threads_config(fuel)) {
+            Err(..) => (),
+            _ => panic!("unexpected success"),
+        }
+    }
+
+    #[test]
+    fn workload_run_threads_limit() {
+        // Spawns threads spinning until the flag at 0 is set and returns how many were spawned.
+        let bytes = wat::parse_str(
+            r#"(module
+              (import "env" "memory" (memory 1 1 shared))
+              (import "wasi" "thread-spawn" (func $thread_spawn (param i32) (result i32)))
+              (func (export "wasi_thread_start") (param i32 i32)
+                (loop $spin
+                  (br_if $spin (i32.eqz (i32.atomic.load (i32.const 0)))))
+              )
+              (func (export "_start") (result i32)
+                (local $i i32)
+                (local $spawned i32)
+                (loop $spawn
+                  (if (i32.gt_s (call $thread_spawn (i32.const 0)) (i32.const 0))
+                    (then (local.set $spawned (i32.add (local.get $spawned) (i32.const 1)))))
+                  (br_if $spawn
+                    (i32.lt_u (local.tee $i (i32.add (local.get $i) (i32.const 1))) (i32.const 80))))
+                (i32.atomic.store (i32.const 0) (i32.const 1))
+                (local.get $spawned)
+              )
+            )"#,
+        )
+        .expect("error parsing wat");
+        let results: Vec<i32> = Loader::run_with_config(&bytes, threads_config(None))
+            .unwrap()
+            .iter()
+            .map(wasmtime::Val::unwrap_i32)
+            .collect();
+        // At most `MAX_THREADS` threads run at a time.
+        assert_eq!(results, vec![64]);
+    }
+
+    #[test]
+    fn workload_run_threads_atomic_wait() {
+        let bytes = wat::parse_str(
+            r#"(module
+              (import "env" "memory" (memory 1 1 shared))
+              (func (export "_start") (result i32)
+                (memory.atomic.wait32 (i32.const 0) (i32.const 1) (i64.const 0))
+              )
+            )"#,
+        )
+        .expect("error parsing wat");
+        let err = Loader::run_with_config(&bytes, threads_config(None)).unwrap_err();
+        assert!(format!("{err:#}").contains("memory.atomic.wait"));
+    }
 }
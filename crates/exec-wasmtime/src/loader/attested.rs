@@ -1,40 +1,369 @@
 // SPDX-License-Identifier: Apache-2.0
 
-use super::{Attested, Compiled, Loader};
+use super::super::events::{self, Event};
+use super::configured::platform::Technology;
+use super::connected;
+use super::faults::Skewed;
+use super::http::{self, Http};
+use super::threads::{self, Spawner};
+use super::time::{self, Anchored};
+use super::{Attested, Compiled, Ctx, Faults, Instance, Loader};
 
-use anyhow::Result;
+use std::sync::Arc;
+use std::thread;
+
+use anyhow::{anyhow, bail, Context, Result};
+use enarx_config::{Config, Limits, Network};
+use log::warn;
+use rustls::ClientConfig;
+use wasi_common::clocks::WasiSystemClock;
+use wasmtime::{
+    Engine, ExternType, Linker, MemoryType, Module, OptLevel, SharedMemory, Store,
+    StoreLimitsBuilder, ValType, WasmBacktraceDetails,
+};
 use wasmtime_wasi::WasiCtxBuilder;
 
+/// Returns the system clock of the host, read from the time page of the shim, if any.
+fn host_clock() -> Box<dyn WasiSystemClock> {
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    if let Some((system, _)) = super::timepage::clocks() {
+        return Box::new(system);
+    }
+    wasmtime_wasi::clocks_ctx().system
+}
+
+/// Returns the size of the linear memory guard regions tuned for `technology`.
+fn guard_size(technology: Technology) -> u64 {
+    match technology {
+        // SGX enclaves are limited to 4 GiB of address space.
+        Technology::Sgx => 0,
+        Technology::Kvm | Technology::Snp => 64 * 1024,
+    }
+}
+
+/// Size of a WebAssembly page in bytes.
+const WASM_PAGE_SIZE: u64 = 64 * 1024;
+
+/// Defines the shared memories imported by `module` in `linker`.
+///
+/// Shared memories are reserved up front, so their maximum size is clamped to
+/// `static_maximum_size` and the memory limit, if any.
+fn define_shared_memories(
+    linker: &mut Linker<Ctx>,
+    engine: &Engine,
+    module: &Module,
+    static_maximum_size: u64,
+    limit: Option<u64>,
+) -> Result<()> {
+    let bound = limit.map_or(static_maximum_size, |limit| limit.min(static_maximum_size));
+    let bound = bound / WASM_PAGE_SIZE;
+
+    for import in module.imports() {
+        let ty = match import.ty() {
+            ExternType::Memory(ty) if ty.is_shared() => ty,
+            _ => continue,
+        };
+
+        let minimum = ty.minimum();
+        let maximum = ty.maximum().unwrap_or(bound).min(bound);
+        if maximum < minimum {
+            bail!(
+                "shared memory `{}::{}` of {minimum} pages exceeds the static memory bound of {bound} pages, \
+                 consider increasing `memory.static_maximum_size`",
+                import.module(),
+                import.name()
+            );
+        }
+
+        let memory = SharedMemory::new(engine, MemoryType::shared(minimum as _, maximum as _))
+            .context("failed to create shared memory")?;
+        linker.define(import.module(), import.name(), memory)?;
+    }
+    Ok(())
+}
+
+/// Checks that `handler` is a function of `module` taking no parameters and returning an `i32`,
+/// which requires `module` to be a reactor, i.e. not to export `_start`.
+fn check_handler(module: &Module, handler: &str) -> Result<()> {
+    if module.get_export("_start").is_some() {
+        bail!("handler `{handler}` cannot be used with a command module exporting `_start`");
+    }
+    match module.get_export(handler) {
+        Some(ExternType::Func(ty)) if ty.params().len() == 0 && ty.results().eq([ValType::I32]) => {
+            Ok(())
+        }
+        Some(_) => bail!("handler `{handler}` must be a function of type `() -> i32`"),
+        None => bail!("module does not export handler `{handler}`"),
+    }
+}
+
+/// Everything needed to set up the stores of the workload, i.e. the ones of the main module, of
+/// the services and of the threads they spawn
+pub(super) struct Setup {
+    engine: Engine,
+    limits: Limits,
+    clock: Option<Anchored>,
+    network: Network,
+    cltcfg: Arc<ClientConfig>,
+    faults: Option<Faults>,
+    static_maximum_size: u64,
+}
+
+impl Setup {
+    /// Returns a new store with its own WASI context and limits.
+    pub fn store(&self) -> Result<Store<Ctx>> {
+        self.store_with_fuel(self.limits.fuel)
+    }
+
+    /// Returns a new store with its own WASI context and limits, but `fuel` instead of the fuel
+    /// limit, e.g. the share of a spawned thread.
+    pub fn store_with_fuel(&self, fuel: Option<u64>) -> Result<Store<Ctx>> {
+        // Set up the store limits.
+        let mut store_limits = StoreLimitsBuilder::new();
+        if let Some(memory) = self.limits.memory {
+            let memory = memory
+                .try_into()
+                .with_context(|| format!("failed to convert memory limit `{memory}` to usize"))?;
+            store_limits = store_limits.memory_size(memory);
+        }
+
+        // Set up WASI, waiting on all files with a single exit from the keep.
+        #[allow(unused_mut)]
+        let mut wasi = WasiCtxBuilder::new().build();
+        #[cfg(unix)]
+        {
+            wasi.sched = Box::new(super::sched::Sched);
+        }
+        #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+        if let Some((system, monotonic)) = super::timepage::clocks() {
+            wasi.clocks.system = Box::new(system);
+            wasi.clocks.monotonic = Box::new(monotonic);
+        }
+        if let Some(clock) = self.clock.clone() {
+            wasi.clocks.system = Box::new(clock);
+        }
+        if let Some(Faults { clock_skew, .. }) = self.faults {
+            let clock: Box<dyn WasiSystemClock> = match self.clock.clone() {
+                Some(clock) => Box::new(clock),
+                None => host_clock(),
+            };
+            wasi.clocks.system = Box::new(Skewed::new(clock, clock_skew));
+        }
+
+        // Create the store.
+        let mut wstore = Store::new(
+            &self.engine,
+            Ctx {
+                wasi,
+                limits: store_limits.build(),
+                http: Http::new(self.network.clone(), self.cltcfg.clone()),
+            },
+        );
+        wstore.limiter(|ctx| ctx);
+        if let Some(fuel) = fuel {
+            wstore
+                .add_fuel(fuel)
+                .context("failed to add fuel to the store")?;
+        }
+
+        // The epoch is only ever incremented to interrupt the workload.
+        wstore.set_epoch_deadline(1);
+        wstore.epoch_deadline_trap();
+        Ok(wstore)
+    }
+
+    /// Sets up a new store for `module`, which is linked by `linker` once the WASI context is
+    /// complete, and checks its `handler`, if any.
+    fn instantiate(
+        self: &Arc<Self>,
+        linker: &Linker<Ctx>,
+        module: &Module,
+        handler: Option<&str>,
+    ) -> Result<Instance> {
+        if let Some(handler) = handler {
+            check_handler(module, handler)?;
+        }
+        let wstore = self.store()?;
+
+        // Define the shared memories of the module, which the threads it spawns share.
+        let mut linker = linker.clone();
+        define_shared_memories(
+            &mut linker,
+            &self.engine,
+            module,
+            self.static_maximum_size,
+            self.limits.memory,
+        )?;
+        let spawner = Spawner::new(self.clone(), linker.clone(), module.clone());
+        threads::add_to_linker(&mut linker, Arc::new(spawner))?;
+
+        Ok(Instance {
+            wstore,
+            linker,
+            module: module.clone(),
+            handler: handler.map(Into::into),
+        })
+    }
+}
+
+/// Returns the wasmtime config of the engine running the workload with `config` on `technology`.
+///
+/// Memory is never reserved statically by default, since all of it is
+/// committed up front in a keep.
+fn engine_config(config: &Config, technology: Technology) -> wasmtime::Config {
+    let memory = &config.memory;
+    let guard_size = guard_size(technology);
+    let mut wconfig = wasmtime::Config::new();
+    wconfig.wasm_multi_memory(true);
+    wconfig.wasm_threads(true);
+    wconfig.static_memory_maximum_size(memory.static_maximum_size.unwrap_or(0));
+    wconfig.static_memory_guard_size(memory.static_guard_size.unwrap_or(guard_size));
+    wconfig.dynamic_memory_guard_size(memory.dynamic_guard_size.unwrap_or(guard_size));
+    wconfig.dynamic_memory_reserved_for_growth(
+        memory
+            .dynamic_reserved_for_growth
+            .unwrap_or(16 * 1024 * 1024),
+    );
+    wconfig.wasm_simd(config.float.simd.unwrap_or(true));
+    wconfig.cranelift_nan_canonicalization(config.float.canonicalize_nans.unwrap_or(false));
+    wconfig.consume_fuel(config.limits.fuel.is_some());
+    wconfig.epoch_interruption(true);
+    wconfig
+}
+
 impl Loader<Attested> {
-    pub fn next(self) -> Result<Loader<Compiled>> {
-        // Set up the wasmtime config.
-        let mut config = wasmtime::Config::new();
-        config.wasm_multi_memory(true);
-        config.static_memory_maximum_size(0);
-        config.static_memory_guard_size(0);
-        config.dynamic_memory_guard_size(0);
-        config.dynamic_memory_reserved_for_growth(16 * 1024 * 1024);
-
-        // Create the execution engine.
-        let engine = wasmtime::Engine::new(&config)?;
+    pub fn next(mut self) -> Result<Loader<Compiled>> {
+        let limits = &self.0.config.limits;
+        let static_maximum_size = self.0.config.memory.static_maximum_size.unwrap_or(0);
+
+        // Create the execution engine. When debugging, the compiled code is registered with the
+        // GDB JIT interface along with its DWARF, such that a debugger attached to the keep can
+        // map it to the source code of the workload.
+        let mut wconfig = engine_config(&self.0.config, self.0.technology);
+        if self.0.debug {
+            wconfig.debug_info(true);
+            wconfig.wasm_backtrace_details(WasmBacktraceDetails::Enable);
+            wconfig.cranelift_opt_level(OptLevel::None);
+        }
+        let engine = Engine::new(&wconfig)?;
 
         // Set up the linker and add WASI.
-        let mut linker = wasmtime::Linker::new(&engine);
-        wasmtime_wasi::add_to_linker(&mut linker, |s| s)?;
+        let mut linker = Linker::new(&engine);
+        wasmtime_wasi::add_to_linker(&mut linker, |s: &mut Ctx| &mut s.wasi)?;
 
-        // Create the store.
-        let mut wstore = wasmtime::Store::new(&engine, WasiCtxBuilder::new().build());
+        // Add the HTTP client, which sends requests allowed by `network.outgoing` only.
+        http::add_to_linker(&mut linker)?;
 
-        // Compile and link the module.
-        let module = wasmtime::Module::from_binary(&engine, &self.0.webasm)?;
-        linker.module(&mut wstore, "", &module)?;
+        // All modules share the system clock, which starts before any of them.
+        let clock = time::clock(&self.0.config.time).context("failed to set up the clock")?;
+
+        // Every service gets its own store, such that it has its own WASI context and limits.
+        let faults = self.0.faults;
+        let setup = Arc::new(Setup {
+            engine: engine.clone(),
+            limits: limits.clone(),
+            clock,
+            network: self.0.config.network.clone(),
+            cltcfg: self.0.cltcfg.clone(),
+            faults,
+            static_maximum_size,
+        });
+        let compile =
+            |module: &Module, handler: Option<&str>| setup.instantiate(&linker, module, handler);
+        let compile_service = |(name, webasm): (String, Vec<u8>)| {
+            let module = threads::check(&webasm)
+                .and_then(|()| Module::from_binary(&engine, &webasm))
+                .with_context(|| format!("failed to compile service `{name}`"))?;
+            Ok::<_, anyhow::Error>((name, module))
+        };
+        // Every module is dropped once it is compiled, such that at most one of them is kept
+        // in memory next to the compiled code, unless the keep can create threads. Then the
+        // services are compiled in parallel to the main module and to each other.
+        let webasm = std::mem::take(&mut self.0.webasm);
+        let modules = std::mem::take(&mut self.0.modules);
+        let parallel = !modules.is_empty() && connected::threads();
+        let (module, services) = thread::scope(|scope| {
+            let mut threads = vec![];
+            let mut serial = vec![];
+            for (name, webasm) in modules {
+                if !parallel {
+                    serial.push((name, webasm));
+                    continue;
+                }
+                let thread = thread::Builder::new()
+                    .name(format!("compile-{name}"))
+                    .spawn_scoped(scope, move || compile_service((name, webasm)))
+                    .context("failed to spawn compilation thread")?;
+                threads.push(thread);
+            }
+
+            threads::check(&webasm)?;
+            // Cached modules lack debug info, so they are compiled anew when debugging.
+            #[cfg(unix)]
+            let module = match self.0.cache.take().filter(|_| !self.0.debug) {
+                Some(mut cache) => match cache.load(&engine, &webasm) {
+                    Some(module) => module,
+                    None => {
+                        let module = Module::from_binary(&engine, &webasm)?;
+                        if let Err(e) = cache.store(&webasm, &module) {
+                            warn!("failed to cache compiled module: {e:#}");
+                        }
+                        module
+                    }
+                },
+                None => Module::from_binary(&engine, &webasm)?,
+            };
+            #[cfg(windows)]
+            let module = Module::from_binary(&engine, &webasm)?;
+            drop(webasm);
+
+            let services = threads
+                .into_iter()
+                .map(|thread| {
+                    thread
+                        .join()
+                        .unwrap_or_else(|_| Err(anyhow!("compilation thread panicked")))
+                })
+                .chain(serial.into_iter().map(compile_service))
+                .collect::<Result<Vec<_>>>()?;
+            Ok::<_, anyhow::Error>((module, services))
+        })?;
+        let Instance {
+            wstore,
+            linker,
+            module,
+            ..
+        } = compile(&module, self.0.config.handler.as_deref())?;
+        let services = services
+            .into_iter()
+            .map(|(name, module)| {
+                let handler = self.0.config.services[&name].handler.as_deref();
+                let instance = compile(&module, handler)
+                    .with_context(|| format!("failed to instantiate service `{name}`"))?;
+                Ok((name, instance))
+            })
+            .collect::<Result<_>>()?;
+        events::emit(Event::WasmCompiled);
 
         Ok(Loader(Compiled {
             srvcfg: self.0.srvcfg,
             cltcfg: self.0.cltcfg,
+            certs: self.0.certs,
+            secrets: self.0.secrets,
+            data: self.0.data,
             config: self.0.config,
+            identity: self.0.identity,
+            prvkey: self.0.prvkey,
+            binding: self.0.binding,
             wstore,
             linker,
+            module,
+            services,
+            faults,
+            #[cfg(unix)]
+            listeners: self.0.listeners,
+            #[cfg(unix)]
+            logs: self.0.logs,
         }))
     }
 }
@@ -0,0 +1,130 @@
+// SPDX-License-Identifier: Apache-2.0
+//! A WasiFile yielding fresh attestation evidence of the keep
+
+use std::any::Any;
+use std::io::{self, IoSlice, IoSliceMut, Read};
+
+use wasi_common::file::{FdFlags, FileType};
+use wasi_common::{Error, ErrorExt, WasiFile};
+
+/// The size of the report data the evidence is bound to
+const DATA_SIZE: usize = 64;
+
+/// Yields fresh evidence obtained from `attest` bound to the data written to the file
+pub struct Attestation<F> {
+    attest: F,
+    data: Vec<u8>,
+    buf: io::Cursor<Vec<u8>>,
+}
+
+impl<F: FnMut(&[u8]) -> io::Result<Vec<u8>>> Attestation<F> {
+    pub fn new(attest: F) -> Self {
+        Self {
+            attest,
+            data: Vec::with_capacity(DATA_SIZE),
+            buf: Default::default(),
+        }
+    }
+
+    /// Appends `bufs` to the report data, failing if it would exceed [`DATA_SIZE`] bytes.
+    fn write(&mut self, bufs: &[IoSlice<'_>]) -> Option<usize> {
+        let n = bufs.iter().map(|buf| buf.len()).sum::<usize>();
+        if self.data.len() + n > DATA_SIZE {
+            return None;
+        }
+        bufs.iter().for_each(|buf| self.data.extend_from_slice(buf));
+        // Restart, such that the next read takes evidence bound to the new data.
+        self.buf = Default::default();
+        Some(n)
+    }
+
+    /// Reads the evidence into `bufs`, returning `0` once it has been read completely.
+    fn read(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        if self.buf.position() == 0 {
+            let mut data = [0; DATA_SIZE];
+            data[..self.data.len()].copy_from_slice(&self.data);
+            self.buf = io::Cursor::new((self.attest)(&data)?);
+        }
+        let n = self.buf.read_vectored(bufs)?;
+        if n == 0 {
+            // Rewind and clear the data, such that the next read takes new evidence.
+            self.buf = Default::default();
+            self.data.clear();
+        }
+        Ok(n)
+    }
+}
+
+#[wiggle::async_trait]
+impl<F: FnMut(&[u8]) -> io::Result<Vec<u8>> + Send + Sync + 'static> WasiFile for Attestation<F> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::CharacterDevice)
+    }
+
+    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
+        Ok(FdFlags::empty())
+    }
+
+    async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        let n = self.read(bufs).map_err(|e| Error::io().context(e))?;
+        Ok(n as _)
+    }
+
+    async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        let n = self.write(bufs).ok_or_else(|| {
+            Error::invalid_argument().context("attestation data exceeds 64 bytes")
+        })?;
+        Ok(n as _)
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Attestation;
+
+    use std::io::{IoSlice, IoSliceMut};
+
+    #[test]
+    fn binds_data() {
+        let mut attestation = Attestation::new(|data: &[u8]| Ok(data.to_vec()));
+
+        let read = |attestation: &mut Attestation<_>| {
+            let mut out = Vec::new();
+            loop {
+                let mut buf = [0; 16];
+                match attestation.read(&mut [IoSliceMut::new(&mut buf)]).unwrap() {
+                    0 => break out,
+                    n => out.extend_from_slice(&buf[..n]),
+                }
+            }
+        };
+
+        assert_eq!(read(&mut attestation), [0; 64]);
+
+        assert_eq!(
+            attestation.write(&[IoSlice::new(b"nonce"), IoSlice::new(b"!")]),
+            Some(6)
+        );
+        let evidence = read(&mut attestation);
+        assert_eq!(evidence.len(), 64);
+        assert_eq!(&evidence[..6], b"nonce!");
+        assert_eq!(evidence[6..], [0; 58]);
+
+        // The data is cleared once the evidence has been read.
+        assert_eq!(read(&mut attestation), [0; 64]);
+
+        assert_eq!(attestation.write(&[IoSlice::new(&[1; 65])]), None);
+    }
+}
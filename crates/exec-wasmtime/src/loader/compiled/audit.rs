@@ -0,0 +1,237 @@
+// SPDX-License-Identifier: Apache-2.0
+//! A WasiFile hash-chaining written lines into an audit trail anchored by the keep key
+
+use super::super::pki::{self, hex};
+
+use std::any::Any;
+use std::io::{self, IoSlice, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+use ureq::serde_json::json;
+use wasi_common::file::{FdFlags, FileType};
+use wasi_common::{Error, ErrorExt, WasiFile};
+use zeroize::Zeroizing;
+
+/// Number of entries after which the chain is anchored by default
+pub const DEFAULT_ANCHOR_INTERVAL: u64 = 64;
+
+/// Emits every line written to it as a hash-chained JSON record to `out` and anchors the chain
+/// with a signature of `prvkey` every `interval` entries and once dropped
+pub struct Audit<W: Write> {
+    out: W,
+    stream: String,
+    keep: String,
+    prvkey: Zeroizing<Vec<u8>>,
+    interval: u64,
+    /// Number of entries emitted
+    seq: u64,
+    /// Number of entries covered by the latest anchor
+    anchored: u64,
+    /// Hash of the latest entry
+    hash: [u8; 32],
+    line: Vec<u8>,
+}
+
+impl<W: Write> Audit<W> {
+    pub fn new(
+        out: W,
+        stream: impl Into<String>,
+        keep: impl Into<String>,
+        prvkey: Zeroizing<Vec<u8>>,
+        interval: u64,
+    ) -> Self {
+        Self {
+            out,
+            stream: stream.into(),
+            keep: keep.into(),
+            prvkey,
+            interval: interval.max(1),
+            seq: 0,
+            anchored: 0,
+            hash: [0; 32],
+            line: Vec::new(),
+        }
+    }
+
+    /// Emits `msg` as the next entry of the chain, anchoring it if due.
+    fn emit(&mut self, msg: &[u8]) -> io::Result<()> {
+        let time_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let msg = String::from_utf8_lossy(msg.strip_suffix(b"\r").unwrap_or(msg));
+        self.hash = Sha256::new()
+            .chain_update(self.hash)
+            .chain_update(self.seq.to_be_bytes())
+            .chain_update(time_ms.to_be_bytes())
+            .chain_update(msg.as_bytes())
+            .finalize()
+            .into();
+        let record = json!({
+            "stream": self.stream,
+            "keep": self.keep,
+            "seq": self.seq,
+            "time_ms": time_ms,
+            "msg": msg,
+            "hash": hex(&self.hash),
+        });
+        writeln!(self.out, "{record}")?;
+        self.seq += 1;
+
+        if self.seq - self.anchored >= self.interval {
+            self.anchor()?;
+        }
+        Ok(())
+    }
+
+    /// Signs the hash of the latest entry with the key of the keep.
+    fn anchor(&mut self) -> io::Result<()> {
+        let (algorithm, signature) = pki::sign(&self.prvkey, &self.hash)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{e:#}")))?;
+        let record = json!({
+            "stream": self.stream,
+            "keep": self.keep,
+            "anchor": {
+                "seq": self.seq - 1,
+                "hash": hex(&self.hash),
+            },
+            "algorithm": algorithm,
+            "signature": hex(&signature),
+        });
+        writeln!(self.out, "{record}")?;
+        self.anchored = self.seq;
+        Ok(())
+    }
+
+    /// Buffers `buf` and emits all complete lines.
+    fn write_lines(&mut self, mut buf: &[u8]) -> io::Result<()> {
+        while let Some(n) = buf.iter().position(|b| *b == b'\n') {
+            if self.line.is_empty() {
+                self.emit(&buf[..n])?;
+            } else {
+                let mut line = std::mem::take(&mut self.line);
+                line.extend_from_slice(&buf[..n]);
+                self.emit(&line)?;
+            }
+            buf = &buf[n + 1..];
+        }
+        self.line.extend_from_slice(buf);
+        Ok(())
+    }
+}
+
+impl<W: Write> Drop for Audit<W> {
+    fn drop(&mut self) {
+        // Emit the last incomplete line, if any, and anchor the rest of the chain.
+        if !self.line.is_empty() {
+            let line = std::mem::take(&mut self.line);
+            let _ = self.emit(&line);
+        }
+        if self.seq > self.anchored {
+            let _ = self.anchor();
+        }
+    }
+}
+
+#[wiggle::async_trait]
+impl<W: Write + Send + Sync + 'static> WasiFile for Audit<W> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::Pipe)
+    }
+
+    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
+        Ok(FdFlags::APPEND)
+    }
+
+    async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        let mut n = 0;
+        for buf in bufs {
+            self.write_lines(buf).map_err(|e| Error::io().context(e))?;
+            n += buf.len();
+        }
+        Ok(n as _)
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::super::pki::PrivateKeyInfoExt;
+    use super::Audit;
+
+    use const_oid::db::rfc5912::SECP_256_R_1;
+    use pkcs8::PrivateKeyInfo;
+    use ring::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_ASN1};
+    use sha2::{Digest, Sha256};
+    use ureq::serde_json::{self, Value};
+    use x509_cert::der::Decode;
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn chain() {
+        let prvkey = PrivateKeyInfo::generate(SECP_256_R_1).unwrap();
+        let mut out = Vec::new();
+        {
+            let mut audit = Audit::new(&mut out, "/attest/log", "keep", prvkey.clone(), 2);
+            audit.write_lines(b"first\nsec").unwrap();
+            audit.write_lines(b"ond\r\nthird").unwrap();
+        }
+
+        let records: Vec<Value> = serde_json::Deserializer::from_slice(&out)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 5);
+
+        // Recompute the chain.
+        let mut hash = [0u8; 32];
+        for (seq, (i, msg)) in [(0, "first"), (1, "second"), (3, "third")]
+            .into_iter()
+            .enumerate()
+        {
+            let record = &records[i];
+            assert_eq!(record["seq"], seq);
+            assert_eq!(record["msg"], msg);
+            assert_eq!(record["stream"], "/attest/log");
+            assert_eq!(record["keep"], "keep");
+            hash = Sha256::new()
+                .chain_update(hash)
+                .chain_update((seq as u64).to_be_bytes())
+                .chain_update(record["time_ms"].as_u64().unwrap().to_be_bytes())
+                .chain_update(msg)
+                .finalize()
+                .into();
+            assert_eq!(unhex(record["hash"].as_str().unwrap()), hash);
+        }
+
+        // Both anchors are signed by the key of the keep.
+        let pki = PrivateKeyInfo::from_der(&prvkey).unwrap();
+        let public = pki.public_key().unwrap().subject_public_key.to_vec();
+        for (i, entry) in [(2, 1), (4, 3)] {
+            let anchor = &records[i];
+            assert_eq!(anchor["anchor"]["seq"], records[entry]["seq"]);
+            assert_eq!(anchor["anchor"]["hash"], records[entry]["hash"]);
+            assert_eq!(anchor["algorithm"], "ecdsa-with-SHA256");
+            UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, &public)
+                .verify(
+                    &unhex(anchor["anchor"]["hash"].as_str().unwrap()),
+                    &unhex(anchor["signature"].as_str().unwrap()),
+                )
+                .unwrap();
+        }
+    }
+}
@@ -0,0 +1,258 @@
+// SPDX-License-Identifier: Apache-2.0
+//! A WasiFile connecting two services of the keep
+
+use std::any::Any;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::io::{self, IoSlice, IoSliceMut, Read};
+use std::sync::{Arc, Condvar, Mutex};
+
+use anyhow::{bail, Result};
+use enarx_config::{Config, File, MAIN_SERVICE};
+use rustix::io::Errno;
+use wasi_common::file::{FdFlags, FileType};
+use wasi_common::{Error, WasiFile};
+
+/// The bytes written to one end of a channel, which are not read yet
+#[derive(Default)]
+struct Buffer {
+    data: VecDeque<u8>,
+    writer_closed: bool,
+    reader_closed: bool,
+}
+
+/// A unidirectional pipe between the ends of a channel
+#[derive(Default)]
+struct Pipe {
+    buffer: Mutex<Buffer>,
+    ready: Condvar,
+}
+
+/// One end of a bidirectional in-keep channel
+///
+/// Writes never block, reads block until the peer has written data or has
+/// been dropped, unless the end is non-blocking.
+pub struct Channel {
+    rx: Arc<Pipe>,
+    tx: Arc<Pipe>,
+    flags: FdFlags,
+}
+
+impl Channel {
+    /// Returns both ends of a new channel.
+    pub fn pair() -> (Self, Self) {
+        let (a, b) = (Arc::new(Pipe::default()), Arc::new(Pipe::default()));
+        (
+            Self {
+                rx: a.clone(),
+                tx: b.clone(),
+                flags: FdFlags::empty(),
+            },
+            Self {
+                rx: b,
+                tx: a,
+                flags: FdFlags::empty(),
+            },
+        )
+    }
+
+    fn read(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        let mut buffer = self.rx.buffer.lock().unwrap();
+        while buffer.data.is_empty() && !buffer.writer_closed {
+            if self.flags.contains(FdFlags::NONBLOCK) {
+                return Err(Errno::AGAIN.into());
+            }
+            buffer = self.rx.ready.wait(buffer).unwrap();
+        }
+        let (front, back) = buffer.data.as_slices();
+        let n = front.chain(back).read_vectored(bufs)?;
+        buffer.data.drain(..n);
+        Ok(n)
+    }
+
+    fn write(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let mut buffer = self.tx.buffer.lock().unwrap();
+        if buffer.reader_closed {
+            return Err(Errno::PIPE.into());
+        }
+        let n = bufs.iter().fold(0, |n, buf| {
+            buffer.data.extend(buf.iter());
+            n + buf.len()
+        });
+        self.tx.ready.notify_all();
+        Ok(n)
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        self.tx.buffer.lock().unwrap().writer_closed = true;
+        self.tx.ready.notify_all();
+        self.rx.buffer.lock().unwrap().reader_closed = true;
+    }
+}
+
+#[wiggle::async_trait]
+impl WasiFile for Channel {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::SocketStream)
+    }
+
+    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
+        Ok(self.flags)
+    }
+
+    async fn set_fdflags(&mut self, flags: FdFlags) -> Result<(), Error> {
+        self.flags = flags & FdFlags::NONBLOCK;
+        Ok(())
+    }
+
+    async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        Ok(self.read(bufs)? as _)
+    }
+
+    async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        Ok(self.write(bufs)? as _)
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Returns the ends of the channels declared in `config` keyed by the service
+/// owning them and their peer.
+///
+/// Every channel must be declared by both of the services it connects.
+pub fn channels(config: &Config) -> Result<HashMap<(String, String), Channel>> {
+    let services = BTreeMap::from_iter(
+        [(MAIN_SERVICE, &config.files)]
+            .into_iter()
+            .chain(config.services.iter().map(|(n, s)| (n.as_str(), &s.files))),
+    );
+
+    let mut peers = BTreeSet::new();
+    for (&service, files) in services.iter() {
+        for file in files.iter() {
+            let peer = match file {
+                File::Channel { peer, .. } => peer.as_str(),
+                _ => continue,
+            };
+            if peer == service {
+                bail!("service `{service}` declares a channel to itself");
+            }
+            if !services.contains_key(peer) {
+                bail!("service `{service}` declares a channel to unknown service `{peer}`");
+            }
+            if !peers.insert((service, peer)) {
+                bail!("service `{service}` declares more than one channel to service `{peer}`");
+            }
+        }
+    }
+
+    let mut channels = HashMap::new();
+    for &(service, peer) in peers.iter() {
+        if !peers.contains(&(peer, service)) {
+            bail!("service `{peer}` declares no channel to service `{service}`");
+        }
+        if service < peer {
+            let (a, b) = Channel::pair();
+            channels.insert((service.into(), peer.into()), a);
+            channels.insert((peer.into(), service.into()), b);
+        }
+    }
+    Ok(channels)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{channels, Channel};
+
+    use std::io::{IoSlice, IoSliceMut};
+    use std::thread;
+
+    use enarx_config::Config;
+    use rustix::io::Errno;
+    use wasi_common::file::FdFlags;
+
+    fn read(channel: &mut Channel) -> std::io::Result<String> {
+        let mut buf = [0; 16];
+        let n = channel.read(&mut [IoSliceMut::new(&mut buf)])?;
+        Ok(String::from_utf8(buf[..n].to_vec()).unwrap())
+    }
+
+    #[test]
+    fn pair() {
+        let (mut a, mut b) = Channel::pair();
+        a.write(&[IoSlice::new(b"ping")]).unwrap();
+        assert_eq!(read(&mut b).unwrap(), "ping");
+
+        b.flags = FdFlags::NONBLOCK;
+        assert_eq!(
+            read(&mut b).unwrap_err().raw_os_error(),
+            Some(Errno::AGAIN.raw_os_error())
+        );
+
+        let peer = thread::spawn(move || read(&mut a).unwrap());
+        b.write(&[IoSlice::new(b"pong")]).unwrap();
+        assert_eq!(peer.join().unwrap(), "pong");
+
+        // `a` was dropped by the peer.
+        assert_eq!(read(&mut b).unwrap(), "");
+        assert_eq!(
+            b.write(&[IoSlice::new(b"ping")])
+                .unwrap_err()
+                .raw_os_error(),
+            Some(Errno::PIPE.raw_os_error())
+        );
+    }
+
+    #[test]
+    fn declarations() {
+        let config: Config = toml::from_str(
+            r#"
+            [[files]]
+            kind = "channel"
+            peer = "api"
+
+            [services.api]
+            module = "api.wasm"
+
+            [[services.api.files]]
+            kind = "channel"
+            peer = "main"
+            "#,
+        )
+        .unwrap();
+        let ends = channels(&config).unwrap();
+        assert_eq!(ends.len(), 2);
+        assert!(ends.contains_key(&("main".into(), "api".into())));
+        assert!(ends.contains_key(&("api".into(), "main".into())));
+
+        for (config, err) in [
+            (
+                "[[files]]\nkind = \"channel\"\npeer = \"main\"",
+                "service `main` declares a channel to itself",
+            ),
+            (
+                "[[files]]\nkind = \"channel\"\npeer = \"db\"",
+                "service `main` declares a channel to unknown service `db`",
+            ),
+            (
+                "[[files]]\nkind = \"channel\"\npeer = \"db\"\n[services.db]\nmodule = \"db.wasm\"",
+                "service `db` declares no channel to service `main`",
+            ),
+        ] {
+            let config: Config = toml::from_str(config).unwrap();
+            let e = channels(&config).err().unwrap().to_string();
+            assert_eq!(e, err);
+        }
+    }
+}
@@ -0,0 +1,83 @@
+// SPDX-License-Identifier: Apache-2.0
+//! A WasiFile, which the workload writes to in order to signal its readiness or liveness to the host
+
+use std::any::Any;
+use std::io::IoSlice;
+
+use wasi_common::file::{FdFlags, FileType};
+use wasi_common::{Error, WasiFile};
+
+/// Reports the state signalled by the data written to the file to `report`, whenever it changes
+///
+/// Every write sets the state. Writing `0`, optionally surrounded by whitespace, clears it,
+/// writing anything else, including nothing, sets it.
+pub struct Health<F> {
+    report: F,
+    state: Option<bool>,
+}
+
+impl<F: FnMut(bool)> Health<F> {
+    pub fn new(report: F) -> Self {
+        Self {
+            report,
+            state: None,
+        }
+    }
+
+    /// Sets the state signalled by `bufs`.
+    fn write(&mut self, bufs: &[IoSlice<'_>]) -> usize {
+        let data = bufs.iter().flat_map(|buf| buf.iter()).copied();
+        let mut data = data.filter(|b| !b.is_ascii_whitespace());
+        let state = !matches!((data.next(), data.next()), (Some(b'0'), None));
+        if self.state != Some(state) {
+            self.state = Some(state);
+            (self.report)(state);
+        }
+        bufs.iter().map(|buf| buf.len()).sum()
+    }
+}
+
+#[wiggle::async_trait]
+impl<F: FnMut(bool) + Send + Sync + 'static> WasiFile for Health<F> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::CharacterDevice)
+    }
+
+    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
+        Ok(FdFlags::empty())
+    }
+
+    async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        Ok(self.write(bufs) as _)
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Health;
+
+    use std::io::IoSlice;
+
+    #[test]
+    fn changes() {
+        let mut reported = vec![];
+        {
+            let mut health = Health::new(|state| reported.push(state));
+            assert_eq!(health.write(&[]), 0);
+            assert_eq!(health.write(&[IoSlice::new(b"ok\n")]), 3);
+            assert_eq!(health.write(&[IoSlice::new(b" 0"), IoSlice::new(b"\n")]), 3);
+            health.write(&[IoSlice::new(b"0\n")]);
+            health.write(&[IoSlice::new(b"00\n")]);
+            health.write(&[IoSlice::new(b"1\n")]);
+        }
+        assert_eq!(reported, vec![true, false, true]);
+    }
+}
@@ -0,0 +1,385 @@
+// SPDX-License-Identifier: Apache-2.0
+//! An HTTP/1.1 server over TLS, which hands the requests to the workload
+//!
+//! The host parses the requests and writes the responses, while the workload handles every
+//! request by a call of its `handle_http_request` export, in which it reads the request and
+//! builds the response with the functions of the `wasi_experimental_http` module. Connections are
+//! served one at a time and closed after a single request.
+
+use super::tls::{self, Deadline, Timeout};
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use log::debug;
+
+/// Default maximum size of the body of a request in bytes
+pub const DEFAULT_MAX_BODY_SIZE: usize = 1 << 20;
+
+/// Maximum size of the request line and the headers in bytes
+const MAX_HEAD_SIZE: usize = 8192;
+
+/// Interval, in which a pending accept returns to check for a shutdown request
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A request received from a client
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    pub uri: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Returns the value of the first header `name`.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A response built by the workload
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Default for Response {
+    fn default() -> Self {
+        Self::status(200)
+    }
+}
+
+impl Response {
+    /// Returns an empty response with `status`.
+    pub fn status(status: u16) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: vec![],
+        }
+    }
+}
+
+/// Returns the reason phrase of `status`, which is optional in HTTP/1.1.
+fn reason(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        505 => "HTTP Version Not Supported",
+        _ => "",
+    }
+}
+
+/// Writes `response` to `stream`, framing the body by its length.
+///
+/// The framing headers set by the workload are replaced, since the host frames the body.
+fn respond(stream: &mut impl Write, response: &Response) -> io::Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\n",
+        response.status,
+        reason(response.status)
+    );
+    for (name, value) in &response.headers {
+        if ["content-length", "connection", "transfer-encoding"]
+            .iter()
+            .any(|n| name.eq_ignore_ascii_case(n))
+        {
+            continue;
+        }
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str(&format!(
+        "content-length: {}\r\nconnection: close\r\n\r\n",
+        response.body.len()
+    ));
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(&response.body)?;
+    stream.flush()
+}
+
+/// Answers a malformed request with `status` and fails.
+fn reject(stream: &mut impl Write, status: u16) -> io::Result<Request> {
+    respond(stream, &Response::status(status))?;
+    Err(io::Error::new(
+        ErrorKind::InvalidData,
+        format!("rejected HTTP request: {status} {}", reason(status)),
+    ))
+}
+
+/// Reads a request with a body of at most `max_body_size` bytes from `stream`.
+fn request(stream: &mut (impl Read + Write), max_body_size: usize) -> io::Result<Request> {
+    let mut buf = vec![];
+    let end = loop {
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end;
+        }
+        if buf.len() > MAX_HEAD_SIZE {
+            return reject(stream, 431);
+        }
+
+        let mut chunk = [0; 1024];
+        match stream.read(&mut chunk) {
+            Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => continue,
+            Err(e) => return Err(e),
+        }
+    };
+    let mut body = buf.split_off(end + 4);
+
+    let head = match std::str::from_utf8(&buf[..end]) {
+        Ok(head) => head,
+        Err(..) => return reject(stream, 400),
+    };
+    let mut lines = head.split("\r\n");
+    let (method, uri, version) = match lines
+        .next()
+        .unwrap_or_default()
+        .split(' ')
+        .collect::<Vec<_>>()[..]
+    {
+        [method, uri, version] if !method.is_empty() && !uri.is_empty() => (method, uri, version),
+        _ => return reject(stream, 400),
+    };
+    if version != "HTTP/1.1" && version != "HTTP/1.0" {
+        return reject(stream, 505);
+    }
+    let mut headers = vec![];
+    for line in lines {
+        match line.split_once(':') {
+            Some((name, value)) if !name.is_empty() && !name.contains(char::is_whitespace) => {
+                headers.push((name.into(), value.trim().into()))
+            }
+            _ => return reject(stream, 400),
+        }
+    }
+    let mut request = Request {
+        method: method.into(),
+        uri: uri.into(),
+        headers,
+        body: vec![],
+    };
+
+    if request.header("transfer-encoding").is_some() {
+        return reject(stream, 411);
+    }
+    let length = match request.header("content-length").map(str::parse::<usize>) {
+        None => 0,
+        Some(Ok(length)) if length <= max_body_size => length,
+        Some(Ok(..)) => return reject(stream, 413),
+        Some(Err(..)) => return reject(stream, 400),
+    };
+    if body.len() > length {
+        return reject(stream, 400);
+    }
+    if body.len() < length
+        && request
+            .header("expect")
+            .map_or(false, |v| v.eq_ignore_ascii_case("100-continue"))
+    {
+        stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+        stream.flush()?;
+    }
+    let start = body.len();
+    body.resize(length, 0);
+    stream.read_exact(&mut body[start..])?;
+    request.body = body;
+    Ok(request)
+}
+
+/// A TLS stream, the writes of which do not wait for data from the client
+struct Sender(tls::Stream);
+
+impl Timeout for Sender {
+    fn timeout(&self) -> io::Result<Option<Duration>> {
+        self.0.timeout()
+    }
+
+    fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.0.set_timeout(timeout)
+    }
+}
+
+impl Read for Sender {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for Sender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.send(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A connection accepted by the server
+///
+/// The request must be received and the response sent within the handshake timeout each, such
+/// that a slow client cannot hold up the server.
+pub struct Connection {
+    stream: Sender,
+    timeout: Duration,
+    max_body_size: usize,
+}
+
+impl Connection {
+    /// Reads the request of the client.
+    pub fn request(&mut self) -> io::Result<Request> {
+        request(
+            &mut Deadline::new(&mut self.stream, self.timeout),
+            self.max_body_size,
+        )
+    }
+
+    /// Writes `response` to the client and closes the connection.
+    pub fn respond(mut self, response: &Response) -> io::Result<()> {
+        respond(&mut Deadline::new(&mut self.stream, self.timeout), response)
+    }
+}
+
+/// The server of a listen socket with `prot = "https"`
+pub struct Server {
+    listener: tls::Listener,
+    idle_timeout: Option<Duration>,
+    max_body_size: usize,
+}
+
+impl Server {
+    /// Returns a server accepting connections on `listener`, which get `idle_timeout`.
+    pub fn new(
+        listener: tls::Listener,
+        idle_timeout: Option<Duration>,
+        max_body_size: usize,
+    ) -> Self {
+        Self {
+            listener,
+            idle_timeout,
+            max_body_size,
+        }
+    }
+
+    /// Accepts a connection, returning `None` if no client connected within the accept interval.
+    pub fn accept(&mut self) -> Result<Option<Connection>> {
+        let stream = match self.listener.accept(Some(ACCEPT_INTERVAL)) {
+            Ok(stream) => stream,
+            Err(e) if matches!(e.downcast_ref(), Some(wasi_common::ErrorKind::WouldBlk)) => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        };
+        stream
+            .set_timeout(self.idle_timeout)
+            .context("failed to set the idle timeout of the connection")?;
+        debug!("accepted HTTP connection");
+        Ok(Some(Connection {
+            stream: Sender(stream),
+            timeout: self.listener.timeout(),
+            max_body_size: self.max_body_size,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::os::unix::net::UnixStream;
+
+    fn response(mut client: &UnixStream) -> String {
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn request_body() {
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+        client
+            .write_all(
+                b"POST /echo?x=1 HTTP/1.1\r\nHost: example.com\r\nContent-Length: 5\r\n\r\nhello",
+            )
+            .unwrap();
+
+        let request = request(&mut server, DEFAULT_MAX_BODY_SIZE).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.uri, "/echo?x=1");
+        assert_eq!(request.header("host"), Some("example.com"));
+        assert_eq!(request.body, b"hello");
+    }
+
+    #[test]
+    fn rejected() {
+        for (input, status) in [
+            (&b"GET /\r\n\r\n"[..], "400"),
+            (b"GET / HTTP/2.0\r\n\r\n", "505"),
+            (b"GET / HTTP/1.1\r\nbroken\r\n\r\n", "400"),
+            (
+                b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n",
+                "411",
+            ),
+            (b"POST / HTTP/1.1\r\nContent-Length: 17\r\n\r\n", "413"),
+        ] {
+            let (mut client, mut server) = UnixStream::pair().unwrap();
+            client.write_all(input).unwrap();
+            assert!(request(&mut server, 16).is_err());
+            drop(server);
+            assert!(response(&client).starts_with(&format!("HTTP/1.1 {status} ")));
+        }
+
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+        client.write_all(&[b'a'; MAX_HEAD_SIZE + 2]).unwrap();
+        assert!(request(&mut server, 16).is_err());
+        drop(server);
+        assert!(response(&client).starts_with("HTTP/1.1 431 "));
+    }
+
+    #[test]
+    fn response_framing() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let response = Response {
+            status: 201,
+            headers: vec![
+                ("content-type".into(), "text/plain".into()),
+                ("Content-Length".into(), "1000".into()),
+            ],
+            body: b"created".to_vec(),
+        };
+        respond(&mut server, &response).unwrap();
+        drop(server);
+        assert_eq!(
+            super::test::response(&client),
+            "HTTP/1.1 201 Created\r\n\
+             content-type: text/plain\r\n\
+             content-length: 7\r\n\
+             connection: close\r\n\r\n\
+             created"
+        );
+    }
+}
@@ -0,0 +1,259 @@
+// SPDX-License-Identifier: Apache-2.0
+//! A WasiFile deriving keys from a secret of the keep
+
+use std::any::Any;
+use std::io::{self, IoSlice, IoSliceMut, Read};
+
+use ring::hkdf::{Salt, HKDF_SHA256};
+use sha2::{Digest, Sha256};
+use wasi_common::file::{FdFlags, FileType};
+use wasi_common::{Error, ErrorExt, WasiFile};
+use zeroize::Zeroizing;
+
+/// The maximum size of a label
+const LABEL_SIZE: usize = 256;
+
+/// The size of the derived keys
+pub const KEY_SIZE: usize = 32;
+
+/// Info of the HKDF expansion of the secret, preceding the label
+const KEY_INFO: &[u8] = b"enarx derived key";
+
+/// Returns the SHA-256 digests of the Wasm module and the config of the workload, which the keys
+/// are bound to, such that another workload sharing the secret derives different keys.
+pub fn binding(webasm: &[u8], config: Option<&str>) -> Vec<u8> {
+    let config = config.unwrap_or_default().as_bytes();
+    [Sha256::digest(webasm), Sha256::digest(config)].concat()
+}
+
+/// Returns the key derived from `secret` for `label` with HKDF-SHA256, salted with `binding`.
+fn derive(secret: &[u8], binding: &[u8], label: &[u8]) -> io::Result<Zeroizing<Vec<u8>>> {
+    let mut key = Zeroizing::new(vec![0; KEY_SIZE]);
+    Salt::new(HKDF_SHA256, binding)
+        .extract(secret)
+        .expand(&[KEY_INFO, label], HKDF_SHA256)
+        .and_then(|okm| okm.fill(&mut key))
+        .map_err(|_| io::Error::new(io::ErrorKind::Other, "failed to derive key"))?;
+    Ok(key)
+}
+
+/// Yields the key derived from `secret` for the workload and the label written to the file
+///
+/// Keys are only derived for the allowed `labels`, any if empty, and at most `max_uses` times.
+/// The policy is part of the config of the workload, which the keys are bound to.
+pub struct Keys {
+    secret: Zeroizing<Vec<u8>>,
+    binding: Vec<u8>,
+    labels: Vec<String>,
+    uses: Option<u64>,
+    label: Vec<u8>,
+    buf: io::Cursor<Zeroizing<Vec<u8>>>,
+}
+
+impl Keys {
+    pub fn new(
+        secret: Zeroizing<Vec<u8>>,
+        binding: Vec<u8>,
+        labels: Vec<String>,
+        max_uses: Option<u64>,
+    ) -> Self {
+        Self {
+            secret,
+            binding,
+            labels,
+            uses: max_uses,
+            label: Vec::with_capacity(LABEL_SIZE),
+            buf: Default::default(),
+        }
+    }
+
+    /// Returns whether the policy allows deriving a key for the current label.
+    fn allowed(&self) -> bool {
+        self.labels.is_empty()
+            || self
+                .labels
+                .iter()
+                .any(|allowed| match allowed.strip_suffix('*') {
+                    Some(prefix) => self.label.starts_with(prefix.as_bytes()),
+                    None => self.label == allowed.as_bytes(),
+                })
+    }
+
+    /// Appends `bufs` to the label, failing if it would exceed [`LABEL_SIZE`] bytes.
+    fn write(&mut self, bufs: &[IoSlice<'_>]) -> Option<usize> {
+        let n = bufs.iter().map(|buf| buf.len()).sum::<usize>();
+        if self.label.len() + n > LABEL_SIZE {
+            return None;
+        }
+        bufs.iter()
+            .for_each(|buf| self.label.extend_from_slice(buf));
+        // Restart, such that the next read derives the key for the new label.
+        self.buf = Default::default();
+        Some(n)
+    }
+
+    /// Reads the derived key into `bufs`, returning `0` once it has been read completely.
+    ///
+    /// Fails with [`io::ErrorKind::PermissionDenied`], if the policy does not allow deriving the
+    /// key.
+    fn read(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        if self.buf.position() == 0 {
+            if !self.allowed() {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "key label is not allowed",
+                ));
+            }
+            match self.uses {
+                Some(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        "maximum number of derived keys reached",
+                    ))
+                }
+                Some(ref mut uses) => *uses -= 1,
+                None => {}
+            }
+            self.buf = io::Cursor::new(derive(&self.secret, &self.binding, &self.label)?);
+        }
+        let n = self.buf.read_vectored(bufs)?;
+        if n == 0 {
+            // Rewind and clear the label, such that the next read derives a key for a new one.
+            self.buf = Default::default();
+            self.label.clear();
+        }
+        Ok(n)
+    }
+}
+
+#[wiggle::async_trait]
+impl WasiFile for Keys {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::CharacterDevice)
+    }
+
+    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
+        Ok(FdFlags::empty())
+    }
+
+    async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        let n = self.read(bufs).map_err(|e| match e.kind() {
+            io::ErrorKind::PermissionDenied => Error::not_capable().context(e),
+            _ => Error::io().context(e),
+        })?;
+        Ok(n as _)
+    }
+
+    async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        let n = self
+            .write(bufs)
+            .ok_or_else(|| Error::invalid_argument().context("key label exceeds 256 bytes"))?;
+        Ok(n as _)
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{binding, Keys, KEY_SIZE};
+
+    use std::io::{self, IoSlice, IoSliceMut};
+
+    use zeroize::Zeroizing;
+
+    fn read(keys: &mut Keys) -> Vec<u8> {
+        let mut out = Vec::new();
+        loop {
+            let mut buf = [0; 16];
+            match keys.read(&mut [IoSliceMut::new(&mut buf)]).unwrap() {
+                0 => break out,
+                n => out.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    #[test]
+    fn derives_keys() {
+        let workload = binding(b"\0asm", Some("args = []"));
+        let mut keys = Keys::new(
+            Zeroizing::new(b"secret".to_vec()),
+            workload.clone(),
+            vec![],
+            None,
+        );
+        let mut other = Keys::new(
+            Zeroizing::new(b"other secret".to_vec()),
+            workload,
+            vec![],
+            None,
+        );
+
+        assert_eq!(
+            keys.write(&[IoSlice::new(b"db"), IoSlice::new(b"/1")]),
+            Some(4)
+        );
+        let db = read(&mut keys);
+        assert_eq!(db.len(), KEY_SIZE);
+
+        // The label is cleared once the key has been read.
+        let empty = read(&mut keys);
+        assert_ne!(empty, db);
+
+        // Keys only depend on the secret, the workload and the label.
+        keys.write(&[IoSlice::new(b"db/1")]);
+        assert_eq!(read(&mut keys), db);
+        other.write(&[IoSlice::new(b"db/1")]);
+        assert_ne!(read(&mut other), db);
+
+        // Other workloads derive other keys from the same secret.
+        for workload in [
+            binding(b"\0asm\x01", Some("args = []")),
+            binding(b"\0asm", Some("args = [\"1\"]")),
+            binding(b"\0asm", None),
+        ] {
+            let mut other = Keys::new(Zeroizing::new(b"secret".to_vec()), workload, vec![], None);
+            other.write(&[IoSlice::new(b"db/1")]);
+            assert_ne!(read(&mut other), db);
+        }
+
+        assert_eq!(keys.write(&[IoSlice::new(&[1; 257])]), None);
+    }
+
+    #[test]
+    fn policy() {
+        let labels = vec!["db".into(), "backup/*".into()];
+        let mut keys = Keys::new(
+            Zeroizing::new(b"secret".to_vec()),
+            binding(b"\0asm", None),
+            labels,
+            Some(2),
+        );
+        let mut buf = [0; KEY_SIZE];
+        let mut derive = |label: &[u8]| {
+            keys.write(&[IoSlice::new(label)]);
+            let res = keys.read(&mut [IoSliceMut::new(&mut buf)]);
+            // Rewind, such that the next read derives a key for a new label.
+            keys.buf = Default::default();
+            keys.label.clear();
+            res.map_err(|e| e.kind())
+        };
+
+        assert_eq!(derive(b"db"), Ok(KEY_SIZE));
+        assert_eq!(derive(b"db/1"), Err(io::ErrorKind::PermissionDenied));
+        assert_eq!(derive(b"backup"), Err(io::ErrorKind::PermissionDenied));
+        assert_eq!(derive(b"backup/1"), Ok(KEY_SIZE));
+        // The maximum number of keys has been derived.
+        assert_eq!(derive(b"db"), Err(io::ErrorKind::PermissionDenied));
+    }
+}
@@ -0,0 +1,217 @@
+// SPDX-License-Identifier: Apache-2.0
+//! A WasiFile emitting written lines as structured log records
+
+use std::any::Any;
+#[cfg(unix)]
+use std::collections::BTreeMap;
+use std::io::{self, IoSlice, Write};
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(unix)]
+use anyhow::anyhow;
+#[cfg(unix)]
+use rustix::fd::BorrowedFd;
+use ureq::serde_json::json;
+use wasi_common::file::{FdFlags, FileType};
+use wasi_common::{Error, ErrorExt, WasiFile};
+
+/// Emits every line written to it as a JSON log record to `out`
+pub struct Log<W: Write> {
+    out: W,
+    stream: String,
+    keep: String,
+    line: Vec<u8>,
+}
+
+impl<W: Write> Log<W> {
+    pub fn new(out: W, stream: impl Into<String>, keep: impl Into<String>) -> Self {
+        Self {
+            out,
+            stream: stream.into(),
+            keep: keep.into(),
+            line: Vec::new(),
+        }
+    }
+
+    /// Emits `msg` as a single log record.
+    fn emit(&mut self, msg: &[u8]) -> io::Result<()> {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let msg = String::from_utf8_lossy(msg.strip_suffix(b"\r").unwrap_or(msg));
+        let record = json!({
+            "ts": ts,
+            "stream": self.stream,
+            "keep": self.keep,
+            "msg": msg,
+        });
+        writeln!(self.out, "{record}")
+    }
+
+    /// Buffers `buf` and emits all complete lines.
+    fn write_lines(&mut self, mut buf: &[u8]) -> io::Result<()> {
+        while let Some(n) = buf.iter().position(|b| *b == b'\n') {
+            if self.line.is_empty() {
+                self.emit(&buf[..n])?;
+            } else {
+                let mut line = std::mem::take(&mut self.line);
+                line.extend_from_slice(&buf[..n]);
+                self.emit(&line)?;
+            }
+            buf = &buf[n + 1..];
+        }
+        self.line.extend_from_slice(buf);
+        Ok(())
+    }
+}
+
+impl<W: Write> Drop for Log<W> {
+    fn drop(&mut self) {
+        // Emit the last incomplete line, if any.
+        if !self.line.is_empty() {
+            let line = std::mem::take(&mut self.line);
+            let _ = self.emit(&line);
+        }
+    }
+}
+
+/// A file of the host opened for appending by the host, to which complete lines are written at
+/// once, such that the records of several files writing to it do not interleave
+#[cfg(unix)]
+pub struct HostFile {
+    fd: RawFd,
+    buf: Vec<u8>,
+}
+
+#[cfg(unix)]
+impl HostFile {
+    /// Returns the file at `path`, whose file descriptor the host passed in `logs`.
+    pub fn new(logs: &BTreeMap<String, RawFd>, path: Option<&str>) -> anyhow::Result<Self> {
+        let path = path.ok_or_else(|| anyhow!("`target = \"file\"` requires a `path`"))?;
+        let fd = logs.get(path).ok_or_else(|| {
+            anyhow!("log file `{path}` was not opened by the host, which requires a local config")
+        })?;
+        Ok(Self {
+            fd: *fd,
+            buf: Vec::new(),
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Write for HostFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        if let Some(end) = self.buf.iter().rposition(|b| *b == b'\n') {
+            // SAFETY: The file descriptor is managed by the host, so it is never closed.
+            let fd = unsafe { BorrowedFd::borrow_raw(self.fd) };
+            let mut lines = &self.buf[..=end];
+            while !lines.is_empty() {
+                let n = rustix::io::write(fd, lines)?;
+                lines = &lines[n..];
+            }
+            self.buf.drain(..=end);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[wiggle::async_trait]
+impl<W: Write + Send + Sync + 'static> WasiFile for Log<W> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::Pipe)
+    }
+
+    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
+        Ok(FdFlags::APPEND)
+    }
+
+    async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        let mut n = 0;
+        for buf in bufs {
+            self.write_lines(buf).map_err(|e| Error::io().context(e))?;
+            n += buf.len();
+        }
+        Ok(n as _)
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Log;
+    #[cfg(unix)]
+    use super::{BTreeMap, HostFile};
+
+    #[cfg(unix)]
+    use std::io::{Read, Write};
+    #[cfg(unix)]
+    use std::os::unix::io::AsRawFd;
+    #[cfg(unix)]
+    use std::os::unix::net::UnixStream;
+
+    use ureq::serde_json::{self, Value};
+
+    #[test]
+    fn lines() {
+        let mut out = Vec::new();
+        {
+            let mut log = Log::new(&mut out, "stdout", "keep");
+            log.write_lines(b"hello").unwrap();
+            log.write_lines(b", world!\r\nsecond\nthi").unwrap();
+            log.write_lines(b"rd").unwrap();
+        }
+
+        let records: Vec<Value> = serde_json::Deserializer::from_slice(&out)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            records
+                .iter()
+                .map(|r| r["msg"].as_str().unwrap())
+                .collect::<Vec<_>>(),
+            vec!["hello, world!", "second", "third"]
+        );
+        for record in records {
+            assert_eq!(record["stream"], "stdout");
+            assert_eq!(record["keep"], "keep");
+            assert!(record["ts"].is_f64());
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn host_file() {
+        let (keep, mut host) = UnixStream::pair().unwrap();
+        let logs = BTreeMap::from([("/var/log/app.log".into(), keep.as_raw_fd())]);
+        assert!(HostFile::new(&logs, None).is_err());
+        assert!(HostFile::new(&logs, Some("/var/log/other.log")).is_err());
+
+        let mut a = HostFile::new(&logs, Some("/var/log/app.log")).unwrap();
+        let mut b = HostFile::new(&logs, Some("/var/log/app.log")).unwrap();
+        // Only complete lines are written, so the lines of `a` and `b` do not interleave.
+        write!(a, "{{\"msg\":").unwrap();
+        writeln!(b, "{{\"msg\":\"b\"}}").unwrap();
+        writeln!(a, "\"a\"}}").unwrap();
+        drop(keep);
+
+        let mut out = String::new();
+        host.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "{\"msg\":\"b\"}\n{\"msg\":\"a\"}\n");
+    }
+}
@@ -0,0 +1,491 @@
+// SPDX-License-Identifier: Apache-2.0
+//! A WasiFile recording and limiting the traffic of a network stream and the connections of a listener
+
+use super::tune;
+
+use crate::metrics::METRICS;
+
+use std::any::Any;
+use std::io::{self, IoSlice, IoSliceMut, SeekFrom};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(windows)]
+use io_extras::os::windows::RawHandleOrSocket;
+use rustix::io::Errno;
+use socket2::SockRef;
+use wasi_common::file::{Advice, FdFlags, FileType, Filestat, RiFlags, RoFlags, SdFlags, SiFlags};
+use wasi_common::{Error, ErrorExt, ErrorKind, SystemTimeSpec, WasiFile};
+
+/// Limit on the number of concurrently open connections accepted on a listener
+struct Limit {
+    max: usize,
+    open: Arc<AtomicUsize>,
+}
+
+impl Limit {
+    /// Reserves a connection, unless `max` connections are open already.
+    fn acquire(&self) -> Option<Permit> {
+        self.open
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+                (open < self.max).then_some(open + 1)
+            })
+            .ok()?;
+        Some(Permit(self.open.clone()))
+    }
+}
+
+/// An open connection counted against a [`Limit`], released on drop
+struct Permit(Arc<AtomicUsize>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Returns the prefix of `bufs` holding at most `max` bytes.
+fn truncate<'a>(bufs: &'a [IoSlice<'_>], max: u64) -> Vec<IoSlice<'a>> {
+    let mut max = usize::try_from(max).unwrap_or(usize::MAX);
+    bufs.iter()
+        .map(|buf| {
+            let n = buf.len().min(max);
+            max -= n;
+            IoSlice::new(&buf[..n])
+        })
+        .collect()
+}
+
+/// Returns the prefix of `bufs` holding at most `max` bytes.
+fn truncate_mut<'a>(bufs: &'a mut [IoSliceMut<'_>], max: u64) -> Vec<IoSliceMut<'a>> {
+    let mut max = usize::try_from(max).unwrap_or(usize::MAX);
+    bufs.iter_mut()
+        .map(|buf| {
+            let n = buf.len().min(max);
+            max -= n;
+            IoSliceMut::new(&mut buf[..n])
+        })
+        .collect()
+}
+
+/// Records the bytes read from and written to `file` as the traffic of the stream `name`
+///
+/// Connections accepted on a metered listener are metered under the name of the listener.
+/// If the listener is limited, connections accepted beyond the limit are closed immediately.
+/// If the stream has a quota, reads and writes fail with `EDQUOT` once it is used up.
+/// If the listener has an idle timeout, it is set on the connections accepted on it.
+pub struct Metered {
+    name: Arc<str>,
+    file: Box<dyn WasiFile>,
+    limit: Option<Limit>,
+    idle_timeout: Option<Duration>,
+    quota: Option<u64>,
+    _permit: Option<Permit>,
+}
+
+impl Metered {
+    pub fn new(name: impl Into<Arc<str>>, file: Box<dyn WasiFile>) -> Self {
+        Self {
+            name: name.into(),
+            file,
+            limit: None,
+            idle_timeout: None,
+            quota: None,
+            _permit: None,
+        }
+    }
+
+    /// Limits the number of concurrently open connections accepted on the listener to `max`.
+    pub fn limit(mut self, max: usize) -> Self {
+        self.limit = Some(Limit {
+            max,
+            open: Default::default(),
+        });
+        self
+    }
+
+    /// Makes blocking reads and writes on connections accepted on the listener fail after
+    /// waiting for `timeout`.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Limits the number of bytes read from and written to the stream in total to `max`.
+    pub fn quota(mut self, max: u64) -> Self {
+        self.quota = Some(max);
+        self
+    }
+
+    /// Returns the number of bytes left in the quota of the stream, if it has one.
+    fn remaining(&self) -> Result<Option<u64>, Error> {
+        match self.quota {
+            Some(0) => Err(io::Error::from(Errno::DQUOT).into()),
+            quota => Ok(quota),
+        }
+    }
+
+    /// Closes a pending connection without performing any handshake on it.
+    #[cfg(unix)]
+    async fn reject(&mut self, _fdflags: FdFlags) -> Result<(), Error> {
+        let fd = self.file.pollable().ok_or_else(Error::badf)?;
+        rustix::net::accept(fd)?;
+        Ok(())
+    }
+
+    /// Closes a pending connection.
+    #[cfg(windows)]
+    async fn reject(&mut self, fdflags: FdFlags) -> Result<(), Error> {
+        self.file.sock_accept(fdflags).await?;
+        Ok(())
+    }
+
+    /// Sets the idle timeout of the listener on `file`, a connection accepted on it.
+    #[cfg(unix)]
+    fn tune(&self, file: &dyn WasiFile) -> Result<(), Error> {
+        if let Some(timeout) = self.idle_timeout {
+            let fd = file.pollable().ok_or_else(Error::badf)?;
+            tune(SockRef::from(&fd), None, Some(timeout))?;
+        }
+        Ok(())
+    }
+
+    /// Sets the idle timeout of the listener on `file`, a connection accepted on it.
+    #[cfg(windows)]
+    fn tune(&self, file: &dyn WasiFile) -> Result<(), Error> {
+        use std::os::windows::io::BorrowedSocket;
+
+        if let Some(timeout) = self.idle_timeout {
+            let socket = file
+                .pollable()
+                .and_then(|handle| handle.as_raw_socket())
+                .ok_or_else(Error::badf)?;
+            // SAFETY: The socket is owned by `file`, which outlives the borrow.
+            let socket = unsafe { BorrowedSocket::borrow_raw(socket) };
+            tune(SockRef::from(&socket), None, Some(timeout))?;
+        }
+        Ok(())
+    }
+
+    fn received(&mut self, n: u64) -> u64 {
+        METRICS.lock().unwrap().stream(&self.name).received += n;
+        self.consume(n)
+    }
+
+    fn sent(&mut self, n: u64) -> u64 {
+        METRICS.lock().unwrap().stream(&self.name).sent += n;
+        self.consume(n)
+    }
+
+    fn consume(&mut self, n: u64) -> u64 {
+        if let Some(ref mut quota) = self.quota {
+            *quota = quota.saturating_sub(n);
+        }
+        n
+    }
+}
+
+impl From<Metered> for Box<dyn WasiFile> {
+    fn from(value: Metered) -> Self {
+        Box::new(value)
+    }
+}
+
+#[wiggle::async_trait]
+impl WasiFile for Metered {
+    fn as_any(&self) -> &dyn Any {
+        self.file.as_any()
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        self.file.get_filetype().await
+    }
+
+    #[cfg(unix)]
+    fn pollable(&self) -> Option<rustix::fd::BorrowedFd<'_>> {
+        self.file.pollable()
+    }
+
+    #[cfg(windows)]
+    fn pollable(&self) -> Option<RawHandleOrSocket> {
+        self.file.pollable()
+    }
+
+    fn isatty(&mut self) -> bool {
+        self.file.isatty()
+    }
+
+    async fn sock_accept(&mut self, fdflags: FdFlags) -> Result<Box<dyn WasiFile>, Error> {
+        let permit = match self.limit.as_ref().map(Limit::acquire) {
+            Some(None) => {
+                self.reject(fdflags).await?;
+                return Err(ErrorKind::WouldBlk.into());
+            }
+            permit => permit.flatten(),
+        };
+        let file = self.file.sock_accept(fdflags).await?;
+        self.tune(&*file)?;
+        METRICS.lock().unwrap().stream(&self.name).connections += 1;
+        Ok(Box::new(Self {
+            name: self.name.clone(),
+            file,
+            limit: None,
+            idle_timeout: None,
+            quota: None,
+            _permit: permit,
+        }))
+    }
+
+    async fn sock_recv<'a>(
+        &mut self,
+        ri_data: &mut [IoSliceMut<'a>],
+        ri_flags: RiFlags,
+    ) -> Result<(u64, RoFlags), Error> {
+        let (n, flags) = match self.remaining()? {
+            Some(max) => {
+                let mut ri_data = truncate_mut(ri_data, max);
+                self.file.sock_recv(&mut ri_data, ri_flags).await?
+            }
+            None => self.file.sock_recv(ri_data, ri_flags).await?,
+        };
+        Ok((self.received(n), flags))
+    }
+
+    async fn sock_send<'a>(
+        &mut self,
+        si_data: &[IoSlice<'a>],
+        si_flags: SiFlags,
+    ) -> Result<u64, Error> {
+        let n = match self.remaining()? {
+            Some(max) => {
+                self.file
+                    .sock_send(&truncate(si_data, max), si_flags)
+                    .await?
+            }
+            None => self.file.sock_send(si_data, si_flags).await?,
+        };
+        Ok(self.sent(n))
+    }
+
+    async fn sock_shutdown(&mut self, how: SdFlags) -> Result<(), Error> {
+        self.file.sock_shutdown(how).await
+    }
+
+    async fn datasync(&mut self) -> Result<(), Error> {
+        self.file.datasync().await
+    }
+
+    async fn sync(&mut self) -> Result<(), Error> {
+        self.file.sync().await
+    }
+
+    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
+        self.file.get_fdflags().await
+    }
+
+    async fn set_fdflags(&mut self, flags: FdFlags) -> Result<(), Error> {
+        self.file.set_fdflags(flags).await
+    }
+
+    async fn get_filestat(&mut self) -> Result<Filestat, Error> {
+        self.file.get_filestat().await
+    }
+
+    async fn set_filestat_size(&mut self, size: u64) -> Result<(), Error> {
+        self.file.set_filestat_size(size).await
+    }
+
+    async fn advise(&mut self, offset: u64, len: u64, advice: Advice) -> Result<(), Error> {
+        self.file.advise(offset, len, advice).await
+    }
+
+    async fn allocate(&mut self, offset: u64, len: u64) -> Result<(), Error> {
+        self.file.allocate(offset, len).await
+    }
+
+    async fn set_times(
+        &mut self,
+        atime: Option<SystemTimeSpec>,
+        mtime: Option<SystemTimeSpec>,
+    ) -> Result<(), Error> {
+        self.file.set_times(atime, mtime).await
+    }
+
+    async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        let n = match self.remaining()? {
+            Some(max) => {
+                self.file
+                    .read_vectored(&mut truncate_mut(bufs, max))
+                    .await?
+            }
+            None => self.file.read_vectored(bufs).await?,
+        };
+        Ok(self.received(n))
+    }
+
+    async fn read_vectored_at<'a>(
+        &mut self,
+        bufs: &mut [IoSliceMut<'a>],
+        offset: u64,
+    ) -> Result<u64, Error> {
+        let n = self.file.read_vectored_at(bufs, offset).await?;
+        Ok(self.received(n))
+    }
+
+    async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        let n = match self.remaining()? {
+            Some(max) => self.file.write_vectored(&truncate(bufs, max)).await?,
+            None => self.file.write_vectored(bufs).await?,
+        };
+        Ok(self.sent(n))
+    }
+
+    async fn write_vectored_at<'a>(
+        &mut self,
+        bufs: &[IoSlice<'a>],
+        offset: u64,
+    ) -> Result<u64, Error> {
+        let n = self.file.write_vectored_at(bufs, offset).await?;
+        Ok(self.sent(n))
+    }
+
+    async fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error> {
+        self.file.seek(pos).await
+    }
+
+    async fn peek(&mut self, buf: &mut [u8]) -> Result<u64, Error> {
+        self.file.peek(buf).await
+    }
+
+    async fn num_ready_bytes(&self) -> Result<u64, Error> {
+        self.file.num_ready_bytes().await
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        self.file.readable().await
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        self.file.writable().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Metered;
+    use crate::metrics::METRICS;
+
+    use std::io::{IoSlice, IoSliceMut, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::time::Duration;
+
+    use rustix::io::Errno;
+    use socket2::SockRef;
+    use wasi_common::file::FdFlags;
+    use wasi_common::WasiFile;
+    use wasmtime_wasi::net::Socket;
+
+    #[test]
+    fn traffic() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let listener = Socket::from(cap_std::net::TcpListener::from_std(listener)).into();
+        let mut listener = Metered::new("metered-traffic", listener);
+
+        let mut server = wiggle::run_in_dummy_executor(listener.sock_accept(FdFlags::empty()))
+            .unwrap()
+            .unwrap();
+
+        client.write_all(b"ping").unwrap();
+        let mut buf = [0; 4];
+        let n =
+            wiggle::run_in_dummy_executor(server.read_vectored(&mut [IoSliceMut::new(&mut buf)]))
+                .unwrap()
+                .unwrap();
+        assert_eq!(&buf[..n as usize], b"ping");
+
+        wiggle::run_in_dummy_executor(server.write_vectored(&[IoSlice::new(b"pong!")]))
+            .unwrap()
+            .unwrap();
+        let mut buf = [0; 5];
+        client.read_exact(&mut buf).unwrap();
+
+        let traffic = METRICS.lock().unwrap().streams["metered-traffic"];
+        assert_eq!(traffic.received, n);
+        assert_eq!(traffic.sent, 5);
+        assert_eq!(traffic.connections, 1);
+    }
+
+    #[test]
+    fn quota() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        let client = Socket::from(cap_std::net::TcpStream::from_std(client)).into();
+        let mut client = Metered::new("metered-quota", client).quota(6);
+        let mut write = |buf: &[u8]| {
+            wiggle::run_in_dummy_executor(client.write_vectored(&[IoSlice::new(buf)])).unwrap()
+        };
+
+        assert_eq!(write(b"ping").unwrap(), 4);
+        // The write crossing the quota is shortened to it.
+        assert_eq!(write(b"pong").unwrap(), 2);
+        let err = write(b"ping").unwrap_err();
+        assert_eq!(
+            err.downcast::<std::io::Error>().unwrap().raw_os_error(),
+            Some(Errno::DQUOT.raw_os_error())
+        );
+
+        let mut buf = [0; 6];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"pingpo");
+    }
+
+    #[test]
+    fn idle_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let listener = Socket::from(cap_std::net::TcpListener::from_std(listener)).into();
+        let mut listener =
+            Metered::new("metered-idle-timeout", listener).idle_timeout(Duration::from_secs(1));
+
+        let server = wiggle::run_in_dummy_executor(listener.sock_accept(FdFlags::empty()))
+            .unwrap()
+            .unwrap();
+        let fd = server.pollable().unwrap();
+        let socket = SockRef::from(&fd);
+        assert_eq!(socket.read_timeout().unwrap(), Some(Duration::from_secs(1)));
+        assert_eq!(
+            socket.write_timeout().unwrap(),
+            Some(Duration::from_secs(1))
+        );
+
+        // The listener itself does not time out.
+        let fd = listener.pollable().unwrap();
+        assert_eq!(SockRef::from(&fd).read_timeout().unwrap(), None);
+    }
+
+    #[test]
+    fn limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let listener = Socket::from(cap_std::net::TcpListener::from_std(listener)).into();
+        let mut listener = Metered::new("metered-limit", listener).limit(1);
+        let mut accept =
+            || wiggle::run_in_dummy_executor(listener.sock_accept(FdFlags::empty())).unwrap();
+
+        let _first = TcpStream::connect(addr).unwrap();
+        let mut second = TcpStream::connect(addr).unwrap();
+        let _third = TcpStream::connect(addr).unwrap();
+
+        // The second connection exceeds the limit and is closed.
+        let first = accept().unwrap();
+        assert!(accept().is_err());
+        assert_eq!(second.read(&mut [0; 1]).unwrap(), 0);
+
+        // The third connection is accepted once the first one is closed.
+        drop(first);
+        accept().unwrap();
+    }
+}
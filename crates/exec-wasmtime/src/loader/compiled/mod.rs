@@ -1,98 +1,857 @@
 // SPDX-License-Identifier: Apache-2.0
 
+mod attestation;
+mod audit;
+mod channel;
+mod health;
+pub(super) mod https;
+mod keys;
+mod log;
+mod metered;
 mod null;
+mod package;
+mod proxy;
+mod stats;
+pub(super) mod status;
 mod tls;
+mod ws;
 
+#[cfg(unix)]
+use self::log::HostFile;
+use self::log::Log;
+use attestation::Attestation;
+use audit::{Audit, DEFAULT_ANCHOR_INTERVAL};
+use health::Health;
+pub(super) use keys::binding;
+use keys::Keys;
+use metered::Metered;
 use null::Null;
+use package::Package;
+use stats::Stats;
 
-use super::{Compiled, Connected, Loader};
+use super::super::events::{self, Event};
+use super::super::metrics;
+use super::configured::platform::Platform;
+use super::connected;
+use super::faults::Faulty;
+use super::random::Random;
+use super::{Compiled, Connected, Instance, Loader};
 
-use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::io;
+use std::net::ToSocketAddrs;
+#[cfg(unix)]
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
 use cap_std::net::{TcpListener, TcpStream};
-use enarx_config::{File, Protocol};
-use wasi_common::{file::FileCaps, WasiFile};
-use wasmtime::AsContextMut;
+use enarx_config::{File, KeySource, LogTarget, Protocol, MAIN_SERVICE};
+#[cfg(unix)]
+use rustix::fd::BorrowedFd;
+#[cfg(unix)]
+use rustix::io::PollFlags;
+use socket2::{Domain, SockRef, Socket, TcpKeepalive, Type};
+use wasi_common::dir::DirCaps;
+use wasi_common::{file::FileCaps, WasiCtx, WasiFile};
 use wasmtime_wasi::stdio::{stderr, stdin, stdout};
+use zeroize::Zeroizing;
 
-impl Loader<Compiled> {
-    pub fn next(mut self) -> Result<Loader<Connected>> {
-        let mut ctx = self.0.wstore.as_context_mut();
-        let ctx = ctx.data_mut();
+/// Version of the conventions of the environment of the workloads, e.g. the `FD_*` variables
+///
+/// Bumped on incompatible changes only, such that workloads can refuse to run in an environment
+/// they do not understand.
+const ABI: u32 = 1;
+
+/// Maximum length of the queue of pending connections of a listen socket, unless configured
+const DEFAULT_BACKLOG: u32 = 128;
+
+/// Returns the sockets to poll for `file` to become ready and the events to poll them for, if
+/// `file` accepts TLS connections, whose pending handshakes progress on accept.
+#[cfg(unix)]
+pub(super) fn pollables(file: &dyn WasiFile) -> Option<Vec<(BorrowedFd<'_>, PollFlags)>> {
+    let file = file.as_any();
+    let tls = file
+        .downcast_ref::<tls::Listener>()
+        .or_else(|| file.downcast_ref::<ws::Listener>().map(ws::Listener::tls))?;
+    Some(tls.pollables())
+}
+
+/// Returns the TCP listen socket at `fd` passed by the host.
+///
+/// The shims cannot query the type of a socket, which the host checks, so only its address is.
+#[cfg(unix)]
+fn inherit(fd: RawFd) -> Result<std::net::TcpListener> {
+    // SAFETY: The host passes every socket to a single file only.
+    let tcp = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+    if tcp.local_addr().is_err() {
+        // The FD is owned by the host.
+        std::mem::forget(tcp);
+        bail!("FD `{fd}` is not a TCP socket");
+    }
+    tcp.set_nonblocking(false)?;
+    Ok(tcp)
+}
+
+/// Returns a TCP socket listening on the first address `addr` resolves to, which it can be bound to.
+///
+/// The socket is set up like by [`std::net::TcpListener::bind`], unless configured otherwise,
+/// but the options, which must be set before binding, can be overridden. If `only_v6` is set,
+/// only the IPv6 addresses `addr` resolves to are considered.
+fn bind(
+    addr: &str,
+    port: u16,
+    backlog: Option<u32>,
+    reuseaddr: Option<bool>,
+    only_v6: Option<bool>,
+) -> Result<std::net::TcpListener> {
+    let backlog = backlog
+        .unwrap_or(DEFAULT_BACKLOG)
+        .try_into()
+        .context("backlog is too large")?;
+    let mut last = None;
+    let addrs = (addr, port).to_socket_addrs()?;
+    for addr in addrs.filter(|addr| addr.is_ipv6() || only_v6.is_none()) {
+        let socket = Socket::new(
+            Domain::for_address(addr),
+            Type::STREAM,
+            Some(socket2::Protocol::TCP),
+        )?;
+        socket.set_reuse_address(reuseaddr.unwrap_or(cfg!(unix)))?;
+        if let Some(only_v6) = only_v6 {
+            socket.set_only_v6(only_v6)?;
+        }
+        match socket.bind(&addr.into()) {
+            Ok(()) => {
+                socket.listen(backlog)?;
+                return Ok(socket.into());
+            }
+            Err(e) => last = Some(e),
+        }
+    }
+    Err(last.map_or_else(
+        || match only_v6 {
+            Some(_) => anyhow!("`only_v6` requires an address resolving to an IPv6 address"),
+            None => anyhow!("address resolves to no socket address"),
+        },
+        Into::into,
+    ))
+}
 
-        // Set up environment variables.
-        for (k, v) in self.0.config.env.iter() {
-            ctx.push_env(k, v)?;
+/// Connects to the first address `host` resolves to, which accepts the connection.
+///
+/// If `timeout` is set, each connection attempt is bounded by it.
+fn connect(host: &str, port: u16, timeout: Option<Duration>) -> io::Result<std::net::TcpStream> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return std::net::TcpStream::connect((host, port)),
+    };
+    let mut last = None;
+    for addr in (host, port).to_socket_addrs()? {
+        match std::net::TcpStream::connect_timeout(&addr, timeout) {
+            Ok(tcp) => return Ok(tcp),
+            Err(e) => last = Some(e),
         }
+    }
+    Err(last.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "address resolves to no socket address",
+        )
+    }))
+}
+
+/// Sets the options of `socket`, which detect and close connections to hung peers.
+///
+/// TCP keepalive probes are sent after `keepalive` seconds of idleness and blocking reads and
+/// writes fail after waiting for `idle_timeout`. Connections accepted on a listen socket inherit
+/// its keepalive, but the idle timeout is set on each of them by [`Metered`] instead, as it would
+/// also bound the blocking accepts on the listen socket.
+fn tune(
+    socket: SockRef<'_>,
+    keepalive: Option<u64>,
+    idle_timeout: Option<Duration>,
+) -> io::Result<()> {
+    if let Some(keepalive) = keepalive {
+        let keepalive = TcpKeepalive::new().with_time(Duration::from_secs(keepalive));
+        socket.set_tcp_keepalive(&keepalive)?;
+    }
+    if let Some(idle_timeout) = idle_timeout {
+        socket.set_read_timeout(Some(idle_timeout))?;
+        socket.set_write_timeout(Some(idle_timeout))?;
+    }
+    Ok(())
+}
 
-        // Set up the arguments.
-        ctx.push_arg("main.wasm")
-            .context("failed to push argv[0]")?;
-        for arg in self.0.config.args.iter() {
-            ctx.push_arg(arg).context("failed to push argument")?;
+impl Loader<Compiled> {
+    pub fn next(self) -> Result<Loader<Connected>> {
+        let Compiled {
+            srvcfg,
+            cltcfg,
+            certs,
+            secrets,
+            config,
+            data,
+            identity,
+            prvkey,
+            binding,
+            mut wstore,
+            linker,
+            module,
+            mut services,
+            faults,
+            #[cfg(unix)]
+            mut listeners,
+            #[cfg(unix)]
+            logs,
+        } = self.0;
+
+        let mut channels = channel::channels(&config).context("invalid channels")?;
+
+        let signals_ready = config
+            .files
+            .iter()
+            .chain(config.services.values().flat_map(|s| s.files.iter()))
+            .any(|file| matches!(file, File::Ready { .. }));
+
+        // The status server runs next to the workload, until the keep exits, which the shims of the
+        // hardware backends cannot do, so the host serves the status of their keeps instead.
+        match config.status {
+            Some(ref status) if connected::threads() => {
+                status::spawn(status, srvcfg.clone(), certs.clone(), signals_ready)
+                    .context("failed to start status server")?
+            }
+            Some(_) => ::log::warn!("the keep cannot run the status server, serve its status from the host with `--status-listen`"),
+            None => {}
         }
 
-        // Set up the file descriptor environment variables.
-        let names: Vec<_> = self.0.config.files.iter().map(|f| f.name()).collect();
-        ctx.push_env("FD_COUNT", &names.len().to_string())?;
-        ctx.push_env("FD_NAMES", &names.join(":"))?;
-
-        // Set up all the file descriptors.
-        for (fd, file) in self.0.config.files.iter().enumerate() {
-            let srv = self.0.srvcfg.clone();
-            let clt = self.0.cltcfg.clone();
-
-            let (mut file, mut caps): (Box<dyn WasiFile>, _) = match file {
-                File::Null { .. } => (Box::new(Null), FileCaps::all()),
-                File::Stdin { .. } => (Box::new(stdin()), FileCaps::all()),
-                File::Stdout { .. } => (Box::new(stdout()), FileCaps::all()),
-                File::Stderr { .. } => (Box::new(stderr()), FileCaps::all()),
-
-                File::Listen {
-                    addr, port, prot, ..
-                } => {
-                    let caps = FileCaps::FILESTAT_GET
-                        | FileCaps::FDSTAT_SET_FLAGS
-                        | FileCaps::POLL_READWRITE
-                        | FileCaps::READ;
-
-                    let tcp = std::net::TcpListener::bind((addr.as_str(), *port))?;
-                    let tcp = TcpListener::from_std(tcp);
-                    match prot {
-                        Protocol::Tcp => (wasmtime_wasi::net::Socket::from(tcp).into(), caps),
-                        Protocol::Tls => (tls::Listener::new(tcp, srv).into(), caps),
+        // Sets up the WASI context of `service`.
+        let mut preopen = |service: &str,
+                           ctx: &mut WasiCtx,
+                           argv0: &str,
+                           env: &HashMap<String, String>,
+                           args: &[String],
+                           files: &[File]|
+         -> Result<Option<https::Server>> {
+            // Seed the random number generator of WASI according to the entropy policy.
+            let host_entropy = config.entropy.host.unwrap_or(true);
+            ctx.random = Box::new(Random::new(host_entropy)?);
+
+            // Set up environment variables.
+            for (k, v) in env.iter() {
+                ctx.push_env(k, v)?;
+            }
+
+            // Set up the arguments.
+            ctx.push_arg(argv0).context("failed to push argv[0]")?;
+            for arg in args.iter() {
+                ctx.push_arg(arg).context("failed to push argument")?;
+            }
+
+            // Set up the file descriptor environment variables.
+            let names: Vec<_> = files.iter().map(|f| f.name()).collect();
+            ctx.push_env("FD_COUNT", &names.len().to_string())?;
+            ctx.push_env("FD_NAMES", &names.join(":"))?;
+            let kinds: Vec<_> = files.iter().map(File::kind).collect();
+            ctx.push_env("FD_KINDS", &kinds.join(":"))?;
+            ctx.push_env("ENARX_ABI", &ABI.to_string())?;
+            ctx.push_env("ENARX_TIME_SOURCE", config.time.source())?;
+
+            // Set up the sessions of the sockets yielded by the `kind = "session"` files.
+            let mut sessions = HashMap::new();
+            for file in files {
+                if let File::Session { socket, .. } = file {
+                    match files.iter().find(|f| f.name() == &**socket) {
+                        Some(
+                            File::Listen {
+                                prot: Protocol::Tls,
+                                ..
+                            }
+                            | File::Connect {
+                                prot: Protocol::Tls,
+                                ..
+                            },
+                        ) => {}
+                        _ => bail!(
+                            "`socket` of file `{}` must name a file of `kind = \"listen\"` or `kind = \"connect\"` with `prot = \"tls\"`",
+                            file.name()
+                        ),
                     }
+                    sessions
+                        .entry(socket.to_string())
+                        .or_insert_with(tls::LastSession::default);
                 }
+            }
+
+            // Set up all the file descriptors.
+            let mut ports = vec![String::new(); names.len()];
+            let mut server = None;
+            for (fd, file) in files.iter().enumerate() {
+                let srv = srvcfg.clone();
+                let clt = cltcfg.clone();
+                let file_name = file.name();
+
+                let (mut file, mut caps): (Box<dyn WasiFile>, _) = match file {
+                    // The package is a directory, which is preopened at its name.
+                    File::Package { .. } => {
+                        let caps = DirCaps::OPEN
+                            | DirCaps::READDIR
+                            | DirCaps::READLINK
+                            | DirCaps::PATH_FILESTAT_GET
+                            | DirCaps::FILESTAT_GET;
+                        let file_caps = FileCaps::READ
+                            | FileCaps::SEEK
+                            | FileCaps::TELL
+                            | FileCaps::FILESTAT_GET
+                            | FileCaps::POLL_READWRITE;
+                        let dir = Box::new(Package::new(data.clone()));
+                        ctx.insert_dir(
+                            fd.try_into().unwrap(),
+                            dir,
+                            caps,
+                            file_caps,
+                            file_name.into(),
+                        );
+                        continue;
+                    }
+                    File::Null { .. } => (Box::new(Null), FileCaps::all()),
+                    File::Stdin { .. } => (Box::new(stdin()), FileCaps::all()),
+                    File::Stdout { .. } => (Box::new(stdout()), FileCaps::all()),
+                    File::Stderr { .. } => (Box::new(stderr()), FileCaps::all()),
+                    File::Log { target, path, .. } => {
+                        let stream = file.name();
+                        let keep = identity.as_str();
+                        let file: Box<dyn WasiFile> = match target {
+                            LogTarget::Stdout => {
+                                Box::new(Log::new(std::io::stdout(), stream, keep))
+                            }
+                            LogTarget::Stderr => {
+                                Box::new(Log::new(std::io::stderr(), stream, keep))
+                            }
+                            #[cfg(unix)]
+                            LogTarget::File => Box::new(Log::new(
+                                HostFile::new(&logs, path.as_deref())?,
+                                stream,
+                                keep,
+                            )),
+                            #[cfg(windows)]
+                            LogTarget::File => {
+                                let _ = path;
+                                bail!("`target = \"file\"` is not supported on this platform")
+                            }
+                        };
+                        (file, FileCaps::all())
+                    }
+                    File::Stats { .. } => {
+                        let caps =
+                            FileCaps::FILESTAT_GET | FileCaps::POLL_READWRITE | FileCaps::READ;
+                        (Box::new(Stats::new(Platform::stats)), caps)
+                    }
+                    File::Metrics { .. } => {
+                        let caps =
+                            FileCaps::FILESTAT_GET | FileCaps::POLL_READWRITE | FileCaps::READ;
+                        let render = || Ok(metrics::snapshot()?.render());
+                        (Box::new(Stats::new(render)), caps)
+                    }
+                    File::Attestation { .. } => {
+                        let platform = Platform::get().context("failed to query platform")?;
+                        let caps = FileCaps::FILESTAT_GET
+                            | FileCaps::POLL_READWRITE
+                            | FileCaps::READ
+                            | FileCaps::WRITE;
+                        let attest = move |data: &[u8]| platform.attest(data);
+                        (Box::new(Attestation::new(attest)), caps)
+                    }
+                    File::Certs { .. } => {
+                        let caps =
+                            FileCaps::FILESTAT_GET | FileCaps::POLL_READWRITE | FileCaps::READ;
+                        let certs = certs.clone();
+                        (Box::new(Stats::new(move || Ok(certs.pem()))), caps)
+                    }
+                    File::Keys {
+                        source,
+                        labels,
+                        max_uses,
+                        ..
+                    } => {
+                        let caps = FileCaps::FILESTAT_GET
+                            | FileCaps::POLL_READWRITE
+                            | FileCaps::READ
+                            | FileCaps::WRITE;
+                        let secret = match source {
+                            KeySource::Keep => prvkey.clone(),
+                            KeySource::Platform => {
+                                let platform =
+                                    Platform::get().context("failed to query platform")?;
+                                let key = Zeroizing::new(
+                                    platform.key().context("failed to get the sealing key")?,
+                                );
+                                // Keys derived from an empty secret would be known to anyone.
+                                if key.is_empty() {
+                                    bail!(
+                                        "file `{file_name}` requires a platform with a sealing key, which the {:?} platform lacks",
+                                        platform.technology()
+                                    );
+                                }
+                                key
+                            }
+                        };
+                        (
+                            Box::new(Keys::new(
+                                secret,
+                                binding.clone(),
+                                labels.clone(),
+                                *max_uses,
+                            )),
+                            caps,
+                        )
+                    }
+                    File::Random { .. } => {
+                        let caps =
+                            FileCaps::FILESTAT_GET | FileCaps::POLL_READWRITE | FileCaps::READ;
+                        (Box::new(Random::new(host_entropy)?), caps)
+                    }
+                    File::Secret { name, secret } => {
+                        let caps =
+                            FileCaps::FILESTAT_GET | FileCaps::POLL_READWRITE | FileCaps::READ;
+                        let secret = secret
+                            .as_deref()
+                            .unwrap_or_else(|| name.strip_prefix("/secrets/").unwrap_or(name));
+                        let value = secrets.get(secret).cloned().ok_or_else(|| {
+                            anyhow!("secret `{secret}` of file `{file_name}` is not in the bundle")
+                        })?;
+                        (Box::new(Stats::new(move || Ok(value.to_string()))), caps)
+                    }
+                    File::Ready { .. } => {
+                        let caps =
+                            FileCaps::FILESTAT_GET | FileCaps::POLL_READWRITE | FileCaps::WRITE;
+                        let report = |ready| {
+                            status::ready(ready);
+                            events::emit(Event::Ready { ready })
+                        };
+                        (Box::new(Health::new(report)), caps)
+                    }
+                    File::Healthy { .. } => {
+                        let caps =
+                            FileCaps::FILESTAT_GET | FileCaps::POLL_READWRITE | FileCaps::WRITE;
+                        let report = |healthy| {
+                            status::healthy(healthy);
+                            events::emit(Event::Healthy { healthy })
+                        };
+                        (Box::new(Health::new(report)), caps)
+                    }
+                    File::Audit {
+                        target,
+                        path,
+                        anchor_interval,
+                        ..
+                    } => {
+                        let caps =
+                            FileCaps::FILESTAT_GET | FileCaps::POLL_READWRITE | FileCaps::WRITE;
+                        let stream = file.name();
+                        let keep = identity.as_str();
+                        let prvkey = prvkey.clone();
+                        let interval = anchor_interval.unwrap_or(DEFAULT_ANCHOR_INTERVAL);
+                        let file: Box<dyn WasiFile> = match target {
+                            LogTarget::Stdout => Box::new(Audit::new(
+                                std::io::stdout(),
+                                stream,
+                                keep,
+                                prvkey,
+                                interval,
+                            )),
+                            LogTarget::Stderr => Box::new(Audit::new(
+                                std::io::stderr(),
+                                stream,
+                                keep,
+                                prvkey,
+                                interval,
+                            )),
+                            #[cfg(unix)]
+                            LogTarget::File => Box::new(Audit::new(
+                                HostFile::new(&logs, path.as_deref())?,
+                                stream,
+                                keep,
+                                prvkey,
+                                interval,
+                            )),
+                            #[cfg(windows)]
+                            LogTarget::File => {
+                                let _ = path;
+                                bail!("`target = \"file\"` is not supported on this platform")
+                            }
+                        };
+                        (file, caps)
+                    }
+
+                    File::Listen {
+                        addr,
+                        port,
+                        prot,
+                        max_message_size,
+                        origins,
+                        backlog,
+                        reuseaddr,
+                        only_v6,
+                        max_connections,
+                        handshake_timeout,
+                        idle_timeout,
+                        keepalive,
+                        alpn,
+                        sni,
+                        ..
+                    } => {
+                        let caps = FileCaps::FILESTAT_GET
+                            | FileCaps::FDSTAT_SET_FLAGS
+                            | FileCaps::POLL_READWRITE
+                            | FileCaps::READ;
+
+                        if *prot != Protocol::Wss && origins.is_some() {
+                            bail!("`origins` requires `prot = \"wss\"`");
+                        }
+                        if !matches!(prot, Protocol::Wss | Protocol::Https)
+                            && max_message_size.is_some()
+                        {
+                            bail!("`max_message_size` requires `prot = \"wss\"` or `prot = \"https\"`");
+                        }
+                        if *prot == Protocol::Tcp
+                            && (alpn.is_some() || sni.is_some() || handshake_timeout.is_some())
+                        {
+                            bail!("`alpn`, `sni` and `handshake_timeout` require `prot = \"tls\"`, `prot = \"wss\"` or `prot = \"https\"`");
+                        }
+                        if *prot == Protocol::Https && server.is_some() {
+                            bail!("only a single file can have `prot = \"https\"`");
+                        }
+                        let timeout = handshake_timeout
+                            .map_or(tls::DEFAULT_HANDSHAKE_TIMEOUT, Duration::from_secs);
+                        let srv = if alpn.is_some() || sni.is_some() || *prot == Protocol::Https {
+                            let mut srv = (*srv).clone();
+                            if let Some(alpn) = alpn {
+                                srv.alpn_protocols =
+                                    alpn.iter().map(|p| p.as_bytes().into()).collect();
+                            } else if *prot == Protocol::Https {
+                                srv.alpn_protocols = vec![b"http/1.1".to_vec()];
+                            }
+                            if let Some(sni) = sni {
+                                tls::Sni::restrict(&mut srv, sni);
+                            }
+                            Arc::new(srv)
+                        } else {
+                            srv
+                        };
+
+                        // A socket passed by socket activation replaces the configured one.
+                        #[cfg(unix)]
+                        let inherited = listeners
+                            .remove(file_name)
+                            .map(inherit)
+                            .transpose()
+                            .with_context(|| {
+                                format!("invalid listen socket `{file_name}` passed by the host")
+                            })?;
+                        #[cfg(windows)]
+                        let inherited = None;
+                        let tcp = match inherited {
+                            Some(tcp) => tcp,
+                            None => bind(addr, *port, *backlog, *reuseaddr, *only_v6)
+                                .with_context(|| {
+                                    format!("failed to listen on `{addr}` port {port}")
+                                })?,
+                        };
+                        tune(SockRef::from(&tcp), *keepalive, None)
+                            .context("failed to set listen socket options")?;
+                        // The port is allocated by the host, if `port = 0`.
+                        let local = tcp.local_addr().context("failed to query listen address")?;
+                        ports[fd] = local.port().to_string();
+                        events::emit(Event::ListeningOn {
+                            name: file_name.into(),
+                            addr: local.ip().to_string(),
+                            port: local.port(),
+                        });
+                        let tcp = TcpListener::from_std(tcp);
+                        let file: Box<dyn WasiFile> = match prot {
+                            Protocol::Tcp => wasmtime_wasi::net::Socket::from(tcp).into(),
+                            Protocol::Tls => {
+                                let tls = tls::Listener::new(tcp, srv, timeout)?;
+                                match sessions.get(file_name) {
+                                    Some(session) => tls.with_session(session.clone()).into(),
+                                    None => tls.into(),
+                                }
+                            }
+                            Protocol::Wss => {
+                                let policy = ws::Policy {
+                                    max_message_size: max_message_size
+                                        .unwrap_or(ws::DEFAULT_MAX_MESSAGE_SIZE),
+                                    origins: origins.clone(),
+                                };
+                                let tls = tls::Listener::new(tcp, srv, timeout)?;
+                                ws::Listener::new(tls, policy).into()
+                            }
+                            Protocol::Https => {
+                                let tls = tls::Listener::new(tcp, srv, timeout)?;
+                                server = Some(https::Server::new(
+                                    tls,
+                                    idle_timeout.map(Duration::from_secs),
+                                    max_message_size.unwrap_or(https::DEFAULT_MAX_BODY_SIZE),
+                                ));
+                                // The connections are served by the host, not by the workload.
+                                ctx.insert_file(fd.try_into().unwrap(), Box::new(Null), caps);
+                                continue;
+                            }
+                        };
+                        let mut file = Metered::new(file_name, file);
+                        if let Some(idle_timeout) = idle_timeout {
+                            file = file.idle_timeout(Duration::from_secs(*idle_timeout));
+                        }
+                        let file = match max_connections {
+                            Some(max) => file.limit(*max).into(),
+                            None => file.into(),
+                        };
+                        match faults {
+                            Some(faults) => (Faulty::new(faults, file).into(), caps),
+                            None => (file, caps),
+                        }
+                    }
 
-                File::Connect {
-                    host, port, prot, ..
-                } => {
-                    let caps = FileCaps::FILESTAT_GET
-                        | FileCaps::FDSTAT_SET_FLAGS
-                        | FileCaps::POLL_READWRITE
-                        | FileCaps::READ
-                        | FileCaps::WRITE;
-
-                    let tcp = std::net::TcpStream::connect((&**host, *port))?;
-                    let tcp = TcpStream::from_std(tcp);
-                    match prot {
-                        Protocol::Tcp => (wasmtime_wasi::net::Socket::from(tcp).into(), caps),
-                        Protocol::Tls => (tls::Stream::connect(tcp, host, clt)?.into(), caps),
+                    File::Connect {
+                        host,
+                        port,
+                        prot,
+                        alpn,
+                        connect_timeout,
+                        idle_timeout,
+                        keepalive,
+                        max_bytes,
+                        proxy,
+                        ..
+                    } => {
+                        let caps = FileCaps::FILESTAT_GET
+                            | FileCaps::FDSTAT_SET_FLAGS
+                            | FileCaps::POLL_READWRITE
+                            | FileCaps::READ
+                            | FileCaps::WRITE;
+
+                        if *prot == Protocol::Wss {
+                            bail!("`prot = \"wss\"` is only supported for `kind = \"listen\"`");
+                        }
+                        if *prot == Protocol::Https {
+                            bail!("`prot = \"https\"` is only supported for `kind = \"listen\"`");
+                        }
+                        if *prot == Protocol::Tcp && alpn.is_some() {
+                            bail!("`alpn` requires `prot = \"tls\"`");
+                        }
+                        let clt = match alpn {
+                            Some(alpn) => {
+                                let mut clt = (*clt).clone();
+                                clt.alpn_protocols =
+                                    alpn.iter().map(|p| p.as_bytes().into()).collect();
+                                Arc::new(clt)
+                            }
+                            None => clt,
+                        };
+
+                        let timeout = connect_timeout.map(Duration::from_secs);
+                        let tcp = match proxy {
+                            Some(proxy) => proxy::connect(proxy, host, *port, timeout)?,
+                            None => connect(host, *port, timeout)
+                                .with_context(|| format!("failed to connect to `{host}`"))?,
+                        };
+                        tune(
+                            SockRef::from(&tcp),
+                            *keepalive,
+                            idle_timeout.map(Duration::from_secs),
+                        )
+                        .context("failed to set socket options")?;
+                        let tcp = TcpStream::from_std(tcp);
+                        let file: Box<dyn WasiFile> = match prot {
+                            Protocol::Tcp => wasmtime_wasi::net::Socket::from(tcp).into(),
+                            Protocol::Tls => {
+                                let tls = tls::Stream::connect(tcp, host, clt, timeout)?;
+                                if let Some(session) = sessions.get(file_name) {
+                                    session.set(tls.session());
+                                }
+                                tls.into()
+                            }
+                            Protocol::Wss | Protocol::Https => unreachable!(),
+                        };
+                        let file = Metered::new(file_name, file);
+                        let file = match max_bytes {
+                            Some(max) => file.quota(*max).into(),
+                            None => file.into(),
+                        };
+                        match faults {
+                            Some(faults) => (Faulty::new(faults, file).into(), caps),
+                            None => (file, caps),
+                        }
+                    }
+
+                    File::Session { socket, .. } => {
+                        let caps =
+                            FileCaps::FILESTAT_GET | FileCaps::POLL_READWRITE | FileCaps::READ;
+                        let session = sessions[&**socket].clone();
+                        (Box::new(Stats::new(move || session.json())), caps)
+                    }
+
+                    File::Channel { peer, .. } => {
+                        let caps = FileCaps::FILESTAT_GET
+                            | FileCaps::FDSTAT_SET_FLAGS
+                            | FileCaps::POLL_READWRITE
+                            | FileCaps::READ
+                            | FileCaps::WRITE;
+                        let channel = channels
+                            .remove(&(service.into(), peer.clone()))
+                            .ok_or_else(|| anyhow!("no channel to service `{peer}`"))?;
+                        (Box::new(channel), caps)
                     }
+                };
+
+                // Ensure wasmtime can detect the TTY.
+                if file.isatty() {
+                    caps &= !(FileCaps::TELL | FileCaps::SEEK);
                 }
-            };
 
-            // Ensure wasmtime can detect the TTY.
-            if file.isatty() {
-                caps &= !(FileCaps::TELL | FileCaps::SEEK);
+                // Insert the file.
+                ctx.insert_file(fd.try_into().unwrap(), file, caps);
+            }
+            ctx.push_env("FD_PORTS", &ports.join(":"))?;
+            Ok(server)
+        };
+
+        let server = preopen(
+            MAIN_SERVICE,
+            &mut wstore.data_mut().wasi,
+            "main.wasm",
+            &config.env,
+            &config.args,
+            &config.files,
+        )?;
+        if let Some(server) = server {
+            wstore.data_mut().http.serve(server);
+        }
+        // Host directories follow the files, such that wasi-libc discovers them as preopens.
+        for (i, (path, mount)) in config.mount.iter().enumerate() {
+            let access = if mount.writable {
+                "read-write"
+            } else {
+                "read-only"
+            };
+            ::log::warn!(
+                "mounting host directory `{}` at `{path}` {access}, its contents are not protected by the keep",
+                mount.host
+            );
+            let dir = cap_std::fs::Dir::open_ambient_dir(&mount.host, cap_std::ambient_authority())
+                .with_context(|| format!("failed to open host directory `{}`", mount.host))?;
+            let dir = Box::new(wasmtime_wasi::dir::Dir::from_cap_std(dir));
+            let (caps, file_caps) = if mount.writable {
+                (DirCaps::all(), FileCaps::all())
+            } else {
+                (
+                    DirCaps::OPEN
+                        | DirCaps::READDIR
+                        | DirCaps::READLINK
+                        | DirCaps::PATH_FILESTAT_GET
+                        | DirCaps::FILESTAT_GET,
+                    FileCaps::READ
+                        | FileCaps::SEEK
+                        | FileCaps::TELL
+                        | FileCaps::FILESTAT_GET
+                        | FileCaps::POLL_READWRITE,
+                )
+            };
+            let fd = config.files.len() + i;
+            wstore.data_mut().wasi.insert_dir(
+                fd.try_into().unwrap(),
+                dir,
+                caps,
+                file_caps,
+                path.into(),
+            );
+        }
+        for (name, Instance { wstore, .. }) in services.iter_mut() {
+            let service = &config.services[name];
+            let server = preopen(
+                name,
+                &mut wstore.data_mut().wasi,
+                &service.module,
+                &service.env,
+                &service.args,
+                &service.files,
+            )
+            .with_context(|| format!("failed to set up service `{name}`"))?;
+            if let Some(server) = server {
+                wstore.data_mut().http.serve(server);
             }
+        }
 
-            // Insert the file.
-            ctx.insert_file(fd.try_into().unwrap(), file, caps);
+        #[cfg(unix)]
+        for name in listeners.keys() {
+            ::log::warn!(
+                "listen socket `{name}` passed by the host matches no `kind = \"listen\"` file"
+            );
         }
+        events::emit(Event::Started { signals_ready });
 
         Ok(Loader(Connected {
-            wstore: self.0.wstore,
-            linker: self.0.linker,
+            wstore,
+            linker,
+            module,
+            handler: config.handler,
+            services,
+            timeout: config.limits.time.map(Duration::from_secs),
+            cpu_timeout: config.limits.cpu_time.map(Duration::from_secs),
         }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{bind, connect, tune};
+
+    use std::io::{ErrorKind, Read};
+    use std::time::Duration;
+
+    use socket2::SockRef;
+
+    #[test]
+    fn bind_options() {
+        let tcp = bind("127.0.0.1", 0, Some(4), None, None).unwrap();
+        let port = tcp.local_addr().unwrap().port();
+
+        // The address is in use by the listener itself.
+        assert!(bind("127.0.0.1", port, None, Some(false), None).is_err());
+
+        assert!(bind("127.0.0.1", 0, None, None, Some(false)).is_err());
+
+        // The host may have no IPv6 loopback address.
+        if std::net::TcpListener::bind("[::1]:0").is_err() {
+            return;
+        }
+        let tcp = bind("::1", 0, None, Some(false), Some(true)).unwrap();
+        assert!(SockRef::from(&tcp).only_v6().unwrap());
+    }
+
+    #[test]
+    fn socket_options() {
+        let tcp = bind("127.0.0.1", 0, None, None, None).unwrap();
+        tune(SockRef::from(&tcp), Some(60), None).unwrap();
+        let port = tcp.local_addr().unwrap().port();
+
+        let client = connect("localhost", port, Some(Duration::from_secs(1))).unwrap();
+        let (mut accepted, _) = tcp.accept().unwrap();
+
+        // The accepted connection inherits the keepalive of the listener.
+        let socket = SockRef::from(&accepted);
+        assert!(socket.keepalive().unwrap());
+        assert_eq!(socket.read_timeout().unwrap(), None);
+
+        tune(socket, None, Some(Duration::from_secs(1))).unwrap();
+        // A blocking read from a peer, which sends nothing, fails after the idle timeout.
+        let err = accepted.read(&mut [0; 1]).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::WouldBlock | ErrorKind::TimedOut
+        ));
+        drop(client);
+    }
+}
@@ -0,0 +1,333 @@
+// SPDX-License-Identifier: Apache-2.0
+//! A read-only WasiDir of the data files of the package
+
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::io::{IoSliceMut, SeekFrom};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use wasi_common::dir::{ReaddirCursor, ReaddirEntity};
+use wasi_common::file::{FdFlags, FileType, Filestat, OFlags};
+use wasi_common::{Error, ErrorExt, SystemTimeSpec, WasiDir, WasiFile};
+
+/// The data files of the package by name
+pub type Data = Arc<BTreeMap<String, Vec<u8>>>;
+
+/// What a path of the directory refers to
+#[derive(Debug, PartialEq, Eq)]
+enum Entry<'a> {
+    Dir,
+    File(&'a str),
+}
+
+/// The directory containing the data files of the package
+pub struct Package {
+    data: Data,
+}
+
+impl Package {
+    pub fn new(data: Data) -> Self {
+        Self { data }
+    }
+
+    /// Resolves `path` relative to the directory, which has no subdirectories.
+    fn lookup(&self, path: &str) -> Result<Entry<'_>, Error> {
+        let mut components = path.split('/').filter(|c| !c.is_empty() && *c != ".");
+        match (components.next(), components.next()) {
+            (None, _) => Ok(Entry::Dir),
+            (Some(".."), _) => Err(Error::not_capable().context("path escapes the package")),
+            (Some(name), None) => self
+                .data
+                .get_key_value(name)
+                .map(|(name, _)| Entry::File(name))
+                .ok_or_else(Error::not_found),
+            (Some(name), Some(_)) if self.data.contains_key(name) => Err(Error::not_dir()),
+            (Some(_), Some(_)) => Err(Error::not_found()),
+        }
+    }
+
+    /// Returns the stat of `entry`, numbering the files after the directory.
+    fn filestat(&self, entry: &Entry<'_>) -> Filestat {
+        let (inode, filetype, size) = match entry {
+            Entry::Dir => (0, FileType::Directory, 0),
+            Entry::File(name) => (
+                self.inode(name),
+                FileType::RegularFile,
+                self.data[*name].len() as _,
+            ),
+        };
+        Filestat {
+            device_id: 0,
+            inode,
+            filetype,
+            nlink: 1,
+            size,
+            atim: None,
+            mtim: None,
+            ctim: None,
+        }
+    }
+
+    fn inode(&self, name: &str) -> u64 {
+        self.data.keys().position(|n| n == name).unwrap_or_default() as u64 + 1
+    }
+}
+
+/// Returns the error of a modification of the read-only package.
+fn read_only() -> Error {
+    Error::not_capable().context("the package is read-only")
+}
+
+#[wiggle::async_trait]
+impl WasiDir for Package {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn open_file(
+        &self,
+        _symlink_follow: bool,
+        path: &str,
+        oflags: OFlags,
+        _read: bool,
+        write: bool,
+        _fdflags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        if write || oflags.intersects(OFlags::CREATE | OFlags::TRUNCATE) {
+            return Err(read_only());
+        }
+        match self.lookup(path)? {
+            Entry::Dir => Err(Error::badf().context("is a directory")),
+            Entry::File(name) => Ok(Box::new(File {
+                data: self.data.clone(),
+                name: name.into(),
+                position: 0,
+            })),
+        }
+    }
+
+    async fn open_dir(&self, _symlink_follow: bool, path: &str) -> Result<Box<dyn WasiDir>, Error> {
+        match self.lookup(path)? {
+            Entry::Dir => Ok(Box::new(Self::new(self.data.clone()))),
+            Entry::File(..) => Err(Error::not_dir()),
+        }
+    }
+
+    async fn create_dir(&self, _path: &str) -> Result<(), Error> {
+        Err(read_only())
+    }
+
+    async fn readdir(
+        &self,
+        cursor: ReaddirCursor,
+    ) -> Result<Box<dyn Iterator<Item = Result<ReaddirEntity, Error>> + Send>, Error> {
+        let dots = [(".", 0), ("..", 0)].map(|(name, inode)| (name, inode, FileType::Directory));
+        let files = self
+            .data
+            .keys()
+            .enumerate()
+            .map(|(i, name)| (name.as_str(), i as u64 + 1, FileType::RegularFile));
+        // The entries must not borrow the directory.
+        let mut entries = vec![];
+        for (i, (name, inode, filetype)) in dots.into_iter().chain(files).enumerate() {
+            entries.push(Ok(ReaddirEntity {
+                next: ReaddirCursor::from(i as u64 + 1),
+                inode,
+                name: name.into(),
+                filetype,
+            }));
+        }
+        Ok(Box::new(entries.into_iter().skip(u64::from(cursor) as _)))
+    }
+
+    async fn symlink(&self, _old_path: &str, _new_path: &str) -> Result<(), Error> {
+        Err(read_only())
+    }
+
+    async fn remove_dir(&self, _path: &str) -> Result<(), Error> {
+        Err(read_only())
+    }
+
+    async fn unlink_file(&self, _path: &str) -> Result<(), Error> {
+        Err(read_only())
+    }
+
+    async fn read_link(&self, path: &str) -> Result<PathBuf, Error> {
+        self.lookup(path)?;
+        Err(Error::invalid_argument().context("not a symbolic link"))
+    }
+
+    async fn get_filestat(&self) -> Result<Filestat, Error> {
+        Ok(self.filestat(&Entry::Dir))
+    }
+
+    async fn get_path_filestat(
+        &self,
+        path: &str,
+        _follow_symlinks: bool,
+    ) -> Result<Filestat, Error> {
+        let entry = self.lookup(path)?;
+        Ok(self.filestat(&entry))
+    }
+
+    async fn rename(
+        &self,
+        _path: &str,
+        _dest_dir: &dyn WasiDir,
+        _dest_path: &str,
+    ) -> Result<(), Error> {
+        Err(read_only())
+    }
+
+    async fn hard_link(
+        &self,
+        _path: &str,
+        _target_dir: &dyn WasiDir,
+        _target_path: &str,
+    ) -> Result<(), Error> {
+        Err(read_only())
+    }
+
+    async fn set_times(
+        &self,
+        _path: &str,
+        _atime: Option<SystemTimeSpec>,
+        _mtime: Option<SystemTimeSpec>,
+        _follow_symlinks: bool,
+    ) -> Result<(), Error> {
+        Err(read_only())
+    }
+}
+
+/// A data file of the package opened for reading
+struct File {
+    data: Data,
+    name: String,
+    position: u64,
+}
+
+impl File {
+    fn contents(&self) -> &[u8] {
+        &self.data[&self.name]
+    }
+
+    /// Reads the contents at `offset` into `bufs`.
+    fn read_at(&self, bufs: &mut [IoSliceMut<'_>], offset: u64) -> u64 {
+        let contents = self.contents();
+        let mut rest = contents
+            .get(offset.try_into().unwrap_or(usize::MAX)..)
+            .unwrap_or_default();
+        let mut n = 0;
+        for buf in bufs.iter_mut() {
+            let len = buf.len().min(rest.len());
+            buf[..len].copy_from_slice(&rest[..len]);
+            rest = &rest[len..];
+            n += len;
+        }
+        n as _
+    }
+}
+
+#[wiggle::async_trait]
+impl WasiFile for File {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::RegularFile)
+    }
+
+    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
+        Ok(FdFlags::empty())
+    }
+
+    async fn get_filestat(&mut self) -> Result<Filestat, Error> {
+        let dir = Package::new(self.data.clone());
+        Ok(dir.filestat(&Entry::File(&self.name)))
+    }
+
+    async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        let n = self.read_at(bufs, self.position);
+        self.position += n;
+        Ok(n)
+    }
+
+    async fn read_vectored_at<'a>(
+        &mut self,
+        bufs: &mut [IoSliceMut<'a>],
+        offset: u64,
+    ) -> Result<u64, Error> {
+        Ok(self.read_at(bufs, offset))
+    }
+
+    async fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => (offset, 0),
+            SeekFrom::Current(offset) => (self.position, offset),
+            SeekFrom::End(offset) => (self.contents().len() as u64, offset),
+        };
+        self.position = match offset {
+            0.. => base.checked_add(offset.unsigned_abs()),
+            _ => base.checked_sub(offset.unsigned_abs()),
+        }
+        .ok_or_else(Error::invalid_argument)?;
+        Ok(self.position)
+    }
+
+    async fn num_ready_bytes(&self) -> Result<u64, Error> {
+        Ok((self.contents().len() as u64).saturating_sub(self.position))
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        Err(read_only())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Entry, File, Package};
+
+    use std::collections::BTreeMap;
+    use std::io::IoSliceMut;
+    use std::sync::Arc;
+
+    #[test]
+    fn package() {
+        let data = Arc::new(BTreeMap::from([
+            ("index.html".to_string(), b"<html></html>".to_vec()),
+            ("model.bin".to_string(), vec![1, 2, 3]),
+        ]));
+        let dir = Package::new(data.clone());
+
+        assert_eq!(dir.lookup(".").unwrap(), Entry::Dir);
+        assert_eq!(dir.lookup("").unwrap(), Entry::Dir);
+        assert_eq!(dir.lookup("./model.bin").unwrap(), Entry::File("model.bin"));
+        assert!(dir.lookup("../etc/passwd").is_err());
+        assert!(dir.lookup("model.bin/x").is_err());
+        assert!(dir.lookup("missing").is_err());
+        assert_eq!(dir.filestat(&Entry::File("model.bin")).size, 3);
+        assert_eq!(dir.inode("index.html"), 1);
+
+        let file = File {
+            data,
+            name: "index.html".into(),
+            position: 0,
+        };
+        let (mut head, mut tail) = ([0; 6], [0; 16]);
+        let n = file.read_at(
+            &mut [IoSliceMut::new(&mut head), IoSliceMut::new(&mut tail)],
+            0,
+        );
+        assert_eq!(n, 13);
+        assert_eq!(&head, b"<html>");
+        assert_eq!(&tail[..7], b"</html>");
+        assert_eq!(file.read_at(&mut [IoSliceMut::new(&mut tail)], 13), 0);
+        assert_eq!(file.read_at(&mut [IoSliceMut::new(&mut tail)], u64::MAX), 0);
+    }
+}
@@ -0,0 +1,238 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Connecting to a host through a SOCKS5 or HTTP proxy
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use anyhow::{bail, ensure, Context, Result};
+use url::Url;
+
+/// Maximum size of the response head of an HTTP proxy
+const MAX_RESPONSE_SIZE: usize = 8192;
+
+/// Connects to `host` at `port` through the proxy at `proxy`.
+///
+/// `proxy` is either a `socks5://` or an `http://` URL, optionally with credentials.
+/// If `timeout` is set, both connecting to the proxy and each step of the handshake are bounded by it.
+pub fn connect(proxy: &Url, host: &str, port: u16, timeout: Option<Duration>) -> Result<TcpStream> {
+    type Handshake = fn(&mut TcpStream, &str, u16, Option<(&str, &str)>) -> Result<()>;
+    let (handshake, default_port): (Handshake, _) = match proxy.scheme() {
+        "socks5" => (socks5, 1080),
+        "http" => (http, 80),
+        scheme => bail!("unsupported proxy scheme `{scheme}`, expected `socks5` or `http`"),
+    };
+    let addr = proxy
+        .host_str()
+        .with_context(|| format!("proxy URL `{proxy}` has no host"))?;
+    let mut tcp = super::connect(addr, proxy.port().unwrap_or(default_port), timeout)
+        .with_context(|| format!("failed to connect to proxy `{proxy}`"))?;
+    tcp.set_read_timeout(timeout)?;
+    tcp.set_write_timeout(timeout)?;
+    let credentials = (!proxy.username().is_empty())
+        .then(|| (proxy.username(), proxy.password().unwrap_or_default()));
+    handshake(&mut tcp, host, port, credentials)
+        .with_context(|| format!("proxy `{proxy}` failed to connect to `{host}`"))?;
+    tcp.set_read_timeout(None)?;
+    tcp.set_write_timeout(None)?;
+    Ok(tcp)
+}
+
+/// Performs the SOCKS5 handshake requesting a connection to `host` at `port` (RFC 1928).
+fn socks5(
+    tcp: &mut TcpStream,
+    host: &str,
+    port: u16,
+    credentials: Option<(&str, &str)>,
+) -> Result<()> {
+    // Offer "no authentication" or "username/password".
+    let method = if credentials.is_some() { 0x02 } else { 0x00 };
+    tcp.write_all(&[0x05, 0x01, method])?;
+    let mut reply = [0; 2];
+    tcp.read_exact(&mut reply)?;
+    ensure!(reply[0] == 0x05, "invalid SOCKS version {}", reply[0]);
+    ensure!(reply[1] == method, "no acceptable authentication method");
+
+    if let Some((user, pass)) = credentials {
+        // Username/password authentication (RFC 1929).
+        let user = u8::try_from(user.len())
+            .map(|len| [&[0x01, len], user.as_bytes()].concat())
+            .context("username is too long")?;
+        let pass = u8::try_from(pass.len())
+            .map(|len| [&[len], pass.as_bytes()].concat())
+            .context("password is too long")?;
+        tcp.write_all(&[user, pass].concat())?;
+        tcp.read_exact(&mut reply)?;
+        ensure!(reply[1] == 0x00, "authentication failed");
+    }
+
+    // Request a connection to the domain name, which is resolved by the proxy.
+    let len = u8::try_from(host.len()).context("host name is too long")?;
+    let mut request = vec![0x05, 0x01, 0x00, 0x03, len];
+    request.extend(host.as_bytes());
+    request.extend(port.to_be_bytes());
+    tcp.write_all(&request)?;
+
+    let mut reply = [0; 4];
+    tcp.read_exact(&mut reply)?;
+    ensure!(
+        reply[1] == 0x00,
+        "request failed with reply code {}",
+        reply[1]
+    );
+    // Skip the bound address and port.
+    let len = match reply[3] {
+        0x01 => 4,
+        0x03 => {
+            let mut len = [0];
+            tcp.read_exact(&mut len)?;
+            len[0].into()
+        }
+        0x04 => 16,
+        typ => bail!("invalid address type {typ}"),
+    };
+    tcp.read_exact(&mut vec![0; len + 2])?;
+    Ok(())
+}
+
+/// Requests a tunnel to `host` at `port` with the HTTP `CONNECT` method.
+fn http(
+    tcp: &mut TcpStream,
+    host: &str,
+    port: u16,
+    credentials: Option<(&str, &str)>,
+) -> Result<()> {
+    let mut request = format!("CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n");
+    if let Some((user, pass)) = credentials {
+        let credentials = base64::encode(format!("{user}:{pass}"));
+        request.push_str(&format!("Proxy-Authorization: Basic {credentials}\r\n"));
+    }
+    request.push_str("\r\n");
+    tcp.write_all(request.as_bytes())?;
+
+    // Read the response head byte by byte, such that no data of the tunnel is consumed.
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        ensure!(
+            head.len() < MAX_RESPONSE_SIZE,
+            "response exceeds {MAX_RESPONSE_SIZE} bytes"
+        );
+        let mut byte = [0];
+        tcp.read_exact(&mut byte)?;
+        head.push(byte[0]);
+    }
+    let head = String::from_utf8_lossy(&head);
+    let status = head.lines().next().unwrap_or_default();
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => bail!("unexpected response `{status}`"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::connect;
+
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn socks5() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = format!("socks5://user:pass@{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut tcp, _) = listener.accept().unwrap();
+            let mut buf = [0; 3];
+            tcp.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, [0x05, 0x01, 0x02]);
+            tcp.write_all(&[0x05, 0x02]).unwrap();
+
+            let mut buf = [0; 11];
+            tcp.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"\x01\x04user\x04pass");
+            tcp.write_all(&[0x01, 0x00]).unwrap();
+
+            let mut buf = [0; 18];
+            tcp.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"\x05\x01\x00\x03\x0bexample.com\x01\xbb");
+            tcp.write_all(&[0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0x12, 0x34])
+                .unwrap();
+            tcp.write_all(b"ping").unwrap();
+        });
+
+        let mut tcp = connect(&proxy.parse().unwrap(), "example.com", 443, None).unwrap();
+        let mut buf = [0; 4];
+        tcp.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn http() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = format!("http://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (tcp, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(tcp.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line, "CONNECT example.com:443 HTTP/1.1\r\n");
+            while line != "\r\n" {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+            }
+            (&tcp)
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\nping")
+                .unwrap();
+        });
+
+        let mut tcp = connect(&proxy.parse().unwrap(), "example.com", 443, None).unwrap();
+        let mut buf = [0; 4];
+        tcp.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn stalled() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = format!("socks5://{}", listener.local_addr().unwrap());
+        let (done, wait) = mpsc::channel::<()>();
+        let server = thread::spawn(move || {
+            // Accept the connection, but never reply.
+            let (_tcp, _) = listener.accept().unwrap();
+            let _ = wait.recv();
+        });
+
+        let timeout = Duration::from_millis(200);
+        let err = connect(&proxy.parse().unwrap(), "example.com", 443, Some(timeout)).unwrap_err();
+        assert!(format!("{err:#}").starts_with("proxy `socks5://"));
+        drop(done);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = format!("http://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut tcp, _) = listener.accept().unwrap();
+            let mut buf = [0; 1024];
+            let _ = tcp.read(&mut buf).unwrap();
+            tcp.write_all(b"HTTP/1.1 403 Forbidden\r\n\r\n").unwrap();
+        });
+
+        let err = connect(&proxy.parse().unwrap(), "example.com", 443, None).unwrap_err();
+        assert!(format!("{err:#}").ends_with("unexpected response `HTTP/1.1 403 Forbidden`"));
+        server.join().unwrap();
+
+        let err = connect(&"ftp://proxy".parse().unwrap(), "example.com", 443, None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unsupported proxy scheme `ftp`, expected `socks5` or `http`"
+        );
+    }
+}
@@ -0,0 +1,89 @@
+// SPDX-License-Identifier: Apache-2.0
+//! A WasiFile reporting the event counters of the keep
+
+use std::any::Any;
+use std::io::{self, IoSliceMut, Read};
+
+use wasi_common::file::{FdFlags, FileType};
+use wasi_common::{Error, ErrorExt, WasiFile};
+
+/// Yields a fresh report obtained from `report` on every read from the start of the file
+pub struct Stats<F> {
+    report: F,
+    buf: io::Cursor<Vec<u8>>,
+}
+
+impl<F: FnMut() -> io::Result<String>> Stats<F> {
+    pub fn new(report: F) -> Self {
+        Self {
+            report,
+            buf: Default::default(),
+        }
+    }
+
+    /// Reads the current report into `bufs`, returning `0` once it has been read completely.
+    fn read(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        if self.buf.position() == 0 {
+            self.buf = io::Cursor::new((self.report)()?.into_bytes());
+        }
+        let n = self.buf.read_vectored(bufs)?;
+        if n == 0 {
+            // Rewind, such that the next read takes a new report.
+            self.buf = Default::default();
+        }
+        Ok(n)
+    }
+}
+
+#[wiggle::async_trait]
+impl<F: FnMut() -> io::Result<String> + Send + Sync + 'static> WasiFile for Stats<F> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::CharacterDevice)
+    }
+
+    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
+        Ok(FdFlags::empty())
+    }
+
+    async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        let n = self.read(bufs).map_err(|e| Error::io().context(e))?;
+        Ok(n as _)
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Stats;
+
+    use std::io::IoSliceMut;
+
+    #[test]
+    fn reports() {
+        let mut exits = 0;
+        let mut stats = Stats::new(|| {
+            exits += 1;
+            Ok(format!("exits {exits}\n"))
+        });
+
+        let mut read = || {
+            let mut out = Vec::new();
+            loop {
+                let mut buf = [0; 4];
+                match stats.read(&mut [IoSliceMut::new(&mut buf)]).unwrap() {
+                    0 => break String::from_utf8(out).unwrap(),
+                    n => out.extend_from_slice(&buf[..n]),
+                }
+            }
+        };
+        assert_eq!(read(), "exits 1\n");
+        assert_eq!(read(), "exits 2\n");
+    }
+}
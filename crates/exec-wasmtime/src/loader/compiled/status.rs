@@ -0,0 +1,209 @@
+// SPDX-License-Identifier: Apache-2.0
+//! The status server of the keep
+//!
+//! The server answers health checks and reports the identity of the keep over TLS with the
+//! certificate of the keep, independently of the workload:
+//!
+//! * `GET /health` returns `{"status":"SERVING"}` with `200 OK`, or `{"status":"NOT_SERVING"}`
+//!   with `503 Service Unavailable`, following the statuses of the gRPC health checking protocol.
+//! * `GET /identity` returns the identity of the keep, its certificate chain, its attestation
+//!   evidence and the digests of the workload.
+
+use super::super::configured::platform::Technology;
+use super::super::renewal::Certs;
+use super::https::{self, Response};
+use super::tls;
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use anyhow::{Context, Result};
+use cap_std::net::TcpListener;
+use enarx_config::Status;
+use log::{debug, info};
+use once_cell::sync::OnceCell;
+use rustls::ServerConfig;
+use serde::Serialize;
+use ureq::serde_json;
+
+/// The identity of the keep, set once the keep is attested
+static IDENTITY: OnceCell<Identity> = OnceCell::new();
+
+/// The readiness and liveness last signalled by the workload
+static HEALTH: Mutex<Health> = Mutex::new(Health {
+    ready: None,
+    healthy: None,
+});
+
+/// The identity of the keep and of its workload
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Identity {
+    /// Hex-encoded SHA-256 digest of the public key of the keep
+    pub identity: String,
+    /// Technology of the keep, e.g. `snp`
+    pub technology: &'static str,
+    /// Hex-encoded attestation report binding the key of the keep to its measurement, if the
+    /// platform attests keeps
+    pub evidence: Option<String>,
+    /// Digest of the main Wasm module, `sha256:` followed by the hex-encoded SHA-256 digest
+    pub workload_digest: String,
+    /// Digest of `Enarx.toml`, if the package has one
+    pub config_digest: Option<String>,
+}
+
+impl Identity {
+    /// Returns the name of `technology` in the identity.
+    pub fn technology(technology: Technology) -> &'static str {
+        match technology {
+            Technology::Kvm => "kvm",
+            Technology::Snp => "snp",
+            Technology::Sgx => "sgx",
+        }
+    }
+}
+
+/// The identity reported by `GET /identity`
+#[derive(Serialize)]
+struct Report<'a> {
+    #[serde(flatten)]
+    identity: &'a Identity,
+    /// PEM-encoded certificate chain of the keep
+    certificates: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Health {
+    ready: Option<bool>,
+    healthy: Option<bool>,
+}
+
+impl Health {
+    /// Returns whether the workload is serving, i.e. neither signalled that it is not ready nor
+    /// that it is not healthy, and if it `signals_ready`, signalled that it is ready.
+    fn serving(&self, signals_ready: bool) -> bool {
+        self.healthy != Some(false)
+            && match self.ready {
+                Some(ready) => ready,
+                None => !signals_ready,
+            }
+    }
+}
+
+/// Sets the identity of the keep, once it is attested.
+pub fn attested(identity: Identity) {
+    let _ = IDENTITY.set(identity);
+}
+
+/// Records the readiness signalled by the workload.
+pub fn ready(ready: bool) {
+    HEALTH.lock().unwrap().ready = Some(ready);
+}
+
+/// Records the liveness signalled by the workload.
+pub fn healthy(healthy: bool) {
+    HEALTH.lock().unwrap().healthy = Some(healthy);
+}
+
+/// Returns the response to `GET` of `path`.
+fn respond(path: &str, certs: &Certs, signals_ready: bool) -> Response {
+    let (status, body) = match path {
+        "/health" => {
+            let serving = HEALTH.lock().unwrap().serving(signals_ready);
+            let status = if serving { "SERVING" } else { "NOT_SERVING" };
+            let body = serde_json::json!({ "status": status }).to_string();
+            (if serving { 200 } else { 503 }, body)
+        }
+        "/identity" => match IDENTITY.get() {
+            Some(identity) => {
+                let report = Report {
+                    identity,
+                    certificates: certs.pem(),
+                };
+                match serde_json::to_string(&report) {
+                    Ok(body) => (200, body),
+                    Err(e) => {
+                        debug!("failed to encode identity: {e}");
+                        return Response::status(500);
+                    }
+                }
+            }
+            None => return Response::status(503),
+        },
+        _ => return Response::status(404),
+    };
+    Response {
+        status,
+        headers: vec![("content-type".into(), "application/json".into())],
+        body: body.into_bytes(),
+    }
+}
+
+/// Spawns a thread serving the status of the keep on the socket configured by `status`.
+///
+/// The workload `signals_ready`, if it has a `kind = "ready"` file, in which case it is not
+/// serving until it signalled its readiness.
+pub fn spawn(
+    status: &Status,
+    srvcfg: Arc<ServerConfig>,
+    certs: Arc<Certs>,
+    signals_ready: bool,
+) -> Result<()> {
+    let Status { addr, port } = status;
+    let tcp = super::bind(addr, *port, None, None, None)
+        .with_context(|| format!("failed to listen on `{addr}` port {port}"))?;
+    let local = tcp.local_addr().context("failed to query listen address")?;
+    info!("status server listening on {local}");
+
+    let tls = tls::Listener::new(
+        TcpListener::from_std(tcp),
+        srvcfg,
+        tls::DEFAULT_HANDSHAKE_TIMEOUT,
+    )
+    .context("failed to set up the status listener")?;
+    let mut server = https::Server::new(tls, None, 0);
+    thread::Builder::new()
+        .name("status".into())
+        .spawn(move || loop {
+            let mut conn = match server.accept() {
+                Ok(Some(conn)) => conn,
+                Ok(None) => continue,
+                Err(e) => {
+                    debug!("failed to accept status connection: {e:#}");
+                    continue;
+                }
+            };
+            let response = match conn.request() {
+                Ok(request) if request.method == "GET" => {
+                    let path = request.uri.split('?').next().unwrap_or_default();
+                    respond(path, &certs, signals_ready)
+                }
+                Ok(..) => Response::status(405),
+                Err(e) => {
+                    debug!("failed to read status request: {e}");
+                    continue;
+                }
+            };
+            if let Err(e) = conn.respond(&response) {
+                debug!("failed to write status response: {e}");
+            }
+        })
+        .context("failed to spawn status server thread")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::Health;
+
+    #[test]
+    fn serving() {
+        let health = |ready, healthy| Health { ready, healthy };
+
+        assert!(health(None, None).serving(false));
+        assert!(!health(None, None).serving(true));
+        assert!(health(Some(true), None).serving(true));
+        assert!(!health(Some(false), None).serving(false));
+        assert!(!health(Some(true), Some(false)).serving(true));
+        assert!(health(None, Some(true)).serving(false));
+    }
+}
@@ -1,10 +1,13 @@
 // SPDX-License-Identifier: Apache-2.0
 //! A WasiFile for transparent TLS
 
+use crate::metrics::METRICS;
+
 use std::any::Any;
 use std::io;
 use std::io::{IoSlice, IoSliceMut, Read, Write};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use cap_std::net::{TcpListener as CapListener, TcpStream as CapStream};
 #[cfg(windows)]
@@ -12,16 +15,36 @@ use io_extras::os::windows::AsRawHandleOrSocket;
 #[cfg(unix)]
 use io_lifetimes::{AsFd, AsFilelike};
 
-use rustls::{ClientConfig, ClientConnection, Connection, ServerConfig, ServerConnection};
+use log::debug;
+#[cfg(unix)]
+use rustix::fd::BorrowedFd;
+#[cfg(unix)]
+use rustix::io::{Errno, PollFd, PollFlags};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{
+    ClientConfig, ClientConnection, Connection, ProtocolVersion, ServerConfig, ServerConnection,
+};
+use serde::Serialize;
 #[cfg(unix)]
 use system_interface::fs::GetSetFdFlags;
 use system_interface::io::IsReadWrite;
+use ureq::serde_json;
 use wasi_common::file::{FdFlags, FileType};
 use wasi_common::{Context, Error, ErrorExt, ErrorKind, WasiFile};
 #[cfg(unix)]
 use wasmtime_wasi::net::from_sysif_fdflags;
 
-fn errmap(error: std::io::Error) -> Error {
+/// Time, within which the handshake of an accepted connection must complete, unless configured
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Records a failed TLS handshake.
+fn handshake_failed<T>(error: T) -> T {
+    METRICS.lock().unwrap().tls_handshake_failures += 1;
+    error
+}
+
+pub(super) fn errmap(error: std::io::Error) -> Error {
     use std::io::ErrorKind::*;
 
     match error.kind() {
@@ -102,11 +125,177 @@ impl IOAsync for Connection {
     }
 }
 
+/// A stream, whose blocking reads and writes can time out
+pub(super) trait Timeout {
+    fn timeout(&self) -> io::Result<Option<Duration>>;
+    fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Timeout for CapStream {
+    fn timeout(&self) -> io::Result<Option<Duration>> {
+        self.read_timeout()
+    }
+
+    fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.set_read_timeout(timeout)?;
+        self.set_write_timeout(timeout)
+    }
+}
+
+/// Bounds the blocking reads and writes on a stream by a deadline
+///
+/// Every read and write times out once the deadline passes, not only the ones, which stall,
+/// such that a peer cannot hold up a handshake by trickling data. The previous timeout of the
+/// stream, like the idle timeout of the connection, is restored on drop.
+pub(super) struct Deadline<'a, S: Timeout> {
+    stream: &'a mut S,
+    deadline: Instant,
+    previous: Option<Duration>,
+}
+
+impl<'a, S: Timeout> Deadline<'a, S> {
+    pub fn new(stream: &'a mut S, timeout: Duration) -> Self {
+        let previous = stream.timeout().unwrap_or_default();
+        Self {
+            stream,
+            deadline: Instant::now() + timeout,
+            previous,
+        }
+    }
+
+    /// Sets the timeout of the stream to the time left until the deadline.
+    fn arm(&self) -> io::Result<()> {
+        match self.deadline.checked_duration_since(Instant::now()) {
+            Some(left) if !left.is_zero() => self.stream.set_timeout(Some(left)),
+            _ => Err(io::ErrorKind::TimedOut.into()),
+        }
+    }
+}
+
+/// A blocking read or write, which would block, has timed out.
+fn timed_out(error: io::Error) -> io::Error {
+    if error.kind() == io::ErrorKind::WouldBlock {
+        io::ErrorKind::TimedOut.into()
+    } else {
+        error
+    }
+}
+
+impl<S: Timeout + Read> Read for Deadline<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // A TLS stream would block until a whole record has arrived, so the read is retried until
+        // the deadline passes.
+        loop {
+            self.arm()?;
+            match self.stream.read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                res => return res,
+            }
+        }
+    }
+}
+
+impl<S: Timeout + Write> Write for Deadline<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.arm()?;
+        self.stream.write(buf).map_err(timed_out)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.arm()?;
+        self.stream.flush().map_err(timed_out)
+    }
+}
+
+impl<S: Timeout> Drop for Deadline<'_, S> {
+    fn drop(&mut self) {
+        if let Err(e) = self.stream.set_timeout(self.previous) {
+            debug!("failed to restore stream timeout: {e}");
+        }
+    }
+}
+
+/// The parameters negotiated for a TLS connection
+///
+/// The workload reads the session in JSON from a `kind = "session"` file, such that it can enforce
+/// its own policy on top of the TLS terminated by the keep.
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Session {
+    /// Negotiated protocol version, `1.2` or `1.3`
+    pub version: Option<String>,
+    /// Negotiated cipher suite, e.g. `TLS13_AES_128_GCM_SHA256`
+    pub cipher_suite: Option<String>,
+    /// Application-layer protocol negotiated via ALPN
+    pub alpn: Option<String>,
+    /// Server name requested by the client via SNI, if the keep accepted the connection
+    pub server_name: Option<String>,
+    /// Base64-encoded DER certificates presented by the peer, starting with its own
+    pub peer_certificates: Vec<String>,
+}
+
+impl From<&Connection> for Session {
+    fn from(tls: &Connection) -> Self {
+        Self {
+            version: tls.protocol_version().map(|version| match version {
+                ProtocolVersion::TLSv1_2 => "1.2".into(),
+                ProtocolVersion::TLSv1_3 => "1.3".into(),
+                version => format!("{version:?}"),
+            }),
+            cipher_suite: tls
+                .negotiated_cipher_suite()
+                .map(|suite| format!("{:?}", suite.suite())),
+            alpn: tls
+                .alpn_protocol()
+                .map(|alpn| String::from_utf8_lossy(alpn).into()),
+            server_name: match tls {
+                Connection::Server(tls) => tls.sni_hostname().map(String::from),
+                Connection::Client(_) => None,
+            },
+            peer_certificates: tls
+                .peer_certificates()
+                .unwrap_or_default()
+                .iter()
+                .map(|cert| base64::encode(&cert.0))
+                .collect(),
+        }
+    }
+}
+
+/// The session of the connection established last on a socket, which a `kind = "session"` file
+/// yields
+#[derive(Clone, Default)]
+pub struct LastSession(Arc<Mutex<Option<Session>>>);
+
+impl LastSession {
+    /// Records the session of the connection established last.
+    pub fn set(&self, session: Session) {
+        *self.0.lock().unwrap() = Some(session);
+    }
+
+    /// Returns the session of the connection established last in JSON as a line, `null` if none
+    /// was established yet.
+    pub fn json(&self) -> io::Result<String> {
+        let mut line = serde_json::to_string(&*self.0.lock().unwrap())?;
+        line.push('\n');
+        Ok(line)
+    }
+}
+
 pub struct Stream {
     tcp: CapStream,
     tls: Connection,
 }
 
+impl Timeout for Stream {
+    fn timeout(&self) -> io::Result<Option<Duration>> {
+        self.tcp.timeout()
+    }
+
+    fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.tcp.set_timeout(timeout)
+    }
+}
+
 impl From<Stream> for Box<dyn WasiFile> {
     fn from(value: Stream) -> Self {
         Box::new(value)
@@ -114,21 +303,70 @@ impl From<Stream> for Box<dyn WasiFile> {
 }
 
 impl Stream {
-    pub fn connect(mut tcp: CapStream, name: &str, cfg: Arc<ClientConfig>) -> Result<Self, Error> {
+    pub fn connect(
+        mut tcp: CapStream,
+        name: &str,
+        cfg: Arc<ClientConfig>,
+        timeout: Option<Duration>,
+    ) -> Result<Self, Error> {
         // Set up connection.
         let tls = ClientConnection::new(cfg, name.try_into()?)?;
         let mut tls = Connection::Client(tls);
 
         // Finish the connection.
-        tls.complete_io(&mut tcp)?;
+        match timeout {
+            Some(timeout) => tls.complete_io(&mut Deadline::new(&mut tcp, timeout)),
+            None => tls.complete_io(&mut tcp),
+        }
+        .map_err(handshake_failed)?;
 
         Ok(Self { tcp, tls })
     }
 
+    /// Returns the parameters negotiated for the connection.
+    pub fn session(&self) -> Session {
+        Session::from(&self.tls)
+    }
+
     fn complete_io(&mut self) -> Result<(), Error> {
         self.tls.complete_io_async(&mut self.tcp).map_err(errmap)?;
         Ok(())
     }
+
+    /// Writes all of `buf` to the peer in blocking mode.
+    ///
+    /// Unlike [`Write::write`], this does not wait for data from the peer first.
+    pub fn send(&mut self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            let n = self.tls.writer().write(buf)?;
+            buf = &buf[n..];
+            while self.tls.wants_write() {
+                self.tls.write_tls(&mut self.tcp)?;
+            }
+        }
+        self.tcp.flush()
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.tls.complete_io_async(&mut self.tcp)?;
+        self.tls.reader().read(buf)
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tls.complete_io_async(&mut self.tcp)?;
+        let n = self.tls.writer().write(buf)?;
+        self.tls.complete_io_async(&mut self.tcp)?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.tls.complete_io_async(&mut self.tcp)?;
+        self.tcp.flush()
+    }
 }
 
 #[wiggle::async_trait]
@@ -217,14 +455,240 @@ impl WasiFile for Stream {
     }
 }
 
+/// Resolves the certificate of `inner` only for clients requesting the server name `sni`
+///
+/// Handshakes of clients requesting a different server name or none at all fail.
+pub struct Sni {
+    sni: String,
+    inner: Arc<dyn ResolvesServerCert>,
+}
+
+impl Sni {
+    /// Restricts the server name accepted by `cfg` to `sni`.
+    pub fn restrict(cfg: &mut ServerConfig, sni: &str) {
+        cfg.cert_resolver = Arc::new(Self {
+            sni: sni.into(),
+            inner: cfg.cert_resolver.clone(),
+        });
+    }
+}
+
+impl ResolvesServerCert for Sni {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        match client_hello.server_name() {
+            Some(name) if name.eq_ignore_ascii_case(&self.sni) => self.inner.resolve(client_hello),
+            name => {
+                debug!("rejecting TLS connection for server name {name:?}");
+                None
+            }
+        }
+    }
+}
+
+/// Number of accepted connections, whose TLS handshakes may be pending at once
+const MAX_PENDING_HANDSHAKES: usize = 64;
+
+/// Interval, in which pending TLS handshakes are progressed, where sockets cannot be polled
+#[cfg(windows)]
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The TLS handshake of an accepted connection in progress
+struct Handshake {
+    tcp: CapStream,
+    tls: Connection,
+    deadline: Instant,
+}
+
+impl Handshake {
+    /// Progresses the handshake without blocking and returns whether it completed.
+    fn progress(&mut self) -> io::Result<bool> {
+        if Instant::now() >= self.deadline {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.tls.complete_io_async(&mut self.tcp)?;
+        Ok(!self.tls.is_handshaking())
+    }
+
+    /// Returns the events of the socket, which the handshake waits for.
+    #[cfg(unix)]
+    fn events(&self) -> PollFlags {
+        if self.tls.wants_write() {
+            PollFlags::OUT
+        } else {
+            PollFlags::IN
+        }
+    }
+}
+
 pub struct Listener {
     listener: CapListener,
     cfg: Arc<ServerConfig>,
+    timeout: Duration,
+    /// The session of the connection accepted last, if a `kind = "session"` file yields it
+    session: Option<LastSession>,
+    /// Whether accepting a connection must not block, as set by the workload
+    nonblocking: bool,
+    pending: Vec<Handshake>,
 }
 
 impl Listener {
-    pub fn new(listener: CapListener, cfg: Arc<ServerConfig>) -> Self {
-        Self { listener, cfg }
+    pub fn new(
+        listener: CapListener,
+        cfg: Arc<ServerConfig>,
+        timeout: Duration,
+    ) -> io::Result<Self> {
+        // Connections are accepted and their handshakes progressed without blocking, such that a
+        // client stalling its handshake does not stall the others.
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            cfg,
+            timeout,
+            session: None,
+            nonblocking: false,
+            pending: vec![],
+        })
+    }
+
+    /// Records the session of every accepted connection in `session`.
+    pub fn with_session(mut self, session: LastSession) -> Self {
+        self.session = Some(session);
+        self
+    }
+
+    /// Returns the time, within which the handshakes of accepted connections must complete.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Returns the sockets to poll for [`accept`](Self::accept) to make progress and the events
+    /// to poll them for, the listener first.
+    ///
+    /// Once [`MAX_PENDING_HANDSHAKES`] are pending, no connections are accepted, so the listener
+    /// is only polled for errors.
+    #[cfg(unix)]
+    pub fn pollables(&self) -> Vec<(BorrowedFd<'_>, PollFlags)> {
+        let events = if self.pending.len() < MAX_PENDING_HANDSHAKES {
+            PollFlags::IN
+        } else {
+            PollFlags::empty()
+        };
+        std::iter::once((self.listener.as_fd(), events))
+            .chain(self.pending.iter().map(|h| (h.tcp.as_fd(), h.events())))
+            .collect()
+    }
+
+    /// Accepts a connection, whose TLS handshake completed, waiting at most `timeout` for one or
+    /// indefinitely, if `None`, and fails with `WouldBlk` otherwise.
+    ///
+    /// The handshakes of at most [`MAX_PENDING_HANDSHAKES`] accepted connections are progressed
+    /// without blocking at once, each of which must complete within the handshake timeout.
+    /// The accepted connection is blocking.
+    pub fn accept(&mut self, timeout: Option<Duration>) -> Result<Stream, Error> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            if let Some(stream) = self.progress()? {
+                return Ok(stream);
+            }
+            let now = Instant::now();
+            if matches!(deadline, Some(deadline) if deadline <= now) {
+                return Err(ErrorKind::WouldBlk.into());
+            }
+            // Wake up, once the first pending handshake times out, to drop it.
+            let wake = self
+                .pending
+                .iter()
+                .map(|h| h.deadline)
+                .chain(deadline)
+                .min();
+            self.wait(wake.map(|wake| wake.saturating_duration_since(now)))?;
+        }
+    }
+
+    /// Accepts connections, while less than [`MAX_PENDING_HANDSHAKES`] are pending, and
+    /// progresses their handshakes, returning the first connection, whose handshake completed.
+    fn progress(&mut self) -> Result<Option<Stream>, Error> {
+        while self.pending.len() < MAX_PENDING_HANDSHAKES {
+            let tcp = match self.listener.accept() {
+                Ok((tcp, ..)) => tcp,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e.into()),
+            };
+            tcp.set_nonblocking(true)?;
+            let tls = Connection::Server(
+                ServerConnection::new(self.cfg.clone())
+                    .map_err(|e| Error::io().context(e))
+                    .context("could not create new TLS connection")?,
+            );
+            self.pending.push(Handshake {
+                tcp,
+                tls,
+                deadline: Instant::now() + self.timeout,
+            });
+        }
+
+        let mut i = 0;
+        while i < self.pending.len() {
+            match self.pending[i].progress() {
+                Ok(false) => i += 1,
+                Ok(true) => {
+                    let Handshake { tcp, tls, .. } = self.pending.remove(i);
+                    return self.accepted(tcp, tls).map(Some);
+                }
+                Err(e) => {
+                    let e = handshake_failed(e);
+                    if e.kind() == io::ErrorKind::TimedOut {
+                        debug!("TLS handshake timed out");
+                    } else {
+                        debug!("TLS handshake failed: {e}");
+                    }
+                    self.pending.remove(i);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the connection, whose handshake completed.
+    fn accepted(&self, tcp: CapStream, tls: Connection) -> Result<Stream, Error> {
+        tcp.set_nonblocking(false)?;
+        let stream = Stream { tcp, tls };
+        if let Connection::Server(ref tls) = stream.tls {
+            debug!(
+                "accepted TLS connection for server name {:?} with ALPN protocol {:?}",
+                tls.sni_hostname(),
+                tls.alpn_protocol().map(String::from_utf8_lossy)
+            );
+        }
+        if let Some(ref session) = self.session {
+            session.set(stream.session());
+        }
+        Ok(stream)
+    }
+
+    /// Waits at most `timeout` or indefinitely, if `None`, for the listener or any of the pending
+    /// handshakes to make progress.
+    #[cfg(unix)]
+    fn wait(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        let pollables = self.pollables();
+        let mut fds: Vec<_> = pollables
+            .iter()
+            .map(|(fd, events)| PollFd::from_borrowed_fd(*fd, *events))
+            .collect();
+        let ms = match timeout {
+            Some(timeout) => (timeout.as_millis() + 1).try_into().unwrap_or(i32::MAX),
+            None => -1,
+        };
+        match rustix::io::poll(&mut fds, ms) {
+            Ok(..) | Err(Errno::INTR) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[cfg(windows)]
+    fn wait(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        std::thread::sleep(timeout.map_or(POLL_INTERVAL, |t| t.min(POLL_INTERVAL)));
+        Ok(())
     }
 }
 
@@ -251,20 +715,7 @@ impl WasiFile for Listener {
     }
 
     async fn sock_accept(&mut self, fdflags: FdFlags) -> Result<Box<dyn WasiFile>, Error> {
-        // Accept the connection.
-        let (tcp, ..) = self.listener.accept()?;
-
-        // Create a new TLS connection.
-        let tls = Connection::Server(
-            ServerConnection::new(self.cfg.clone())
-                .map_err(|e| Error::io().context(e))
-                .context("could not create new TLS connection")?,
-        );
-
-        tcp.set_nonblocking(false)?;
-        let mut stream = Stream { tcp, tls };
-        stream.complete_io()?;
-
+        let mut stream = self.accept(self.nonblocking.then_some(Duration::ZERO))?;
         stream.set_fdflags(fdflags).await?;
         Ok(Box::new(stream))
     }
@@ -273,22 +724,19 @@ impl WasiFile for Listener {
         Ok(FileType::SocketStream)
     }
 
-    #[cfg(unix)]
     async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
-        let fdflags = self.listener.as_filelike().get_fd_flags()?;
-        Ok(from_sysif_fdflags(fdflags))
-    }
-
-    #[cfg(windows)]
-    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
-        Ok(FdFlags::empty())
+        if self.nonblocking {
+            Ok(FdFlags::NONBLOCK)
+        } else {
+            Ok(FdFlags::empty())
+        }
     }
 
     async fn set_fdflags(&mut self, fdflags: FdFlags) -> Result<(), Error> {
         if fdflags == FdFlags::NONBLOCK {
-            self.listener.set_nonblocking(true)?;
+            self.nonblocking = true;
         } else if fdflags.is_empty() {
-            self.listener.set_nonblocking(false)?;
+            self.nonblocking = false;
         } else {
             return Err(Error::invalid_argument().context("cannot set anything else than NONBLOCK"));
         }
@@ -330,3 +778,128 @@ impl WasiFile for Listener {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    struct Resolver;
+
+    impl ResolvesServerCert for Resolver {
+        fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+            None
+        }
+    }
+
+    #[test]
+    fn handshake_timeout() {
+        let cfg = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(Resolver));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let timeout = Duration::from_millis(200);
+        let mut listener =
+            Listener::new(CapListener::from_std(listener), Arc::new(cfg), timeout).unwrap();
+
+        // The client never sends its hello, which does not block the accept.
+        let _client = TcpStream::connect(addr).unwrap();
+        let start = Instant::now();
+        match listener.accept(Some(Duration::ZERO)) {
+            Err(e) => assert!(matches!(e.downcast_ref(), Some(ErrorKind::WouldBlk))),
+            Ok(..) => panic!("accepted a connection without a handshake"),
+        }
+        assert!(start.elapsed() < timeout);
+        assert_eq!(listener.pending.len(), 1);
+
+        // The handshake is dropped, once it timed out.
+        assert!(listener.accept(Some(timeout * 2)).is_err());
+        assert!(start.elapsed() >= timeout);
+        assert!(listener.pending.is_empty());
+
+        // The client trickles its hello, each byte well within the timeout.
+        let mut client = TcpStream::connect(addr).unwrap();
+        let trickle = thread::spawn(move || {
+            for _ in 0..20 {
+                if client.write_all(&[0x16]).is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        });
+        let start = Instant::now();
+        assert!(listener.accept(Some(timeout * 3)).is_err());
+        assert!(start.elapsed() < Duration::from_millis(800));
+        assert!(listener.pending.is_empty());
+        trickle.join().unwrap();
+    }
+
+    #[test]
+    fn pending_handshakes() {
+        let cfg = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(Resolver));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut listener = Listener::new(
+            CapListener::from_std(listener),
+            Arc::new(cfg),
+            DEFAULT_HANDSHAKE_TIMEOUT,
+        )
+        .unwrap();
+
+        // Connections beyond the maximum wait to be accepted.
+        let _clients: Vec<_> = (0..=MAX_PENDING_HANDSHAKES)
+            .map(|_| TcpStream::connect(addr).unwrap())
+            .collect();
+        thread::sleep(Duration::from_millis(100));
+        assert!(listener.accept(Some(Duration::ZERO)).is_err());
+        assert_eq!(listener.pending.len(), MAX_PENDING_HANDSHAKES);
+
+        // The listener is not polled for connections, but the handshakes for their hellos.
+        let pollables = listener.pollables();
+        assert_eq!(pollables.len(), MAX_PENDING_HANDSHAKES + 1);
+        assert_eq!(pollables[0].1, PollFlags::empty());
+        assert!(pollables[1..]
+            .iter()
+            .all(|(_, events)| *events == PollFlags::IN));
+    }
+
+    #[test]
+    fn session() {
+        let cfg = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(Resolver));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (tcp, _) = listener.accept().unwrap();
+        let stream = Stream {
+            tcp: CapStream::from_std(tcp),
+            tls: Connection::Server(ServerConnection::new(Arc::new(cfg)).unwrap()),
+        };
+        assert_eq!(stream.session(), Session::default());
+
+        // Nothing is yielded, until a connection is established.
+        let last = LastSession::default();
+        assert_eq!(last.json().unwrap(), "null\n");
+
+        last.set(stream.session());
+        let session: serde_json::Value = serde_json::from_str(&last.json().unwrap()).unwrap();
+        assert_eq!(
+            session,
+            serde_json::json!({
+                "version": null,
+                "cipher_suite": null,
+                "alpn": null,
+                "server_name": null,
+                "peer_certificates": [],
+            })
+        );
+    }
+}
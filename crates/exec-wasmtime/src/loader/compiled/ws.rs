@@ -0,0 +1,602 @@
+// SPDX-License-Identifier: Apache-2.0
+//! A WasiFile for WebSocket messages over TLS
+//!
+//! The host performs the WebSocket handshake and handles the framing, the
+//! workload reads and writes whole messages as records consisting of a 1 byte
+//! opcode, the 32-bit big-endian length of the payload and the payload itself.
+
+use super::tls::{self, errmap, Deadline};
+
+use std::any::Any;
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, IoSlice, IoSliceMut, Read, Write};
+use std::time::Duration;
+
+use ring::digest::{digest, SHA1_FOR_LEGACY_USE_ONLY};
+use wasi_common::file::{FdFlags, FileType};
+use wasi_common::{Error, WasiFile};
+
+/// Default maximum size of a message in bytes
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 1 << 20;
+
+/// The GUID used to compute `Sec-WebSocket-Accept`, see RFC 6455, section 1.3
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Maximum size of the handshake request in bytes
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// Size of the header of a message record
+const RECORD_HEADER_SIZE: usize = 5;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+const CLOSE_INVALID_DATA: u16 = 1007;
+const CLOSE_TOO_BIG: u16 = 1009;
+
+/// Policy applied to WebSocket connections
+#[derive(Clone, Debug)]
+pub struct Policy {
+    /// Maximum size of a message in either direction
+    pub max_message_size: usize,
+
+    /// Values of the `Origin` header allowed in a handshake, any if `None`
+    pub origins: Option<Vec<String>>,
+}
+
+/// Reads the handshake request from `stream` and answers it according to
+/// `policy`, returning any data received after the request.
+fn handshake(stream: &mut (impl Read + Write), policy: &Policy) -> io::Result<Vec<u8>> {
+    let mut buf = vec![];
+    let end = loop {
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end;
+        }
+        if buf.len() > MAX_REQUEST_SIZE {
+            return reject(stream, "431 Request Header Fields Too Large");
+        }
+
+        let mut chunk = [0; 1024];
+        match stream.read(&mut chunk) {
+            Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => continue,
+            Err(e) => return Err(e),
+        }
+    };
+
+    let head = match std::str::from_utf8(&buf[..end]) {
+        Ok(head) => head,
+        Err(..) => return reject(stream, "400 Bad Request"),
+    };
+    let mut lines = head.split("\r\n");
+    let request = lines.next().unwrap_or_default();
+    if !request.starts_with("GET ") || !request.ends_with(" HTTP/1.1") {
+        return reject(stream, "400 Bad Request");
+    }
+    let headers: Vec<_> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim(), value.trim()))
+        .collect();
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| *v)
+    };
+
+    let upgrade = header("Upgrade").map_or(false, |v| v.eq_ignore_ascii_case("websocket"));
+    let connection = header("Connection").map_or(false, |v| {
+        v.split(',')
+            .any(|t| t.trim().eq_ignore_ascii_case("upgrade"))
+    });
+    let key = match header("Sec-WebSocket-Key") {
+        Some(key) if upgrade && connection && header("Sec-WebSocket-Version") == Some("13") => key,
+        _ => return reject(stream, "400 Bad Request"),
+    };
+
+    if let Some(origins) = &policy.origins {
+        if !header("Origin").map_or(false, |o| origins.iter().any(|x| x == o)) {
+            return reject(stream, "403 Forbidden");
+        }
+    }
+
+    let accept = base64::encode(digest(
+        &SHA1_FOR_LEGACY_USE_ONLY,
+        format!("{key}{GUID}").as_bytes(),
+    ));
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {accept}\r\n\r\n"
+    )?;
+    stream.flush()?;
+
+    Ok(buf.split_off(end + 4))
+}
+
+/// Answers a handshake request with `status` and fails.
+fn reject(stream: &mut impl Write, status: &str) -> io::Result<Vec<u8>> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nConnection: close\r\nContent-Length: 0\r\n\r\n"
+    )?;
+    stream.flush()?;
+    Err(io::Error::new(
+        ErrorKind::InvalidData,
+        format!("rejected WebSocket handshake: {status}"),
+    ))
+}
+
+/// A frame received from the client
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// Parses a frame from `input`, returning it and its size, if it is complete,
+/// or the close status code, if the frame is invalid.
+fn frame(input: &[u8], max_message_size: usize) -> Result<Option<(Frame, usize)>, u16> {
+    let (b0, b1) = match input {
+        [b0, b1, ..] => (*b0, *b1),
+        _ => return Ok(None),
+    };
+    let fin = b0 & 0x80 != 0;
+    let opcode = b0 & 0x0f;
+
+    // Extensions are never negotiated and clients must mask all frames.
+    if b0 & 0x70 != 0 || b1 & 0x80 == 0 {
+        return Err(CLOSE_PROTOCOL_ERROR);
+    }
+
+    let (len, offset) = match b1 & 0x7f {
+        126 if input.len() >= 4 => (u16::from_be_bytes([input[2], input[3]]) as u64, 4),
+        127 if input.len() >= 10 => (u64::from_be_bytes(input[2..10].try_into().unwrap()), 10),
+        126 | 127 => return Ok(None),
+        len => (len as u64, 2),
+    };
+    if opcode & 0x8 != 0 && (!fin || len > 125) {
+        return Err(CLOSE_PROTOCOL_ERROR);
+    }
+    if len > max_message_size as u64 {
+        return Err(CLOSE_TOO_BIG);
+    }
+
+    let start = offset + 4;
+    let end = start + len as usize;
+    if input.len() < end {
+        return Ok(None);
+    }
+
+    let mask = &input[offset..start];
+    let payload = input[start..end]
+        .iter()
+        .zip(mask.iter().cycle())
+        .map(|(b, m)| b ^ m)
+        .collect();
+    Ok(Some((
+        Frame {
+            fin,
+            opcode,
+            payload,
+        },
+        end,
+    )))
+}
+
+/// A WebSocket connection over `S`, on which the handshake has been completed
+pub struct Socket<S> {
+    stream: S,
+    policy: Policy,
+
+    /// Data received from the client, which has not been parsed yet
+    input: Vec<u8>,
+
+    /// Opcode and payload of the fragmented message being received
+    message: Option<(u8, Vec<u8>)>,
+
+    /// Records of the received messages, which have not been read yet
+    records: VecDeque<u8>,
+
+    /// Incomplete record written by the workload
+    output: Vec<u8>,
+
+    /// Whether the connection has been closed
+    closed: bool,
+
+    /// Whether `stream` is in non-blocking mode
+    nonblocking: bool,
+}
+
+/// A WebSocket connection over TLS
+pub type Stream = Socket<tls::Stream>;
+
+impl<S: Read + Write> Socket<S> {
+    fn new(stream: S, policy: Policy, input: Vec<u8>) -> Self {
+        Self {
+            stream,
+            policy,
+            input,
+            message: None,
+            records: VecDeque::new(),
+            output: vec![],
+            closed: false,
+            nonblocking: false,
+        }
+    }
+
+    /// Sends a single frame.
+    fn send(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mut header = vec![0x80 | opcode];
+        match payload.len() {
+            len @ 0..=125 => header.push(len as u8),
+            len @ 126..=0xffff => {
+                header.push(126);
+                header.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                header.push(127);
+                header.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        self.stream.write_all(&header)?;
+        self.stream.write_all(payload)?;
+        self.stream.flush()
+    }
+
+    /// Closes the connection with status `code`.
+    fn close(&mut self, code: u16) -> io::Result<()> {
+        self.closed = true;
+        self.send(OP_CLOSE, &code.to_be_bytes())
+    }
+
+    /// Processes a frame from the received data and returns whether there was one.
+    fn receive(&mut self) -> io::Result<bool> {
+        let frame = match frame(&self.input, self.policy.max_message_size) {
+            Ok(Some((frame, size))) => {
+                self.input.drain(..size);
+                frame
+            }
+            Ok(None) => return Ok(false),
+            Err(code) => return self.close(code).map(|_| true),
+        };
+
+        match frame.opcode {
+            OP_CLOSE => {
+                // Echo the status code of the client.
+                self.closed = true;
+                let code = frame.payload.get(..2).unwrap_or_default().to_vec();
+                self.send(OP_CLOSE, &code)?;
+            }
+            OP_PING => self.send(OP_PONG, &frame.payload)?,
+            OP_PONG => {}
+            OP_TEXT | OP_BINARY | OP_CONTINUATION => {
+                let (opcode, payload) = match (frame.opcode, self.message.take()) {
+                    (OP_CONTINUATION, Some((opcode, mut payload))) => {
+                        payload.extend(frame.payload);
+                        (opcode, payload)
+                    }
+                    (OP_CONTINUATION, None) | (_, Some(..)) => {
+                        return self.close(CLOSE_PROTOCOL_ERROR).map(|_| true)
+                    }
+                    (opcode, None) => (opcode, frame.payload),
+                };
+
+                if payload.len() > self.policy.max_message_size {
+                    self.close(CLOSE_TOO_BIG)?;
+                } else if !frame.fin {
+                    self.message = Some((opcode, payload));
+                } else if opcode == OP_TEXT && std::str::from_utf8(&payload).is_err() {
+                    self.close(CLOSE_INVALID_DATA)?;
+                } else {
+                    self.records.push_back(opcode);
+                    self.records.extend((payload.len() as u32).to_be_bytes());
+                    self.records.extend(payload);
+                }
+            }
+            _ => self.close(CLOSE_PROTOCOL_ERROR)?,
+        }
+        Ok(true)
+    }
+
+    /// Reads records of received messages into `bufs`, returning `0` once the
+    /// connection has been closed.
+    fn read(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        loop {
+            if !self.records.is_empty() {
+                return self.records.read_vectored(bufs);
+            }
+            if self.closed {
+                return Ok(0);
+            }
+            if self.receive()? {
+                continue;
+            }
+
+            let mut buf = [0; 4096];
+            match self.stream.read(&mut buf) {
+                Ok(0) => self.closed = true,
+                Ok(n) => self.input.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock && !self.nonblocking => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Sends the messages of all complete records written so far.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.closed {
+            return Err(ErrorKind::BrokenPipe.into());
+        }
+
+        self.output.extend_from_slice(buf);
+        while self.output.len() >= RECORD_HEADER_SIZE {
+            let opcode = self.output[0];
+            let len = u32::from_be_bytes(self.output[1..RECORD_HEADER_SIZE].try_into().unwrap());
+            let len = len as usize;
+            if !matches!(opcode, OP_TEXT | OP_BINARY) || len > self.policy.max_message_size {
+                self.output.clear();
+                return Err(ErrorKind::InvalidInput.into());
+            }
+            if self.output.len() < RECORD_HEADER_SIZE + len {
+                break;
+            }
+
+            let record: Vec<_> = self.output.drain(..RECORD_HEADER_SIZE + len).collect();
+            let payload = &record[RECORD_HEADER_SIZE..];
+            if opcode == OP_TEXT && std::str::from_utf8(payload).is_err() {
+                return Err(ErrorKind::InvalidInput.into());
+            }
+            self.send(opcode, payload)?;
+        }
+        Ok(buf.len())
+    }
+}
+
+impl From<Stream> for Box<dyn WasiFile> {
+    fn from(value: Stream) -> Self {
+        Box::new(value)
+    }
+}
+
+#[wiggle::async_trait]
+impl WasiFile for Stream {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::SocketStream)
+    }
+
+    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
+        self.stream.get_fdflags().await
+    }
+
+    async fn set_fdflags(&mut self, fdflags: FdFlags) -> Result<(), Error> {
+        self.stream.set_fdflags(fdflags).await?;
+        self.nonblocking = fdflags == FdFlags::NONBLOCK;
+        Ok(())
+    }
+
+    async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        let n = self.read(bufs).map_err(errmap)?;
+        Ok(n as _)
+    }
+
+    async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        let mut n = 0;
+        for buf in bufs {
+            n += self.write(buf).map_err(errmap)?;
+        }
+        Ok(n as _)
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        self.stream.readable().await
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        self.stream.writable().await
+    }
+
+    #[cfg(unix)]
+    fn pollable(&self) -> Option<rustix::fd::BorrowedFd<'_>> {
+        self.stream.pollable()
+    }
+
+    #[cfg(windows)]
+    fn pollable(&self) -> Option<io_extras::os::windows::RawHandleOrSocket> {
+        self.stream.pollable()
+    }
+}
+
+/// A listener accepting WebSocket connections over TLS
+pub struct Listener {
+    tls: tls::Listener,
+    policy: Policy,
+}
+
+impl Listener {
+    pub fn new(tls: tls::Listener, policy: Policy) -> Self {
+        Self { tls, policy }
+    }
+
+    /// Returns the TLS listener, which the connections are accepted on.
+    pub fn tls(&self) -> &tls::Listener {
+        &self.tls
+    }
+}
+
+impl From<Listener> for Box<dyn WasiFile> {
+    fn from(value: Listener) -> Self {
+        Box::new(value)
+    }
+}
+
+#[wiggle::async_trait]
+impl WasiFile for Listener {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    #[cfg(unix)]
+    fn pollable(&self) -> Option<rustix::fd::BorrowedFd<'_>> {
+        self.tls.pollable()
+    }
+
+    #[cfg(windows)]
+    fn pollable(&self) -> Option<io_extras::os::windows::RawHandleOrSocket> {
+        self.tls.pollable()
+    }
+
+    async fn sock_accept(&mut self, fdflags: FdFlags) -> Result<Box<dyn WasiFile>, Error> {
+        let nonblocking = self.tls.get_fdflags().await? == FdFlags::NONBLOCK;
+        let mut stream = self.tls.accept(nonblocking.then_some(Duration::ZERO))?;
+        let timeout = self.tls.timeout();
+        let input =
+            handshake(&mut Deadline::new(&mut stream, timeout), &self.policy).map_err(errmap)?;
+
+        let mut socket = Socket::new(stream, self.policy.clone(), input);
+        socket.set_fdflags(fdflags).await?;
+        Ok(Box::new(socket))
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::SocketStream)
+    }
+
+    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
+        self.tls.get_fdflags().await
+    }
+
+    async fn set_fdflags(&mut self, fdflags: FdFlags) -> Result<(), Error> {
+        self.tls.set_fdflags(fdflags).await
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::os::unix::net::UnixStream;
+
+    const REQUEST: &str = "GET /chat HTTP/1.1\r\n\
+        Host: server.example.com\r\n\
+        Upgrade: websocket\r\n\
+        Connection: keep-alive, Upgrade\r\n\
+        Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+        Origin: http://example.com\r\n\
+        Sec-WebSocket-Version: 13\r\n\r\n";
+
+    fn policy(max_message_size: usize) -> Policy {
+        Policy {
+            max_message_size,
+            origins: None,
+        }
+    }
+
+    /// Returns a masked frame sent by a client.
+    fn masked(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = vec![first, 0x80 | payload.len() as u8];
+        frame.extend(mask);
+        frame.extend(payload.iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m));
+        frame
+    }
+
+    fn read(socket: &mut Socket<UnixStream>) -> Vec<u8> {
+        let mut buf = [0; 64];
+        let n = socket.read(&mut [IoSliceMut::new(&mut buf)]).unwrap();
+        buf[..n].to_vec()
+    }
+
+    fn response(mut client: &UnixStream) -> String {
+        let mut response = vec![];
+        while !response.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            client.read_exact(&mut byte).unwrap();
+            response.push(byte[0]);
+        }
+        String::from_utf8(response).unwrap()
+    }
+
+    #[test]
+    fn accept() {
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+        client.write_all(REQUEST.as_bytes()).unwrap();
+        client.write_all(&masked(0x82, &[1])).unwrap();
+
+        let input = handshake(&mut server, &policy(DEFAULT_MAX_MESSAGE_SIZE)).unwrap();
+        assert_eq!(input, masked(0x82, &[1]));
+
+        let response = response(&client);
+        assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+    }
+
+    #[test]
+    fn origins() {
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+        client.write_all(REQUEST.as_bytes()).unwrap();
+
+        let policy = Policy {
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            origins: Some(vec!["https://example.com".into()]),
+        };
+        assert!(handshake(&mut server, &policy).is_err());
+        assert!(response(&client).starts_with("HTTP/1.1 403 Forbidden\r\n"));
+    }
+
+    #[test]
+    fn messages() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let mut socket = Socket::new(server, policy(16), vec![]);
+
+        // A fragmented text message interleaved with a ping.
+        client.write_all(&masked(0x01, b"Hel")).unwrap();
+        client.write_all(&masked(0x89, b"ping")).unwrap();
+        client.write_all(&masked(0x80, b"lo")).unwrap();
+        assert_eq!(read(&mut socket), b"\x01\0\0\0\x05Hello");
+
+        let mut pong = [0; 6];
+        client.read_exact(&mut pong).unwrap();
+        assert_eq!(&pong, b"\x8a\x04ping");
+
+        // A record written in two parts is sent as a single frame.
+        assert_eq!(socket.write(b"\x02\0\0").unwrap(), 3);
+        assert_eq!(socket.write(b"\0\x03\x01\x02\x03").unwrap(), 5);
+        let mut frame = [0; 5];
+        client.read_exact(&mut frame).unwrap();
+        assert_eq!(&frame, b"\x82\x03\x01\x02\x03");
+
+        // Invalid records are rejected.
+        assert!(socket.write(b"\x09\0\0\0\0").is_err());
+    }
+
+    #[test]
+    fn too_big() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let mut socket = Socket::new(server, policy(4), vec![]);
+
+        client.write_all(&masked(0x82, b"12345")).unwrap();
+        assert_eq!(read(&mut socket), b"");
+
+        let mut close = [0; 4];
+        client.read_exact(&mut close).unwrap();
+        assert_eq!(close, [0x88, 0x02, 0x03, 0xf1]);
+        assert!(socket.write(b"\x02\0\0\0\0").is_err());
+    }
+}
@@ -0,0 +1,116 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Policy hints for the Steward
+//!
+//! A keep requests the hints listed in `steward_hints` of its config as non-critical extensions of
+//! its certificate signing request, next to the attestation report, such that a Steward can
+//! enforce a fine-grained issuance policy. None are requested by default, since older Stewards
+//! may reject requests with unknown extensions.
+
+use super::platform::Technology;
+
+use anyhow::Result;
+use const_oid::db::rfc5912::ID_SHA_256;
+use const_oid::ObjectIdentifier;
+use enarx_config::StewardHint;
+use sha2::{Digest, Sha256};
+use x509_cert::der::asn1::{OctetStringRef, Utf8StringRef};
+use x509_cert::der::Encode;
+use x509_cert::ext::Extension;
+
+/// Version of Enarx running the keep, a `UTF8String`
+const VERSION: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.4.1.58270.2.1");
+/// Backend technology of the keep, the OID of its attestation report extension
+const TECHNOLOGY: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.4.1.58270.2.2");
+/// SHA-256 digest of `Enarx.toml`, an `OCTET STRING`
+const CONFIG: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.4.1.58270.2.3");
+/// Digest algorithms the workload is digested with, a `SEQUENCE OF` OIDs
+const DIGEST_ALGORITHMS: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.4.1.58270.2.4");
+
+/// A policy hint, the DER-encoded value of a certificate signing request extension
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hint {
+    oid: ObjectIdentifier,
+    value: Vec<u8>,
+}
+
+impl Hint {
+    /// Returns the hint as a certificate signing request extension.
+    pub fn extension(&self) -> Extension<'_> {
+        Extension {
+            extn_id: self.oid,
+            critical: false,
+            extn_value: &self.value,
+        }
+    }
+}
+
+/// Encodes the hints `requested` for a keep on `technology` with the config `config`, if any.
+///
+/// Every hint is encoded once, in the order it is requested. The config hint is omitted without
+/// a config.
+pub fn encode(
+    requested: &[StewardHint],
+    technology: Technology,
+    config: Option<&str>,
+) -> Result<Vec<Hint>> {
+    let mut hints: Vec<Hint> = Vec::with_capacity(requested.len());
+    for hint in requested {
+        let (oid, value) = match hint {
+            StewardHint::Version => (
+                VERSION,
+                Utf8StringRef::new(env!("CARGO_PKG_VERSION"))?.to_vec()?,
+            ),
+            StewardHint::Technology => (TECHNOLOGY, ObjectIdentifier::from(technology).to_vec()?),
+            StewardHint::Config => match config {
+                Some(config) => (
+                    CONFIG,
+                    OctetStringRef::new(&Sha256::digest(config))?.to_vec()?,
+                ),
+                None => continue,
+            },
+            StewardHint::DigestAlgorithms => (DIGEST_ALGORITHMS, vec![ID_SHA_256].to_vec()?),
+        };
+        if hints.iter().all(|hint| hint.oid != oid) {
+            hints.push(Hint { oid, value });
+        }
+    }
+    Ok(hints)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use x509_cert::der::Decode;
+
+    #[test]
+    fn encoding() {
+        let requested = [
+            StewardHint::Version,
+            StewardHint::Technology,
+            StewardHint::Config,
+            StewardHint::DigestAlgorithms,
+            StewardHint::Version,
+        ];
+        let hints = encode(&requested, Technology::Sgx, Some("args = []")).unwrap();
+        let oids: Vec<_> = hints.iter().map(|hint| hint.oid).collect();
+        assert_eq!(oids, [VERSION, TECHNOLOGY, CONFIG, DIGEST_ALGORITHMS]);
+
+        let version = Utf8StringRef::from_der(&hints[0].value).unwrap();
+        assert_eq!(version.as_str(), env!("CARGO_PKG_VERSION"));
+        assert_eq!(
+            ObjectIdentifier::from_der(&hints[1].value).unwrap(),
+            ObjectIdentifier::from(Technology::Sgx)
+        );
+        let digest = OctetStringRef::from_der(&hints[2].value).unwrap();
+        assert_eq!(digest.as_bytes(), &Sha256::digest("args = []")[..]);
+        assert_eq!(
+            Vec::<ObjectIdentifier>::from_der(&hints[3].value).unwrap(),
+            [ID_SHA_256]
+        );
+        assert!(!hints[3].extension().critical);
+
+        let hints = encode(&[StewardHint::Config], Technology::Kvm, None).unwrap();
+        assert!(hints.is_empty());
+    }
+}
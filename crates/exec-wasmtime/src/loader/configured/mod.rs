@@ -2,14 +2,20 @@
 
 #![allow(dead_code)]
 
-mod platform;
+pub(super) mod hints;
+pub(super) mod platform;
 
 #[allow(unused_imports)]
 use platform::{Platform, Technology};
 
+use hints::Hint;
+
+use super::super::DEFAULT_MAX_WASM_SIZE;
+#[cfg(unix)]
+use super::sealed::Sealer;
 use super::{pki::PrivateKeyInfoExt, Configured, Loader, Requested};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use const_oid::db::rfc5912::{SECP_256_R_1, SECP_384_R_1};
 use const_oid::AssociatedOid;
 use pkcs8::PrivateKeyInfo;
@@ -52,18 +58,9 @@ impl Loader<Configured> {
         Ok(req.to_vec()?)
     }
 
-    pub fn next(self) -> Result<Loader<Requested>> {
-        let platform = Platform::get()?;
-        let cert_algo = match platform.technology() {
-            Technology::Snp => SECP_384_R_1,
-            Technology::Sgx => SECP_256_R_1,
-            Technology::Kvm => SECP_256_R_1,
-        };
-
-        // Generate a keypair.
-        let raw = PrivateKeyInfo::generate(cert_algo)?;
-        let pki = PrivateKeyInfo::from_der(raw.as_ref())?;
-        let der = pki.public_key().unwrap().to_vec().unwrap();
+    /// Attests `pki` on `platform` and returns the attestation report.
+    pub fn attest(platform: &Platform, pki: &PrivateKeyInfo<'_>) -> Result<Vec<u8>> {
+        let der = pki.public_key()?.to_vec()?;
 
         let mut key_hash = [0u8; 64];
         match platform.technology() {
@@ -77,22 +74,96 @@ impl Loader<Configured> {
             }
         };
 
-        let attestation_report = platform.attest(&key_hash)?;
+        Ok(platform.attest(&key_hash)?)
+    }
 
+    /// Returns a certificate signing request for `pki` including the attestation `report` of
+    /// `technology` and the policy `hints` for the Steward.
+    pub fn csr(
+        technology: Technology,
+        report: &[u8],
+        pki: &PrivateKeyInfo<'_>,
+        hints: &[Hint],
+    ) -> Result<Vec<u8>> {
         // Create extensions.
-        let ext = vec![Extension {
-            extn_id: platform.technology().into(),
+        let mut exts = vec![Extension {
+            extn_id: technology.into(),
             critical: false,
-            extn_value: &attestation_report,
+            extn_value: report,
         }];
+        exts.extend(hints.iter().map(Hint::extension));
 
         // Make a certificate signing request.
-        let req = Self::make_csr(&pki, ext)?;
+        Self::make_csr(pki, exts)
+    }
+
+    /// Attests `pki` on `platform` and returns a certificate signing request including the
+    /// attestation report and the policy `hints` for the Steward.
+    pub fn request(
+        platform: &Platform,
+        pki: &PrivateKeyInfo<'_>,
+        hints: &[Hint],
+    ) -> Result<Vec<u8>> {
+        let report = Self::attest(platform, pki)?;
+        Self::csr(platform.technology(), &report, pki, hints)
+    }
+
+    pub fn next(self) -> Result<Loader<Requested>> {
+        let platform = Platform::get()?;
+        if self.0.args.faults.is_some() && platform.technology() != Technology::Kvm {
+            bail!("fault injection is only supported on backends without hardware isolation");
+        }
+        if self.0.args.mounts && platform.technology() != Technology::Kvm {
+            bail!("mounting host directories is only supported on backends without hardware isolation");
+        }
+        if self.0.args.debug && !cfg!(debug_assertions) {
+            bail!("debugging the workload is only supported by debug builds");
+        }
+        let cert_algo = match platform.technology() {
+            Technology::Snp => SECP_384_R_1,
+            Technology::Sgx => SECP_256_R_1,
+            Technology::Kvm => SECP_256_R_1,
+        };
+
+        // Generate a keypair.
+        let raw = PrivateKeyInfo::generate(cert_algo)?;
+        let pki = PrivateKeyInfo::from_der(raw.as_ref())?;
+
+        // Attest the key, the certificate signing request is made once the config is known.
+        let report = Self::attest(&platform, &pki)?;
+
+        // Compiled modules are cached sealed to the platform.
+        #[cfg(unix)]
+        let cache = self
+            .0
+            .args
+            .cache
+            .map(|fd| Ok::<_, anyhow::Error>((fd, Sealer::new(&platform.key()?)?)))
+            .transpose()?;
 
         Ok(Loader(Requested {
             package: self.0.args.package,
+            env: self.0.args.env,
             prvkey: raw,
-            crtreq: req,
+            report,
+            technology: platform.technology(),
+            faults: self.0.args.faults,
+            max_wasm_size: self.0.args.max_wasm_size.unwrap_or(DEFAULT_MAX_WASM_SIZE),
+            digest: self.0.args.digest,
+            debug: self.0.args.debug,
+            mounts: self.0.args.mounts,
+            #[cfg(unix)]
+            hold: self.0.args.hold,
+            #[cfg(unix)]
+            handoff: self.0.args.csr.zip(self.0.args.crt),
+            #[cfg(unix)]
+            cached: self.0.args.cached,
+            #[cfg(unix)]
+            cache,
+            #[cfg(unix)]
+            listeners: self.0.args.listeners,
+            #[cfg(unix)]
+            logs: self.0.args.logs,
         }))
     }
 }
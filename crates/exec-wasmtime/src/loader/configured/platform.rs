@@ -117,6 +117,91 @@ impl Platform {
         }
     }
 
+    #[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+    pub fn stats() -> Result<String> {
+        Ok(String::new())
+    }
+
+    #[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+    pub fn collect(_metrics: &mut crate::Metrics) -> Result<()> {
+        Ok(())
+    }
+
+    /// Obtains the event counters of the keep via the `get_stats` syscall to the shim.
+    ///
+    /// Returns `None` if not running in a keep.
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    fn get_stats() -> Result<Option<Box<sallyport::item::enarxcall::Stats>>> {
+        use sallyport::item::enarxcall::{Stats, SYS_GETSTATS};
+        use std::arch::asm;
+
+        const ENOSYS: isize = -(libc::ENOSYS as isize);
+        const EPERM: isize = -(libc::EPERM as isize);
+
+        let mut stats = Box::new(Stats::new());
+        let mut rax: isize;
+
+        unsafe {
+            asm!(
+            "syscall",
+            lateout("rax") rax,
+            in("rax") SYS_GETSTATS,
+            in("rdi") stats.as_mut() as *mut Stats,
+            lateout("rcx") _, // clobbered
+            lateout("r11") _, // clobbered
+            )
+        }
+
+        match rax {
+            ENOSYS | EPERM => Ok(None),
+            n if n < 0 => Err(std::io::Error::from_raw_os_error(-n as i32)),
+            _ => Ok(Some(stats)),
+        }
+    }
+
+    /// Renders the event counters of the keep obtained via the `get_stats` syscall to the shim.
+    ///
+    /// Returns an empty report if not running in a keep.
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    pub fn stats() -> Result<String> {
+        Ok(Self::get_stats()?
+            .map(|stats| Self::render_stats(&stats))
+            .unwrap_or_default())
+    }
+
+    /// Copies the event counters of the keep into `metrics`.
+    ///
+    /// Leaves `metrics` untouched if not running in a keep.
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    pub fn collect(metrics: &mut crate::Metrics) -> Result<()> {
+        if let Some(stats) = Self::get_stats()? {
+            metrics.exits = stats.exits;
+            metrics.bytes = stats.bytes;
+            metrics.syscalls = stats
+                .syscalls
+                .iter()
+                .enumerate()
+                .filter(|(_, count)| **count > 0)
+                .map(|(nr, count)| (nr, *count))
+                .collect();
+        }
+        Ok(())
+    }
+
+    /// Renders `stats` as `<counter> <value>` lines, omitting syscalls, which were never made.
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    fn render_stats(stats: &sallyport::item::enarxcall::Stats) -> String {
+        use std::fmt::Write;
+
+        let mut out = format!("exits {}\nbytes {}\n", stats.exits, stats.bytes);
+        for (nr, count) in stats.syscalls.iter().enumerate() {
+            if *count > 0 {
+                writeln!(out, "syscall.{nr} {count}").unwrap();
+            }
+        }
+        out
+    }
+
     pub fn get() -> Result<Self> {
         let (technology, report_size) = Self::get_att(None, None)?;
         let key_size = Self::get_key(None)?;
@@ -165,3 +250,21 @@ fn test() {
     let report = platform.attest(b"00000000").unwrap();
     assert!(report.is_empty());
 }
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+#[test]
+fn stats() {
+    use sallyport::item::enarxcall::Stats;
+
+    assert_eq!(Platform::stats().unwrap(), "");
+
+    let mut stats = Stats::new();
+    stats.exits = 3;
+    stats.bytes = 4096;
+    stats.syscalls[libc::SYS_read as usize] = 2;
+    stats.syscalls[libc::SYS_write as usize] = 1;
+    assert_eq!(
+        Platform::render_stats(&stats),
+        "exits 3\nbytes 4096\nsyscall.0 2\nsyscall.1 1\n"
+    );
+}
@@ -1,25 +1,428 @@
 // SPDX-License-Identifier: Apache-2.0
 
-use super::{Completed, Connected, Loader};
+use super::super::metrics::METRICS;
+use super::super::{shutdown, ExitCode};
+use super::compiled::https::{Response, Server};
+#[cfg(unix)]
+use super::renewal;
+use super::{Completed, Connected, Ctx, Instance, Loader};
 
-use anyhow::{bail, Context, Result};
-use wasmtime::Trap;
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, error, warn};
+use wasmtime::{Engine, Func, Linker, Module, Store, Trap, TrapCode, TypedFunc, Val};
+
+/// Interval at which the ticker checks whether the workload should be interrupted
+const TICK: Duration = Duration::from_millis(10);
+
+/// Number of epoch checks of the workload, after which the watchdog reads the clocks and polls the
+/// renewal of the certificate
+const CHECKS: u32 = 1 << 12;
+
+/// Name of the export handling the requests of a listen socket with `prot = "https"`
+const HTTP_HANDLER: &str = "handle_http_request";
+
+/// The limit, which the workload was interrupted for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Exceeded {
+    /// The wall-clock execution time limit
+    Time(Duration),
+    /// The CPU time limit
+    CpuTime(Duration),
+}
+
+impl fmt::Display for Exceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Time(limit) => write!(f, "execution time limit of {}s", limit.as_secs()),
+            Self::CpuTime(limit) => write!(f, "CPU time limit of {}s", limit.as_secs()),
+        }
+    }
+}
+
+/// Returns the CPU time consumed by the keep.
+///
+/// In a keep, the clock is read on the host, which accounts the time the threads of the keep
+/// spent running, i.e. in the vCPUs on kvm and sev and in the enclave on sgx, but not the time
+/// they spent waiting.
+#[cfg(unix)]
+fn cpu_time() -> io::Result<Duration> {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    if unsafe { libc::clock_gettime(libc::CLOCK_PROCESS_CPUTIME_ID, &mut ts) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Duration::new(ts.tv_sec as _, ts.tv_nsec as _))
+}
+
+#[cfg(not(unix))]
+fn cpu_time() -> io::Result<Duration> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "CPU time limit is not supported on this platform",
+    ))
+}
+
+/// Returns whether the keep can create threads, which the shims of hardware backends cannot.
+pub(super) fn threads() -> bool {
+    thread::Builder::new()
+        .name("probe".into())
+        .spawn(|| {})
+        .map_or(false, |probe| probe.join().is_ok())
+}
+
+/// Returns whether the workload renews its certificate itself.
+fn renewal_polled() -> bool {
+    #[cfg(unix)]
+    return renewal::polled();
+    #[cfg(not(unix))]
+    false
+}
+
+/// Spawns a thread, which increments the epoch of `engine` once `timeout` or `cpu_timeout` is
+/// reached or a shutdown is requested, interrupting the workload. The thread exits once `done` is
+/// set and returns the exceeded limit, if any.
+fn spawn_ticker(
+    engine: Engine,
+    timeout: Option<Duration>,
+    cpu_timeout: Option<Duration>,
+    done: Arc<AtomicBool>,
+) -> io::Result<thread::JoinHandle<Option<Exceeded>>> {
+    let start = Instant::now();
+    // The CPU time consumed before, e.g. by compiling the workload, does not count.
+    let cpu_start = cpu_timeout.map(|_| cpu_time()).transpose()?;
+    thread::Builder::new().name("ticker".into()).spawn(move || {
+        while !done.load(Ordering::Relaxed) {
+            let exceeded = match (timeout, cpu_timeout, cpu_start) {
+                (Some(timeout), ..) if start.elapsed() >= timeout => Some(Exceeded::Time(timeout)),
+                (_, Some(cpu_timeout), Some(cpu_start))
+                    if cpu_time().map_or(false, |now| now - cpu_start >= cpu_timeout) =>
+                {
+                    Some(Exceeded::CpuTime(cpu_timeout))
+                }
+                _ => None,
+            };
+            if exceeded.is_some() || shutdown::requested() {
+                engine.increment_epoch();
+                return exceeded;
+            }
+            thread::sleep(TICK);
+        }
+        None
+    })
+}
+
+/// Checks the time limits and renews the certificate, if due, at the epoch checks of the workload
+/// in `wstore`, i.e. at the entry of every function and the header of every loop, for keeps which
+/// cannot spawn the ticker and renewal threads.
+///
+/// Every check calls into the keep, so this slows the workload down, and since reading the clocks
+/// exits the keep, they are only read every [`CHECKS`] checks. The workload traps once a limit is
+/// exceeded, which is then recorded in the returned slot.
+fn watch<T>(
+    wstore: &mut Store<T>,
+    timeout: Option<Duration>,
+    cpu_timeout: Option<Duration>,
+) -> io::Result<Arc<Mutex<Option<Exceeded>>>> {
+    let start = Instant::now();
+    // The CPU time consumed before, e.g. by compiling the workload, does not count.
+    let cpu_start = cpu_timeout.map(|_| cpu_time()).transpose()?;
+    let exceeded = Arc::new(Mutex::new(None));
+    let mut checks = 0;
+    wstore.epoch_deadline_callback({
+        let exceeded = exceeded.clone();
+        move |_| {
+            checks += 1;
+            if checks < CHECKS {
+                return Ok(0);
+            }
+            checks = 0;
+            #[cfg(unix)]
+            renewal::poll();
+            let limit = match (timeout, cpu_timeout, cpu_start) {
+                (Some(timeout), ..) if start.elapsed() >= timeout => Exceeded::Time(timeout),
+                (_, Some(cpu_timeout), Some(cpu_start))
+                    if cpu_time().map_or(false, |now| now - cpu_start >= cpu_timeout) =>
+                {
+                    Exceeded::CpuTime(cpu_timeout)
+                }
+                // The engine never advances its epoch, so the deadline is reached at every check.
+                _ => return Ok(0),
+            };
+            *exceeded.lock().unwrap() = Some(limit);
+            Err(anyhow!("workload exceeded its {limit}"))
+        }
+    });
+    wstore.set_epoch_deadline(0);
+    Ok(exceeded)
+}
+
+/// The function running a linked module
+enum Entry {
+    /// The default function of a command or of a reactor without a handler
+    Default(Func),
+    /// The handler of a reactor, which is called until it returns a non-zero value
+    Handler(TypedFunc<(), i32>),
+    /// The HTTP request handler of a reactor, which is called for every request of the server
+    Server(TypedFunc<(), ()>, Server),
+}
+
+/// Links `module` in `wstore` and returns the function running it.
+///
+/// Every call to an export of a command, which exports `_start`, creates a new instance, while
+/// any other module is a reactor, which is instantiated once and initialized by calling its
+/// `_initialize` export, if any. A module serving HTTP requests must be a reactor.
+fn link(
+    wstore: &mut Store<Ctx>,
+    linker: &mut Linker<Ctx>,
+    module: &Module,
+    handler: Option<&str>,
+) -> Result<Entry> {
+    let server = wstore.data_mut().http.take_server();
+    if server.is_some() {
+        if module.get_export("_start").is_some() {
+            bail!("`prot = \"https\"` requires a reactor module, which does not export `_start`");
+        }
+        if handler.is_some() {
+            bail!("`prot = \"https\"` cannot be combined with `handler`");
+        }
+    }
+
+    if module.get_export("_start").is_some() {
+        linker.module(&mut *wstore, "", module)?;
+        return Ok(Entry::Default(linker.get_default(&mut *wstore, "")?));
+    }
+
+    let instance = linker.instantiate(&mut *wstore, module)?;
+    if module.get_export("_initialize").is_some() {
+        instance
+            .get_typed_func::<(), (), _>(&mut *wstore, "_initialize")?
+            .call(&mut *wstore, ())
+            .context("failed to initialize reactor")?;
+    }
+    if let Some(server) = server {
+        let handler = instance
+            .get_typed_func::<(), (), _>(&mut *wstore, HTTP_HANDLER)
+            .with_context(|| format!("`prot = \"https\"` requires a `{HTTP_HANDLER}` export"))?;
+        return Ok(Entry::Server(handler, server));
+    }
+    match handler {
+        Some(handler) => Ok(Entry::Handler(
+            instance.get_typed_func::<(), i32, _>(&mut *wstore, handler)?,
+        )),
+        None => {
+            linker.instance(&mut *wstore, "", instance)?;
+            Ok(Entry::Default(linker.get_default(&mut *wstore, "")?))
+        }
+    }
+}
+
+/// Links and runs `module`, returning the results of its default function or the non-zero
+/// result of its handler.
+fn run(
+    wstore: &mut Store<Ctx>,
+    linker: &mut Linker<Ctx>,
+    module: &Module,
+    handler: Option<&str>,
+) -> Result<Vec<Val>> {
+    match link(wstore, linker, module, handler)? {
+        Entry::Default(func) => {
+            let mut values = vec![Val::null(); func.ty(&*wstore).results().len()];
+            func.call(&mut *wstore, Default::default(), &mut values)?;
+            Ok(values)
+        }
+        Entry::Handler(handler) => loop {
+            // A shutdown stops a reactor gracefully in between two calls.
+            if shutdown::requested() {
+                return Ok(vec![]);
+            }
+            match handler.call(&mut *wstore, ())? {
+                0 => continue,
+                ret => return Ok(vec![Val::I32(ret)]),
+            }
+        },
+        Entry::Server(handler, mut server) => loop {
+            // A shutdown stops the server gracefully in between two requests.
+            if shutdown::requested() {
+                return Ok(vec![]);
+            }
+            let mut conn = match server.accept() {
+                Ok(Some(conn)) => conn,
+                Ok(None) => continue,
+                Err(e) => {
+                    debug!("failed to accept HTTP connection: {e:#}");
+                    continue;
+                }
+            };
+            let request = match conn.request() {
+                Ok(request) => request,
+                Err(e) => {
+                    debug!("failed to read HTTP request: {e}");
+                    continue;
+                }
+            };
+
+            wstore.data_mut().http.begin(request);
+            let res = handler.call(&mut *wstore, ());
+            let response = wstore.data_mut().http.end();
+            let response = match res {
+                Ok(()) => response,
+                Err(trap) => {
+                    // An exit or an interruption ends the workload, any other trap only the request.
+                    if trap.i32_exit_status().is_some()
+                        || trap.trap_code() == Some(TrapCode::Interrupt)
+                    {
+                        let _ = conn.respond(&Response::status(503));
+                        return Err(trap.into());
+                    }
+                    error!("failed to handle HTTP request: {trap}");
+                    Response::status(500)
+                }
+            };
+            if let Err(e) = conn.respond(&response) {
+                debug!("failed to write HTTP response: {e}");
+            }
+        },
+    }
+}
+
+/// Spawns a thread running the service `name`.
+///
+/// A failure of the service is only logged, since the keep runs until the main module exits.
+fn spawn_service(name: String, instance: Instance) -> io::Result<thread::JoinHandle<()>> {
+    let Instance {
+        mut wstore,
+        mut linker,
+        module,
+        handler,
+    } = instance;
+    thread::Builder::new().name(name.clone()).spawn(move || {
+        if let Err(e) = run(&mut wstore, &mut linker, &module, handler.as_deref()) {
+            match e.downcast_ref::<Trap>().map(Trap::i32_exit_status) {
+                Some(Some(0)) => {}
+                _ => error!("service `{name}` failed: {e:#}"),
+            }
+        }
+    })
+}
 
 impl Loader<Connected> {
     pub fn next(self) -> Result<Loader<Completed>> {
-        let Self(Connected { mut wstore, linker }) = self;
+        let Self(Connected {
+            mut wstore,
+            mut linker,
+            module,
+            handler,
+            services,
+            timeout,
+            cpu_timeout,
+        }) = self;
 
-        let func = linker
-            .get_default(&mut wstore, "")
-            .context("failed to get default function")?;
+        // Services run next to the main module, which cannot be done without threads.
+        for (name, instance) in services {
+            spawn_service(name.clone(), instance)
+                .with_context(|| format!("failed to spawn thread of service `{name}`"))?;
+        }
 
-        let mut values = vec![wasmtime::Val::null(); func.ty(&wstore).results().len()];
-        if let Err(e) = func.call(wstore, Default::default(), &mut values) {
-            match e.downcast_ref::<Trap>().map(Trap::i32_exit_status) {
-                Some(Some(0)) => {} // function exited with a code of 0, treat as success
-                _ => bail!(e.context("failed to execute default function")),
+        // Interrupt the workload once a time limit is reached or a shutdown is requested.
+        let done = Arc::new(AtomicBool::new(false));
+        let engine = wstore.engine().clone();
+        let mut watched = None;
+        let ticker = match spawn_ticker(engine, timeout, cpu_timeout, done.clone()) {
+            Ok(ticker) => Some(ticker),
+            // The shims of the hardware backends cannot create threads, so the workload checks
+            // the time limits and renews its certificate itself.
+            Err(e) if timeout.is_some() || cpu_timeout.is_some() || renewal_polled() => {
+                debug!("failed to spawn ticker thread, checking the time limits and renewal inline: {e}");
+                watched = Some(
+                    watch(&mut wstore, timeout, cpu_timeout)
+                        .context("failed to set up the execution time limits")?,
+                );
+                None
+            }
+            Err(e) => {
+                warn!("failed to spawn ticker thread, the workload cannot be preempted: {e}");
+                None
+            }
+        };
+
+        let res = run(&mut wstore, &mut linker, &module, handler.as_deref());
+        METRICS.lock().unwrap().fuel_consumed = wstore.fuel_consumed();
+
+        done.store(true, Ordering::Relaxed);
+        let exceeded = match (ticker, watched) {
+            (Some(ticker), _) => ticker.join().expect("failed to join ticker thread"),
+            (None, Some(watched)) => *watched.lock().unwrap(),
+            (None, None) => None,
+        };
+
+        let values = match res {
+            Ok(values) => values,
+            Err(e) => {
+                let trap = e.downcast_ref::<Trap>();
+                // The ticker interrupts the workload, while the watchdog traps it with an error.
+                if let (Some(limit), Some(_)) = (exceeded, trap) {
+                    bail!("workload exceeded its {limit}")
+                }
+                match trap.map(Trap::i32_exit_status) {
+                    Some(Some(0)) => vec![], // function exited with a code of 0, treat as success
+                    Some(Some(code)) => return Err(ExitCode(code).into()),
+                    // The workload is interrupted by the ticker or trapped by the scheduler.
+                    _ if trap.is_some() && shutdown::requested() => {
+                        let signal = shutdown::signal();
+                        warn!("workload was shut down by signal {signal}");
+                        return Err(ExitCode(128 + signal).into());
+                    }
+                    _ => bail!(e.context("failed to execute workload")),
+                }
             }
         };
         Ok(Loader(Completed { values }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wasmtime::{Config, Instance};
+
+    /// Runs an endless loop watched for `timeout` and `cpu_timeout` and returns the exceeded limit.
+    fn watched(timeout: Option<Duration>, cpu_timeout: Option<Duration>) -> Option<Exceeded> {
+        let mut config = Config::new();
+        config.epoch_interruption(true);
+        let engine = Engine::new(&config).unwrap();
+        let wasm = wat::parse_str(r#"(module (func (export "main") (loop br 0)))"#).unwrap();
+        let module = Module::new(&engine, wasm).unwrap();
+
+        let mut wstore = Store::new(&engine, ());
+        let exceeded = watch(&mut wstore, timeout, cpu_timeout).unwrap();
+        let main = Instance::new(&mut wstore, &module, &[])
+            .unwrap()
+            .get_typed_func::<(), (), _>(&mut wstore, "main")
+            .unwrap();
+        main.call(&mut wstore, ()).unwrap_err();
+        let exceeded = *exceeded.lock().unwrap();
+        exceeded
+    }
+
+    #[test]
+    fn watch_time() {
+        let limit = Duration::from_millis(100);
+        assert_eq!(watched(Some(limit), None), Some(Exceeded::Time(limit)));
+    }
+
+    #[test]
+    fn watch_cpu_time() {
+        let limit = Duration::from_millis(100);
+        assert_eq!(watched(None, Some(limit)), Some(Exceeded::CpuTime(limit)));
+    }
+}
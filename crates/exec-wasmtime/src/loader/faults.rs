@@ -0,0 +1,301 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Fault injection into the network streams and the clock of the workload
+
+use crate::Faults;
+
+use std::any::Any;
+use std::io::{IoSlice, IoSliceMut, SeekFrom};
+use std::thread;
+use std::time::Duration;
+
+use cap_std::time::SystemTime;
+use getrandom::getrandom;
+#[cfg(windows)]
+use io_extras::os::windows::RawHandleOrSocket;
+use wasi_common::clocks::WasiSystemClock;
+use wasi_common::file::{Advice, FdFlags, FileType, Filestat, RiFlags, RoFlags, SdFlags, SiFlags};
+use wasi_common::{Error, ErrorKind, SystemTimeSpec, WasiFile};
+
+/// Returns a random number in `[0, 1)`, or 1 if no randomness is available.
+fn random() -> f64 {
+    let mut bytes = [0; 8];
+    match getrandom(&mut bytes) {
+        Ok(()) => (u64::from_le_bytes(bytes) >> 11) as f64 / (1u64 << 53) as f64,
+        Err(_) => 1.0,
+    }
+}
+
+impl Faults {
+    /// Returns the error injected into an operation on a stream, if any.
+    fn error(&self, reset: bool) -> Option<Error> {
+        if random() >= self.error_rate {
+            return None;
+        }
+        if reset && random() < 0.5 {
+            Some(std::io::Error::from(rustix::io::Errno::CONNRESET).into())
+        } else {
+            Some(ErrorKind::WouldBlk.into())
+        }
+    }
+
+    /// Sleeps for a random duration of up to `read_latency` milliseconds.
+    fn delay(&self) {
+        if self.read_latency > 0 {
+            let latency = random() * (self.read_latency + 1) as f64;
+            thread::sleep(Duration::from_millis(latency as u64));
+        }
+    }
+}
+
+/// Injects errors into the reads and writes of a network stream and delays its reads
+///
+/// Reads and writes fail with `EAGAIN` or `ECONNRESET`, accepting connections fails with
+/// `EAGAIN`. Connections accepted on a faulty listener are faulty as well.
+pub struct Faulty {
+    faults: Faults,
+    file: Box<dyn WasiFile>,
+}
+
+impl Faulty {
+    pub fn new(faults: Faults, file: Box<dyn WasiFile>) -> Self {
+        Self { faults, file }
+    }
+
+    fn read(&self) -> Result<(), Error> {
+        self.faults.delay();
+        self.faults.error(true).map_or(Ok(()), Err)
+    }
+
+    fn write(&self) -> Result<(), Error> {
+        self.faults.error(true).map_or(Ok(()), Err)
+    }
+}
+
+impl From<Faulty> for Box<dyn WasiFile> {
+    fn from(value: Faulty) -> Self {
+        Box::new(value)
+    }
+}
+
+#[wiggle::async_trait]
+impl WasiFile for Faulty {
+    fn as_any(&self) -> &dyn Any {
+        self.file.as_any()
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        self.file.get_filetype().await
+    }
+
+    #[cfg(unix)]
+    fn pollable(&self) -> Option<rustix::fd::BorrowedFd<'_>> {
+        self.file.pollable()
+    }
+
+    #[cfg(windows)]
+    fn pollable(&self) -> Option<RawHandleOrSocket> {
+        self.file.pollable()
+    }
+
+    fn isatty(&mut self) -> bool {
+        self.file.isatty()
+    }
+
+    async fn sock_accept(&mut self, fdflags: FdFlags) -> Result<Box<dyn WasiFile>, Error> {
+        if let Some(e) = self.faults.error(false) {
+            return Err(e);
+        }
+        let file = self.file.sock_accept(fdflags).await?;
+        Ok(Box::new(Self::new(self.faults, file)))
+    }
+
+    async fn sock_recv<'a>(
+        &mut self,
+        ri_data: &mut [IoSliceMut<'a>],
+        ri_flags: RiFlags,
+    ) -> Result<(u64, RoFlags), Error> {
+        self.read()?;
+        self.file.sock_recv(ri_data, ri_flags).await
+    }
+
+    async fn sock_send<'a>(
+        &mut self,
+        si_data: &[IoSlice<'a>],
+        si_flags: SiFlags,
+    ) -> Result<u64, Error> {
+        self.write()?;
+        self.file.sock_send(si_data, si_flags).await
+    }
+
+    async fn sock_shutdown(&mut self, how: SdFlags) -> Result<(), Error> {
+        self.file.sock_shutdown(how).await
+    }
+
+    async fn datasync(&mut self) -> Result<(), Error> {
+        self.file.datasync().await
+    }
+
+    async fn sync(&mut self) -> Result<(), Error> {
+        self.file.sync().await
+    }
+
+    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
+        self.file.get_fdflags().await
+    }
+
+    async fn set_fdflags(&mut self, flags: FdFlags) -> Result<(), Error> {
+        self.file.set_fdflags(flags).await
+    }
+
+    async fn get_filestat(&mut self) -> Result<Filestat, Error> {
+        self.file.get_filestat().await
+    }
+
+    async fn set_filestat_size(&mut self, size: u64) -> Result<(), Error> {
+        self.file.set_filestat_size(size).await
+    }
+
+    async fn advise(&mut self, offset: u64, len: u64, advice: Advice) -> Result<(), Error> {
+        self.file.advise(offset, len, advice).await
+    }
+
+    async fn allocate(&mut self, offset: u64, len: u64) -> Result<(), Error> {
+        self.file.allocate(offset, len).await
+    }
+
+    async fn set_times(
+        &mut self,
+        atime: Option<SystemTimeSpec>,
+        mtime: Option<SystemTimeSpec>,
+    ) -> Result<(), Error> {
+        self.file.set_times(atime, mtime).await
+    }
+
+    async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        self.read()?;
+        self.file.read_vectored(bufs).await
+    }
+
+    async fn read_vectored_at<'a>(
+        &mut self,
+        bufs: &mut [IoSliceMut<'a>],
+        offset: u64,
+    ) -> Result<u64, Error> {
+        self.read()?;
+        self.file.read_vectored_at(bufs, offset).await
+    }
+
+    async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        self.write()?;
+        self.file.write_vectored(bufs).await
+    }
+
+    async fn write_vectored_at<'a>(
+        &mut self,
+        bufs: &[IoSlice<'a>],
+        offset: u64,
+    ) -> Result<u64, Error> {
+        self.write()?;
+        self.file.write_vectored_at(bufs, offset).await
+    }
+
+    async fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error> {
+        self.file.seek(pos).await
+    }
+
+    async fn peek(&mut self, buf: &mut [u8]) -> Result<u64, Error> {
+        self.file.peek(buf).await
+    }
+
+    async fn num_ready_bytes(&self) -> Result<u64, Error> {
+        self.file.num_ready_bytes().await
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        self.file.readable().await
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        self.file.writable().await
+    }
+}
+
+/// A system clock running `skew` seconds ahead of `clock`, or behind it if negative
+pub struct Skewed {
+    clock: Box<dyn WasiSystemClock>,
+    skew: i64,
+}
+
+impl Skewed {
+    pub fn new(clock: Box<dyn WasiSystemClock>, skew: i64) -> Self {
+        Self { clock, skew }
+    }
+}
+
+impl WasiSystemClock for Skewed {
+    fn resolution(&self) -> Duration {
+        self.clock.resolution()
+    }
+
+    fn now(&self, precision: Duration) -> SystemTime {
+        let now = self.clock.now(precision);
+        let skew = Duration::from_secs(self.skew.unsigned_abs());
+        let skewed = if self.skew < 0 {
+            now.checked_sub(skew)
+        } else {
+            now.checked_add(skew)
+        };
+        skewed.unwrap_or(now)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Faults, Faulty, Skewed};
+
+    use std::io::{IoSliceMut, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::time::Duration;
+
+    use wasi_common::clocks::WasiSystemClock;
+    use wasi_common::WasiFile;
+    use wasmtime_wasi::net::Socket;
+
+    #[test]
+    fn errors() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let server = Socket::from(cap_std::net::TcpStream::from_std(server)).into();
+        client.write_all(b"ping").unwrap();
+
+        let faults = Faults {
+            error_rate: 1.0,
+            ..Default::default()
+        };
+        let mut server = Faulty::new(faults, server);
+        let read = |server: &mut Faulty| {
+            let mut buf = [0; 1];
+            wiggle::run_in_dummy_executor(server.read_vectored(&mut [IoSliceMut::new(&mut buf)]))
+                .unwrap()
+        };
+        assert!(read(&mut server).is_err());
+
+        server.faults.error_rate = 0.0;
+        assert_eq!(read(&mut server).unwrap(), 1);
+    }
+
+    #[test]
+    fn skew() {
+        let clock = wasmtime_wasi::clocks_ctx().system;
+        let now = clock.now(Duration::ZERO);
+
+        let ahead = Skewed::new(wasmtime_wasi::clocks_ctx().system, 3600);
+        let ahead = ahead.now(Duration::ZERO).duration_since(now).unwrap();
+        assert!(ahead >= Duration::from_secs(3600));
+
+        let behind = Skewed::new(wasmtime_wasi::clocks_ctx().system, -3600);
+        let behind = now.duration_since(behind.now(Duration::ZERO)).unwrap();
+        assert!(behind >= Duration::from_secs(3599));
+    }
+}
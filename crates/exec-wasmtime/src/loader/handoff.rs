@@ -0,0 +1,109 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Out-of-band issuance of the certificate of the keep
+//!
+//! Instead of sending its certificate signing request to a Steward, the keep hands it to the host
+//! PEM-encoded, which passes it on to an issuer out of band, e.g. across an air gap, and reads the
+//! certificate chain issued for it back from the host.
+
+use super::super::shutdown;
+use super::pki::{self, PrivateKeyInfoExt};
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::mem::ManuallyDrop;
+use std::os::unix::io::{FromRawFd, RawFd};
+
+use anyhow::{bail, ensure, Context, Result};
+use pkcs8::PrivateKeyInfo;
+use rustix::io::{Errno, PollFd, PollFlags};
+use x509_cert::der::{Decode, Encode};
+use x509_cert::{Certificate, PkiPath};
+
+/// Maximum size of the certificate chain in bytes
+const MAX_CHAIN_SIZE: usize = 1_000_000;
+
+/// Reads from `file` until EOF or a shutdown is requested.
+fn read(file: &mut File) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    let mut chunk = [0; 4096];
+    loop {
+        if shutdown::requested() {
+            bail!("keep was shut down before its certificate chain was provided");
+        }
+        let mut fds = [PollFd::new(&*file, PollFlags::IN)];
+        match rustix::io::poll(&mut fds, 100) {
+            Ok(0) | Err(Errno::INTR) => continue,
+            Ok(..) => {}
+            Err(e) => return Err(e).context("failed to wait for the certificate chain"),
+        }
+        match file.read(&mut chunk)? {
+            0 => return Ok(buf),
+            n => buf.extend_from_slice(&chunk[..n]),
+        }
+        ensure!(
+            buf.len() <= MAX_CHAIN_SIZE,
+            "certificate chain exceeds the limit of `{MAX_CHAIN_SIZE}` bytes"
+        );
+    }
+}
+
+/// Decodes a PEM-encoded certificate chain or a DER-encoded `PkiPath` and returns its
+/// certificates leaf first.
+fn decode(chain: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let chain = match std::str::from_utf8(chain) {
+        Ok(pem) if pem.trim_start().starts_with("-----BEGIN") => pki::unpem("CERTIFICATE", pem)?,
+        _ => PkiPath::from_der(chain)
+            .context("certificate chain is neither PEM nor a DER-encoded `PkiPath`")?
+            .iter()
+            .rev()
+            .map(|c| Ok(c.to_vec()?))
+            .collect::<Result<_>>()?,
+    };
+    ensure!(!chain.is_empty(), "empty certificate chain");
+    Ok(chain)
+}
+
+/// Writes the certificate signing request `crtreq` to the host at `csr` and returns the
+/// certificate chain for the key `pki` read from the host at `crt`, once it was issued.
+pub fn request(
+    csr: RawFd,
+    crt: RawFd,
+    crtreq: &[u8],
+    pki: &PrivateKeyInfo<'_>,
+) -> Result<Vec<Vec<u8>>> {
+    // The FDs are managed by the host, so they are never closed.
+    let mut csr = ManuallyDrop::new(unsafe { File::from_raw_fd(csr) });
+    let mut crt = ManuallyDrop::new(unsafe { File::from_raw_fd(crt) });
+
+    csr.write_all(pki::pem("CERTIFICATE REQUEST", crtreq).as_bytes())
+        .context("failed to hand the certificate signing request to the host")?;
+    let chain = decode(&read(&mut crt)?)?;
+
+    // A chain issued for another key would only fail in the TLS handshakes of the workload.
+    let leaf = Certificate::from_der(&chain[0]).context("failed to decode certificate")?;
+    ensure!(
+        leaf.tbs_certificate.subject_public_key_info.to_vec()? == pki.public_key()?.to_vec()?,
+        "certificate chain was not issued for the key of the keep"
+    );
+    Ok(chain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, pki};
+
+    #[test]
+    fn decoding() {
+        assert!(decode(b"").is_err());
+        assert!(decode(
+            b"-----BEGIN CERTIFICATE REQUEST-----\n-----END CERTIFICATE REQUEST-----\n"
+        )
+        .is_err());
+
+        let pem = pki::pem("CERTIFICATE", b"leaf") + &pki::pem("CERTIFICATE", b"root");
+        assert_eq!(
+            decode(pem.as_bytes()).unwrap(),
+            [b"leaf".to_vec(), b"root".to_vec()]
+        );
+    }
+}
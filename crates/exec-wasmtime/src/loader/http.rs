@@ -0,0 +1,594 @@
+// SPDX-License-Identifier: Apache-2.0
+//! The HTTP client of the workload, implementing the `wasi_experimental_http` interface
+//!
+//! Requests are sent over TLS terminated inside of the keep, only to the hosts allowed by the
+//! `network.outgoing` policy of the config, as updated by the host.
+//!
+//! The interface is extended by functions reading the request served by a listen socket with
+//! `prot = "https"` and building its response, which are available during a call of the
+//! `handle_http_request` export of the workload.
+
+use super::super::reload;
+use super::compiled::https::{Request, Response, Server};
+use super::Ctx;
+
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use enarx_config::Network;
+use log::debug;
+use rustls::ClientConfig;
+use url::Url;
+use wasmtime::{Caller, Extern, Linker};
+
+/// Name of the module of the imported functions
+const MODULE: &str = "wasi_experimental_http";
+
+/// Maximum number of open responses of a store
+const MAX_RESPONSES: usize = 16;
+
+/// Time to wait for the connection to a host
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Errors returned by the functions of the interface
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u32)]
+enum Error {
+    InvalidHandle = 1,
+    MemoryNotFound = 2,
+    MemoryAccess = 3,
+    BufferTooSmall = 4,
+    HeaderNotFound = 5,
+    Utf8 = 6,
+    DestinationNotAllowed = 7,
+    InvalidMethod = 8,
+    InvalidEncoding = 9,
+    InvalidUrl = 10,
+    Request = 11,
+    Runtime = 12,
+    TooManySessions = 13,
+}
+
+/// A response, the body of which is read by the workload
+struct Received {
+    headers: Vec<(String, String)>,
+    body: Box<dyn Read + Send + Sync>,
+}
+
+/// A request served by the workload and the response it builds
+struct Incoming {
+    request: Request,
+    read: usize,
+    response: Response,
+}
+
+/// The HTTP client and server of a store
+pub struct Http {
+    network: Network,
+    agent: ureq::Agent,
+    responses: BTreeMap<u32, Received>,
+    next: u32,
+    server: Option<Server>,
+    incoming: Option<Incoming>,
+}
+
+impl Http {
+    /// Returns a client sending requests allowed by `network` with the TLS client config `cltcfg`.
+    pub fn new(network: Network, cltcfg: Arc<ClientConfig>) -> Self {
+        // Redirects are returned to the workload, such that they are subject to the policy.
+        let agent = ureq::AgentBuilder::new()
+            .tls_config(cltcfg)
+            .timeout_connect(CONNECT_TIMEOUT)
+            .redirects(0)
+            .build();
+        Self {
+            network,
+            agent,
+            responses: BTreeMap::new(),
+            next: 1,
+            server: None,
+            incoming: None,
+        }
+    }
+
+    /// Hands the requests of `server` to the workload.
+    pub fn serve(&mut self, server: Server) {
+        self.server = Some(server);
+    }
+
+    /// Returns the server, the requests of which are handed to the workload, if any.
+    pub fn take_server(&mut self) -> Option<Server> {
+        self.server.take()
+    }
+
+    /// Starts serving `request`.
+    pub fn begin(&mut self, request: Request) {
+        self.incoming = Some(Incoming {
+            request,
+            read: 0,
+            response: Response::default(),
+        });
+    }
+
+    /// Finishes serving the current request and returns its response.
+    pub fn end(&mut self) -> Response {
+        self.incoming
+            .take()
+            .map(|incoming| incoming.response)
+            .unwrap_or_default()
+    }
+
+    fn incoming(&mut self) -> Result<&mut Incoming, Error> {
+        self.incoming.as_mut().ok_or(Error::Runtime)
+    }
+
+    /// Reads the body of the current request into `buf`, returning `0` at its end.
+    fn request_read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let incoming = self.incoming()?;
+        let rest = &incoming.request.body[incoming.read..];
+        let n = rest.len().min(buf.len());
+        buf[..n].copy_from_slice(&rest[..n]);
+        incoming.read += n;
+        Ok(n)
+    }
+
+    /// Sets the status code of the response to the current request.
+    fn set_status(&mut self, status: u32) -> Result<(), Error> {
+        let incoming = self.incoming()?;
+        incoming.response.status = match status {
+            100..=999 => status as _,
+            _ => return Err(Error::InvalidEncoding),
+        };
+        Ok(())
+    }
+
+    /// Adds a header to the response to the current request.
+    fn add_header(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        let incoming = self.incoming()?;
+        let invalid = |c: char| c.is_ascii_control() && c != '\t';
+        if name.is_empty()
+            || name.contains(|c: char| c == ':' || c.is_whitespace() || invalid(c))
+            || value.contains(invalid)
+        {
+            return Err(Error::InvalidEncoding);
+        }
+        incoming.response.headers.push((name.into(), value.into()));
+        Ok(())
+    }
+
+    /// Sends a request and returns the status code and the handle of the response.
+    fn request(
+        &mut self,
+        url: &str,
+        method: &str,
+        headers: &str,
+        body: &[u8],
+    ) -> Result<(u16, u32), Error> {
+        let url = Url::parse(url).map_err(|_| Error::InvalidUrl)?;
+        let host = url.host_str().ok_or(Error::InvalidUrl)?;
+        let port = url.port_or_known_default().ok_or(Error::InvalidUrl)?;
+        if url.scheme() != "https" || !reload::network(&self.network).allows(host, port) {
+            return Err(Error::DestinationNotAllowed);
+        }
+        if method.is_empty() || !method.bytes().all(|b| b.is_ascii_uppercase()) {
+            return Err(Error::InvalidMethod);
+        }
+        if self.responses.len() >= MAX_RESPONSES {
+            return Err(Error::TooManySessions);
+        }
+
+        let mut request = self.agent.request_url(method, &url);
+        for (name, value) in parse_headers(headers) {
+            request = request.set(name, value);
+        }
+        let response = match request.send_bytes(body) {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(e) => {
+                debug!("HTTP request to `{host}` failed: {e}");
+                return Err(Error::Request);
+            }
+        };
+
+        let status = response.status();
+        let headers = response
+            .headers_names()
+            .into_iter()
+            .filter_map(|name| {
+                let value = response.header(&name)?.to_string();
+                Some((name, value))
+            })
+            .collect();
+        let body = response.into_reader();
+
+        let handle = self.next;
+        self.next = self.next.wrapping_add(1).max(1);
+        self.responses.insert(handle, Received { headers, body });
+        Ok((status, handle))
+    }
+
+    fn response(&mut self, handle: u32) -> Result<&mut Received, Error> {
+        self.responses.get_mut(&handle).ok_or(Error::InvalidHandle)
+    }
+
+    /// Returns the value of the header `name` of the response `handle`.
+    fn header(&mut self, handle: u32, name: &str) -> Result<String, Error> {
+        self.response(handle)?
+            .headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.clone())
+            .ok_or(Error::HeaderNotFound)
+    }
+
+    /// Returns all headers of the response `handle`, one `name:value` pair per line.
+    fn headers(&mut self, handle: u32) -> Result<String, Error> {
+        Ok(join_headers(&self.response(handle)?.headers))
+    }
+
+    /// Reads the body of the response `handle` into `buf`, returning `0` at its end.
+    fn read(&mut self, handle: u32, buf: &mut [u8]) -> Result<usize, Error> {
+        self.response(handle)?.body.read(buf).map_err(|e| {
+            debug!("failed to read HTTP response: {e}");
+            Error::Request
+        })
+    }
+
+    fn close(&mut self, handle: u32) -> Result<(), Error> {
+        self.responses
+            .remove(&handle)
+            .map(drop)
+            .ok_or(Error::InvalidHandle)
+    }
+}
+
+/// Returns `headers` as `name:value` pairs, one per line.
+fn join_headers(headers: &[(String, String)]) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| format!("{name}:{value}\n"))
+        .collect()
+}
+
+/// Returns the `name:value` pairs of the lines of `headers`.
+fn parse_headers(headers: &str) -> impl Iterator<Item = (&str, &str)> {
+    headers
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim(), value.trim()))
+}
+
+/// Returns the exported memory of the calling instance and the data of the store.
+fn memory<'a>(caller: &'a mut Caller<'_, Ctx>) -> Result<(&'a mut [u8], &'a mut Ctx), Error> {
+    match caller.get_export("memory") {
+        Some(Extern::Memory(memory)) => Ok(memory.data_and_store_mut(caller)),
+        _ => Err(Error::MemoryNotFound),
+    }
+}
+
+/// Returns the `len` bytes at `ptr` of `memory`.
+fn slice(memory: &mut [u8], ptr: u32, len: u32) -> Result<&mut [u8], Error> {
+    let start = ptr as usize;
+    let end = start.checked_add(len as usize).ok_or(Error::MemoryAccess)?;
+    memory.get_mut(start..end).ok_or(Error::MemoryAccess)
+}
+
+/// Returns the string of `len` bytes at `ptr` of `memory`.
+fn string(memory: &mut [u8], ptr: u32, len: u32) -> Result<String, Error> {
+    let bytes = slice(memory, ptr, len)?.to_vec();
+    String::from_utf8(bytes).map_err(|_| Error::Utf8)
+}
+
+/// Writes `bytes` to `ptr` of `memory`.
+fn write(memory: &mut [u8], ptr: u32, bytes: &[u8]) -> Result<(), Error> {
+    slice(memory, ptr, bytes.len() as _)?.copy_from_slice(bytes);
+    Ok(())
+}
+
+/// Copies `value` to the buffer of `len` bytes at `ptr` and its size to `written_ptr`.
+fn copy(memory: &mut [u8], value: &str, ptr: u32, len: u32, written_ptr: u32) -> Result<(), Error> {
+    if value.len() > len as usize {
+        return Err(Error::BufferTooSmall);
+    }
+    write(memory, ptr, value.as_bytes())?;
+    write(memory, written_ptr, &(value.len() as u32).to_le_bytes())
+}
+
+/// Returns the code of `result`, which is `0` on success.
+fn code(result: Result<(), Error>) -> u32 {
+    match result {
+        Ok(()) => 0,
+        Err(e) => e as u32,
+    }
+}
+
+/// Adds the functions of the interface to `linker`.
+pub fn add_to_linker(linker: &mut Linker<Ctx>) -> Result<()> {
+    linker.func_wrap(
+        MODULE,
+        "req",
+        |mut caller: Caller<'_, Ctx>,
+         url_ptr: u32,
+         url_len: u32,
+         method_ptr: u32,
+         method_len: u32,
+         headers_ptr: u32,
+         headers_len: u32,
+         body_ptr: u32,
+         body_len: u32,
+         status_ptr: u32,
+         handle_ptr: u32|
+         -> u32 {
+            code(memory(&mut caller).and_then(|(memory, ctx)| {
+                let url = string(memory, url_ptr, url_len)?;
+                let method = string(memory, method_ptr, method_len)?;
+                let headers = string(memory, headers_ptr, headers_len)?;
+                let body = slice(memory, body_ptr, body_len)?.to_vec();
+                let (status, handle) = ctx.http.request(&url, &method, &headers, &body)?;
+                write(memory, status_ptr, &status.to_le_bytes())?;
+                write(memory, handle_ptr, &handle.to_le_bytes())
+            }))
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "header_get",
+        |mut caller: Caller<'_, Ctx>,
+         handle: u32,
+         name_ptr: u32,
+         name_len: u32,
+         value_ptr: u32,
+         value_len: u32,
+         written_ptr: u32|
+         -> u32 {
+            code(memory(&mut caller).and_then(|(memory, ctx)| {
+                let name = string(memory, name_ptr, name_len)?;
+                let value = ctx.http.header(handle, &name)?;
+                copy(memory, &value, value_ptr, value_len, written_ptr)
+            }))
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "headers_get_all",
+        |mut caller: Caller<'_, Ctx>,
+         handle: u32,
+         buf_ptr: u32,
+         buf_len: u32,
+         written_ptr: u32|
+         -> u32 {
+            code(memory(&mut caller).and_then(|(memory, ctx)| {
+                let headers = ctx.http.headers(handle)?;
+                copy(memory, &headers, buf_ptr, buf_len, written_ptr)
+            }))
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "body_read",
+        |mut caller: Caller<'_, Ctx>,
+         handle: u32,
+         buf_ptr: u32,
+         buf_len: u32,
+         written_ptr: u32|
+         -> u32 {
+            code(memory(&mut caller).and_then(|(memory, ctx)| {
+                let n = ctx.http.read(handle, slice(memory, buf_ptr, buf_len)?)?;
+                write(memory, written_ptr, &(n as u32).to_le_bytes())
+            }))
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "close",
+        |mut caller: Caller<'_, Ctx>, handle: u32| -> u32 {
+            code(caller.data_mut().http.close(handle))
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "request_method",
+        |mut caller: Caller<'_, Ctx>, buf_ptr: u32, buf_len: u32, written_ptr: u32| -> u32 {
+            code(memory(&mut caller).and_then(|(memory, ctx)| {
+                let method = ctx.http.incoming()?.request.method.clone();
+                copy(memory, &method, buf_ptr, buf_len, written_ptr)
+            }))
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "request_uri",
+        |mut caller: Caller<'_, Ctx>, buf_ptr: u32, buf_len: u32, written_ptr: u32| -> u32 {
+            code(memory(&mut caller).and_then(|(memory, ctx)| {
+                let uri = ctx.http.incoming()?.request.uri.clone();
+                copy(memory, &uri, buf_ptr, buf_len, written_ptr)
+            }))
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "request_header_get",
+        |mut caller: Caller<'_, Ctx>,
+         name_ptr: u32,
+         name_len: u32,
+         value_ptr: u32,
+         value_len: u32,
+         written_ptr: u32|
+         -> u32 {
+            code(memory(&mut caller).and_then(|(memory, ctx)| {
+                let name = string(memory, name_ptr, name_len)?;
+                let request = &ctx.http.incoming()?.request;
+                let value = request.header(&name).ok_or(Error::HeaderNotFound)?;
+                copy(memory, value, value_ptr, value_len, written_ptr)
+            }))
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "request_headers_get_all",
+        |mut caller: Caller<'_, Ctx>, buf_ptr: u32, buf_len: u32, written_ptr: u32| -> u32 {
+            code(memory(&mut caller).and_then(|(memory, ctx)| {
+                let headers = join_headers(&ctx.http.incoming()?.request.headers);
+                copy(memory, &headers, buf_ptr, buf_len, written_ptr)
+            }))
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "request_body_read",
+        |mut caller: Caller<'_, Ctx>, buf_ptr: u32, buf_len: u32, written_ptr: u32| -> u32 {
+            code(memory(&mut caller).and_then(|(memory, ctx)| {
+                let n = ctx.http.request_read(slice(memory, buf_ptr, buf_len)?)?;
+                write(memory, written_ptr, &(n as u32).to_le_bytes())
+            }))
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "response_status_set",
+        |mut caller: Caller<'_, Ctx>, status: u32| -> u32 {
+            code(caller.data_mut().http.set_status(status))
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "response_header_add",
+        |mut caller: Caller<'_, Ctx>,
+         name_ptr: u32,
+         name_len: u32,
+         value_ptr: u32,
+         value_len: u32|
+         -> u32 {
+            code(memory(&mut caller).and_then(|(memory, ctx)| {
+                let name = string(memory, name_ptr, name_len)?;
+                let value = string(memory, value_ptr, value_len)?;
+                ctx.http.add_header(&name, &value)
+            }))
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "response_body_write",
+        |mut caller: Caller<'_, Ctx>, buf_ptr: u32, buf_len: u32| -> u32 {
+            code(memory(&mut caller).and_then(|(memory, ctx)| {
+                let body = slice(memory, buf_ptr, buf_len)?;
+                ctx.http.incoming()?.response.body.extend_from_slice(body);
+                Ok(())
+            }))
+        },
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::compiled::https::Request;
+    use super::{parse_headers, Error, Http};
+
+    use std::sync::Arc;
+
+    use enarx_config::Network;
+    use rustls::{ClientConfig, RootCertStore};
+
+    fn http(outgoing: &[&str]) -> Http {
+        let cltcfg = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(RootCertStore::empty())
+            .with_no_client_auth();
+        let network = Network {
+            outgoing: outgoing.iter().map(|host| host.to_string()).collect(),
+            ..Default::default()
+        };
+        Http::new(network, Arc::new(cltcfg))
+    }
+
+    #[test]
+    fn policy() {
+        let mut http = http(&["api.example.com"]);
+        let mut request = |url, method| http.request(url, method, "", b"");
+
+        assert_eq!(
+            request("https://example.com/", "GET"),
+            Err(Error::DestinationNotAllowed)
+        );
+        assert_eq!(
+            request("https://api.example.com:8443/", "GET"),
+            Err(Error::DestinationNotAllowed)
+        );
+        assert_eq!(
+            request("http://api.example.com/", "GET"),
+            Err(Error::DestinationNotAllowed)
+        );
+        assert_eq!(request("api.example.com", "GET"), Err(Error::InvalidUrl));
+        assert_eq!(
+            request("https://api.example.com/", "get"),
+            Err(Error::InvalidMethod)
+        );
+
+        assert_eq!(http.header(1, "content-type"), Err(Error::InvalidHandle));
+        assert_eq!(http.close(1), Err(Error::InvalidHandle));
+    }
+
+    #[test]
+    fn incoming() {
+        let mut http = http(&[]);
+        assert_eq!(http.request_read(&mut [0; 4]), Err(Error::Runtime));
+        assert_eq!(http.set_status(404), Err(Error::Runtime));
+
+        http.begin(Request {
+            method: "POST".into(),
+            uri: "/".into(),
+            headers: vec![],
+            body: b"hello".to_vec(),
+        });
+        let mut buf = [0; 4];
+        assert_eq!(http.request_read(&mut buf), Ok(4));
+        assert_eq!(&buf, b"hell");
+        assert_eq!(http.request_read(&mut buf), Ok(1));
+        assert_eq!(http.request_read(&mut buf), Ok(0));
+
+        assert_eq!(http.set_status(42), Err(Error::InvalidEncoding));
+        assert_eq!(http.set_status(201), Ok(()));
+        assert_eq!(
+            http.add_header("x-injected", "a\r\nset-cookie: b"),
+            Err(Error::InvalidEncoding)
+        );
+        assert_eq!(
+            http.add_header("bad name", "value"),
+            Err(Error::InvalidEncoding)
+        );
+        assert_eq!(http.add_header("content-type", "text/plain"), Ok(()));
+
+        let response = http.end();
+        assert_eq!(response.status, 201);
+        assert_eq!(
+            response.headers,
+            vec![("content-type".into(), "text/plain".into())]
+        );
+        assert_eq!(http.end().status, 200);
+    }
+
+    #[test]
+    fn headers() {
+        let headers = "content-type: application/json\nx-token:abc:def\n\ninvalid\n";
+        assert_eq!(
+            parse_headers(headers).collect::<Vec<_>>(),
+            vec![("content-type", "application/json"), ("x-token", "abc:def")]
+        );
+    }
+}
@@ -13,17 +13,42 @@ mod attested;
 mod compiled;
 mod configured;
 mod connected;
-mod pki;
+mod faults;
+#[cfg(unix)]
+mod handoff;
+mod http;
+pub(crate) mod pki;
+mod random;
+mod renewal;
 mod requested;
+#[cfg(unix)]
+mod sched;
+#[cfg(unix)]
+mod sealed;
+mod secrets;
+mod threads;
+mod time;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+mod timepage;
 
-use super::{Args, Package};
+use super::metrics::METRICS;
+use super::{Args, Faults, Package};
+use configured::platform::Technology;
+use renewal::Certs;
+use secrets::Secrets;
 
+pub(crate) use configured::platform::Platform;
+
+use std::collections::BTreeMap;
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
 use std::sync::Arc;
+use std::time::Duration;
 
 use enarx_config::Config;
 use rustls::{ClientConfig, ServerConfig};
 use wasi_common::WasiCtx;
-use wasmtime::{Linker, Store, Val};
+use wasmtime::{Linker, Module, ResourceLimiter, Store, StoreLimits, Val};
 use zeroize::Zeroizing;
 
 /// The first state, indicating successful configuration
@@ -31,34 +56,87 @@ pub struct Configured {
     args: Args,
 }
 
-/// The second state, indicating that a CSR has been generated
+/// The second state, indicating that the key has been attested
 pub struct Requested {
     package: Package,
+    env: BTreeMap<String, String>,
     prvkey: Zeroizing<Vec<u8>>,
-    crtreq: Vec<u8>,
+    report: Vec<u8>,
+    technology: Technology,
+    faults: Option<Faults>,
+    max_wasm_size: u64,
+    digest: Option<String>,
+    debug: bool,
+    mounts: bool,
+    #[cfg(unix)]
+    hold: Option<RawFd>,
+    #[cfg(unix)]
+    handoff: Option<(RawFd, RawFd)>,
+    #[cfg(unix)]
+    cached: Option<RawFd>,
+    #[cfg(unix)]
+    cache: Option<(RawFd, sealed::Sealer)>,
+    #[cfg(unix)]
+    listeners: BTreeMap<String, RawFd>,
+    #[cfg(unix)]
+    logs: BTreeMap<String, RawFd>,
 }
 
 /// The third state, indicating receipt of the configuration, certificate, WASM module and configuration
 pub struct Attested {
     srvcfg: Arc<ServerConfig>,
     cltcfg: Arc<ClientConfig>,
+    certs: Arc<Certs>,
+    secrets: Secrets,
     config: Config,
     webasm: Vec<u8>,
+    data: Arc<BTreeMap<String, Vec<u8>>>,
+    modules: BTreeMap<String, Vec<u8>>,
+    identity: String,
+    prvkey: Zeroizing<Vec<u8>>,
+    binding: Vec<u8>,
+    technology: Technology,
+    faults: Option<Faults>,
+    debug: bool,
+    #[cfg(unix)]
+    cache: Option<sealed::Cache>,
+    #[cfg(unix)]
+    listeners: BTreeMap<String, RawFd>,
+    #[cfg(unix)]
+    logs: BTreeMap<String, RawFd>,
 }
 
 /// The fifth state, indicating compilation of the WASM module
 pub struct Compiled {
     srvcfg: Arc<ServerConfig>,
     cltcfg: Arc<ClientConfig>,
+    certs: Arc<Certs>,
+    secrets: Secrets,
     config: Config,
-    wstore: Store<WasiCtx>,
-    linker: Linker<WasiCtx>,
+    data: Arc<BTreeMap<String, Vec<u8>>>,
+    identity: String,
+    prvkey: Zeroizing<Vec<u8>>,
+    binding: Vec<u8>,
+    wstore: Store<Ctx>,
+    linker: Linker<Ctx>,
+    module: Module,
+    services: BTreeMap<String, Instance>,
+    faults: Option<Faults>,
+    #[cfg(unix)]
+    listeners: BTreeMap<String, RawFd>,
+    #[cfg(unix)]
+    logs: BTreeMap<String, RawFd>,
 }
 
 /// The sixth state, indicating connection of all sockets
 pub struct Connected {
-    wstore: Store<WasiCtx>,
-    linker: Linker<WasiCtx>,
+    wstore: Store<Ctx>,
+    linker: Linker<Ctx>,
+    module: Module,
+    handler: Option<String>,
+    services: BTreeMap<String, Instance>,
+    timeout: Option<Duration>,
+    cpu_timeout: Option<Duration>,
 }
 
 /// The final state, indicating completion of the workload
@@ -66,11 +144,69 @@ pub struct Completed {
     values: Vec<Val>,
 }
 
+/// A compiled service running next to the main module
+pub struct Instance {
+    wstore: Store<Ctx>,
+    linker: Linker<Ctx>,
+    module: Module,
+    handler: Option<String>,
+}
+
+/// The data associated with the workload store
+pub struct Ctx {
+    wasi: WasiCtx,
+    limits: StoreLimits,
+    http: http::Http,
+}
+
+/// Enforces the store limits and records the size of the linear memories of the workload.
+impl ResourceLimiter for Ctx {
+    fn memory_growing(&mut self, current: usize, desired: usize, maximum: Option<usize>) -> bool {
+        let allowed = self.limits.memory_growing(current, desired, maximum);
+        if allowed {
+            METRICS.lock().unwrap().memory += (desired - current) as u64;
+        }
+        allowed
+    }
+
+    fn memory_grow_failed(&mut self, error: &anyhow::Error) {
+        self.limits.memory_grow_failed(error)
+    }
+
+    fn table_growing(&mut self, current: u32, desired: u32, maximum: Option<u32>) -> bool {
+        self.limits.table_growing(current, desired, maximum)
+    }
+
+    fn table_grow_failed(&mut self, error: &anyhow::Error) {
+        self.limits.table_grow_failed(error)
+    }
+
+    fn instances(&self) -> usize {
+        self.limits.instances()
+    }
+
+    fn tables(&self) -> usize {
+        self.limits.tables()
+    }
+
+    fn memories(&self) -> usize {
+        self.limits.memories()
+    }
+}
+
 pub struct Loader<T>(T);
 
 impl Loader<Attested> {
     #[cfg(test)]
     pub fn run(module: &[u8]) -> anyhow::Result<Vec<Val>> {
+        Self::run_with_config(module, Default::default())
+    }
+
+    #[cfg(test)]
+    pub fn run_with_config(module: &[u8], config: Config) -> anyhow::Result<Vec<Val>> {
+        use const_oid::db::rfc5912::SECP_256_R_1;
+        use pkcs8::PrivateKeyInfo;
+        use pki::PrivateKeyInfoExt;
         use rustls::{server::ResolvesServerCert, RootCertStore};
 
         struct Resolver;
@@ -94,11 +230,28 @@ impl Loader<Attested> {
             .with_root_certificates(RootCertStore::empty())
             .with_no_client_auth();
 
+        let prvkey = PrivateKeyInfo::generate(SECP_256_R_1)?;
         let attested = Self(Attested {
             srvcfg: Arc::new(srvcfg),
             cltcfg: Arc::new(cltcfg),
-            config: Default::default(),
+            certs: Arc::new(Certs::new(&prvkey, vec![])?),
+            secrets: Secrets::new(),
+            config,
             webasm: module.to_vec(),
+            data: Default::default(),
+            modules: BTreeMap::new(),
+            identity: "test".into(),
+            binding: compiled::binding(module, None),
+            prvkey,
+            technology: Technology::Kvm,
+            faults: None,
+            debug: false,
+            #[cfg(unix)]
+            cache: None,
+            #[cfg(unix)]
+            listeners: BTreeMap::new(),
+            #[cfg(unix)]
+            logs: BTreeMap::new(),
         });
 
         let compiled = attested.next()?;
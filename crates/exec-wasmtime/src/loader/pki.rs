@@ -3,6 +3,7 @@
 
 use anyhow::{anyhow, Result};
 use pkcs8::{AlgorithmIdentifier, ObjectIdentifier, PrivateKeyInfo, SubjectPublicKeyInfo};
+use sha2::{Digest, Sha256};
 use zeroize::Zeroizing;
 
 use sec1::EcPrivateKey;
@@ -23,6 +24,72 @@ const ES384: AlgorithmIdentifier<'static> = AlgorithmIdentifier {
     parameters: None,
 };
 
+/// Returns the name of the signature algorithm `oid`, e.g. `ecdsa-with-SHA256`.
+pub fn algorithm_name(oid: ObjectIdentifier) -> String {
+    match oid {
+        ECDSA_WITH_SHA_256 => "ecdsa-with-SHA256".into(),
+        ECDSA_WITH_SHA_384 => "ecdsa-with-SHA384".into(),
+        oid => oid.to_string(),
+    }
+}
+
+/// Returns the hex encoding of `bytes`.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Returns the digest of `bytes`, `sha256:` followed by the hex-encoded SHA-256 digest.
+pub fn digest(bytes: &[u8]) -> String {
+    format!("sha256:{}", hex(&Sha256::digest(bytes)))
+}
+
+/// Signs `msg` with the DER-encoded `PrivateKeyInfo` `prvkey`.
+///
+/// Returns the [`algorithm_name`] of the signature algorithm and the signature.
+pub fn sign(prvkey: &[u8], msg: &[u8]) -> Result<(String, Vec<u8>)> {
+    let pki = PrivateKeyInfo::from_der(prvkey)?;
+    let algo = pki.signs_with()?;
+    let signature = pki.sign(msg, algo)?;
+    Ok((algorithm_name(algo.oid), signature))
+}
+
+/// Returns `der` PEM-encoded with `label`, e.g. `CERTIFICATE`.
+pub fn pem(label: &str, der: &[u8]) -> String {
+    let mut pem = format!("-----BEGIN {label}-----\n");
+    // `base64` encodes ASCII only, so the chunks are valid UTF-8.
+    for line in base64::encode(der).as_bytes().chunks(64) {
+        pem.push_str(std::str::from_utf8(line).unwrap());
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {label}-----\n"));
+    pem
+}
+
+/// Returns the DER encodings of the PEM blocks labeled with `label` in `pem` in order.
+pub fn unpem(label: &str, pem: &str) -> Result<Vec<Vec<u8>>> {
+    let (begin, end) = (
+        format!("-----BEGIN {label}-----"),
+        format!("-----END {label}-----"),
+    );
+    let mut blocks = vec![];
+    let mut block: Option<String> = None;
+    for line in pem.lines().map(str::trim) {
+        match block {
+            None if line == begin => block = Some(String::new()),
+            None => {}
+            Some(ref b64) if line == end => {
+                blocks.push(base64::decode(b64).map_err(|e| anyhow!("invalid PEM: {e}"))?);
+                block = None;
+            }
+            Some(ref mut b64) => b64.push_str(line),
+        }
+    }
+    if block.is_some() {
+        return Err(anyhow!("invalid PEM: missing `{end}`"));
+    }
+    Ok(blocks)
+}
+
 pub trait PrivateKeyInfoExt {
     /// Generates a keypair
     ///
@@ -109,3 +176,21 @@ impl<'a> PrivateKeyInfoExt for PrivateKeyInfo<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{pem, unpem};
+
+    #[test]
+    fn encoding() {
+        let der: Vec<u8> = (0..=255).collect();
+        let encoded = pem("CERTIFICATE", &der) + &pem("CERTIFICATE", b"leaf");
+        assert!(encoded.lines().all(|line| line.len() <= 64));
+        assert_eq!(
+            unpem("CERTIFICATE", &encoded).unwrap(),
+            [der, b"leaf".to_vec()]
+        );
+        assert!(unpem("CERTIFICATE REQUEST", &encoded).unwrap().is_empty());
+        assert!(unpem("CERTIFICATE", "-----BEGIN CERTIFICATE-----\nAAAA\n").is_err());
+    }
+}
@@ -0,0 +1,204 @@
+// SPDX-License-Identifier: Apache-2.0
+//! The random number generator of the workload
+//!
+//! The generator is a ChaCha stream cipher seeded with the SHA-256 digest of the output of the
+//! RDSEED and RDRAND instructions, which are executed inside of the keep, and unless forbidden by
+//! `entropy.host`, of entropy obtained from the host. Inside of keeps, the shims serve requests for
+//! entropy with RDRAND as well, so only the `nil` backend obtains it from the host.
+
+use std::any::Any;
+use std::io::IoSliceMut;
+
+use anyhow::{bail, Result};
+use cap_rand::rngs::StdRng;
+use cap_rand::{CryptoRng, RngCore, SeedableRng};
+use log::warn;
+use sha2::{Digest, Sha256};
+use wasi_common::file::{FdFlags, FileType};
+use wasi_common::{Error, WasiFile};
+
+/// Number of bytes generated before the generator is reseeded
+const RESEED_INTERVAL: usize = 1 << 20;
+
+/// Number of retries of the RDSEED and RDRAND instructions, which fail if the CPU runs out of
+/// entropy
+const RETRIES: usize = 1024;
+
+/// Returns 32 bytes of the output of RDSEED, `None` if it is unavailable or exhausted.
+#[cfg(target_arch = "x86_64")]
+fn rdseed() -> Option<[u8; 32]> {
+    #[target_feature(enable = "rdseed")]
+    unsafe fn step(out: &mut u64) -> i32 {
+        core::arch::x86_64::_rdseed64_step(out)
+    }
+
+    if !is_x86_feature_detected!("rdseed") {
+        return None;
+    }
+    // SAFETY: The CPU supports RDSEED.
+    cpu(|out| unsafe { step(out) })
+}
+
+/// Returns 32 bytes of the output of RDRAND, `None` if it is unavailable or exhausted.
+#[cfg(target_arch = "x86_64")]
+fn rdrand() -> Option<[u8; 32]> {
+    #[target_feature(enable = "rdrand")]
+    unsafe fn step(out: &mut u64) -> i32 {
+        core::arch::x86_64::_rdrand64_step(out)
+    }
+
+    if !is_x86_feature_detected!("rdrand") {
+        return None;
+    }
+    // SAFETY: The CPU supports RDRAND.
+    cpu(|out| unsafe { step(out) })
+}
+
+/// Returns 32 bytes of the output of the instruction executed by `step`, retrying failed steps.
+#[cfg(target_arch = "x86_64")]
+fn cpu(step: impl Fn(&mut u64) -> i32) -> Option<[u8; 32]> {
+    let mut bytes = [0; 32];
+    for chunk in bytes.chunks_mut(8) {
+        let mut out = 0;
+        (0..RETRIES).find(|_| step(&mut out) == 1)?;
+        chunk.copy_from_slice(&out.to_ne_bytes());
+    }
+    Some(bytes)
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn rdseed() -> Option<[u8; 32]> {
+    None
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn rdrand() -> Option<[u8; 32]> {
+    None
+}
+
+/// Returns a seed mixing the entropy of the CPU and, if `host` is set, of the host.
+fn seed(host: bool) -> Result<[u8; 32]> {
+    let mut digest = Sha256::new();
+    let mut sources = 0;
+    for bytes in [rdseed(), rdrand()].into_iter().flatten() {
+        digest.update(bytes);
+        sources += 1;
+    }
+    if host {
+        let mut bytes = [0; 32];
+        getrandom::getrandom(&mut bytes)?;
+        digest.update(bytes);
+        sources += 1;
+    }
+    if sources == 0 {
+        bail!("no entropy available, the CPU supports neither RDSEED nor RDRAND and `entropy.host` is `false`");
+    }
+    Ok(digest.finalize().into())
+}
+
+/// The random number generator of the workload
+pub struct Random {
+    rng: StdRng,
+    host: bool,
+    generated: usize,
+}
+
+impl Random {
+    /// Returns a generator seeded with the entropy of the CPU and, if `host` is set, of the host.
+    pub fn new(host: bool) -> Result<Self> {
+        Ok(Self {
+            rng: StdRng::from_seed(seed(host)?),
+            host,
+            generated: 0,
+        })
+    }
+
+    /// Accounts for `n` generated bytes, reseeding the generator after every [`RESEED_INTERVAL`].
+    fn account(&mut self, n: usize) {
+        self.generated += n;
+        if self.generated >= RESEED_INTERVAL {
+            self.generated = 0;
+            match seed(self.host) {
+                Ok(seed) => self.rng = StdRng::from_seed(seed),
+                Err(e) => warn!("failed to reseed the random number generator: {e:#}"),
+            }
+        }
+    }
+}
+
+impl RngCore for Random {
+    fn next_u32(&mut self) -> u32 {
+        self.account(4);
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.account(8);
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.account(dest.len());
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), cap_rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl CryptoRng for Random {}
+
+#[wiggle::async_trait]
+impl WasiFile for Random {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::CharacterDevice)
+    }
+
+    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
+        Ok(FdFlags::empty())
+    }
+
+    async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        let mut n = 0;
+        for buf in bufs.iter_mut() {
+            self.fill_bytes(buf);
+            n += buf.len();
+        }
+        Ok(n as _)
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Random, RESEED_INTERVAL};
+
+    use cap_rand::RngCore;
+
+    #[test]
+    fn random() {
+        let (mut a, mut b) = ([0; 32], [0; 32]);
+        Random::new(true).unwrap().fill_bytes(&mut a);
+        Random::new(true).unwrap().fill_bytes(&mut b);
+        assert_ne!(a, b);
+
+        // Without RDSEED and RDRAND, there is no entropy without the host.
+        let cpu = super::rdseed().is_some() || super::rdrand().is_some();
+        assert_eq!(Random::new(false).is_ok(), cpu);
+
+        let mut random = Random::new(true).unwrap();
+        random.fill_bytes(&mut vec![0; RESEED_INTERVAL - 1]);
+        assert_eq!(random.generated, RESEED_INTERVAL - 1);
+        random.next_u32();
+        assert_eq!(random.generated, 0);
+    }
+}
@@ -0,0 +1,343 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Renewal of the certificate chain issued by the Steward
+//!
+//! The certificate is renewed by a thread of its own, unless the keep cannot create threads, i.e.
+//! on the shims of hardware backends. There, the workload renews it itself, which blocks it during
+//! the renewal: the scheduler of the workload, while it waits for files with `poll_oneoff`, and the
+//! epoch checks of the workload, while it runs, see [`poll`].
+
+use super::configured::hints::Hint;
+use super::configured::platform::Platform;
+#[cfg(unix)]
+use super::connected;
+use super::pki;
+use super::requested::Stewards;
+use super::{Configured, Loader};
+
+#[cfg(unix)]
+use std::sync::Mutex;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use pkcs8::PrivateKeyInfo;
+use rustls::client::ResolvesClientCert;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::{any_supported_type, CertifiedKey, SigningKey};
+use rustls::{Certificate, PrivateKey, SignatureScheme};
+use x509_cert::der::Decode;
+use zeroize::Zeroizing;
+
+/// Interval at which a failed renewal is retried
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Maximum interval at which the renewal thread checks the time
+const TICK: Duration = Duration::from_secs(60);
+
+/// The renewal driven by the scheduler of the workload, if the keep cannot create threads
+#[cfg(unix)]
+static POLLED: Mutex<Option<Renewal>> = Mutex::new(None);
+
+/// The certificate chain of the keep, which may be replaced at runtime
+///
+/// Used as the certificate resolver of both the server and the client TLS configs,
+/// such that a renewed chain is presented in all subsequent handshakes.
+pub struct Certs {
+    key: Arc<dyn SigningKey>,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl Certs {
+    pub fn new(prvkey: &[u8], chain: Vec<Certificate>) -> Result<Self> {
+        let key = any_supported_type(&PrivateKey(prvkey.to_vec()))
+            .context("failed to load private key")?;
+        let current = RwLock::new(Arc::new(CertifiedKey::new(chain, key.clone())));
+        Ok(Self { key, current })
+    }
+
+    fn current(&self) -> Arc<CertifiedKey> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Returns the current chain PEM-encoded, leaf first.
+    pub fn pem(&self) -> String {
+        self.current()
+            .cert
+            .iter()
+            .map(|crt| pki::pem("CERTIFICATE", &crt.0))
+            .collect()
+    }
+
+    /// Presents `chain` in all subsequent handshakes.
+    fn replace(&self, chain: Vec<Certificate>) {
+        *self.current.write().unwrap() = Arc::new(CertifiedKey::new(chain, self.key.clone()));
+    }
+}
+
+impl ResolvesServerCert for Certs {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current())
+    }
+}
+
+impl ResolvesClientCert for Certs {
+    fn resolve(
+        &self,
+        _acceptable_issuers: &[&[u8]],
+        _sigschemes: &[SignatureScheme],
+    ) -> Option<Arc<CertifiedKey>> {
+        Some(self.current())
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+/// Returns the time at which the leaf certificate of `chain` should be renewed.
+///
+/// That is `margin` before its expiry, but no earlier than halfway through its validity period.
+fn renewal_time(chain: &[Certificate], margin: Duration) -> Result<SystemTime> {
+    let leaf = chain.first().context("empty certificate chain")?;
+    let leaf = x509_cert::Certificate::from_der(&leaf.0).context("failed to decode certificate")?;
+    let validity = leaf.tbs_certificate.validity;
+    let not_before = validity.not_before.to_system_time();
+    let not_after = validity.not_after.to_system_time();
+
+    let halfway = not_before + not_after.duration_since(not_before).unwrap_or_default() / 2;
+    let renewal = not_after.checked_sub(margin).unwrap_or(not_before);
+    if renewal < halfway {
+        warn!(
+            "renewal margin of {}s exceeds half of the validity period of the certificate, renewing it halfway through instead",
+            margin.as_secs()
+        );
+    }
+    Ok(renewal.max(halfway))
+}
+
+/// Sleeps until `time`.
+fn sleep_until(time: SystemTime) {
+    while let Ok(remaining) = time.duration_since(SystemTime::now()) {
+        thread::sleep(remaining.min(TICK));
+    }
+}
+
+/// Re-attests the keep and obtains a new certificate chain for `prvkey` from the first available
+/// of the `stewards`, requesting the policy `hints` again.
+fn renew(stewards: &Stewards, prvkey: &[u8], hints: &[Hint]) -> Result<Vec<Certificate>> {
+    let platform = Platform::get().context("failed to query platform")?;
+    let pki = PrivateKeyInfo::from_der(prvkey).context("failed to decode private key")?;
+    let req = Loader::<Configured>::request(&platform, &pki, hints)
+        .context("failed to make certificate signing request")?;
+    let (url, chain) = stewards
+        .request(&req)
+        .context("failed to request certificate from steward")?;
+    info!("renewed certificate from steward `{url}`");
+    Ok(chain.into_iter().map(Certificate).collect())
+}
+
+/// Renews the certificate chain of `certs` from the `stewards` `margin` before its expiry for the
+/// rest of the lifetime of the keep
+///
+/// The requests request the policy `hints`.
+pub struct Renewal {
+    certs: Arc<Certs>,
+    stewards: Stewards,
+    prvkey: Zeroizing<Vec<u8>>,
+    hints: Vec<Hint>,
+    margin: Duration,
+    /// Time of the next attempt, `None` once the renewal stopped
+    next: Option<SystemTime>,
+}
+
+impl Renewal {
+    pub fn new(
+        certs: Arc<Certs>,
+        stewards: Stewards,
+        prvkey: Zeroizing<Vec<u8>>,
+        hints: Vec<Hint>,
+        margin: Duration,
+    ) -> Self {
+        let mut renewal = Self {
+            certs,
+            stewards,
+            prvkey,
+            hints,
+            margin,
+            next: None,
+        };
+        renewal.schedule();
+        renewal
+    }
+
+    /// Requests a certificate from the `stewards` again after a while, e.g. if the current one is
+    /// self-signed, since none of them was available.
+    pub fn retry(mut self) -> Self {
+        self.next = Some(SystemTime::now() + RETRY_INTERVAL);
+        self
+    }
+
+    /// Schedules the renewal of the current certificate chain.
+    fn schedule(&mut self) {
+        self.next = match renewal_time(&self.certs.current().cert, self.margin) {
+            Ok(renewal) => Some(renewal),
+            Err(e) => {
+                warn!("failed to determine certificate renewal time, not renewing: {e:#}");
+                None
+            }
+        };
+    }
+
+    /// Renews the certificate chain, if due.
+    fn poll(&mut self) {
+        match self.next {
+            Some(next) if next <= SystemTime::now() => {}
+            _ => return,
+        }
+        match renew(&self.stewards, &self.prvkey, &self.hints) {
+            Ok(chain) => {
+                self.certs.replace(chain);
+                self.schedule();
+            }
+            Err(e) => {
+                warn!("failed to renew certificate, retrying: {e:#}");
+                self.next = Some(SystemTime::now() + RETRY_INTERVAL);
+            }
+        }
+    }
+
+    /// Renews the certificate chain in a thread or, if the keep cannot create threads, while the
+    /// workload runs or waits for files, see [`poll`].
+    pub fn start(mut self) {
+        #[cfg(unix)]
+        if !connected::threads() {
+            warn!("the keep cannot create threads, so the certificate is only renewed, while the workload runs or waits for files with `poll_oneoff`, but not while it blocks in other calls");
+            *POLLED.lock().unwrap() = Some(self);
+            return;
+        }
+        let thread = thread::Builder::new()
+            .name("renewal".into())
+            .spawn(move || {
+                while let Some(next) = self.next {
+                    sleep_until(next);
+                    self.poll();
+                }
+            });
+        if let Err(e) = thread {
+            warn!("failed to spawn renewal thread, the certificate will not be renewed: {e}");
+        }
+    }
+}
+
+/// Returns the time until the certificate chain is due to be renewed by [`poll`], if the
+/// scheduler of the workload renews it.
+#[cfg(unix)]
+pub fn due_in() -> Option<Duration> {
+    let renewal = POLLED.lock().unwrap();
+    let next = renewal.as_ref()?.next?;
+    Some(next.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+/// Returns whether the workload renews the certificate chain itself, i.e. the scheduler and the
+/// epoch checks of the workload have to [`poll`] it.
+#[cfg(unix)]
+pub fn polled() -> bool {
+    POLLED.lock().unwrap().is_some()
+}
+
+/// Renews the certificate chain, if the workload renews it itself and it is due.
+#[cfg(unix)]
+pub fn poll() {
+    if let Some(renewal) = POLLED.lock().unwrap().as_mut() {
+        renewal.poll();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::pki::PrivateKeyInfoExt;
+    use super::{renewal_time, Certs};
+
+    use std::time::{Duration, SystemTime};
+
+    use rustls::Certificate;
+    use x509_cert::der::asn1::{BitStringRef, UIntRef};
+    use x509_cert::der::{Decode, Encode};
+    use x509_cert::name::RdnSequence;
+    use x509_cert::time::{Time, Validity};
+    use x509_cert::TbsCertificate;
+
+    use const_oid::db::rfc5912::SECP_256_R_1;
+    use pkcs8::PrivateKeyInfo;
+
+    /// Returns a private key and a self-signed certificate for it valid from `not_before` until `not_after`.
+    fn certificate(not_before: SystemTime, not_after: SystemTime) -> (Vec<u8>, Certificate) {
+        let raw = PrivateKeyInfo::generate(SECP_256_R_1).unwrap();
+        let pki = PrivateKeyInfo::from_der(&raw).unwrap();
+        let rdns = RdnSequence::encode_from_string("CN=localhost").unwrap();
+        let tbs = TbsCertificate {
+            version: x509_cert::Version::V3,
+            serial_number: UIntRef::new(&[1]).unwrap(),
+            signature: pki.signs_with().unwrap(),
+            issuer: RdnSequence::from_der(&rdns).unwrap(),
+            validity: Validity {
+                not_before: Time::try_from(not_before).unwrap(),
+                not_after: Time::try_from(not_after).unwrap(),
+            },
+            subject: RdnSequence::from_der(&rdns).unwrap(),
+            subject_public_key_info: pki.public_key().unwrap(),
+            issuer_unique_id: None,
+            subject_unique_id: None,
+            extensions: None,
+        };
+        let sig = pki.sign(&tbs.to_vec().unwrap(), tbs.signature).unwrap();
+        let crt = x509_cert::Certificate {
+            signature_algorithm: tbs.signature,
+            tbs_certificate: tbs,
+            signature: BitStringRef::from_bytes(&sig).unwrap(),
+        };
+        (raw.as_slice().to_vec(), Certificate(crt.to_vec().unwrap()))
+    }
+
+    #[test]
+    fn renewal() {
+        const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+        // Whole seconds, since certificates have a resolution of seconds.
+        let now = SystemTime::UNIX_EPOCH
+            + Duration::from_secs(
+                SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs(),
+            );
+
+        let (_, crt) = certificate(now, now + 10 * DAY);
+        assert_eq!(renewal_time(&[crt.clone()], DAY).unwrap(), now + 9 * DAY);
+
+        // A margin exceeding half of the validity period is capped.
+        assert_eq!(renewal_time(&[crt], 30 * DAY).unwrap(), now + 5 * DAY);
+    }
+
+    #[test]
+    fn replace() {
+        let now = SystemTime::now();
+        let (key, old) = certificate(now, now + Duration::from_secs(60));
+        let (_, new) = certificate(now, now + Duration::from_secs(120));
+
+        let certs = Certs::new(&key, vec![old.clone()]).unwrap();
+        assert_eq!(certs.current().cert, vec![old]);
+        certs.replace(vec![new.clone()]);
+        assert_eq!(certs.current().cert, vec![new.clone()]);
+
+        // The PEM encoding follows the renewed chain.
+        let pem = certs.pem();
+        assert!(pem.starts_with("-----BEGIN CERTIFICATE-----\n"));
+        assert!(pem.ends_with("-----END CERTIFICATE-----\n"));
+        assert!(pem.lines().all(|line| line.len() <= 64));
+        let b64: String = pem.lines().filter(|l| !l.starts_with("-----")).collect();
+        assert_eq!(base64::decode(b64).unwrap(), new.0);
+    }
+}
@@ -1,15 +1,31 @@
 // SPDX-License-Identifier: Apache-2.0
 
-use super::super::{Package, PACKAGE_CONFIG, PACKAGE_ENTRYPOINT};
-use super::pki::PrivateKeyInfoExt;
-use super::{Attested, Loader, Requested};
+use super::super::events::{self, Event};
+#[cfg(unix)]
+use super::super::report;
+use super::super::{
+    is_package_data, mounts_package_data, Package, StewardRejected, MAX_PACKAGE_DATA_SIZE,
+    PACKAGE_CONFIG, PACKAGE_ENTRYPOINT,
+};
+use super::compiled::{self, status};
+use super::configured::hints;
+use super::connected;
+#[cfg(unix)]
+use super::handoff;
+use super::pki::{self, hex, PrivateKeyInfoExt};
+use super::renewal::{Certs, Renewal};
+#[cfg(unix)]
+use super::sealed::Cache;
+use super::secrets::{self, Secrets};
+use super::{Attested, Configured, Loader, Requested};
 
+use std::collections::BTreeMap;
 use std::io::Read;
-use std::ops::Deref;
 
 #[cfg(unix)]
-use std::os::unix::prelude::FromRawFd;
+use std::os::unix::prelude::{FromRawFd, RawFd};
 use std::sync::Arc;
+use std::thread;
 use std::time::Duration;
 
 use anyhow::{anyhow, bail, ensure, Context, Result};
@@ -17,12 +33,21 @@ use const_oid::db::rfc5280::{
     ID_CE_BASIC_CONSTRAINTS, ID_CE_EXT_KEY_USAGE, ID_CE_KEY_USAGE, ID_KP_CLIENT_AUTH,
     ID_KP_SERVER_AUTH,
 };
-use drawbridge_client::types::{Meta, TagEntry, TreeDirectory, TreeEntry, TreePath};
+use drawbridge_client::types::{Meta, TagEntry, TreeDirectory, TreeEntry, TreeName, TreePath};
 use drawbridge_client::{scope, Client, Entity, Node, Scope};
-use enarx_config::Config;
+use enarx_config::{
+    CipherSuite, ClientAuth, Config, File, KxGroup, LogTarget, Service, Tls, TlsVersion,
+    MAIN_SERVICE,
+};
 use getrandom::getrandom;
+use log::warn;
 use pkcs8::PrivateKeyInfo;
-use rustls::{cipher_suite::*, kx_group::*, version::TLS13, *};
+use rustls::server::AllowAnyAnonymousOrAuthenticatedClient;
+use rustls::{
+    ClientConfig, OwnedTrustAnchor, RootCertStore, ServerConfig, SupportedCipherSuite,
+    SupportedKxGroup, SupportedProtocolVersion,
+};
+use sha2::{Digest, Sha256};
 use ureq::serde_json;
 use url::Url;
 use x509_cert::der::asn1::{BitStringRef, UIntRef};
@@ -32,50 +57,82 @@ use x509_cert::name::RdnSequence;
 use x509_cert::time::Validity;
 use x509_cert::{Certificate, PkiPath, TbsCertificate};
 
-/// Maximum size of WASM module in bytes
-const MAX_WASM_SIZE: u64 = 100_000_000;
 /// Maximum size of Enarx.toml in bytes
 const MAX_CONF_SIZE: u64 = 1_000_000;
 /// Maximum directory size in bytes
 const MAX_DIR_SIZE: u64 = 1_000_000;
 
-/// Maximum size of top-level response body in bytes
-const MAX_TOP_SIZE: u64 = MAX_WASM_SIZE;
+/// Time before the expiry of the certificate issued by the steward, at which it is renewed by default
+const DEFAULT_RENEWAL_MARGIN: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Time waited before retrying the Stewards by default
+const DEFAULT_STEWARD_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Maximum time waited before retrying the Stewards
+const MAX_STEWARD_BACKOFF: Duration = Duration::from_secs(60);
 
 const TOML_MEDIA_TYPE: &str = "application/toml";
 const WASM_MEDIA_TYPE: &str = "application/wasm";
 
-fn get_wasm(root: Entity<'_, impl Scope, scope::Node>, entry: &TreeEntry) -> Result<Vec<u8>> {
+/// Ratio of the maximum size of a cached compiled main module to the maximum size of a Wasm
+/// module, since native code is larger than Wasm
+const COMPILED_SIZE_RATIO: u64 = 4;
+
+/// The main module, the unparsed config, the modules of the services and the data files
+type Contents = (
+    Vec<u8>,
+    Option<String>,
+    BTreeMap<String, Vec<u8>>,
+    BTreeMap<String, Vec<u8>>,
+);
+
+fn get_wasm(
+    root: Entity<'_, impl Scope, scope::Node>,
+    name: &TreeName,
+    entry: &TreeEntry,
+    limit: u64,
+) -> Result<Vec<u8>> {
     ensure!(
         entry.meta.mime.essence_str() == WASM_MEDIA_TYPE,
-        "invalid `{}` media type `{}`",
-        *PACKAGE_ENTRYPOINT,
+        "invalid `{name}` media type `{}`",
         entry.meta.mime.essence_str()
     );
-    let (meta, wasm) = Node::new(root, &PACKAGE_ENTRYPOINT.clone().into())
-        .get_bytes(MAX_WASM_SIZE)
-        .with_context(|| format!("failed to fetch `{}`", *PACKAGE_ENTRYPOINT))?;
+    get_file(root, name, entry, limit)
+}
+
+fn get_file(
+    root: Entity<'_, impl Scope, scope::Node>,
+    name: &TreeName,
+    entry: &TreeEntry,
+    limit: u64,
+) -> Result<Vec<u8>> {
+    let (meta, wasm) = Node::new(root, &name.clone().into())
+        .get_bytes(limit)
+        .with_context(|| format!("failed to fetch `{name}`"))?;
     ensure!(
         meta == entry.meta,
-        "`{}` metadata does not match directory entry metadata",
-        *PACKAGE_ENTRYPOINT,
+        "`{name}` metadata does not match directory entry metadata",
     );
+
     Ok(wasm)
 }
 
 fn get_package(
     root: Entity<'_, impl Scope, scope::Node>,
     dir: TreeDirectory,
-) -> Result<(Vec<u8>, Option<String>)> {
+    limit: u64,
+) -> Result<Contents> {
     let wasm = dir
         .get(&PACKAGE_ENTRYPOINT)
         .ok_or_else(|| anyhow!("directory does not contain `{}`", *PACKAGE_ENTRYPOINT))
-        .and_then(|e| get_wasm(root.clone(), e).context("failed to get Wasm"))?;
+        .and_then(|e| {
+            get_wasm(root.clone(), &PACKAGE_ENTRYPOINT, e, limit).context("failed to get Wasm")
+        })?;
 
     let entry = if let Some(entry) = dir.get(&PACKAGE_CONFIG) {
         entry
     } else {
-        return Ok((wasm, None));
+        return Ok((wasm, None, BTreeMap::new(), BTreeMap::new()));
     };
     ensure!(
         entry.meta.mime.essence_str() == TOML_MEDIA_TYPE,
@@ -83,7 +140,8 @@ fn get_package(
         *PACKAGE_CONFIG,
         entry.meta.mime.essence_str()
     );
-    let (meta, conf) = Node::new(root, &PACKAGE_CONFIG.clone().into())
+
+    let (meta, conf) = Node::new(root.clone(), &PACKAGE_CONFIG.clone().into())
         .get_string(MAX_CONF_SIZE)
         .with_context(|| format!("failed to fetch `{}`", *PACKAGE_CONFIG))?;
     ensure!(
@@ -91,35 +149,298 @@ fn get_package(
         "`{}` metadata does not match directory entry metadata",
         *PACKAGE_CONFIG,
     );
+    let parsed: Config = toml::from_str(&conf).context("failed to parse config")?;
+
+    // The modules of the services are part of the same package.
+    let mut modules = BTreeMap::new();
+    for (service, Service { module, .. }) in parsed.services.iter() {
+        let name: TreeName = module
+            .parse()
+            .with_context(|| format!("invalid module name `{module}` of service `{service}`"))?;
+        let wasm = dir
+            .get(&name)
+            .ok_or_else(|| anyhow!("directory does not contain `{name}` of service `{service}`"))
+            .and_then(|e| get_wasm(root.clone(), &name, e, limit))
+            .with_context(|| format!("failed to get Wasm of service `{service}`"))?;
+        modules.insert(service.clone(), wasm);
+    }
+
+    // The data files are only fetched, if the workload mounts them.
+    let mut data = BTreeMap::new();
+    if mounts_package_data(&parsed) {
+        let mut remaining = MAX_PACKAGE_DATA_SIZE;
+        for (name, entry) in dir.iter() {
+            if !is_package_data(&parsed, name.as_str())
+                || entry.meta.mime.essence_str() == TreeDirectory::<()>::TYPE
+            {
+                continue;
+            }
+            ensure!(
+                entry.meta.size <= remaining,
+                "data files of the package exceed the limit of `{MAX_PACKAGE_DATA_SIZE}`"
+            );
+            let file = get_file(root.clone(), name, entry, remaining)
+                .with_context(|| format!("failed to get data file `{name}`"))?;
+            remaining -= file.len() as u64;
+            data.insert(name.to_string(), file);
+        }
+    }
 
-    Ok((wasm, Some(conf)))
+    Ok((wasm, Some(conf), modules, data))
 }
 
-impl Loader<Requested> {
-    fn steward(&self, url: &Url) -> Result<Vec<Vec<u8>>> {
-        if url.scheme() != "https" {
-            return Err(anyhow!("refusing to use an unencrypted steward url"));
+/// Reads the data files of a local package, which are at most [`MAX_PACKAGE_DATA_SIZE`] bytes in total.
+fn read_data(
+    files: impl IntoIterator<Item = (String, std::fs::File)>,
+) -> Result<BTreeMap<String, Vec<u8>>> {
+    let mut remaining = MAX_PACKAGE_DATA_SIZE;
+    files
+        .into_iter()
+        .map(|(name, file)| {
+            let mut data = vec![];
+            file.take(remaining + 1)
+                .read_to_end(&mut data)
+                .with_context(|| format!("failed to read data file `{name}`"))?;
+            remaining = remaining.checked_sub(data.len() as _).ok_or_else(|| {
+                anyhow!("data files of the package exceed the limit of `{MAX_PACKAGE_DATA_SIZE}`")
+            })?;
+            Ok((name, data))
+        })
+        .collect()
+}
+
+/// Reads a local Wasm module of at most `limit` bytes from `file`.
+///
+/// The buffer is allocated at once, if the size of `file` is known, such that the module is
+/// not copied while it is read.
+fn read_wasm(file: &mut std::fs::File, limit: u64) -> Result<Vec<u8>> {
+    // The size is unknown inside of keeps, which do not support `fstat` on files of the host.
+    let size = file.metadata().map(|md| md.len()).unwrap_or_default();
+    ensure!(
+        size <= limit,
+        "Wasm size of `{size}` exceeds the limit of `{limit}`"
+    );
+    let mut webasm = Vec::with_capacity(size.try_into()?);
+    file.take(limit + 1).read_to_end(&mut webasm)?;
+    ensure!(
+        webasm.len() as u64 <= limit,
+        "Wasm size exceeds the limit of `{limit}`"
+    );
+    Ok(webasm)
+}
+
+/// Returns the TLS protocol versions allowed by `tls`.
+fn protocol_versions(tls: &Tls) -> Vec<&'static SupportedProtocolVersion> {
+    tls.versions()
+        .iter()
+        .map(|version| match version {
+            TlsVersion::Tls12 => &rustls::version::TLS12,
+            TlsVersion::Tls13 => &rustls::version::TLS13,
+        })
+        .collect()
+}
+
+/// Returns the key exchange groups allowed by `tls`.
+fn kx_groups(tls: &Tls) -> Vec<&'static SupportedKxGroup> {
+    use rustls::kx_group::*;
+
+    tls.kx_groups()
+        .iter()
+        .map(|group| match group {
+            KxGroup::X25519 => &X25519,
+            KxGroup::SECP384R1 => &SECP384R1,
+            KxGroup::SECP256R1 => &SECP256R1,
+        })
+        .collect()
+}
+
+/// Returns the cipher suites allowed by `tls`.
+fn cipher_suites(tls: &Tls) -> Vec<SupportedCipherSuite> {
+    use rustls::cipher_suite::*;
+
+    tls.cipher_suites()
+        .iter()
+        .map(|suite| match suite {
+            CipherSuite::TLS13_AES_256_GCM_SHA384 => TLS13_AES_256_GCM_SHA384,
+            CipherSuite::TLS13_AES_128_GCM_SHA256 => TLS13_AES_128_GCM_SHA256,
+            CipherSuite::TLS13_CHACHA20_POLY1305_SHA256 => TLS13_CHACHA20_POLY1305_SHA256,
+            CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384 => {
+                TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384
+            }
+            CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256 => {
+                TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256
+            }
+            CipherSuite::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256 => {
+                TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256
+            }
+            CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384 => {
+                TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384
+            }
+            CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256 => {
+                TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256
+            }
+            CipherSuite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256 => {
+                TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256
+            }
+        })
+        .collect()
+}
+
+/// Sends the certificate signing request `crtreq` to the Steward at `url`, optionally through
+/// the HTTP proxy at `proxy`, and returns the issued certificate chain.
+pub(super) fn steward(url: &Url, proxy: Option<&Url>, crtreq: &[u8]) -> Result<Vec<Vec<u8>>> {
+    if url.scheme() != "https" {
+        return Err(anyhow!("refusing to use an unencrypted steward url"));
+    }
+
+    let mut agent = ureq::AgentBuilder::new();
+    if let Some(proxy) = proxy {
+        ensure!(
+            proxy.scheme() == "http",
+            "unsupported steward proxy scheme `{}`, expected `http`",
+            proxy.scheme()
+        );
+        agent = agent.proxy(ureq::Proxy::new(proxy.as_str())?);
+    }
+
+    // Send the attestation to the steward.
+    let response = agent
+        .build()
+        .post(url.as_str())
+        .set("Content-Type", "application/pkcs10")
+        .send_bytes(crtreq)
+        .map_err(|e| match e {
+            ureq::Error::Status(status, response) => StewardRejected {
+                status,
+                reason: response.into_string().unwrap_or_default(),
+            }
+            .into(),
+            e => anyhow::Error::from(e),
+        })?;
+
+    // Read the result.
+    let mut body = Vec::new();
+    response.into_reader().read_to_end(&mut body)?;
+
+    // Decode the certificate chain.
+    let path = PkiPath::from_der(&body)?;
+    path.iter().rev().map(|c| Ok(c.to_vec()?)).collect()
+}
+
+/// Returns whether `e` is a rejection of a certificate signing request by a Steward, i.e. a client
+/// error, as opposed to a server error, after which the next Steward is tried.
+pub(super) fn rejected(e: &anyhow::Error) -> bool {
+    matches!(e.downcast_ref::<StewardRejected>(), Some(e) if e.status < 500)
+}
+
+/// The Stewards of a keep, which are tried in order until one issues a certificate
+#[derive(Clone, Debug)]
+pub struct Stewards {
+    urls: Vec<Url>,
+    proxy: Option<Url>,
+    retries: u32,
+    backoff: Duration,
+}
+
+impl Stewards {
+    pub(super) fn new(config: &Config) -> Self {
+        Self {
+            urls: config.steward.clone(),
+            proxy: config.steward_proxy.clone(),
+            retries: config.steward_retries.unwrap_or_default(),
+            backoff: config
+                .steward_backoff
+                .map_or(DEFAULT_STEWARD_BACKOFF, Duration::from_secs),
         }
+    }
 
-        // Send the attestation to the steward.
-        let response = ureq::post(url.as_str())
-            .set("Content-Type", "application/pkcs10")
-            .send_bytes(&self.0.crtreq)?;
+    /// Sends the certificate signing request `crtreq` to the Stewards in order and returns the
+    /// URL of the Steward, which issued the certificate chain, and the chain.
+    ///
+    /// A Steward, which cannot be reached or fails with a server error, is skipped, while a
+    /// rejection is returned immediately. If all Stewards were skipped, they are tried again up to
+    /// `retries` times with an exponential backoff.
+    pub(super) fn request(&self, crtreq: &[u8]) -> Result<(Url, Vec<Vec<u8>>)> {
+        let mut backoff = self.backoff;
+        let mut retries = self.retries;
+        loop {
+            let mut last = None;
+            for url in &self.urls {
+                match steward(url, self.proxy.as_ref(), crtreq) {
+                    Ok(chain) => return Ok((url.clone(), chain)),
+                    Err(e) if rejected(&e) => return Err(e),
+                    Err(e) => {
+                        warn!("failed to request certificate from steward `{url}`: {e:#}");
+                        last = Some(e.context(format!(
+                            "failed to request certificate from steward `{url}`"
+                        )));
+                    }
+                }
+            }
+            let e = last.unwrap_or_else(|| anyhow!("no steward configured"));
+            if retries == 0 {
+                return Err(e);
+            }
+            retries -= 1;
+            thread::sleep(backoff);
+            backoff = (backoff * 2).min(MAX_STEWARD_BACKOFF);
+        }
+    }
+}
 
-        // Read the result.
-        let mut body = Vec::new();
-        response.into_reader().read_to_end(&mut body)?;
+/// Blocks until the host releases the keep by writing a byte to `fd` or a shutdown is requested.
+#[cfg(unix)]
+fn hold(fd: RawFd) -> Result<()> {
+    use super::super::shutdown;
+    use rustix::io::{Errno, PollFd, PollFlags};
+    use std::mem::ManuallyDrop;
+
+    // The FD is managed by the host, so it is never closed.
+    let mut file = ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(fd) });
+    loop {
+        if shutdown::requested() {
+            bail!("keep was shut down before it was released");
+        }
+        let mut fds = [PollFd::new(&*file, PollFlags::IN)];
+        match rustix::io::poll(&mut fds, 100) {
+            Ok(0) | Err(Errno::INTR) => continue,
+            Ok(..) => break,
+            Err(e) => return Err(e).context("failed to wait for the release of the keep"),
+        }
+    }
+    let mut byte = [0];
+    match file
+        .read(&mut byte)
+        .context("failed to read the release of the keep")?
+    {
+        0 => bail!("keep was not released by the host"),
+        _ => Ok(()),
+    }
+}
 
-        // Decode the certificate chain.
-        let path = PkiPath::from_der(&body)?;
-        path.iter().rev().map(|c| Ok(c.to_vec()?)).collect()
+/// Parses a pinned `digest` of the form `sha256:<hex>`.
+fn pinned(digest: &str) -> Result<[u8; 32]> {
+    let hex = digest
+        .strip_prefix("sha256:")
+        .with_context(|| format!("digest `{digest}` does not start with `sha256:`"))?;
+    ensure!(
+        hex.len() == 64 && hex.bytes().all(|b| b.is_ascii_hexdigit()),
+        "digest `{digest}` is not a hex-encoded SHA-256 digest"
+    );
+    let mut bytes = [0; 32];
+    for (byte, i) in bytes.iter_mut().zip((0..hex.len()).step_by(2)) {
+        *byte = u8::from_str_radix(&hex[i..i + 2], 16)?;
     }
+    Ok(bytes)
+}
 
-    fn selfsigned(&self) -> Result<Vec<Vec<u8>>> {
+impl Loader<Requested> {
+    /// Returns a self-signed certificate chain with common name `cn`.
+    fn selfsigned(&self, cn: &str) -> Result<Vec<Vec<u8>>> {
         let pki = PrivateKeyInfo::from_der(&self.0.prvkey)?;
 
         // Create a relative distinguished name.
-        let rdns = RdnSequence::encode_from_string("CN=localhost")?;
+        let rdns = RdnSequence::encode_from_string(&format!("CN={cn}"))?;
 
         // Create the extensions.
         let ku = KeyUsage(KeyUsages::DigitalSignature | KeyUsages::KeyEncipherment).to_vec()?;
@@ -175,20 +496,30 @@ impl Loader<Requested> {
         Ok(vec![crt.to_vec()?])
     }
 
+    /// Returns the identity of the keep, i.e. the hex-encoded SHA-256 digest of its public key.
+    fn identity(&self) -> Result<String> {
+        let pki = PrivateKeyInfo::from_der(&self.0.prvkey)?;
+        let der = pki.public_key()?.to_vec()?;
+        Ok(hex(&Sha256::digest(der)))
+    }
+
     pub fn next(mut self) -> Result<Loader<Attested>> {
-        let (webasm, config) = match self.0.package {
+        let limit = self.0.max_wasm_size;
+        // Fail before fetching anything, if the pinned digest is malformed.
+        let pinned = self.0.digest.as_deref().map(pinned).transpose()?;
+        let (webasm, config, modules, data) = match self.0.package {
             Package::Remote(ref url) => {
                 let cl = Client::<scope::Unknown>::new_scoped(url.clone())
                     .context("failed to construct client")?;
                 let top = Entity::new(&cl);
                 let (Meta { size, mime, .. }, mut rdr) = top
-                    .get(MAX_TOP_SIZE)
+                    .get(limit.max(MAX_DIR_SIZE))
                     .with_context(|| format!("failed to fetch top-level URL `{url}`"))?;
                 match mime.essence_str() {
                     WASM_MEDIA_TYPE => {
                         ensure!(
-                            size <= MAX_WASM_SIZE,
-                            "Wasm size of `{size}` exceeds the limit of `{MAX_WASM_SIZE}`"
+                            size <= limit,
+                            "Wasm size of `{size}` exceeds the limit of `{limit}`"
                         );
                         let size = size
                             .try_into()
@@ -198,12 +529,13 @@ impl Loader<Requested> {
                             .read_to_end(&mut wasm)
                             .context("failed to fetch workload")?;
                         ensure!(n == size, "invalid amount of Wasm bytes fetched");
-                        (wasm, None)
+                        (wasm, None, BTreeMap::new(), BTreeMap::new())
                     }
                     TreeDirectory::<()>::TYPE => serde_json::from_reader(rdr)
                         .context("failed to decode response body")
                         .and_then(|dir| {
-                            get_package(top.clone().scope(), dir).context("failed to fetch package")
+                            get_package(top.clone().scope(), dir, limit)
+                                .context("failed to fetch package")
                         })?,
                     typ => {
                         let tag = serde_json::from_reader(rdr).with_context(|| format!("failed to decode top-level entity of type `{typ}` as either Wasm module, Drawbridge directory or a tag"))?;
@@ -217,8 +549,8 @@ impl Loader<Requested> {
                         let tree = top.child("tree");
                         let root = Node::new(tree.clone(), &TreePath::ROOT);
                         match entry.meta.mime.essence_str() {
-                            WASM_MEDIA_TYPE => get_wasm(tree, &entry)
-                                .map(|wasm| (wasm, None))
+                            WASM_MEDIA_TYPE => get_wasm(tree, &PACKAGE_ENTRYPOINT, &entry, limit)
+                                .map(|wasm| (wasm, None, BTreeMap::new(), BTreeMap::new()))
                                 .context("failed to fetch workload")?,
                             TreeDirectory::<()>::TYPE => {
                                 let (meta, dir) = root
@@ -228,7 +560,7 @@ impl Loader<Requested> {
                                     meta == entry.meta,
                                     "directory metadata does not match tag entry metadata"
                                 );
-                                get_package(tree, dir).context("failed to fetch package")?
+                                get_package(tree, dir, limit).context("failed to fetch package")?
                             }
                             typ => bail!("unsupported root type `{typ}`"),
                         }
@@ -238,15 +570,15 @@ impl Loader<Requested> {
             Package::Local {
                 ref mut wasm,
                 ref mut conf,
+                ref mut modules,
+                ref mut data,
             } => {
-                let mut webasm = Vec::new();
                 // SAFETY: This FD was passed to us by the host and we trust that we have exclusive
                 // access to it.
                 #[cfg(unix)]
                 let mut wasm = unsafe { std::fs::File::from_raw_fd(*wasm) };
 
-                wasm.read_to_end(&mut webasm)
-                    .context("failed to read WASM module")?;
+                let webasm = read_wasm(&mut wasm, limit).context("failed to read WASM module")?;
 
                 let config = if let Some(conf) = conf.as_mut() {
                     let mut config = String::new();
@@ -257,46 +589,296 @@ impl Loader<Requested> {
 
                     conf.read_to_string(&mut config)
                         .context("failed to read config")?;
-
                     Some(config)
                 } else {
                     None
                 };
-                (webasm, config)
+
+                let modules = modules
+                    .iter_mut()
+                    .map(|(service, module)| {
+                        // SAFETY: This FD was passed to us by the host and we trust that we have exclusive
+                        // access to it.
+                        #[cfg(unix)]
+                        let mut module = unsafe { std::fs::File::from_raw_fd(*module) };
+
+                        let webasm = read_wasm(&mut module, limit).with_context(|| {
+                            format!("failed to read WASM module of service `{service}`")
+                        })?;
+                        Ok((service.clone(), webasm))
+                    })
+                    .collect::<Result<_>>()?;
+                let data = read_data(std::mem::take(data).into_iter().map(|(name, file)| {
+                    // SAFETY: This FD was passed to us by the host and we trust that we have exclusive
+                    // access to it.
+                    #[cfg(unix)]
+                    let file = unsafe { std::fs::File::from_raw_fd(file) };
+
+                    (name, file)
+                }))?;
+                (webasm, config, modules, data)
+            }
+        };
+        events::emit(Event::PackageFetched {
+            url: match self.0.package {
+                Package::Remote(ref url) => Some(url.clone()),
+                Package::Local { .. } => None,
+            },
+            size: webasm.len(),
+        });
+
+        // The pinned digest is authoritative, regardless of the digest claimed by the server.
+        let cn = match pinned {
+            Some(pinned) => {
+                let digest: [u8; 32] = Sha256::digest(&webasm).into();
+                let hex = hex(&digest);
+                ensure!(
+                    digest == pinned,
+                    "Wasm module digest `sha256:{hex}` does not match the pinned digest `{}`",
+                    self.0.digest.as_deref().unwrap_or_default()
+                );
+                hex
             }
+            None => String::from("localhost"),
         };
-        let config: Config = if let Some(ref config) = config {
-            toml::from_str(config).context("failed to parse config")?
+
+        #[cfg(unix)]
+        let config_digest = config
+            .as_deref()
+            .map(|config| pki::digest(config.as_bytes()));
+        let raw = config;
+        let mut config: Config = raw
+            .as_deref()
+            .map(toml::from_str)
+            .transpose()
+            .context("failed to parse config")?
+            .unwrap_or_default();
+        if config.services.contains_key(MAIN_SERVICE) {
+            bail!(
+                "service name `{MAIN_SERVICE}` is reserved for `{}`",
+                *PACKAGE_ENTRYPOINT
+            );
+        }
+        if let Some(service) = config.services.keys().find(|s| !modules.contains_key(*s)) {
+            bail!("package does not contain the module of service `{service}`");
+        }
+        // Every service runs on its own thread, so refuse them before compiling any of the modules,
+        // if the keep cannot create threads.
+        if !config.services.is_empty() && !connected::threads() {
+            bail!("`services` are not supported by this backend, whose shim cannot create threads, only by `nil`");
+        }
+        // The host only allows mounts when asked to, on backends which run the workload as a process
+        // of the host.
+        if !config.mount.is_empty() && !self.0.mounts {
+            bail!("`mount` requires `--allow-mounts` on the `nil` backend, keeps cannot access the files of the host");
+        }
+        if let Some(path) = config.mount.keys().find(|path| !path.starts_with('/')) {
+            bail!("mount path `{path}` must be absolute");
+        }
+        for file in config
+            .files
+            .iter()
+            .chain(config.services.values().flat_map(|s| s.files.iter()))
+        {
+            match file {
+                File::Log { target, path, .. } | File::Audit { target, path, .. } => {
+                    match (target, path) {
+                        (LogTarget::File, None) => {
+                            bail!(
+                                "`target = \"file\"` of file `{}` requires a `path`",
+                                file.name()
+                            )
+                        }
+                        (LogTarget::File, Some(path)) if !path.starts_with('/') => {
+                            bail!("log file path `{path}` must be absolute")
+                        }
+                        (LogTarget::Stdout | LogTarget::Stderr, Some(_)) => {
+                            bail!(
+                                "`path` of file `{}` requires `target = \"file\"`",
+                                file.name()
+                            )
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        // The host may only hand the certificate signing request off, if the config allows it, since
+        // the certificate is then issued by whoever the host chooses instead of the Stewards.
+        #[cfg(unix)]
+        let handoff = self.0.handoff;
+        #[cfg(not(unix))]
+        let handoff = None::<()>;
+        if handoff.is_some() && config.steward_handoff != Some(true) {
+            bail!("the host requested to hand off the certificate signing request, which requires `steward_handoff = true`");
+        }
+        // Only the certificate issued by a Steward attests the keep to the secrets server.
+        if config.secrets.is_some() && config.steward.is_empty() {
+            bail!("`secrets` requires a `steward` to issue the certificate of the keep");
+        }
+        if config.secrets.is_some() && config.steward_required == Some(false) {
+            bail!("`secrets` requires `steward_required`, self-signed keeps cannot fetch secrets");
+        }
+        if config.secrets.is_some() && config.steward_handoff == Some(true) {
+            bail!("`secrets` cannot be combined with `steward_handoff`, keeps with a certificate issued out of band cannot fetch secrets");
+        }
+        if let Some(url) = config.steward.iter().find(|url| url.scheme() != "https") {
+            bail!("refusing to use the unencrypted steward url `{url}`");
+        }
+        if config.secrets.is_none() {
+            let mut files = config.files.iter().chain(
+                config
+                    .services
+                    .values()
+                    .flat_map(|service| service.files.iter()),
+            );
+            if let Some(file) = files.find(|file| matches!(file, File::Secret { .. })) {
+                bail!("file `{}` requires a `secrets` bundle url", file.name());
+            }
+        }
+
+        // Pass the allowed host environment variables, overriding the ones in the config.
+        let env = std::mem::take(&mut self.0.env);
+        config.env.extend(
+            env.into_iter()
+                .filter(|(name, _)| config.env_host.iter().any(|p| p.matches(name))),
+        );
+
+        let identity = self.identity().context("failed to compute keep identity")?;
+
+        // The policy hints for the Steward requested by the config, also requested on renewal
+        let hints = hints::encode(&config.steward_hints, self.0.technology, raw.as_deref())
+            .context("failed to encode steward hints")?;
+
+        // If specified in the config, the certificate is issued by the first available Steward,
+        // unless the host hands the certificate signing request off to be issued out of band.
+        let stewards = Stewards::new(&config);
+        // Whether the keep self-signed its certificate, since no steward issued one
+        let mut fallback = false;
+        let (steward, certs) = if config.steward.is_empty() && handoff.is_none() {
+            (None, self.selfsigned(&cn)?)
         } else {
-            Default::default()
+            let pki = PrivateKeyInfo::from_der(&self.0.prvkey)?;
+            let crtreq = Loader::<Configured>::csr(self.0.technology, &self.0.report, &pki, &hints)
+                .context("failed to make certificate signing request")?;
+            #[cfg(unix)]
+            let handed_off = handoff
+                .map(|(csr, crt)| handoff::request(csr, crt, &crtreq, &pki))
+                .transpose()
+                .context("failed to obtain certificate issued out of band")?;
+            #[cfg(not(unix))]
+            let handed_off: Option<Vec<Vec<u8>>> = None;
+            match handed_off {
+                // The certificate issued out of band is not renewed, since it is issued by hand.
+                Some(chain) => (None, chain),
+                None => match stewards.request(&crtreq) {
+                    Ok((url, chain)) => (Some(url), chain),
+                    Err(e) if config.steward_required == Some(false) && !rejected(&e) => {
+                        warn!("no steward issued a certificate, self-signing it instead: {e:#}");
+                        fallback = true;
+                        (None, self.selfsigned(&cn)?)
+                    }
+                    Err(e) => return Err(e),
+                },
+            }
         };
+        let certs = certs
+            .into_iter()
+            .map(rustls::Certificate)
+            .collect::<Vec<_>>();
+        // The root of an issued chain, e.g. the CA of the Steward, unlike a self-signed certificate
+        let issuer = certs.last().filter(|_| certs.len() > 1).cloned();
+        let (certificate, serial) = certs
+            .first()
+            .map(|crt| -> Result<(String, String)> {
+                let serial = Certificate::from_der(&crt.0)
+                    .context("failed to decode certificate")?
+                    .tbs_certificate
+                    .serial_number;
+                Ok((hex(&Sha256::digest(&crt.0)), hex(serial.as_bytes())))
+            })
+            .ok_or_else(|| anyhow!("empty certificate chain"))??;
+        // The execution report is signed by the key of the keep and carries its certificates.
+        #[cfg(unix)]
+        if report::requested() {
+            report::attested(report::Signer {
+                prvkey: self.0.prvkey.clone(),
+                certs: certs.iter().map(|crt| crt.0.clone()).collect(),
+                identity: identity.clone(),
+                workload: pki::digest(&webasm),
+                config: config_digest,
+            });
+        }
+        status::attested(status::Identity {
+            identity: identity.clone(),
+            technology: status::Identity::technology(self.0.technology),
+            evidence: (!self.0.report.is_empty()).then(|| hex(&self.0.report)),
+            workload_digest: pki::digest(&webasm),
+            config_digest: raw.as_deref().map(|config| pki::digest(config.as_bytes())),
+        });
+        let certs = Arc::new(Certs::new(&self.0.prvkey, certs)?);
+        let binding = compiled::binding(&webasm, raw.as_deref());
+        events::emit(Event::Attested {
+            identity: identity.clone(),
+            certificate,
+            steward: steward.clone(),
+            serial,
+            workload: pki::digest(&webasm),
+        });
+
+        // Let the host register the identity of the keep, before any of the workload runs.
+        #[cfg(unix)]
+        if let Some(fd) = self.0.hold {
+            hold(fd)?;
+        }
 
-        // If specified in the config
-        let certs = match config.steward.as_ref() {
-            Some(url) => self.steward(url)?,
-            None => self.selfsigned()?,
+        // Read the compiled main module cached by the host, which is validated when it is loaded.
+        #[cfg(unix)]
+        let cache = self
+            .0
+            .cache
+            .map(|(fd, sealer)| {
+                // SAFETY: These FDs were passed to us by the host and we trust that we have
+                // exclusive access to them.
+                let out = unsafe { std::fs::File::from_raw_fd(fd) };
+                let cached = self
+                    .0
+                    .cached
+                    .map(|fd| {
+                        let mut cached = unsafe { std::fs::File::from_raw_fd(fd) };
+                        let limit = self.0.max_wasm_size.saturating_mul(COMPILED_SIZE_RATIO);
+                        read_wasm(&mut cached, limit).context("failed to read cached module")
+                    })
+                    .transpose()?
+                    .filter(|cached| !cached.is_empty());
+                Ok::<_, anyhow::Error>(Cache {
+                    sealer,
+                    cached,
+                    out,
+                })
+            })
+            .transpose()?;
+
+        // Renew the certificate issued by the steward before it expires, and replace the
+        // self-signed fallback by one issued by the steward, once it is available again.
+        if steward.is_some() || fallback {
+            let margin = config
+                .renewal_margin
+                .map_or(DEFAULT_RENEWAL_MARGIN, Duration::from_secs);
+            let prvkey = self.0.prvkey.clone();
+            let renewal = Renewal::new(certs.clone(), stewards, prvkey, hints, margin);
+            if fallback {
+                renewal.retry().start();
+            } else {
+                renewal.start();
+            }
         }
-        .into_iter()
-        .map(rustls::Certificate)
-        .collect::<Vec<_>>();
-
-        // TODO: load this policy from `Config`.
-        // https://github.com/enarx/enarx/issues/1548
-        let protocol_versions = &[&TLS13];
-        let kx_groups = &[&X25519, &SECP384R1, &SECP256R1];
-        let cipher_suites = &[
-            TLS13_AES_256_GCM_SHA384,
-            TLS13_AES_128_GCM_SHA256,
-            TLS13_CHACHA20_POLY1305_SHA256,
-        ];
 
-        // Set up the server config.
-        let srvcfg = ServerConfig::builder()
-            .with_cipher_suites(cipher_suites)
-            .with_kx_groups(kx_groups)
-            .with_protocol_versions(protocol_versions)?
-            .with_no_client_auth() // TODO: https://github.com/enarx/enarx/issues/1547
-            .with_single_cert(certs.clone(), PrivateKey(self.0.prvkey.deref().clone()))?;
+        // Load the TLS policy from the config.
+        let protocol_versions = &protocol_versions(&config.tls);
+        let kx_groups = &kx_groups(&config.tls);
+        let cipher_suites = &cipher_suites(&config.tls);
 
         // Set up root store.
         let mut root_store = RootCertStore::empty();
@@ -308,19 +890,107 @@ impl Loader<Requested> {
             )
         }));
 
+        // Set up the server config.
+        let srvcfg = ServerConfig::builder()
+            .with_cipher_suites(cipher_suites)
+            .with_kx_groups(kx_groups)
+            .with_protocol_versions(protocol_versions)?;
+        let srvcfg = match config.tls.client_auth() {
+            ClientAuth::None => srvcfg.with_no_client_auth(),
+            // Client certificates are verified against the roots of server certificates and the
+            // issuer of the certificate of the keep, such that other keeps can authenticate.
+            ClientAuth::Optional => {
+                let mut roots = root_store.clone();
+                if let Some(issuer) = &issuer {
+                    roots
+                        .add(issuer)
+                        .context("failed to trust the issuer of the keep certificate")?;
+                }
+                srvcfg.with_client_cert_verifier(AllowAnyAnonymousOrAuthenticatedClient::new(roots))
+            }
+        }
+        .with_cert_resolver(certs.clone());
+
         // Set up client config.
         let cltcfg = ClientConfig::builder()
             .with_cipher_suites(cipher_suites)
             .with_kx_groups(kx_groups)
             .with_protocol_versions(protocol_versions)?
             .with_root_certificates(root_store)
-            .with_single_cert(certs, PrivateKey(self.0.prvkey.deref().clone()))?;
+            .with_client_cert_resolver(certs.clone());
+        let cltcfg = Arc::new(cltcfg);
+
+        // Fetch the secrets of the workload with the certificate of the keep.
+        let secrets = match config.secrets.as_ref() {
+            Some(url) => secrets::fetch(url, cltcfg.clone())
+                .with_context(|| format!("failed to fetch secrets from `{url}`"))?,
+            None => Secrets::new(),
+        };
 
         Ok(Loader(Attested {
             srvcfg: Arc::new(srvcfg),
-            cltcfg: Arc::new(cltcfg),
+            cltcfg,
+            certs,
+            secrets,
             config,
             webasm,
+            data: Arc::new(data),
+            modules,
+            identity,
+            prvkey: self.0.prvkey,
+            binding,
+            technology: self.0.technology,
+            faults: self.0.faults,
+            debug: self.0.debug,
+            #[cfg(unix)]
+            cache,
+            #[cfg(unix)]
+            listeners: self.0.listeners,
+            #[cfg(unix)]
+            logs: self.0.logs,
         }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{pinned, rejected, StewardRejected, Stewards};
+
+    use enarx_config::Config;
+
+    #[test]
+    fn pinning() {
+        let hex = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let digest = pinned(&format!("sha256:{hex}")).unwrap();
+        assert_eq!(digest[..4], [0xe3, 0xb0, 0xc4, 0x42]);
+        assert_eq!(digest[31], 0x55);
+
+        assert!(pinned(hex).is_err());
+        assert!(pinned("sha256:e3b0").is_err());
+        assert!(pinned(&format!("sha256:{}", hex.replace('e', "g"))).is_err());
+    }
+
+    #[test]
+    fn failover() {
+        let config: Config = toml::from_str(
+            r#"
+            steward = ["https://127.0.0.1:1", "https://127.0.0.1:2"]
+            steward_retries = 1
+            steward_backoff = 0
+            "#,
+        )
+        .unwrap();
+        let e = Stewards::new(&config).request(b"csr").unwrap_err();
+        assert!(!rejected(&e));
+        assert!(e.to_string().contains("https://127.0.0.1:2/"));
+
+        let rejection = |status| {
+            anyhow::Error::from(StewardRejected {
+                status,
+                reason: String::new(),
+            })
+        };
+        assert!(rejected(&rejection(403)));
+        assert!(!rejected(&rejection(503)));
+    }
+}
@@ -0,0 +1,272 @@
+// SPDX-License-Identifier: Apache-2.0
+//! A WASI scheduler waiting on all subscribed files with a single `poll`
+
+use super::super::shutdown;
+use super::compiled;
+use super::renewal;
+
+use std::thread;
+use std::time::Duration;
+
+use rustix::io::{Errno, PollFd, PollFlags};
+use wasi_common::sched::subscription::{RwEventFlags, RwSubscription, Subscription};
+use wasi_common::sched::{Poll, WasiSched};
+use wasi_common::{Error, ErrorExt};
+
+/// Returns the file subscription of `sub` and the poll events it waits for.
+fn subscription<'a, 'b>(sub: &'b mut Subscription<'a>) -> (&'b mut RwSubscription<'a>, PollFlags) {
+    match sub {
+        Subscription::Read(sub) => (sub, PollFlags::IN),
+        Subscription::Write(sub) => (sub, PollFlags::OUT),
+        Subscription::MonotonicClock(..) => unreachable!(),
+    }
+}
+
+/// Completes `sub` according to the events returned by `poll`.
+fn complete(sub: &mut RwSubscription<'_>, revents: PollFlags) {
+    if revents.contains(PollFlags::NVAL) {
+        sub.error(Error::badf());
+    } else if revents.contains(PollFlags::ERR) {
+        sub.error(Error::io());
+    } else if revents.contains(PollFlags::HUP) {
+        sub.complete(1, RwEventFlags::HANGUP);
+    } else if !revents.is_empty() {
+        sub.complete(1, RwEventFlags::empty());
+    }
+}
+
+/// Returns the `poll` timeout in milliseconds, which waits at least `duration`.
+fn timeout(duration: Duration) -> Result<i32, Error> {
+    (duration.as_millis() + 1)
+        .try_into()
+        .map_err(|_| Error::overflow().context("poll timeout"))
+}
+
+/// The scheduler of the workload.
+///
+/// Unlike the default scheduler, this only completes the subscriptions of
+/// files, which are actually ready, and does not query the number of bytes
+/// ready to be read on each of them. Waiting on any number of sockets thus
+/// costs a single `poll` call, which is a single exit from the keep. The
+/// number of bytes reported for a ready file is always 1.
+///
+/// Files without a host file descriptor, e.g. the null or stats files, never
+/// block and are always ready. TLS listeners are also ready, once any of the
+/// handshakes pending on them can progress, which their accept drives.
+///
+/// A shutdown requested by the host traps the workload, even if it is waiting.
+///
+/// In keeps, which cannot create threads, the scheduler also renews the
+/// certificate of the keep, once due, which blocks the workload meanwhile.
+pub struct Sched;
+
+#[wiggle::async_trait]
+impl WasiSched for Sched {
+    async fn poll_oneoff<'a>(&self, poll: &mut Poll<'a>) -> Result<(), Error> {
+        if poll.is_empty() {
+            return Ok(());
+        }
+        if shutdown::requested() {
+            return Err(Error::trap("keep was shut down"));
+        }
+        renewal::poll();
+
+        // The number of file descriptors polled for each file subscription
+        let mut counts = vec![];
+        let mut fds = vec![];
+        let mut ready = false;
+        for sub in poll.rw_subscriptions() {
+            let (sub, flags) = subscription(sub);
+            if let Some(pollables) = compiled::pollables(sub.file) {
+                counts.push(pollables.len());
+                fds.extend(
+                    pollables
+                        .into_iter()
+                        .map(|(fd, events)| PollFd::from_borrowed_fd(fd, events)),
+                );
+                continue;
+            }
+            match sub.file.pollable() {
+                Some(fd) => {
+                    counts.push(1);
+                    fds.push(PollFd::from_borrowed_fd(fd, flags));
+                }
+                None => {
+                    counts.push(0);
+                    sub.complete(1, RwEventFlags::empty());
+                    ready = true;
+                }
+            }
+        }
+
+        // The shutdown request is waited for after the files, such that they keep their indices.
+        let requests = shutdown::fd();
+        if let Some(fd) = requests {
+            fds.push(PollFd::from_borrowed_fd(fd, PollFlags::IN));
+        }
+
+        let mut ms = match poll.earliest_clock_deadline() {
+            _ if ready => 0,
+            Some(deadline) => timeout(deadline.duration_until().unwrap_or_default())?,
+            None => -1,
+        };
+
+        loop {
+            // Wake up for the renewal of the certificate as well, unless the clock deadline is earlier.
+            let renewal = renewal::due_in().map(timeout).transpose()?;
+            let (wait, renew) = match renewal {
+                Some(renewal) if ms < 0 || renewal < ms => (renewal, true),
+                _ => (ms, false),
+            };
+            match rustix::io::poll(&mut fds, wait) {
+                // The clock subscription completes once its deadline has passed, so keep waiting,
+                // if the host returned early, such that the poll does not return without events.
+                Ok(0) => {
+                    if renew {
+                        renewal::poll();
+                    }
+                    match poll.earliest_clock_deadline() {
+                        Some(deadline) if !ready => match deadline.duration_until() {
+                            Some(rest) if !rest.is_zero() => ms = timeout(rest)?,
+                            _ => return Ok(()),
+                        },
+                        _ if renew => {}
+                        _ => return Ok(()),
+                    }
+                }
+                Ok(..) => break,
+                Err(Errno::INTR) if shutdown::requested() => {
+                    return Err(Error::trap("keep was shut down"))
+                }
+                Err(Errno::INTR) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        if requests.is_some() && !fds.last().unwrap().revents().is_empty() && shutdown::requested()
+        {
+            return Err(Error::trap("keep was shut down"));
+        }
+
+        let mut fds = fds.iter();
+        for (sub, count) in poll.rw_subscriptions().zip(counts) {
+            let (sub, _) = subscription(sub);
+            if count == 0 {
+                continue;
+            }
+            let mut revents = fds.next().unwrap().revents();
+            // A TLS listener is ready to progress its pending handshakes, once any of them is.
+            if fds
+                .by_ref()
+                .take(count - 1)
+                .any(|fd| !fd.revents().is_empty())
+            {
+                revents |= PollFlags::IN;
+            }
+            complete(sub, revents);
+        }
+        Ok(())
+    }
+
+    async fn sched_yield(&self) -> Result<(), Error> {
+        thread::yield_now();
+        Ok(())
+    }
+
+    async fn sleep(&self, duration: Duration) -> Result<(), Error> {
+        thread::sleep(duration);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Sched;
+
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+
+    use cap_std::time::Duration;
+    use wasi_common::sched::{Poll, SubscriptionResult, Userdata, WasiSched};
+    use wasi_common::WasiFile;
+    use wasmtime_wasi::net::Socket;
+    use wasmtime_wasi::sync::clocks_ctx;
+
+    fn socket(stream: TcpStream) -> Box<dyn WasiFile> {
+        Socket::from(cap_std::net::TcpStream::from_std(stream)).into()
+    }
+
+    #[test]
+    fn ready() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let streams: Vec<_> = (0..64)
+            .map(|_| {
+                let client = TcpStream::connect(addr).unwrap();
+                let (server, _) = listener.accept().unwrap();
+                (client, socket(server))
+            })
+            .collect();
+
+        // Only the last stream is readable.
+        (&streams[63].0).write_all(b"ready").unwrap();
+
+        let clocks = clocks_ctx();
+        let mut poll = Poll::new();
+        for (i, (_, file)) in streams.iter().enumerate() {
+            poll.subscribe_read(&**file, Userdata::from(i as u64));
+        }
+        let deadline = clocks.monotonic.now(Duration::ZERO) + Duration::from_secs(5);
+        poll.subscribe_monotonic_clock(
+            &*clocks.monotonic,
+            deadline,
+            Duration::ZERO,
+            Userdata::from(u64::MAX),
+        );
+        wiggle::run_in_dummy_executor(Sched.poll_oneoff(&mut poll))
+            .unwrap()
+            .unwrap();
+
+        let results = poll.results();
+        assert_eq!(results.len(), 1);
+        match &results[0] {
+            (SubscriptionResult::Read(Ok((1, flags))), ud) => {
+                assert!(flags.is_empty());
+                assert_eq!(u64::from(*ud), 63);
+            }
+            (res, _) => panic!("unexpected result {res:?}"),
+        }
+    }
+
+    #[test]
+    fn timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let file = socket(server);
+
+        let clocks = clocks_ctx();
+        let mut poll = Poll::new();
+        poll.subscribe_read(&*file, Userdata::from(0));
+        let deadline = clocks.monotonic.now(Duration::ZERO) + Duration::from_millis(20);
+        poll.subscribe_monotonic_clock(
+            &*clocks.monotonic,
+            deadline,
+            Duration::ZERO,
+            Userdata::from(1),
+        );
+        wiggle::run_in_dummy_executor(Sched.poll_oneoff(&mut poll))
+            .unwrap()
+            .unwrap();
+
+        // Only the clock subscription completes, once its deadline passed.
+        assert!(clocks.monotonic.now(Duration::ZERO) >= deadline);
+        let results = poll.results();
+        assert_eq!(results.len(), 1);
+        match &results[0] {
+            (SubscriptionResult::MonotonicClock(Ok(())), ud) => assert_eq!(u64::from(*ud), 1),
+            (res, _) => panic!("unexpected result {res:?}"),
+        }
+        drop(client);
+    }
+}
@@ -0,0 +1,216 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Compiled main modules sealed to the platform of the keep, which are cached by the host
+//!
+//! A sealed artifact consists of a magic number, the SHA-256 digest of the Wasm module, the
+//! fingerprint of the CPU, a nonce and the module serialized by wasmtime, encrypted with
+//! AES-256-GCM under a key derived from the sealing key of the platform and the digest of the
+//! Wasm module. The sealing key is bound to the measurement of the keep, so that another build of
+//! the keep cannot unseal the artifacts. All but the encrypted module are authenticated as
+//! additional data, such that a keep only deserializes modules compiled by a keep with the same
+//! measurement from the same Wasm module on the same CPU.
+//!
+//! Keeps without a sealing key, i.e. on the `kvm` and `nil` backends, derive the key from an
+//! empty secret. Their artifacts are only authenticated against corruption, since the host
+//! controls the memory of such keeps anyway.
+
+use std::fs::File;
+use std::io::Write;
+
+use anyhow::{anyhow, ensure, Context, Result};
+use getrandom::getrandom;
+use log::warn;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::hkdf::{Prk, Salt, HKDF_SHA256};
+use sha2::{Digest, Sha256};
+use wasmtime::{Engine, Module};
+
+/// Magic number of sealed artifacts
+const MAGIC: &[u8; 8] = b"\0enarxsc";
+
+/// Info of the HKDF expansion of the sealing key of the platform
+const KEY_INFO: &[u8] = b"enarx compiled module cache";
+
+/// Length of the header of a sealed artifact preceding the nonce
+const HEADER_LEN: usize = MAGIC.len() + 32 + 32;
+
+/// Returns the SHA-256 digest of the CPU vendor and the features reported by CPUID, on which
+/// the code generated by wasmtime depends.
+#[cfg(target_arch = "x86_64")]
+fn fingerprint() -> [u8; 32] {
+    use std::arch::x86_64::__cpuid_count;
+
+    let mut hash = Sha256::new();
+    for (leaf, subleaf) in [(0, 0), (1, 0), (7, 0), (0x8000_0001, 0)] {
+        // SAFETY: CPUID is available on all x86_64 CPUs, leaves beyond the maximum are reported
+        // as zeroes or as the highest leaf.
+        let cpuid = unsafe { __cpuid_count(leaf, subleaf) };
+        // The low bits of leaf 1 EBX identify the APIC and the logical processor count, and
+        // its EAX is the stepping, none of which the generated code depends on.
+        let (eax, ebx) = match leaf {
+            1 => (0, 0),
+            _ => (cpuid.eax, cpuid.ebx),
+        };
+        for reg in [eax, ebx, cpuid.ecx, cpuid.edx] {
+            hash.update(reg.to_le_bytes());
+        }
+    }
+    hash.finalize().into()
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn fingerprint() -> [u8; 32] {
+    [0; 32]
+}
+
+/// Returns the header of an artifact compiled from `webasm` on this CPU.
+fn header(webasm: &[u8]) -> Vec<u8> {
+    [MAGIC.as_slice(), &Sha256::digest(webasm), &fingerprint()].concat()
+}
+
+/// Seals and unseals compiled modules with keys derived from the sealing key of the platform
+pub(super) struct Sealer(Prk);
+
+impl Sealer {
+    /// Extracts the keys from `secret`, the sealing key of the platform.
+    pub fn new(secret: &[u8]) -> Result<Self> {
+        Ok(Self(Salt::new(HKDF_SHA256, &[]).extract(secret)))
+    }
+
+    /// Returns the key of the modules compiled from `webasm`.
+    fn key(&self, webasm: &[u8]) -> Result<LessSafeKey> {
+        let digest = Sha256::digest(webasm);
+        let key: UnboundKey = self
+            .0
+            .expand(&[KEY_INFO, &digest], &AES_256_GCM)
+            .map_err(|_| anyhow!("failed to derive sealing key"))?
+            .into();
+        Ok(LessSafeKey::new(key))
+    }
+
+    /// Returns `serialized`, the module compiled from `webasm`, as a sealed artifact.
+    pub fn seal(&self, webasm: &[u8], serialized: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0; NONCE_LEN];
+        getrandom(&mut nonce).context("failed to generate nonce")?;
+
+        let header = header(webasm);
+        let mut sealed = serialized.to_vec();
+        self.key(webasm)?
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(&header),
+                &mut sealed,
+            )
+            .map_err(|_| anyhow!("failed to seal compiled module"))?;
+        Ok([header.as_slice(), &nonce, &sealed].concat())
+    }
+
+    /// Returns the serialized module of `artifact`, if it was sealed by a keep with the same
+    /// sealing key for `webasm` on this CPU.
+    pub fn unseal(&self, mut artifact: Vec<u8>, webasm: &[u8]) -> Result<Vec<u8>> {
+        ensure!(artifact.starts_with(MAGIC), "not a sealed compiled module");
+        ensure!(
+            artifact.len() >= HEADER_LEN + NONCE_LEN,
+            "truncated sealed compiled module"
+        );
+        let header = header(webasm);
+        ensure!(
+            artifact[MAGIC.len()..MAGIC.len() + 32] == header[MAGIC.len()..MAGIC.len() + 32],
+            "sealed compiled module was compiled from a different Wasm module"
+        );
+        ensure!(
+            artifact[MAGIC.len() + 32..HEADER_LEN] == header[MAGIC.len() + 32..],
+            "sealed compiled module was compiled on a different CPU"
+        );
+
+        let mut sealed = artifact.split_off(HEADER_LEN + NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(&artifact[HEADER_LEN..])
+            .map_err(|_| anyhow!("invalid nonce"))?;
+        let len = self
+            .key(webasm)?
+            .open_in_place(nonce, Aad::from(&header), &mut sealed)
+            .map_err(|_| anyhow!("failed to unseal compiled module"))?
+            .len();
+        sealed.truncate(len);
+        Ok(sealed)
+    }
+}
+
+/// The cache of the compiled main module of the keep
+pub(super) struct Cache {
+    /// Sealer of the artifacts
+    pub sealer: Sealer,
+    /// Artifact sealed by a previous keep, if any
+    pub cached: Option<Vec<u8>>,
+    /// File of the host to write a new artifact to
+    pub out: File,
+}
+
+impl Cache {
+    /// Returns the cached module compiled from `webasm`, if it is valid for `engine`.
+    pub fn load(&mut self, engine: &Engine, webasm: &[u8]) -> Option<Module> {
+        let artifact = self.cached.take()?;
+        // SAFETY: The artifact was sealed by a keep with the same sealing key, i.e. on the same
+        // platform, after compiling the same Wasm module, and is authenticated.
+        match self
+            .sealer
+            .unseal(artifact, webasm)
+            .and_then(|serialized| unsafe { Module::deserialize(engine, serialized) })
+        {
+            Ok(module) => Some(module),
+            Err(e) => {
+                warn!("failed to load cached compiled module, compiling it instead: {e:#}");
+                None
+            }
+        }
+    }
+
+    /// Seals `module`, which was compiled from `webasm`, and writes it to the host.
+    pub fn store(&mut self, webasm: &[u8], module: &Module) -> Result<()> {
+        let serialized = module
+            .serialize()
+            .context("failed to serialize compiled module")?;
+        let sealed = self.sealer.seal(webasm, &serialized)?;
+        self.out
+            .write_all(&sealed)
+            .context("failed to write sealed compiled module")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealing() {
+        let sealer = Sealer::new(b"secret").unwrap();
+        let webasm = b"\0asm\x01\0\0\0";
+        let sealed = sealer.seal(webasm, b"compiled").unwrap();
+        assert_eq!(sealer.unseal(sealed.clone(), webasm).unwrap(), b"compiled");
+
+        // Another platform cannot unseal it.
+        let other = Sealer::new(b"other").unwrap();
+        assert!(other.unseal(sealed.clone(), webasm).is_err());
+
+        // Neither can a keep running another module.
+        assert!(sealer.unseal(sealed.clone(), b"\0asm").is_err());
+
+        // Even with a forged header, the key of another module does not open it.
+        let other = b"\0asm\x01\0\0\0\0";
+        let mut forged = sealed.clone();
+        forged[MAGIC.len()..MAGIC.len() + 32].copy_from_slice(&Sha256::digest(other));
+        assert!(sealer.unseal(forged, other).is_err());
+
+        // The nonce and the module are authenticated.
+        let mut tampered = sealed.clone();
+        tampered[HEADER_LEN] ^= 1;
+        assert!(sealer.unseal(tampered, webasm).is_err());
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(sealer.unseal(tampered, webasm).is_err());
+
+        assert!(sealer
+            .unseal(sealed[..HEADER_LEN].to_vec(), webasm)
+            .is_err());
+        assert!(sealer.unseal(webasm.to_vec(), webasm).is_err());
+    }
+}
@@ -0,0 +1,79 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Provisioning of the secrets bundle of the workload
+//!
+//! The bundle is fetched after attestation with the certificate of the keep as the TLS client
+//! certificate, such that the server can release the secrets to attested keeps only.
+
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use rustls::ClientConfig;
+use ureq::serde_json;
+use url::Url;
+use zeroize::Zeroizing;
+
+/// Maximum size of the secrets bundle in bytes
+const MAX_BUNDLE_SIZE: u64 = 1_000_000;
+
+/// The secrets of the workload by name
+pub type Secrets = BTreeMap<String, Zeroizing<String>>;
+
+/// Fetches the secrets bundle from `url` authenticating with the client certificate of `cltcfg`.
+pub fn fetch(url: &Url, cltcfg: Arc<ClientConfig>) -> Result<Secrets> {
+    if url.scheme() != "https" {
+        bail!("refusing to use an unencrypted secrets url");
+    }
+
+    let response = ureq::AgentBuilder::new()
+        .tls_config(cltcfg)
+        .build()
+        .get(url.as_str())
+        .call()
+        .map_err(|e| match e {
+            ureq::Error::Status(status, response) => anyhow!(
+                "secrets server rejected the keep with status {status}: {}",
+                response.into_string().unwrap_or_default()
+            ),
+            e => anyhow::Error::from(e),
+        })?;
+
+    let mut body = Zeroizing::new(Vec::new());
+    response
+        .into_reader()
+        .take(MAX_BUNDLE_SIZE + 1)
+        .read_to_end(&mut body)
+        .context("failed to read secrets bundle")?;
+    if body.len() as u64 > MAX_BUNDLE_SIZE {
+        bail!("secrets bundle exceeds {MAX_BUNDLE_SIZE} bytes");
+    }
+    parse(&body)
+}
+
+/// Parses a bundle, which is a JSON object mapping the names of the secrets to their values.
+fn parse(body: &[u8]) -> Result<Secrets> {
+    let bundle: BTreeMap<String, String> =
+        serde_json::from_slice(body).context("invalid secrets bundle")?;
+    Ok(bundle
+        .into_iter()
+        .map(|(name, value)| (name, Zeroizing::new(value)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    #[test]
+    fn bundle() {
+        let secrets = parse(br#"{"db": "postgres://user:pass@db", "api": ""}"#).unwrap();
+        assert_eq!(secrets.len(), 2);
+        assert_eq!(secrets["db"].as_str(), "postgres://user:pass@db");
+        assert_eq!(secrets["api"].as_str(), "");
+
+        assert!(parse(br#"{"db": 1}"#).is_err());
+        assert!(parse(br#"["db"]"#).is_err());
+        assert!(parse(b"db=secret").is_err());
+    }
+}
@@ -0,0 +1,181 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Threads spawned by workloads built for `wasm32-wasi-threads` with the `wasi` `thread-spawn`
+//! import
+//!
+//! Every thread instantiates the module anew in a store of its own, which shares the linear
+//! memories of the module, and calls its `wasi_thread_start` export. The WASI context of a thread
+//! is not shared with the module though, so a thread has the same clocks, but none of the files,
+//! the arguments or the environment variables of the module: its calls on them fail with `EBADF`.
+//!
+//! The fuel of the module is a budget shared by all of its threads: a spawned thread gets half of
+//! the fuel left to the spawning one. At most [`MAX_THREADS`] threads of a module run at a time.
+//!
+//! The runtime does not implement `memory.atomic.wait32`, `memory.atomic.wait64` and
+//! `memory.atomic.notify` yet, so modules using them are refused by [`check`] instead of trapping
+//! once e.g. a mutex is contended.
+//!
+//! Keeps, which cannot create threads, i.e. the ones on the shims of hardware backends, fail to
+//! spawn them, which `pthread_create()` reports as `EAGAIN`.
+
+use super::attested::Setup;
+use super::Ctx;
+
+use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, error};
+use wasmparser::{Operator, Parser, Payload};
+use wasmtime::{Caller, ExternType, Linker, Module, Store, TypedFunc, ValType};
+
+/// Name of the export, which a spawned thread starts executing at
+const START: &str = "wasi_thread_start";
+
+/// The largest ID of a thread, which leaves the upper bits of the ID to the mutexes of wasi-libc
+const MAX_TID: i32 = 0x1FFF_FFFF;
+
+/// The ID of the next thread, IDs are positive
+static NEXT_TID: AtomicI32 = AtomicI32::new(1);
+
+/// The maximum number of threads spawned by a module, which run at the same time
+pub const MAX_THREADS: usize = 64;
+
+/// Refuses `wasm`, if it waits on or notifies other threads, which the runtime does not support.
+pub(super) fn check(wasm: &[u8]) -> Result<()> {
+    for payload in Parser::new(0).parse_all(wasm) {
+        let body = match payload? {
+            Payload::CodeSectionEntry(body) => body,
+            _ => continue,
+        };
+        for op in body.get_operators_reader()? {
+            match op? {
+                Operator::MemoryAtomicWait32 { .. } => {}
+                Operator::MemoryAtomicWait64 { .. } => {}
+                Operator::MemoryAtomicNotify { .. } => {}
+                _ => continue,
+            }
+            bail!(
+                "module uses `memory.atomic.wait` or `memory.atomic.notify`, which are not supported"
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The start function of a thread, `wasi_thread_start(tid, start_arg)`
+type Start = TypedFunc<(i32, i32), ()>;
+
+/// Decrements the number of running threads of a module once dropped
+struct Running(Arc<AtomicUsize>);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Spawns the threads of a module
+pub(super) struct Spawner {
+    setup: Arc<Setup>,
+    /// Linker of the module including its shared memories, but not `thread-spawn`
+    linker: Linker<Ctx>,
+    module: Module,
+    /// The number of running threads of the module
+    running: Arc<AtomicUsize>,
+}
+
+impl Spawner {
+    pub fn new(setup: Arc<Setup>, linker: Linker<Ctx>, module: Module) -> Self {
+        Self {
+            setup,
+            linker,
+            module,
+            running: Default::default(),
+        }
+    }
+
+    /// Spawns a thread calling the start function of the module with `start_arg` on behalf of
+    /// `caller` and returns its ID.
+    fn spawn(self: &Arc<Self>, caller: &mut Caller<'_, Ctx>, start_arg: i32) -> Result<i32> {
+        match self.module.get_export(START) {
+            Some(ExternType::Func(ty))
+                if ty.params().eq([ValType::I32, ValType::I32]) && ty.results().len() == 0 => {}
+            _ => bail!("module does not export `{START}` of type `(i32, i32) -> ()`"),
+        }
+
+        self.running
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |running| {
+                (running < MAX_THREADS).then_some(running + 1)
+            })
+            .map_err(|_| anyhow!("module already runs {MAX_THREADS} threads"))?;
+        let running = Running(self.running.clone());
+
+        let tid = NEXT_TID
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |tid| {
+                (tid <= MAX_TID).then_some(tid + 1)
+            })
+            .map_err(|_| anyhow!("thread IDs are exhausted"))?;
+
+        // The thread gets half of the fuel left to the caller, which is returned on failure.
+        let fuel = match caller.fuel_consumed() {
+            Some(_) => {
+                let fuel = caller.consume_fuel(0)? / 2;
+                caller.consume_fuel(fuel)?;
+                Some(fuel)
+            }
+            None => None,
+        };
+        let spawned = self.instantiate(fuel).and_then(|(mut wstore, start)| {
+            thread::Builder::new()
+                .name(format!("wasi-thread-{tid}"))
+                .spawn(move || {
+                    let _running = running;
+                    if let Err(trap) = start.call(&mut wstore, (tid, start_arg)) {
+                        match trap.i32_exit_status() {
+                            Some(0) => {}
+                            _ => error!("thread {tid} failed: {trap}"),
+                        }
+                    }
+                })
+                .context("failed to create thread")
+        });
+        if let (Err(_), Some(fuel)) = (&spawned, fuel) {
+            caller.add_fuel(fuel)?;
+        }
+        spawned?;
+        Ok(tid)
+    }
+
+    /// Instantiates the module in a new store with `fuel` and returns its start function.
+    fn instantiate(self: &Arc<Self>, fuel: Option<u64>) -> Result<(Store<Ctx>, Start)> {
+        let mut wstore = self.setup.store_with_fuel(fuel)?;
+        let mut linker = self.linker.clone();
+        add_to_linker(&mut linker, self.clone())?;
+        let start = linker
+            .instantiate(&mut wstore, &self.module)
+            .and_then(|instance| instance.get_typed_func::<(i32, i32), (), _>(&mut wstore, START))
+            .context("failed to instantiate thread")?;
+        Ok((wstore, start))
+    }
+}
+
+/// Adds the `wasi` `thread-spawn` import spawning threads with `spawner` to `linker`.
+///
+/// A failure to spawn a thread is reported to the workload as a negative ID.
+pub(super) fn add_to_linker(linker: &mut Linker<Ctx>, spawner: Arc<Spawner>) -> Result<()> {
+    linker.func_wrap(
+        "wasi",
+        "thread-spawn",
+        move |mut caller: Caller<'_, Ctx>, start_arg: i32| -> i32 {
+            match spawner.spawn(&mut caller, start_arg) {
+                Ok(tid) => tid,
+                Err(e) => {
+                    debug!("failed to spawn thread: {e:#}");
+                    -1
+                }
+            }
+        },
+    )?;
+    Ok(())
+}
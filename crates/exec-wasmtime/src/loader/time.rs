@@ -0,0 +1,451 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Sources of the system clock of the workload
+//!
+//! Every source but the clock of the host advances with the monotonic clock of the keep from the
+//! time it starts at, which is the Unix epoch, a fixed time or the time authenticated by a
+//! Roughtime server.
+//!
+//! The monotonic clock of the keep is controlled by the host, so only the time a clock is anchored
+//! at is authenticated by the Roughtime server, which the host can skew the clock from afterwards.
+//! A Roughtime clock is therefore anchored at a freshly authenticated time every
+//! [`RESYNC_INTERVAL`], which bounds the skew to what the host accumulates within the interval.
+
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, ensure, Context, Result};
+use enarx_config::Time;
+use log::{info, warn};
+use ring::digest::{Context as Digest, SHA512};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{UnparsedPublicKey, ED25519};
+use wasi_common::clocks::WasiSystemClock;
+
+/// Size of the Roughtime requests, which are padded, such that responses are never larger
+const REQUEST_SIZE: usize = 1024;
+
+/// Size of the nonce of the Roughtime requests
+const NONCE_SIZE: usize = 64;
+
+/// Number of Roughtime requests sent before giving up
+const ATTEMPTS: usize = 3;
+
+/// Time to wait for a Roughtime response
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Context of the signature of the delegated key by the long-term key of the server
+const DELEGATION_CONTEXT: &[u8] = b"RoughTime v1 delegation signature--\0";
+
+/// Context of the signature of the response by the delegated key
+const RESPONSE_CONTEXT: &[u8] = b"RoughTime v1 response signature\0";
+
+/// Interval, after which the time of a Roughtime clock is authenticated again
+const RESYNC_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Interval, after which a failed authentication of the time of a Roughtime clock is retried
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A time and the instant of the monotonic clock it was taken at
+#[derive(Copy, Clone, Debug)]
+struct Anchor {
+    start: SystemTime,
+    instant: Instant,
+}
+
+impl Anchor {
+    fn new(start: SystemTime) -> Self {
+        Self {
+            start,
+            instant: Instant::now(),
+        }
+    }
+
+    fn now(&self) -> SystemTime {
+        self.start + self.instant.elapsed()
+    }
+}
+
+/// The Roughtime server authenticating the time of a clock
+#[derive(Debug)]
+struct Resync {
+    server: String,
+    key: String,
+    /// Instant of the monotonic clock, at which the time is authenticated next
+    next: Mutex<Instant>,
+}
+
+impl Resync {
+    /// Anchors `anchor` at a freshly authenticated time, once due.
+    ///
+    /// Only a single thread queries the server, while the others keep reading the current anchor.
+    fn poll(&self, anchor: &Mutex<Anchor>) {
+        let mut next = match self.next.try_lock() {
+            Ok(next) if Instant::now() >= *next => next,
+            _ => return,
+        };
+        match roughtime(&self.server, &self.key) {
+            Ok(start) => {
+                let mut anchor = anchor.lock().unwrap();
+                let now = anchor.now();
+                let skew = now
+                    .duration_since(start)
+                    .or_else(|_| start.duration_since(now))
+                    .unwrap_or_default();
+                info!(
+                    "clock skewed by {skew:?} from the time authenticated by Roughtime server `{}`",
+                    self.server
+                );
+                *anchor = Anchor::new(start);
+                *next = Instant::now() + RESYNC_INTERVAL;
+            }
+            Err(e) => {
+                warn!(
+                    "failed to authenticate the time again, retrying in {RETRY_INTERVAL:?}: {e:#}"
+                );
+                *next = Instant::now() + RETRY_INTERVAL;
+            }
+        }
+    }
+}
+
+/// A system clock advancing with the monotonic clock from the time it is anchored at
+///
+/// A clock authenticated by a Roughtime server is anchored at a freshly authenticated time by the
+/// first read after every [`RESYNC_INTERVAL`], which blocks the reading thread for the query.
+#[derive(Clone, Debug)]
+pub struct Anchored {
+    anchor: Arc<Mutex<Anchor>>,
+    resync: Option<Arc<Resync>>,
+}
+
+impl Anchored {
+    pub fn new(start: SystemTime) -> Self {
+        Self {
+            anchor: Arc::new(Mutex::new(Anchor::new(start))),
+            resync: None,
+        }
+    }
+
+    /// Returns a clock starting at `start` authenticated by the Roughtime `server` with `key`,
+    /// which authenticates its time again periodically.
+    fn authenticated(start: SystemTime, server: &str, key: &str) -> Self {
+        Self {
+            resync: Some(Arc::new(Resync {
+                server: server.into(),
+                key: key.into(),
+                next: Mutex::new(Instant::now() + RESYNC_INTERVAL),
+            })),
+            ..Self::new(start)
+        }
+    }
+}
+
+impl WasiSystemClock for Anchored {
+    fn resolution(&self) -> Duration {
+        Duration::from_nanos(1)
+    }
+
+    fn now(&self, _precision: Duration) -> cap_std::time::SystemTime {
+        if let Some(resync) = &self.resync {
+            resync.poll(&self.anchor);
+        }
+        cap_std::time::SystemTime::from_std(self.anchor.lock().unwrap().now())
+    }
+}
+
+/// Returns the system clock of `time`, `None` for the clock of the host.
+pub fn clock(time: &Time) -> Result<Option<Anchored>> {
+    let start = match time {
+        Time::Host => return Ok(None),
+        Time::Monotonic => UNIX_EPOCH,
+        Time::Fixed { epoch } => UNIX_EPOCH
+            .checked_add(Duration::from_secs(*epoch))
+            .ok_or_else(|| anyhow!("`time.epoch` {epoch} is out of range"))?,
+        Time::Roughtime { server, key } => {
+            let start = roughtime(server, key)
+                .with_context(|| format!("failed to query Roughtime server `{server}`"))?;
+            return Ok(Some(Anchored::authenticated(start, server, key)));
+        }
+    };
+    Ok(Some(Anchored::new(start)))
+}
+
+/// Returns the time authenticated by the Roughtime `server` with the base64-encoded `key`.
+fn roughtime(server: &str, key: &str) -> Result<SystemTime> {
+    let key = base64::decode(key).context("invalid Roughtime key")?;
+    ensure!(
+        key.len() == 32,
+        "Roughtime key is not an Ed25519 public key"
+    );
+
+    let mut nonce = [0; NONCE_SIZE];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| anyhow!("failed to generate nonce"))?;
+    let request = request(&nonce);
+
+    let addr = server
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| anyhow!("failed to resolve address"))?;
+    let socket = match addr {
+        addr if addr.is_ipv4() => UdpSocket::bind("0.0.0.0:0"),
+        _ => UdpSocket::bind("[::]:0"),
+    }?;
+    socket.set_read_timeout(Some(TIMEOUT))?;
+
+    let mut buf = [0; 4096];
+    for _ in 0..ATTEMPTS {
+        socket.send_to(&request, addr)?;
+        match socket.recv_from(&mut buf) {
+            Ok((n, from)) if from == addr => {
+                let (midpoint, radius) = verify(&buf[..n], &nonce, &key)?;
+                info!("Roughtime server `{server}` authenticated the time within {radius:?}");
+                return Ok(midpoint);
+            }
+            Ok((_, from)) => warn!("ignoring Roughtime response from `{from}`"),
+            Err(e) => warn!("no Roughtime response, retrying: {e}"),
+        }
+    }
+    Err(anyhow!("no response after {ATTEMPTS} attempts"))
+}
+
+/// Returns the request for `nonce`.
+fn request(nonce: &[u8; NONCE_SIZE]) -> Vec<u8> {
+    // The header of a message of two values is 16 bytes.
+    let pad = [0; REQUEST_SIZE - 16 - NONCE_SIZE];
+    encode(&mut [(*b"NONC", nonce.as_slice()), (*b"PAD\xff", &pad)])
+}
+
+/// Verifies `response` to the request with `nonce` with the long-term `key` of the server and
+/// returns the midpoint and the radius of the authenticated time.
+fn verify(response: &[u8], nonce: &[u8], key: &[u8]) -> Result<(SystemTime, Duration)> {
+    let response = Message::parse(response)?;
+
+    // The long-term key delegates signing to an online key for a limited time.
+    let cert = Message::parse(response.get(b"CERT")?)?;
+    let dele = cert.get(b"DELE")?;
+    UnparsedPublicKey::new(&ED25519, key)
+        .verify(&[DELEGATION_CONTEXT, dele].concat(), cert.get(b"SIG\0")?)
+        .map_err(|_| anyhow!("invalid delegation signature"))?;
+    let dele = Message::parse(dele)?;
+
+    let srep = response.get(b"SREP")?;
+    UnparsedPublicKey::new(&ED25519, dele.get(b"PUBK")?)
+        .verify(&[RESPONSE_CONTEXT, srep].concat(), response.get(b"SIG\0")?)
+        .map_err(|_| anyhow!("invalid response signature"))?;
+    let srep = Message::parse(srep)?;
+
+    // The signed root of the Merkle tree of the nonces of a batch of requests includes the nonce.
+    let path = response.get(b"PATH")?;
+    ensure!(path.len() % 64 == 0, "invalid Merkle tree path");
+    let mut index = u32::from_le_bytes(fixed(response.get(b"INDX")?)?);
+    let mut hash = sha512(&[&[0], nonce]);
+    for sibling in path.chunks(64) {
+        hash = match index & 1 {
+            0 => sha512(&[&[1], &hash, sibling]),
+            _ => sha512(&[&[1], sibling, &hash]),
+        };
+        index >>= 1;
+    }
+    ensure!(
+        hash == srep.get(b"ROOT")?,
+        "response does not include the nonce"
+    );
+
+    let midpoint = u64::from_le_bytes(fixed(srep.get(b"MIDP")?)?);
+    let radius = u32::from_le_bytes(fixed(srep.get(b"RADI")?)?);
+    let min = u64::from_le_bytes(fixed(dele.get(b"MINT")?)?);
+    let max = u64::from_le_bytes(fixed(dele.get(b"MAXT")?)?);
+    ensure!(
+        (min..=max).contains(&midpoint),
+        "time is outside of the validity of the delegated key"
+    );
+    let midpoint = UNIX_EPOCH
+        .checked_add(Duration::from_micros(midpoint))
+        .ok_or_else(|| anyhow!("time is out of range"))?;
+    Ok((midpoint, Duration::from_micros(radius.into())))
+}
+
+/// Returns the SHA-512 digest of the concatenation of `parts`.
+fn sha512(parts: &[&[u8]]) -> Vec<u8> {
+    let mut digest = Digest::new(&SHA512);
+    parts.iter().for_each(|part| digest.update(part));
+    digest.finish().as_ref().into()
+}
+
+/// Returns `value` as an array, failing if its size differs.
+fn fixed<const N: usize>(value: &[u8]) -> Result<[u8; N]> {
+    value
+        .try_into()
+        .map_err(|_| anyhow!("Roughtime value is not {N} bytes"))
+}
+
+/// Returns the message of `values`, which are sorted by their tags.
+fn encode(values: &mut [([u8; 4], &[u8])]) -> Vec<u8> {
+    values.sort_by_key(|(tag, _)| u32::from_le_bytes(*tag));
+    let mut message = (values.len() as u32).to_le_bytes().to_vec();
+    let mut offset = 0;
+    for (_, value) in values.iter().take(values.len().saturating_sub(1)) {
+        offset += value.len() as u32;
+        message.extend(offset.to_le_bytes());
+    }
+    for (tag, _) in values.iter() {
+        message.extend(tag);
+    }
+    for (_, value) in values.iter() {
+        message.extend(*value);
+    }
+    message
+}
+
+/// A Roughtime message, which maps tags to values
+struct Message<'a>(Vec<([u8; 4], &'a [u8])>);
+
+impl<'a> Message<'a> {
+    fn parse(bytes: &'a [u8]) -> Result<Self> {
+        let word = |i: usize| -> Result<u32> {
+            let word = bytes
+                .get(4 * i..4 * i + 4)
+                .ok_or_else(|| anyhow!("truncated Roughtime message"))?;
+            Ok(u32::from_le_bytes(fixed(word)?))
+        };
+
+        // The header consists of the number of values, the offsets of all values but the first
+        // and the tags of all values.
+        let n = word(0)? as usize;
+        ensure!(
+            n > 0 && n <= bytes.len() / 8,
+            "invalid number of Roughtime values"
+        );
+        let values = &bytes[8 * n..];
+        let mut offsets = vec![0];
+        for i in 1..n {
+            offsets.push(word(i)? as usize);
+        }
+        offsets.push(values.len());
+
+        let mut message = Vec::with_capacity(n);
+        for (i, bounds) in offsets.windows(2).enumerate() {
+            ensure!(
+                bounds[0] <= bounds[1] && bounds[1] <= values.len() && bounds[0] % 4 == 0,
+                "invalid Roughtime value offset"
+            );
+            let tag = word(n + i)?.to_le_bytes();
+            message.push((tag, &values[bounds[0]..bounds[1]]));
+        }
+        Ok(Self(message))
+    }
+
+    /// Returns the value of `tag`.
+    fn get(&self, tag: &[u8; 4]) -> Result<&'a [u8]> {
+        self.0
+            .iter()
+            .find(|(t, _)| t == tag)
+            .map(|(_, value)| *value)
+            .ok_or_else(|| anyhow!("Roughtime message lacks `{}`", tag.escape_ascii()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        encode, request, sha512, verify, Anchored, Message, DELEGATION_CONTEXT, RESPONSE_CONTEXT,
+        RETRY_INTERVAL,
+    };
+
+    use std::net::UdpSocket;
+    use std::time::{Duration, Instant, UNIX_EPOCH};
+
+    use wasi_common::clocks::WasiSystemClock;
+
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    fn keypair() -> Ed25519KeyPair {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap()
+    }
+
+    /// Returns the response of a server with the long-term key `root` to the request with
+    /// `nonce`, batched with another request.
+    fn response(root: &Ed25519KeyPair, nonce: &[u8], midpoint: u64) -> Vec<u8> {
+        let online = keypair();
+        let (min, max) = (0u64.to_le_bytes(), u64::MAX.to_le_bytes());
+        let dele = encode(&mut [
+            (*b"PUBK", online.public_key().as_ref()),
+            (*b"MINT", &min),
+            (*b"MAXT", &max),
+        ]);
+        let sig = root.sign(&[DELEGATION_CONTEXT, &dele].concat());
+        let cert = encode(&mut [(*b"DELE", &dele), (*b"SIG\0", sig.as_ref())]);
+
+        let sibling = sha512(&[&[0], &[1; 64]]);
+        let tree = sha512(&[&[1], &sha512(&[&[0], nonce]), &sibling]);
+        let (midpoint, radius) = (midpoint.to_le_bytes(), 1_000_000u32.to_le_bytes());
+        let srep = encode(&mut [
+            (*b"ROOT", &tree),
+            (*b"MIDP", &midpoint),
+            (*b"RADI", &radius),
+        ]);
+        let sig = online.sign(&[RESPONSE_CONTEXT, &srep].concat());
+        encode(&mut [
+            (*b"SIG\0", sig.as_ref()),
+            (*b"PATH", &sibling),
+            (*b"SREP", &srep),
+            (*b"CERT", &cert),
+            (*b"INDX", &0u32.to_le_bytes()),
+        ])
+    }
+
+    #[test]
+    fn roughtime() {
+        let nonce = [7; 64];
+        let request = request(&nonce);
+        assert_eq!(request.len(), 1024);
+        assert_eq!(
+            Message::parse(&request).unwrap().get(b"NONC").unwrap(),
+            nonce
+        );
+
+        let root = keypair();
+        let key = root.public_key().as_ref();
+        let response = response(&root, &nonce, 1_672_531_200_000_000);
+        let (midpoint, radius) = verify(&response, &nonce, key).unwrap();
+        assert_eq!(midpoint, UNIX_EPOCH + Duration::from_secs(1_672_531_200));
+        assert_eq!(radius, Duration::from_secs(1));
+
+        // Responses to other requests or signed by other servers are rejected.
+        assert!(verify(&response, &[8; 64], key).is_err());
+        assert!(verify(&response, &nonce, keypair().public_key().as_ref()).is_err());
+        let mut tampered = response;
+        let len = tampered.len();
+        tampered[len - 5] ^= 1;
+        assert!(verify(&tampered, &nonce, key).is_err());
+
+        assert!(Message::parse(&[]).is_err());
+        assert!(Message::parse(&[2, 0, 0, 0, 64, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn resync() {
+        // The server never responds.
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        let start = UNIX_EPOCH + Duration::from_secs(1_672_531_200);
+        let clock = Anchored::authenticated(start, &addr, &base64::encode([0; 32]));
+
+        // The time is not authenticated again before the interval passed.
+        let resync = clock.resync.clone().unwrap();
+        let now = clock.now(Duration::ZERO).into_std();
+        assert!(now >= start && now < start + Duration::from_secs(1));
+
+        // A failed authentication keeps the anchor and is retried later.
+        *resync.next.lock().unwrap() = Instant::now();
+        let now = clock.now(Duration::ZERO).into_std();
+        assert!(now >= start + super::TIMEOUT);
+        let next = *resync.next.lock().unwrap();
+        assert!(next > Instant::now() + RETRY_INTERVAL - Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,184 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Clocks of the workload read from the time page of the shim
+//!
+//! The shim publishes every sample it takes of the host clocks to a page mapped read-only into the
+//! exec, akin to the data page of the Linux vDSO, such that reading the clocks extrapolates the last
+//! sample with the TSC without trapping into the shim. Only once the sample is too old, the clocks
+//! are read via `clock_gettime`, which takes and publishes a new sample.
+//!
+//! Values read from the page are guarded against going backwards across samples, whenever the TSC
+//! runs faster than the host clocks.
+
+use std::arch::x86_64::_rdtsc;
+use std::env;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use once_cell::sync::Lazy;
+use sallyport::guest::{Guard, TimePage, TIME_PAGE_ENV};
+use sallyport::libc::{clockid_t, CLOCK_MONOTONIC, CLOCK_REALTIME};
+use wasi_common::clocks::{WasiMonotonicClock, WasiSystemClock};
+
+/// The time page mapped by the shim, if any
+static PAGE: Lazy<Option<Page>> = Lazy::new(|| {
+    let addr = env::var(TIME_PAGE_ENV).ok()?;
+    let addr = usize::from_str_radix(&addr, 16).ok()?;
+    // Safety: the shim maps the page read-only at `addr` for the lifetime of the exec.
+    let page = unsafe { (addr as *const TimePage).as_ref()? };
+    Some(Page::new(page))
+});
+
+/// Returns `clockid` via `clock_gettime` in nanoseconds.
+fn gettime(clockid: clockid_t) -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // Safety: `ts` is a valid `timespec` and `clockid` is supported on Linux.
+    let ret = unsafe { libc::clock_gettime(clockid, &mut ts) };
+    assert_eq!(ret, 0, "failed to read clock {clockid}");
+    (ts.tv_sec as u64)
+        .saturating_mul(1_000_000_000)
+        .saturating_add(ts.tv_nsec as u64)
+}
+
+/// A time page and the latest values read from it
+struct Page {
+    page: &'static TimePage,
+    guard: Guard,
+    /// Instant of the monotonic clock at `CLOCK_MONOTONIC` of `base` nanoseconds
+    instant: Instant,
+    base: u64,
+}
+
+impl Page {
+    fn new(page: &'static TimePage) -> Self {
+        let instant = Instant::now();
+        let base = gettime(CLOCK_MONOTONIC);
+        Self {
+            page,
+            guard: Guard::new(),
+            instant,
+            base,
+        }
+    }
+
+    /// Returns `clockid` in nanoseconds.
+    fn read(&self, clockid: clockid_t) -> u64 {
+        // Safety: the TSC is available on every x86_64 CPU.
+        let tsc = unsafe { _rdtsc() };
+        let ns = self
+            .page
+            .read(clockid, tsc)
+            .unwrap_or_else(|| gettime(clockid));
+        self.guard.apply(clockid, ns)
+    }
+
+    fn system(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_nanos(self.read(CLOCK_REALTIME))
+    }
+
+    fn monotonic(&self) -> Instant {
+        let ns = self.read(CLOCK_MONOTONIC).saturating_sub(self.base);
+        self.instant + Duration::from_nanos(ns)
+    }
+}
+
+/// The system clock read from the time page
+pub struct SystemClock(&'static Page);
+
+impl WasiSystemClock for SystemClock {
+    fn resolution(&self) -> Duration {
+        Duration::from_nanos(1)
+    }
+
+    fn now(&self, _precision: Duration) -> cap_std::time::SystemTime {
+        cap_std::time::SystemTime::from_std(self.0.system())
+    }
+}
+
+/// The monotonic clock read from the time page
+pub struct MonotonicClock(&'static Page);
+
+impl WasiMonotonicClock for MonotonicClock {
+    fn resolution(&self) -> Duration {
+        Duration::from_nanos(1)
+    }
+
+    fn now(&self, _precision: Duration) -> cap_std::time::Instant {
+        cap_std::time::Instant::from_std(self.0.monotonic())
+    }
+}
+
+/// Returns the clocks read from the time page, `None` if the shim maps none.
+pub fn clocks() -> Option<(SystemClock, MonotonicClock)> {
+    let page = PAGE.as_ref()?;
+    Some((SystemClock(page), MonotonicClock(page)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{gettime, Page};
+
+    use std::arch::x86_64::_rdtsc;
+    use std::thread;
+    use std::time::{Duration, Instant, SystemTime};
+
+    use sallyport::guest::{Clock, TimePage};
+    use sallyport::libc::{timespec, CLOCK_MONOTONIC, CLOCK_REALTIME};
+
+    fn timespec(ns: u64) -> timespec {
+        timespec {
+            tv_sec: (ns / 1_000_000_000) as _,
+            tv_nsec: (ns % 1_000_000_000) as _,
+        }
+    }
+
+    /// Takes a sample of the host clocks like the shim, offsetting `CLOCK_REALTIME` by `skew`.
+    fn update(clock: &mut Clock, skew: i64) {
+        let realtime = (gettime(CLOCK_REALTIME) as i64 + skew) as u64;
+        let tsc = unsafe { _rdtsc() };
+        let monotonic = gettime(CLOCK_MONOTONIC);
+        clock.update(tsc, &timespec(realtime), &timespec(monotonic));
+    }
+
+    #[test]
+    fn page() {
+        let page = Box::leak(Box::new(Page::new(Box::leak(Box::new(TimePage::new())))));
+
+        // Without a sample, the clocks are read via `clock_gettime`.
+        let before = SystemTime::now();
+        let now = page.system();
+        assert!(before <= now && now <= SystemTime::now());
+        let before = Instant::now();
+        let now = page.monotonic();
+        let slack = Duration::from_millis(1);
+        assert!(before <= now + slack && now <= Instant::now() + slack);
+
+        // With a calibrated sample, the clocks are extrapolated with the TSC.
+        let mut clock = Clock::new();
+        update(&mut clock, 0);
+        thread::sleep(Duration::from_millis(10));
+        update(&mut clock, 0);
+        page.page.publish(&clock.sample());
+        assert!(page
+            .page
+            .read(CLOCK_REALTIME, unsafe { _rdtsc() })
+            .is_some());
+
+        let now = page.system();
+        let diff = match SystemTime::now().duration_since(now) {
+            Ok(diff) => diff,
+            Err(e) => e.duration(),
+        };
+        assert!(diff < Duration::from_millis(100), "{diff:?}");
+        let before = page.monotonic();
+        thread::sleep(Duration::from_millis(10));
+        assert!(page.monotonic() >= before + Duration::from_millis(5));
+
+        // A sample behind the values read before does not take the clocks backwards.
+        let before = page.system();
+        update(&mut clock, -50_000_000);
+        page.page.publish(&clock.sample());
+        assert!(page.system() >= before);
+    }
+}
@@ -5,7 +5,7 @@
 #![deny(clippy::all)]
 #![warn(rust_2018_idioms)]
 
-use enarx_exec_wasmtime::execute;
+use enarx_exec_wasmtime::{execute, ExitCode};
 
 /// Set FSBASE
 ///
@@ -36,5 +36,12 @@ pub extern "C" fn __set_thread_area(p: *mut core::ffi::c_void) -> core::ffi::c_i
 fn main() -> anyhow::Result<()> {
     env_logger::Builder::from_default_env().init();
 
-    execute()
+    // Exit with the exit code of the workload, such that the host can report it.
+    match execute() {
+        Err(e) => match e.downcast_ref::<ExitCode>() {
+            Some(&ExitCode(code)) => std::process::exit(code),
+            None => Err(e),
+        },
+        Ok(()) => Ok(()),
+    }
 }
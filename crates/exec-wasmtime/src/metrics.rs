@@ -0,0 +1,328 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Metrics of the running workload reported to the host and rendered in the Prometheus text format
+
+use crate::loader::Platform;
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use ureq::serde_json;
+
+/// Interval at which the metrics are reported to the host
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Interval at which the reporter checks whether it should stop
+const TICK: Duration = Duration::from_millis(10);
+
+/// The metrics recorded by the workload since its start
+pub(crate) static METRICS: Lazy<Mutex<Metrics>> = Lazy::new(Default::default);
+
+/// Number of bytes transferred on a network stream
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Traffic {
+    /// Number of bytes received from the peer
+    pub received: u64,
+    /// Number of bytes sent to the peer
+    pub sent: u64,
+    /// Number of connections accepted, if the stream is a listener
+    #[serde(default)]
+    pub connections: u64,
+}
+
+/// A snapshot of the metrics of a workload
+///
+/// Snapshots are written by exec-wasmtime to the host as single lines of JSON.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Metrics {
+    /// Number of exits to the host
+    pub exits: u64,
+    /// Number of bytes passed through the sallyport block
+    pub bytes: u64,
+    /// Number of sallyport calls handled, indexed by syscall number
+    pub syscalls: BTreeMap<usize, u64>,
+    /// Traffic of the network streams, indexed by the name of the file in `Enarx.toml`
+    pub streams: BTreeMap<String, Traffic>,
+    /// Fuel consumed by the workload, if fuel consumption is enabled
+    ///
+    /// This is only updated once the workload returns.
+    pub fuel_consumed: Option<u64>,
+    /// Size of the linear memories of the workload in bytes
+    pub memory: u64,
+    /// Number of failed TLS handshakes
+    pub tls_handshake_failures: u64,
+}
+
+impl Metrics {
+    /// Returns the traffic counters of the stream `name`.
+    pub(crate) fn stream(&mut self, name: &str) -> &mut Traffic {
+        if !self.streams.contains_key(name) {
+            self.streams.insert(name.into(), Traffic::default());
+        }
+        self.streams.get_mut(name).unwrap()
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        describe(
+            &mut out,
+            "enarx_sallyport_exits_total",
+            "counter",
+            "Number of exits from the keep to the host.",
+        );
+        writeln!(out, "enarx_sallyport_exits_total {}", self.exits).unwrap();
+
+        describe(
+            &mut out,
+            "enarx_sallyport_bytes_total",
+            "counter",
+            "Number of bytes passed through the sallyport block.",
+        );
+        writeln!(out, "enarx_sallyport_bytes_total {}", self.bytes).unwrap();
+
+        describe(
+            &mut out,
+            "enarx_sallyport_calls_total",
+            "counter",
+            "Number of sallyport calls handled by syscall number.",
+        );
+        for (nr, count) in &self.syscalls {
+            writeln!(
+                out,
+                "enarx_sallyport_calls_total{{syscall=\"{nr}\"}} {count}"
+            )
+            .unwrap();
+        }
+
+        describe(
+            &mut out,
+            "enarx_stream_received_bytes_total",
+            "counter",
+            "Number of bytes received on a network stream.",
+        );
+        for (name, traffic) in &self.streams {
+            let name = escape(name);
+            writeln!(
+                out,
+                "enarx_stream_received_bytes_total{{stream=\"{name}\"}} {}",
+                traffic.received
+            )
+            .unwrap();
+        }
+
+        describe(
+            &mut out,
+            "enarx_stream_sent_bytes_total",
+            "counter",
+            "Number of bytes sent on a network stream.",
+        );
+        for (name, traffic) in &self.streams {
+            let name = escape(name);
+            writeln!(
+                out,
+                "enarx_stream_sent_bytes_total{{stream=\"{name}\"}} {}",
+                traffic.sent
+            )
+            .unwrap();
+        }
+
+        describe(
+            &mut out,
+            "enarx_stream_connections_total",
+            "counter",
+            "Number of connections accepted on a listen socket.",
+        );
+        for (name, traffic) in &self.streams {
+            let name = escape(name);
+            writeln!(
+                out,
+                "enarx_stream_connections_total{{stream=\"{name}\"}} {}",
+                traffic.connections
+            )
+            .unwrap();
+        }
+
+        if let Some(fuel) = self.fuel_consumed {
+            describe(
+                &mut out,
+                "enarx_wasm_fuel_consumed_total",
+                "counter",
+                "Fuel consumed by the workload.",
+            );
+            writeln!(out, "enarx_wasm_fuel_consumed_total {fuel}").unwrap();
+        }
+
+        describe(
+            &mut out,
+            "enarx_wasm_memory_bytes",
+            "gauge",
+            "Size of the linear memories of the workload in bytes.",
+        );
+        writeln!(out, "enarx_wasm_memory_bytes {}", self.memory).unwrap();
+
+        describe(
+            &mut out,
+            "enarx_tls_handshake_failures_total",
+            "counter",
+            "Number of failed TLS handshakes.",
+        );
+        writeln!(
+            out,
+            "enarx_tls_handshake_failures_total {}",
+            self.tls_handshake_failures
+        )
+        .unwrap();
+
+        out
+    }
+}
+
+/// Escapes `value` for use as a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Writes the description of the metric `name`.
+fn describe(out: &mut String, name: &str, typ: &str, help: &str) {
+    writeln!(out, "# HELP {name} {help}").unwrap();
+    writeln!(out, "# TYPE {name} {typ}").unwrap();
+}
+
+/// Returns a snapshot of the metrics recorded so far, including the sallyport counters of the keep.
+pub(crate) fn snapshot() -> io::Result<Metrics> {
+    let mut metrics = METRICS.lock().unwrap().clone();
+    Platform::collect(&mut metrics)?;
+    Ok(metrics)
+}
+
+/// Writes a snapshot of the metrics to `out` as a single line of JSON.
+fn report(out: &mut impl Write) -> io::Result<()> {
+    let mut line = serde_json::to_vec(&snapshot()?)?;
+    line.push(b'\n');
+    out.write_all(&line)
+}
+
+/// Reports the metrics to `out` periodically while `done` runs.
+///
+/// The reports are written by a separate thread, if one can be spawned.
+/// A final report is always written once `done` returns.
+pub(crate) fn report_while<T>(
+    out: &mut (impl Write + Send),
+    done: impl FnOnce() -> T,
+) -> io::Result<T> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let ret = thread::scope(|scope| {
+        let reporter = thread::Builder::new()
+            .name("reporter".into())
+            .spawn_scoped(scope, {
+                let stop = stop.clone();
+                let out = &mut *out;
+                move || loop {
+                    if let Err(e) = report(out) {
+                        log::warn!("failed to report metrics: {e}");
+                        return;
+                    }
+                    let start = Instant::now();
+                    while start.elapsed() < REPORT_INTERVAL {
+                        if stop.load(Ordering::Relaxed) {
+                            return;
+                        }
+                        thread::sleep(TICK);
+                    }
+                }
+            });
+        if let Err(e) = &reporter {
+            log::warn!("failed to spawn reporter thread, metrics other than the sallyport counters of the host are only reported on exit: {e}");
+        }
+        let ret = done();
+        stop.store(true, Ordering::Relaxed);
+        ret
+    });
+    report(out)?;
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{report_while, Metrics, Traffic};
+
+    use ureq::serde_json;
+
+    #[test]
+    fn reports() {
+        let mut out = vec![];
+        let ret = report_while(&mut out, || 42).unwrap();
+        assert_eq!(ret, 42);
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.ends_with('\n'));
+        for line in out.lines() {
+            serde_json::from_str::<Metrics>(line).unwrap();
+        }
+    }
+
+    #[test]
+    fn stream() {
+        let mut metrics = Metrics::default();
+        metrics.stream("web").received += 2;
+        metrics.stream("web").sent += 3;
+        assert_eq!(
+            metrics.streams["web"],
+            Traffic {
+                received: 2,
+                sent: 3,
+                connections: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn rendering() {
+        let metrics = Metrics {
+            exits: 3,
+            bytes: 4096,
+            syscalls: [(0, 2), (1, 1)].into(),
+            streams: [(
+                "web\"1\"".into(),
+                Traffic {
+                    received: 10,
+                    sent: 20,
+                    connections: 2,
+                },
+            )]
+            .into(),
+            fuel_consumed: Some(100),
+            memory: 65536,
+            tls_handshake_failures: 1,
+        };
+        let out = metrics.render();
+        let samples: Vec<_> = out.lines().filter(|l| !l.starts_with('#')).collect();
+        assert_eq!(
+            samples,
+            [
+                "enarx_sallyport_exits_total 3",
+                "enarx_sallyport_bytes_total 4096",
+                r#"enarx_sallyport_calls_total{syscall="0"} 2"#,
+                r#"enarx_sallyport_calls_total{syscall="1"} 1"#,
+                r#"enarx_stream_received_bytes_total{stream="web\"1\""} 10"#,
+                r#"enarx_stream_sent_bytes_total{stream="web\"1\""} 20"#,
+                r#"enarx_stream_connections_total{stream="web\"1\""} 2"#,
+                "enarx_wasm_fuel_consumed_total 100",
+                "enarx_wasm_memory_bytes 65536",
+                "enarx_tls_handshake_failures_total 1",
+            ]
+        );
+        assert!(!Metrics::default().render().contains("fuel"));
+    }
+}
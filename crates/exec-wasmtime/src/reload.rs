@@ -0,0 +1,196 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Updates of the network policy of the workload delivered by the host
+//!
+//! The host writes the updated `network` section of the config as a line of JSON to a pipe, whose
+//! read end is passed to the keep, e.g. once it is asked to reload the config. The pipe is read
+//! without blocking, whenever the policy is consulted, so the updates are applied by keeps which
+//! cannot create threads as well, and they take effect for all requests sent afterwards.
+//!
+//! An update may only narrow the policy of the config, unless the config allows widening it with
+//! `allow_widening`, which an update cannot change.
+
+use enarx_config::Network;
+#[cfg(unix)]
+use log::{info, warn};
+
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+#[cfg(unix)]
+use std::sync::Mutex;
+
+#[cfg(unix)]
+use rustix::fd::BorrowedFd;
+#[cfg(unix)]
+use rustix::io::{PollFd, PollFlags};
+#[cfg(unix)]
+use ureq::serde_json;
+
+/// Maximum size of an update
+#[cfg(unix)]
+const MAX_UPDATE_SIZE: usize = 64 * 1024;
+
+/// The updates read from the host
+#[cfg(unix)]
+struct Updates {
+    /// File descriptor of the host to read the updates from, if any
+    fd: Option<RawFd>,
+    /// The incomplete line of the update being read
+    buf: Vec<u8>,
+    /// The policy of the last accepted update, if any
+    current: Option<Network>,
+}
+
+#[cfg(unix)]
+static UPDATES: Mutex<Updates> = Mutex::new(Updates {
+    fd: None,
+    buf: Vec::new(),
+    current: None,
+});
+
+/// Reads the updates of the network policy from `fd` from now on.
+#[cfg(unix)]
+pub fn init(fd: RawFd) {
+    UPDATES.lock().unwrap().fd = Some(fd);
+}
+
+#[cfg(unix)]
+impl Updates {
+    /// Reads the pending updates and applies the last one allowed by the policy of the `config`.
+    fn read(&mut self, config: &Network) {
+        let fd = match self.fd {
+            // SAFETY: The file descriptor is managed by the host, so it is never closed.
+            Some(fd) => unsafe { BorrowedFd::borrow_raw(fd) },
+            None => return,
+        };
+        loop {
+            let mut fds = [PollFd::new(&fd, PollFlags::IN)];
+            match rustix::io::poll(&mut fds, 0) {
+                Ok(1) if !fds[0].revents().is_empty() => {}
+                _ => return,
+            }
+            let mut chunk = [0; 4096];
+            let n = match rustix::io::read(fd, &mut chunk) {
+                Ok(n) if n > 0 => n,
+                // The host closed the pipe or it failed, so there are no more updates.
+                _ => {
+                    self.fd = None;
+                    return;
+                }
+            };
+            self.buf.extend_from_slice(&chunk[..n]);
+            while let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+                let line: Vec<_> = self.buf.drain(..=end).collect();
+                self.apply(&line, config);
+            }
+            if self.buf.len() > MAX_UPDATE_SIZE {
+                warn!("network policy update exceeds {MAX_UPDATE_SIZE} bytes, ignoring it");
+                self.buf.clear();
+            }
+        }
+    }
+
+    /// Applies the update encoded in `line`, if the policy of the `config` allows it.
+    fn apply(&mut self, line: &[u8], config: &Network) {
+        let update = match serde_json::from_slice::<Network>(line) {
+            Ok(update) => Network {
+                allow_widening: config.allow_widening,
+                ..update
+            },
+            Err(e) => {
+                warn!("failed to decode network policy update, ignoring it: {e}");
+                return;
+            }
+        };
+        if !config.allow_widening && !config.includes(&update) {
+            warn!("network policy update allows more than the config, ignoring it");
+            return;
+        }
+        info!("updated network policy: {:?}", update.outgoing);
+        self.current = Some(update);
+    }
+}
+
+/// Returns the network policy of the workload, i.e. the one of the `config` updated by the host.
+pub fn network(config: &Network) -> Network {
+    #[cfg(unix)]
+    {
+        let mut updates = UPDATES.lock().unwrap();
+        updates.read(config);
+        if let Some(ref current) = updates.current {
+            return current.clone();
+        }
+    }
+    config.clone()
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::Updates;
+
+    use std::io::Write;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+
+    use enarx_config::Network;
+
+    #[test]
+    fn updates() {
+        let (mut host, keep) = UnixStream::pair().unwrap();
+        let mut updates = Updates {
+            fd: Some(keep.as_raw_fd()),
+            buf: vec![],
+            current: None,
+        };
+        let config = Network {
+            outgoing: vec!["api.example.com".into(), "storage.example.com".into()],
+            allow_widening: false,
+        };
+
+        // Nothing is pending.
+        updates.read(&config);
+        assert_eq!(updates.current, None);
+
+        // Incomplete updates are kept until the rest arrives.
+        host.write_all(br#"{"outgoing":["api.exa"#).unwrap();
+        updates.read(&config);
+        assert_eq!(updates.current, None);
+        host.write_all(b"mple.com\"]}\n").unwrap();
+        updates.read(&config);
+        assert_eq!(
+            updates.current.as_ref().unwrap().outgoing,
+            vec!["api.example.com"]
+        );
+
+        // Widening updates are rejected, also if they try to allow widening themselves.
+        host.write_all(b"{\"outgoing\":[\"*\"],\"allow_widening\":true}\n")
+            .unwrap();
+        host.write_all(b"not json\n").unwrap();
+        updates.read(&config);
+        assert_eq!(
+            updates.current.as_ref().unwrap().outgoing,
+            vec!["api.example.com"]
+        );
+
+        // Reverting to the policy of the config is no widening.
+        host.write_all(b"{\"outgoing\":[\"storage.example.com\",\"api.example.com\"]}\n")
+            .unwrap();
+        updates.read(&config);
+        assert!(updates
+            .current
+            .as_ref()
+            .unwrap()
+            .allows("storage.example.com", 443));
+
+        let config = Network {
+            allow_widening: true,
+            ..config
+        };
+        host.write_all(b"{\"outgoing\":[\"*\"]}\n").unwrap();
+        updates.read(&config);
+        assert!(updates.current.as_ref().unwrap().allows("example.org", 443));
+
+        drop(host);
+        updates.read(&config);
+        assert_eq!(updates.fd, None);
+    }
+}
@@ -0,0 +1,225 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Execution report of the keep signed by its key
+//!
+//! If requested by the host, the keep writes a report on what it ran and how it exited to a file
+//! descriptor of the host before it is torn down, e.g.
+//! `{"report":"{\"identity\":\"08f5…\",…}","algorithm":"ecdsa-with-SHA256","signature":"3045…","certificates":["3082…"]}`.
+//! `report` is the JSON-encoded report, which `signature` signs with the key of the keep, and
+//! `certificates` is the certificate chain of the keep, hex-encoded in DER. The report can only
+//! be signed once the keep has its key and certificate, so a keep failing before is not reported.
+
+use super::loader::pki::{self, hex};
+use super::metrics::METRICS;
+use super::ExitCode;
+
+use std::fs::File;
+use std::io::Write;
+use std::mem::ManuallyDrop;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use log::warn;
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use ureq::serde_json;
+use zeroize::Zeroizing;
+
+/// The file descriptor of the host, which the report is written to, if requested
+static REPORTER: OnceCell<Mutex<Reporter>> = OnceCell::new();
+
+/// The state of the report, until it is written
+struct Reporter {
+    out: ManuallyDrop<File>,
+    start: SystemTime,
+    signer: Option<Signer>,
+}
+
+/// The key of the keep and the workload it attested to
+pub(crate) struct Signer {
+    /// The DER-encoded `PrivateKeyInfo` of the keep
+    pub prvkey: Zeroizing<Vec<u8>>,
+    /// The DER-encoded certificate chain of the keep
+    pub certs: Vec<Vec<u8>>,
+    /// The identity of the keep
+    pub identity: String,
+    /// The [`pki::digest`] of the main Wasm module
+    pub workload: String,
+    /// The [`pki::digest`] of `Enarx.toml`, if any
+    pub config: Option<String>,
+}
+
+/// The report signed by the keep
+#[derive(Serialize)]
+struct Report<'a> {
+    /// Hex-encoded SHA-256 digest of the public key of the keep
+    identity: &'a str,
+    /// Digest of the main Wasm module
+    workload_digest: &'a str,
+    /// Digest of `Enarx.toml`, if the package has one
+    config_digest: Option<&'a str>,
+    /// Exit code of the workload, if it exited
+    exit_code: Option<i32>,
+    /// Error the keep failed with, if any
+    error: Option<String>,
+    /// Start of the keep in milliseconds since the Unix epoch, as told by the host
+    start_unix_ms: u128,
+    /// Teardown of the keep in milliseconds since the Unix epoch, as told by the host
+    stop_unix_ms: u128,
+    /// Number of bytes received on network streams
+    bytes_received: u64,
+    /// Number of bytes sent on network streams
+    bytes_sent: u64,
+}
+
+/// The report with its signature
+#[derive(Serialize)]
+struct Signed {
+    report: String,
+    algorithm: String,
+    signature: String,
+    certificates: Vec<String>,
+}
+
+/// Writes the report to the file descriptor `fd` of the host on [`finish`].
+///
+/// The FD is managed by the host, so it is never closed.
+pub(crate) fn init(fd: RawFd) {
+    let out = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+    let _ = REPORTER.set(Mutex::new(Reporter {
+        out,
+        start: SystemTime::now(),
+        signer: None,
+    }));
+}
+
+/// Whether the host requested a report.
+pub(crate) fn requested() -> bool {
+    REPORTER.get().is_some()
+}
+
+/// Sets the key the report is signed with, once the keep has obtained its certificate.
+pub(crate) fn attested(signer: Signer) {
+    if let Some(reporter) = REPORTER.get() {
+        reporter.lock().unwrap().signer = Some(signer);
+    }
+}
+
+/// Signs and writes the report on the keep, which exited with `res`, if requested.
+pub(crate) fn finish(res: &Result<()>) {
+    let reporter = match REPORTER.get() {
+        Some(reporter) => reporter,
+        None => return,
+    };
+    let mut reporter = reporter.lock().unwrap();
+    let signer = reporter.signer.take();
+    let signer = match signer {
+        Some(signer) => signer,
+        None => return warn!("the keep failed before attestation, so it cannot sign a report"),
+    };
+
+    let (exit_code, error) = match res {
+        Ok(()) => (Some(0), None),
+        Err(e) => match e.downcast_ref::<ExitCode>() {
+            Some(ExitCode(code)) => (Some(*code), None),
+            None => (None, Some(format!("{e:#}"))),
+        },
+    };
+    let (bytes_received, bytes_sent) = METRICS
+        .lock()
+        .unwrap()
+        .streams
+        .values()
+        .fold((0, 0), |(rx, tx), t| (rx + t.received, tx + t.sent));
+    let millis = |time: SystemTime| {
+        time.duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default()
+    };
+    let report = Report {
+        identity: &signer.identity,
+        workload_digest: &signer.workload,
+        config_digest: signer.config.as_deref(),
+        exit_code,
+        error,
+        start_unix_ms: millis(reporter.start),
+        stop_unix_ms: millis(SystemTime::now()),
+        bytes_received,
+        bytes_sent,
+    };
+
+    let res = sign(&report, &signer).and_then(|signed| {
+        let mut line = serde_json::to_vec(&signed)?;
+        line.push(b'\n');
+        reporter
+            .out
+            .write_all(&line)
+            .context("failed to write report")
+    });
+    if let Err(e) = res {
+        warn!("failed to report the execution: {e:#}");
+    }
+}
+
+/// Signs `report` with the key of the keep.
+fn sign(report: &Report<'_>, signer: &Signer) -> Result<Signed> {
+    let report = serde_json::to_string(report)?;
+    let (algorithm, signature) = pki::sign(&signer.prvkey, report.as_bytes())?;
+    Ok(Signed {
+        report,
+        algorithm,
+        signature: hex(&signature),
+        certificates: signer.certs.iter().map(|crt| hex(crt)).collect(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::loader::pki::{digest, PrivateKeyInfoExt};
+    use super::*;
+
+    use const_oid::db::rfc5912::SECP_256_R_1;
+    use pkcs8::PrivateKeyInfo;
+    use ring::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_ASN1};
+    use x509_cert::der::Decode;
+
+    #[test]
+    fn signature() {
+        let prvkey = PrivateKeyInfo::generate(SECP_256_R_1).unwrap();
+        let signer = Signer {
+            prvkey,
+            certs: vec![vec![0x30, 0x00]],
+            identity: "08f5".into(),
+            workload: digest(b""),
+            config: None,
+        };
+        let report = Report {
+            identity: &signer.identity,
+            workload_digest: &signer.workload,
+            config_digest: None,
+            exit_code: Some(0),
+            error: None,
+            start_unix_ms: 1,
+            stop_unix_ms: 2,
+            bytes_received: 3,
+            bytes_sent: 4,
+        };
+        let signed = sign(&report, &signer).unwrap();
+        assert_eq!(signed.algorithm, "ecdsa-with-SHA256");
+        assert_eq!(signed.certificates, ["3000"]);
+        assert!(signed.report.contains(
+            "\"workload_digest\":\"sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855\""
+        ));
+
+        let pki = PrivateKeyInfo::from_der(&signer.prvkey).unwrap();
+        let public = pki.public_key().unwrap().subject_public_key.to_vec();
+        let signature: Vec<u8> = (0..signed.signature.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&signed.signature[i..i + 2], 16).unwrap())
+            .collect();
+        UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, public)
+            .verify(signed.report.as_bytes(), &signature)
+            .unwrap();
+    }
+}
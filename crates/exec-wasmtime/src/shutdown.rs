@@ -0,0 +1,87 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Graceful shutdown of the keep requested by the host
+//!
+//! The host forwards the signals requesting its shutdown, e.g. `SIGTERM`, to the keep by writing
+//! the number of the signal as a single byte to a pipe, whose read end is passed to the keep.
+//! The workload is interrupted at its next epoch check, if the keep can spawn the ticker thread,
+//! and otherwise once it waits for files with `poll_oneoff`. The keep then exits with the code of
+//! a process terminated by the signal, i.e. 128 plus the number of the signal.
+
+use std::sync::atomic::{AtomicI32, Ordering};
+
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+#[cfg(unix)]
+use std::sync::Mutex;
+
+#[cfg(unix)]
+use rustix::fd::BorrowedFd;
+#[cfg(unix)]
+use rustix::io::{PollFd, PollFlags};
+
+/// Number of the signal, which the shutdown of the keep was requested for, if any
+static SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+/// File descriptor of the host to read the shutdown request from, if any
+#[cfg(unix)]
+static FD: AtomicI32 = AtomicI32::new(-1);
+
+/// Serializes the reads of the signal
+#[cfg(unix)]
+static READ: Mutex<()> = Mutex::new(());
+
+/// Reads the shutdown request from `fd` from now on.
+#[cfg(unix)]
+pub fn init(fd: RawFd) {
+    FD.store(fd, Ordering::SeqCst);
+}
+
+/// Returns the file descriptor, which becomes readable once the host requests a shutdown, if any.
+///
+/// The file descriptor is managed by the host, so it is never closed.
+#[cfg(unix)]
+pub fn fd() -> Option<BorrowedFd<'static>> {
+    match FD.load(Ordering::SeqCst) {
+        fd if fd < 0 => None,
+        // SAFETY: The file descriptor is never closed by the keep.
+        fd => Some(unsafe { BorrowedFd::borrow_raw(fd) }),
+    }
+}
+
+/// Returns whether the host requested the shutdown of the keep.
+///
+/// The request is left unread, such that the file descriptor stays readable for every thread
+/// waiting for it, until the signal is read by [`signal`].
+pub fn requested() -> bool {
+    if SIGNAL.load(Ordering::SeqCst) != 0 {
+        return true;
+    }
+    #[cfg(unix)]
+    if let Some(fd) = fd() {
+        let mut fds = [PollFd::new(&fd, PollFlags::IN)];
+        if let Ok(1) = rustix::io::poll(&mut fds, 0) {
+            return fds[0].revents().contains(PollFlags::IN);
+        }
+    }
+    false
+}
+
+/// Returns the number of the signal, which the host requested the shutdown of the keep for.
+///
+/// Must only be called once the shutdown was [`requested`], i.e. once the workload stopped.
+pub fn signal() -> i32 {
+    #[cfg(unix)]
+    if let Some(fd) = fd() {
+        let _read = READ.lock().unwrap();
+        if SIGNAL.load(Ordering::SeqCst) == 0 {
+            let mut signal = [0];
+            if let Ok(1) = rustix::io::read(fd, &mut signal) {
+                SIGNAL.store(signal[0].into(), Ordering::SeqCst);
+            }
+        }
+    }
+    match SIGNAL.load(Ordering::SeqCst) {
+        0 => libc::SIGTERM,
+        signal => signal,
+    }
+}
@@ -0,0 +1,280 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Trace of the keep exported to the host
+//!
+//! The loader, wasmtime and the WASI implementation are instrumented with [`tracing`] spans and
+//! events, which are written to a file descriptor of the host as JSON lines, if requested. Spans
+//! are written once they are closed, e.g.
+//! `{"duration_us":4016182,"fields":{},"id":3,"parent":null,"span":"compile","start_us":3634,"target":"enarx_exec_wasmtime"}`,
+//! events as soon as they occur, e.g.
+//! `{"event":"event src/snapshots/preview_1.rs:21","fields":{"result":"Ok(0)"},"level":"TRACE","span":8,"target":"wasi_common::snapshots::preview_1::wasi_snapshot_preview1","time_us":4021771}`.
+//! Times are in microseconds since the start of the trace.
+//!
+//! The trace reveals what the workload does, so it is only exported on request of the host.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+use std::num::NonZeroU64;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+use ureq::serde_json::{self, Map, Value};
+
+thread_local! {
+    /// IDs of the spans entered by the current thread, innermost last
+    static ENTERED: RefCell<Vec<u64>> = RefCell::new(Vec::new());
+}
+
+/// Returns the innermost span entered by the current thread, if any.
+fn current() -> Option<u64> {
+    ENTERED.with(|entered| entered.borrow().last().copied())
+}
+
+/// Fields of a span or event
+#[derive(Default)]
+struct Fields(Map<String, Value>);
+
+impl Visit for Fields {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().into(), format!("{value:?}").into());
+    }
+}
+
+/// A span, which is not closed yet
+struct Span {
+    metadata: &'static Metadata<'static>,
+    parent: Option<u64>,
+    fields: Fields,
+    start: Instant,
+    /// Number of handles to the span
+    refs: usize,
+}
+
+/// Subscriber writing the trace to `W`, the host
+struct Tracer<W> {
+    out: Mutex<W>,
+    start: Instant,
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, Span>>,
+}
+
+impl<W> Tracer<W> {
+    fn new(out: W) -> Self {
+        Self {
+            out: Mutex::new(out),
+            start: Instant::now(),
+            next_id: AtomicU64::new(1),
+            spans: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the microseconds elapsed between the start of the trace and `instant`.
+    fn micros(&self, instant: Instant) -> u64 {
+        instant.duration_since(self.start).as_micros() as u64
+    }
+}
+
+impl<W: Write> Tracer<W> {
+    /// Writes `line` to the host.
+    fn write(&self, line: Map<String, Value>) {
+        if let Ok(mut line) = serde_json::to_vec(&line) {
+            line.push(b'\n');
+            // Failures are ignored, since the trace must not affect the workload.
+            let _ = self.out.lock().unwrap().write_all(&line);
+        }
+    }
+}
+
+impl<W: Write + 'static> Subscriber for Tracer<W> {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let parent = if attrs.is_contextual() {
+            current()
+        } else {
+            attrs.parent().map(Id::into_u64)
+        };
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        let span = Span {
+            metadata: attrs.metadata(),
+            parent,
+            fields,
+            start: Instant::now(),
+            refs: 1,
+        };
+        self.spans.lock().unwrap().insert(id, span);
+        Id::from_non_zero_u64(NonZeroU64::new(id).unwrap())
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        if let Some(span) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            values.record(&mut span.fields);
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let time = self.micros(Instant::now());
+        let span = if event.is_contextual() {
+            current()
+        } else {
+            event.parent().map(Id::into_u64)
+        };
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+
+        let metadata = event.metadata();
+        let mut line = Map::new();
+        line.insert("event".into(), metadata.name().into());
+        line.insert("span".into(), span.into());
+        line.insert("level".into(), metadata.level().as_str().into());
+        line.insert("target".into(), metadata.target().into());
+        line.insert("time_us".into(), time.into());
+        line.insert("fields".into(), fields.0.into());
+        self.write(line);
+    }
+
+    fn enter(&self, span: &Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.into_u64()));
+    }
+
+    fn exit(&self, span: &Id) {
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(i) = entered.iter().rposition(|id| *id == span.into_u64()) {
+                entered.remove(i);
+            }
+        });
+    }
+
+    fn clone_span(&self, id: &Id) -> Id {
+        if let Some(span) = self.spans.lock().unwrap().get_mut(&id.into_u64()) {
+            span.refs += 1;
+        }
+        id.clone()
+    }
+
+    fn try_close(&self, id: Id) -> bool {
+        let end = Instant::now();
+        let mut spans = self.spans.lock().unwrap();
+        let refs = spans.get_mut(&id.into_u64()).map(|span| {
+            span.refs -= 1;
+            span.refs
+        });
+        if refs != Some(0) {
+            return false;
+        }
+        let span = spans.remove(&id.into_u64()).unwrap();
+        drop(spans);
+
+        let mut line = Map::new();
+        line.insert("span".into(), span.metadata.name().into());
+        line.insert("id".into(), id.into_u64().into());
+        line.insert("parent".into(), span.parent.into());
+        line.insert("target".into(), span.metadata.target().into());
+        line.insert("start_us".into(), self.micros(span.start).into());
+        line.insert(
+            "duration_us".into(),
+            (end.duration_since(span.start).as_micros() as u64).into(),
+        );
+        line.insert("fields".into(), span.fields.0.into());
+        self.write(line);
+        true
+    }
+}
+
+/// Writes the trace to the file descriptor `fd` of the host.
+///
+/// The FD is managed by the host, so it is never closed.
+pub(crate) fn init(fd: RawFd) {
+    let out: &'static File = Box::leak(Box::new(unsafe { File::from_raw_fd(fd) }));
+    if tracing::subscriber::set_global_default(Tracer::new(out)).is_err() {
+        log::warn!("failed to export the trace, another subscriber is set");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Tracer;
+
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    use tracing::{info_span, trace};
+    use ureq::serde_json::{self, Value};
+
+    #[derive(Clone, Default)]
+    struct Lines(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Lines {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn lines() {
+        let out = Lines::default();
+        tracing::subscriber::with_default(Tracer::new(out.clone()), || {
+            info_span!("compile", size = 42u64).in_scope(|| {
+                let _run = info_span!("run").entered();
+                trace!(fd = 1, "write");
+            })
+        });
+
+        let out = out.0.lock().unwrap();
+        let lines: Vec<Value> = out
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+
+        assert_eq!(lines[0]["level"], "TRACE");
+        assert_eq!(lines[0]["span"], 2);
+        assert_eq!(lines[0]["fields"]["fd"], 1);
+        assert_eq!(lines[0]["fields"]["message"], "write");
+
+        assert_eq!(lines[1]["span"], "run");
+        assert_eq!(lines[1]["id"], 2);
+        assert_eq!(lines[1]["parent"], 1);
+
+        assert_eq!(lines[2]["span"], "compile");
+        assert_eq!(lines[2]["parent"], Value::Null);
+        assert_eq!(lines[2]["fields"]["size"], 42);
+        assert!(lines[2]["duration_us"].as_u64() >= lines[1]["duration_us"].as_u64());
+    }
+}
@@ -0,0 +1,394 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Conventions of the environment of WebAssembly workloads running in Enarx Keeps
+//!
+#![doc = include_str!("../README.md")]
+#![cfg_attr(target_os = "wasi", feature(wasi_ext))]
+#![deny(missing_docs)]
+#![deny(clippy::all)]
+#![warn(rust_2018_idioms)]
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+#[cfg(unix)]
+use std::os::unix::io::{FromRawFd, RawFd};
+
+#[cfg(target_os = "wasi")]
+use std::os::wasi::io::{FromRawFd, RawFd};
+
+/// Version of the conventions of the runtime supported by this crate
+pub const ABI: u32 = 1;
+
+/// Errors of parsing the environment of the workload
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// A variable is missing or malformed
+    Variable(&'static str),
+
+    /// The runtime follows conventions of another version
+    Abi(u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Variable(name) => {
+                write!(f, "environment variable `{name}` is missing or invalid")
+            }
+            Self::Abi(abi) => write!(
+                f,
+                "runtime ABI version {abi} is not supported, expected {ABI}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A file descriptor of the workload configured in `Enarx.toml`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fd {
+    /// The number of the file descriptor
+    pub fd: RawFd,
+
+    /// The name of the file descriptor
+    pub name: String,
+
+    /// The kind of the file descriptor, `None` for runtimes not reporting it
+    pub kind: Option<String>,
+
+    /// The port of a listen socket, once it is bound
+    pub port: Option<u16>,
+}
+
+/// The file descriptors of the workload
+///
+/// Every file descriptor is handed out once, as its handle owns it.
+#[derive(Debug)]
+pub struct Files {
+    abi: Option<u32>,
+    fds: Vec<Fd>,
+    taken: Vec<bool>,
+}
+
+impl Files {
+    /// Parses the file descriptors from the environment of the workload.
+    pub fn from_env() -> Result<Self, Error> {
+        Self::parse(|name| std::env::var(name).ok())
+    }
+
+    /// Parses the file descriptors from the variables returned by `var`.
+    fn parse(var: impl Fn(&str) -> Option<String>) -> Result<Self, Error> {
+        let abi = var("ENARX_ABI")
+            .map(|abi| abi.parse().map_err(|_| Error::Variable("ENARX_ABI")))
+            .transpose()?;
+        match abi {
+            Some(abi) if abi != ABI => return Err(Error::Abi(abi)),
+            _ => {}
+        }
+
+        let count: usize = var("FD_COUNT")
+            .and_then(|count| count.parse().ok())
+            .ok_or(Error::Variable("FD_COUNT"))?;
+        let list = |name: &'static str, required: bool| -> Result<Option<Vec<String>>, Error> {
+            let value = match var(name) {
+                Some(value) => value,
+                None if required => return Err(Error::Variable(name)),
+                None => return Ok(None),
+            };
+            let values: Vec<_> = match count {
+                0 => vec![],
+                _ => value.split(':').map(String::from).collect(),
+            };
+            match values.len() == count {
+                true => Ok(Some(values)),
+                false => Err(Error::Variable(name)),
+            }
+        };
+
+        let names = list("FD_NAMES", true)?.unwrap_or_default();
+        // Runtimes predating the versioning report no kinds.
+        let kinds = match abi {
+            Some(_) => list("FD_KINDS", true)?,
+            None => list("FD_KINDS", false)?,
+        };
+        let ports = list("FD_PORTS", false)?;
+
+        let fds = names
+            .into_iter()
+            .enumerate()
+            .map(|(fd, name)| Fd {
+                fd: fd as _,
+                name,
+                kind: kinds.as_ref().map(|kinds| kinds[fd].clone()),
+                port: ports.as_ref().and_then(|ports| ports[fd].parse().ok()),
+            })
+            .collect();
+        Ok(Self {
+            abi,
+            taken: vec![false; count],
+            fds,
+        })
+    }
+
+    /// Returns the version of the conventions of the runtime, `None` for runtimes predating it.
+    pub fn abi(&self) -> Option<u32> {
+        self.abi
+    }
+
+    /// Returns all file descriptors in order.
+    pub fn fds(&self) -> &[Fd] {
+        &self.fds
+    }
+
+    /// Returns the file descriptor named `name`.
+    pub fn get(&self, name: &str) -> Option<&Fd> {
+        self.fds.iter().find(|fd| fd.name == name)
+    }
+
+    /// Returns the number of the file descriptor matching `name` or, if `None`, the first one
+    /// of `kind`, marking it as taken.
+    fn take(&mut self, name: Option<&str>, kind: &str) -> io::Result<RawFd> {
+        let index = self
+            .fds
+            .iter()
+            .position(|fd| match name {
+                Some(name) => fd.name == name,
+                None => fd.kind.as_deref() == Some(kind),
+            })
+            .ok_or_else(|| {
+                let what = name.map_or_else(|| format!("of kind `{kind}`"), |n| format!("`{n}`"));
+                io::Error::new(io::ErrorKind::NotFound, format!("no file {what}"))
+            })?;
+        let fd = &self.fds[index];
+        match fd.kind.as_deref() {
+            Some(actual) if actual != kind => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("file `{}` is of kind `{actual}`, not `{kind}`", fd.name),
+                ))
+            }
+            _ => {}
+        }
+        if std::mem::replace(&mut self.taken[index], true) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("file `{}` was taken already", fd.name),
+            ));
+        }
+        Ok(fd.fd)
+    }
+
+    /// Takes the listen socket named `name`.
+    pub fn listener(&mut self, name: &str) -> io::Result<TcpListener> {
+        let fd = self.take(Some(name), "listen")?;
+        // Safety: the runtime opened the socket and it was not taken before.
+        Ok(unsafe { TcpListener::from_raw_fd(fd) })
+    }
+
+    /// Takes the connected socket named `name`.
+    pub fn stream(&mut self, name: &str) -> io::Result<TcpStream> {
+        let fd = self.take(Some(name), "connect")?;
+        // Safety: the runtime opened the socket and it was not taken before.
+        Ok(unsafe { TcpStream::from_raw_fd(fd) })
+    }
+
+    /// Takes the channel named `name` to another service of the keep.
+    pub fn channel(&mut self, name: &str) -> io::Result<File> {
+        let fd = self.take(Some(name), "channel")?;
+        // Safety: the runtime opened the channel and it was not taken before.
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+
+    /// Takes the first file of `kind`.
+    fn file(&mut self, kind: &str) -> io::Result<File> {
+        let fd = self.take(None, kind)?;
+        // Safety: the runtime opened the file and it was not taken before.
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+
+    /// Takes the first file yielding attestation evidence.
+    pub fn attestation(&mut self) -> io::Result<Attestation> {
+        self.file("attestation").map(Attestation)
+    }
+
+    /// Takes the first file yielding the certificate chain of the keep.
+    pub fn certs(&mut self) -> io::Result<Certs> {
+        self.file("certs").map(Certs)
+    }
+
+    /// Takes the first file deriving keys.
+    pub fn keys(&mut self) -> io::Result<Keys> {
+        self.file("keys").map(Keys)
+    }
+
+    /// Takes the first file yielding random bytes, seeded according to the entropy policy.
+    pub fn random(&mut self) -> io::Result<File> {
+        self.file("random")
+    }
+
+    /// Takes the secret file named `name`, e.g. `/secrets/db`, and returns the secret.
+    pub fn secret(&mut self, name: &str) -> io::Result<String> {
+        let fd = self.take(Some(name), "secret")?;
+        // Safety: the runtime opened the file and it was not taken before.
+        let mut file = unsafe { File::from_raw_fd(fd) };
+        let mut secret = String::new();
+        file.read_to_string(&mut secret)?;
+        Ok(secret)
+    }
+
+    /// Takes the first file signaling the readiness of the workload.
+    pub fn ready(&mut self) -> io::Result<Signal> {
+        self.file("ready").map(Signal)
+    }
+
+    /// Takes the first file signaling the liveness of the workload.
+    pub fn healthy(&mut self) -> io::Result<Signal> {
+        self.file("healthy").map(Signal)
+    }
+}
+
+/// The size of the report data the evidence is bound to
+const DATA_SIZE: usize = 64;
+
+/// A file of `kind = "attestation"`
+#[derive(Debug)]
+pub struct Attestation(File);
+
+impl Attestation {
+    /// Returns fresh attestation evidence of the keep bound to `nonce` of up to 64 bytes.
+    pub fn evidence(&mut self, nonce: &[u8]) -> io::Result<Vec<u8>> {
+        if nonce.len() > DATA_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("nonce exceeds {DATA_SIZE} bytes"),
+            ));
+        }
+        self.0.write_all(nonce)?;
+        let mut evidence = vec![];
+        self.0.read_to_end(&mut evidence)?;
+        Ok(evidence)
+    }
+}
+
+/// A file of `kind = "certs"`
+#[derive(Debug)]
+pub struct Certs(File);
+
+impl Certs {
+    /// Returns the current certificate chain of the keep, PEM-encoded and leaf first.
+    pub fn chain(&mut self) -> io::Result<String> {
+        let mut chain = String::new();
+        self.0.read_to_string(&mut chain)?;
+        Ok(chain)
+    }
+}
+
+/// The maximum size of a key label
+const LABEL_SIZE: usize = 256;
+
+/// A file of `kind = "keys"`
+#[derive(Debug)]
+pub struct Keys(File);
+
+impl Keys {
+    /// Returns the key derived for `label` of up to 256 bytes.
+    pub fn derive(&mut self, label: &[u8]) -> io::Result<[u8; 32]> {
+        if label.len() > LABEL_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("label exceeds {LABEL_SIZE} bytes"),
+            ));
+        }
+        self.0.write_all(label)?;
+        let mut key = vec![];
+        self.0.read_to_end(&mut key)?;
+        key.try_into()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid key size"))
+    }
+}
+
+/// A file of `kind = "ready"` or `kind = "healthy"`
+#[derive(Debug)]
+pub struct Signal(File);
+
+impl Signal {
+    /// Reports the state of the workload to the host.
+    pub fn set(&mut self, state: bool) -> io::Result<()> {
+        self.0.write_all(if state { b"1\n" } else { b"0\n" })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, Fd, Files, ABI};
+
+    use std::collections::HashMap;
+    use std::io;
+
+    fn parse(vars: &[(&str, &str)]) -> Result<Files, Error> {
+        let vars: HashMap<_, _> = vars.iter().copied().collect();
+        Files::parse(|name| vars.get(name).map(|value| value.to_string()))
+    }
+
+    #[test]
+    fn files() {
+        let mut files = parse(&[
+            ("ENARX_ABI", &ABI.to_string()),
+            ("FD_COUNT", "5"),
+            ("FD_NAMES", "stdin:stdout:stderr:LISTEN:/run/ready"),
+            ("FD_KINDS", "stdin:stdout:stderr:listen:ready"),
+            ("FD_PORTS", ":::9000:"),
+        ])
+        .unwrap();
+        assert_eq!(files.abi(), Some(ABI));
+        assert_eq!(
+            files.get("LISTEN"),
+            Some(&Fd {
+                fd: 3,
+                name: "LISTEN".into(),
+                kind: Some("listen".into()),
+                port: Some(9000),
+            })
+        );
+        assert_eq!(files.take(None, "ready").unwrap(), 4);
+        assert_eq!(
+            files.take(None, "ready").unwrap_err().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+        assert_eq!(
+            files.take(Some("stdin"), "listen").unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            files.take(Some("CONNECT"), "connect").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn compatibility() {
+        // Runtimes predating the versioning report no kinds, which are not checked then.
+        let mut files = parse(&[("FD_COUNT", "1"), ("FD_NAMES", "LISTEN")]).unwrap();
+        assert_eq!(files.abi(), None);
+        assert_eq!(files.fds()[0].kind, None);
+        assert_eq!(files.take(Some("LISTEN"), "listen").unwrap(), 0);
+
+        assert_eq!(parse(&[("ENARX_ABI", "2")]).unwrap_err(), Error::Abi(2));
+        assert_eq!(
+            parse(&[("ENARX_ABI", "1"), ("FD_COUNT", "1"), ("FD_NAMES", "x")]).unwrap_err(),
+            Error::Variable("FD_KINDS")
+        );
+        assert_eq!(
+            parse(&[("FD_COUNT", "2"), ("FD_NAMES", "x")]).unwrap_err(),
+            Error::Variable("FD_NAMES")
+        );
+        assert_eq!(
+            parse(&[("FD_COUNT", "0"), ("FD_NAMES", "")]).unwrap().fds(),
+            &[]
+        );
+    }
+}
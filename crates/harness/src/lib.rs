@@ -0,0 +1,317 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Running Enarx Keeps concurrently from one process
+//!
+#![doc = include_str!("../README.md")]
+#![deny(missing_docs)]
+#![deny(clippy::all)]
+#![warn(rust_2018_idioms)]
+
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Write};
+use std::net::{Ipv4Addr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use process_control::{ChildExt, Control};
+use tempfile::TempDir;
+
+/// Ports handed out by [`port`] in this process
+static PORTS: Mutex<Option<HashSet<u16>>> = Mutex::new(None);
+
+/// Returns a free TCP port, which no other call in this process returns.
+///
+/// The port is free on all interfaces at the time of the call, so a keep can listen on it.
+pub fn port() -> io::Result<u16> {
+    let mut ports = PORTS.lock().unwrap_or_else(|e| e.into_inner());
+    let ports = ports.get_or_insert_with(HashSet::new);
+    // Keep the listeners of ports handed out before open, so the OS picks another one.
+    let mut taken = vec![];
+    loop {
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        let port = listener.local_addr()?.port();
+        if ports.insert(port) {
+            return Ok(port);
+        }
+        taken.push(listener);
+    }
+}
+
+/// The output of a keep
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Output {
+    /// The exit code of `enarx`, `None` if it was terminated by a signal
+    pub code: Option<i32>,
+
+    /// The standard output of the keep
+    pub stdout: Vec<u8>,
+
+    /// The standard error of the keep
+    pub stderr: Vec<u8>,
+}
+
+/// Runs keeps with the `enarx` binary
+///
+/// Every keep runs in a temporary directory of its own, which is its working directory and
+/// `TMPDIR`, such that the anonymous files `enarx` creates for a keep never collide. `ENARX_*`
+/// variables of this process are not passed on to the keeps, only the options of the harness
+/// and of the keep.
+#[derive(Debug)]
+pub struct Harness {
+    enarx: PathBuf,
+    backend: Option<String>,
+    signatures: Option<PathBuf>,
+    timeout: Duration,
+    dir: TempDir,
+}
+
+impl Harness {
+    /// Creates a harness running keeps with the `enarx` binary at `enarx`.
+    pub fn new(enarx: impl Into<PathBuf>) -> io::Result<Self> {
+        Ok(Self {
+            enarx: enarx.into(),
+            backend: None,
+            signatures: None,
+            timeout: Duration::from_secs(60 * 60),
+            dir: tempfile::Builder::new()
+                .prefix("enarx-harness-")
+                .tempdir()?,
+        })
+    }
+
+    /// Sets the backend of the keeps, picked by `enarx` by default.
+    pub fn backend(mut self, backend: impl Into<String>) -> Self {
+        self.backend = Some(backend.into());
+        self
+    }
+
+    /// Sets the signature file of the keeps.
+    ///
+    /// The file is copied, such that all keeps use the same signatures, even if the file is
+    /// rewritten, e.g. by `enarx sign`, while they run.
+    pub fn signatures(mut self, path: impl AsRef<Path>) -> io::Result<Self> {
+        let pinned = self.dir.path().join("signatures.json");
+        fs::copy(path, &pinned)?;
+        self.signatures = Some(pinned);
+        Ok(self)
+    }
+
+    /// Sets the time after which a keep is killed, an hour by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Returns a keep running the WebAssembly module at `wasm`.
+    pub fn keep(&self, wasm: impl Into<PathBuf>) -> Keep<'_> {
+        Keep {
+            harness: self,
+            wasm: wasm.into(),
+            conf: None,
+            args: vec![],
+            envs: vec![],
+            stdin: vec![],
+        }
+    }
+
+    /// Runs `keeps` concurrently, returning their outputs in order.
+    pub fn run_all<'a>(
+        &'a self,
+        keeps: impl IntoIterator<Item = Keep<'a>>,
+    ) -> Vec<io::Result<Output>> {
+        thread::scope(|scope| {
+            // Spawn all keeps before waiting for the first one.
+            #[allow(clippy::needless_collect)]
+            let threads: Vec<_> = keeps
+                .into_iter()
+                .map(|keep| scope.spawn(|| keep.run()))
+                .collect();
+            threads
+                .into_iter()
+                .map(|thread| {
+                    thread.join().unwrap_or_else(|_| {
+                        Err(io::Error::new(io::ErrorKind::Other, "keep panicked"))
+                    })
+                })
+                .collect()
+        })
+    }
+}
+
+/// A keep to run with a [`Harness`]
+#[derive(Debug)]
+pub struct Keep<'a> {
+    harness: &'a Harness,
+    wasm: PathBuf,
+    conf: Option<PathBuf>,
+    args: Vec<OsString>,
+    envs: Vec<(OsString, OsString)>,
+    stdin: Vec<u8>,
+}
+
+impl Keep<'_> {
+    /// Sets the `Enarx.toml` of the keep.
+    pub fn conf(mut self, path: impl Into<PathBuf>) -> Self {
+        self.conf = Some(path.into());
+        self
+    }
+
+    /// Appends an option of `enarx run`, e.g. `--max-wasm-size`.
+    pub fn arg(mut self, arg: impl Into<OsString>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Sets an environment variable of `enarx`, e.g. `ENARX_MEMORY_RESIDENCY`.
+    pub fn env(mut self, key: impl Into<OsString>, value: impl Into<OsString>) -> Self {
+        self.envs.push((key.into(), value.into()));
+        self
+    }
+
+    /// Sets the standard input of the keep, which is empty by default.
+    pub fn stdin(mut self, stdin: impl Into<Vec<u8>>) -> Self {
+        self.stdin = stdin.into();
+        self
+    }
+
+    /// Returns the `enarx run` command of the keep running in `dir` from a process with the
+    /// environment `vars`.
+    fn command(&self, dir: &Path, vars: impl IntoIterator<Item = (OsString, OsString)>) -> Command {
+        let harness = self.harness;
+        let mut cmd = Command::new(&harness.enarx);
+        for (key, _) in vars {
+            if key.to_string_lossy().starts_with("ENARX_") {
+                cmd.env_remove(key);
+            }
+        }
+        cmd.current_dir(dir).env("TMPDIR", dir).arg("run");
+        if let Some(ref backend) = harness.backend {
+            cmd.arg("--backend").arg(backend);
+        }
+        if let Some(ref signatures) = harness.signatures {
+            cmd.arg("--signatures").arg(signatures);
+        }
+        if let Some(ref conf) = self.conf {
+            cmd.arg("--wasmcfgfile").arg(conf);
+        }
+        cmd.args(&self.args)
+            .envs(self.envs.iter().map(|(k, v)| (k, v)));
+        cmd.arg(&self.wasm);
+        cmd
+    }
+
+    /// Runs the keep and waits for it to exit.
+    pub fn run(self) -> io::Result<Output> {
+        let dir = tempfile::Builder::new()
+            .prefix("keep-")
+            .tempdir_in(self.harness.dir.path())?;
+        let mut child = self
+            .command(dir.path(), std::env::vars_os())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let mut stdin = child.stdin.take().unwrap();
+        let input = self.stdin;
+        let input = thread::spawn(move || stdin.write_all(&input));
+
+        let output = child
+            .controlled_with_output()
+            .time_limit(self.harness.timeout)
+            .terminate_for_timeout()
+            .wait()?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::TimedOut, "keep timed out"))?;
+        // The keep may exit without reading its input.
+        let _ = input.join();
+
+        Ok(Output {
+            code: output.status.code().map(|code| code as _),
+            stdout: output.stdout,
+            stderr: output.stderr,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{port, Harness};
+
+    use std::collections::HashSet;
+    use std::ffi::OsStr;
+    use std::fs;
+    #[cfg(unix)]
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    #[test]
+    fn ports() {
+        let ports: HashSet<_> = (0..64).map(|_| port().unwrap()).collect();
+        assert_eq!(ports.len(), 64);
+    }
+
+    #[test]
+    fn env() {
+        let harness = Harness::new("enarx").unwrap();
+        let keep = harness.keep("main.wasm").env("ENARX_MEMORY_RESIDENCY", "1");
+        let vars = [("ENARX_BACKEND", "sgx"), ("PATH", "/bin")].map(|(k, v)| (k.into(), v.into()));
+        let cmd = keep.command(Path::new("."), vars);
+
+        // The variables of the process are removed, unless set for the keep.
+        let envs: Vec<_> = cmd.get_envs().collect();
+        assert!(envs.contains(&(OsStr::new("ENARX_BACKEND"), None)));
+        assert!(envs.contains(&(OsStr::new("ENARX_MEMORY_RESIDENCY"), Some(OsStr::new("1")))));
+        assert!(!envs.iter().any(|(k, _)| *k == "PATH"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn isolated() {
+        let dir = tempfile::tempdir().unwrap();
+        // A fake `enarx`, which prints its arguments, environment and input.
+        let enarx = dir.path().join("enarx");
+        fs::write(
+            &enarx,
+            "#!/bin/sh\necho \"$@\"\necho \"$TMPDIR $(pwd) ${ENARX_BACKEND:-unset}\"\ncat\n",
+        )
+        .unwrap();
+        fs::set_permissions(&enarx, fs::Permissions::from_mode(0o755)).unwrap();
+        let signatures = dir.path().join("sig.json");
+        fs::write(&signatures, "{}").unwrap();
+
+        let harness = Harness::new(&enarx)
+            .unwrap()
+            .backend("nil")
+            .signatures(&signatures)
+            .unwrap();
+        fs::remove_file(&signatures).unwrap();
+
+        let keeps = (0..8).map(|i| {
+            harness
+                .keep(format!("{i}.wasm"))
+                .arg("--hold")
+                .stdin(format!("input {i}"))
+        });
+        let mut dirs = HashSet::new();
+        for (i, output) in harness.run_all(keeps).into_iter().enumerate() {
+            let output = output.unwrap();
+            assert_eq!(output.code, Some(0));
+            let stdout = String::from_utf8(output.stdout).unwrap();
+            let lines: Vec<_> = stdout.lines().collect();
+            let pinned = harness.signatures.as_ref().unwrap().display();
+            assert_eq!(
+                lines[0],
+                format!("run --backend nil --signatures {pinned} --hold {i}.wasm")
+            );
+            let (tmp, rest) = lines[1].split_once(' ').unwrap();
+            assert_eq!(rest, format!("{tmp} unset"));
+            assert!(dirs.insert(tmp.to_string()));
+            assert_eq!(lines[2], format!("input {i}"));
+        }
+    }
+}
@@ -2,7 +2,7 @@
 
 use super::super::types::Argv;
 use super::Alloc;
-use crate::guest::alloc::{Allocator, Collector, InOut, Output};
+use crate::guest::alloc::{Allocator, Collect, Collector, InOut, Output};
 use crate::libc::{pollfd, SYS_poll};
 use crate::Result;
 
@@ -35,8 +35,10 @@ unsafe impl<'a> Alloc<'a> for Poll<'a> {
     ) -> Self::Collected {
         match ret {
             Ok(ret) if ret as usize > fds.len() => None,
-            res @ Ok(ret) => {
-                unsafe { fds.collect_range(col, 0..ret as _) };
+            res @ Ok(..) => {
+                // `ret` is the number of ready file descriptors, which may be
+                // anywhere in `fds`, so the events of all of them are collected.
+                fds.collect(col);
                 Some(res)
             }
             err => Some(err),
@@ -0,0 +1,360 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::libc::{clockid_t, time_t, timespec, CLOCK_MONOTONIC, CLOCK_REALTIME};
+
+use core::hint::spin_loop;
+use core::sync::atomic::{fence, AtomicU64, Ordering};
+
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/// Maximum time in nanoseconds a [`Clock`] extrapolates the host clocks for.
+pub const CLOCK_REFRESH_INTERVAL: u64 = NSEC_PER_SEC;
+
+/// Minimum time in nanoseconds between two host clock samples used for TSC calibration.
+const CLOCK_CALIBRATION_INTERVAL: u64 = NSEC_PER_SEC / 1000;
+
+/// Name of the environment variable of the exec holding the hex-encoded address of the
+/// [`TimePage`], if the shim maps one.
+pub const TIME_PAGE_ENV: &str = "ENARX_TIME_PAGE";
+
+fn to_nanos(ts: &timespec) -> u64 {
+    (ts.tv_sec as u64)
+        .saturating_mul(NSEC_PER_SEC)
+        .saturating_add(ts.tv_nsec as u64)
+}
+
+fn from_nanos(ns: u64) -> timespec {
+    timespec {
+        tv_sec: (ns / NSEC_PER_SEC) as time_t,
+        tv_nsec: (ns % NSEC_PER_SEC) as _,
+    }
+}
+
+/// A host clock sample and the TSC frequency it is extrapolated with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Sample {
+    /// TSC value of the host clock sample.
+    tsc: u64,
+    /// `CLOCK_REALTIME` of the host clock sample in nanoseconds.
+    realtime: u64,
+    /// `CLOCK_MONOTONIC` of the host clock sample in nanoseconds.
+    monotonic: u64,
+    /// Nanoseconds per TSC tick as a 32.32 fixed point number, `0` if not calibrated.
+    mult: u64,
+}
+
+impl Sample {
+    /// Returns `clockid` at `tsc` in nanoseconds.
+    ///
+    /// Returns `None` if `clockid` is not supported, the TSC frequency is not calibrated yet
+    /// or the sample is too old.
+    fn extrapolate(&self, clockid: clockid_t, tsc: u64) -> Option<u64> {
+        if self.mult == 0 {
+            return None;
+        }
+
+        let ticks = tsc.checked_sub(self.tsc)?;
+        let ns = ((ticks as u128 * self.mult as u128) >> 32) as u64;
+        if ns >= CLOCK_REFRESH_INTERVAL {
+            return None;
+        }
+
+        match clockid {
+            CLOCK_REALTIME => Some(self.realtime + ns),
+            CLOCK_MONOTONIC => Some(self.monotonic + ns),
+            _ => None,
+        }
+    }
+}
+
+/// Latest clock values returned, which keep the clocks from going backwards across host clock
+/// samples, whenever the TSC runs faster than the host clocks.
+///
+/// `CLOCK_REALTIME` only follows the host clock backwards, if it was set back by more than
+/// [`CLOCK_REFRESH_INTERVAL`], i.e. more than the extrapolation can be off.
+#[derive(Debug, Default)]
+pub struct Guard {
+    realtime: AtomicU64,
+    monotonic: AtomicU64,
+}
+
+impl Guard {
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            realtime: AtomicU64::new(0),
+            monotonic: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns `ns` of `clockid`, unless it is earlier than a value returned before.
+    pub fn apply(&self, clockid: clockid_t, ns: u64) -> u64 {
+        match clockid {
+            CLOCK_REALTIME => {
+                let mut last = self.realtime.load(Ordering::Relaxed);
+                loop {
+                    if ns <= last && last - ns <= CLOCK_REFRESH_INTERVAL {
+                        return last;
+                    }
+                    match self.realtime.compare_exchange_weak(
+                        last,
+                        ns,
+                        Ordering::Relaxed,
+                        Ordering::Relaxed,
+                    ) {
+                        Ok(_) => return ns,
+                        Err(current) => last = current,
+                    }
+                }
+            }
+            CLOCK_MONOTONIC => self.monotonic.fetch_max(ns, Ordering::Relaxed).max(ns),
+            _ => ns,
+        }
+    }
+}
+
+/// Clock fast path akin to the clock page of the Linux vDSO.
+///
+/// The clock is calibrated against samples of the host clocks, which are taken via [`update`](Clock::update)
+/// and used to serve subsequent reads of `CLOCK_REALTIME` and `CLOCK_MONOTONIC` from the TSC only
+/// via [`read`](Clock::read) for up to [`CLOCK_REFRESH_INTERVAL`] without exiting to the host.
+#[derive(Debug, Default)]
+pub struct Clock {
+    /// The last host clock sample.
+    sample: Sample,
+    /// TSC value and `CLOCK_MONOTONIC` in nanoseconds of the host clock sample used for calibration.
+    anchor: Option<(u64, u64)>,
+    /// Latest values returned, which guarantee that the clocks do not go backwards across samples.
+    guard: Guard,
+}
+
+impl Clock {
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            sample: Sample {
+                tsc: 0,
+                realtime: 0,
+                monotonic: 0,
+                mult: 0,
+            },
+            anchor: None,
+            guard: Guard::new(),
+        }
+    }
+
+    /// Records a host clock sample taken at `tsc` and recalibrates the TSC frequency.
+    pub fn update(&mut self, tsc: u64, realtime: &timespec, monotonic: &timespec) {
+        let realtime = to_nanos(realtime);
+        let monotonic = to_nanos(monotonic);
+
+        match self.anchor {
+            Some((anchor_tsc, anchor_monotonic))
+                if tsc > anchor_tsc
+                    && monotonic >= anchor_monotonic.saturating_add(CLOCK_CALIBRATION_INTERVAL) =>
+            {
+                let ns = (monotonic - anchor_monotonic) as u128;
+                self.sample.mult = ((ns << 32) / (tsc - anchor_tsc) as u128) as u64;
+                self.anchor = Some((tsc, monotonic));
+            }
+            Some(_) => {}
+            None => self.anchor = Some((tsc, monotonic)),
+        }
+
+        self.sample.tsc = tsc;
+        self.sample.realtime = realtime;
+        self.sample.monotonic = monotonic;
+    }
+
+    /// Returns the last host clock sample, e.g. to publish it to a [`TimePage`].
+    #[inline]
+    pub fn sample(&self) -> Sample {
+        self.sample
+    }
+
+    /// Reads `clockid` at `tsc`.
+    ///
+    /// Returns `None` if `clockid` is not supported, the clock is not calibrated yet
+    /// or the last host clock sample is too old, in which case a new sample must be taken.
+    pub fn read(&mut self, clockid: clockid_t, tsc: u64) -> Option<timespec> {
+        let ns = self.sample.extrapolate(clockid, tsc)?;
+        Some(from_nanos(self.guard.apply(clockid, ns)))
+    }
+}
+
+/// The host clock sample of a [`Clock`] shared read-only with the exec akin to the data page of
+/// the Linux vDSO.
+///
+/// The shim publishes every host clock sample to the page, which it maps into the exec at the
+/// address in [`TIME_PAGE_ENV`], such that the exec can read the clocks without trapping into the
+/// shim. The page is guarded by a sequence lock, whose counter is odd while the shim writes it.
+#[derive(Debug, Default)]
+#[repr(C, align(4096))]
+pub struct TimePage {
+    seq: AtomicU64,
+    tsc: AtomicU64,
+    realtime: AtomicU64,
+    monotonic: AtomicU64,
+    mult: AtomicU64,
+}
+
+impl TimePage {
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            seq: AtomicU64::new(0),
+            tsc: AtomicU64::new(0),
+            realtime: AtomicU64::new(0),
+            monotonic: AtomicU64::new(0),
+            mult: AtomicU64::new(0),
+        }
+    }
+
+    /// Publishes `sample`.
+    ///
+    /// The page has a single writer, so callers must serialize the calls.
+    pub fn publish(&self, sample: &Sample) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+        self.tsc.store(sample.tsc, Ordering::Relaxed);
+        self.realtime.store(sample.realtime, Ordering::Relaxed);
+        self.monotonic.store(sample.monotonic, Ordering::Relaxed);
+        self.mult.store(sample.mult, Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+
+    /// Reads `clockid` at `tsc` in nanoseconds from the last published sample.
+    ///
+    /// Returns `None` under the same conditions as [`Clock::read`], in which case the clock must
+    /// be read via `clock_gettime`, which publishes a new sample. The value is not guarded
+    /// against going backwards, see [`Guard`].
+    pub fn read(&self, clockid: clockid_t, tsc: u64) -> Option<u64> {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq & 1 != 0 {
+                spin_loop();
+                continue;
+            }
+            let sample = Sample {
+                tsc: self.tsc.load(Ordering::Relaxed),
+                realtime: self.realtime.load(Ordering::Relaxed),
+                monotonic: self.monotonic.load(Ordering::Relaxed),
+                mult: self.mult.load(Ordering::Relaxed),
+            };
+            fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == seq {
+                return sample.extrapolate(clockid, tsc);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calibration() {
+        let mut clock = Clock::new();
+        assert_eq!(clock.read(CLOCK_MONOTONIC, 1), None);
+
+        // 1 GHz TSC
+        clock.update(1_000, &from_nanos(10 * NSEC_PER_SEC), &from_nanos(5));
+        assert_eq!(clock.read(CLOCK_MONOTONIC, 1_001), None);
+
+        clock.update(
+            2_001_000,
+            &from_nanos(10 * NSEC_PER_SEC + 2_000_000),
+            &from_nanos(2_000_005),
+        );
+        assert_eq!(
+            clock.read(CLOCK_REALTIME, 2_002_000),
+            Some(from_nanos(10 * NSEC_PER_SEC + 2_001_000))
+        );
+        assert_eq!(
+            clock.read(CLOCK_MONOTONIC, 2_002_000),
+            Some(from_nanos(2_001_005))
+        );
+        assert_eq!(clock.read(2, 2_002_000), None);
+
+        // The sample is too old.
+        assert_eq!(clock.read(CLOCK_MONOTONIC, 2_001_000 + NSEC_PER_SEC), None);
+    }
+
+    #[test]
+    fn monotonic() {
+        let mut clock = Clock::new();
+        clock.update(0, &from_nanos(0), &from_nanos(0));
+        clock.update(1_000_000, &from_nanos(1_000_000), &from_nanos(1_000_000));
+        assert_eq!(
+            clock.read(CLOCK_MONOTONIC, 1_500_000),
+            Some(from_nanos(1_500_000))
+        );
+
+        // The host clock went slower than the TSC.
+        clock.update(3_000_000, &from_nanos(1_200_000), &from_nanos(1_200_000));
+        assert_eq!(
+            clock.read(CLOCK_MONOTONIC, 3_000_000),
+            Some(from_nanos(1_500_000))
+        );
+    }
+
+    #[test]
+    fn realtime() {
+        let mut clock = Clock::new();
+        clock.update(0, &from_nanos(0), &from_nanos(0));
+        clock.update(
+            1_000_000,
+            &from_nanos(10 * NSEC_PER_SEC),
+            &from_nanos(1_000_000),
+        );
+        let ahead = clock.read(CLOCK_REALTIME, 1_500_000).unwrap();
+        assert_eq!(ahead, from_nanos(10 * NSEC_PER_SEC + 500_000));
+
+        // The host clock went slower than the TSC.
+        clock.update(
+            3_000_000,
+            &from_nanos(10 * NSEC_PER_SEC + 200_000),
+            &from_nanos(1_200_000),
+        );
+        assert_eq!(clock.read(CLOCK_REALTIME, 3_000_000), Some(ahead));
+        assert_eq!(
+            clock.read(CLOCK_REALTIME, 3_400_000),
+            Some(from_nanos(10 * NSEC_PER_SEC + 600_000))
+        );
+
+        // The host clock was set back.
+        clock.update(4_000_000, &from_nanos(NSEC_PER_SEC), &from_nanos(2_000_000));
+        assert_eq!(
+            clock.read(CLOCK_REALTIME, 4_000_000),
+            Some(from_nanos(NSEC_PER_SEC))
+        );
+    }
+
+    #[test]
+    fn time_page() {
+        let page = TimePage::new();
+        assert_eq!(page.read(CLOCK_MONOTONIC, 0), None);
+
+        let mut clock = Clock::new();
+        clock.update(0, &from_nanos(10 * NSEC_PER_SEC), &from_nanos(0));
+        clock.update(
+            1_000_000,
+            &from_nanos(10 * NSEC_PER_SEC + 1_000_000),
+            &from_nanos(1_000_000),
+        );
+        page.publish(&clock.sample());
+        assert_eq!(page.seq.load(Ordering::Relaxed), 2);
+        assert_eq!(page.read(CLOCK_MONOTONIC, 1_500_000), Some(1_500_000));
+        assert_eq!(
+            page.read(CLOCK_REALTIME, 1_500_000),
+            Some(10 * NSEC_PER_SEC + 1_500_000)
+        );
+        assert_eq!(page.read(CLOCK_MONOTONIC, 1_000_000 + NSEC_PER_SEC), None);
+
+        let guard = Guard::new();
+        assert_eq!(guard.apply(CLOCK_MONOTONIC, 2), 2);
+        assert_eq!(guard.apply(CLOCK_MONOTONIC, 1), 2);
+    }
+}
@@ -4,7 +4,7 @@ use super::alloc::{Alloc, Allocator, Collect, Commit, Committer};
 use super::call::kind;
 use super::syscall::types::{MremapFlags, SockaddrInput, SockaddrOutput, SockoptInput};
 use super::{enarxcall, gdbcall, syscall, Call, Platform, ThreadLocalStorage, SIGRTMAX};
-use crate::item::enarxcall::sgx;
+use crate::item::enarxcall::{sgx, Stats, SYS_GETSTATS};
 use crate::item::syscall::sigaction;
 use crate::libc::{
     clockid_t, epoll_event, gid_t, mode_t, off_t, pid_t, pollfd, sigset_t, stack_t, stat, timespec,
@@ -16,8 +16,9 @@ use crate::libc::{
     SYS_mprotect, SYS_mremap, SYS_munmap, SYS_nanosleep, SYS_open, SYS_poll, SYS_read,
     SYS_readlink, SYS_readv, SYS_recvfrom, SYS_rt_sigaction, SYS_rt_sigprocmask, SYS_sendto,
     SYS_set_tid_address, SYS_setsockopt, SYS_sigaltstack, SYS_socket, SYS_sync, SYS_uname,
-    SYS_write, SYS_writev, EFAULT, EINVAL, ENOSYS, ENOTSUP, FIONBIO, FIONREAD, MAP_ANONYMOUS,
-    MAP_PRIVATE, MREMAP_DONTUNMAP, MREMAP_FIXED, MREMAP_MAYMOVE, PROT_EXEC, PROT_READ, PROT_WRITE,
+    SYS_write, SYS_writev, CLOCK_MONOTONIC, CLOCK_REALTIME, EFAULT, EINVAL, ENOSYS, ENOTSUP,
+    FIONBIO, FIONREAD, MAP_ANONYMOUS, MAP_PRIVATE, MREMAP_DONTUNMAP, MREMAP_FIXED, MREMAP_MAYMOVE,
+    PROT_EXEC, PROT_READ, PROT_WRITE,
 };
 use crate::{item, Result};
 
@@ -42,6 +43,22 @@ pub trait Handler {
     /// Returns a mutable borrow of shared [ThreadLocalStorage].
     fn thread_local_storage(&mut self) -> &mut ThreadLocalStorage;
 
+    /// Returns the current value of the time stamp counter, if it can be used
+    /// to serve clock reads without exiting to the host.
+    ///
+    /// See [`Clock`](super::Clock) for details.
+    #[inline]
+    fn tsc(&self) -> Option<u64> {
+        None
+    }
+
+    /// Returns the [`TimePage`](super::TimePage) shared with the exec, to which every host clock
+    /// sample is published, if any.
+    #[inline]
+    fn time_page(&self) -> Option<&super::TimePage> {
+        None
+    }
+
     /// Executes an arbitrary call.
     /// Examples of calls that this method can execute are:
     /// - [`syscall::Exit`]
@@ -66,6 +83,9 @@ pub trait Handler {
                 },
             );
             let collect = alloc.sally();
+            let stats = &mut self.thread_local_storage().stats;
+            stats.exits += 1;
+            stats.bytes += len as u64;
             self.sally()?;
             collect(self.block())?
         } else {
@@ -132,9 +152,49 @@ pub trait Handler {
     }
 
     /// Executes [`clock_gettime`](https://man7.org/linux/man-pages/man2/clock_gettime.2.html) syscall akin to [`libc::clock_gettime`].
-    #[inline]
+    ///
+    /// If [`tsc`](Handler::tsc) is available, `CLOCK_REALTIME` and `CLOCK_MONOTONIC` are served
+    /// from the [`Clock`](super::Clock) fast path and the host clocks are only sampled periodically.
+    /// Every sample is published to the [`time_page`](Handler::time_page), if any.
     fn clock_gettime(&mut self, clockid: clockid_t, tp: &mut timespec) -> Result<()> {
-        self.execute(syscall::ClockGettime { clockid, tp })?
+        let tsc = match self.tsc() {
+            Some(tsc) => tsc,
+            None => return self.execute(syscall::ClockGettime { clockid, tp })?,
+        };
+        if let Some(now) = self.thread_local_storage().clock.read(clockid, tsc) {
+            *tp = now;
+            return Ok(());
+        }
+
+        // Take a new sample of the host clocks.
+        let mut realtime = timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        let mut monotonic = realtime;
+        self.execute(syscall::ClockGettime {
+            clockid: CLOCK_REALTIME,
+            tp: &mut realtime,
+        })??;
+        let tsc = self.tsc().unwrap_or(tsc);
+        self.execute(syscall::ClockGettime {
+            clockid: CLOCK_MONOTONIC,
+            tp: &mut monotonic,
+        })??;
+
+        let clock = &mut self.thread_local_storage().clock;
+        clock.update(tsc, &realtime, &monotonic);
+        let sample = clock.sample();
+        if let Some(page) = self.time_page() {
+            page.publish(&sample);
+        }
+        match self.thread_local_storage().clock.read(clockid, tsc) {
+            Some(now) => {
+                *tp = now;
+                Ok(())
+            }
+            None => self.execute(syscall::ClockGettime { clockid, tp })?,
+        }
     }
 
     /// Executes [`close`](https://man7.org/linux/man-pages/man2/close.2.html) syscall akin to [`libc::close`].
@@ -630,6 +690,15 @@ pub trait Handler {
             .unwrap_or_else(|| self.attacked())
     }
 
+    /// Copies the event counters of the keep since its start into `stats` without exiting to the host.
+    ///
+    /// Invoked via the [`SYS_GETSTATS`] enarxcall.
+    #[inline]
+    fn get_stats(&mut self, stats: &mut Stats) -> Result<()> {
+        *stats = self.thread_local_storage().stats;
+        Ok(())
+    }
+
     /// Executes a supported syscall expressed as an opaque 7-word array akin to [`libc::syscall`].
     ///
     /// # Safety
@@ -645,6 +714,9 @@ pub trait Handler {
         registers: [usize; 7],
     ) -> Result<[usize; 2]> {
         let [num, argv @ ..] = registers;
+        if let Some(count) = self.thread_local_storage().stats.syscalls.get_mut(num) {
+            *count += 1;
+        }
         #[allow(non_upper_case_globals)]
         match (num as _, argv) {
             (SYS_accept, [sockfd, addr, addrlen, ..]) => {
@@ -735,6 +807,10 @@ pub trait Handler {
             (SYS_geteuid, ..) => self.geteuid().map(|ret| [ret as _, 0]),
             (SYS_getgid, ..) => self.getgid().map(|ret| [ret as _, 0]),
             (SYS_getpid, ..) => self.getpid().map(|ret| [ret as _, 0]),
+            (SYS_GETSTATS, [stats, ..]) => {
+                let stats = platform.validate_mut(stats)?;
+                self.get_stats(stats).map(|_| [0, 0])
+            }
             (SYS_getrandom, [buf, buflen, flags, ..]) => {
                 let buf = platform.validate_slice_mut(buf, buflen)?;
                 self.getrandom(buf, flags as _).map(|ret| [ret as _, 0])
@@ -55,11 +55,15 @@
 pub mod alloc;
 pub mod call;
 
+mod clock;
 mod handler;
 mod platform;
+mod process;
 mod tls;
 
 pub use call::{enarxcall, gdbcall, syscall, Call};
+pub use clock::*;
 pub use handler::*;
 pub use platform::*;
+pub use process::*;
 pub use tls::*;
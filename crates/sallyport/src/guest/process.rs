@@ -0,0 +1,121 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Diagnostics for process management syscalls, which are not supported inside a keep.
+
+use crate::libc::{
+    SYS_clone, SYS_execve, SYS_execveat, SYS_fork, SYS_vfork, SYS_wait4, CLONE_THREAD,
+};
+
+use core::ffi::c_ulong;
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const SPAWN: &str = "A keep runs a single process, so child processes cannot be created. \
+This is usually caused by `std::process::Command`, `posix_spawn`, `system` or `popen`.";
+
+const EXEC: &str = "A keep cannot execute other programs. \
+This is usually caused by `std::process::Command` or one of the `exec` functions.";
+
+const WAIT: &str = "A keep has no child processes to wait for. \
+This is usually caused by `std::process::Child::wait` or `waitpid`.";
+
+/// Process management syscalls with their compatibility notes.
+const DENIED: &[(usize, &str, &str)] = &[
+    (SYS_clone as _, "clone", SPAWN),
+    (SYS_fork as _, "fork", SPAWN),
+    (SYS_vfork as _, "vfork", SPAWN),
+    (SYS_execve as _, "execve", EXEC),
+    (SYS_execveat as _, "execveat", EXEC),
+    (SYS_wait4 as _, "wait4", WAIT),
+];
+
+/// Bitmask of the entries of [`DENIED`], which were reported already.
+static REPORTED: AtomicUsize = AtomicUsize::new(0);
+
+/// A process management syscall attempted inside a keep
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ProcessManagement {
+    index: usize,
+    num: usize,
+}
+
+impl ProcessManagement {
+    /// Returns the process management syscall `num` with arguments `argv`, if it is one.
+    ///
+    /// `clone` creating a thread is not considered process management.
+    pub fn classify(num: usize, argv: &[usize]) -> Option<Self> {
+        let flags = argv.first().copied().unwrap_or_default() as c_ulong;
+        if num == SYS_clone as usize && flags & CLONE_THREAD != 0 {
+            return None;
+        }
+        let index = DENIED.iter().position(|(denied, ..)| *denied == num)?;
+        Some(Self { index, num })
+    }
+
+    /// Returns the name of the syscall.
+    pub fn name(&self) -> &'static str {
+        DENIED[self.index].1
+    }
+
+    /// Returns the compatibility note pointing at the usual callers of the syscall.
+    pub fn note(&self) -> &'static str {
+        DENIED[self.index].2
+    }
+
+    /// Returns whether this is the first attempt of the syscall, such that it is only reported once.
+    pub fn first(&self) -> bool {
+        REPORTED.fetch_or(1 << self.index, Ordering::Relaxed) & (1 << self.index) == 0
+    }
+
+    /// Returns the diagnostic of the syscall attempted at the instruction pointer `rip`.
+    pub fn diagnostic(self, rip: usize) -> Diagnostic {
+        Diagnostic { syscall: self, rip }
+    }
+}
+
+/// The diagnostic of a process management syscall attempted inside a keep
+pub struct Diagnostic {
+    syscall: ProcessManagement,
+    rip: usize,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "enarx: process management syscall `{}` ({}) attempted at {:#x} is not supported inside a keep and fails with ENOSYS. {}",
+            self.syscall.name(),
+            self.syscall.num,
+            self.rip,
+            self.syscall.note()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProcessManagement;
+    use crate::libc::{SYS_clone, SYS_fork, SYS_write, CLONE_THREAD};
+
+    #[test]
+    fn classify() {
+        let fork = ProcessManagement::classify(SYS_fork as _, &[0; 6]).unwrap();
+        assert_eq!(fork.name(), "fork");
+
+        let clone = ProcessManagement::classify(SYS_clone as _, &[0x11, 0, 0, 0, 0, 0]).unwrap();
+        assert_eq!(clone.name(), "clone");
+        assert_eq!(clone.note(), fork.note());
+        assert_eq!(
+            ProcessManagement::classify(SYS_clone as _, &[CLONE_THREAD as _, 0, 0, 0, 0, 0]),
+            None
+        );
+        assert_eq!(ProcessManagement::classify(SYS_write as _, &[0; 6]), None);
+
+        assert!(fork.first());
+        assert!(!fork.first());
+
+        assert!(fork.diagnostic(0x1000).to_string().starts_with(
+            "enarx: process management syscall `fork` (57) attempted at 0x1000 is not supported"
+        ));
+    }
+}
@@ -1,5 +1,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
+use super::Clock;
+use crate::item::enarxcall::Stats;
 use crate::item::syscall::sigaction;
 
 use core::ffi::c_int;
@@ -9,6 +11,8 @@ pub(super) const SIGRTMAX: c_int = 64;
 /// Thread-local storage shared between [`Handler`](super::Handler) instances.
 pub struct ThreadLocalStorage {
     pub(super) actions: [Option<sigaction>; SIGRTMAX as _],
+    pub(super) clock: Clock,
+    pub(super) stats: Stats,
 }
 
 impl ThreadLocalStorage {
@@ -16,6 +20,8 @@ impl ThreadLocalStorage {
     pub const fn new() -> Self {
         Self {
             actions: [None; SIGRTMAX as _],
+            clock: Clock::new(),
+            stats: Stats::new(),
         }
     }
 }
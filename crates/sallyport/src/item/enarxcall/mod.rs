@@ -19,6 +19,47 @@ pub const SYS_GETATT: i64 = 0xEA01;
 #[allow(dead_code)]
 pub const SYS_GETKEY: i64 = 0xEA02;
 
+/// `get_stats` syscall number used by the shim.
+///
+/// Copies the [`Stats`] of the keep into the buffer pointed to by the first argument.
+#[allow(dead_code)]
+pub const SYS_GETSTATS: i64 = 0xEA03;
+
+/// Number of syscall numbers tracked individually in [`Stats::syscalls`].
+pub const STATS_SYSCALLS: usize = 512;
+
+/// Event counters of a keep since its start, as returned by [`SYS_GETSTATS`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct Stats {
+    /// Number of exits to the host.
+    pub exits: u64,
+
+    /// Number of bytes passed through the sallyport block.
+    pub bytes: u64,
+
+    /// Number of syscalls handled, indexed by syscall number.
+    pub syscalls: [u64; STATS_SYSCALLS],
+}
+
+impl Stats {
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            exits: 0,
+            bytes: 0,
+            syscalls: [0; STATS_SYSCALLS],
+        }
+    }
+}
+
+impl Default for Stats {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Payload of an [`Item`](super::Item) of [`Kind::Enarxcall`](super::Kind::Enarxcall).
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(C, align(8))]
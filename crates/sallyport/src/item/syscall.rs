@@ -34,6 +34,12 @@ pub const ARCH_GET_FS: c_int = 0x1003;
 /// missing in libc
 pub const ARCH_GET_GS: c_int = 0x1004;
 
+/// Exclusive upper bound of the user address space.
+///
+/// `arch_prctl(ARCH_SET_FS)` and `arch_prctl(ARCH_SET_GS)` fail with `EPERM` for base addresses
+/// at or above it, like on Linux.
+pub const TASK_SIZE_MAX: u64 = (1 << 47) - 4096;
+
 // [`libc::sigaction`] is not in the format used by the kernel.
 /// sigaction as expected by the kernel.
 #[allow(non_camel_case_types)] // follow `libc` conventions
@@ -175,6 +175,9 @@ pub struct utsname {
 }
 
 pub const AF_INET: c_int = 2;
+pub const CLOCK_MONOTONIC: clockid_t = 1;
+pub const CLOCK_REALTIME: clockid_t = 0;
+pub const CLONE_THREAD: c_ulong = 0x10000;
 pub const EACCES: c_int = 13;
 pub const EAGAIN: c_int = 11;
 pub const EBADF: c_int = 9;
@@ -230,6 +233,7 @@ pub const SYS_bind: c_long = 49;
 pub const SYS_brk: c_long = 12;
 pub const SYS_clock_getres: c_long = 229;
 pub const SYS_clock_gettime: c_long = 228;
+pub const SYS_clone: c_long = 56;
 pub const SYS_close: c_long = 3;
 pub const SYS_connect: c_long = 42;
 pub const SYS_dup: c_long = 32;
@@ -240,9 +244,12 @@ pub const SYS_epoll_ctl: c_long = 233;
 pub const SYS_epoll_pwait: c_long = 281;
 pub const SYS_epoll_wait: c_long = 232;
 pub const SYS_eventfd2: c_long = 290;
+pub const SYS_execve: c_long = 59;
+pub const SYS_execveat: c_long = 322;
 pub const SYS_exit: c_long = 60;
 pub const SYS_exit_group: c_long = 231;
 pub const SYS_fcntl: c_long = 72;
+pub const SYS_fork: c_long = 57;
 pub const SYS_fstat: c_long = 5;
 pub const SYS_getegid: c_long = 108;
 pub const SYS_geteuid: c_long = 107;
@@ -274,6 +281,8 @@ pub const SYS_sigaltstack: c_long = 131;
 pub const SYS_socket: c_long = 41;
 pub const SYS_sync: c_long = 162;
 pub const SYS_uname: c_long = 63;
+pub const SYS_vfork: c_long = 58;
+pub const SYS_wait4: c_long = 61;
 pub const SYS_write: c_long = 1;
 pub const SYS_writev: c_long = 20;
 pub const TIOCGWINSZ: Ioctl = 0x5413;
@@ -12,8 +12,8 @@ use libc::{
     SYS_sigaltstack, SYS_socket, SYS_uname, SYS_write, SYS_writev, AF_INET, CLOCK_MONOTONIC,
     CLOCK_REALTIME, EACCES, EBADF, EBADFD, EINVAL, ENOENT, ENOSYS, ENOTSUP, F_GETFD, F_GETFL,
     F_SETFD, F_SETFL, GRND_RANDOM, MREMAP_DONTUNMAP, MREMAP_FIXED, MREMAP_MAYMOVE, MSG_NOSIGNAL,
-    O_APPEND, O_CREAT, O_RDONLY, O_RDWR, O_WRONLY, SIGCHLD, SIG_BLOCK, SOCK_CLOEXEC, SOCK_STREAM,
-    SOL_SOCKET, SO_RCVTIMEO, SO_REUSEADDR, STDERR_FILENO, STDIN_FILENO, STDOUT_FILENO,
+    O_APPEND, O_CREAT, O_RDONLY, O_RDWR, O_WRONLY, POLLIN, SIGCHLD, SIG_BLOCK, SOCK_CLOEXEC,
+    SOCK_STREAM, SOL_SOCKET, SO_RCVTIMEO, SO_REUSEADDR, STDERR_FILENO, STDIN_FILENO, STDOUT_FILENO,
 };
 use std::env::temp_dir;
 use std::ffi::CString;
@@ -770,6 +770,33 @@ fn poll() {
     });
 }
 
+#[test]
+#[serial]
+fn poll_ready() {
+    let dev_null_0 = dev_null().into_raw_fd();
+    let dev_null_1 = dev_null().into_raw_fd();
+    let dev_null_2 = dev_null().into_raw_fd();
+
+    run_test(1, [0xff; 16], move |_, _, handler| {
+        let mut fds = [dev_null_0, dev_null_1, dev_null_2].map(|fd| pollfd {
+            fd,
+            events: 0,
+            revents: 0,
+        });
+        fds[2].events = POLLIN;
+
+        // Only the last file descriptor is ready, so its events must be
+        // returned even though `poll` reports a single ready descriptor.
+        let ret = handler.poll(unsafe { transmute::<_, &mut [_; 3]>(&mut fds) }, 0);
+        if cfg!(not(miri)) {
+            assert_eq!(ret, Ok(1));
+            assert_eq!(fds.map(|fd| fd.revents), [0, 0, POLLIN]);
+        } else {
+            assert_eq!(ret, Err(ENOSYS));
+        }
+    });
+}
+
 #[test]
 #[serial]
 fn read() {
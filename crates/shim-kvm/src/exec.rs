@@ -4,6 +4,7 @@
 
 use crate::addr::ShimPhysAddr;
 use crate::allocator::ALLOCATOR;
+use crate::hostcall::TIME_PAGE;
 use crate::random::random;
 use crate::shim_stack::init_stack_with_guard;
 use crate::snp::cpuid;
@@ -18,6 +19,7 @@ use goblin::elf::header::ELFMAG;
 use goblin::elf::program_header::program_header64::*;
 use lset::Line;
 use nbytes::bytes;
+use sallyport::guest::TIME_PAGE_ENV;
 use spinning::{Lazy, RwLock};
 use x86_64::structures::paging::{Page, PageTableFlags, Size4KiB};
 use x86_64::{PhysAddr, VirtAddr};
@@ -38,6 +40,9 @@ const EXEC_STACK_VIRT_ADDR_BASE: VirtAddr = VirtAddr::new_truncate(0x7ff0_0000_0
 #[allow(clippy::integer_arithmetic)]
 const EXEC_STACK_SIZE: u64 = bytes![2; MiB];
 
+/// The virtual address of the time page in the exec
+const EXEC_TIME_PAGE_VIRT_ADDR: VirtAddr = VirtAddr::new_truncate(0x7fe0_0000_0000);
+
 /// The randomized virtual address of the exec
 #[cfg(not(feature = "gdb"))]
 pub static EXEC_VIRT_ADDR: Lazy<RwLock<VirtAddr>> = Lazy::new(|| {
@@ -122,6 +127,25 @@ fn map_elf(app_virt_start: VirtAddr) -> &'static Header {
     header
 }
 
+/// map the time page read-only into the exec
+fn map_time_page() {
+    let time_page_phys = ShimPhysAddr::try_from(&TIME_PAGE as *const _)
+        .unwrap()
+        .raw()
+        .raw();
+
+    ALLOCATOR
+        .lock()
+        .map_memory(
+            PhysAddr::new(time_page_phys),
+            EXEC_TIME_PAGE_VIRT_ADDR,
+            Page::<Size4KiB>::SIZE as _,
+            PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::NO_EXECUTE,
+            PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE,
+        )
+        .expect("Map time page failed!");
+}
+
 fn crt0setup(
     app_virt_start: VirtAddr,
     stack_slice: &'static mut [u8],
@@ -142,6 +166,20 @@ fn crt0setup(
     // inside the keep. The actual implementation may be completely different.
     builder.push("ENARX_STDIO_FDS=0,1,2").unwrap();
     builder.push("ENARX_MODULE_FD=3").unwrap();
+
+    // `<TIME_PAGE_ENV>=<hex address>`
+    let mut time_page_env = [0u8; TIME_PAGE_ENV.len() + 17];
+    let (name, value) = time_page_env.split_at_mut(TIME_PAGE_ENV.len() + 1);
+    name[..TIME_PAGE_ENV.len()].copy_from_slice(TIME_PAGE_ENV.as_bytes());
+    name[TIME_PAGE_ENV.len()] = b'=';
+    for (i, digit) in value.iter_mut().enumerate() {
+        let nibble = (EXEC_TIME_PAGE_VIRT_ADDR.as_u64() >> ((15 - i) * 4)) & 0xf;
+        *digit = b"0123456789abcdef"[nibble as usize];
+    }
+    builder
+        .push(core::str::from_utf8(&time_page_env).unwrap())
+        .unwrap();
+
     builder
         .push("RUST_LOG=enarx=debug,enarx-exec-wasmtime=debug")
         .unwrap();
@@ -187,6 +225,7 @@ fn crt0setup(
 /// execute the exec
 pub fn execute_exec() -> ! {
     let header = map_elf(*EXEC_VIRT_ADDR.read());
+    map_time_page();
 
     let stack = init_stack_with_guard(
         EXEC_STACK_VIRT_ADDR_BASE + (random() & 0xFFFF_F000),
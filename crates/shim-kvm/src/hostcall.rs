@@ -9,21 +9,22 @@ use crate::exec::{BRK_LINE, NEXT_MMAP_RWLOCK};
 use crate::paging::SHIM_PAGETABLE;
 use crate::snp::attestation::asn1_encode_report_vcek;
 use crate::snp::ghcb::{GHCB, GHCB_EXT, SNP_ATTESTATION_LEN_MAX, SNP_KEY_LEN};
-use crate::snp::snp_active;
+use crate::snp::{cpuid, snp_active};
 use crate::spin::{RacyCell, RwLocked};
 
 use const_default::ConstDefault;
+use core::arch::x86_64::_rdtsc;
 use core::ffi::{c_int, c_size_t, c_ulong, c_void};
 use core::ops::{Deref, DerefMut};
 use core::ptr::NonNull;
 use core::slice;
 
-use sallyport::guest::{self, Handler, Platform, ThreadLocalStorage};
+use sallyport::guest::{self, Handler, Platform, ThreadLocalStorage, TimePage};
 use sallyport::item::enarxcall::sev::TECH;
 use sallyport::item::syscall;
 use sallyport::libc::{
-    off_t, EAGAIN, EFAULT, EINVAL, EIO, EMSGSIZE, ENOMEM, MAP_ANONYMOUS, MAP_PRIVATE, PROT_EXEC,
-    PROT_WRITE,
+    off_t, EAGAIN, EFAULT, EINVAL, EIO, EMSGSIZE, ENOMEM, EPERM, MAP_ANONYMOUS, MAP_PRIVATE,
+    PROT_EXEC, PROT_WRITE,
 };
 use sallyport::util::ptr::is_aligned_non_null;
 use sallyport::{libc, KVM_SYSCALL_TRIGGER_PORT};
@@ -45,6 +46,12 @@ const BLOCK_SIZE_USIZE: usize = BLOCK_SIZE / core::mem::size_of::<usize>();
 pub static SHIM_LOCAL_STORAGE: Lazy<RwLocked<guest::ThreadLocalStorage>> =
     Lazy::new(|| RwLocked::<guest::ThreadLocalStorage>::new(guest::ThreadLocalStorage::new()));
 
+/// Whether the TSC runs at a constant rate, which is required for the clock fast path
+static TSC_INVARIANT: Lazy<bool> = Lazy::new(|| cpuid(0x8000_0007).edx & (1 << 8) != 0);
+
+/// The host clock samples, mapped read-only into the exec
+pub static TIME_PAGE: TimePage = TimePage::new();
+
 const SNP_VCEK_BUF_SIZE: usize = 4096;
 
 /// SNP VCEK buffer
@@ -308,6 +315,16 @@ impl Handler for HostCall<'_> {
         self.tls
     }
 
+    #[inline]
+    fn tsc(&self) -> Option<u64> {
+        TSC_INVARIANT.then(|| unsafe { _rdtsc() })
+    }
+
+    #[inline]
+    fn time_page(&self) -> Option<&TimePage> {
+        Some(&TIME_PAGE)
+    }
+
     fn arch_prctl(
         &mut self,
         platform: &impl Platform,
@@ -315,8 +332,11 @@ impl Handler for HostCall<'_> {
         addr: c_ulong,
     ) -> sallyport::Result<()> {
         match code {
+            syscall::ARCH_SET_FS | syscall::ARCH_SET_GS if addr >= syscall::TASK_SIZE_MAX => {
+                eprintln!("SC> arch_prctl({:#x}, {:#x}) = -EPERM", code, addr);
+                Err(EPERM)
+            }
             syscall::ARCH_SET_FS => {
-                // FIXME: check `addr` value
                 unsafe {
                     FS::write_base(VirtAddr::new(addr));
                 }
@@ -329,7 +349,6 @@ impl Handler for HostCall<'_> {
                 Ok(())
             }
             syscall::ARCH_SET_GS => {
-                // FIXME: check `addr` value
                 unsafe {
                     GS::write_base(VirtAddr::new(addr));
                 }
@@ -832,7 +832,8 @@ impl Locked<&mut GhcbExtHandle> {
             root_key_select: 0,
             _rsvd: 0,
             guest_field_select: GuestFieldSelect::GUEST_SVN.bits
-                | GuestFieldSelect::GUEST_POLICY.bits,
+                | GuestFieldSelect::GUEST_POLICY.bits
+                | GuestFieldSelect::MEASUREMENT.bits,
             vmpl: 0,
             guest_svn,
             tcb_version: 0,
@@ -9,7 +9,7 @@ use core::arch::asm;
 use core::mem::size_of;
 
 use sallyport::guest;
-use sallyport::guest::Handler;
+use sallyport::guest::{Handler, ProcessManagement};
 use sallyport::item::enarxcall::{SYS_GETATT, SYS_GETKEY};
 #[cfg(feature = "dbg")]
 use sallyport::libc::{SYS_write, STDERR_FILENO, STDOUT_FILENO};
@@ -65,13 +65,19 @@ pub unsafe extern "sysv64" fn _syscall_enter() -> ! {
         "push   r9",
         "push   r8",
 
+        // keep the stack 16-byte aligned for the call
+        "sub    rsp,                    0x8",
+
+        // userspace return pointer on the stack as the eighth argument
+        "push   QWORD PTR [rbp + 0x8]",
+
         // syscall number on the stack as the seventh argument
         "push   rax",
 
         "call   {syscall_rust}",
 
-        // skip rax pop, as it is the return value
-        "add    rsp,                    0x8",
+        // skip rax pop, as it is the return value, the userspace return pointer and the alignment
+        "add    rsp,                    0x18",
 
         // restore registers
         "pop    r8",
@@ -121,9 +127,17 @@ extern "sysv64" fn syscall_rust(
     e: usize,
     f: usize,
     nr: usize,
+    rip: usize,
 ) -> X8664DoubleReturn {
     let orig_rdx: usize = c;
 
+    // Tell the user which process management syscall failed and where it came from.
+    if let Some(syscall) = ProcessManagement::classify(nr, &[a, b, c, d, e, f]) {
+        if syscall.first() {
+            crate::print::_eprint(format_args!("{}\n", syscall.diagnostic(rip)));
+        }
+    }
+
     #[cfg(feature = "dbg")]
     if !(nr == SYS_write as usize && (a == STDERR_FILENO as usize || a == STDOUT_FILENO as usize)) {
         eprintln!("syscall {} …", nr)
@@ -42,11 +42,12 @@ use core::ptr::NonNull;
 use mmledger::Access;
 use primordial::{Address, Offset, Page};
 use sallyport::guest::Handler as _;
-use sallyport::guest::{self, Platform, ThreadLocalStorage};
+use sallyport::guest::{self, Platform, ProcessManagement, ThreadLocalStorage};
 use sallyport::item::enarxcall::sgx::{Report, ReportData, TargetInfo, TECH};
 use sallyport::item::enarxcall::{SYS_GETATT, SYS_GETKEY};
+use sallyport::item::syscall::{ARCH_GET_FS, ARCH_GET_GS, ARCH_SET_FS, ARCH_SET_GS, TASK_SIZE_MAX};
 use sallyport::libc::{
-    off_t, EACCES, EINVAL, EIO, EMSGSIZE, ENOMEM, ENOSYS, ENOTSUP, MAP_ANONYMOUS, MAP_PRIVATE,
+    off_t, EACCES, EINVAL, EIO, EMSGSIZE, ENOMEM, ENOTSUP, EPERM, MAP_ANONYMOUS, MAP_PRIVATE,
     PROT_EXEC, PROT_READ, PROT_WRITE, STDERR_FILENO,
 };
 use sgx::page::{Class, Flags};
@@ -254,12 +255,42 @@ impl guest::Handler for Handler<'_> {
 
     fn arch_prctl(
         &mut self,
-        _platform: &impl Platform,
-        _code: c_int,
-        _addr: c_ulong,
+        platform: &impl Platform,
+        code: c_int,
+        addr: c_ulong,
     ) -> sallyport::Result<()> {
-        debugln!(self, "arch_prctl should have never been called");
-        Err(ENOSYS)
+        // The FS and GS bases of the exec are saved in the SSA of the current TCS
+        // and restored on `ERESUME`, so every thread has its own set.
+        match code {
+            ARCH_SET_FS | ARCH_SET_GS if addr >= TASK_SIZE_MAX => {
+                debugln!(self, "arch_prctl({:#x}, {:#x}) = -EPERM", code, addr);
+                Err(EPERM)
+            }
+            ARCH_SET_FS => {
+                self.ssa.gpr.fsbase = addr;
+                debugln!(self, "arch_prctl(ARCH_SET_FS, {:#x}) = 0", addr);
+                Ok(())
+            }
+            ARCH_GET_FS => {
+                let addr: &mut u64 = platform.validate_mut(addr as _)?;
+                *addr = self.ssa.gpr.fsbase;
+                Ok(())
+            }
+            ARCH_SET_GS => {
+                self.ssa.gpr.gsbase = addr;
+                debugln!(self, "arch_prctl(ARCH_SET_GS, {:#x}) = 0", addr);
+                Ok(())
+            }
+            ARCH_GET_GS => {
+                let addr: &mut u64 = platform.validate_mut(addr as _)?;
+                *addr = self.ssa.gpr.gsbase;
+                Ok(())
+            }
+            x => {
+                debugln!(self, "arch_prctl({:#x}, {:#x}) = -EINVAL", x, addr);
+                Err(EINVAL)
+            }
+        }
     }
 
     fn brk(
@@ -273,15 +304,25 @@ impl guest::Handler for Handler<'_> {
         );
 
         let mut heap = HEAP.write();
+        let prev = heap.brk_current();
         let max = heap.brk_max();
         let addr = heap.brk(addr);
 
         if addr > max {
-            self.mmap_host(
-                NonNull::new(max.raw() as *mut _).unwrap(),
-                addr.raw() - max.raw(),
-                PROT_READ | PROT_WRITE,
-            )?;
+            // The pages are added with ENCLS[EAUG] by the host on demand. If the
+            // host is unable to do so, e.g. due to EPC exhaustion, keep the
+            // previous `brk` just like Linux does on failure.
+            if self
+                .mmap_host(
+                    NonNull::new(max.raw() as *mut _).unwrap(),
+                    addr.raw() - max.raw(),
+                    PROT_READ | PROT_WRITE,
+                )
+                .is_err()
+            {
+                heap.brk_revert(prev, max).map_err(|_| ENOMEM)?;
+                return Ok(NonNull::new(prev.raw() as *mut _).unwrap());
+            }
             self.mmap_guest(max, addr - max, PROT_READ | PROT_WRITE);
         }
 
@@ -340,11 +381,15 @@ impl guest::Handler for Handler<'_> {
         if let Some(addr) = heap.mmap(None, length, access) {
             let ret = NonNull::new(addr.raw() as *mut c_void).unwrap();
 
-            self.mmap_host(
+            // Release the reservation, if the host is unable to back it with pages.
+            if let Err(e) = self.mmap_host(
                 NonNull::new(addr.raw() as *mut _).unwrap(),
                 length.bytes(),
                 PROT_READ | PROT_WRITE,
-            )?;
+            ) {
+                heap.munmap(addr, length).map_err(|_| ENOMEM)?;
+                return Err(e);
+            }
             self.mmap_guest(addr, length, prot);
 
             // If the previous operations succeeded, the virtual memory area
@@ -457,7 +502,7 @@ impl<'a> Handler<'a> {
 
         let key_request = key::Request {
             name: key::Names::SealKey,
-            policy: key::Policy::MRSIGNER,
+            policy: key::Policy::MRENCLAVE,
             isvsvn: 0,
             ..Default::default()
         };
@@ -559,22 +604,29 @@ impl<'a> Handler<'a> {
                 }
             }
             _ => unsafe {
+                let registers = [
+                    nr,
+                    self.ssa.gpr.rdi as usize,
+                    self.ssa.gpr.rsi as usize,
+                    self.ssa.gpr.rdx as usize,
+                    self.ssa.gpr.r10 as usize,
+                    self.ssa.gpr.r8 as usize,
+                    self.ssa.gpr.r9 as usize,
+                ];
+
+                // Tell the user which process management syscall failed and where it came from.
+                if let Some(syscall) = ProcessManagement::classify(nr, &registers[1..]) {
+                    if syscall.first() {
+                        let rip = self.ssa.gpr.rip as usize;
+                        let _ = writeln!(self, "{}", syscall.diagnostic(rip));
+                    }
+                }
+
                 // Safety:
                 // with `usermemscope` we
                 // * limit the lifetime of objects created from the userspace syscall arguments to this function.
                 // * make sure only memory of the userspace application is addressed
-                let ret = self.syscall(
-                    &usermemscope,
-                    [
-                        nr,
-                        self.ssa.gpr.rdi as usize,
-                        self.ssa.gpr.rsi as usize,
-                        self.ssa.gpr.rdx as usize,
-                        self.ssa.gpr.r10 as usize,
-                        self.ssa.gpr.r8 as usize,
-                        self.ssa.gpr.r9 as usize,
-                    ],
-                );
+                let ret = self.syscall(&usermemscope, registers);
                 match ret {
                     Err(e) => self.ssa.gpr.rax = -e as u64,
                     Ok([rax, _]) => {
@@ -694,3 +746,75 @@ impl<'a> Handler<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Barrier;
+    use std::thread;
+
+    /// The memory of the test process, which is all valid
+    struct TestPlatform;
+
+    impl Platform for TestPlatform {
+        fn validate_mut<T>(&self, ptr: usize) -> sallyport::Result<&mut T> {
+            Ok(unsafe { &mut *(ptr as *mut T) })
+        }
+
+        fn validate<T>(&self, ptr: usize) -> sallyport::Result<&T> {
+            Ok(unsafe { &*(ptr as *const T) })
+        }
+
+        fn validate_slice_mut<T: Sized>(
+            &self,
+            ptr: usize,
+            count: usize,
+        ) -> sallyport::Result<&mut [T]> {
+            Ok(unsafe { core::slice::from_raw_parts_mut(ptr as *mut T, count) })
+        }
+
+        fn validate_slice<T: Sized>(&self, ptr: usize, count: usize) -> sallyport::Result<&[T]> {
+            Ok(unsafe { core::slice::from_raw_parts(ptr as *const T, count) })
+        }
+    }
+
+    #[test]
+    fn arch_prctl_threads() {
+        const THREADS: u64 = 4;
+        let barrier = Barrier::new(THREADS as _);
+
+        // Every thread enters the enclave through its own TCS and so has its own SSA.
+        thread::scope(|scope| {
+            for i in 0..THREADS {
+                let barrier = &barrier;
+                scope.spawn(move || {
+                    let mut block = [0usize; 0];
+                    let mut ssa: StateSaveArea = unsafe { core::mem::zeroed() };
+                    let mut handler = Handler {
+                        block: &mut block,
+                        ssa: &mut ssa,
+                    };
+                    let (fs, gs) = (0x7f00_0000_0000 + i * 0x1000, 0x7e00_0000_0000 + i * 0x1000);
+                    handler.arch_prctl(&TestPlatform, ARCH_SET_FS, fs).unwrap();
+                    handler.arch_prctl(&TestPlatform, ARCH_SET_GS, gs).unwrap();
+
+                    // All threads have set their bases before any reads them back.
+                    barrier.wait();
+
+                    let (mut current_fs, mut current_gs) = (0u64, 0u64);
+                    let addr = &mut current_fs as *mut u64 as _;
+                    handler
+                        .arch_prctl(&TestPlatform, ARCH_GET_FS, addr)
+                        .unwrap();
+                    let addr = &mut current_gs as *mut u64 as _;
+                    handler
+                        .arch_prctl(&TestPlatform, ARCH_GET_GS, addr)
+                        .unwrap();
+                    assert_eq!((current_fs, current_gs), (fs, gs));
+                    assert_eq!((ssa.gpr.fsbase, ssa.gpr.gsbase), (fs, gs));
+                });
+            }
+        });
+    }
+}
@@ -37,6 +37,11 @@ impl Heap {
         self.ledger.contains(addr, length)
     }
 
+    /// Return the current `brk` address.
+    pub fn brk_current(&self) -> Address<usize, Page> {
+        self.brk
+    }
+
     /// Return the maximum `brk` address reached.
     pub fn brk_max(&self) -> Address<usize, Page> {
         self.brk_max
@@ -71,6 +76,21 @@ impl Heap {
         }
     }
 
+    /// Revert a growth of `brk` beyond `brk_max`, which could not be backed
+    /// by enclave pages, and restore `brk`.
+    pub fn brk_revert(
+        &mut self,
+        brk: Address<usize, Page>,
+        brk_max: Address<usize, Page>,
+    ) -> Result<(), mmledger::Error> {
+        if brk_max < self.brk_max {
+            self.ledger.unmap(brk_max, self.brk_max - brk_max)?;
+            self.brk_max = brk_max;
+        }
+        self.brk = brk;
+        Ok(())
+    }
+
     /// Find and reserve an address range.
     pub fn mmap(
         &mut self,
@@ -151,6 +171,22 @@ mod tests {
         }
     }
 
+    #[test]
+    fn brk_revert() {
+        let mut heap = Heap::new(Address::new(0), Address::new(BYTES));
+
+        let prev = heap.brk(Address::new(4 * Page::SIZE));
+        let next = heap.brk(Address::new(16 * Page::SIZE));
+        assert_eq!(next, Address::new(16 * Page::SIZE));
+
+        heap.brk_revert(prev, prev).unwrap();
+        assert_eq!(heap.brk_current(), prev);
+        assert_eq!(heap.brk_max(), prev);
+        for page in 0..PAGES {
+            assert_eq!(heap.is_allocated(page), page < 4);
+        }
+    }
+
     #[test]
     fn mmap_oversubscribe() {
         let mut heap = Heap::new(Address::new(0), Address::new(BYTES));
@@ -23,7 +23,7 @@ struct Segment<'a> {
 pub struct Binary<'a>(&'a [u8], Elf<'a>);
 
 impl<'a> Binary<'a> {
-    fn new(bytes: &'a [u8]) -> Result<Self> {
+    pub(super) fn new(bytes: &'a [u8]) -> Result<Self> {
         let elf = Elf::parse(bytes)?;
 
         if elf.header.e_ident[EI_CLASS] != ELFCLASS64 {
@@ -68,7 +68,8 @@ impl super::super::Mapper for Builder {
             self.regions.len() as _,
         )?;
 
-        self.regions.push(Region::new(mem_region, pages));
+        let region = Region::new(mem_region, pages).context("Failed to map keep memory")?;
+        self.regions.push(region);
 
         Ok(())
     }
@@ -30,6 +30,31 @@ pub fn kvm_version() -> Datum {
     }
 }
 
+/// Returns the total size of the active swap devices in KiB from the contents of `/proc/swaps`.
+fn swap_size(swaps: &str) -> u64 {
+    swaps
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(2)?.parse::<u64>().ok())
+        .sum()
+}
+
+pub fn swap() -> Datum {
+    let info = match std::fs::read_to_string("/proc/swaps").map(|swaps| swap_size(&swaps)) {
+        Ok(0) => "inactive".into(),
+        Ok(size) => format!("active ({} KiB)", size),
+        Err(_) => "unknown".into(),
+    };
+
+    // Swapping only affects performance, keep memory can be locked with `--memory-residency`.
+    Datum {
+        name: "Swap".into(),
+        pass: true,
+        info: Some(info),
+        mesg: None,
+    }
+}
+
 pub const CPUIDS: &[CpuId] = &[
     CpuId {
         name: "CPU",
@@ -53,3 +78,19 @@ pub const CPUIDS: &[CpuId] = &[
         vend: None,
     },
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::swap_size;
+
+    #[test]
+    fn swaps() {
+        const SWAPS: &str = "\
+Filename\t\t\t\tType\t\tSize\t\tUsed\t\tPriority
+/dev/dm-1                               partition\t8388604\t\t0\t\t-2
+/swapfile                               file\t\t1048572\t\t512\t\t-3
+";
+        assert_eq!(swap_size(SWAPS), 8388604 + 1048572);
+        assert_eq!(swap_size("Filename\tType\tSize\tUsed\tPriority\n"), 0);
+    }
+}
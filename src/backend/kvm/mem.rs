@@ -2,21 +2,102 @@
 
 use super::KvmUserspaceMemoryRegion;
 
+use std::str::FromStr;
+use std::sync::Once;
+use std::{error, fmt, io};
+
+use anyhow::anyhow;
+use log::warn;
 use lset::Span;
 use mmarinus::{perms, Map};
+use once_cell::sync::OnceCell;
 use x86_64::{PhysAddr, VirtAddr};
 
+/// Policy for keeping the backing memory of a keep resident in RAM
+///
+/// Swapping out the backing memory of a keep slows it down and, for encrypted guest memory,
+/// writes the ciphertext of the keep to the swap device.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Residency {
+    /// Lock the backing memory, but run the keep, if that fails
+    Preferred,
+
+    /// Lock the backing memory and refuse to run the keep, if that fails
+    Required,
+}
+
+impl FromStr for Residency {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "preferred" => Ok(Self::Preferred),
+            "required" => Ok(Self::Required),
+            _ => Err(anyhow!("unknown memory residency policy {:?}", s)),
+        }
+    }
+}
+
+/// Error of a keep, whose memory could not be locked with the `required` residency
+#[derive(Debug)]
+pub struct Unlocked(pub io::Error);
+
+impl fmt::Display for Unlocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to lock keep memory, is the MEMLOCK rlimit large enough? {}",
+            self.0
+        )
+    }
+}
+
+impl error::Error for Unlocked {}
+
+static RESIDENCY: OnceCell<Residency> = OnceCell::new();
+
+/// Sets the residency policy of the backing memory of all keeps created by this process.
+pub fn set_residency(residency: Residency) {
+    let _ = RESIDENCY.set(residency);
+}
+
+/// Locks `backing` in RAM according to the residency policy.
+fn lock(backing: &Map<perms::ReadWrite>) -> io::Result<()> {
+    let residency = match RESIDENCY.get() {
+        Some(residency) => residency,
+        None => return Ok(()),
+    };
+
+    if unsafe { libc::mlock(backing.addr() as *const _, backing.size()) } == 0 {
+        return Ok(());
+    }
+
+    let err = io::Error::last_os_error();
+    match residency {
+        Residency::Required => Err(io::Error::new(err.kind(), Unlocked(err))),
+        Residency::Preferred => {
+            static WARN: Once = Once::new();
+            WARN.call_once(|| warn!("failed to lock keep memory, it may be swapped out: {err}"));
+            Ok(())
+        }
+    }
+}
+
 pub struct Region {
     kvm_region: KvmUserspaceMemoryRegion,
     _backing: Map<perms::ReadWrite>,
 }
 
 impl Region {
-    pub fn new(kvm_region: KvmUserspaceMemoryRegion, backing: Map<perms::ReadWrite>) -> Self {
-        Self {
+    pub fn new(
+        kvm_region: KvmUserspaceMemoryRegion,
+        backing: Map<perms::ReadWrite>,
+    ) -> io::Result<Self> {
+        lock(&backing)?;
+        Ok(Self {
             kvm_region,
             _backing: backing,
-        }
+        })
     }
 
     #[allow(dead_code)]
@@ -3,7 +3,7 @@
 pub use kvm_bindings::kvm_userspace_memory_region as KvmUserspaceMemoryRegion;
 
 use super::Loader;
-use data::{dev_kvm, kvm_version, CPUIDS};
+use data::{dev_kvm, kvm_version, swap, CPUIDS};
 use mem::Region;
 
 use std::sync::Arc;
@@ -64,7 +64,7 @@ impl<P: KeepPersonality> Keep<P> {
 
         unsafe { self.vm_fd.set_user_memory_region(kvm_region)? };
 
-        let region = Region::new(kvm_region, pages);
+        let region = Region::new(kvm_region, pages)?;
 
         P::map(&mut self.vm_fd, &region)?;
 
@@ -99,7 +99,7 @@ impl crate::backend::Backend for Backend {
     }
 
     fn config(&self) -> Vec<super::Datum> {
-        vec![]
+        vec![swap()]
     }
 
     #[inline]
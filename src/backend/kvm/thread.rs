@@ -1,6 +1,6 @@
 // SPDX-License-Identifier: Apache-2.0
 
-use super::super::Command;
+use super::super::{stats, Command};
 use super::KeepPersonality;
 #[cfg(feature = "gdb")]
 use crate::backend::execute_gdb;
@@ -126,7 +126,12 @@ impl<P: KeepPersonality> Thread<P> {
 impl<P: KeepPersonality> super::super::Thread for Thread<P> {
     fn enter(&mut self, _gdblisten: &Option<String>) -> Result<Command> {
         let vcpu_fd = self.vcpu_fd.as_mut().unwrap();
-        match vcpu_fd.run()? {
+        let exit = match vcpu_fd.run() {
+            // A signal interrupted the vCPU, e.g. one forwarded to the keep to shut it down.
+            Err(e) if e.errno() == libc::EINTR => return Ok(Command::Continue),
+            exit => exit?,
+        };
+        match exit {
             VcpuExit::IoOut(KVM_SYSCALL_TRIGGER_PORT, data) => {
                 debug_assert_eq!(data.len(), 2);
                 let block_nr = data[0] as usize + ((data[1] as usize) << 8);
@@ -144,7 +149,9 @@ impl<P: KeepPersonality> super::super::Thread for Thread<P> {
                 }
                 .into();
 
+                stats::exit();
                 for item in block {
+                    stats::item(&item);
                     match item {
                         Item::Gdbcall(_gdbcall, _data) => {
                             #[cfg(feature = "gdb")]
@@ -10,6 +10,7 @@ pub mod sev;
 pub mod sgx;
 
 pub mod nil;
+pub mod signatures;
 
 #[cfg(enarx_with_shim)]
 mod binary;
@@ -17,19 +18,24 @@ mod binary;
 #[cfg(enarx_with_shim)]
 mod probe;
 
+#[cfg(enarx_with_shim)]
+pub mod stats;
+
 #[cfg(enarx_with_shim)]
 use binary::{Binary, Loader, Mapper};
 
-use std::fs::File;
-use std::io::Read;
-use std::sync::Arc;
+pub use signatures::{Mismatch, SevSignature, Signatures};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
 
-use anyhow::{bail, Context, Result};
-use camino::Utf8PathBuf;
+use anyhow::Result;
 #[cfg(windows)]
 use enarx_exec_wasmtime::Args;
+use enarx_exec_wasmtime::Skipped;
 use libc::c_int;
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 use serde::ser::{Serialize, SerializeStruct, Serializer};
 
 #[cfg(not(enarx_with_shim))]
@@ -38,57 +44,6 @@ pub struct Binary<'a> {
     phantom: std::marker::PhantomData<&'a ()>,
 }
 
-pub const SIGNATURES_VERSION: &str = env!("CARGO_PKG_VERSION");
-
-#[derive(Default, Debug, serde::Deserialize, serde::Serialize)]
-pub struct SevSignature {
-    pub id_block: Vec<u8>,
-    pub id_auth: Vec<u8>,
-}
-
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
-pub struct Signatures {
-    pub version: String,
-    pub sev: SevSignature,
-    pub sgx: Vec<u8>,
-}
-
-impl Default for Signatures {
-    fn default() -> Self {
-        Self {
-            version: SIGNATURES_VERSION.into(),
-            sev: SevSignature::default(),
-            sgx: vec![],
-        }
-    }
-}
-
-impl Signatures {
-    pub fn load(path: Option<Utf8PathBuf>) -> anyhow::Result<Option<Self>> {
-        match path {
-            None => Ok(None),
-            Some(path) => {
-                let mut file = File::open(path).context("Failed to open hashes file")?;
-                let mut buffer = String::new();
-                file.read_to_string(&mut buffer)?;
-                let ret = serde_json::from_str::<Signatures>(&buffer)
-                    .context("serde_json")
-                    .map(Some);
-                if let Ok(Some(Signatures { ref version, .. })) = ret {
-                    if version != SIGNATURES_VERSION {
-                        bail!(
-                            "Signature file version {} does not match current version {}",
-                            version,
-                            SIGNATURES_VERSION
-                        );
-                    }
-                }
-                ret
-            }
-        }
-    }
-}
-
 /// A trait for types that can be serialized and deserialized to/from a byte slice.
 ///
 /// # Safety
@@ -156,6 +111,11 @@ pub trait Backend: Sync + Send {
         !self.config().iter().fold(false, |e, d| e | !d.pass)
     }
 
+    /// Whether or not keeps of this type can be attested on this machine
+    fn attestation(&self) -> bool {
+        false
+    }
+
     #[cfg(windows)]
     /// set wasmtime args directly
     fn set_args(&self, _args: Args) {}
@@ -163,9 +123,13 @@ pub trait Backend: Sync + Send {
 
 impl Serialize for dyn Backend {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        let mut backend = serializer.serialize_struct("Backend", 2)?;
+        let mut backend = serializer.serialize_struct("Backend", 6)?;
         backend.serialize_field("backend", self.name())?;
+        backend.serialize_field("available", &self.have())?;
+        backend.serialize_field("configured", &self.configured())?;
+        backend.serialize_field("attestation", &self.attestation())?;
         backend.serialize_field("data", &self.data())?;
+        backend.serialize_field("config", &self.config())?;
         backend.end()
     }
 }
@@ -262,6 +226,78 @@ pub static BACKENDS: Lazy<Vec<Box<dyn Backend>>> = Lazy::new(|| {
     ]
 });
 
+/// Usability of a backend on this host
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Probe {
+    Usable,
+    Unavailable,
+    Misconfigured,
+}
+
+impl Probe {
+    /// Returns why a backend, which is not usable, is skipped.
+    pub fn reason(self) -> &'static str {
+        match self {
+            Self::Usable => "usable",
+            Self::Unavailable => "unavailable",
+            Self::Misconfigured => "misconfigured",
+        }
+    }
+}
+
+/// Results of probing the backends, which do not change for the lifetime of the process
+static PROBES: Lazy<Mutex<HashMap<&'static str, Probe>>> = Lazy::new(Default::default);
+
+/// Returns the usability of every backend in `backends`.
+///
+/// Probing opens devices and reads CPUID, which takes long on some hosts, e.g. for a missing
+/// `/dev/sev`, so the backends, which have not been probed yet, are probed concurrently.
+pub fn probe(backends: &[&'static dyn Backend]) -> Vec<Probe> {
+    let cached = PROBES.lock().unwrap().clone();
+    let probed: Vec<_> = thread::scope(|scope| {
+        // All probes are spawned before any is joined, such that they run concurrently.
+        #[allow(clippy::needless_collect)]
+        let probes: Vec<_> = backends
+            .iter()
+            .filter(|backend| !cached.contains_key(backend.name()))
+            .map(|backend| {
+                scope.spawn(move || {
+                    let probe = if !backend.have() {
+                        Probe::Unavailable
+                    } else if !backend.configured() {
+                        Probe::Misconfigured
+                    } else {
+                        Probe::Usable
+                    };
+                    (backend.name(), probe)
+                })
+            })
+            .collect();
+        probes.into_iter().map(|p| p.join().unwrap()).collect()
+    });
+
+    let mut probes = PROBES.lock().unwrap();
+    probes.extend(probed);
+    backends
+        .iter()
+        .map(|backend| probes[backend.name()])
+        .collect()
+}
+
+/// Backends skipped by `--backend auto` before the backend of the keep was selected
+static SKIPPED: OnceCell<Vec<Skipped>> = OnceCell::new();
+
+/// Records the backends skipped by `--backend auto`, which are reported with the selected one.
+pub fn set_skipped(skipped: Vec<Skipped>) {
+    let _ = SKIPPED.set(skipped);
+}
+
+/// Returns the backends skipped by `--backend auto`, if any.
+#[cfg(unix)]
+pub fn skipped() -> Vec<Skipped> {
+    SKIPPED.get().cloned().unwrap_or_default()
+}
+
 #[cfg(feature = "gdb")]
 pub fn wait_for_gdb_connection(sockaddr: &str) -> std::io::Result<std::net::TcpStream> {
     use std::net::TcpListener;
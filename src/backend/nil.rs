@@ -6,6 +6,7 @@ use crate::backend::Signatures;
 use anyhow::{bail, Result};
 #[cfg(windows)]
 use enarx_exec_wasmtime::Args;
+use enarx_exec_wasmtime::ExitCode;
 
 #[cfg(unix)]
 #[derive(Default)]
@@ -105,12 +106,18 @@ struct Thread(Option<Args>);
 impl super::Thread for Thread {
     fn enter(&mut self, _gdblisten: &Option<String>) -> Result<super::Command> {
         #[cfg(unix)]
-        enarx_exec_wasmtime::execute()?;
+        let ret = enarx_exec_wasmtime::execute();
 
         #[cfg(windows)]
-        enarx_exec_wasmtime::execute_with_args(self.0.take().unwrap())?;
-
-        Ok(super::Command::Exit(0))
+        let ret = enarx_exec_wasmtime::execute_with_args(self.0.take().unwrap());
+
+        match ret {
+            Ok(()) => Ok(super::Command::Exit(0)),
+            Err(e) => match e.downcast_ref::<ExitCode>() {
+                Some(&ExitCode(code)) => Ok(super::Command::Exit(code)),
+                None => Err(e),
+            },
+        }
     }
 }
 
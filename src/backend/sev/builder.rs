@@ -1,6 +1,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::cpuid_page::CpuidPage;
+use super::snp;
 use super::snp::firmware::Firmware;
 use super::snp::launch::*;
 
@@ -8,7 +9,7 @@ use super::SnpKeepPersonality;
 use crate::backend::kvm::builder::kvm_try_from_builder;
 use crate::backend::kvm::mem::Region;
 use crate::backend::sev::config::Config;
-use crate::backend::ByteSized;
+use crate::backend::{ByteSized, Mismatch};
 
 use std::convert::TryFrom;
 use std::sync::{Arc, RwLock};
@@ -65,6 +66,10 @@ impl TryFrom<super::config::Config> for Builder {
     type Error = Error;
 
     fn try_from(config: super::config::Config) -> anyhow::Result<Self> {
+        // Fail early with a meaningful error instead of the one of SNP_LAUNCH_START.
+        let mut sev = retry(|| Firmware::open().context("Failed to open '/dev/sev'"))?;
+        super::policy::check(config.parameters.policy, &mut sev)?;
+
         let (kvm_fd, launcher) = retry(|| {
             // try to open /dev/sev and start the Launcher several times
 
@@ -177,7 +182,8 @@ impl super::super::Mapper for Builder {
                 .context("SNP Launcher update_data failed")?;
         };
 
-        self.regions.push(Region::new(mem_region, pages));
+        let region = Region::new(mem_region, pages).context("Failed to map keep memory")?;
+        self.regions.push(region);
 
         Ok(())
     }
@@ -203,6 +209,7 @@ impl TryFrom<Builder> for Arc<dyn super::super::Keep> {
 
         let vcpu_fd = kvm_try_from_builder(&sallyports, &mut kvm_fd, launcher.as_mut())?;
 
+        let signed = signatures.is_some();
         let finish = if let Some(signatures) = signatures {
             let sig_blob = signatures.sev;
 
@@ -211,14 +218,28 @@ impl TryFrom<Builder> for Arc<dyn super::super::Keep> {
             id_block = IdBlock::from_bytes(&sig_blob.id_block)
                 .ok_or_else(|| anyhow!("Invalid SEV signature IdBlock blob size."))?;
 
-            Finish::new(Some((&id_block, &id_auth)), true, [0u8; 32])
+            Finish::new(
+                Some((&id_block, &id_auth)),
+                id_auth.has_author_key(),
+                [0u8; 32],
+            )
         } else {
             Finish::new(None, false, [0u8; 32])
         };
 
-        let (vm_fd, sev_fd) = launcher
-            .finish(finish)
-            .context("SNP Launcher finish failed")?;
+        let (vm_fd, sev_fd) = launcher.finish(finish).map_err(|e| {
+            // The firmware rejects an ID block, which does not cover the measurement of the keep.
+            let rejected = matches!(
+                e.get_ref().and_then(|e| e.downcast_ref()),
+                Some(snp::Error::BadMeasurement | snp::Error::BadSignature)
+            );
+            let e = Error::new(e).context("SNP Launcher finish failed");
+            if signed && rejected {
+                e.context(Mismatch { backend: "sev" })
+            } else {
+                e
+            }
+        })?;
 
         Ok(Arc::new(RwLock::new(super::Keep::<SnpKeepPersonality> {
             kvm_fd,
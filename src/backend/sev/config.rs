@@ -2,8 +2,8 @@
 
 use super::snp::Parameters;
 use crate::backend::sev::snp::launch::IdBlock;
-use crate::backend::Signatures;
-use anyhow::{anyhow, Result};
+use crate::backend::{ByteSized, Signatures};
+use anyhow::{anyhow, bail, Result};
 use goblin::elf64::program_header::PT_LOAD;
 use sallyport::elf::{self, pf::kvm::SALLYPORT};
 
@@ -26,6 +26,26 @@ impl Config {
     }
 }
 
+/// Returns the launch parameters required by `shim`, the defaults of the ID block of a keep.
+pub fn parameters(shim: &super::super::Binary<'_>) -> Result<Parameters> {
+    unsafe {
+        Ok(Parameters {
+            policy: shim
+                .note(elf::note::NAME, elf::note::snp::POLICY)
+                .ok_or_else(|| anyhow!("KVM shim is missing POLICY"))?,
+            family_id: shim
+                .note(elf::note::NAME, elf::note::snp::FAMILY_ID)
+                .ok_or_else(|| anyhow!("KVM shim is missing FAMILY_ID"))?,
+            image_id: shim
+                .note(elf::note::NAME, elf::note::snp::IMAGE_ID)
+                .ok_or_else(|| anyhow!("KVM shim is missing IMAGE_ID"))?,
+            guest_svn: shim
+                .note(elf::note::NAME, elf::note::snp::SVN)
+                .ok_or_else(|| anyhow!("KVM shim is missing GUEST_SVN"))?,
+        })
+    }
+}
+
 impl super::super::Config for Config {
     type Flags = u32;
 
@@ -49,20 +69,27 @@ impl super::super::Config for Config {
             unsafe { shim.note::<u64>(elf::note::NAME, elf::note::BLOCK_SIZE) }
                 .ok_or_else(|| anyhow!("KVM shim is missing BLOCK_SIZE"))? as usize;
 
-        let parameters: Parameters = unsafe {
-            Parameters {
-                policy: shim
-                    .note(elf::note::NAME, elf::note::snp::POLICY)
-                    .ok_or_else(|| anyhow!("KVM shim is missing POLICY"))?,
-                family_id: shim
-                    .note(elf::note::NAME, elf::note::snp::FAMILY_ID)
-                    .ok_or_else(|| anyhow!("KVM shim is missing FAMILY_ID"))?,
-                image_id: shim
-                    .note(elf::note::NAME, elf::note::snp::IMAGE_ID)
-                    .ok_or_else(|| anyhow!("KVM shim is missing IMAGE_ID"))?,
-                guest_svn: shim
-                    .note(elf::note::NAME, elf::note::snp::SVN)
-                    .ok_or_else(|| anyhow!("KVM shim is missing GUEST_SVN"))?,
+        // A signed keep is launched with the parameters of its ID block.
+        let parameters = match signatures {
+            Some(_) if super::policy::policy().is_some() => {
+                bail!("The SEV guest policy of a signed keep is set by its signature")
+            }
+            Some(ref signatures) => {
+                let id_block = IdBlock::from_bytes(&signatures.sev.id_block)
+                    .ok_or_else(|| anyhow!("Invalid SEV signature IdBlock blob size."))?;
+                Parameters {
+                    policy: id_block.policy,
+                    family_id: id_block.family_id,
+                    image_id: id_block.image_id,
+                    guest_svn: id_block.guest_svn,
+                }
+            }
+            None => {
+                let mut parameters = parameters(shim)?;
+                if let Some(policy) = super::policy::policy() {
+                    parameters.policy = policy.apply(parameters.policy);
+                }
+                parameters
             }
         };
 
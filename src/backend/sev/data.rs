@@ -1,6 +1,6 @@
 // SPDX-License-Identifier: Apache-2.0
 
-pub use crate::backend::kvm::data::{dev_kvm, kvm_version};
+pub use crate::backend::kvm::data::{dev_kvm, kvm_version, swap};
 
 use crate::backend::probe::x86_64::{CpuId, Vendor};
 use crate::backend::Datum;
@@ -1,5 +1,6 @@
 // SPDX-License-Identifier: Apache-2.0
 
+pub mod policy;
 pub mod snp;
 
 pub use snp::firmware::Firmware;
@@ -17,7 +18,7 @@ use super::kvm::{Keep, KeepPersonality};
 use super::Loader;
 use data::{
     dev_kvm, dev_sev, dev_sev_readable, dev_sev_writable, has_reasonable_memlock_rlimit,
-    kvm_version, sev_enabled_in_kernel, CPUIDS,
+    kvm_version, sev_enabled_in_kernel, swap, CPUIDS,
 };
 
 use std::io;
@@ -65,10 +66,12 @@ impl KeepPersonality for SnpKeepPersonality {
                         .map_err(io::Error::from_raw_os_error)
                         .context("snp::enarxcall deref")?
                 };
-                let mut vcek_reader = get_vcek_reader()?;
+                let mut vcek_reader = get_vcek_reader().context(
+                    "Could not get SEV-SNP vcek! Import it with `enarx platform snp vcek import` on hosts without access to the AMD KDS",
+                )?;
                 *ret = std::io::copy(&mut vcek_reader, &mut vcek_buf)? as _;
                 if *ret == 0 {
-                    bail!("Could not get SEV-SNP vcek! Run `enarx snp vcek update`")
+                    bail!("Could not get SEV-SNP vcek! Run `enarx platform snp update`")
                 }
                 Ok(None)
             }
@@ -106,9 +109,15 @@ impl super::Backend for Backend {
             dev_sev_readable(),
             dev_sev_writable(),
             has_reasonable_memlock_rlimit(),
+            swap(),
         ]
     }
 
+    #[inline]
+    fn attestation(&self) -> bool {
+        self.have() && self.configured()
+    }
+
     #[inline]
     fn keep(
         &self,
@@ -0,0 +1,190 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use super::snp::Version;
+use super::Firmware;
+
+use std::fs;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+
+/// Allows SMT on the host
+const SMT: u64 = 1 << 16;
+/// Reserved, must be set
+const RESERVED: u64 = 1 << 17;
+/// Allows the association with a migration agent
+const MIGRATE_MA: u64 = 1 << 18;
+/// Allows debugging the guest
+const DEBUG: u64 = 1 << 19;
+/// Restricts the guest to a single socket
+const SINGLE_SOCKET: u64 = 1 << 20;
+
+/// Changes of the SEV-SNP guest policy, the unspecified ones keep the policy of the shim
+///
+/// Parsed from a comma-separated list of `key=value` pairs, e.g. `smt=false,abi=1.51`, with the
+/// keys of the fields.
+#[derive(Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// Whether SMT may be enabled on the host
+    pub smt: Option<bool>,
+
+    /// Whether a migration agent may be associated with the guest
+    pub migrate_ma: Option<bool>,
+
+    /// Whether the guest may be debugged
+    pub debug: Option<bool>,
+
+    /// Whether the guest may only run on a single socket
+    pub single_socket: Option<bool>,
+
+    /// Minimum ABI version of the firmware, `MAJOR.MINOR`
+    #[serde(default, deserialize_with = "abi")]
+    pub abi: Option<Version>,
+}
+
+/// Parses an ABI version `MAJOR.MINOR`.
+fn parse_abi(s: &str) -> Result<Version> {
+    let (major, minor) = s
+        .split_once('.')
+        .ok_or_else(|| anyhow!("invalid ABI version {:?}, expected MAJOR.MINOR", s))?;
+    Ok(Version {
+        major: major.parse().context("invalid ABI major version")?,
+        minor: minor.parse().context("invalid ABI minor version")?,
+    })
+}
+
+fn abi<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<Version>, D::Error> {
+    let abi = String::deserialize(deserializer)?;
+    parse_abi(&abi).map(Some).map_err(serde::de::Error::custom)
+}
+
+impl FromStr for Policy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut policy = Self::default();
+        for pair in s.split(',').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow!("invalid SEV policy {:?}, expected KEY=VALUE", pair))?;
+            let flag = || {
+                value
+                    .parse()
+                    .map(Some)
+                    .with_context(|| format!("invalid value of SEV policy `{key}`"))
+            };
+            match key {
+                "smt" => policy.smt = flag()?,
+                "migrate_ma" => policy.migrate_ma = flag()?,
+                "debug" => policy.debug = flag()?,
+                "single_socket" => policy.single_socket = flag()?,
+                "abi" => policy.abi = Some(parse_abi(value)?),
+                _ => bail!("unknown SEV policy `{key}`"),
+            }
+        }
+        Ok(policy)
+    }
+}
+
+impl Policy {
+    /// Returns `policy` with the specified flags and ABI version replaced.
+    pub fn apply(&self, mut policy: u64) -> u64 {
+        for (flag, bit) in [
+            (self.smt, SMT),
+            (self.migrate_ma, MIGRATE_MA),
+            (self.debug, DEBUG),
+            (self.single_socket, SINGLE_SOCKET),
+        ] {
+            match flag {
+                Some(true) => policy |= bit,
+                Some(false) => policy &= !bit,
+                None => {}
+            }
+        }
+        if let Some(abi) = self.abi {
+            policy = policy & !0xffff | (abi.major as u64) << 8 | abi.minor as u64;
+        }
+        policy
+    }
+}
+
+static POLICY: OnceCell<Policy> = OnceCell::new();
+
+/// Sets the changes of the guest policy of the unsigned keeps created by this process.
+pub fn set_policy(policy: Policy) {
+    let _ = POLICY.set(policy);
+}
+
+/// Returns the changes of the guest policy of unsigned keeps.
+pub(super) fn policy() -> Option<Policy> {
+    POLICY.get().copied()
+}
+
+/// Whether SMT is active on this host
+fn smt_active() -> bool {
+    fs::read_to_string("/sys/devices/system/cpu/smt/active")
+        .map(|active| active.trim() == "1")
+        .unwrap_or(false)
+}
+
+/// Checks that a guest with `policy` can be launched on this platform.
+pub(super) fn check(policy: u64, firmware: &mut Firmware) -> Result<()> {
+    ensure!(
+        policy & RESERVED != 0,
+        "invalid SEV guest policy {policy:#x}, bit 17 must be set"
+    );
+    if policy & SMT == 0 && smt_active() {
+        bail!("the SEV guest policy {policy:#x} forbids SMT, which is active on this host");
+    }
+    let abi = Version {
+        major: (policy >> 8) as u8,
+        minor: policy as u8,
+    };
+    let status = firmware
+        .platform_status()
+        .context("failed to query the SEV platform status")?;
+    if status.build.version < abi {
+        bail!(
+            "the SEV guest policy {policy:#x} requires firmware ABI {abi}, the platform has {}",
+            status.build.version
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Policy, Version};
+
+    #[test]
+    fn policy() {
+        let policy: Policy = "smt=false,debug=true,abi=1.52".parse().unwrap();
+        assert_eq!(
+            policy,
+            Policy {
+                smt: Some(false),
+                debug: Some(true),
+                abi: Some(Version {
+                    major: 1,
+                    minor: 52
+                }),
+                ..Default::default()
+            }
+        );
+        assert_eq!(policy.apply(0x30133), 0xa0134);
+        assert_eq!(Policy::default().apply(0x30133), 0x30133);
+        assert_eq!("".parse::<Policy>().unwrap(), Policy::default());
+
+        let toml: Policy = toml::from_str("single_socket = true\nabi = \"1.52\"").unwrap();
+        assert_eq!(toml.apply(0x30133), 0x130134);
+
+        assert!("smt".parse::<Policy>().is_err());
+        assert!("smt=no".parse::<Policy>().is_err());
+        assert!("abi=1".parse::<Policy>().is_err());
+        assert!("tsme=true".parse::<Policy>().is_err());
+        assert!(toml::from_str::<Policy>("abi = \"1\"").is_err());
+    }
+}
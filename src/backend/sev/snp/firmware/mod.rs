@@ -65,10 +65,11 @@ impl Identifier {
         )
     }
 
-    /// Get the URL to download the VCEK.
-    pub fn vcek_url(&self, version: &TcbVersion) -> String {
+    /// Get the URL to download the VCEK from the AMD Key Distribution Service at `kds`.
+    pub fn vcek_url(&self, kds: &str, version: &TcbVersion) -> String {
         format!(
-            "https://kdsintf.amd.com/vcek/v1/Milan/{:x}?blSPL={:02}&teeSPL={:02}&snpSPL={:02}&ucodeSPL={:02}",
+            "{}/vcek/v1/Milan/{:x}?blSPL={:02}&teeSPL={:02}&snpSPL={:02}&ucodeSPL={:02}",
+            kds.trim_end_matches('/'),
             self,
             version.bootloader,
             version.tee,
@@ -90,6 +91,12 @@ impl From<Vec<u8>> for Identifier {
     }
 }
 
+impl AsRef<[u8]> for Identifier {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
 impl std::fmt::Display for Identifier {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for b in self.0.iter() {
@@ -206,7 +213,8 @@ mod test {
             ..Default::default()
         };
 
-        assert_eq!(URL, id.vcek_url(&tcb));
+        assert_eq!(URL, id.vcek_url("https://kdsintf.amd.com", &tcb));
+        assert_eq!(URL, id.vcek_url("https://kdsintf.amd.com/", &tcb));
     }
 
     #[test]
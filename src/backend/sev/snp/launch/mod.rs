@@ -309,6 +309,13 @@ impl Default for IdAuth {
     }
 }
 
+impl IdAuth {
+    /// Whether the ID key is signed by an author key, whose digest the attestation report carries.
+    pub fn has_author_key(&self) -> bool {
+        self.author_key.component != Default::default()
+    }
+}
+
 // SAFETY: IdBlock is a C struct with no UD states and pointers.
 unsafe impl ByteSized for IdBlock {}
 
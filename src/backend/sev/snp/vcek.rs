@@ -1,17 +1,197 @@
 // SPDX-License-Identifier: Apache-2.0
-
+//! The VCEK certificate of the platform
+//!
+//! The VCEK signs the attestation reports of the platform and is issued by the AMD Key
+//! Distribution Service (KDS) for a chip ID and TCB version. It is cached in
+//! `/var/cache/amd-sev` under a name derived from both, fetched from the KDS when missing and
+//! checked against the chip ID and TCB version of the platform whenever it is used or imported.
+
+use crate::backend::sev::snp::firmware::{Identifier, TcbVersion};
 use crate::backend::sev::Firmware;
 
 use std::fs::{self, remove_file};
-use std::io::{self, ErrorKind, Read};
+use std::io::{self, Cursor, ErrorKind, Read};
 use std::os::unix::fs::OpenOptionsExt;
 use std::path::PathBuf;
 
-use anyhow::{anyhow, Context};
+use anyhow::{anyhow, ensure, Context};
+use log::warn;
+use once_cell::sync::OnceCell;
+use x509_cert::der::asn1::ObjectIdentifier;
+use x509_cert::der::Decode;
+use x509_cert::ext::Extension;
+use x509_cert::Certificate;
+
+/// URL of the AMD Key Distribution Service
+pub const KDS_URL: &str = "https://kdsintf.amd.com";
+
+/// Maximum size of a VCEK certificate read from the KDS
+const MAX_SIZE: u64 = 64 * 1024;
+
+/// The chip ID of the platform, the raw 64 bytes
+const HW_ID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.4.1.3704.1.4");
+/// The SPL of the bootloader, an `INTEGER`
+const BL_SPL: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.4.1.3704.1.3.1");
+/// The SPL of the PSP OS, an `INTEGER`
+const TEE_SPL: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.4.1.3704.1.3.2");
+/// The SPL of the SNP firmware, an `INTEGER`
+const SNP_SPL: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.4.1.3704.1.3.3");
+/// The SPL of the microcode, an `INTEGER`
+const UCODE_SPL: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.4.1.3704.1.3.8");
+
+/// Where missing VCEK certificates are fetched from
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Kds {
+    /// URL of the KDS or of a mirror of it, [`KDS_URL`] by default
+    pub url: Option<String>,
+
+    /// URL of the HTTP proxy to reach the KDS through
+    pub proxy: Option<String>,
+}
+
+static KDS: OnceCell<Kds> = OnceCell::new();
+
+/// Sets where the VCEK certificates missing in the cache are fetched from by this process.
+pub fn set_kds(kds: Kds) {
+    let _ = KDS.set(kds);
+}
+
+/// The chip ID and TCB version of the platform, which its VCEK is issued for
+struct Platform {
+    id: Identifier,
+    tcb: TcbVersion,
+}
+
+impl Platform {
+    fn query() -> anyhow::Result<Self> {
+        let mut sev = Firmware::open().context("failed to open /dev/sev")?;
+        let id = sev.identifier().context("failed to query identifier")?;
+        let status = sev
+            .platform_status()
+            .context("failed to query platform status")?;
+
+        // Ensure the versions match.
+        if status.tcb.platform_version != status.tcb.reported_version {
+            // It is not clear from the documentation what the difference between the two is,
+            // therefore only proceed if they are identical to ensure correctness.
+            // TODO: Figure out which one should be used and drop this check.
+            return Err(anyhow!("reported TCB version mismatch"));
+        }
+
+        Ok(Self {
+            id,
+            tcb: status.tcb.reported_version,
+        })
+    }
+
+    /// Returns the name of the cache file of the VCEK.
+    fn cache_name(&self) -> String {
+        self.id.vcek_cache_name(&self.tcb)
+    }
+
+    /// Returns the URL of the VCEK at the KDS.
+    fn url(&self) -> String {
+        let kds = KDS.get().and_then(|kds| kds.url.as_deref());
+        self.id.vcek_url(kds.unwrap_or(KDS_URL), &self.tcb)
+    }
+
+    /// Fetches the VCEK from the KDS.
+    fn fetch(&self) -> anyhow::Result<Vec<u8>> {
+        let kds = KDS.get().cloned().unwrap_or_default();
+        let url = self.url();
+
+        let mut agent = ureq::AgentBuilder::new();
+        if let Some(ref proxy) = kds.proxy {
+            let proxy = ureq::Proxy::new(proxy)
+                .with_context(|| format!("invalid KDS proxy URL `{proxy}`"))?;
+            agent = agent.proxy(proxy);
+        }
+        let mut vcek = vec![];
+        agent
+            .build()
+            .get(&url)
+            .call()
+            .with_context(|| format!("Error getting vcek from URL {}", &url))?
+            .into_reader()
+            .take(MAX_SIZE)
+            .read_to_end(&mut vcek)
+            .with_context(|| format!("Error reading vcek from URL {}", &url))?;
+        self.check(&vcek)
+            .with_context(|| format!("invalid VCEK certificate from URL {url}"))?;
+        Ok(vcek)
+    }
+
+    /// Checks that the DER-encoded certificate `vcek` is the VCEK of this platform.
+    fn check(&self, vcek: &[u8]) -> anyhow::Result<()> {
+        let cert = Certificate::from_der(vcek).context("invalid VCEK certificate encoding")?;
+        let extensions = cert.tbs_certificate.extensions.unwrap_or_default();
+        pinned(&extensions, self.id.as_ref(), &self.tcb)
+    }
+}
+
+/// Checks that the `extensions` of a VCEK certificate carry the chip ID `id` and the TCB
+/// version `tcb`.
+fn pinned(extensions: &[Extension<'_>], id: &[u8], tcb: &TcbVersion) -> anyhow::Result<()> {
+    let value = |oid: ObjectIdentifier| {
+        extensions
+            .iter()
+            .find(|ext| ext.extn_id == oid)
+            .map(|ext| ext.extn_value)
+            .ok_or_else(|| anyhow!("VCEK certificate lacks the extension {oid}"))
+    };
+    ensure!(
+        value(HW_ID)? == id,
+        "VCEK certificate was issued for another chip"
+    );
+    for (oid, name, spl) in [
+        (BL_SPL, "bootloader", tcb.bootloader),
+        (TEE_SPL, "TEE", tcb.tee),
+        (SNP_SPL, "SNP", tcb.snp),
+        (UCODE_SPL, "microcode", tcb.microcode),
+    ] {
+        let issued = u8::from_der(value(oid)?)
+            .with_context(|| format!("invalid {name} SPL of VCEK certificate"))?;
+        ensure!(
+            issued == spl,
+            "VCEK certificate was issued for {name} SPL {issued}, the platform has {spl}"
+        );
+    }
+    Ok(())
+}
 
 /// Return a reader, which provides the VCEK certificate
+///
+/// A VCEK missing in the cache is fetched from the KDS and cached, if the cache is writable.
 pub fn get_vcek_reader() -> anyhow::Result<Box<dyn Read>> {
-    get_vcek_reader_with_path(sev_cache_dir()?).map(|(_, r)| r)
+    let platform = Platform::query()?;
+    let cache_dir = sev_cache_dir()?;
+    let vcek = match read(cache_dir.clone(), platform.cache_name()) {
+        Ok((path, mut reader)) => {
+            let mut vcek = vec![];
+            reader
+                .read_to_end(&mut vcek)
+                .with_context(|| format!("Error reading `{}`", path.display()))?;
+            platform
+                .check(&vcek)
+                .with_context(|| format!("invalid cached VCEK certificate `{}`", path.display()))?;
+            vcek
+        }
+        Err(e) if matches!(io_kind(&e), Some(ErrorKind::NotFound)) => {
+            let vcek = platform.fetch()?;
+            let contents = || Ok(Box::new(Cursor::new(vcek.clone())) as Box<dyn Read>);
+            if let Err(e) = write(cache_dir, platform.cache_name(), contents) {
+                warn!("failed to cache the VCEK certificate: {e:#}");
+            }
+            vcek
+        }
+        Err(e) => return Err(e),
+    };
+    Ok(Box::new(Cursor::new(vcek)))
+}
+
+/// Returns the URL to download the VCEK certificate of this platform from.
+pub fn vcek_url() -> anyhow::Result<String> {
+    Ok(Platform::query()?.url())
 }
 
 /// Update the global VCEK cache file
@@ -20,6 +200,22 @@ pub fn vcek_write() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Imports the VCEK certificate `vcek` of this platform, encoded in DER or PEM, into the global
+/// cache, e.g. on hosts without access to the KDS
+///
+/// Returns the path of the cache file.
+pub fn vcek_import(vcek: &[u8]) -> anyhow::Result<PathBuf> {
+    let vcek = match x509_cert::der::pem::decode_vec(vcek) {
+        Ok((_, der)) => der,
+        Err(_) => vcek.to_vec(),
+    };
+    let platform = Platform::query()?;
+    platform.check(&vcek)?;
+    write(sev_cache_dir()?, platform.cache_name(), || {
+        Ok(Box::new(Cursor::new(vcek.clone())))
+    })
+}
+
 /// Returns the "system-level" search path for the SEV
 /// certificate chain (`/var/cache/amd-sev`).
 pub fn sev_cache_dir() -> anyhow::Result<PathBuf> {
@@ -37,47 +233,23 @@ pub fn sev_cache_dir() -> anyhow::Result<PathBuf> {
 
 /// Returns a reader and a path, which provides the VCEK certificate
 pub fn get_vcek_reader_with_path(cache_dir: PathBuf) -> anyhow::Result<(PathBuf, Box<dyn Read>)> {
-    let (_, path) = get_vcek_url_path()?;
-
-    read(cache_dir, path)
+    read(cache_dir, Platform::query()?.cache_name())
 }
 
 /// Write the VCEK certificate to a cache directory
 ///
-/// Downloads the certificate from the standard URL, and stores it in the provided directory.
+/// Downloads the certificate from the KDS, and stores it in the provided directory.
 /// Returns the path, where it has been stored.
 pub fn vcek_write_with_path(cache_dir: PathBuf) -> anyhow::Result<PathBuf> {
-    let (url, path) = get_vcek_url_path()?;
+    let platform = Platform::query()?;
 
-    write(cache_dir, path, || {
-        let call = ureq::get(&url)
-            .call()
-            .with_context(|| format!("Error getting vcek from URL {}", &url))?;
-        let reader = call.into_reader();
-        Ok(Box::new(reader))
+    write(cache_dir, platform.cache_name(), || {
+        Ok(Box::new(Cursor::new(platform.fetch()?)))
     })
 }
 
-fn get_vcek_url_path() -> anyhow::Result<(String, String)> {
-    // Get the platform information.
-    let mut sev = Firmware::open().context("failed to open /dev/sev")?;
-    let id = sev.identifier().context("failed to query identifier")?;
-    let status = sev
-        .platform_status()
-        .context("failed to query platform status")?;
-
-    // Ensure the versions match.
-    if status.tcb.platform_version != status.tcb.reported_version {
-        // It is not clear from the documentation what the difference between the two is,
-        // therefore only proceed if they are identical to ensure correctness.
-        // TODO: Figure out which one should be used and drop this check.
-        return Err(anyhow!("reported TCB version mismatch"));
-    }
-
-    let url = id.vcek_url(&status.tcb.reported_version);
-
-    let path = id.vcek_cache_name(&status.tcb.reported_version);
-    Ok((url, path))
+fn io_kind(e: &anyhow::Error) -> Option<ErrorKind> {
+    e.downcast_ref::<io::Error>().map(io::Error::kind)
 }
 
 // read the cached file
@@ -163,7 +335,11 @@ fn write(
 
 #[cfg(test)]
 mod tests {
-    use super::{read, write};
+    use super::{pinned, read, write, HW_ID, SNP_SPL};
+    use super::{BL_SPL, TEE_SPL, UCODE_SPL};
+    use crate::backend::sev::snp::firmware::TcbVersion;
+
+    use x509_cert::ext::Extension;
 
     use std::io::{self, ErrorKind, Read};
     use std::path::PathBuf;
@@ -249,4 +425,44 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_pinned() {
+        let id = [0xab; 64];
+        let tcb = TcbVersion {
+            bootloader: 2,
+            tee: 0,
+            snp: 6,
+            microcode: 0xa8,
+            ..Default::default()
+        };
+        let ext = |extn_id, extn_value| Extension {
+            extn_id,
+            critical: false,
+            extn_value,
+        };
+        let mut extensions = vec![
+            ext(BL_SPL, &[0x02, 0x01, 0x02]),
+            ext(TEE_SPL, &[0x02, 0x01, 0x00]),
+            ext(SNP_SPL, &[0x02, 0x01, 0x06]),
+            ext(UCODE_SPL, &[0x02, 0x02, 0x00, 0xa8]),
+            ext(HW_ID, &id),
+        ];
+        pinned(&extensions, &id, &tcb).unwrap();
+        assert!(pinned(&extensions, &[0xcd; 64], &tcb).is_err());
+        assert!(pinned(
+            &extensions,
+            &id,
+            &TcbVersion {
+                snp: 8,
+                ..tcb.clone()
+            }
+        )
+        .is_err());
+
+        extensions[3].extn_value = &[0x02, 0x01, 0x08];
+        assert!(pinned(&extensions, &id, &tcb).is_err());
+        extensions.remove(3);
+        assert!(pinned(&extensions, &id, &tcb).is_err());
+    }
 }
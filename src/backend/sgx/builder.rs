@@ -1,6 +1,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::config::Config;
+use super::epc;
 use super::ioctls::*;
 
 use std::convert::TryFrom;
@@ -15,9 +16,9 @@ use sgx::crypto::{rcrypto::*, *};
 use sgx::page::{Class, Flags, SecInfo};
 use sgx::signature::{Author, Hasher, Signature};
 
-use log::trace;
+use log::{info, trace, warn};
 
-use crate::backend::ByteSized;
+use crate::backend::{ByteSized, Mismatch};
 
 pub struct Builder {
     file: File,
@@ -26,6 +27,7 @@ pub struct Builder {
     mmap: Map<perms::Unknown>,
     perm: Vec<(*const (), usize, SecInfo)>,
     tcsp: Vec<*const super::Tcs>,
+    epc: usize,
 }
 
 impl TryFrom<super::config::Config> for Builder {
@@ -77,6 +79,7 @@ impl TryFrom<super::config::Config> for Builder {
             tcsp: Vec::new(),
             cnfg: config,
             file,
+            epc: Page::SIZE, // The SECS page.
         })
     }
 }
@@ -109,6 +112,8 @@ impl super::super::Mapper for Builder {
             .ioctl(&mut self.file, &mut ap)
             .context("Failed to add pages to SGX enclave")?;
 
+        self.epc += pages.size();
+
         // Update the hasher.
         self.hash.load(&pages, to, with.0, with.1).unwrap();
 
@@ -135,7 +140,15 @@ impl TryFrom<Builder> for Arc<dyn super::super::Keep> {
         let signature = if let Some(signatures) = builder.cnfg.signatures {
             let sig_blob = signatures.sgx;
 
-            Signature::from_bytes(&sig_blob).ok_or_else(|| anyhow!("Invalid SGX signature"))?
+            let signature =
+                Signature::from_bytes(&sig_blob).ok_or_else(|| anyhow!("Invalid SGX signature"))?;
+
+            // EINIT fails with an opaque error, if the signature does not cover the enclave.
+            let body = builder.cnfg.parameters.body(builder.hash.finish());
+            if signature.body().mrenclave() != body.mrenclave() {
+                return Err(Mismatch { backend: "sgx" }.into());
+            }
+            signature
         } else {
             // Create the enclave signature
             let hash = builder.hash.finish();
@@ -166,6 +179,15 @@ impl TryFrom<Builder> for Arc<dyn super::super::Keep> {
             .context("Failed to initialize SGX enclave")?;
         trace!("enclave initialized");
 
+        // Warn about EPC paging, which slows down all keeps on the machine.
+        info!("enclave uses {} bytes of EPC", builder.epc);
+        if let Some(pressure) = epc::pressure().filter(epc::Pressure::overcommitted) {
+            warn!(
+                "EPC is overcommitted, {} of {} bytes are in use by all enclaves",
+                pressure.used, pressure.capacity
+            );
+        }
+
         // Fix up mapped permissions.
         builder.perm.sort_by_key(|x| x.0);
         for (addr, size, si) in builder.perm {
@@ -2,12 +2,13 @@
 
 use std::num::NonZeroU32;
 
-use crate::backend::Signatures;
+use crate::backend::{ByteSized, Signatures};
 use anyhow::{anyhow, Result};
 use goblin::elf::program_header::{PF_R, PF_W, PF_X};
 use sallyport::elf;
 use sgx::page::{Class, Flags, SecInfo};
 use sgx::parameters::{Masked, Parameters};
+use sgx::signature::Signature;
 
 #[derive(Debug)]
 pub struct Config {
@@ -18,6 +19,36 @@ pub struct Config {
     pub sallyport_block_size: u64,
 }
 
+/// Returns the enclave parameters required by `shim`, the defaults of the SIGSTRUCT of a keep.
+pub fn parameters(shim: &super::super::Binary<'_>) -> Result<Parameters> {
+    unsafe {
+        Ok(Parameters {
+            misc: Masked {
+                data: shim
+                    .note(elf::note::NAME, elf::note::sgx::MISC)
+                    .ok_or_else(|| anyhow!("SGX shim is missing MISC"))?,
+                mask: shim
+                    .note(elf::note::NAME, elf::note::sgx::MISCMASK)
+                    .ok_or_else(|| anyhow!("SGX shim is missing MISCMASK"))?,
+            },
+            attr: Masked {
+                data: shim
+                    .note(elf::note::NAME, elf::note::sgx::ATTR)
+                    .ok_or_else(|| anyhow!("SGX shim is missing ATTR"))?,
+                mask: shim
+                    .note(elf::note::NAME, elf::note::sgx::ATTRMASK)
+                    .ok_or_else(|| anyhow!("SGX shim is missing ATTRMASK"))?,
+            },
+            pid: shim
+                .note(elf::note::NAME, elf::note::sgx::PID)
+                .ok_or_else(|| anyhow!("SGX shim is missing PID"))?,
+            svn: shim
+                .note(elf::note::NAME, elf::note::sgx::SVN)
+                .ok_or_else(|| anyhow!("SGX shim is missing SVN"))?,
+        })
+    }
+}
+
 impl super::super::Config for Config {
     type Flags = (SecInfo, bool);
 
@@ -52,32 +83,16 @@ impl super::super::Config for Config {
         _exec: &super::super::Binary<'_>,
         signatures: Option<Signatures>,
     ) -> Result<Self> {
-        unsafe {
-            let params: Parameters = Parameters {
-                misc: Masked {
-                    data: shim
-                        .note(elf::note::NAME, elf::note::sgx::MISC)
-                        .ok_or_else(|| anyhow!("SGX shim is missing MISC"))?,
-                    mask: shim
-                        .note(elf::note::NAME, elf::note::sgx::MISCMASK)
-                        .ok_or_else(|| anyhow!("SGX shim is missing MISCMASK"))?,
-                },
-                attr: Masked {
-                    data: shim
-                        .note(elf::note::NAME, elf::note::sgx::ATTR)
-                        .ok_or_else(|| anyhow!("SGX shim is missing ATTR"))?,
-                    mask: shim
-                        .note(elf::note::NAME, elf::note::sgx::ATTRMASK)
-                        .ok_or_else(|| anyhow!("SGX shim is missing ATTRMASK"))?,
-                },
-                pid: shim
-                    .note(elf::note::NAME, elf::note::sgx::PID)
-                    .ok_or_else(|| anyhow!("SGX shim is missing PID"))?,
-                svn: shim
-                    .note(elf::note::NAME, elf::note::sgx::SVN)
-                    .ok_or_else(|| anyhow!("SGX shim is missing SVN"))?,
-            };
+        // A signed keep is created with the parameters required by its SIGSTRUCT.
+        let params = match signatures {
+            Some(ref signatures) => Signature::from_bytes(&signatures.sgx)
+                .ok_or_else(|| anyhow!("Invalid SGX signature"))?
+                .body()
+                .parameters(),
+            None => parameters(shim)?,
+        };
 
+        unsafe {
             let ssap: u8 = shim
                 .note(elf::note::NAME, elf::note::sgx::SSAP)
                 .ok_or_else(|| anyhow!("SGX shim is missing SSAP"))?;
@@ -1,8 +1,9 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::backend::probe::x86_64::{CpuId, Vendor};
-use crate::backend::sgx::AESM_SOCKET;
-use crate::backend::Datum;
+use crate::backend::sgx::quote::{self, Provider};
+use crate::backend::sgx::{config, epc, AESM_SOCKET};
+use crate::backend::{Binary, Datum};
 
 use sgx::parameters::{Features, MiscSelect, Xfrm};
 
@@ -155,6 +156,26 @@ pub fn epc_size(max: u32) -> Datum {
     }
 }
 
+pub fn epc_pressure() -> Datum {
+    let info = match epc::pressure() {
+        Some(epc::Pressure { used, capacity }) => {
+            let (u, us) = humanize(used as f64);
+            let (c, cs) = humanize(capacity as f64);
+            let percent = used as f64 * 100.0 / capacity.max(1) as f64;
+            format!("{:.0} {} of {:.0} {} used ({:.0}%)", u, us, c, cs, percent)
+        }
+        None => "unknown".into(),
+    };
+
+    // EPC pressure only affects performance, so it never fails.
+    Datum {
+        name: "  EPC Pressure".into(),
+        pass: true,
+        info: Some(info),
+        mesg: None,
+    }
+}
+
 pub fn dev_sgx_enclave() -> Datum {
     Datum {
         name: "Driver".into(),
@@ -165,10 +186,44 @@ pub fn dev_sgx_enclave() -> Datum {
 }
 
 pub fn aesm_socket() -> Datum {
+    let provider = quote::provider();
+    let aesm = Path::new(AESM_SOCKET).exists();
+    // Without the AESM daemon, quotes may still be produced by the DCAP quote library.
+    let mesg = match provider {
+        Provider::Auto if !aesm && provider.available() => {
+            Some(format!("quotes are produced with {}", provider.info()))
+        }
+        _ => None,
+    };
     Datum {
         name: "AESM Daemon Socket".into(),
-        pass: cfg!(feature = "disable-sgx-attestation") || Path::new(AESM_SOCKET).exists(),
-        info: Some(AESM_SOCKET.into()),
+        pass: cfg!(feature = "disable-sgx-attestation") || provider.available(),
+        info: Some(provider.info()),
+        mesg,
+    }
+}
+
+/// Reports the SIGSTRUCT parameters of keeps, which are not signed with other ones by `enarx sign`
+pub fn launch_parameters(shim: &[u8]) -> Datum {
+    let params = Binary::new(shim).and_then(|shim| config::parameters(&shim));
+    let info = match params {
+        Ok(ref params) => format!(
+            "ISVPRODID {}, ISVSVN {}, MISCSELECT {:#x}/{:#x}, FEATURES {:#x}/{:#x}, XFRM {:#x}/{:#x}",
+            params.pid,
+            params.svn,
+            params.misc.data.bits(),
+            params.misc.mask.bits(),
+            params.attr.data.features().bits(),
+            params.attr.mask.features().bits(),
+            params.attr.data.xfrm().bits(),
+            params.attr.mask.xfrm().bits(),
+        ),
+        Err(ref e) => format!("{e:#}"),
+    };
+    Datum {
+        name: "Launch Parameters".into(),
+        pass: params.is_ok(),
+        info: Some(info),
         mesg: None,
     }
 }
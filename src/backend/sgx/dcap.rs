@@ -0,0 +1,163 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! In-process quote generation with the Intel DCAP quote library
+//!
+//! The library loads the Quoting Enclave and the Provisioning Certification Enclave itself, so
+//! quotes can be produced without the AESM daemon, e.g. in containers. It is loaded at runtime,
+//! such that `enarx` does not depend on it, if the AESM daemon is used.
+
+use std::ffi::{c_void, CStr};
+use std::io::{Error, ErrorKind};
+use std::mem::size_of;
+
+use once_cell::sync::OnceCell;
+use sallyport::item::enarxcall::sgx::{Report, TargetInfo};
+
+/// The soname of the DCAP quote library
+pub const LIBRARY: &str = "libsgx_dcap_ql.so.1";
+
+/// `SGX_QL_SUCCESS` of `quote3_error_t`
+const SUCCESS: u32 = 0;
+
+/// `SGX_QL_PERSISTENT` of `sgx_ql_request_policy_t`, keeping the enclaves loaded between quotes
+const PERSISTENT: u32 = 0;
+
+type SetEnclaveLoadPolicy = unsafe extern "C" fn(policy: u32) -> u32;
+type GetTargetInfo = unsafe extern "C" fn(target_info: *mut TargetInfo) -> u32;
+type GetQuoteSize = unsafe extern "C" fn(quote_size: *mut u32) -> u32;
+type GetQuote = unsafe extern "C" fn(report: *const Report, quote_size: u32, quote: *mut u8) -> u32;
+
+/// The functions of the loaded library, which is never unloaded
+struct Library {
+    get_target_info: GetTargetInfo,
+    get_quote_size: GetQuoteSize,
+    get_quote: GetQuote,
+}
+
+static DCAP: OnceCell<Result<Library, String>> = OnceCell::new();
+
+/// Returns the last error of the dynamic linker.
+fn dlerror() -> String {
+    let err = unsafe { libc::dlerror() };
+    if err.is_null() {
+        return "unknown error".into();
+    }
+    unsafe { CStr::from_ptr(err) }.to_string_lossy().into()
+}
+
+impl Library {
+    fn load() -> Result<Self, String> {
+        let name = format!("{LIBRARY}\0");
+        let handle = unsafe { libc::dlopen(name.as_ptr().cast(), libc::RTLD_NOW) };
+        if handle.is_null() {
+            return Err(format!("failed to load {LIBRARY}: {}", dlerror()));
+        }
+
+        let symbol = |name: &str| -> Result<*mut c_void, String> {
+            let name = format!("{name}\0");
+            match unsafe { libc::dlsym(handle, name.as_ptr().cast()) } {
+                ptr if ptr.is_null() => Err(format!(
+                    "{LIBRARY} lacks {}: {}",
+                    name.trim_end_matches('\0'),
+                    dlerror()
+                )),
+                ptr => Ok(ptr),
+            }
+        };
+
+        // Safety: the symbols have the signatures of `sgx_dcap_ql_wrapper.h`.
+        let library = unsafe {
+            let set_policy: SetEnclaveLoadPolicy =
+                std::mem::transmute(symbol("sgx_qe_set_enclave_load_policy")?);
+            let library = Self {
+                get_target_info: std::mem::transmute(symbol("sgx_qe_get_target_info")?),
+                get_quote_size: std::mem::transmute(symbol("sgx_qe_get_quote_size")?),
+                get_quote: std::mem::transmute(symbol("sgx_qe_get_quote")?),
+            };
+            match set_policy(PERSISTENT) {
+                SUCCESS => {}
+                code => log::warn!("failed to keep the DCAP quoting enclaves loaded: {code:#x}"),
+            }
+            library
+        };
+        Ok(library)
+    }
+}
+
+/// Returns the loaded library.
+fn library() -> Result<&'static Library, Error> {
+    DCAP.get_or_init(Library::load)
+        .as_ref()
+        .map_err(|e| Error::new(ErrorKind::NotFound, e.clone()))
+}
+
+/// Returns whether the DCAP quote library can be loaded.
+pub fn available() -> bool {
+    library().is_ok()
+}
+
+/// Returns an error of the failed library function `name`.
+fn error(name: &str, code: u32) -> Error {
+    Error::new(ErrorKind::Other, format!("{name} failed: {code:#x}"))
+}
+
+/// Fills the Target Info of the QE into `out_buf` and returns the number of bytes written.
+pub fn get_target_info(out_buf: &mut [u8]) -> Result<usize, Error> {
+    if out_buf.len() != size_of::<TargetInfo>() {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!(
+                "Invalid output buffer size: {} != {}",
+                out_buf.len(),
+                size_of::<TargetInfo>()
+            ),
+        ));
+    }
+    let library = library()?;
+    let mut target_info = TargetInfo::default();
+    match unsafe { (library.get_target_info)(&mut target_info) } {
+        SUCCESS => {}
+        code => return Err(error("sgx_qe_get_target_info", code)),
+    }
+    out_buf.copy_from_slice(target_info.as_mut());
+    Ok(out_buf.len())
+}
+
+/// Returns the size of the quotes.
+pub fn get_quote_size() -> Result<usize, Error> {
+    let library = library()?;
+    let mut size = 0;
+    match unsafe { (library.get_quote_size)(&mut size) } {
+        SUCCESS => Ok(size as _),
+        code => Err(error("sgx_qe_get_quote_size", code)),
+    }
+}
+
+/// Fills the quote of `report` into `out_buf`, which must have the size of the quotes, and
+/// returns the number of bytes written.
+pub fn get_quote(report: &[u8], out_buf: &mut [u8]) -> Result<usize, Error> {
+    if report.len() < size_of::<Report>() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Invalid report size: {} < {}",
+                report.len(),
+                size_of::<Report>()
+            ),
+        ));
+    }
+    let size = get_quote_size()?;
+    if out_buf.len() != size {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid QUOTE buffer size: {} != {}", out_buf.len(), size),
+        ));
+    }
+    // The report in the sallyport block is not necessarily aligned.
+    let report = unsafe { report.as_ptr().cast::<Report>().read_unaligned() };
+    let library = library()?;
+    match unsafe { (library.get_quote)(&report, size as _, out_buf.as_mut_ptr()) } {
+        SUCCESS => Ok(size),
+        code => Err(error("sgx_qe_get_quote", code)),
+    }
+}
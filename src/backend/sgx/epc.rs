@@ -0,0 +1,183 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Accounting of the SGX Enclave Page Cache (EPC) via the `sgx_epc` resource of the cgroup v2 misc controller
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use once_cell::sync::OnceCell;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const RESOURCE: &str = "sgx_epc";
+
+/// EPC usage of the whole machine
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Pressure {
+    /// Bytes of EPC in use by all enclaves
+    pub used: u64,
+
+    /// Bytes of EPC available on the machine
+    pub capacity: u64,
+}
+
+impl Pressure {
+    /// Returns whether the enclaves use more EPC than available, which causes EPC paging.
+    pub fn overcommitted(&self) -> bool {
+        self.used > self.capacity
+    }
+}
+
+/// Parses the value of the `sgx_epc` resource from the contents of a misc controller file.
+fn parse(content: &str) -> Option<u64> {
+    content
+        .lines()
+        .find_map(|line| match line.split_once(' ')? {
+            (RESOURCE, value) => value.trim().parse().ok(),
+            _ => None,
+        })
+}
+
+/// Reads the value of the `sgx_epc` resource from the misc controller file at `path`.
+fn read(path: impl AsRef<Path>) -> Option<u64> {
+    parse(&fs::read_to_string(path).ok()?)
+}
+
+/// Returns the EPC usage of the machine, if the misc controller accounts for it.
+pub fn pressure() -> Option<Pressure> {
+    let root = Path::new(CGROUP_ROOT);
+    Some(Pressure {
+        used: read(root.join("misc.current"))?,
+        capacity: read(root.join("misc.capacity"))?,
+    })
+}
+
+/// Parses the cgroup v2 path from the contents of `/proc/self/cgroup`.
+fn parse_cgroup(content: &str) -> Option<&str> {
+    content.lines().find_map(|line| line.strip_prefix("0::"))
+}
+
+/// Returns the cgroup v2 directory of the current process.
+fn cgroup() -> Result<PathBuf> {
+    let content = fs::read_to_string("/proc/self/cgroup").context("failed to read own cgroup")?;
+    let path = parse_cgroup(&content).ok_or_else(|| anyhow!("not running in a cgroup v2"))?;
+    Ok(Path::new(CGROUP_ROOT).join(path.trim_start_matches('/')))
+}
+
+/// The cgroup of the keep created by [`limit`], which is removed, when the process exits
+struct KeepCgroup {
+    /// The cgroup the process was moved out of
+    parent: PathBuf,
+    /// The cgroup of the keep
+    path: PathBuf,
+    /// Whether the misc controller was enabled for the children of `parent` by [`limit`]
+    enabled: AtomicBool,
+}
+
+impl KeepCgroup {
+    /// Moves the process back to the parent cgroup and removes the cgroup of the keep.
+    fn remove(&self) -> Result<()> {
+        // The parent cannot contain processes, while a controller is enabled for its children.
+        if self.enabled.load(Ordering::SeqCst) {
+            write(&self.parent.join("cgroup.subtree_control"), "-misc")?;
+        }
+        write(
+            &self.parent.join("cgroup.procs"),
+            &std::process::id().to_string(),
+        )?;
+        fs::remove_dir(&self.path)
+            .with_context(|| format!("failed to remove cgroup `{}`", self.path.display()))
+    }
+}
+
+static KEEP_CGROUP: OnceCell<KeepCgroup> = OnceCell::new();
+
+/// Removes the cgroup of the keep on exit.
+extern "C" fn remove_keep_cgroup() {
+    if let Some(cgroup) = KEEP_CGROUP.get() {
+        if let Err(e) = cgroup.remove() {
+            warn!("{e:#}");
+        }
+    }
+}
+
+/// Writes `value` to the cgroup interface file at `path`.
+fn write(path: &Path, value: &str) -> Result<()> {
+    fs::write(path, format!("{value}\n"))
+        .with_context(|| format!("failed to write `{value}` to `{}`", path.display()))
+}
+
+/// Limits the EPC usable by the keep run by the current process to `bytes`.
+///
+/// The process is moved into a child cgroup of its own cgroup, whose `misc.max` is set to the limit,
+/// and which is removed again, when the process exits. The process must be the only one in its
+/// cgroup, e.g. a systemd unit or a container, with the misc controller delegated to the user
+/// running it, since the processes of a cgroup cannot share it with a child using a controller.
+pub fn limit(bytes: u64) -> Result<()> {
+    if let Some(cgroup) = KEEP_CGROUP.get() {
+        return set_max(&cgroup.path, bytes);
+    }
+
+    let parent = cgroup()?;
+    let path = parent.join(format!("enarx-keep-{}", std::process::id()));
+    let subtree_control = parent.join("cgroup.subtree_control");
+    let enable = !fs::read_to_string(&subtree_control)
+        .with_context(|| format!("failed to read `{}`", subtree_control.display()))?
+        .split_whitespace()
+        .any(|controller| controller == "misc");
+
+    fs::create_dir(&path)
+        .with_context(|| format!("failed to create cgroup `{}`", path.display()))?;
+    let cgroup = KEEP_CGROUP.get_or_init(|| KeepCgroup {
+        parent,
+        path,
+        enabled: AtomicBool::new(false),
+    });
+    // SAFETY: `remove_keep_cgroup` does not unwind.
+    if unsafe { libc::atexit(remove_keep_cgroup) } != 0 {
+        warn!(
+            "failed to register the removal of `{}`",
+            cgroup.path.display()
+        );
+    }
+
+    write(
+        &cgroup.path.join("cgroup.procs"),
+        &std::process::id().to_string(),
+    )?;
+    if enable {
+        write(&subtree_control, "+misc").context(
+            "is the misc controller delegated and the keep the only process in its cgroup?",
+        )?;
+        // Only record the controller as enabled once it is, such that a failure does not disable
+        // it for the parent on exit.
+        cgroup.enabled.store(true, Ordering::SeqCst);
+    }
+    set_max(&cgroup.path, bytes)
+}
+
+/// Sets the limit of the EPC usable by the cgroup at `path` to `bytes`.
+fn set_max(path: &Path, bytes: u64) -> Result<()> {
+    write(&path.join("misc.max"), &format!("{RESOURCE} {bytes}"))
+        .context("failed to limit EPC usage, is the misc controller available?")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, parse_cgroup};
+
+    #[test]
+    fn parsing() {
+        assert_eq!(parse("res_a 1\nsgx_epc 4096\n"), Some(4096));
+        assert_eq!(parse("sgx_epc max\n"), None);
+        assert_eq!(parse("res_a 1\n"), None);
+
+        assert_eq!(
+            parse_cgroup("0::/system.slice/enarx.service\n"),
+            Some("/system.slice/enarx.service")
+        );
+        assert_eq!(parse_cgroup("1:name=systemd:/\n"), None);
+    }
+}
@@ -4,8 +4,11 @@ mod attestation;
 mod builder;
 mod config;
 mod data;
+mod dcap;
+pub mod epc;
 mod hasher;
 mod ioctls;
+pub mod quote;
 mod thread;
 
 use super::Loader;
@@ -54,12 +57,18 @@ impl crate::backend::Backend for Backend {
 
         let max = unsafe { __cpuid_count(0x00000000, 0x00000000) }.eax;
         data.push(data::epc_size(max));
+        data.push(data::epc_pressure());
 
         data
     }
 
     fn config(&self) -> Vec<super::Datum> {
-        vec![data::aesm_socket()]
+        vec![data::aesm_socket(), data::launch_parameters(self.shim())]
+    }
+
+    #[inline]
+    fn attestation(&self) -> bool {
+        self.have() && self.configured()
     }
 
     #[inline]
@@ -0,0 +1,192 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Selection of the provider of the quotes of SGX keeps
+
+use super::{attestation, dcap, AESM_SOCKET};
+
+use std::io::Error;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+use once_cell::sync::OnceCell;
+
+/// The provider of the quotes of SGX keeps
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Provider {
+    /// The AESM daemon, if its socket exists, the DCAP quote library otherwise
+    #[default]
+    Auto,
+
+    /// The AESM daemon listening on [`AESM_SOCKET`]
+    Aesm,
+
+    /// The DCAP quote library loaded into the process of the keep
+    Dcap,
+}
+
+impl FromStr for Provider {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "aesm" => Ok(Self::Aesm),
+            "dcap" => Ok(Self::Dcap),
+            _ => Err(anyhow!(
+                "unknown SGX quote provider {:?}, expected \"auto\", \"aesm\" or \"dcap\"",
+                s
+            )),
+        }
+    }
+}
+
+impl Provider {
+    /// Returns the provider used for [`Provider::Auto`], `None` if neither is available.
+    fn resolve(self) -> Option<Self> {
+        match self {
+            Self::Auto if Path::new(AESM_SOCKET).exists() => Some(Self::Aesm),
+            Self::Auto if dcap::available() => Some(Self::Dcap),
+            Self::Auto => None,
+            provider => Some(provider),
+        }
+    }
+
+    /// Returns whether the provider can produce quotes on this host.
+    pub fn available(self) -> bool {
+        match self.resolve() {
+            Some(Self::Aesm) => Path::new(AESM_SOCKET).exists(),
+            Some(Self::Dcap) => dcap::available(),
+            _ => false,
+        }
+    }
+
+    /// Returns where the quotes come from.
+    pub fn info(self) -> String {
+        match self.resolve() {
+            Some(Self::Dcap) => dcap::LIBRARY.into(),
+            _ => AESM_SOCKET.into(),
+        }
+    }
+}
+
+static PROVIDER: OnceCell<Provider> = OnceCell::new();
+
+/// Sets the quote provider of the keeps created by this process.
+pub fn set_provider(provider: Provider) {
+    let _ = PROVIDER.set(provider);
+}
+
+/// Returns the quote provider of the keeps.
+pub(super) fn provider() -> Provider {
+    PROVIDER.get().copied().unwrap_or_default()
+}
+
+/// The hint of the errors of the AESM daemon
+const AESM_HINT: &str = "Check your aesmd / pccs service installation.";
+/// The hint of the errors of the DCAP quote library
+const DCAP_HINT: &str = "Check your DCAP quote library / pccs installation.";
+
+/// Returns the resolved quote provider, failing if none is available.
+fn resolved() -> Result<Provider> {
+    provider().resolve().ok_or_else(|| {
+        anyhow!(
+            "No SGX quote provider available: neither {AESM_SOCKET} exists, nor can {} be loaded.",
+            dcap::LIBRARY
+        )
+    })
+}
+
+/// Adds the context `what` with the hint of `provider` to `res`.
+fn hint<T>(res: Result<T, Error>, provider: Provider, what: &str) -> Result<T> {
+    let hint = match provider {
+        Provider::Dcap => DCAP_HINT,
+        _ => AESM_HINT,
+    };
+    res.with_context(|| format!("Error {what}. {hint}"))
+}
+
+/// Fills the Target Info of the QE into `out_buf` and returns the number of bytes written.
+pub(super) fn target_info(out_buf: &mut [u8]) -> Result<usize> {
+    match resolved()? {
+        Provider::Dcap => hint(
+            dcap::get_target_info(out_buf),
+            Provider::Dcap,
+            "getting target info",
+        ),
+        _ => {
+            let akid = hint(
+                attestation::get_attestation_key_id(),
+                Provider::Aesm,
+                "obtaining attestation key id",
+            )?;
+            let pkeysize = hint(
+                attestation::get_key_size(akid.clone()),
+                Provider::Aesm,
+                "obtaining key size",
+            )?;
+            hint(
+                attestation::get_target_info(akid, pkeysize, out_buf),
+                Provider::Aesm,
+                "getting target info",
+            )
+        }
+    }
+}
+
+/// Returns the size of the quotes.
+pub(super) fn quote_size() -> Result<usize> {
+    match resolved()? {
+        Provider::Dcap => hint(dcap::get_quote_size(), Provider::Dcap, "getting quote size"),
+        _ => {
+            let akid = hint(
+                attestation::get_attestation_key_id(),
+                Provider::Aesm,
+                "obtaining attestation key id",
+            )?;
+            hint(
+                attestation::get_quote_size(akid),
+                Provider::Aesm,
+                "getting quote size",
+            )
+        }
+    }
+}
+
+/// Fills the quote of `report` into `out_buf` and returns the number of bytes written.
+pub(super) fn quote(report: &[u8], out_buf: &mut [u8]) -> Result<usize> {
+    match resolved()? {
+        Provider::Dcap => hint(
+            dcap::get_quote(report, out_buf),
+            Provider::Dcap,
+            "getting quote",
+        ),
+        _ => {
+            let akid = hint(
+                attestation::get_attestation_key_id(),
+                Provider::Aesm,
+                "obtaining attestation key id",
+            )?;
+            hint(
+                attestation::get_quote(report, akid, out_buf),
+                Provider::Aesm,
+                "getting quote",
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Provider;
+
+    #[test]
+    fn provider() {
+        assert_eq!("auto".parse::<Provider>().unwrap(), Provider::Auto);
+        assert_eq!("aesm".parse::<Provider>().unwrap(), Provider::Aesm);
+        assert_eq!("dcap".parse::<Provider>().unwrap(), Provider::Dcap);
+        assert!("qe".parse::<Provider>().is_err());
+        assert_eq!(Provider::Aesm.info(), super::AESM_SOCKET);
+        assert_eq!(Provider::Dcap.info(), super::dcap::LIBRARY);
+    }
+}
@@ -1,11 +1,10 @@
 // SPDX-License-Identifier: Apache-2.0
 
-use super::attestation::{get_attestation_key_id, get_key_size, get_quote, get_target_info};
+use super::quote;
 #[cfg(feature = "gdb")]
 use crate::backend::execute_gdb;
-use crate::backend::sgx::attestation::get_quote_size;
 use crate::backend::sgx::ioctls::*;
-use crate::backend::Command;
+use crate::backend::{stats, Command};
 
 use std::arch::asm;
 use std::arch::x86_64::CpuidResult;
@@ -115,15 +114,7 @@ fn sgx_enarxcall<'a>(
                     .map_err(io::Error::from_raw_os_error)
                     .context("sgx_enarxcall deref")?
             };
-            let akid = get_attestation_key_id().context(
-                "Error obtaining attestation key id. Check your aesmd / pccs service installation.",
-            )?;
-            let pkeysize = get_key_size(akid.clone()).context(
-                "Error obtaining key size. Check your aesmd / pccs service installation.",
-            )?;
-            *ret = get_target_info(akid, pkeysize, out_buf).context(
-                "Error getting target info. Check your aesmd / pccs service installation.",
-            )?;
+            *ret = quote::target_info(out_buf)?;
 
             Ok(None)
         }
@@ -151,11 +142,7 @@ fn sgx_enarxcall<'a>(
                     .context("sgx_enarxcall deref")?
             };
 
-            let akid = get_attestation_key_id().context(
-                "Error obtaining attestation key id. Check your aesmd / pccs service installation.",
-            )?;
-            *ret = get_quote(report_buf, akid, quote_buf)
-                .context("Error getting quote. Check your aesmd / pccs service installation.")?;
+            *ret = quote::quote(report_buf, quote_buf)?;
 
             Ok(None)
         }
@@ -165,12 +152,7 @@ fn sgx_enarxcall<'a>(
             ret,
             ..
         } => {
-            let akid = get_attestation_key_id().context(
-                "Error obtaining attestation key id. Check your aesmd / pccs service installation.",
-            )?;
-            *ret = get_quote_size(akid).context(
-                "Error getting quote size. Check your aesmd / pccs service installation.",
-            )?;
+            *ret = quote::quote_size()?;
 
             Ok(None)
         }
@@ -359,7 +341,9 @@ impl super::super::Thread for Thread {
         if self.cssa > 0 {
             if let (EENTER, ERESUME) = (how, self.how) {
                 let block: Block = self.block.as_mut_slice().into();
+                stats::exit();
                 for item in block {
+                    stats::item(&item);
                     match item {
                         Item::Gdbcall(_gdbcall, _data) => {
                             #[cfg(feature = "gdb")]
@@ -0,0 +1,169 @@
+// SPDX-License-Identifier: Apache-2.0
+//! The signature file written by `enarx sign`
+//!
+//! A signature file is a JSON object carrying the version of its format and the signatures of
+//! the keep payload for every backend, which signs its keeps. Format versions are
+//! `<major>.<minor>`: files of a newer minor version remain readable, since fields are only
+//! ever added, while files of another major version are rejected. The JSON schema of the
+//! current version is [`SCHEMA`].
+
+use std::fmt;
+use std::fs;
+
+use anyhow::{anyhow, bail, Context, Result};
+use camino::Utf8PathBuf;
+use serde::{Deserialize, Serialize};
+
+/// Version of the format of signature files written by this version of enarx
+pub const SIGNATURES_VERSION: &str = "1.0";
+
+/// JSON schema of the current format of signature files
+pub const SCHEMA: &str = include_str!("signatures.schema.json");
+
+/// The SEV-SNP signature of a keep
+#[derive(Default, Debug, Deserialize, Serialize)]
+pub struct SevSignature {
+    /// The signed ID block
+    pub id_block: Vec<u8>,
+    /// The ID auth information structure, carrying the signatures and the public keys
+    pub id_auth: Vec<u8>,
+}
+
+/// The contents of a signature file
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Signatures {
+    /// Version of the format of the file
+    pub version: String,
+    /// The SEV-SNP signature, empty if the keep is not signed for SEV
+    #[serde(default)]
+    pub sev: SevSignature,
+    /// The SGX SIGSTRUCT, empty if the keep is not signed for SGX
+    #[serde(default)]
+    pub sgx: Vec<u8>,
+}
+
+impl Default for Signatures {
+    fn default() -> Self {
+        Self {
+            version: SIGNATURES_VERSION.into(),
+            sev: SevSignature::default(),
+            sgx: vec![],
+        }
+    }
+}
+
+/// Error of a keep, whose signature does not cover its measurement
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    /// Name of the backend of the keep
+    pub backend: &'static str,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} signature does not match the measurement",
+            self.backend.to_uppercase()
+        )
+    }
+}
+
+impl std::error::Error for Mismatch {}
+
+/// Only the version of a signature file, which is parsed before the rest of it
+#[derive(Deserialize)]
+struct Versioned {
+    version: String,
+}
+
+/// Returns the major and minor version of the format `version`.
+///
+/// Signature files written before the format was versioned carry the version of enarx instead,
+/// e.g. `0.6.2`, and are of format version `1.0`.
+fn format(version: &str) -> Result<(u64, u64)> {
+    let parts = version
+        .split('.')
+        .map(str::parse)
+        .collect::<Result<Vec<u64>, _>>()
+        .map_err(|_| anyhow!("Invalid signature file version {:?}", version))?;
+    match parts[..] {
+        [major, minor] => Ok((major, minor)),
+        [_, _, _] => Ok((1, 0)),
+        _ => bail!("Invalid signature file version {:?}", version),
+    }
+}
+
+impl Signatures {
+    /// Parses the signature file `json`, if its format is supported.
+    pub fn parse(json: &str) -> Result<Self> {
+        let Versioned { version } =
+            serde_json::from_str(json).context("Invalid signature file, which lacks a version")?;
+        let (major, _) = format(&version)?;
+        let (supported, _) = format(SIGNATURES_VERSION)?;
+        if major != supported {
+            bail!(
+                "Signature file version {} is not supported, this version of enarx reads version {}.x",
+                version,
+                supported
+            );
+        }
+        serde_json::from_str(json)
+            .with_context(|| format!("Invalid signature file of version {version}"))
+    }
+
+    pub fn load(path: Option<Utf8PathBuf>) -> Result<Option<Self>> {
+        match path {
+            None => Ok(None),
+            Some(path) => {
+                let json = fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read signature file `{path}`"))?;
+                Self::parse(&json).map(Some)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions() {
+        assert_eq!(format(SIGNATURES_VERSION).unwrap(), (1, 0));
+        assert_eq!(format("0.6.2").unwrap(), (1, 0));
+        assert_eq!(format("1.3").unwrap(), (1, 3));
+        assert!(format("1").is_err());
+        assert!(format("v1.0").is_err());
+    }
+
+    #[test]
+    fn parsing() {
+        let json = serde_json::to_string(&Signatures::default()).unwrap();
+        assert_eq!(
+            Signatures::parse(&json).unwrap().version,
+            SIGNATURES_VERSION
+        );
+
+        // Files of a newer minor version may carry unknown fields and lack signatures.
+        let newer = Signatures::parse(r#"{"version":"1.1","sgx":[1,2],"tdx":[]}"#).unwrap();
+        assert_eq!(newer.sgx, [1, 2]);
+        assert!(newer.sev.id_block.is_empty());
+
+        let legacy = r#"{"version":"0.6.2","sev":{"id_block":[],"id_auth":[]},"sgx":[]}"#;
+        assert!(Signatures::parse(legacy).is_ok());
+
+        assert!(Signatures::parse(r#"{"version":"2.0","sgx":[]}"#).is_err());
+        assert!(Signatures::parse(r#"{"sgx":[]}"#).is_err());
+        assert!(Signatures::parse(r#"{"version":"1.0","sgx":"invalid"}"#).is_err());
+    }
+
+    #[test]
+    fn schema() {
+        let schema: serde_json::Value = serde_json::from_str(SCHEMA).unwrap();
+        assert_eq!(
+            schema["properties"]["version"]["pattern"],
+            format!("^{}\\.[0-9]+$", format(SIGNATURES_VERSION).unwrap().0)
+        );
+    }
+}
@@ -0,0 +1,53 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Counters of the exits of the keep to the host, recorded by the host while handling them.
+//!
+//! Unlike the counters of the shim, which are only reported by the keep, these are available to
+//! the host at any time, e.g. while the keep runs a workload, which cannot spawn a thread to
+//! report its metrics periodically.
+
+use std::mem::{size_of, size_of_val};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use enarx_exec_wasmtime::Metrics;
+use sallyport::item::enarxcall::STATS_SYSCALLS;
+use sallyport::item::{Header, Item};
+
+static EXITS: AtomicU64 = AtomicU64::new(0);
+static BYTES: AtomicU64 = AtomicU64::new(0);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+static SYSCALLS: [AtomicU64; STATS_SYSCALLS] = [ZERO; STATS_SYSCALLS];
+
+/// Records an exit of the keep to the host.
+pub fn exit() {
+    EXITS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records `item` of the sallyport block passed to the host by an exit.
+pub fn item(item: &Item<'_>) {
+    let (size, data) = match item {
+        Item::Syscall(syscall, data) => {
+            if let Some(count) = SYSCALLS.get(syscall.num) {
+                count.fetch_add(1, Ordering::Relaxed);
+            }
+            (size_of_val(*syscall), data.len())
+        }
+        Item::Gdbcall(gdbcall, data) => (size_of_val(*gdbcall), data.len()),
+        Item::Enarxcall(enarxcall, data) => (size_of_val(*enarxcall), data.len()),
+    };
+    BYTES.fetch_add((size_of::<Header>() + size + data) as _, Ordering::Relaxed);
+}
+
+/// Overwrites the sallyport counters of `metrics` with the ones recorded by the host.
+pub fn collect(metrics: &mut Metrics) {
+    metrics.exits = EXITS.load(Ordering::Relaxed);
+    metrics.bytes = BYTES.load(Ordering::Relaxed);
+    metrics.syscalls = SYSCALLS
+        .iter()
+        .map(|count| count.load(Ordering::Relaxed))
+        .enumerate()
+        .filter(|(_, count)| *count > 0)
+        .collect();
+}
@@ -0,0 +1,468 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::backend::Signatures;
+use crate::cli::rundev::anonymous;
+use crate::cli::BackendOptions;
+use crate::exec::events::{self, Target};
+use crate::exec::{run_package, RunOptions, EXECS};
+
+use std::fmt::Debug;
+use std::io::{BufRead, BufReader};
+use std::os::unix::io::{AsRawFd, IntoRawFd};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, ensure, Context};
+use camino::Utf8PathBuf;
+use clap::Args;
+use enarx_config::{Config, File};
+use enarx_exec_wasmtime::{Event, Package};
+use url::Url;
+use wasm_encoder::{
+    BlockType, CodeSection, ConstExpr, DataSection, EntityType, ExportKind, ExportSection,
+    Function, FunctionSection, ImportSection, Instruction, MemArg, MemorySection, MemoryType,
+    Module, TypeSection, ValType,
+};
+
+/// Print attestation evidence of an Enarx Keep bound to a nonce.
+///
+/// Launches a keep running a built-in module, which writes the nonce to the
+/// `attestation` file of the keep and copies the evidence it yields to
+/// standard output: the SGX quote, or the SEV-SNP attestation report with the
+/// VCEK certificate. The evidence of a KVM keep is empty.
+///
+/// The evidence is binary, so redirect standard output to a file, which can be
+/// checked with `enarx verify --report-data <NONCE>`.
+///
+/// With `--steward` or `--wasmcfgfile` instead of `--nonce`, the keep runs the
+/// full attestation handshake with the Stewards as a pre-flight check, without
+/// running a workload: it prints the certificate chain issued by the Steward
+/// to standard output and the time each step took to standard error, such
+/// that the platform and the reachability of the Stewards can be checked
+/// before deploying an application.
+#[derive(Args, Debug)]
+pub struct Options {
+    #[clap(flatten)]
+    pub backend: BackendOptions,
+
+    /// Hex-encoded nonce of up to 64 bytes the evidence is bound to, padded with zeros
+    #[clap(
+        long,
+        value_name = "HEX",
+        required_unless_present_any = &["stewards", "wasmcfgfile"],
+        conflicts_with_all = &["stewards", "wasmcfgfile"]
+    )]
+    pub nonce: Option<String>,
+
+    /// URL of a Steward to obtain the certificate of the keep from
+    ///
+    /// May be given multiple times, the Stewards are tried in order.
+    #[clap(long = "steward", value_name = "URL", conflicts_with = "wasmcfgfile")]
+    pub stewards: Vec<Url>,
+
+    /// Path of the Enarx.toml of an application to take the Steward settings from
+    #[clap(long, value_name = "FILE")]
+    pub wasmcfgfile: Option<Utf8PathBuf>,
+
+    /// Path of the signature file to use.
+    #[clap(long, value_name = "SIGNATURES")]
+    pub signatures: Option<Utf8PathBuf>,
+}
+
+/// The config of the module, the `attestation` file being fd 3
+const CONFIG: &str = r#"[[files]]
+kind = "null"
+
+[[files]]
+kind = "stdout"
+
+[[files]]
+kind = "stderr"
+
+[[files]]
+kind = "attestation"
+"#;
+
+/// The files of the module of the pre-flight check, the `certs` file being fd 3
+fn preflight_files() -> Vec<File> {
+    vec![
+        File::Null { name: None },
+        File::Stdout { name: None },
+        File::Stderr { name: None },
+        File::Certs { name: None },
+    ]
+}
+
+/// The size of the report data the evidence is bound to
+const DATA_SIZE: usize = 64;
+
+/// Returns the nonce encoded in `hex`.
+fn nonce(hex: &str) -> anyhow::Result<Vec<u8>> {
+    ensure!(hex.len() % 2 == 0, "invalid hex string {:?}", hex);
+    let nonce = (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+                .ok_or_else(|| anyhow!("invalid hex string {:?}", hex))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    ensure!(nonce.len() <= DATA_SIZE, "nonce exceeds {DATA_SIZE} bytes");
+    Ok(nonce)
+}
+
+/// Returns the config of the pre-flight check with the Steward settings of `stewards` or of the
+/// config at `wasmcfgfile`.
+fn preflight_config(
+    stewards: Vec<Url>,
+    wasmcfgfile: Option<Utf8PathBuf>,
+) -> anyhow::Result<String> {
+    let config = match wasmcfgfile {
+        Some(path) => {
+            let config = std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read `{path}`"))?;
+            let config: Config =
+                toml::from_str(&config).with_context(|| format!("failed to parse `{path}`"))?;
+            ensure!(
+                !config.steward.is_empty(),
+                "`{path}` configures no `steward`"
+            );
+            Config {
+                steward: config.steward,
+                steward_retries: config.steward_retries,
+                steward_backoff: config.steward_backoff,
+                steward_proxy: config.steward_proxy,
+                steward_hints: config.steward_hints,
+                files: preflight_files(),
+                ..Default::default()
+            }
+        }
+        None => Config {
+            steward: stewards,
+            files: preflight_files(),
+            ..Default::default()
+        },
+    };
+    toml::to_string(&config).context("failed to encode package config")
+}
+
+/// Returns `duration` in seconds with millisecond precision.
+fn secs(duration: Duration) -> String {
+    format!("{:.3}s", duration.as_secs_f64())
+}
+
+/// Prints the lifecycle events of the keep read from `reader` with the time elapsed since
+/// `start`, once the keep was attested.
+fn report(reader: std::fs::File, start: Instant) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut measured = None;
+        for line in BufReader::new(reader).lines() {
+            let event = match line.map(|line| serde_json::from_str::<Event>(&line)) {
+                Ok(Ok(event)) => event,
+                _ => continue,
+            };
+            let elapsed = start.elapsed();
+            match event {
+                Event::KeepMeasured { measurement } => {
+                    eprintln!("Keep measured after {}", secs(elapsed));
+                    measured = measurement;
+                }
+                Event::PackageFetched { .. } => {
+                    eprintln!("Keep started after {}", secs(elapsed))
+                }
+                Event::Attested {
+                    identity,
+                    steward,
+                    serial,
+                    ..
+                } => {
+                    match steward {
+                        Some(steward) => {
+                            eprintln!("Keep attested by `{steward}` after {}", secs(elapsed))
+                        }
+                        None => eprintln!("Keep attested after {}", secs(elapsed)),
+                    }
+                    eprintln!("  measurement: {}", measured.as_deref().unwrap_or("none"));
+                    eprintln!("  identity:    {identity}");
+                    eprintln!("  serial:      {serial}");
+                }
+                _ => {}
+            }
+        }
+    })
+}
+
+impl Options {
+    pub fn execute(self) -> anyhow::Result<()> {
+        let Self {
+            backend,
+            nonce: hex,
+            stewards,
+            wasmcfgfile,
+            signatures,
+        } = self;
+        let (nonce, config) = match hex {
+            Some(hex) => (Some(nonce(&hex)?), CONFIG.to_string()),
+            None => (None, preflight_config(stewards, wasmcfgfile)?),
+        };
+        if nonce.is_some() && atty::is(atty::Stream::Stdout) {
+            bail!("refusing to write binary evidence to a terminal, redirect standard output to a file");
+        }
+        let start = Instant::now();
+
+        let backend = backend.pick()?;
+        let exec = EXECS
+            .iter()
+            .find(|w| w.with_backend(backend))
+            .ok_or_else(|| anyhow!("no supported exec found"))
+            .map(|b| b.exec())?;
+
+        let signatures = Signatures::load(signatures)?;
+
+        // The events of the pre-flight check are timed as they arrive.
+        let events = match nonce {
+            Some(_) => None,
+            None => {
+                let (reader, writer) = events::pipe().context("failed to create events pipe")?;
+                Some((writer, report(reader, start)))
+            }
+        };
+
+        let get_pkg = || {
+            let wasm = anonymous("attest.wasm", &module(nonce.as_deref()))
+                .context("failed to write module")?;
+            let conf = anonymous("attest.toml", config.as_bytes())
+                .context("failed to write package config")?;
+            Ok(Package::Local {
+                wasm: wasm.into_raw_fd(),
+                conf: Some(conf.into_raw_fd()),
+                modules: Default::default(),
+                data: Default::default(),
+            })
+        };
+
+        let target = events
+            .as_ref()
+            .map(|(writer, _)| Target::Fd(writer.as_raw_fd()));
+        let options = RunOptions {
+            signatures,
+            events: target,
+            ..Default::default()
+        };
+        let code = run_package(backend, exec, options, get_pkg);
+        if let Some((writer, reporter)) = events {
+            drop(writer);
+            reporter.join().expect("failed to join events thread");
+        }
+        std::process::exit(code?);
+    }
+}
+
+/// The `iovec` of the nonce
+const NONCE_IOV: i32 = 0;
+/// The `iovec` of the buffer the evidence is read into
+const READ_IOV: i32 = 16;
+/// The `iovec` of the part of the buffer left to write
+const WRITE_IOV: i32 = 32;
+/// The number of bytes read or written
+const OUT: i32 = 48;
+/// The nonce
+const NONCE: i32 = 64;
+/// The buffer the evidence is read into, up to the end of the page
+const BUF: i32 = 4096;
+/// The size of [`BUF`]
+const BUF_SIZE: i32 = 65536 - BUF;
+
+/// The file descriptor of standard output
+const STDOUT: i32 = 1;
+/// The file descriptor of the `attestation` file in [`CONFIG`]
+const ATTESTATION: i32 = 3;
+
+fn mem(offset: u64) -> MemArg {
+    MemArg {
+        offset,
+        align: 2,
+        memory_index: 0,
+    }
+}
+
+/// Returns the module writing `nonce` to the `attestation` file and copying the evidence to
+/// standard output, or copying the `certs` file to standard output without a `nonce`.
+///
+/// The module exits with the errno of the first failing call.
+fn module(nonce: Option<&[u8]>) -> Vec<u8> {
+    const FD_READ: u32 = 0;
+    const FD_WRITE: u32 = 1;
+    const PROC_EXIT: u32 = 2;
+    const CHECK: u32 = 3;
+    const START: u32 = 4;
+
+    let mut types = TypeSection::new();
+    types.function([ValType::I32; 4], [ValType::I32]);
+    types.function([ValType::I32], []);
+    types.function([], []);
+
+    let mut imports = ImportSection::new();
+    for (name, ty) in [("fd_read", 0), ("fd_write", 0), ("proc_exit", 1)] {
+        imports.import("wasi_snapshot_preview1", name, EntityType::Function(ty));
+    }
+
+    let mut functions = FunctionSection::new();
+    functions.function(1);
+    functions.function(2);
+
+    let mut memories = MemorySection::new();
+    memories.memory(MemoryType {
+        minimum: 1,
+        maximum: None,
+        memory64: false,
+        shared: false,
+    });
+
+    let mut exports = ExportSection::new();
+    exports.export("memory", ExportKind::Memory, 0);
+    exports.export("_start", ExportKind::Func, START);
+
+    // Exit with the errno (param 0) unless it is zero.
+    let mut check_fn = Function::new([]);
+    for instruction in [
+        Instruction::LocalGet(0),
+        Instruction::If(BlockType::Empty),
+        Instruction::LocalGet(0),
+        Instruction::Call(PROC_EXIT),
+        Instruction::End,
+        Instruction::End,
+    ] {
+        check_fn.instruction(&instruction);
+    }
+
+    let mut start_fn = Function::new([]);
+    if nonce.is_some() {
+        for instruction in [
+            // Bind the evidence to the nonce.
+            Instruction::I32Const(ATTESTATION),
+            Instruction::I32Const(NONCE_IOV),
+            Instruction::I32Const(1),
+            Instruction::I32Const(OUT),
+            Instruction::Call(FD_WRITE),
+            Instruction::Call(CHECK),
+        ] {
+            start_fn.instruction(&instruction);
+        }
+    }
+    for instruction in [
+        // Copy the evidence until the end of it is read.
+        Instruction::Block(BlockType::Empty),
+        Instruction::Loop(BlockType::Empty),
+        Instruction::I32Const(ATTESTATION),
+        Instruction::I32Const(READ_IOV),
+        Instruction::I32Const(1),
+        Instruction::I32Const(OUT),
+        Instruction::Call(FD_READ),
+        Instruction::Call(CHECK),
+        Instruction::I32Const(OUT),
+        Instruction::I32Load(mem(0)),
+        Instruction::I32Eqz,
+        Instruction::BrIf(1),
+        Instruction::I32Const(WRITE_IOV),
+        Instruction::I32Const(BUF),
+        Instruction::I32Store(mem(0)),
+        Instruction::I32Const(WRITE_IOV),
+        Instruction::I32Const(OUT),
+        Instruction::I32Load(mem(0)),
+        Instruction::I32Store(mem(4)),
+        // Write the read bytes, continuing after partial writes.
+        Instruction::Loop(BlockType::Empty),
+        Instruction::I32Const(STDOUT),
+        Instruction::I32Const(WRITE_IOV),
+        Instruction::I32Const(1),
+        Instruction::I32Const(OUT),
+        Instruction::Call(FD_WRITE),
+        Instruction::Call(CHECK),
+        Instruction::I32Const(WRITE_IOV),
+        Instruction::I32Const(WRITE_IOV),
+        Instruction::I32Load(mem(0)),
+        Instruction::I32Const(OUT),
+        Instruction::I32Load(mem(0)),
+        Instruction::I32Add,
+        Instruction::I32Store(mem(0)),
+        Instruction::I32Const(WRITE_IOV),
+        Instruction::I32Const(WRITE_IOV),
+        Instruction::I32Load(mem(4)),
+        Instruction::I32Const(OUT),
+        Instruction::I32Load(mem(0)),
+        Instruction::I32Sub,
+        Instruction::I32Store(mem(4)),
+        Instruction::I32Const(WRITE_IOV),
+        Instruction::I32Load(mem(4)),
+        Instruction::BrIf(0),
+        Instruction::End,
+        Instruction::Br(0),
+        Instruction::End,
+        Instruction::End,
+        Instruction::End,
+    ] {
+        start_fn.instruction(&instruction);
+    }
+
+    let mut code = CodeSection::new();
+    code.function(&check_fn);
+    code.function(&start_fn);
+
+    let nonce = nonce.unwrap_or_default();
+    let iovec = |ptr: i32, len: i32| [ptr.to_le_bytes(), len.to_le_bytes()].concat();
+    let mut data = DataSection::new();
+    data.active(
+        0,
+        &ConstExpr::i32_const(NONCE_IOV),
+        iovec(NONCE, nonce.len() as _),
+    );
+    data.active(0, &ConstExpr::i32_const(READ_IOV), iovec(BUF, BUF_SIZE));
+    data.active(0, &ConstExpr::i32_const(NONCE), nonce.iter().copied());
+
+    let mut module = Module::new();
+    module.section(&types);
+    module.section(&imports);
+    module.section(&functions);
+    module.section(&memories);
+    module.section(&exports);
+    module.section(&code);
+    module.section(&data);
+    module.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{module, nonce, preflight_config, CONFIG};
+
+    use enarx_config::{Config, File};
+
+    #[test]
+    fn valid() {
+        wasmparser::validate(&module(Some(&[]))).unwrap();
+        wasmparser::validate(&module(Some(&[0xff; 64]))).unwrap();
+        wasmparser::validate(&module(None)).unwrap();
+        let config: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(config.files.len(), 4);
+    }
+
+    #[test]
+    fn preflight() {
+        let url = "https://steward.example.com".parse().unwrap();
+        let config = preflight_config(vec![url], None).unwrap();
+        let config: Config = toml::from_str(&config).unwrap();
+        assert_eq!(config.steward.len(), 1);
+        assert_eq!(config.steward_required, None);
+        assert_eq!(config.files[3], File::Certs { name: None });
+    }
+
+    #[test]
+    fn nonces() {
+        assert_eq!(nonce("").unwrap(), Vec::<u8>::new());
+        assert_eq!(nonce("00ff7A").unwrap(), vec![0x00, 0xff, 0x7a]);
+        assert!(nonce("0").is_err());
+        assert!(nonce("zz").is_err());
+        assert!(nonce(&"00".repeat(64)).is_ok());
+        assert!(nonce(&"00".repeat(65)).is_err());
+    }
+}
@@ -0,0 +1,22 @@
+// SPDX-License-Identifier: Apache-2.0
+
+mod show;
+mod verify;
+
+use clap::Subcommand;
+
+/// Commands for inspecting the audit log of keep launches.
+#[derive(Subcommand, Debug)]
+pub enum Subcommands {
+    Show(show::Options),
+    Verify(verify::Options),
+}
+
+impl Subcommands {
+    pub fn dispatch(self) -> anyhow::Result<()> {
+        match self {
+            Self::Show(cmd) => cmd.execute(),
+            Self::Verify(cmd) => cmd.execute(),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::exec::audit::{read, Entry};
+
+use std::fs::File;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::Args;
+
+/// Print the keep launches recorded in an audit log.
+///
+/// The chain of the entries is verified while reading them, so a log, which
+/// was tampered with, is not shown.
+#[derive(Args, Debug)]
+pub struct Options {
+    /// Print the entries as JSON lines
+    #[clap(long)]
+    json: bool,
+
+    /// Path of the audit log
+    #[clap(value_name = "FILE", env = "ENARX_AUDIT_LOG")]
+    path: PathBuf,
+}
+
+/// Returns the human-readable form of `entry`.
+fn format(entry: &Entry) -> String {
+    let launch = &entry.launch;
+    let mut out = format!("#{} {} on {}\n", entry.seq, entry.time, entry.host);
+    let mut field = |name: &str, value: Option<&str>| {
+        if let Some(value) = value {
+            out.push_str(&format!("  {name:<12}{value}\n"));
+        }
+    };
+    field("backend", launch.backend.as_deref());
+    field("measurement", launch.measurement.as_deref());
+    field("package", launch.package.as_deref());
+    field("workload", launch.workload.as_deref());
+    field("identity", launch.identity.as_deref());
+    field("certificate", launch.certificate.as_deref());
+    field("serial", launch.serial.as_deref());
+    match (&launch.attested, &launch.steward) {
+        (Some(_), Some(steward)) => field("steward", Some(steward)),
+        (Some(_), None) => field("steward", Some("none, self-signed")),
+        (None, _) => {}
+    }
+    field("started", Some(&launch.started));
+    match (&launch.attested, launch.code) {
+        (Some(attested), _) => field("attested", Some(attested)),
+        (None, Some(code)) => field("attested", Some(&format!("no, exited with {code}"))),
+        (None, None) => field("attested", Some("no")),
+    }
+    out
+}
+
+impl Options {
+    pub fn execute(self) -> anyhow::Result<()> {
+        let file = File::open(&self.path)
+            .with_context(|| format!("failed to open audit log `{}`", self.path.display()))?;
+        for (entry, _) in read(file)? {
+            if self.json {
+                println!("{}", serde_json::to_string(&entry)?);
+            } else {
+                println!("{}", format(&entry));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::exec::audit::{read, GENESIS};
+
+use std::fs::File;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context};
+use clap::Args;
+
+/// Verify the chain of the entries of an audit log.
+///
+/// Prints the number of entries and the digest of the last entry. The removal
+/// of the last entries of a log cannot be detected from the log itself, so
+/// record the digest elsewhere and pass it to `--head` on later
+/// verifications, which fail, unless the recorded entry is still in the log.
+#[derive(Args, Debug)]
+pub struct Options {
+    /// Hex-encoded digest of an entry, which must be part of the chain
+    #[clap(long, value_name = "DIGEST")]
+    head: Option<String>,
+
+    /// Path of the audit log
+    #[clap(value_name = "FILE", env = "ENARX_AUDIT_LOG")]
+    path: PathBuf,
+}
+
+impl Options {
+    pub fn execute(self) -> anyhow::Result<()> {
+        let file = File::open(&self.path)
+            .with_context(|| format!("failed to open audit log `{}`", self.path.display()))?;
+        let entries = read(file)
+            .with_context(|| format!("audit log `{}` is invalid", self.path.display()))?;
+        if let Some(head) = self.head {
+            if !entries
+                .iter()
+                .any(|(_, hash)| hash.eq_ignore_ascii_case(&head))
+            {
+                bail!(
+                    "audit log `{}` does not contain the entry `{head}`, entries were removed",
+                    self.path.display()
+                );
+            }
+        }
+        let last = entries.last().map_or(GENESIS, |(_, hash)| hash);
+        println!("{} entries, last entry {last}", entries.len());
+        Ok(())
+    }
+}
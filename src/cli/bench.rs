@@ -0,0 +1,285 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::backend::{Backend, BACKENDS};
+
+use std::fs;
+use std::ops::Deref;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::time::Instant;
+
+use anyhow::{anyhow, bail, Context, Result};
+use camino::Utf8PathBuf;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+
+/// Benchmark running a WebAssembly module inside Enarx Keeps.
+///
+/// The module is run the given number of times on every selected backend, in a new
+/// `enarx run` process with `--trace-out` every time. The time spent in every stage of the
+/// keep is read from its trace and reported with the total time of the run in microseconds,
+/// as JSON or CSV on stdout. The output of the module is discarded and it does not read stdin.
+#[derive(Args, Debug)]
+pub struct Options {
+    /// Backend to run the module on, all backends available on this host by default
+    ///
+    /// May be given multiple times.
+    #[clap(long = "backend", value_name = "BACKEND")]
+    pub backends: Vec<String>,
+
+    /// Number of times to run the module on every backend
+    #[clap(short = 'n', long, value_name = "N", default_value_t = 10)]
+    pub iterations: usize,
+
+    /// Format of the report ("json", "csv")
+    #[clap(long, default_value = "json")]
+    pub format: Format,
+
+    #[clap(long, env = "ENARX_WASMCFGFILE")]
+    pub wasmcfgfile: Option<Utf8PathBuf>,
+
+    /// Path of the WebAssembly module to benchmark
+    #[clap(value_name = "MODULE")]
+    pub module: Utf8PathBuf,
+}
+
+/// Format of the report of `enarx bench`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            _ => Err(anyhow!("unknown report format {:?}", s)),
+        }
+    }
+}
+
+/// Stages of exec-wasmtime, in the order they are run, as named in the trace
+const STAGES: [&str; 5] = ["attest", "fetch", "compile", "connect", "run"];
+
+/// Times of a single run of the module in microseconds
+#[derive(Serialize, Debug, PartialEq, Eq)]
+struct Sample {
+    backend: &'static str,
+    iteration: usize,
+    /// Time between launching `enarx run` and its exit
+    total_us: u64,
+    /// Time spent outside of the stages of exec-wasmtime, i.e. creating and tearing down the keep
+    keep_us: u64,
+    /// Time spent attesting the keep
+    attest_us: u64,
+    /// Time spent obtaining a certificate and the package
+    fetch_us: u64,
+    /// Time spent compiling the module
+    compile_us: u64,
+    /// Time spent setting up the sockets of the module
+    connect_us: u64,
+    /// Time spent executing the module
+    run_us: u64,
+}
+
+impl Sample {
+    const CSV_HEADER: &'static str =
+        "backend,iteration,total_us,keep_us,attest_us,fetch_us,compile_us,connect_us,run_us";
+
+    fn csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{}",
+            self.backend,
+            self.iteration,
+            self.total_us,
+            self.keep_us,
+            self.attest_us,
+            self.fetch_us,
+            self.compile_us,
+            self.connect_us,
+            self.run_us
+        )
+    }
+}
+
+/// A closed span of the trace of a keep
+#[derive(Deserialize, Debug)]
+struct Span {
+    span: String,
+    parent: Option<u64>,
+    target: String,
+    duration_us: u64,
+}
+
+/// Returns the durations of the [`STAGES`] recorded in `trace`.
+fn stages(trace: &str) -> Result<[u64; STAGES.len()]> {
+    let mut durations = [None; STAGES.len()];
+    for line in trace.lines() {
+        // Events have a numeric `span`, so they fail to parse as spans.
+        let span: Span = match serde_json::from_str(line) {
+            Ok(span) => span,
+            Err(_) => continue,
+        };
+        if span.parent.is_some() || span.target != "enarx_exec_wasmtime" {
+            continue;
+        }
+        if let Some(i) = STAGES.iter().position(|stage| *stage == span.span) {
+            durations[i] = Some(span.duration_us);
+        }
+    }
+
+    let mut stages = [0; STAGES.len()];
+    for (i, duration) in durations.into_iter().enumerate() {
+        stages[i] = duration.ok_or_else(|| anyhow!("the trace lacks the {} stage", STAGES[i]))?;
+    }
+    Ok(stages)
+}
+
+impl Options {
+    /// Returns the backends to run the module on.
+    fn backends(&self) -> Result<Vec<&'static dyn Backend>> {
+        let all = || BACKENDS.deref().iter().map(|b| b.deref());
+        if self.backends.is_empty() {
+            return Ok(all().filter(|b| b.have() && b.configured()).collect());
+        }
+        self.backends
+            .iter()
+            .map(|name| {
+                let backend = all()
+                    .find(|b| b.name() == name)
+                    .ok_or_else(|| anyhow!("Keep backend identifier {:?} is unknown.", name))?;
+                if !backend.have() || !backend.configured() {
+                    bail!("Keep backend {:?} is not available on this platform.", name)
+                }
+                Ok(backend)
+            })
+            .collect()
+    }
+
+    /// Runs the module once on `backend`, writing the trace to `trace`.
+    fn sample(
+        &self,
+        backend: &'static str,
+        iteration: usize,
+        trace: &Utf8PathBuf,
+    ) -> Result<Sample> {
+        let exe = std::env::current_exe().context("failed to locate the enarx executable")?;
+        let mut cmd = Command::new(exe);
+        cmd.args(["run", "--backend", backend, "--trace-out", trace.as_str()]);
+        if let Some(ref wasmcfgfile) = self.wasmcfgfile {
+            cmd.args(["--wasmcfgfile", wasmcfgfile.as_str()]);
+        }
+        cmd.arg(&self.module)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped());
+
+        let start = Instant::now();
+        let output = cmd
+            .output()
+            .with_context(|| format!("failed to launch a keep on {backend}"))?;
+        let total_us = start.elapsed().as_micros() as u64;
+        let read = fs::read_to_string(trace).with_context(|| format!("failed to read `{trace}`"));
+        let _ = fs::remove_file(trace);
+        if !output.status.success() {
+            bail!(
+                "iteration {iteration} on {backend} failed with {}:\n{}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        let [attest_us, fetch_us, compile_us, connect_us, run_us] = stages(&read?)
+            .with_context(|| format!("invalid trace of iteration {iteration} on {backend}"))?;
+        Ok(Sample {
+            backend,
+            iteration,
+            total_us,
+            keep_us: total_us
+                .saturating_sub(attest_us + fetch_us + compile_us + connect_us + run_us),
+            attest_us,
+            fetch_us,
+            compile_us,
+            connect_us,
+            run_us,
+        })
+    }
+
+    pub fn execute(self) -> anyhow::Result<()> {
+        if self.iterations == 0 {
+            bail!("at least one iteration is required");
+        }
+        let backends = self.backends()?;
+        if backends.is_empty() {
+            bail!("no keep backend is available on this platform");
+        }
+        let dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .map_err(|dir| anyhow!("invalid temporary directory `{}`", dir.display()))?;
+        let trace = dir.join(format!("enarx-bench-{}.jsonl", std::process::id()));
+
+        let mut samples = Vec::with_capacity(backends.len() * self.iterations);
+        for backend in backends {
+            for iteration in 0..self.iterations {
+                let sample = self.sample(backend.name(), iteration, &trace)?;
+                if self.format == Format::Csv {
+                    if samples.is_empty() {
+                        println!("{}", Sample::CSV_HEADER);
+                    }
+                    // Rows are printed as they are measured, such that long benchmarks show progress.
+                    println!("{}", sample.csv());
+                }
+                samples.push(sample);
+            }
+        }
+
+        if self.format == Format::Json {
+            println!("{}", serde_json::to_string_pretty(&samples)?);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trace() {
+        let trace = r#"{"event":"event","fields":{},"level":"TRACE","span":8,"target":"wasi_common","time_us":4021771}
+{"duration_us":7,"fields":{},"id":2,"parent":1,"span":"run","target":"enarx_exec_wasmtime","start_us":4}
+{"duration_us":1,"fields":{},"id":1,"parent":null,"span":"attest","target":"enarx_exec_wasmtime","start_us":1}
+{"duration_us":2,"fields":{},"id":3,"parent":null,"span":"fetch","target":"enarx_exec_wasmtime","start_us":2}
+{"duration_us":3,"fields":{},"id":4,"parent":null,"span":"compile","target":"enarx_exec_wasmtime","start_us":4}
+{"duration_us":4,"fields":{},"id":5,"parent":null,"span":"connect","target":"enarx_exec_wasmtime","start_us":7}
+{"duration_us":5,"fields":{},"id":6,"parent":null,"span":"run","target":"enarx_exec_wasmtime","start_us":11}
+{"duration_us":6,"fields":{},"id":7,"parent":null,"span":"run","target":"wasmtime","start_us":12}"#;
+        assert_eq!(stages(trace).unwrap(), [1, 2, 3, 4, 5]);
+
+        let truncated: String = trace.lines().take(5).collect::<Vec<_>>().join("\n");
+        assert!(stages(&truncated).is_err());
+    }
+
+    #[test]
+    fn csv() {
+        let sample = Sample {
+            backend: "nil",
+            iteration: 1,
+            total_us: 20,
+            keep_us: 5,
+            attest_us: 1,
+            fetch_us: 2,
+            compile_us: 3,
+            connect_us: 4,
+            run_us: 5,
+        };
+        assert_eq!(
+            Sample::CSV_HEADER.split(',').count(),
+            sample.csv().split(',').count()
+        );
+        assert_eq!(sample.csv(), "nil,1,20,5,1,2,3,4,5");
+    }
+}
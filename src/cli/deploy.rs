@@ -1,19 +1,22 @@
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::cli::BackendOptions;
+use crate::cli::{BackendOptions, EventsOptions, HandoffOptions};
 use crate::drawbridge::parse_tag;
-use crate::exec::{open_package, run_package, EXECS};
+use crate::exec::{
+    cache, log_files, oci, open_package, read_config, run_package, sealed, RunOptions, EXECS,
+};
 
 use std::fmt::Debug;
 use std::fs;
 #[cfg(unix)]
 use std::os::unix::io::IntoRawFd;
+use std::path::PathBuf;
 
 use crate::backend::Signatures;
 use anyhow::{anyhow, bail, Context};
 use camino::Utf8PathBuf;
 use clap::Args;
-use enarx_exec_wasmtime::{Package, PACKAGE_CONFIG, PACKAGE_ENTRYPOINT};
+use enarx_exec_wasmtime::{Package, DEFAULT_MAX_WASM_SIZE, PACKAGE_CONFIG, PACKAGE_ENTRYPOINT};
 use url::Url;
 
 /// Deploy an Enarx package to an Enarx Keep.
@@ -30,6 +33,72 @@ pub struct Options {
     #[clap(long, value_name = "SIGNATURES")]
     pub signatures: Option<Utf8PathBuf>,
 
+    /// PEM-encoded ECDSA P-256 public key, which an `oci://` package must be signed with by cosign
+    #[clap(long, value_name = "FILE")]
+    pub cosign_key: Option<PathBuf>,
+
+    /// Address to serve the metrics of the keep on in the Prometheus text format, e.g. `127.0.0.1:9100`
+    #[clap(long, value_name = "ADDR")]
+    pub metrics_listen: Option<String>,
+
+    /// Address to serve the health and the identity of the keep on over HTTP, e.g. `127.0.0.1:8444`
+    ///
+    /// The status is served by the host from the events of the keep, so unlike `[status]` in
+    /// `Enarx.toml` it is available on every backend, but it is not authenticated by the keep.
+    #[clap(long, value_name = "ADDR")]
+    pub status_listen: Option<String>,
+
+    #[clap(flatten)]
+    pub events: EventsOptions,
+
+    /// File to write the trace of the keep to as JSON lines, e.g. to profile its startup
+    ///
+    /// The trace reveals what the workload does, so it is not written by default.
+    #[clap(long, value_name = "FILE")]
+    pub trace_out: Option<PathBuf>,
+
+    /// File to write the execution report of the keep to on exit, signed by the key of the keep
+    ///
+    /// The report carries the digests of the workload and its config, the exit code, the start
+    /// and stop times and the number of bytes received and sent on network streams.
+    #[clap(long, value_name = "FILE")]
+    pub report_out: Option<PathBuf>,
+
+    /// Hold the keep after attestation until it is released with `enarx release`
+    ///
+    /// The identity, measurement and certificate fingerprint of the keep are printed to stderr,
+    /// before its workload is compiled.
+    #[clap(long)]
+    pub hold: bool,
+
+    #[clap(flatten)]
+    pub handoff: HandoffOptions,
+
+    /// Maximum size of every WebAssembly module of the package in bytes, 100 MB by default
+    #[clap(long, value_name = "BYTES")]
+    pub max_wasm_size: Option<u64>,
+
+    /// Fetch the package without the cache in `$XDG_CACHE_HOME/enarx`
+    ///
+    /// By default, the files of a tagged package are cached by their digest and only fetched
+    /// again if they change.
+    #[clap(long)]
+    pub no_cache: bool,
+
+    /// Cache the compiled main module in `$XDG_CACHE_HOME/enarx`, sealed to the platform of the keep
+    ///
+    /// Later keeps running the same module on the same platform load it instead of compiling
+    /// the module.
+    #[clap(long)]
+    pub cache_compiled: bool,
+
+    /// Let the workload preopen the directories of the host configured by `[mount]` in `Enarx.toml`
+    ///
+    /// The mounted files are neither attested nor protected, so mounts are refused by default and
+    /// only supported on the `nil` backend.
+    #[clap(long)]
+    pub allow_mounts: bool,
+
     /// gdb options
     #[cfg(feature = "gdb")]
     #[clap(long, default_value = "localhost:23456")]
@@ -42,6 +111,18 @@ impl Options {
             backend,
             package,
             signatures,
+            cosign_key,
+            metrics_listen,
+            status_listen,
+            events,
+            trace_out,
+            report_out,
+            hold,
+            handoff,
+            max_wasm_size,
+            no_cache,
+            cache_compiled,
+            allow_mounts,
             #[cfg(feature = "gdb")]
             gdblisten,
         } = self;
@@ -76,7 +157,18 @@ impl Options {
                 })
         })?;
 
-        let code = match package.scheme() {
+        let cosign_key = cosign_key
+            .map(|path| {
+                fs::read_to_string(&path)
+                    .with_context(|| format!("failed to read cosign key `{}`", path.display()))
+                    .and_then(|pem| oci::Key::from_pem(&pem))
+            })
+            .transpose()?;
+        if cosign_key.is_some() && package.scheme() != "oci" {
+            bail!("`--cosign-key` requires an `oci://` package");
+        }
+
+        let local = match package.scheme() {
             "file" => {
                 let path = package
                     .to_file_path()
@@ -84,46 +176,131 @@ impl Options {
                 let md = fs::metadata(&path).with_context(|| {
                     format!("failed to get information about `{}`", path.display())
                 })?;
-                let (wasm, conf) = if md.is_file() {
-                    (path, None)
+                if md.is_file() {
+                    Some((path, None))
                 } else if md.is_dir() {
-                    (
+                    Some((
                         path.join(PACKAGE_ENTRYPOINT.as_str()),
                         Some(path.join(PACKAGE_CONFIG.as_str())),
-                    )
+                    ))
                 } else {
                     bail!(
                         "no Enarx package or WASM module found at `{}`",
                         path.display()
                     )
-                };
+                }
+            }
 
-                let get_pkg = || {
-                    let (wasm, conf) = open_package(wasm, conf)?;
+            // Tagged packages are fetched into the cache and passed to exec-wasmtime like local ones
+            "http" | "https" => match cache::dir().filter(|_| !no_cache) {
+                Some(dir) => cache::fetch(
+                    &dir,
+                    &package,
+                    max_wasm_size.unwrap_or(DEFAULT_MAX_WASM_SIZE),
+                )
+                .with_context(|| format!("failed to fetch `{package}` into the cache"))?,
+                None => None,
+            },
 
-                    #[cfg(unix)]
-                    let pkg = Package::Local {
-                        wasm: wasm.into_raw_fd(),
-                        conf: conf.map(|conf| conf.into_raw_fd()),
-                    };
+            // Artifacts are always pulled into the cache, since exec-wasmtime cannot fetch them.
+            "oci" => {
+                let dir = cache::dir().context(
+                    "`oci://` packages are pulled into the cache, which requires `XDG_CACHE_HOME` or `HOME` to be set",
+                )?;
+                let reference = oci::Reference::from_url(&package)?;
+                Some(
+                    oci::fetch(
+                        &dir,
+                        &reference,
+                        max_wasm_size.unwrap_or(DEFAULT_MAX_WASM_SIZE),
+                        cosign_key.as_ref(),
+                        !no_cache,
+                    )
+                    .with_context(|| format!("failed to pull `{package}`"))?,
+                )
+            }
 
-                    #[cfg(windows)]
-                    let pkg = Package::Local { wasm, conf };
+            s => bail!("unsupported scheme: {}", s),
+        };
 
-                    Ok(pkg)
-                };
+        let sealed = match local {
+            _ if !cache_compiled => None,
+            Some((ref wasm, _)) => Some(sealed::local(backend.name(), wasm)?),
+            None => Some(sealed::remote(backend.name(), &package)?),
+        };
 
-                run_package(backend, exec, signatures, gdblisten, get_pkg)?
-            }
+        // Only the host environment variables allowed by the config are passed to the keep, so a
+        // package fetched by exec-wasmtime, whose config the host does not see, gets none of them.
+        let config = match local {
+            Some((_, Some(ref conf))) => Some(read_config(conf)?),
+            _ => None,
+        };
+        let env_host = config
+            .as_ref()
+            .map(|config| config.env_host.clone())
+            .unwrap_or_default();
+        let log_files = config.as_ref().map(log_files).unwrap_or_default();
+        let reload = match local {
+            Some((_, ref conf)) => conf.clone(),
+            None => None,
+        };
 
-            // The WASM module and config will be downloaded from a remote by exec-wasmtime
-            // TODO: Disallow `http` or guard by an `--insecure` flag
-            "http" | "https" => run_package(backend, exec, signatures, gdblisten, || {
-                Ok(Package::Remote(package))
-            })?,
+        let get_pkg = || {
+            let (wasm, conf) = match local {
+                Some(local) => local,
+                // The WASM module and config will be downloaded from a remote by exec-wasmtime
+                // TODO: Disallow `http` or guard by an `--insecure` flag
+                None => return Ok(Package::Remote(package)),
+            };
+            let (wasm, conf, modules, data) = open_package(wasm, conf)?;
 
-            s => bail!("unsupported scheme: {}", s),
+            #[cfg(unix)]
+            let pkg = Package::Local {
+                wasm: wasm.into_raw_fd(),
+                conf: conf.map(|conf| conf.into_raw_fd()),
+                modules: modules
+                    .into_iter()
+                    .map(|(name, module)| (name, module.into_raw_fd()))
+                    .collect(),
+                data: data
+                    .into_iter()
+                    .map(|(name, file)| (name, file.into_raw_fd()))
+                    .collect(),
+            };
+
+            #[cfg(windows)]
+            let pkg = Package::Local {
+                wasm,
+                conf,
+                modules,
+                data,
+            };
+
+            Ok(pkg)
+        };
+
+        let audit_log = events.audit_log();
+        let handoff = handoff.handoff()?;
+        let options = RunOptions {
+            signatures,
+            gdblisten,
+            metrics_listen,
+            status_listen,
+            events: events.target(),
+            trace_out,
+            report_out,
+            audit_log,
+            hold,
+            handoff,
+            max_wasm_size,
+            sealed,
+            mounts: allow_mounts,
+            env_host,
+            reload,
+            log_files,
+            ..Default::default()
         };
+        let code = run_package(backend, exec, options, get_pkg)?;
 
         std::process::exit(code);
     }
@@ -0,0 +1,389 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use super::platform::info::system_info;
+use crate::backend::{Datum, BACKENDS};
+use crate::drawbridge::DEFAULT_HOST;
+
+use std::fmt::{self, Formatter};
+use std::io::{self, ErrorKind};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::ops::Deref;
+use std::time::Duration;
+
+use anyhow::bail;
+use camino::Utf8PathBuf;
+use clap::Args;
+use enarx_config::Config;
+
+/// Timeout of the network reachability checks
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Diagnose the machine and configuration for running Enarx Keeps
+///
+/// Prints a PASS, WARN or FAIL line for every check together with hints on
+/// how to resolve problems. Please include the output when asking for support.
+#[derive(Args, Debug)]
+pub struct Options {
+    /// Path of the Enarx.toml to check the Steward reachability of
+    #[clap(long, env = "ENARX_WASMCFGFILE")]
+    wasmcfgfile: Option<Utf8PathBuf>,
+
+    /// Drawbridge host to check the reachability of
+    #[clap(long, default_value = DEFAULT_HOST)]
+    drawbridge: String,
+
+    /// Skip the network reachability checks
+    #[clap(long)]
+    offline: bool,
+}
+
+impl Options {
+    pub fn execute(self) -> anyhow::Result<()> {
+        let is_atty = atty::is(atty::Stream::Stdout);
+
+        let checks = self.checks();
+        for check in &checks {
+            if is_atty {
+                println!("{:#}", check);
+            } else {
+                println!("{}", check);
+            }
+        }
+
+        let failed = checks.iter().filter(|c| c.status == Status::Fail).count();
+        if failed > 0 {
+            bail!("{} of {} checks failed", failed, checks.len());
+        }
+        Ok(())
+    }
+
+    fn checks(&self) -> Vec<Check> {
+        let mut checks = vec![
+            Check::pass("Enarx version", env!("CARGO_PKG_VERSION")),
+            Check::pass("System", system_info()),
+        ];
+        checks.extend(backends());
+
+        #[cfg(unix)]
+        checks.push(memlock());
+
+        #[cfg(enarx_with_shim)]
+        checks.extend(vcek());
+
+        if self.offline {
+            return checks;
+        }
+
+        checks.push(reachable("Drawbridge", &self.drawbridge, 443));
+        if let Some(ref path) = self.wasmcfgfile {
+            checks.extend(stewards(path));
+        }
+        checks
+    }
+}
+
+/// The outcome of a check
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// A single diagnostic line
+#[derive(Debug)]
+struct Check {
+    status: Status,
+    name: String,
+    info: String,
+    hint: Option<String>,
+}
+
+impl Check {
+    fn new(status: Status, name: impl Into<String>, info: impl Into<String>) -> Self {
+        Self {
+            status,
+            name: name.into(),
+            info: info.into(),
+            hint: None,
+        }
+    }
+
+    fn pass(name: impl Into<String>, info: impl Into<String>) -> Self {
+        Self::new(Status::Pass, name, info)
+    }
+
+    fn warn(name: impl Into<String>, info: impl Into<String>) -> Self {
+        Self::new(Status::Warn, name, info)
+    }
+
+    fn fail(name: impl Into<String>, info: impl Into<String>) -> Self {
+        Self::new(Status::Fail, name, info)
+    }
+
+    fn hint(self, hint: impl Into<String>) -> Self {
+        Self {
+            hint: Some(hint.into()),
+            ..self
+        }
+    }
+}
+
+/// Formats the check, the alternate form colors the status.
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        use colorful::*;
+
+        let status = match self.status {
+            Status::Pass => "PASS",
+            Status::Warn => "WARN",
+            Status::Fail => "FAIL",
+        };
+        if f.alternate() {
+            let status = match self.status {
+                Status::Pass => status.green(),
+                Status::Warn => status.yellow(),
+                Status::Fail => status.red(),
+            };
+            write!(f, "{}", status)?;
+        } else {
+            write!(f, "{}", status)?;
+        }
+        write!(f, "  {}: {}", self.name, self.info)?;
+
+        if let Some(ref hint) = self.hint {
+            write!(f, "\n      hint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the remediation of the failed `datum` of `backend`, if there is one.
+fn remediation(backend: &str, datum: &Datum) -> Option<String> {
+    if let Some(ref mesg) = datum.mesg {
+        return Some(mesg.clone());
+    }
+    let hint = match (backend, datum.name.trim()) {
+        ("kvm" | "sev", "API Version") => {
+            "Grant the user access to /dev/kvm, e.g. by adding the user to the `kvm` group."
+        }
+        ("sev", "/dev/sev is readable by user" | "/dev/sev is writable by user") => {
+            "Grant the user read and write access to /dev/sev, e.g. with a udev rule."
+        }
+        ("sev", "SEV-SNP is enabled in host kernel") => {
+            "Boot a host kernel with SEV-SNP support and load the `kvm_amd` module with `sev_snp=1`."
+        }
+        ("sgx", "AESM Daemon Socket") => {
+            "Install and start the Intel SGX AESM daemon, e.g. with `systemctl start aesmd`, or install the Intel DCAP quote library `libsgx_dcap_ql.so.1` and use `--sgx-quote-provider dcap`."
+        }
+        _ => return None,
+    };
+    Some(hint.into())
+}
+
+/// Checks the availability and configuration of every backend.
+fn backends() -> Vec<Check> {
+    let mut checks = vec![];
+    let mut isolated = false;
+
+    for backend in BACKENDS.deref() {
+        let name = format!("Backend {}", backend.name());
+
+        // The datums following the `CPU` datum are the features of the CPU.
+        let data = backend.data();
+        let cpu = data
+            .iter()
+            .position(|d| d.name == "CPU")
+            .unwrap_or(data.len());
+        if data[cpu..].iter().any(|d| !d.pass) {
+            checks.push(Check::warn(
+                name,
+                "not supported by the CPU of this machine",
+            ));
+            continue;
+        }
+
+        let missing: Vec<_> = data.iter().filter(|d| !d.pass).collect();
+        if !missing.is_empty() {
+            let names: Vec<_> = missing.iter().map(|d| d.name.trim()).collect();
+            let hint = missing
+                .iter()
+                .find_map(|d| remediation(backend.name(), d))
+                .unwrap_or_else(|| "See `enarx platform info` for details.".into());
+            checks.push(
+                Check::warn(
+                    name,
+                    format!("not available (missing {})", names.join(", ")),
+                )
+                .hint(hint),
+            );
+            continue;
+        }
+
+        let misconfigured: Vec<_> = backend.config().into_iter().filter(|d| !d.pass).collect();
+        if misconfigured.is_empty() {
+            isolated |= backend.name() != "nil";
+            checks.push(Check::pass(name, "available"));
+            continue;
+        }
+        for datum in misconfigured {
+            let info = match datum.info {
+                Some(ref info) => format!("{} ({})", datum.name.trim(), info),
+                None => datum.name.trim().into(),
+            };
+            let hint = remediation(backend.name(), &datum)
+                .unwrap_or_else(|| "See `enarx platform info` for details.".into());
+            checks.push(Check::fail(&name, info).hint(hint));
+        }
+    }
+
+    if !isolated {
+        checks.push(
+            Check::warn("Hardware isolation", "no hardware backend is usable")
+                .hint("Keeps can only run with the `nil` backend, which does not isolate the workload from the host."),
+        );
+    }
+    checks
+}
+
+/// Checks the MEMLOCK rlimit, which limits the memory kvm and sev keeps can lock in RAM.
+#[cfg(unix)]
+fn memlock() -> Check {
+    use std::mem::MaybeUninit;
+
+    let mut rlimit = MaybeUninit::uninit();
+    if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, rlimit.as_mut_ptr()) } != 0 {
+        let err = io::Error::last_os_error();
+        return Check::warn("MEMLOCK rlimit", format!("failed to query: {}", err));
+    }
+    let rlimit = unsafe { rlimit.assume_init() };
+    if rlimit.rlim_cur == libc::RLIM_INFINITY {
+        return Check::pass("MEMLOCK rlimit", "unlimited");
+    }
+
+    let info = format!("{} bytes", rlimit.rlim_cur);
+    let locks = BACKENDS
+        .deref()
+        .iter()
+        .any(|b| matches!(b.name(), "kvm" | "sev") && b.have());
+    if !locks {
+        return Check::pass("MEMLOCK rlimit", info);
+    }
+    Check::warn("MEMLOCK rlimit", info).hint(
+        "sev keeps and keeps run with `--memory-residency` lock their memory in RAM. \
+         Raise the limit, e.g. with `ulimit -l unlimited`, if they fail to start.",
+    )
+}
+
+/// Checks the VCEK certificate, which is required to attest sev keeps, fetching it if missing.
+#[cfg(enarx_with_shim)]
+fn vcek() -> Option<Check> {
+    use crate::backend::sev::snp::vcek::get_vcek_reader;
+
+    let sev = BACKENDS.deref().iter().find(|b| b.name() == "sev")?;
+    if !sev.have() {
+        return None;
+    }
+    Some(match get_vcek_reader() {
+        Ok(_) => Check::pass("SEV-SNP VCEK certificate", "available"),
+        Err(e) => Check::fail("SEV-SNP VCEK certificate", format!("{:#}", e)).hint(
+            "Download it with `enarx platform snp update` as root, or import it with \
+             `enarx platform snp vcek import` on hosts without access to the AMD KDS.",
+        ),
+    })
+}
+
+/// Checks whether a TCP connection to `port` of `host` can be established.
+fn reachable(name: &str, host: &str, port: u16) -> Check {
+    let addr = format!("{}:{}", host, port);
+    let connected = addr.to_socket_addrs().and_then(|mut addrs| {
+        let addr = addrs
+            .next()
+            .ok_or_else(|| io::Error::from(ErrorKind::NotFound))?;
+        TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)
+    });
+    match connected {
+        Ok(_) => Check::pass(name, format!("{} is reachable", addr)),
+        Err(e) => Check::fail(name, format!("{} is unreachable: {}", addr, e))
+            .hint("Check the network connection, DNS resolution and firewall of the machine."),
+    }
+}
+
+/// Checks the reachability of the Stewards configured in the Enarx.toml at `path`.
+///
+/// An unreachable Steward only fails the check, if the keep can neither fail over to another
+/// Steward nor self-sign its certificate.
+fn stewards(path: &Utf8PathBuf) -> Vec<Check> {
+    let config = match std::fs::read_to_string(path) {
+        Ok(config) => config,
+        Err(e) => return vec![Check::fail(path.as_str(), format!("failed to read: {}", e))],
+    };
+    let config: Config = match toml::from_str(&config) {
+        Ok(config) => config,
+        Err(e) => {
+            return vec![
+                Check::fail(path.as_str(), format!("failed to parse: {}", e))
+                    .hint("Compare it to the template generated by `enarx config init`."),
+            ]
+        }
+    };
+    if config.steward.is_empty() {
+        return vec![Check::pass("Steward", "not configured")];
+    }
+    let mut checks: Vec<_> = config
+        .steward
+        .iter()
+        .map(|url| match (url.host_str(), url.port_or_known_default()) {
+            (Some(host), Some(port)) => reachable("Steward", host, port),
+            _ => Check::fail("Steward", format!("{} has no host or port", url)),
+        })
+        .collect();
+    let hint = if checks.iter().any(|c| c.status == Status::Pass) {
+        "The keep fails over to the next Steward."
+    } else if config.steward_required == Some(false) {
+        "The keep self-signs its certificate, since `steward_required` is `false`."
+    } else {
+        return checks;
+    };
+    for check in &mut checks {
+        if check.status == Status::Fail {
+            check.status = Status::Warn;
+            check.hint = Some(hint.into());
+        }
+    }
+    checks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_display() {
+        assert_eq!(
+            Check::pass("Enarx version", "0.6.2").to_string(),
+            "PASS  Enarx version: 0.6.2"
+        );
+        assert_eq!(
+            Check::fail("Backend sgx", "AESM Daemon Socket")
+                .hint("Start aesmd.")
+                .to_string(),
+            "FAIL  Backend sgx: AESM Daemon Socket\n      hint: Start aesmd."
+        );
+    }
+
+    #[test]
+    fn offline() {
+        let checks = Options {
+            wasmcfgfile: None,
+            drawbridge: DEFAULT_HOST.into(),
+            offline: true,
+        }
+        .checks();
+        assert_eq!(checks[0].name, "Enarx version");
+        for backend in BACKENDS.deref() {
+            let name = format!("Backend {}", backend.name());
+            assert!(checks.iter().any(|c| c.name == name), "missing {name}");
+        }
+    }
+}
@@ -0,0 +1,245 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Documented error codes of common failures
+//!
+//! Errors of known causes carry a [`Code`] as their outermost context, which is reported with a
+//! hint and the section of `docs/Errors.md` describing it. The errors of the backends and of the
+//! keep are classified by their type in [`attach`].
+
+use crate::backend::Mismatch;
+
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use enarx_exec_wasmtime::StewardRejected;
+use serde::Serialize;
+
+/// Base URL of the documentation of the error codes
+const DOCS: &str = "https://enarx.dev/docs/Errors";
+
+/// Code of a documented error
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Code {
+    /// The device of the keep backend is missing
+    DeviceMissing,
+
+    /// The memory of the keep could not be locked in RAM
+    Memlock,
+
+    /// The signature of the keep does not match its measurement
+    SignatureMismatch,
+
+    /// The Steward refused to issue a certificate for the keep
+    StewardRejected,
+}
+
+impl Code {
+    /// Returns the identifier of the code, e.g. `E0001`.
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::DeviceMissing => "E0001",
+            Self::Memlock => "E0002",
+            Self::SignatureMismatch => "E0003",
+            Self::StewardRejected => "E0004",
+        }
+    }
+
+    fn summary(self) -> &'static str {
+        match self {
+            Self::DeviceMissing => "device missing",
+            Self::Memlock => "memlock",
+            Self::SignatureMismatch => "signature mismatch",
+            Self::StewardRejected => "steward rejection",
+        }
+    }
+
+    /// Returns how to resolve the error.
+    pub fn hint(self) -> &'static str {
+        match self {
+            Self::DeviceMissing => "check that the device of the backend, e.g. /dev/sgx_enclave, /dev/sev or /dev/kvm, exists and is accessible with `enarx platform info`",
+            Self::Memlock => "raise the MEMLOCK rlimit, e.g. with `ulimit -l unlimited`, or run with `--memory-residency preferred`",
+            Self::SignatureMismatch => "the signature file was created for another version of enarx, sign the keep again with `enarx sign`",
+            Self::StewardRejected => "check that the policy of the Steward accepts the measurement of the keep printed by `enarx measure`",
+        }
+    }
+
+    /// Returns the anchor of the section describing the error, e.g. `e0001-device-missing`.
+    pub fn slug(self) -> String {
+        format!("{}-{}", self.id(), self.summary())
+            .to_lowercase()
+            .replace(' ', "-")
+    }
+
+    /// Returns the URL of the section describing the error.
+    pub fn url(self) -> String {
+        format!("{DOCS}#{}", self.slug())
+    }
+
+    /// Returns the code of `err` classified by the type of the errors in its chain.
+    fn classify(err: &anyhow::Error) -> Option<Self> {
+        if err.downcast_ref::<Mismatch>().is_some() {
+            return Some(Self::SignatureMismatch);
+        }
+        if err.downcast_ref::<StewardRejected>().is_some() {
+            return Some(Self::StewardRejected);
+        }
+        #[cfg(enarx_with_shim)]
+        if err.chain().any(|e| {
+            e.downcast_ref::<std::io::Error>()
+                .and_then(std::io::Error::get_ref)
+                .and_then(|e| e.downcast_ref::<crate::backend::kvm::mem::Unlocked>())
+                .is_some()
+        }) {
+            return Some(Self::Memlock);
+        }
+        None
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.id(), self.summary())
+    }
+}
+
+/// Attaches the code of `err` as its outermost context, unless it has one already.
+pub fn attach(err: anyhow::Error) -> anyhow::Error {
+    if err.downcast_ref::<Code>().is_some() {
+        return err;
+    }
+    match Code::classify(&err) {
+        Some(code) => err.context(code),
+        None => err,
+    }
+}
+
+/// Format of the errors reported on exit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// The error and its causes followed by the hint of its code, if any
+    Text,
+
+    /// A JSON object of the error, its causes and its code, if any
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(anyhow!("unknown error format {:?}", s)),
+        }
+    }
+}
+
+/// An error as reported in the JSON format
+#[derive(Serialize, Debug, PartialEq, Eq)]
+struct Report {
+    /// The identifier of the code, if any
+    code: Option<&'static str>,
+    /// The messages of the error and its causes, outermost first
+    chain: Vec<String>,
+    /// How to resolve the error, if it has a code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hint: Option<&'static str>,
+    /// The documentation of the error, if it has a code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+}
+
+impl From<&anyhow::Error> for Report {
+    fn from(err: &anyhow::Error) -> Self {
+        let code = err.downcast_ref::<Code>().copied();
+        Self {
+            code: code.map(Code::id),
+            chain: err.chain().map(ToString::to_string).collect(),
+            hint: code.map(Code::hint),
+            url: code.map(Code::url),
+        }
+    }
+}
+
+impl Format {
+    /// Writes `err` to standard error.
+    pub fn report(self, err: &anyhow::Error) {
+        match self {
+            Self::Text => {
+                eprintln!("Error: {err:?}");
+                if let Some(&code) = err.downcast_ref::<Code>() {
+                    eprintln!("\nhint: {}\nsee {}", code.hint(), code.url());
+                }
+            }
+            Self::Json => match serde_json::to_string(&Report::from(err)) {
+                Ok(json) => eprintln!("{json}"),
+                Err(_) => eprintln!("Error: {err:?}"),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{attach, Code, Report};
+    use crate::backend::Mismatch;
+
+    use anyhow::anyhow;
+    use enarx_exec_wasmtime::StewardRejected;
+
+    #[test]
+    fn codes() {
+        assert_eq!(Code::DeviceMissing.to_string(), "E0001: device missing");
+        assert_eq!(
+            Code::StewardRejected.url(),
+            "https://enarx.dev/docs/Errors#e0004-steward-rejection"
+        );
+
+        let err = attach(anyhow::Error::new(Mismatch { backend: "sgx" }).context("failed"));
+        assert_eq!(err.downcast_ref::<Code>(), Some(&Code::SignatureMismatch));
+        assert_eq!(err.chain().count(), 3);
+
+        let rejected = StewardRejected {
+            status: 403,
+            reason: "measurement not allowed\n".into(),
+        };
+        let err = attach(anyhow::Error::new(rejected).context("failed to attest"));
+        assert_eq!(
+            serde_json::to_value(Report::from(&err)).unwrap(),
+            serde_json::json!({
+                "code": "E0004",
+                "chain": [
+                    "E0004: steward rejection",
+                    "failed to attest",
+                    "steward rejected the certificate signing request with status 403: measurement not allowed",
+                ],
+                "hint": Code::StewardRejected.hint(),
+                "url": Code::StewardRejected.url(),
+            })
+        );
+
+        // Errors of unknown causes and errors with a code are left alone.
+        let err = attach(anyhow!("failed"));
+        assert!(err.downcast_ref::<Code>().is_none());
+        let err = attach(anyhow!("failed").context(Code::DeviceMissing));
+        assert_eq!(err.chain().count(), 2);
+        assert_eq!(
+            serde_json::to_string(&Report::from(&anyhow!("failed"))).unwrap(),
+            r#"{"code":null,"chain":["failed"]}"#
+        );
+    }
+
+    #[cfg(enarx_with_shim)]
+    #[test]
+    fn memlock() {
+        use crate::backend::kvm::mem::Unlocked;
+        use std::io;
+
+        let unlocked = Unlocked(io::Error::from_raw_os_error(libc::ENOMEM));
+        let err = anyhow::Error::new(io::Error::new(io::ErrorKind::OutOfMemory, unlocked));
+        let err = attach(err.context("failed to create keep"));
+        assert_eq!(err.downcast_ref::<Code>(), Some(&Code::Memlock));
+    }
+}
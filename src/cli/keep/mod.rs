@@ -0,0 +1,19 @@
+// SPDX-License-Identifier: Apache-2.0
+
+mod serve;
+
+use clap::Subcommand;
+
+/// Commands for managing Enarx Keeps.
+#[derive(Subcommand, Debug)]
+pub enum Subcommands {
+    Serve(serve::Options),
+}
+
+impl Subcommands {
+    pub fn dispatch(self) -> anyhow::Result<()> {
+        match self {
+            Self::Serve(cmd) => cmd.execute(),
+        }
+    }
+}
@@ -0,0 +1,693 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::cli::BackendOptions;
+use crate::exec::hold;
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context};
+use camino::Utf8PathBuf;
+use clap::Args;
+use enarx_exec_wasmtime::Event;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+/// Serve a control socket for launching and managing Enarx Keeps.
+///
+/// Every keep is run by a separate `enarx run` or `enarx deploy` process.
+/// The socket accepts newline-delimited JSON requests, each of which is
+/// answered by a single line of JSON. The `request` field of a request is one
+/// of `run` (with `module` and optional `wasmcfgfile`), `deploy` (with
+/// `package`), `list`, `status` (with `id`), `release` (with `id`) or
+/// `terminate` (with `id`), e.g. `{"request":"status","id":0}`. Keeps
+/// launched with `"hold":true` wait after attestation until they are released.
+/// Terminated keeps are sent `SIGTERM` and killed if they have not exited
+/// after 10 seconds. The socket is only accessible to the owner of the
+/// process, and a stale socket left behind by a previous instance is replaced.
+/// The status of a keep includes the readiness and liveness last signalled by
+/// its workload via `kind = "ready"` and `kind = "healthy"` files.
+#[derive(Args, Debug)]
+pub struct Options {
+    #[clap(flatten)]
+    pub backend: BackendOptions,
+
+    /// Path of the signature file to use.
+    #[clap(long, value_name = "SIGNATURES")]
+    pub signatures: Option<Utf8PathBuf>,
+
+    /// Path of the Unix socket to listen on
+    #[clap(long, value_name = "SOCKET")]
+    pub socket: Utf8PathBuf,
+}
+
+impl Options {
+    pub fn execute(self) -> anyhow::Result<()> {
+        let backend = self.backend.pick()?;
+        let mut args = vec!["--backend".into(), backend.name().into()];
+        if let Some(signatures) = self.signatures {
+            args.extend(["--signatures".into(), signatures.into()]);
+        }
+
+        let exe = std::env::current_exe().context("failed to locate the enarx executable")?;
+        let server = Arc::new(Server::new(exe, args));
+
+        let listener = bind(self.socket.as_ref())
+            .with_context(|| format!("failed to bind to `{}`", self.socket))?;
+        info!("serving keeps on `{}`", self.socket);
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("failed to accept control connection: {e}");
+                    continue;
+                }
+            };
+            let server = server.clone();
+            thread::spawn(move || {
+                if let Err(e) = server.serve(stream) {
+                    warn!("control connection failed: {e:#}");
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Binds a control socket at `path` accessible only to the owner of the process, replacing a
+/// stale socket no one listens on anymore.
+fn bind(path: &Path) -> io::Result<UnixListener> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => match UnixStream::connect(path) {
+            Ok(_) => {
+                return Err(io::Error::new(
+                    ErrorKind::AddrInUse,
+                    "another server is listening on the socket",
+                ))
+            }
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused => {
+                warn!("removing stale socket `{}`", path.display());
+                fs::remove_file(path)?;
+            }
+            Err(e) => return Err(e),
+        },
+        _ => {}
+    }
+
+    // Create the socket without any access for the group and others, rather than restricting its
+    // permissions after it has been bound.
+    let umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(path);
+    unsafe { libc::umask(umask) };
+    listener
+}
+
+/// A request received on the control socket
+#[derive(Deserialize, Debug)]
+#[serde(tag = "request", rename_all = "snake_case", deny_unknown_fields)]
+enum Request {
+    /// Launch a keep running a local WebAssembly module
+    Run {
+        module: String,
+        wasmcfgfile: Option<String>,
+        #[serde(default)]
+        hold: bool,
+    },
+
+    /// Launch a keep running a published package
+    Deploy {
+        package: String,
+        #[serde(default)]
+        hold: bool,
+    },
+
+    /// List all keeps
+    List,
+
+    /// Query the status of a keep
+    Status { id: u64 },
+
+    /// Release a keep held after attestation
+    Release { id: u64 },
+
+    /// Terminate a keep and forget about it
+    Terminate { id: u64 },
+}
+
+/// A response sent on the control socket
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(tag = "response", rename_all = "snake_case")]
+enum Response {
+    Launched { id: u64 },
+    Keeps { keeps: Vec<KeepInfo> },
+    Keep(KeepInfo),
+    Error { message: String },
+}
+
+/// The status of a keep
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum Status {
+    Running,
+    /// The keep has exited with `code`, which is `None` if it was killed by a signal
+    Exited {
+        code: Option<i32>,
+    },
+}
+
+/// The description of a keep
+#[derive(Serialize, Debug, PartialEq, Eq)]
+struct KeepInfo {
+    id: u64,
+    pid: u32,
+    /// The command run by the keep process, i.e. `run` or `deploy`
+    command: &'static str,
+    /// The module or package run by the keep
+    workload: String,
+    /// The ports of the listen sockets of the keep, indexed by the name of the file in `Enarx.toml`
+    ports: BTreeMap<String, u16>,
+    /// The identity of the keep reported after attestation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    identity: Option<String>,
+    /// The readiness last signalled by the workload, `None` if it has not signalled any
+    ready: Option<bool>,
+    /// The liveness last signalled by the workload, `None` if it has not signalled any
+    healthy: Option<bool>,
+    #[serde(flatten)]
+    status: Status,
+}
+
+/// The state of a keep reported in its events
+#[derive(Default)]
+struct Reported {
+    ports: BTreeMap<String, u16>,
+    identity: Option<String>,
+    ready: Option<bool>,
+    healthy: Option<bool>,
+}
+
+/// A keep process
+struct Keep {
+    command: &'static str,
+    workload: String,
+    child: Child,
+    reported: Arc<Mutex<Reported>>,
+}
+
+impl Keep {
+    fn info(&mut self, id: u64) -> io::Result<KeepInfo> {
+        let status = match self.child.try_wait()? {
+            None => Status::Running,
+            Some(status) => Status::Exited {
+                code: status.code(),
+            },
+        };
+        let reported = self.reported.lock().unwrap();
+        Ok(KeepInfo {
+            id,
+            pid: self.child.id(),
+            command: self.command,
+            workload: self.workload.clone(),
+            ports: reported.ports.clone(),
+            identity: reported.identity.clone(),
+            ready: reported.ready,
+            healthy: reported.healthy,
+            status,
+        })
+    }
+}
+
+#[derive(Default)]
+struct Keeps {
+    next: u64,
+    keeps: BTreeMap<u64, Keep>,
+}
+
+struct Server {
+    /// The executable run for every keep
+    exe: PathBuf,
+    /// The arguments passed to every command
+    args: Vec<String>,
+    /// How long a keep is given to exit after `SIGTERM` before it is killed
+    grace: Duration,
+    keeps: Mutex<Keeps>,
+}
+
+impl Server {
+    fn new(exe: PathBuf, args: Vec<String>) -> Self {
+        Self {
+            exe,
+            args,
+            grace: Duration::from_secs(10),
+            keeps: Default::default(),
+        }
+    }
+
+    /// Answers all requests received on `stream`.
+    fn serve(&self, stream: UnixStream) -> anyhow::Result<()> {
+        let mut writer = stream.try_clone()?;
+        for line in BufReader::new(stream).lines() {
+            let line = line.context("failed to read request")?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = match serde_json::from_str(&line) {
+                Ok(request) => self.handle(request),
+                Err(e) => Response::Error {
+                    message: format!("invalid request: {e}"),
+                },
+            };
+            serde_json::to_writer(&mut writer, &response)?;
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    fn handle(&self, request: Request) -> Response {
+        self.try_handle(request)
+            .unwrap_or_else(|e| Response::Error {
+                message: format!("{e:#}"),
+            })
+    }
+
+    fn try_handle(&self, request: Request) -> anyhow::Result<Response> {
+        let mut keeps = self.keeps.lock().unwrap();
+        match request {
+            Request::Run {
+                module,
+                wasmcfgfile,
+                hold,
+            } => {
+                let mut args = vec![];
+                if hold {
+                    args.push("--hold".into());
+                }
+                if let Some(wasmcfgfile) = wasmcfgfile {
+                    args.extend(["--wasmcfgfile".into(), wasmcfgfile]);
+                }
+                args.push(module.clone());
+                self.launch(&mut keeps, "run", module, args)
+            }
+
+            Request::Deploy { package, hold } => {
+                let mut args = vec![];
+                if hold {
+                    args.push("--hold".into());
+                }
+                args.push(package.clone());
+                self.launch(&mut keeps, "deploy", package, args)
+            }
+
+            Request::List => {
+                let keeps = keeps
+                    .keeps
+                    .iter_mut()
+                    .map(|(id, keep)| keep.info(*id))
+                    .collect::<io::Result<_>>()?;
+                Ok(Response::Keeps { keeps })
+            }
+
+            Request::Status { id } => {
+                let keep = keeps
+                    .keeps
+                    .get_mut(&id)
+                    .ok_or_else(|| anyhow!("unknown keep {id}"))?;
+                Ok(Response::Keep(keep.info(id)?))
+            }
+
+            Request::Release { id } => {
+                let keep = keeps
+                    .keeps
+                    .get_mut(&id)
+                    .ok_or_else(|| anyhow!("unknown keep {id}"))?;
+                let identity = keep
+                    .reported
+                    .lock()
+                    .unwrap()
+                    .identity
+                    .clone()
+                    .ok_or_else(|| anyhow!("keep {id} has not attested yet"))?;
+                hold::release(&hold::dir(), &identity)
+                    .with_context(|| format!("failed to release keep {id}"))?;
+                info!("released keep {id}");
+                Ok(Response::Keep(keep.info(id)?))
+            }
+
+            Request::Terminate { id } => {
+                let keep = keeps
+                    .keeps
+                    .remove(&id)
+                    .ok_or_else(|| anyhow!("unknown keep {id}"))?;
+                // Do not block the other connections while the keep shuts down.
+                drop(keeps);
+                self.terminate(id, keep)
+            }
+        }
+    }
+
+    /// Sends `SIGTERM` to `keep` and kills it if it has not exited within the grace period.
+    fn terminate(&self, id: u64, mut keep: Keep) -> anyhow::Result<Response> {
+        // The keep has not been reaped yet, so its pid cannot have been reused.
+        if keep.child.try_wait()?.is_none()
+            && unsafe { libc::kill(keep.child.id() as _, libc::SIGTERM) } != 0
+        {
+            return Err(io::Error::last_os_error())
+                .with_context(|| format!("failed to terminate keep {id}"));
+        }
+
+        let deadline = Instant::now() + self.grace;
+        while keep.child.try_wait()?.is_none() {
+            if Instant::now() >= deadline {
+                warn!("keep {id} has not exited after SIGTERM, killing it");
+                match keep.child.kill() {
+                    // The keep has exited in the meantime.
+                    Err(e) if e.kind() == ErrorKind::InvalidInput => {}
+                    res => res.with_context(|| format!("failed to kill keep {id}"))?,
+                }
+                keep.child.wait()?;
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        info!("terminated keep {id}");
+        Ok(Response::Keep(keep.info(id)?))
+    }
+
+    fn launch(
+        &self,
+        keeps: &mut Keeps,
+        command: &'static str,
+        workload: String,
+        args: Vec<String>,
+    ) -> anyhow::Result<Response> {
+        let (events, writer) = events_pipe().context("failed to create events pipe")?;
+        let fd = writer.as_raw_fd();
+        let mut cmd = Command::new(&self.exe);
+        cmd.arg(command)
+            .args(&self.args)
+            .args(["--events-fd".into(), fd.to_string()])
+            .args(args)
+            .stdin(Stdio::null());
+        // Only the keep launched here inherits the write end, not the ones launched concurrently.
+        unsafe {
+            cmd.pre_exec(move || {
+                if libc::fcntl(fd, libc::F_SETFD, 0) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = cmd
+            .spawn()
+            .with_context(|| format!("failed to launch keep for `{workload}`"))?;
+        drop(writer);
+
+        let reported = Arc::new(Mutex::new(Reported::default()));
+        thread::spawn({
+            let reported = reported.clone();
+            move || record_events(events, reported)
+        });
+
+        let id = keeps.next;
+        keeps.next += 1;
+        info!(
+            "launched keep {id} for `{workload}` as process {}",
+            child.id()
+        );
+        keeps.keeps.insert(
+            id,
+            Keep {
+                command,
+                workload,
+                child,
+                reported,
+            },
+        );
+        Ok(Response::Launched { id })
+    }
+}
+
+/// Returns a pipe, both ends of which are closed on exec.
+fn events_pipe() -> io::Result<(File, File)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) })
+}
+
+/// Records the identity, the ports of the listen sockets and the readiness and liveness of the
+/// workload reported in the `events` of a keep until it exits.
+fn record_events(events: File, reported: Arc<Mutex<Reported>>) {
+    for line in BufReader::new(events).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => return warn!("failed to read keep events: {e}"),
+        };
+        match serde_json::from_str(&line) {
+            Ok(Event::ListeningOn { name, port, .. }) => {
+                reported.lock().unwrap().ports.insert(name, port);
+            }
+            Ok(Event::Attested { identity, .. }) => {
+                reported.lock().unwrap().identity = Some(identity);
+            }
+            Ok(Event::Ready { ready }) => {
+                reported.lock().unwrap().ready = Some(ready);
+            }
+            Ok(Event::Healthy { healthy }) => {
+                reported.lock().unwrap().healthy = Some(healthy);
+            }
+            Ok(_) => {}
+            Err(e) => warn!("failed to decode keep event: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::Duration;
+
+    /// Returns a server running `script` as the keep executable in `dir`.
+    fn server(dir: &tempfile::TempDir, script: &str) -> Server {
+        let exe = dir.path().join("enarx");
+        fs::write(&exe, format!("#!/bin/sh\n{script}\n")).unwrap();
+        fs::set_permissions(&exe, fs::Permissions::from_mode(0o755)).unwrap();
+        Server::new(exe, vec!["--backend".into(), "nil".into()])
+    }
+
+    fn launch(server: &Server) -> u64 {
+        let request = Request::Run {
+            module: "main.wasm".into(),
+            wasmcfgfile: None,
+            hold: false,
+        };
+        match server.handle(request) {
+            Response::Launched { id } => id,
+            res => panic!("unexpected response {res:?}"),
+        }
+    }
+
+    fn status(server: &Server, id: u64) -> Status {
+        match server.handle(Request::Status { id }) {
+            Response::Keep(info) => info.status,
+            res => panic!("unexpected response {res:?}"),
+        }
+    }
+
+    #[test]
+    fn exited() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = server(
+            &dir,
+            r#"[ "$*" = "run --backend nil --events-fd $5 main.wasm" ] || exit 1"#,
+        );
+
+        let id = launch(&server);
+        while status(&server, id) == Status::Running {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(status(&server, id), Status::Exited { code: Some(0) });
+
+        match server.handle(Request::List) {
+            Response::Keeps { keeps } => {
+                assert_eq!(keeps.len(), 1);
+                assert_eq!(keeps[0].command, "run");
+                assert_eq!(keeps[0].workload, "main.wasm");
+            }
+            res => panic!("unexpected response {res:?}"),
+        }
+    }
+
+    #[test]
+    fn ports() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = server(
+            &dir,
+            r#"echo '{"event":"listening-on","name":"web","addr":"::","port":40123}' >/proc/self/fd/$5"#,
+        );
+
+        let id = launch(&server);
+        let ports = loop {
+            match server.handle(Request::Status { id }) {
+                Response::Keep(info) if !info.ports.is_empty() => break info.ports,
+                Response::Keep(_) => thread::sleep(Duration::from_millis(10)),
+                res => panic!("unexpected response {res:?}"),
+            }
+        };
+        assert_eq!(ports, BTreeMap::from([("web".into(), 40123)]));
+    }
+
+    #[test]
+    fn health() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = server(
+            &dir,
+            r#"echo '{"event":"healthy","healthy":true}' >/proc/self/fd/$5
+echo '{"event":"ready","ready":true}' >/proc/self/fd/$5
+echo '{"event":"healthy","healthy":false}' >/proc/self/fd/$5
+exec sleep 60"#,
+        );
+
+        let id = launch(&server);
+        loop {
+            match server.handle(Request::Status { id }) {
+                Response::Keep(KeepInfo {
+                    ready: Some(true),
+                    healthy: Some(false),
+                    ..
+                }) => break,
+                Response::Keep(_) => thread::sleep(Duration::from_millis(10)),
+                res => panic!("unexpected response {res:?}"),
+            }
+        }
+        server.handle(Request::Terminate { id });
+    }
+
+    #[test]
+    fn held() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = server(
+            &dir,
+            r#"[ "$*" = "run --backend nil --events-fd $5 --hold main.wasm" ] || exit 1
+echo '{"event":"attested","identity":"08f5","certificate":"ab12"}' >/proc/self/fd/$5"#,
+        );
+
+        let request = Request::Run {
+            module: "main.wasm".into(),
+            wasmcfgfile: None,
+            hold: true,
+        };
+        let id = match server.handle(request) {
+            Response::Launched { id } => id,
+            res => panic!("unexpected response {res:?}"),
+        };
+        let identity = loop {
+            match server.handle(Request::Status { id }) {
+                Response::Keep(KeepInfo {
+                    identity: Some(identity),
+                    ..
+                }) => break identity,
+                Response::Keep(_) => thread::sleep(Duration::from_millis(10)),
+                res => panic!("unexpected response {res:?}"),
+            }
+        };
+        assert_eq!(identity, "08f5");
+    }
+
+    #[test]
+    fn terminate() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = server(&dir, "exec sleep 60");
+
+        let id = launch(&server);
+        assert_eq!(status(&server, id), Status::Running);
+        match server.handle(Request::Terminate { id }) {
+            Response::Keep(info) => assert_eq!(info.status, Status::Exited { code: None }),
+            res => panic!("unexpected response {res:?}"),
+        }
+        assert!(matches!(
+            server.handle(Request::Status { id }),
+            Response::Error { .. }
+        ));
+    }
+
+    #[test]
+    fn terminate_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut server = server(&dir, "trap '' TERM\nexec sleep 60");
+        server.grace = Duration::from_millis(100);
+
+        let id = launch(&server);
+        // Wait for the keep to ignore SIGTERM.
+        thread::sleep(Duration::from_millis(200));
+        match server.handle(Request::Terminate { id }) {
+            Response::Keep(info) => assert_eq!(info.status, Status::Exited { code: None }),
+            res => panic!("unexpected response {res:?}"),
+        }
+    }
+
+    #[test]
+    fn socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("enarx.sock");
+
+        let listener = bind(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(bind(&path).unwrap_err().kind(), ErrorKind::AddrInUse);
+
+        // The socket file is left behind once no one listens on it anymore.
+        drop(listener);
+        assert!(path.exists());
+        bind(&path).unwrap();
+    }
+
+    #[test]
+    fn protocol() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = server(&dir, "exit 0");
+
+        let (client, stream) = UnixStream::pair().unwrap();
+        thread::spawn(move || server.serve(stream).unwrap());
+
+        let mut reader = BufReader::new(client.try_clone().unwrap());
+        let mut request = |request: &str| {
+            writeln!(&client, "{request}").unwrap();
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            serde_json::from_str::<serde_json::Value>(&line).unwrap()
+        };
+
+        assert_eq!(
+            request(r#"{"request":"list"}"#),
+            serde_json::json!({"response": "keeps", "keeps": []})
+        );
+        assert_eq!(
+            request(r#"{"request":"deploy","package":"user/repo:0.1.0"}"#),
+            serde_json::json!({"response": "launched", "id": 0})
+        );
+        assert_eq!(
+            request(r#"{"request":"status","id":0}"#)["workload"],
+            "user/repo:0.1.0"
+        );
+        assert_eq!(
+            request(r#"{"request":"release","id":0}"#)["message"],
+            "keep 0 has not attested yet"
+        );
+        assert_eq!(request(r#"{"request":"stop"}"#)["response"], "error");
+    }
+}
@@ -0,0 +1,190 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::backend::sev::snp::launch::{IdAuth, IdBlock};
+use crate::backend::{Backend, ByteSized, Mismatch, Signatures, BACKENDS};
+use crate::exec::{hex, EXECS};
+
+use std::collections::BTreeMap;
+use std::ops::Deref;
+
+use anyhow::{anyhow, bail, Context, Result};
+use camino::Utf8PathBuf;
+use clap::Args;
+use ring::digest::{digest, SHA256, SHA384};
+use serde::Serialize;
+use sgx::signature::Body;
+
+/// Print the measurements of the compiled-in keep payload in JSON.
+///
+/// The measurements are computed without launching a keep, so relying parties
+/// can pre-compute the values to accept in the Steward policy on any machine.
+/// Given the signatures, the digests of the signing keys are printed as well,
+/// after checking that the signatures cover the measurements.
+#[derive(Args, Debug)]
+pub struct Options {
+    /// Binary to measure, which would be loaded and run inside the keep
+    #[clap(value_name = "BINARY")]
+    pub binpath: Option<Utf8PathBuf>,
+
+    /// Only measure the keep of the given backend ("sgx", "sev")
+    #[clap(long)]
+    pub backend: Option<String>,
+
+    /// Path of the signature file to use.
+    #[clap(long, value_name = "SIGNATURES")]
+    pub signatures: Option<Utf8PathBuf>,
+}
+
+/// The measurements of a keep
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+struct Measurement {
+    /// SGX `MRENCLAVE`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mrenclave: Option<String>,
+
+    /// SGX `MRSIGNER`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mrsigner: Option<String>,
+
+    /// SEV-SNP `MEASUREMENT`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    launch_digest: Option<String>,
+
+    /// SEV-SNP `ID_KEY_DIGEST`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id_key_digest: Option<String>,
+
+    /// SEV-SNP `AUTHOR_KEY_DIGEST`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author_key_digest: Option<String>,
+}
+
+fn measure_sgx(blob: &[u8], signatures: Option<&Signatures>) -> Result<Measurement> {
+    let body = Body::from_bytes(blob).ok_or_else(|| anyhow!("Invalid SGX measurement"))?;
+    let mut measurement = Measurement {
+        mrenclave: Some(hex(body.mrenclave())),
+        ..Default::default()
+    };
+
+    if let Some(sig) = signatures.filter(|s| !s.sgx.is_empty()) {
+        let sig = sgx::signature::Signature::from_bytes(&sig.sgx)
+            .ok_or_else(|| anyhow!("Invalid SGX signature"))?;
+        if sig.body().mrenclave() != body.mrenclave() {
+            bail!(Mismatch { backend: "sgx" });
+        }
+        // `MRSIGNER` is the SHA-256 digest of the little-endian modulus of the signing key.
+        let modulus = &sig.as_bytes()[128..512];
+        measurement.mrsigner = Some(hex(digest(&SHA256, modulus)));
+    }
+    Ok(measurement)
+}
+
+fn measure_sev(blob: &[u8], signatures: Option<&Signatures>) -> Result<Measurement> {
+    let id_block = IdBlock::from_bytes(blob).ok_or_else(|| anyhow!("Invalid SEV measurement"))?;
+    let mut measurement = Measurement {
+        launch_digest: Some(hex(id_block.launch_digest)),
+        ..Default::default()
+    };
+
+    if let Some(sig) = signatures.filter(|s| !s.sev.id_block.is_empty()) {
+        let signed = IdBlock::from_bytes(&sig.sev.id_block)
+            .ok_or_else(|| anyhow!("Invalid SEV ID block"))?;
+        if signed.launch_digest != id_block.launch_digest {
+            bail!(Mismatch { backend: "sev" });
+        }
+        let id_auth =
+            IdAuth::from_bytes(&sig.sev.id_auth).ok_or_else(|| anyhow!("Invalid SEV ID auth"))?;
+        measurement.id_key_digest = Some(hex(digest(&SHA384, id_auth.id_key.as_bytes())));
+        measurement.author_key_digest = Some(hex(digest(&SHA384, id_auth.author_key.as_bytes())));
+    }
+    Ok(measurement)
+}
+
+impl Options {
+    pub fn execute(self) -> anyhow::Result<()> {
+        use mmarinus::{perms, Map, Private};
+        let binary = if let Some(ref path) = self.binpath {
+            Some(Map::load(&path, Private, perms::Read)?)
+        } else {
+            None
+        };
+        let signatures = Signatures::load(self.signatures)?;
+
+        if let Some(ref name) = self.backend {
+            if !BACKENDS.iter().any(|b| b.name() == name) {
+                bail!("Keep backend identifier {:?} is unknown.", name);
+            }
+        }
+
+        let mut measurements = BTreeMap::new();
+
+        for backend in BACKENDS.deref().iter() {
+            let backend: &dyn Backend = backend.deref();
+
+            if matches!(self.backend, Some(ref name) if name != backend.name()) {
+                continue;
+            }
+
+            if backend.shim().is_empty() {
+                continue;
+            }
+
+            let exec = if let Some(ref e) = binary {
+                e.as_ref()
+            } else if let Some(e) = EXECS.iter().find(|w| w.with_backend(backend)) {
+                e.exec()
+            } else {
+                continue;
+            };
+
+            if exec.is_empty() {
+                continue;
+            }
+
+            let blob = backend
+                .hash(backend.shim().as_ref(), exec.as_ref())
+                .with_context(|| format!("Failed to measure the {} keep", backend.name()))?;
+
+            let measurement = match backend.name() {
+                "sgx" => measure_sgx(&blob, signatures.as_ref())?,
+                "sev" => measure_sev(&blob, signatures.as_ref())?,
+                _ => continue,
+            };
+            measurements.insert(backend.name(), measurement);
+        }
+
+        if let Some(ref name) = self.backend {
+            if !measurements.contains_key(name.as_str()) {
+                bail!("Keeps of the {:?} backend have no measurement", name);
+            }
+        }
+
+        println!("{}", serde_json::to_string_pretty(&measurements)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{hex, measure_sev, Measurement};
+
+    use crate::backend::sev::snp::launch::IdBlock;
+    use crate::backend::ByteSized;
+
+    #[test]
+    fn sev() {
+        let id_block = IdBlock {
+            launch_digest: [0xab; 48],
+            ..Default::default()
+        };
+        let measurement = measure_sev(id_block.as_bytes(), None).unwrap();
+        assert_eq!(
+            measurement,
+            Measurement {
+                launch_digest: Some(hex([0xab; 48])),
+                ..Default::default()
+            }
+        );
+        assert!(measure_sev(&[0; 3], None).is_err());
+    }
+}
@@ -1,24 +1,49 @@
 // SPDX-License-Identifier: Apache-2.0
 
+#[cfg(unix)]
+mod attest;
+#[cfg(unix)]
+mod audit;
+#[cfg(unix)]
+mod bench;
 mod config;
 mod deploy;
+mod doctor;
+pub mod error;
+#[cfg(unix)]
+mod keep;
+#[cfg(enarx_with_shim)]
+mod measure;
+#[cfg(unix)]
+mod node;
 mod package;
 mod platform;
+#[cfg(unix)]
+mod release;
 mod repo;
 mod run;
+mod rundev;
 #[cfg(enarx_with_shim)]
 mod sign;
+#[cfg(unix)]
+mod test;
 mod tree;
 mod unstable;
 mod user;
+#[cfg(enarx_with_shim)]
+mod verify;
 
-use crate::backend::{Backend, BACKENDS};
+use crate::backend::{probe, set_skipped, Backend, Probe, BACKENDS};
+use crate::exec::events::Target;
+use crate::exec::handoff::{Endpoint, Handoff};
 
 use std::ops::Deref;
+use std::path::PathBuf;
 use std::str::FromStr;
 
 use anyhow::{anyhow, bail};
 use clap::{Args, Parser, Subcommand};
+use enarx_exec_wasmtime::{Faults, Skipped};
 use log::info;
 
 /// Tool to deploy WebAssembly into Enarx Keeps
@@ -36,19 +61,31 @@ pub struct Options {
     #[clap(flatten)]
     logger: LogOptions,
 
+    /// Set the format of the error reported on failure ("text", "json")
+    ///
+    /// Errors of known causes carry a code, e.g. "E0001", with a hint and a link to their
+    /// documentation.
+    #[clap(long, env = "ENARX_ERROR_FORMAT", default_value = "text")]
+    error_format: error::Format,
+
     /// Subcommands (with their own options)
     #[clap(subcommand)]
     cmd: Subcommands,
 }
 
 impl Options {
+    /// Returns the format of the error reported on failure.
+    pub fn error_format(&self) -> error::Format {
+        self.error_format
+    }
+
     pub fn execute(self) -> anyhow::Result<()> {
         self.logger.init();
 
         info!("logging initialized!");
         info!("CLI opts: {:?}", self);
 
-        self.cmd.dispatch()
+        self.cmd.dispatch().map_err(error::attach)
     }
 }
 
@@ -56,22 +93,46 @@ impl Options {
 #[derive(Subcommand, Debug)]
 enum Subcommands {
     Run(run::Options),
+    #[cfg(unix)]
+    Attest(attest::Options),
+    #[cfg(unix)]
+    Bench(bench::Options),
+    Rundev(rundev::Options),
     Deploy(deploy::Options),
+    Doctor(doctor::Options),
     #[clap(subcommand)]
     Config(config::Subcommands),
+    #[cfg(unix)]
+    #[clap(subcommand)]
+    Keep(keep::Subcommands),
+    #[cfg(unix)]
+    #[clap(subcommand)]
+    Log(audit::Subcommands),
+    #[cfg(enarx_with_shim)]
+    Measure(measure::Options),
+    #[cfg(unix)]
+    #[clap(subcommand)]
+    Node(node::Subcommands),
     #[clap(subcommand)]
     Platform(platform::Subcommands),
     #[clap(subcommand)]
     Package(package::Subcommands),
+    #[cfg(unix)]
+    Release(release::Options),
     #[clap(subcommand)]
     Repo(repo::Subcommands),
     #[cfg(enarx_with_shim)]
     #[clap(hide = true)]
     Sign(sign::Options),
+    #[cfg(unix)]
+    #[clap(subcommand)]
+    Test(test::Subcommands),
     #[clap(subcommand, hide = true)]
     Tree(tree::Subcommands),
     #[clap(subcommand)]
     User(user::Subcommands),
+    #[cfg(enarx_with_shim)]
+    Verify(verify::Options),
     #[clap(subcommand, hide = true)]
     Unstable(unstable::Subcommands),
 }
@@ -80,55 +141,388 @@ impl Subcommands {
     fn dispatch(self) -> anyhow::Result<()> {
         match self {
             Self::Run(cmd) => cmd.execute(),
+            #[cfg(unix)]
+            Self::Attest(cmd) => cmd.execute(),
+            #[cfg(unix)]
+            Self::Bench(cmd) => cmd.execute(),
+            Self::Rundev(cmd) => cmd.execute(),
             Self::Config(subcmd) => subcmd.dispatch(),
             Self::Deploy(cmd) => cmd.execute(),
+            Self::Doctor(cmd) => cmd.execute(),
+            #[cfg(unix)]
+            Self::Keep(subcmd) => subcmd.dispatch(),
+            #[cfg(unix)]
+            Self::Log(subcmd) => subcmd.dispatch(),
+            #[cfg(enarx_with_shim)]
+            Self::Measure(cmd) => cmd.execute(),
+            #[cfg(unix)]
+            Self::Node(subcmd) => subcmd.dispatch(),
             Self::Platform(subcmd) => subcmd.dispatch(),
             Self::Package(subcmd) => subcmd.dispatch(),
+            #[cfg(unix)]
+            Self::Release(cmd) => cmd.execute(),
             Self::Repo(subcmd) => subcmd.dispatch(),
             #[cfg(enarx_with_shim)]
             Self::Sign(cmd) => cmd.execute(),
+            #[cfg(unix)]
+            Self::Test(subcmd) => subcmd.dispatch(),
             Self::Tree(subcmd) => subcmd.dispatch(),
             Self::User(subcmd) => subcmd.dispatch(),
+            #[cfg(enarx_with_shim)]
+            Self::Verify(cmd) => cmd.execute(),
             Self::Unstable(subcmd) => subcmd.dispatch(),
         }
     }
 }
 
+/// Backends picked by `--backend auto`, most preferred first
+const AUTO: [&str; 3] = ["sev", "sgx", "kvm"];
+
+/// Backends running keeps in a TEE
+const TEES: [&str; 2] = ["sev", "sgx"];
+
 /// Common backend and shim options
 #[derive(Args, Debug)]
 pub struct BackendOptions {
-    /// Set which backend to use
+    /// Set which backend to use, "auto" by default
+    ///
+    /// "auto" picks the first backend available on this host in the order sev, sgx, kvm.
     #[clap(long, env = "ENARX_BACKEND")]
     backend: Option<String>,
+
+    /// Refuse to run the keep outside of a TEE, i.e. on the kvm or nil backend
+    #[clap(long)]
+    require_tee: bool,
+
+    /// Limit the SGX Enclave Page Cache usable by the keep, which is moved into a cgroup of its own,
+    /// to the given number of bytes
+    #[cfg(enarx_with_shim)]
+    #[clap(long, env = "ENARX_SGX_EPC_LIMIT", value_name = "BYTES")]
+    sgx_epc_limit: Option<u64>,
+
+    /// Set how SGX keeps produce quotes ("auto", "aesm", "dcap"), "auto" by default
+    ///
+    /// "aesm" requests quotes from the AESM daemon, "dcap" produces them in-process with the
+    /// Intel DCAP quote library, and "auto" picks the AESM daemon, if its socket exists.
+    #[cfg(enarx_with_shim)]
+    #[clap(long, env = "ENARX_SGX_QUOTE_PROVIDER", value_name = "PROVIDER")]
+    sgx_quote_provider: Option<crate::backend::sgx::quote::Provider>,
+
+    /// Lock the memory of kvm and sev keeps in RAM ("preferred", "required")
+    ///
+    /// With "required", the keep refuses to run, if its memory cannot be locked.
+    #[cfg(enarx_with_shim)]
+    #[clap(long, env = "ENARX_MEMORY_RESIDENCY", value_name = "POLICY")]
+    memory_residency: Option<crate::backend::kvm::mem::Residency>,
+
+    /// Change the SEV-SNP guest policy of unsigned sev keeps, e.g. "smt=false,abi=1.51"
+    ///
+    /// A comma-separated list of "smt", "migrate_ma", "debug" and "single_socket" set to
+    /// "true" or "false", and "abi" set to the minimum firmware ABI version "MAJOR.MINOR".
+    #[cfg(enarx_with_shim)]
+    #[clap(long, env = "ENARX_SEV_POLICY", value_name = "POLICY")]
+    sev_policy: Option<crate::backend::sev::policy::Policy>,
+
+    #[cfg(enarx_with_shim)]
+    #[clap(flatten)]
+    kds: KdsOptions,
     // TODO: Path to an external shim binary?
     //shim: Option<PathBuf>,
 }
 
 impl BackendOptions {
     pub fn pick(&self) -> anyhow::Result<&dyn Backend> {
-        if let Some(ref name) = self.backend {
-            match BACKENDS.deref().iter().find(|b| b.name() == name) {
-                None => {
-                    bail!("Keep backend identifier {:?} is unknown.", name)
-                }
-                Some(backend) => {
-                    if !backend.have() {
-                        bail!("Keep backend {:?} is not available on this platform.", name)
-                    }
-                    if !backend.configured() {
-                        bail!("Keep backend {:?} is available on this platform, but the machine is misconfigured. Please check with `enarx platform info`.", name)
+        // The provider is checked while probing the sgx backend, so it is set beforehand.
+        #[cfg(enarx_with_shim)]
+        if let Some(provider) = self.sgx_quote_provider {
+            crate::backend::sgx::quote::set_provider(provider);
+        }
+
+        let backend = self.find()?;
+
+        #[cfg(enarx_with_shim)]
+        if self.sgx_quote_provider.is_some() && backend.name() != "sgx" {
+            bail!("`--sgx-quote-provider` requires the sgx backend");
+        }
+
+        #[cfg(enarx_with_shim)]
+        if let Some(bytes) = self.sgx_epc_limit {
+            if backend.name() != "sgx" {
+                bail!("`--sgx-epc-limit` requires the sgx backend");
+            }
+            crate::backend::sgx::epc::limit(bytes)?;
+        }
+
+        #[cfg(enarx_with_shim)]
+        if let Some(residency) = self.memory_residency {
+            if !matches!(backend.name(), "kvm" | "sev") {
+                bail!("`--memory-residency` requires the kvm or sev backend");
+            }
+            crate::backend::kvm::mem::set_residency(residency);
+        }
+
+        #[cfg(enarx_with_shim)]
+        if let Some(policy) = self.sev_policy {
+            if backend.name() != "sev" {
+                bail!("`--sev-policy` requires the sev backend");
+            }
+            crate::backend::sev::policy::set_policy(policy);
+        }
+
+        #[cfg(enarx_with_shim)]
+        self.kds.apply();
+
+        Ok(backend)
+    }
+
+    fn find(&self) -> anyhow::Result<&'static dyn Backend> {
+        let name = match self.backend.as_deref() {
+            None | Some("auto") => return self.auto(),
+            Some(name) => name,
+        };
+        let backend = match BACKENDS.deref().iter().find(|b| b.name() == name) {
+            None => bail!("Keep backend identifier {:?} is unknown.", name),
+            Some(backend) => &**backend,
+        };
+        match probe(&[backend])[0] {
+            Probe::Unavailable => {
+                return Err(
+                    anyhow!("Keep backend {:?} is not available on this platform.", name)
+                        .context(error::Code::DeviceMissing),
+                )
+            }
+            Probe::Misconfigured => {
+                bail!("Keep backend {:?} is available on this platform, but the machine is misconfigured. Please check with `enarx platform info`.", name)
+            }
+            Probe::Usable => {}
+        }
+        if self.require_tee && !TEES.contains(&name) {
+            bail!(
+                "Keep backend {:?} is not a TEE, but `--require-tee` is set.",
+                name
+            )
+        }
+        Ok(backend)
+    }
+
+    /// Returns the first backend in [`AUTO`] usable on this host.
+    ///
+    /// The skipped backends are logged and reported with the `backend-selected` event.
+    fn auto(&self) -> anyhow::Result<&'static dyn Backend> {
+        // The `nil` backend provides no isolation, so it is never picked instead of a
+        // hardware backend, unless selected explicitly.
+        let nil: &[&str] = if cfg!(enarx_with_shim) { &[] } else { &["nil"] };
+        let candidates: Vec<&'static dyn Backend> = AUTO
+            .iter()
+            .chain(nil)
+            .filter_map(|name| BACKENDS.deref().iter().find(|b| b.name() == *name))
+            .map(|b| &**b)
+            .collect();
+
+        // Backends, which are not TEEs, are not even probed with `--require-tee`.
+        let probed: Vec<_> = candidates
+            .iter()
+            .copied()
+            .filter(|b| !self.require_tee || TEES.contains(&b.name()))
+            .collect();
+        let mut probes = probe(&probed).into_iter();
+
+        let mut skipped = vec![];
+        for backend in candidates {
+            let reason = if self.require_tee && !TEES.contains(&backend.name()) {
+                "not-a-tee"
+            } else {
+                match probes.next() {
+                    Some(Probe::Usable) => {
+                        set_skipped(skipped);
+                        return Ok(backend);
                     }
-                    Ok(backend)
+                    Some(probe) => probe.reason(),
+                    None => unreachable!(),
                 }
-            }
+            };
+            info!("skipping the {} backend: {reason}", backend.name());
+            skipped.push(Skipped {
+                backend: backend.name().into(),
+                reason: reason.into(),
+            });
+        }
+
+        let reasons: Vec<_> = skipped
+            .iter()
+            .map(|s| format!("{}: {}", s.backend, s.reason))
+            .collect();
+        let err = if self.require_tee {
+            anyhow!(
+                "No usable TEE backend found ({}). Please check your machine with `$ enarx platform info`.",
+                reasons.join(", ")
+            )
         } else {
-            BACKENDS.deref().iter().find(|b| b.have()).ok_or_else(|| {
-                anyhow!(
-                    "No supported backend found. Please check your machine with `$ enarx platform info`."
-                )
-            })
+            anyhow!(
+                "No supported backend found ({}). Please check your machine with `$ enarx platform info`, or run without hardware isolation with `--backend nil`.",
+                reasons.join(", ")
+            )
+        };
+        // Misconfigured backends have a device, which is not usable.
+        if skipped
+            .iter()
+            .any(|s| s.reason == Probe::Misconfigured.reason())
+        {
+            return Err(err);
+        }
+        Err(err.context(error::Code::DeviceMissing))
+    }
+}
+
+/// Options of fetching the VCEK certificates of SEV-SNP platforms missing in the cache
+#[cfg(enarx_with_shim)]
+#[derive(Args, Debug)]
+pub struct KdsOptions {
+    /// URL of the AMD Key Distribution Service or of a mirror of it to fetch the VCEK from
+    #[clap(long, env = "ENARX_KDS_URL", value_name = "URL")]
+    kds_url: Option<String>,
+
+    /// URL of the HTTP proxy to reach the AMD Key Distribution Service through
+    #[clap(long, env = "ENARX_KDS_PROXY", value_name = "URL")]
+    kds_proxy: Option<String>,
+}
+
+#[cfg(enarx_with_shim)]
+impl KdsOptions {
+    /// Sets where the VCEK certificates are fetched from by this process.
+    pub fn apply(&self) {
+        crate::backend::sev::snp::vcek::set_kds(crate::backend::sev::snp::vcek::Kds {
+            url: self.kds_url.clone(),
+            proxy: self.kds_proxy.clone(),
+        });
+    }
+}
+
+/// Lifecycle event options
+#[derive(Args, Debug)]
+pub struct EventsOptions {
+    /// File descriptor to write lifecycle events of the keep to as JSON lines
+    #[clap(long, value_name = "FD", conflicts_with = "events-file")]
+    events_fd: Option<i32>,
+
+    /// File to append lifecycle events of the keep to as JSON lines
+    #[clap(long, value_name = "FILE")]
+    events_file: Option<PathBuf>,
+
+    /// Hash-chained audit log to record the launch of the keep in, see `enarx log`
+    #[clap(long, value_name = "FILE", env = "ENARX_AUDIT_LOG")]
+    audit_log: Option<PathBuf>,
+}
+
+impl EventsOptions {
+    /// Returns the audit log to record the launch of the keep in, if any.
+    pub fn audit_log(&self) -> Option<PathBuf> {
+        self.audit_log.clone()
+    }
+
+    /// Returns the destination of the lifecycle events, if any.
+    pub fn target(self) -> Option<Target> {
+        match (self.events_fd, self.events_file) {
+            (Some(fd), _) => Some(Target::Fd(fd)),
+            (None, Some(path)) => Some(Target::File(path)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Out-of-band certificate issuance options
+#[derive(Args, Debug)]
+pub struct HandoffOptions {
+    /// File to write the certificate signing request of the keep to as PEM, instead of sending
+    /// it to the Steward
+    ///
+    /// The keep waits for the certificate chain issued for it at `--crt-in` or on `--crt-fd`.
+    #[clap(long, value_name = "FILE", conflicts_with = "csr-fd")]
+    csr_out: Option<PathBuf>,
+
+    /// File descriptor to write the certificate signing request of the keep to as PEM
+    #[clap(long, value_name = "FD")]
+    csr_fd: Option<i32>,
+
+    /// File to read the certificate chain of the keep from, once it exists, PEM-encoded leaf
+    /// first or as a DER-encoded `PkiPath`
+    #[clap(long, value_name = "FILE", conflicts_with = "crt-fd")]
+    crt_in: Option<PathBuf>,
+
+    /// File descriptor to read the certificate chain of the keep from until EOF
+    #[clap(long, value_name = "FD")]
+    crt_fd: Option<i32>,
+}
+
+impl HandoffOptions {
+    /// Returns where the certificate signing request of the keep is handed off to, if anywhere.
+    pub fn handoff(self) -> anyhow::Result<Option<Handoff>> {
+        let csr = match (self.csr_fd, self.csr_out) {
+            (Some(fd), _) => Some(Endpoint::Fd(fd)),
+            (None, Some(path)) => Some(Endpoint::File(path)),
+            (None, None) => None,
+        };
+        let crt = match (self.crt_fd, self.crt_in) {
+            (Some(fd), _) => Some(Endpoint::Fd(fd)),
+            (None, Some(path)) => Some(Endpoint::File(path)),
+            (None, None) => None,
+        };
+        match (csr, crt) {
+            (Some(csr), Some(crt)) => Ok(Some(Handoff { csr, crt })),
+            (None, None) => Ok(None),
+            (Some(_), None) => bail!("`--csr-out` and `--csr-fd` require `--crt-in` or `--crt-fd`"),
+            (None, Some(_)) => bail!("`--crt-in` and `--crt-fd` require `--csr-out` or `--csr-fd`"),
+        }
+    }
+}
+
+/// Fault injection options for testing the resilience of the workload
+#[derive(Args, Debug)]
+pub struct FaultOptions {
+    /// Probability in `[0, 1]` of a read or write on a network stream failing with `EAGAIN` or `ECONNRESET`
+    #[clap(long, value_name = "PROBABILITY")]
+    inject_error_rate: Option<f64>,
+
+    /// Maximum latency in milliseconds added to reads from network streams
+    #[clap(long, value_name = "MILLISECONDS")]
+    inject_read_latency: Option<u64>,
+
+    /// Offset in seconds of the system clock of the workload, may be negative
+    #[clap(long, value_name = "SECONDS", allow_hyphen_values = true)]
+    inject_clock_skew: Option<i64>,
+}
+
+impl FaultOptions {
+    /// Returns the faults to inject into the workload on `backend`, if any.
+    ///
+    /// Only development backends without hardware isolation support fault injection.
+    pub fn faults(self, backend: &dyn Backend) -> anyhow::Result<Option<Faults>> {
+        let faults = match self {
+            Self {
+                inject_error_rate: None,
+                inject_read_latency: None,
+                inject_clock_skew: None,
+            } => return Ok(None),
+            Self {
+                inject_error_rate,
+                inject_read_latency,
+                inject_clock_skew,
+            } => Faults {
+                error_rate: inject_error_rate.unwrap_or_default(),
+                read_latency: inject_read_latency.unwrap_or_default(),
+                clock_skew: inject_clock_skew.unwrap_or_default(),
+            },
+        };
+        if !(0.0..=1.0).contains(&faults.error_rate) {
+            bail!("`--inject-error-rate` must be in [0, 1]");
+        }
+        if !matches!(backend.name(), "nil" | "kvm") {
+            bail!(
+                "fault injection is not supported on the `{}` backend, use `nil` or `kvm`",
+                backend.name()
+            );
         }
-        .map(|b| &**b)
+        Ok(Some(faults))
     }
 }
 
@@ -0,0 +1,79 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::backend::{probe, Backend, Probe, BACKENDS};
+use crate::cli::TEES;
+
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::Args;
+
+/// Print the labels advertising the backends usable on this node.
+///
+/// The labels are printed as `name=value` lines in the format of the feature
+/// files of node-feature-discovery, e.g. `enarx.dev/backend.sgx=true`, such
+/// that keeps can be scheduled onto nodes with the required TEE.
+#[derive(Args, Debug)]
+pub struct Options {
+    /// Write the labels to this file instead of stdout, e.g. in
+    /// `/etc/kubernetes/node-feature-discovery/features.d`
+    #[clap(long, value_name = "FILE")]
+    output: Option<PathBuf>,
+}
+
+/// Returns the labels advertising the usability of `backends` according to `probes`.
+fn labels(backends: &[&dyn Backend], probes: &[Probe]) -> String {
+    let mut labels = String::new();
+    let mut tee = false;
+    for (backend, probe) in backends.iter().zip(probes) {
+        let usable = *probe == Probe::Usable;
+        tee |= usable && TEES.contains(&backend.name());
+        labels.push_str(&format!("enarx.dev/backend.{}={usable}\n", backend.name()));
+    }
+    labels.push_str(&format!("enarx.dev/tee={tee}\n"));
+    labels.push_str(&format!(
+        "enarx.dev/version={}\n",
+        env!("CARGO_PKG_VERSION")
+    ));
+    labels
+}
+
+impl Options {
+    pub fn execute(self) -> anyhow::Result<()> {
+        let backends: Vec<&'static dyn Backend> = BACKENDS.iter().map(|b| &**b).collect();
+        let labels = labels(&backends, &probe(&backends));
+        match self.output {
+            Some(path) => {
+                // Replace the file atomically, since it is watched by node-feature-discovery.
+                let tmp = path.with_extension("tmp");
+                fs::write(&tmp, labels)
+                    .and_then(|()| fs::rename(&tmp, &path))
+                    .with_context(|| format!("failed to write labels to `{}`", path.display()))
+            }
+            None => {
+                print!("{labels}");
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::backend::nil;
+
+    #[test]
+    fn labelling() {
+        let backend = nil::Backend::default();
+        assert_eq!(
+            labels(&[&backend], &[Probe::Usable]),
+            format!(
+                "enarx.dev/backend.nil=true\nenarx.dev/tee=false\nenarx.dev/version={}\n",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+    }
+}
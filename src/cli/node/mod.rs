@@ -0,0 +1,25 @@
+// SPDX-License-Identifier: Apache-2.0
+
+mod labels;
+mod runtime;
+mod stats;
+
+use clap::Subcommand;
+
+/// Commands for integrating Enarx with cluster nodes, e.g. of Kubernetes.
+#[derive(Subcommand, Debug)]
+pub enum Subcommands {
+    Labels(labels::Options),
+    Runtime(Box<runtime::Options>),
+    Stats(stats::Options),
+}
+
+impl Subcommands {
+    pub fn dispatch(self) -> anyhow::Result<()> {
+        match self {
+            Self::Labels(cmd) => cmd.execute(),
+            Self::Runtime(cmd) => cmd.execute(),
+            Self::Stats(cmd) => cmd.execute(),
+        }
+    }
+}
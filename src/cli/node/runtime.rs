@@ -0,0 +1,574 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::cli::BackendOptions;
+use crate::time::rfc3339;
+
+use std::ffi::CString;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command as Process;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, bail, Context};
+use clap::{Args, Subcommand};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// Default directory of the state of the containers
+pub const DEFAULT_ROOT: &str = "/run/enarx/runtime";
+
+/// Name of the FIFO, on which a created container waits to be started
+const EXEC_FIFO: &str = "exec.fifo";
+
+/// Name of the state file of a container
+const STATE: &str = "state.json";
+
+/// Interval, at which `start` checks whether the container is waiting to be started
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Run the packages in the root file systems of containers in keeps, like `runc`.
+///
+/// The runtime implements the subset of the command line of `runc` used by
+/// `containerd-shim-runc-v2`, such that a containerd runtime with its
+/// `BinaryName` set to a script executing `enarx node runtime "$@"` runs the
+/// `main.wasm` and `Enarx.toml` at the root of the image of a container with
+/// `enarx deploy`. The variables of the `env` of the process of the container
+/// allowed by `env_host` are passed to the keep and on to the workload, while
+/// its `args` are ignored.
+#[derive(Args, Debug)]
+pub struct Options {
+    #[clap(flatten)]
+    pub backend: BackendOptions,
+
+    /// Directory of the state of the containers
+    #[clap(long, value_name = "DIR", default_value = DEFAULT_ROOT)]
+    pub root: PathBuf,
+
+    /// File to append errors to as JSON lines, which containerd reports
+    #[clap(long, value_name = "FILE")]
+    pub log: Option<PathBuf>,
+
+    /// Format of the log, errors are always logged as JSON
+    #[clap(long, value_name = "FORMAT")]
+    pub log_format: Option<String>,
+
+    /// Ignored, since keeps are not placed in cgroups by the runtime
+    #[clap(long)]
+    pub systemd_cgroup: bool,
+
+    #[clap(subcommand)]
+    cmd: Command,
+}
+
+/// Commands of the runtime
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Create a container from a bundle, which runs its package once it is started
+    Create {
+        /// Directory of the bundle
+        #[clap(long, short, value_name = "DIR", default_value = ".")]
+        bundle: PathBuf,
+
+        /// File to write the process ID of the container to
+        #[clap(long, value_name = "FILE")]
+        pid_file: Option<PathBuf>,
+
+        /// Socket to send the pseudoterminal of the container to, which is not supported
+        #[clap(long, value_name = "SOCKET")]
+        console_socket: Option<PathBuf>,
+
+        /// Ignored, since the root file system is not pivoted to
+        #[clap(long)]
+        no_pivot: bool,
+
+        /// Ignored, since keeps do not use session keyrings
+        #[clap(long)]
+        no_new_keyring: bool,
+
+        /// ID of the container
+        id: String,
+    },
+
+    /// Start a created container
+    Start {
+        /// ID of the container
+        id: String,
+    },
+
+    /// Print the state of a container as JSON
+    State {
+        /// ID of the container
+        id: String,
+    },
+
+    /// Send a signal to the keep of a container
+    Kill {
+        /// Send the signal to all processes of the container, of which there is only one
+        #[clap(long, short)]
+        all: bool,
+
+        /// ID of the container
+        id: String,
+
+        /// Signal to send, e.g. `TERM`, `SIGKILL` or `9`
+        #[clap(default_value = "TERM")]
+        signal: String,
+    },
+
+    /// Delete a stopped container
+    Delete {
+        /// Kill the container, if it is not stopped
+        #[clap(long, short)]
+        force: bool,
+
+        /// ID of the container
+        id: String,
+    },
+
+    /// List the processes of a container
+    Ps {
+        /// Output format, `table` or `json`
+        #[clap(long, short, default_value = "table")]
+        format: String,
+
+        /// ID of the container
+        id: String,
+    },
+
+    /// Wait for the container to be started and execute its keep
+    #[clap(hide = true)]
+    Init {
+        /// ID of the container
+        id: String,
+    },
+}
+
+/// The parts of the runtime spec of a bundle used by the runtime
+#[derive(Deserialize, Debug)]
+struct Spec {
+    root: Option<Root>,
+    process: Option<ProcessSpec>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Root {
+    path: PathBuf,
+}
+
+#[derive(Deserialize, Debug)]
+struct ProcessSpec {
+    #[serde(default)]
+    terminal: bool,
+    #[serde(default)]
+    env: Vec<String>,
+}
+
+/// A container created by the runtime, stored in its state directory
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Container {
+    pub id: String,
+    /// ID of the process, which waits to be started and then executes the keep
+    pub pid: u32,
+    pub bundle: PathBuf,
+    pub rootfs: PathBuf,
+    /// Creation time in RFC 3339 format
+    pub created: String,
+    /// Arguments of `enarx` running the package
+    args: Vec<String>,
+    /// Environment of the process of the container as `NAME=value`
+    env: Vec<String>,
+}
+
+/// The status of a container as defined by the runtime spec
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Created,
+    Running,
+    Stopped,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Created => "created",
+            Self::Running => "running",
+            Self::Stopped => "stopped",
+        })
+    }
+}
+
+/// The state of a container as printed by `state`, which is the one of `runc`
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct State<'a> {
+    oci_version: &'static str,
+    id: &'a str,
+    pid: u32,
+    status: Status,
+    bundle: &'a Path,
+    rootfs: &'a Path,
+    created: &'a str,
+    owner: &'static str,
+}
+
+/// Returns whether the process `pid` is alive, i.e. exists and is not a zombie.
+fn alive(pid: u32) -> bool {
+    if pid == 0 {
+        return false;
+    }
+    match fs::read_to_string(format!("/proc/{pid}/stat")) {
+        // The state follows the command name, which may contain spaces and parentheses.
+        Ok(stat) => match stat.rfind(')').map(|i| stat[i + 1..].trim_start()) {
+            Some(rest) => !rest.starts_with('Z') && !rest.starts_with('X'),
+            None => true,
+        },
+        Err(e) if e.kind() == ErrorKind::NotFound => false,
+        Err(_) => unsafe { libc::kill(pid as _, 0) == 0 },
+    }
+}
+
+/// Returns the number of signal `name`, e.g. `TERM`, `SIGKILL` or `9`.
+fn signal(name: &str) -> anyhow::Result<libc::c_int> {
+    if let Ok(num) = name.parse() {
+        return Ok(num);
+    }
+    let name = name.to_ascii_uppercase();
+    Ok(match name.strip_prefix("SIG").unwrap_or(&name) {
+        "HUP" => libc::SIGHUP,
+        "INT" => libc::SIGINT,
+        "QUIT" => libc::SIGQUIT,
+        "KILL" => libc::SIGKILL,
+        "USR1" => libc::SIGUSR1,
+        "USR2" => libc::SIGUSR2,
+        "PIPE" => libc::SIGPIPE,
+        "ALRM" => libc::SIGALRM,
+        "TERM" => libc::SIGTERM,
+        "CONT" => libc::SIGCONT,
+        "STOP" => libc::SIGSTOP,
+        "WINCH" => libc::SIGWINCH,
+        _ => bail!("unknown signal `{name}`"),
+    })
+}
+
+/// Returns the containers in the state directory `root` with their status.
+pub fn containers(root: &Path) -> anyhow::Result<Vec<(Container, Status)>> {
+    let entries = match fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e).with_context(|| format!("failed to read `{}`", root.display())),
+    };
+    let mut containers = vec![];
+    for entry in entries {
+        let entry = entry.with_context(|| format!("failed to read `{}`", root.display()))?;
+        if entry.path().join(STATE).exists() {
+            let container = Container::load(root, &entry.file_name().to_string_lossy())?;
+            let status = container.status(root);
+            containers.push((container, status));
+        }
+    }
+    containers.sort_by(|(a, _), (b, _)| a.id.cmp(&b.id));
+    Ok(containers)
+}
+
+impl Container {
+    /// Returns the state directory of the container `id`.
+    fn dir(root: &Path, id: &str) -> anyhow::Result<PathBuf> {
+        if id.is_empty() || id == "." || id == ".." || id.contains('/') {
+            bail!("invalid container ID `{id}`");
+        }
+        Ok(root.join(id))
+    }
+
+    /// Loads the container `id` from its state directory.
+    fn load(root: &Path, id: &str) -> anyhow::Result<Self> {
+        let path = Self::dir(root, id)?.join(STATE);
+        let state = match fs::read(&path) {
+            Ok(state) => state,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                bail!("container `{id}` does not exist")
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read `{}`", path.display()))
+            }
+        };
+        serde_json::from_slice(&state)
+            .with_context(|| format!("failed to decode `{}`", path.display()))
+    }
+
+    /// Stores the container in its state directory.
+    fn store(&self, root: &Path) -> anyhow::Result<()> {
+        let path = Self::dir(root, &self.id)?.join(STATE);
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec(self)?)
+            .and_then(|()| fs::rename(&tmp, &path))
+            .with_context(|| format!("failed to write `{}`", path.display()))
+    }
+
+    /// Returns the status of the container.
+    pub fn status(&self, root: &Path) -> Status {
+        if !alive(self.pid) {
+            Status::Stopped
+        } else if root.join(&self.id).join(EXEC_FIFO).exists() {
+            Status::Created
+        } else {
+            Status::Running
+        }
+    }
+}
+
+impl Options {
+    pub fn execute(self) -> anyhow::Result<()> {
+        let log = self.log.clone();
+        let res = self.run();
+        if let (Err(e), Some(log)) = (&res, log) {
+            // containerd reports the message of the last error logged by the runtime.
+            let line = serde_json::json!({
+                "level": "error",
+                "msg": format!("{e:#}"),
+                "time": rfc3339(SystemTime::now()),
+            });
+            if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(log) {
+                let _ = writeln!(file, "{line}");
+            }
+        }
+        res
+    }
+
+    fn run(self) -> anyhow::Result<()> {
+        let root = self.root;
+        match self.cmd {
+            Command::Create {
+                bundle,
+                pid_file,
+                console_socket,
+                id,
+                ..
+            } => {
+                if console_socket.is_some() {
+                    bail!("pseudoterminals are not supported by keeps");
+                }
+                let bundle = bundle
+                    .canonicalize()
+                    .with_context(|| format!("failed to find bundle `{}`", bundle.display()))?;
+                let path = bundle.join("config.json");
+                let spec: Spec = serde_json::from_slice(
+                    &fs::read(&path)
+                        .with_context(|| format!("failed to read `{}`", path.display()))?,
+                )
+                .with_context(|| format!("failed to decode `{}`", path.display()))?;
+                let process = spec
+                    .process
+                    .ok_or_else(|| anyhow!("`{}` has no `process`", path.display()))?;
+                if process.terminal {
+                    bail!("pseudoterminals are not supported by keeps");
+                }
+                let rootfs = bundle.join(
+                    spec.root
+                        .ok_or_else(|| anyhow!("`{}` has no `root`", path.display()))?
+                        .path,
+                );
+                let package = Url::from_directory_path(&rootfs).map_err(|()| {
+                    anyhow!("invalid root file system path `{}`", rootfs.display())
+                })?;
+                let backend = self.backend.pick()?;
+
+                let dir = Container::dir(&root, &id)?;
+                fs::create_dir_all(&root)
+                    .with_context(|| format!("failed to create `{}`", root.display()))?;
+                match fs::create_dir(&dir) {
+                    Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                        bail!("container `{id}` already exists")
+                    }
+                    res => res.with_context(|| format!("failed to create `{}`", dir.display()))?,
+                }
+                let fifo = CString::new(dir.join(EXEC_FIFO).as_os_str().as_bytes())?;
+                if unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) } != 0 {
+                    return Err(std::io::Error::last_os_error())
+                        .context("failed to create exec FIFO");
+                }
+
+                let mut container = Container {
+                    id,
+                    pid: 0,
+                    bundle,
+                    rootfs,
+                    created: rfc3339(SystemTime::now()),
+                    args: vec![
+                        "deploy".into(),
+                        "--backend".into(),
+                        backend.name().into(),
+                        package.into(),
+                    ],
+                    env: process.env,
+                };
+                container.store(&root)?;
+
+                // The child outlives this process and is reaped by the shim, which is its
+                // subreaper.
+                let exe = std::env::current_exe()?;
+                let child = Process::new(exe)
+                    .args(["node", "runtime", "--root"])
+                    .arg(&root)
+                    .args(["init", &container.id])
+                    .spawn()
+                    .context("failed to spawn container process")?;
+                container.pid = child.id();
+                container.store(&root)?;
+
+                if let Some(pid_file) = pid_file {
+                    fs::write(&pid_file, container.pid.to_string())
+                        .with_context(|| format!("failed to write `{}`", pid_file.display()))?;
+                }
+                Ok(())
+            }
+
+            Command::Init { id } => {
+                let container = Container::load(&root, &id)?;
+                let fifo = Container::dir(&root, &id)?.join(EXEC_FIFO);
+                // Opening the FIFO for writing blocks, until `start` opens it for reading.
+                OpenOptions::new()
+                    .write(true)
+                    .open(&fifo)
+                    .and_then(|mut fifo| fifo.write_all(b"0"))
+                    .context("failed to wait for the container to be started")?;
+
+                let env = container.env.iter().filter_map(|var| var.split_once('='));
+                let err = Process::new(std::env::current_exe()?)
+                    .args(&container.args)
+                    .envs(env)
+                    .current_dir(&container.rootfs)
+                    .exec();
+                Err(err).context("failed to execute keep")
+            }
+
+            Command::Start { id } => {
+                let container = Container::load(&root, &id)?;
+                let path = Container::dir(&root, &id)?.join(EXEC_FIFO);
+                if container.status(&root) != Status::Created {
+                    bail!("container `{id}` is not created")
+                }
+                // The FIFO is opened without blocking, such that `start` fails instead of
+                // blocking forever, if the container process exits before it is started.
+                let mut fifo = OpenOptions::new()
+                    .read(true)
+                    .custom_flags(libc::O_NONBLOCK)
+                    .open(&path)
+                    .context("failed to open exec FIFO")?;
+                let mut buf = [0; 1];
+                loop {
+                    match fifo.read(&mut buf) {
+                        Ok(1) => break,
+                        Ok(_) => {}
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                        Err(e) => return Err(e).context("failed to read exec FIFO"),
+                    }
+                    if !alive(container.pid) {
+                        bail!("container `{id}` exited before it was started");
+                    }
+                    thread::sleep(POLL_INTERVAL);
+                }
+                fs::remove_file(&path).context("failed to remove exec FIFO")
+            }
+
+            Command::State { id } => {
+                let container = Container::load(&root, &id)?;
+                let state = State {
+                    oci_version: "1.0.2",
+                    id: &container.id,
+                    pid: container.pid,
+                    status: container.status(&root),
+                    bundle: &container.bundle,
+                    rootfs: &container.rootfs,
+                    created: &container.created,
+                    owner: "",
+                };
+                println!("{}", serde_json::to_string_pretty(&state)?);
+                Ok(())
+            }
+
+            Command::Kill {
+                id, signal: sig, ..
+            } => {
+                let container = Container::load(&root, &id)?;
+                let sig = signal(&sig)?;
+                if container.status(&root) == Status::Stopped {
+                    bail!("container not running");
+                }
+                if unsafe { libc::kill(container.pid as _, sig) } != 0 {
+                    return Err(std::io::Error::last_os_error())
+                        .with_context(|| format!("failed to signal container `{id}`"));
+                }
+                Ok(())
+            }
+
+            Command::Delete { force, id } => {
+                let container = match Container::load(&root, &id) {
+                    Ok(container) => container,
+                    // The state directory may remain, if `create` failed.
+                    Err(_) if force => Container {
+                        id: id.clone(),
+                        pid: 0,
+                        bundle: PathBuf::new(),
+                        rootfs: PathBuf::new(),
+                        created: String::new(),
+                        args: vec![],
+                        env: vec![],
+                    },
+                    Err(e) => return Err(e),
+                };
+                match container.status(&root) {
+                    Status::Stopped => {}
+                    _ if force => unsafe {
+                        libc::kill(container.pid as _, libc::SIGKILL);
+                    },
+                    status => bail!("cannot delete container `{id}` that is not stopped: {status}"),
+                }
+                let dir = Container::dir(&root, &id)?;
+                match fs::remove_dir_all(&dir) {
+                    Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+                    res => res.with_context(|| format!("failed to remove `{}`", dir.display())),
+                }
+            }
+
+            Command::Ps { format, id } => {
+                let container = Container::load(&root, &id)?;
+                let pids: Vec<u32> = match container.status(&root) {
+                    Status::Stopped => vec![],
+                    _ => vec![container.pid],
+                };
+                match format.as_str() {
+                    "json" => println!("{}", serde_json::to_string(&pids)?),
+                    "table" => {
+                        println!("PID");
+                        for pid in pids {
+                            println!("{pid}");
+                        }
+                    }
+                    _ => bail!("invalid format `{format}`"),
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signals() {
+        assert_eq!(signal("9").unwrap(), libc::SIGKILL);
+        assert_eq!(signal("TERM").unwrap(), libc::SIGTERM);
+        assert_eq!(signal("SIGKILL").unwrap(), libc::SIGKILL);
+        assert_eq!(signal("sigusr1").unwrap(), libc::SIGUSR1);
+        assert!(signal("SIGFOO").is_err());
+    }
+}
@@ -0,0 +1,77 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use super::runtime::{containers, Status, DEFAULT_ROOT};
+
+use std::fs;
+use std::path::PathBuf;
+
+use clap::Args;
+use serde::Serialize;
+
+/// Print the resource usage of the keeps of the containers created by `enarx node runtime`.
+///
+/// The usage is printed as JSON and comprises the resident memory and the CPU
+/// time of the host process of each keep. The memory of a keep encrypted by the
+/// TEE is not accounted to its resident memory.
+#[derive(Args, Debug)]
+pub struct Options {
+    /// Directory of the state of the containers
+    #[clap(long, value_name = "DIR", default_value = DEFAULT_ROOT)]
+    root: PathBuf,
+}
+
+/// The resource usage of the keep of a container
+#[derive(Serialize, Debug)]
+struct Usage {
+    id: String,
+    pid: u32,
+    status: Status,
+    /// Resident memory in bytes
+    memory: Option<u64>,
+    /// CPU time spent in user and kernel mode in seconds
+    cpu: Option<f64>,
+}
+
+/// Returns the resident memory of the process `pid` in bytes.
+fn memory(pid: u32) -> Option<u64> {
+    let status = fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+    let kib = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kib * 1024)
+}
+
+/// Returns the CPU time spent by the process `pid` in seconds.
+fn cpu(pid: u32) -> Option<f64> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // `utime` and `stime` are the 12th and 13th fields after the command name.
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    let ticks: u64 = fields.get(11)?.parse::<u64>().ok()? + fields.get(12)?.parse::<u64>().ok()?;
+    let hz = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    (hz > 0).then_some(ticks as f64 / hz as f64)
+}
+
+impl Options {
+    pub fn execute(self) -> anyhow::Result<()> {
+        let usage: Vec<Usage> = containers(&self.root)?
+            .into_iter()
+            .map(|(container, status)| {
+                let running = status != Status::Stopped;
+                Usage {
+                    memory: running.then(|| memory(container.pid)).flatten(),
+                    cpu: running.then(|| cpu(container.pid)).flatten(),
+                    id: container.id,
+                    pid: container.pid,
+                    status,
+                }
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&usage)?);
+        Ok(())
+    }
+}
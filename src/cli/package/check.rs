@@ -0,0 +1,204 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use super::publish::validate;
+use crate::exec::hex;
+
+use std::fs;
+
+use anyhow::{bail, ensure, Context};
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::Args;
+use drawbridge_client::types::{TagEntry, Tree};
+use enarx_config::Config;
+use enarx_exec_wasmtime::{
+    is_package_data, mounts_package_data, DEFAULT_MAX_WASM_SIZE, MAX_PACKAGE_DATA_SIZE,
+};
+use ring::digest::{digest, SHA256};
+use serde::Serialize;
+
+/// Maximum size of `Enarx.toml` in bytes accepted by the keep
+const MAX_CONF_SIZE: u64 = 1_000_000;
+
+/// Maximum size of the directory listing of a package in bytes accepted by the keep
+const MAX_DIR_SIZE: u64 = 1_000_000;
+
+/// Check that a local package can be published and deployed, and print its digests.
+///
+/// The package is checked against the limits the keep enforces on a fetched
+/// package, i.e. the sizes and media types of its files and the validity of
+/// its modules and `Enarx.toml`. The printed `digest` is the one printed by
+/// `enarx package digest`, while `workload` is the digest to pin the package
+/// to with `--digest`. A keep pinned to it uses `common_name` as the common
+/// name of its certificate.
+#[derive(Args, Debug)]
+pub struct Options {
+    /// Maximum size of every WebAssembly module of the package in bytes, 100 MB by default
+    #[clap(long, value_name = "BYTES", default_value_t = DEFAULT_MAX_WASM_SIZE)]
+    max_wasm_size: u64,
+
+    /// Path of the package directory or of a `main.wasm`
+    path: Utf8PathBuf,
+}
+
+/// The digests of a package
+#[derive(Serialize, Debug, PartialEq, Eq)]
+struct Digests {
+    /// Digests of the tag entry of the package
+    digest: serde_json::Value,
+    /// Digest of `main.wasm`
+    workload: String,
+    /// Digest of `Enarx.toml`
+    config: Option<String>,
+    /// Common name of the certificate of a keep pinned to `workload`
+    common_name: String,
+}
+
+/// Returns the hex-encoded SHA-256 digest of `bytes`.
+fn sha256(bytes: &[u8]) -> String {
+    hex(digest(&SHA256, bytes))
+}
+
+/// Reads the file at `path` of at most `limit` bytes.
+fn read(path: &Utf8Path, limit: u64) -> anyhow::Result<Vec<u8>> {
+    let size = fs::metadata(path)
+        .with_context(|| format!("failed to read `{path}`"))?
+        .len();
+    ensure!(
+        size <= limit,
+        "size of `{path}` of `{size}` exceeds the limit of `{limit}`"
+    );
+    fs::read(path).with_context(|| format!("failed to read `{path}`"))
+}
+
+/// Reads and validates the Wasm module at `path` of at most `limit` bytes.
+fn read_wasm(path: &Utf8Path, limit: u64) -> anyhow::Result<Vec<u8>> {
+    // Drawbridge derives the media type from the extension.
+    ensure!(
+        path.extension() == Some("wasm"),
+        "`{path}` does not have the `.wasm` extension of Wasm modules"
+    );
+    let wasm = read(path, limit)?;
+    wasmparser::validate(&wasm).with_context(|| format!("invalid Wasm module `{path}`"))?;
+    Ok(wasm)
+}
+
+/// Checks the package at `path` and returns its digests.
+fn check(path: &Utf8Path, limit: u64) -> anyhow::Result<Digests> {
+    validate(path)?;
+    let tree = Tree::from_path_sync(path)
+        .with_context(|| format!("failed to read package at `{path}`"))?;
+    let root = tree.root();
+
+    let (wasm, config) = if path.is_file() {
+        (read_wasm(path, limit)?, None)
+    } else {
+        ensure!(
+            root.meta.size <= MAX_DIR_SIZE,
+            "directory listing of `{}` bytes exceeds the limit of `{MAX_DIR_SIZE}`",
+            root.meta.size
+        );
+        let main = path.join("main.wasm");
+        if !main.is_file() {
+            bail!("`{path}` does not contain `main.wasm`");
+        }
+        let wasm = read_wasm(&main, limit)?;
+
+        let conf = path.join("Enarx.toml");
+        let config = if conf.is_file() {
+            let raw = read(&conf, MAX_CONF_SIZE)?;
+            let parsed: Config = std::str::from_utf8(&raw)
+                .context("`Enarx.toml` is not valid UTF-8")
+                .and_then(|s| toml::from_str(s).context("failed to parse `Enarx.toml`"))?;
+
+            for (service, svc) in &parsed.services {
+                let module = path.join(&svc.module);
+                if !module.is_file() {
+                    bail!(
+                        "`{path}` does not contain `{}` of service `{service}`",
+                        svc.module
+                    );
+                }
+                read_wasm(&module, limit)
+                    .with_context(|| format!("invalid module of service `{service}`"))?;
+            }
+
+            if mounts_package_data(&parsed) {
+                let mut size = 0;
+                for entry in fs::read_dir(path)? {
+                    let entry = entry?;
+                    if entry.file_type()?.is_file()
+                        && is_package_data(&parsed, &entry.file_name().to_string_lossy())
+                    {
+                        size += entry.metadata()?.len();
+                    }
+                }
+                ensure!(
+                    size <= MAX_PACKAGE_DATA_SIZE,
+                    "data files of the package of `{size}` bytes exceed the limit of `{MAX_PACKAGE_DATA_SIZE}`"
+                );
+            }
+            Some(raw)
+        } else {
+            None
+        };
+        (wasm, config)
+    };
+
+    let entry = serde_json::to_value(&TagEntry::Unsigned(root))?;
+    let common_name = sha256(&wasm);
+    Ok(Digests {
+        digest: entry["digest"].clone(),
+        workload: format!("sha256:{common_name}"),
+        config: config.map(|config| format!("sha256:{}", sha256(&config))),
+        common_name,
+    })
+}
+
+impl Options {
+    pub fn execute(self) -> anyhow::Result<()> {
+        let digests = check(&self.path, self.max_wasm_size)?;
+        println!("{}", serde_json::to_string_pretty(&digests)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checking() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(dir.path()).unwrap();
+        let wasm = wat::parse_str("(module)").unwrap();
+        fs::write(dir.join("main.wasm"), &wasm).unwrap();
+        fs::write(dir.join("Enarx.toml"), "[[files]]\nkind = \"stdout\"\n").unwrap();
+
+        let digests = check(dir, DEFAULT_MAX_WASM_SIZE).unwrap();
+        assert_eq!(
+            digests.digest["sha-256"],
+            "lS6HbuG35CEfK7qvqShmyze4RTs7HvCqy0/AUbITZAY="
+        );
+        assert_eq!(
+            digests.common_name,
+            "93a44bbb96c751218e4c00d479e4c14358122a389acca16205b1e4d0dc5f9476"
+        );
+        assert_eq!(digests.workload, format!("sha256:{}", digests.common_name));
+        assert!(digests.config.is_some());
+
+        assert!(check(dir, 4).is_err());
+
+        fs::write(dir.join("main.wasm"), b"\0asm").unwrap();
+        assert!(check(dir, DEFAULT_MAX_WASM_SIZE).is_err());
+
+        fs::write(dir.join("main.wasm"), &wasm).unwrap();
+        fs::write(
+            dir.join("Enarx.toml"),
+            "[services.db]\nmodule = \"db.wasm\"\n",
+        )
+        .unwrap();
+        assert!(check(dir, DEFAULT_MAX_WASM_SIZE).is_err());
+        fs::write(dir.join("db.wasm"), &wasm).unwrap();
+        check(dir, DEFAULT_MAX_WASM_SIZE).unwrap();
+    }
+}
@@ -0,0 +1,69 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use super::publish::validate;
+
+use anyhow::Context;
+use camino::Utf8PathBuf;
+use clap::Args;
+use drawbridge_client::types::{TagEntry, Tree};
+
+/// Compute the tag entry of a local package without publishing it.
+///
+/// The output matches the one of `enarx package info` for the package once
+/// it is published with `enarx package publish`. The digests only depend on
+/// the contents of the files of the package, so they are the same on every
+/// platform.
+#[derive(Args, Debug)]
+pub struct Options {
+    /// Path of the package directory or of a `main.wasm`
+    path: Utf8PathBuf,
+}
+
+impl Options {
+    pub fn execute(self) -> anyhow::Result<()> {
+        validate(&self.path)?;
+        let tree = Tree::from_path_sync(&self.path)
+            .with_context(|| format!("failed to read package at `{}`", self.path))?;
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&TagEntry::Unsigned(tree.root()))?
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use camino::Utf8Path;
+
+    /// Returns the SHA-256 digest of the tag entry of the package at `path`.
+    fn digest(path: &Utf8Path) -> String {
+        validate(path).unwrap();
+        let tree = Tree::from_path_sync(path).unwrap();
+        let entry = serde_json::to_value(&TagEntry::Unsigned(tree.root())).unwrap();
+        entry["digest"]["sha-256"].as_str().unwrap().into()
+    }
+
+    #[test]
+    fn digests() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(dir.path()).unwrap();
+        let wasm = wat::parse_str("(module)").unwrap();
+        fs::write(dir.join("main.wasm"), &wasm).unwrap();
+        fs::write(dir.join("Enarx.toml"), "[[files]]\nkind = \"stdout\"\n").unwrap();
+
+        // The digests are pinned, such that a change in the encoding on any platform fails.
+        assert_eq!(
+            digest(&dir.join("main.wasm")),
+            "k6RLu5bHUSGOTADUeeTBQ1gSKjiazKFiBbHk0NxflHY="
+        );
+        assert_eq!(digest(dir), "lS6HbuG35CEfK7qvqShmyze4RTs7HvCqy0/AUbITZAY=");
+
+        fs::create_dir(dir.join("assets")).unwrap();
+        assert!(validate(dir).is_err());
+    }
+}
@@ -0,0 +1,69 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+
+use anyhow::{bail, Context};
+use camino::Utf8PathBuf;
+use clap::Args;
+use enarx_config::CONFIG_TEMPLATE;
+
+/// A WebAssembly module exporting an empty `_start`, which is replaced by the
+/// module of the application
+const PLACEHOLDER: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+    0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type section: () -> ()
+    0x03, 0x02, 0x01, 0x00, // function section
+    0x07, 0x0a, 0x01, 0x06, b'_', b's', b't', b'a', b'r', b't', 0x00, 0x00, // export section
+    0x0a, 0x04, 0x01, 0x02, 0x00, 0x0b, // code section
+];
+
+/// Create a new package directory.
+///
+/// The directory contains an `Enarx.toml` generated from the template of
+/// `enarx config init` and a placeholder `main.wasm` exporting an empty
+/// `_start`, which is to be replaced by the module of the application. Check
+/// the package with `enarx package check` before publishing or deploying it.
+#[derive(Args, Debug)]
+pub struct Options {
+    /// Path of the package directory, which is created if it does not exist
+    #[clap(default_value = ".")]
+    path: Utf8PathBuf,
+}
+
+impl Options {
+    pub fn execute(self) -> anyhow::Result<()> {
+        fs::create_dir_all(&self.path)
+            .with_context(|| format!("failed to create `{}`", self.path))?;
+        for (name, contents) in [
+            ("Enarx.toml", CONFIG_TEMPLATE.as_bytes()),
+            ("main.wasm", PLACEHOLDER),
+        ] {
+            let path = self.path.join(name);
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => file
+                    .write_all(contents)
+                    .with_context(|| format!("failed to write `{path}`"))?,
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    bail!("`{path}` does already exist")
+                }
+                Err(e) => return Err(e).with_context(|| format!("failed to create `{path}`")),
+            }
+        }
+        println!("Created package in `{}`", self.path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholder() {
+        assert_eq!(
+            PLACEHOLDER,
+            wat::parse_str(r#"(module (func (export "_start")))"#).unwrap()
+        );
+    }
+}
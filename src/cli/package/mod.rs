@@ -1,7 +1,11 @@
 // SPDX-License-Identifier: Apache-2.0
 
+mod check;
+mod digest;
 mod fetch;
 mod info;
+mod init;
+mod optimize;
 mod publish;
 mod yank;
 
@@ -10,7 +14,11 @@ use clap::Subcommand;
 /// Commands for working with Enarx packages.
 #[derive(Subcommand, Debug)]
 pub enum Subcommands {
+    Check(check::Options),
+    Digest(digest::Options),
     Info(info::Options),
+    Init(init::Options),
+    Optimize(optimize::Options),
     #[clap(hide = true)]
     Fetch(fetch::Options),
     Publish(publish::Options),
@@ -21,7 +29,11 @@ pub enum Subcommands {
 impl Subcommands {
     pub fn dispatch(self) -> anyhow::Result<()> {
         match self {
+            Self::Check(cmd) => cmd.execute(),
+            Self::Digest(cmd) => cmd.execute(),
             Self::Info(cmd) => cmd.execute(),
+            Self::Init(cmd) => cmd.execute(),
+            Self::Optimize(cmd) => cmd.execute(),
             Self::Fetch(cmd) => cmd.execute(),
             Self::Publish(cmd) => cmd.execute(),
             Self::Yank(cmd) => cmd.execute(),
@@ -0,0 +1,410 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::exec::hex;
+
+use std::collections::HashMap;
+use std::fs;
+
+use anyhow::{bail, Context};
+use camino::Utf8PathBuf;
+use clap::Args;
+use ring::digest::{digest, SHA256};
+use wasm_encoder::{
+    ConstExpr, CustomSection, DataSection, Encode, ExportKind, ExportSection, GlobalSection,
+    GlobalType, MemorySection, MemoryType, RawSection, ValType,
+};
+use wasmparser::{
+    BinaryReader, ExportSectionReader, ExternalKind, GlobalSectionReader, ImportSectionReader,
+    MemorySectionReader, TypeRef,
+};
+use wasmtime::{Engine, Linker, Module, Store, Val};
+use wasmtime_wasi::{WasiCtx, WasiCtxBuilder};
+
+/// Name of the custom section recording the digest of the module a
+/// pre-initialized module was derived from.
+pub const SOURCE_SECTION: &str = "enarx.source";
+
+const CUSTOM_SECTION: u8 = 0;
+const IMPORT_SECTION: u8 = 2;
+const MEMORY_SECTION: u8 = 5;
+const GLOBAL_SECTION: u8 = 6;
+const EXPORT_SECTION: u8 = 7;
+const START_SECTION: u8 = 8;
+const DATA_SECTION: u8 = 11;
+
+/// Prefix of the exports added to snapshot the state of the module.
+const SNAPSHOT_EXPORT: &str = "__enarx_snapshot";
+
+/// Maximum length of a run of zero bytes, which is included in a data segment
+/// instead of starting a new one.
+const MAX_SEGMENT_GAP: usize = 8;
+
+/// Pre-initialize a WebAssembly module for a faster start.
+///
+/// The initialization function of the module is run and the resulting state of
+/// its memories and globals is snapshotted into a new module. The SHA-256
+/// digest of the original module is recorded in the `enarx.source` custom
+/// section of the new module.
+#[derive(Args, Debug)]
+pub struct Options {
+    /// Name of the exported initialization function
+    #[clap(long, default_value = "wizer.initialize")]
+    init_func: String,
+
+    /// Path to write the pre-initialized module to
+    #[clap(short, long, value_name = "MODULE")]
+    output: Utf8PathBuf,
+
+    /// Path of the WebAssembly module to pre-initialize
+    #[clap(value_name = "MODULE")]
+    module: Utf8PathBuf,
+}
+
+impl Options {
+    pub fn execute(self) -> anyhow::Result<()> {
+        let wasm = fs::read(&self.module)
+            .with_context(|| format!("failed to read module from `{}`", self.module))?;
+        let wasm = optimize(&wasm, &self.init_func)?;
+        fs::write(&self.output, wasm)
+            .with_context(|| format!("failed to write module to `{}`", self.output))
+    }
+}
+
+/// A raw section of a WebAssembly module.
+struct Section<'a> {
+    id: u8,
+    offset: usize,
+    data: &'a [u8],
+}
+
+/// Splits `wasm` into its sections.
+fn sections(wasm: &[u8]) -> anyhow::Result<Vec<Section<'_>>> {
+    let mut reader = BinaryReader::new(wasm);
+    if reader.read_bytes(8).ok() != Some(b"\0asm\x01\0\0\0") {
+        bail!("not a WebAssembly module");
+    }
+
+    let mut sections = vec![];
+    while !reader.eof() {
+        let id = reader.read_u8()?;
+        let size = reader.read_var_u32()?;
+        let offset = reader.original_position();
+        let data = reader.read_bytes(size as _)?;
+        sections.push(Section { id, offset, data });
+    }
+    Ok(sections)
+}
+
+/// Returns the position of a non-custom section with `id` in a module.
+fn rank(id: u8) -> u8 {
+    match id {
+        // Tag section
+        13 => 6,
+        6..=9 => id + 1,
+        // Data count section
+        12 => 11,
+        10 | 11 => id + 2,
+        id => id,
+    }
+}
+
+/// Returns the contents of an encoded section without its size.
+fn contents(section: &impl Encode) -> Vec<u8> {
+    let mut buf = vec![];
+    section.encode(&mut buf);
+    let mut reader = BinaryReader::new(&buf);
+    let size = reader.read_var_u32().unwrap() as usize;
+    buf[buf.len() - size..].to_vec()
+}
+
+/// Encodes `sections` as a module, replacing the contents of the sections in
+/// `replace` or inserting them in order, if missing. Sections replaced with
+/// `None` are dropped.
+fn encode(sections: &[Section<'_>], mut replace: HashMap<u8, Option<Vec<u8>>>) -> Vec<u8> {
+    let mut inserts: Vec<_> = replace
+        .iter()
+        .filter(|(id, _)| !sections.iter().any(|s| s.id == **id))
+        .filter_map(|(id, data)| Some((*id, data.clone()?)))
+        .collect();
+    inserts.sort_by_key(|(id, _)| rank(*id));
+    let mut inserts = inserts.into_iter().peekable();
+
+    let mut module = wasm_encoder::Module::new();
+    for section in sections {
+        if section.id != CUSTOM_SECTION {
+            while let Some((id, data)) = inserts.next_if(|(id, _)| rank(*id) < rank(section.id)) {
+                module.section(&RawSection { id, data: &data });
+            }
+        }
+        match replace.remove(&section.id) {
+            Some(Some(data)) => module.section(&RawSection {
+                id: section.id,
+                data: &data,
+            }),
+            Some(None) => continue,
+            None => module.section(&RawSection {
+                id: section.id,
+                data: section.data,
+            }),
+        };
+    }
+    for (id, data) in inserts {
+        module.section(&RawSection { id, data: &data });
+    }
+    module.finish()
+}
+
+/// Returns the non-zero data segments of `data` as offset and bytes.
+fn segments(data: &[u8]) -> Vec<(usize, &[u8])> {
+    let mut segments = vec![];
+    let mut pos = 0;
+    while let Some(start) = data[pos..].iter().position(|b| *b != 0).map(|n| pos + n) {
+        let mut end = start;
+        loop {
+            let zero = data[end..]
+                .iter()
+                .position(|b| *b == 0)
+                .map_or(data.len(), |n| end + n);
+            end = zero;
+            match data[zero..].iter().position(|b| *b != 0) {
+                Some(gap) if gap <= MAX_SEGMENT_GAP => end = zero + gap,
+                _ => break,
+            }
+        }
+        segments.push((start, &data[start..end]));
+        pos = end;
+    }
+    segments
+}
+
+/// Runs `init_func` of `wasm` and returns a module with the resulting state
+/// as its initial state.
+pub fn optimize(wasm: &[u8], init_func: &str) -> anyhow::Result<Vec<u8>> {
+    let sections = sections(wasm)?;
+
+    let mut memories = vec![];
+    let mut globals = vec![];
+    let mut exports = vec![];
+    for section in &sections {
+        match section.id {
+            IMPORT_SECTION => {
+                for import in ImportSectionReader::new(section.data, section.offset)? {
+                    let import = import?;
+                    if let TypeRef::Memory(..) | TypeRef::Global(..) = import.ty {
+                        bail!(
+                            "imported memory or global `{}::{}` cannot be pre-initialized",
+                            import.module,
+                            import.name
+                        );
+                    }
+                }
+            }
+            MEMORY_SECTION => {
+                for memory in MemorySectionReader::new(section.data, section.offset)? {
+                    memories.push(memory?);
+                }
+            }
+            GLOBAL_SECTION => {
+                for global in GlobalSectionReader::new(section.data, section.offset)? {
+                    globals.push(global?.ty);
+                }
+            }
+            EXPORT_SECTION => {
+                for export in ExportSectionReader::new(section.data, section.offset)? {
+                    let export = export?;
+                    let kind = match export.kind {
+                        ExternalKind::Func => ExportKind::Func,
+                        ExternalKind::Table => ExportKind::Table,
+                        ExternalKind::Memory => ExportKind::Memory,
+                        ExternalKind::Global => ExportKind::Global,
+                        ExternalKind::Tag => ExportKind::Tag,
+                    };
+                    exports.push((export.name, kind, export.index));
+                }
+            }
+            _ => {}
+        }
+    }
+    if !exports
+        .iter()
+        .any(|(name, kind, _)| *name == init_func && *kind == ExportKind::Func)
+    {
+        bail!("module does not export the initialization function `{init_func}`");
+    }
+
+    // Export all memories and globals in order to snapshot them.
+    let mut section = ExportSection::new();
+    for (name, kind, index) in &exports {
+        section.export(name, *kind, *index);
+    }
+    for index in 0..memories.len() as u32 {
+        section.export(
+            &format!("{SNAPSHOT_EXPORT}.memory{index}"),
+            ExportKind::Memory,
+            index,
+        );
+    }
+    for index in 0..globals.len() as u32 {
+        section.export(
+            &format!("{SNAPSHOT_EXPORT}.global{index}"),
+            ExportKind::Global,
+            index,
+        );
+    }
+    let instrumented = encode(
+        &sections,
+        HashMap::from([(EXPORT_SECTION, Some(contents(&section)))]),
+    );
+
+    // Run the initialization. Neither bulk memory operations nor reference
+    // types are enabled, since tables cannot be snapshotted.
+    let mut config = wasmtime::Config::new();
+    config.wasm_bulk_memory(false);
+    config.wasm_reference_types(false);
+    config.wasm_multi_memory(true);
+    let engine = Engine::new(&config)?;
+    let module = Module::new(&engine, &instrumented)?;
+    let mut linker = Linker::new(&engine);
+    wasmtime_wasi::add_to_linker(&mut linker, |s: &mut WasiCtx| s)?;
+    let mut store = Store::new(&engine, WasiCtxBuilder::new().inherit_stdio().build());
+    let instance = linker.instantiate(&mut store, &module)?;
+    instance
+        .get_typed_func::<(), (), _>(&mut store, init_func)?
+        .call(&mut store, ())
+        .with_context(|| format!("failed to run the initialization function `{init_func}`"))?;
+
+    // Snapshot the memories.
+    let mut memory_section = MemorySection::new();
+    let mut data_section = DataSection::new();
+    for (index, ty) in memories.iter().enumerate() {
+        let memory = instance
+            .get_memory(&mut store, &format!("{SNAPSHOT_EXPORT}.memory{index}"))
+            .context("failed to snapshot memory")?;
+        memory_section.memory(MemoryType {
+            minimum: memory.size(&store),
+            maximum: ty.maximum,
+            memory64: ty.memory64,
+            shared: ty.shared,
+        });
+        for (offset, data) in segments(memory.data(&store)) {
+            let offset = if ty.memory64 {
+                ConstExpr::i64_const(offset as _)
+            } else {
+                ConstExpr::i32_const(offset as u32 as _)
+            };
+            data_section.active(index as _, &offset, data.iter().copied());
+        }
+    }
+
+    // Snapshot the globals.
+    let mut global_section = GlobalSection::new();
+    for (index, ty) in globals.iter().enumerate() {
+        let value = instance
+            .get_global(&mut store, &format!("{SNAPSHOT_EXPORT}.global{index}"))
+            .context("failed to snapshot global")?
+            .get(&mut store);
+        let (val_type, init) = match value {
+            Val::I32(v) => (ValType::I32, ConstExpr::i32_const(v)),
+            Val::I64(v) => (ValType::I64, ConstExpr::i64_const(v)),
+            Val::F32(v) => (ValType::F32, ConstExpr::f32_const(f32::from_bits(v))),
+            Val::F64(v) => (ValType::F64, ConstExpr::f64_const(f64::from_bits(v))),
+            Val::V128(v) => (ValType::V128, ConstExpr::v128_const(v as _)),
+            _ => bail!(
+                "global {index} of type {:?} cannot be snapshotted",
+                value.ty()
+            ),
+        };
+        let global_type = GlobalType {
+            val_type,
+            mutable: ty.mutable,
+        };
+        global_section.global(global_type, &init);
+    }
+
+    // The initialization function has already run and so has the start function.
+    let mut export_section = ExportSection::new();
+    for (name, kind, index) in exports.iter().filter(|(name, ..)| *name != init_func) {
+        export_section.export(name, *kind, *index);
+    }
+
+    let mut wasm_out = encode(
+        &sections,
+        HashMap::from([
+            (MEMORY_SECTION, Some(contents(&memory_section))),
+            (GLOBAL_SECTION, Some(contents(&global_section))),
+            (EXPORT_SECTION, Some(contents(&export_section))),
+            (START_SECTION, None),
+            (DATA_SECTION, Some(contents(&data_section))),
+        ]),
+    );
+    let source = format!("sha-256:{}", hex(digest(&SHA256, wasm)));
+    wasm_out.push(CUSTOM_SECTION);
+    CustomSection {
+        name: SOURCE_SECTION,
+        data: source.as_bytes(),
+    }
+    .encode(&mut wasm_out);
+
+    Module::validate(&Engine::default(), &wasm_out)
+        .context("failed to validate the pre-initialized module")?;
+    Ok(wasm_out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODULE: &str = r#"(module
+        (memory (export "memory") 1)
+        (global $value (mut i32) (i32.const 0))
+        (func (export "wizer.initialize")
+            (global.set $value (i32.const 42))
+            (i32.store (i32.const 1024) (i32.const 0x01020304))
+            (drop (memory.grow (i32.const 1))))
+        (func (export "get") (result i32)
+            (i32.add (global.get $value) (i32.load (i32.const 1024)))))"#;
+
+    #[test]
+    fn segments() {
+        let data = [0, 0, 1, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0];
+        assert_eq!(
+            super::segments(&data),
+            [(2, &[1, 0, 0, 2][..]), (16, &[3][..])]
+        );
+    }
+
+    #[test]
+    fn optimize() {
+        let wasm = wat::parse_str(MODULE).unwrap();
+        let optimized = super::optimize(&wasm, "wizer.initialize").unwrap();
+
+        let engine = Engine::default();
+        let module = Module::new(&engine, &optimized).unwrap();
+        assert!(module.get_export("wizer.initialize").is_none());
+
+        let mut store = Store::new(&engine, ());
+        let instance = wasmtime::Instance::new(&mut store, &module, &[]).unwrap();
+        let get = instance
+            .get_typed_func::<(), i32, _>(&mut store, "get")
+            .unwrap();
+        assert_eq!(get.call(&mut store, ()).unwrap(), 42 + 0x01020304);
+        let memory = instance.get_memory(&mut store, "memory").unwrap();
+        assert_eq!(memory.size(&store), 2);
+
+        let source = format!("sha-256:{}", hex(digest(&SHA256, &wasm)));
+        let section = sections(&optimized).unwrap().pop().unwrap();
+        assert_eq!(section.id, CUSTOM_SECTION);
+        let mut reader = BinaryReader::new(section.data);
+        assert_eq!(reader.read_string().unwrap(), SOURCE_SECTION);
+        assert_eq!(
+            reader.read_bytes(reader.bytes_remaining()).unwrap(),
+            source.as_bytes()
+        );
+    }
+
+    #[test]
+    fn missing_init_func() {
+        let wasm = wat::parse_str(MODULE).unwrap();
+        assert!(super::optimize(&wasm, "init").is_err());
+    }
+}
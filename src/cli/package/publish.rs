@@ -3,11 +3,13 @@
 use crate::drawbridge::{client, TagSpec};
 
 use std::ffi::OsString;
-use std::fs::read_dir;
+use std::fs::{self, read_dir};
 
 use anyhow::{bail, Context};
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
 use clap::Args;
+use enarx_config::Config;
+use enarx_exec_wasmtime::mounts_package_data;
 use oauth2::url::Url;
 
 /// Publish a new package.
@@ -35,30 +37,66 @@ impl Options {
             &self.credential_helper,
         )?;
 
-        // TODO: this logic should live in Drawbridge, so that it can be reused for the server
-        if self.path.is_file() {
-            self.path
-                .file_name()
-                .filter(|&name| name == "main.wasm")
-                .with_context(|| format!("Invalid file name: {}", self.path))?;
-        } else {
-            for entry in read_dir(self.path.clone())? {
-                let path = entry?.path();
-                if path.is_file() {
-                    path.file_name()
-                        .filter(|&name| name == "main.wasm" || name == "Enarx.toml")
-                        .with_context(|| format!("Invalid file name: {}", path.display()))?;
-                } else {
-                    bail!("Publishing nested directories is not supported")
-                }
-            }
-        }
+        validate(&self.path)?;
 
         let tag = cl.tag(&self.spec.ctx);
-        let (_tag_created, _tree_created) = tag
+        let (tag_created, _tree_created) = tag
             .create_from_path_unsigned(self.path)
             .context("Failed to create tag and upload tree")?;
+        if tag_created {
+            println!("Published {} to {}", self.spec.ctx, self.spec.host);
+        } else {
+            println!(
+                "{} is already published to {}",
+                self.spec.ctx, self.spec.host
+            );
+        }
 
         Ok(())
     }
 }
+
+/// Checks that `path` is a package, which can be published, i.e. a `main.wasm` or a directory
+/// containing only the files of a package.
+///
+/// The files of a package are `main.wasm`, `Enarx.toml` and the modules of the
+/// services configured in it, as well as any other files, if the workload mounts the data files
+/// of its package.
+// TODO: this logic should live in Drawbridge, so that it can be reused for the server
+pub(super) fn validate(path: &Utf8Path) -> anyhow::Result<()> {
+    if path.is_file() {
+        path.file_name()
+            .filter(|&name| name == "main.wasm")
+            .with_context(|| format!("Invalid file name: {}", path))?;
+    } else {
+        let config = path.join("Enarx.toml");
+        let config: Option<Config> = if config.is_file() {
+            let config = fs::read_to_string(&config)
+                .with_context(|| format!("Failed to read {config}"))
+                .and_then(|s| toml::from_str(&s).with_context(|| format!("Invalid {config}")))?;
+            Some(config)
+        } else {
+            None
+        };
+        let allowed = |name: &str| {
+            name == "main.wasm"
+                || name == "Enarx.toml"
+                || config.as_ref().map_or(false, |config| {
+                    config.services.values().any(|s| s.module == name)
+                        || mounts_package_data(config)
+                })
+        };
+        for entry in read_dir(path)? {
+            let path = entry?.path();
+            if path.is_file() {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .filter(|&name| allowed(name))
+                    .with_context(|| format!("Invalid file name: {}", path.display()))?;
+            } else {
+                bail!("Publishing nested directories is not supported")
+            }
+        }
+    }
+    Ok(())
+}
@@ -22,46 +22,9 @@ impl Options {
     pub fn execute(self) -> anyhow::Result<()> {
         let backends = BACKENDS.deref();
 
-        #[cfg(windows)]
-        fn get_system_info() -> String {
-            // FIXME
-            "Windows".into()
-        }
-
-        #[cfg(unix)]
-        fn get_system_info() -> String {
-            use std::{ffi::CStr, io, mem::MaybeUninit, os::raw::c_char, str::Utf8Error};
-
-            fn utsname_to_string(mut utsname: utsname) -> anyhow::Result<String, Utf8Error> {
-                fn array_to_str<const N: usize>(
-                    array: &'_ mut [c_char; N],
-                ) -> anyhow::Result<&'_ str, Utf8Error> {
-                    array[N - 1] = 0;
-                    unsafe { CStr::from_ptr(array.as_ptr()) }.to_str()
-                }
-
-                Ok(format!(
-                    "{} {} {} {}",
-                    array_to_str(&mut utsname.sysname)?,
-                    array_to_str(&mut utsname.release)?,
-                    array_to_str(&mut utsname.version)?,
-                    array_to_str(&mut utsname.machine)?,
-                ))
-            }
-
-            let mut utsname = MaybeUninit::uninit();
-
-            if unsafe { uname(utsname.as_mut_ptr()) } != 0 {
-                format!("[{}]", io::Error::last_os_error())
-            } else {
-                utsname_to_string(unsafe { utsname.assume_init() })
-                    .unwrap_or_else(|e| format!("[utf8 error: {}]", e))
-            }
-        }
-
         let info = Info {
             version: env!("CARGO_PKG_VERSION"),
-            system_info: get_system_info(),
+            system_info: system_info(),
             backends,
         };
         if self.json {
@@ -74,6 +37,45 @@ impl Options {
     }
 }
 
+/// Returns the name, release, version and machine of the operating system
+#[cfg(windows)]
+pub fn system_info() -> String {
+    // FIXME
+    "Windows".into()
+}
+
+/// Returns the name, release, version and machine of the operating system
+#[cfg(unix)]
+pub fn system_info() -> String {
+    use std::{ffi::CStr, io, mem::MaybeUninit, os::raw::c_char, str::Utf8Error};
+
+    fn utsname_to_string(mut utsname: utsname) -> anyhow::Result<String, Utf8Error> {
+        fn array_to_str<const N: usize>(
+            array: &'_ mut [c_char; N],
+        ) -> anyhow::Result<&'_ str, Utf8Error> {
+            array[N - 1] = 0;
+            unsafe { CStr::from_ptr(array.as_ptr()) }.to_str()
+        }
+
+        Ok(format!(
+            "{} {} {} {}",
+            array_to_str(&mut utsname.sysname)?,
+            array_to_str(&mut utsname.release)?,
+            array_to_str(&mut utsname.version)?,
+            array_to_str(&mut utsname.machine)?,
+        ))
+    }
+
+    let mut utsname = MaybeUninit::uninit();
+
+    if unsafe { uname(utsname.as_mut_ptr()) } != 0 {
+        format!("[{}]", io::Error::last_os_error())
+    } else {
+        utsname_to_string(unsafe { utsname.assume_init() })
+            .unwrap_or_else(|e| format!("[utf8 error: {}]", e))
+    }
+}
+
 #[derive(Serialize)]
 struct Info<'a> {
     version: &'static str,
@@ -137,3 +139,21 @@ fn test_info() {
     Options { json: true }.execute().unwrap();
     Options { json: false }.execute().unwrap();
 }
+
+#[test]
+fn test_info_json() {
+    let info = Info {
+        version: env!("CARGO_PKG_VERSION"),
+        system_info: String::new(),
+        backends: BACKENDS.deref(),
+    };
+    let json = serde_json::to_value(&info).unwrap();
+    for backend in json["backends"].as_array().unwrap() {
+        for key in ["available", "configured", "attestation"] {
+            assert!(backend[key].is_boolean(), "missing `{key}` in {backend}");
+        }
+        for key in ["data", "config"] {
+            assert!(backend[key].is_array(), "missing `{key}` in {backend}");
+        }
+    }
+}
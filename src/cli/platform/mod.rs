@@ -1,6 +1,6 @@
 // SPDX-License-Identifier: Apache-2.0
 
-mod info;
+pub mod info;
 #[cfg(enarx_with_shim)]
 mod sgx;
 #[cfg(enarx_with_shim)]
@@ -1,16 +1,21 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::backend::sev::snp::vcek::vcek_write;
+use crate::cli::KdsOptions;
 
 use clap::Args;
 
 /// Download the current VCEK certificate for this platform
 /// to a cache file in the `/var/cache/amd-sev/` directory
 #[derive(Args, Debug)]
-pub struct Options {}
+pub struct Options {
+    #[clap(flatten)]
+    kds: KdsOptions,
+}
 
 impl Options {
     pub fn execute(self) -> anyhow::Result<()> {
+        self.kds.apply();
         // try to write to the system cache
         vcek_write()?;
         Ok(())
@@ -1,21 +1,71 @@
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::backend::sev::snp::vcek::{get_vcek_reader, get_vcek_reader_with_path, sev_cache_dir};
+use crate::backend::sev::snp::vcek::{
+    get_vcek_reader, get_vcek_reader_with_path, sev_cache_dir, vcek_import, vcek_url,
+};
+use crate::cli::KdsOptions;
 
+use std::fs;
 use std::io::{self, ErrorKind};
 
-use clap::Args;
+use anyhow::Context;
+use camino::Utf8PathBuf;
+use clap::{Args, Subcommand};
 
-/// Print the VCEK certificate for this platform to stdout in PEM format
+/// Print the VCEK certificate for this platform to stdout in DER format
+///
+/// A certificate missing in the `/var/cache/amd-sev/` directory is fetched from the AMD Key
+/// Distribution Service. On hosts without access to it, download the certificate from the URL
+/// printed with `--url` elsewhere and import it with `enarx platform snp vcek import`.
 #[derive(Args, Debug)]
 pub struct Options {
     /// Print the location of the VCEK certificate file
     #[clap(long)]
     file: bool,
+
+    /// Print the URL to download the VCEK certificate from
+    #[clap(long, conflicts_with = "file")]
+    url: bool,
+
+    #[clap(flatten)]
+    kds: KdsOptions,
+
+    #[clap(subcommand)]
+    cmd: Option<Subcommands>,
+}
+
+/// VCEK cache subcommands
+#[derive(Subcommand, Debug)]
+enum Subcommands {
+    Import(Import),
+}
+
+/// Import the VCEK certificate for this platform into the cache
+///
+/// The certificate, in DER or PEM format, must be issued for the chip ID and the TCB version of
+/// this platform.
+#[derive(Args, Debug)]
+struct Import {
+    /// Path of the VCEK certificate
+    file: Utf8PathBuf,
 }
 
 impl Options {
     pub fn execute(self) -> anyhow::Result<()> {
+        self.kds.apply();
+        if let Some(Subcommands::Import(Import { file })) = self.cmd {
+            let vcek =
+                fs::read(&file).with_context(|| format!("Failed to read VCEK file `{file}`"))?;
+            let path = vcek_import(&vcek)?;
+            println!("{:?}", path);
+            return Ok(());
+        }
+
+        if self.url {
+            println!("{}", vcek_url()?);
+            return Ok(());
+        }
+
         if self.file {
             match get_vcek_reader_with_path(sev_cache_dir()?) {
                 Ok((path, _)) => {
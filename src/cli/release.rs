@@ -0,0 +1,19 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::exec::hold;
+
+use clap::Args;
+
+/// Release a keep held with `--hold` after attestation, such that it runs its workload.
+#[derive(Args, Debug)]
+pub struct Options {
+    /// Identity of the keep as printed by `enarx run --hold` and reported by its `attested` event
+    #[clap(value_name = "IDENTITY")]
+    pub identity: String,
+}
+
+impl Options {
+    pub fn execute(self) -> anyhow::Result<()> {
+        hold::release(&hold::dir(), &self.identity)
+    }
+}
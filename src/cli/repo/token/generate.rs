@@ -1,5 +1,6 @@
 // SPDX-License-Identifier: Apache-2.0
 
+use anyhow::bail;
 use clap::Args;
 
 /// Generate a new access token for a repository.
@@ -8,6 +9,6 @@ pub struct Options {}
 
 impl Options {
     pub fn execute(self) -> anyhow::Result<()> {
-        unimplemented!()
+        bail!("repository access tokens are not supported by the package host, use `enarx user token` to obtain a token for non-interactive environments")
     }
 }
@@ -1,5 +1,6 @@
 // SPDX-License-Identifier: Apache-2.0
 
+use anyhow::bail;
 use clap::Args;
 
 /// Revoke a repository access token.
@@ -8,6 +9,6 @@ pub struct Options {}
 
 impl Options {
     pub fn execute(self) -> anyhow::Result<()> {
-        unimplemented!()
+        bail!("repository access tokens are not supported by the package host, tokens printed by `enarx user token` expire on their own")
     }
 }
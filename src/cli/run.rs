@@ -1,17 +1,21 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::backend::Signatures;
-use crate::cli::BackendOptions;
-use crate::exec::{open_package, run_package, EXECS};
+use crate::cli::{BackendOptions, EventsOptions, FaultOptions, HandoffOptions};
+use crate::exec::{
+    log_files, open_package, read_config, replicas, run_package, sealed, RunOptions, EXECS,
+};
 
 use std::fmt::Debug;
 #[cfg(unix)]
 use std::os::unix::io::IntoRawFd;
+use std::path::PathBuf;
 
-use anyhow::anyhow;
+use anyhow::{anyhow, bail};
 use camino::Utf8PathBuf;
 use clap::Args;
 use enarx_exec_wasmtime::Package;
+use url::Url;
 
 /// Run a WebAssembly module inside an Enarx Keep.
 #[derive(Args, Debug)]
@@ -22,14 +26,89 @@ pub struct Options {
     #[clap(long, env = "ENARX_WASMCFGFILE")]
     pub wasmcfgfile: Option<Utf8PathBuf>,
 
-    /// Path of the WebAssembly module to run
+    /// Path or HTTP(S) URL of the WebAssembly module to run
+    ///
+    /// A module at a URL is fetched by the keep, which verifies it against the `Content-Digest`
+    /// header of the response.
     #[clap(value_name = "MODULE")]
-    pub module: Utf8PathBuf,
+    pub module: String,
 
     /// Path of the signature file to use.
     #[clap(long, value_name = "SIGNATURES")]
     pub signatures: Option<Utf8PathBuf>,
 
+    /// Address to serve the metrics of the keep on in the Prometheus text format, e.g. `127.0.0.1:9100`
+    #[clap(long, value_name = "ADDR")]
+    pub metrics_listen: Option<String>,
+
+    /// Address to serve the health and the identity of the keep on over HTTP, e.g. `127.0.0.1:8444`
+    ///
+    /// The status is served by the host from the events of the keep, so unlike `[status]` in
+    /// `Enarx.toml` it is available on every backend, but it is not authenticated by the keep.
+    #[clap(long, value_name = "ADDR")]
+    pub status_listen: Option<String>,
+
+    #[clap(flatten)]
+    pub events: EventsOptions,
+
+    /// File to write the trace of the keep to as JSON lines, e.g. to profile its startup
+    ///
+    /// The trace reveals what the workload does, so it is not written by default.
+    #[clap(long, value_name = "FILE")]
+    pub trace_out: Option<PathBuf>,
+
+    /// File to write the execution report of the keep to on exit, signed by the key of the keep
+    ///
+    /// The report carries the digests of the workload and its config, the exit code, the start
+    /// and stop times and the number of bytes received and sent on network streams.
+    #[clap(long, value_name = "FILE")]
+    pub report_out: Option<PathBuf>,
+
+    /// Hold the keep after attestation until it is released with `enarx release`
+    ///
+    /// The identity, measurement and certificate fingerprint of the keep are printed to stderr,
+    /// before its workload is compiled.
+    #[clap(long)]
+    pub hold: bool,
+
+    #[clap(flatten)]
+    pub handoff: HandoffOptions,
+
+    /// Maximum size of every WebAssembly module of the package in bytes, 100 MB by default
+    #[clap(long, value_name = "BYTES")]
+    pub max_wasm_size: Option<u64>,
+
+    /// Digest to pin the WebAssembly module to, e.g. `sha256:<hex>`
+    ///
+    /// The keep verifies the module against it before compiling it, and uses it as the common
+    /// name of its certificate instead of the digest claimed by the server serving the module.
+    #[clap(long, value_name = "DIGEST")]
+    pub digest: Option<String>,
+
+    /// Number of identical keeps to run the module in
+    ///
+    /// The lines written by every keep are prefixed with its index, and the first keep to fail
+    /// terminates all others. The keeps do not read stdin.
+    #[clap(long, value_name = "N", default_value_t = 1)]
+    pub replicas: usize,
+
+    /// Cache the compiled module in `$XDG_CACHE_HOME/enarx`, sealed to the platform of the keep
+    ///
+    /// Later keeps running the same module on the same platform load it instead of compiling
+    /// the module.
+    #[clap(long)]
+    pub cache_compiled: bool,
+
+    /// Let the workload preopen the directories of the host configured by `[mount]` in `Enarx.toml`
+    ///
+    /// The mounted files are neither attested nor protected, so mounts are refused by default and
+    /// only supported on the `nil` backend.
+    #[clap(long)]
+    pub allow_mounts: bool,
+
+    #[clap(flatten)]
+    pub faults: FaultOptions,
+
     /// gdb options
     #[cfg(feature = "gdb")]
     #[clap(long, default_value = "localhost:23456")]
@@ -43,10 +122,44 @@ impl Options {
             wasmcfgfile,
             module,
             signatures,
+            metrics_listen,
+            status_listen,
+            events,
+            trace_out,
+            report_out,
+            hold,
+            handoff,
+            max_wasm_size,
+            digest,
+            replicas,
+            cache_compiled,
+            allow_mounts,
+            faults,
             #[cfg(feature = "gdb")]
             gdblisten,
         } = self;
+        let handoff = handoff.handoff()?;
+        if replicas != 1 {
+            replicas::check(replicas)?;
+            if metrics_listen.is_some() {
+                bail!("`--metrics-listen` cannot be combined with `--replicas`");
+            }
+            if status_listen.is_some() {
+                bail!("`--status-listen` cannot be combined with `--replicas`");
+            }
+            if trace_out.is_some() {
+                bail!("`--trace-out` cannot be combined with `--replicas`");
+            }
+            if report_out.is_some() {
+                bail!("`--report-out` cannot be combined with `--replicas`");
+            }
+            if handoff.is_some() {
+                bail!("`--csr-out` and `--csr-fd` cannot be combined with `--replicas`");
+            }
+            std::process::exit(replicas::run(replicas)?);
+        }
         let backend = backend.pick()?;
+        let faults = faults.faults(backend)?;
         let exec = EXECS
             .iter()
             .find(|w| w.with_backend(backend))
@@ -55,31 +168,84 @@ impl Options {
 
         let signatures = Signatures::load(signatures)?;
 
+        // The module and its digest are fetched by exec-wasmtime
+        let remote = match Url::parse(&module) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => Some(url),
+            _ => None,
+        };
+        if remote.is_some() && wasmcfgfile.is_some() {
+            bail!("`--wasmcfgfile` is not supported for a module at a URL, use `enarx deploy` to run a package");
+        }
+        let sealed = match remote {
+            _ if !cache_compiled => None,
+            Some(ref url) => Some(sealed::remote(backend.name(), url)?),
+            None => Some(sealed::local(backend.name(), &module)?),
+        };
+
+        // Only the host environment variables allowed by the config are passed to the keep.
+        let config = wasmcfgfile.as_ref().map(read_config).transpose()?;
+        let env_host = config
+            .as_ref()
+            .map(|config| config.env_host.clone())
+            .unwrap_or_default();
+        let log_files = config.as_ref().map(log_files).unwrap_or_default();
+        let reload = wasmcfgfile.as_ref().map(|path| path.clone().into());
+
         let get_pkg = || {
-            let (wasm, conf) = open_package(module, wasmcfgfile)?;
+            if let Some(url) = remote {
+                return Ok(Package::Remote(url));
+            }
+            let (wasm, conf, modules, data) = open_package(module, wasmcfgfile)?;
 
             #[cfg(unix)]
             let pkg = Package::Local {
                 wasm: wasm.into_raw_fd(),
                 conf: conf.map(|conf| conf.into_raw_fd()),
+                modules: modules
+                    .into_iter()
+                    .map(|(name, module)| (name, module.into_raw_fd()))
+                    .collect(),
+                data: data
+                    .into_iter()
+                    .map(|(name, file)| (name, file.into_raw_fd()))
+                    .collect(),
             };
 
             #[cfg(windows)]
-            let pkg = Package::Local { wasm, conf };
+            let pkg = Package::Local {
+                wasm,
+                conf,
+                modules,
+                data,
+            };
 
             Ok(pkg)
         };
 
-        let code = run_package(
-            backend,
-            exec,
+        let audit_log = events.audit_log();
+        let options = RunOptions {
             signatures,
-            #[cfg(not(feature = "gdb"))]
-            None,
             #[cfg(feature = "gdb")]
-            Some(gdblisten),
-            get_pkg,
-        )?;
+            gdblisten: Some(gdblisten),
+            metrics_listen,
+            status_listen,
+            events: events.target(),
+            trace_out,
+            report_out,
+            audit_log,
+            hold,
+            handoff,
+            faults,
+            max_wasm_size,
+            digest,
+            sealed,
+            mounts: allow_mounts,
+            env_host,
+            reload,
+            log_files,
+            ..Default::default()
+        };
+        let code = run_package(backend, exec, options, get_pkg)?;
         std::process::exit(code);
     }
 }
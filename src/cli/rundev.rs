@@ -0,0 +1,174 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::backend::Signatures;
+use crate::cli::{BackendOptions, FaultOptions};
+use crate::exec::{log_files, open_data, open_modules, run_package, RunOptions, EXECS};
+
+use std::fmt::Debug;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+#[cfg(unix)]
+use std::os::unix::io::IntoRawFd;
+#[cfg(windows)]
+use std::os::windows::fs::OpenOptionsExt;
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use camino::Utf8PathBuf;
+use clap::Args;
+use enarx_config::Config;
+use enarx_exec_wasmtime::Package;
+
+/// Host environment variables passed to the module by default
+const ENV_HOST: &[&str] = &["CARGO_*", "RUST_*"];
+
+/// Flag of `CreateFileW`, which removes the file once its last handle is closed
+#[cfg(windows)]
+const FILE_FLAG_DELETE_ON_CLOSE: u32 = 0x0400_0000;
+
+/// Run a WebAssembly module inside an Enarx Keep for development.
+///
+/// The arguments following the module are passed to it and the exit code of
+/// the module is the exit code of this command. This allows using it as a
+/// cargo runner, e.g. by adding the following to `.cargo/config.toml`:
+///
+/// [target.wasm32-wasi]
+/// runner = "enarx rundev"
+///
+/// `cargo run` and `cargo test` then execute the module inside a keep.
+#[derive(Args, Debug)]
+pub struct Options {
+    #[clap(flatten)]
+    pub backend: BackendOptions,
+
+    /// Path of the Enarx.toml to extend, by default a config with the standard streams is used
+    #[clap(long, env = "ENARX_WASMCFGFILE")]
+    pub wasmcfgfile: Option<Utf8PathBuf>,
+
+    /// Path of the signature file to use.
+    #[clap(long, value_name = "SIGNATURES")]
+    pub signatures: Option<Utf8PathBuf>,
+
+    /// Path of the WebAssembly module to run
+    #[clap(value_name = "MODULE")]
+    pub module: Utf8PathBuf,
+
+    #[clap(flatten)]
+    pub faults: FaultOptions,
+
+    /// Arguments passed to the module
+    #[clap(value_name = "ARGS", allow_hyphen_values = true)]
+    pub args: Vec<String>,
+
+    /// gdb options
+    #[cfg(feature = "gdb")]
+    #[clap(long, default_value = "localhost:23456")]
+    pub gdblisten: String,
+}
+
+impl Options {
+    pub fn execute(self) -> anyhow::Result<()> {
+        let Self {
+            backend,
+            wasmcfgfile,
+            signatures,
+            module,
+            faults,
+            args,
+            #[cfg(feature = "gdb")]
+            gdblisten,
+        } = self;
+        let backend = backend.pick()?;
+        let faults = faults.faults(backend)?;
+        let exec = EXECS
+            .iter()
+            .find(|w| w.with_backend(backend))
+            .ok_or_else(|| anyhow!("no supported exec found"))
+            .map(|b| b.exec())?;
+
+        let signatures = Signatures::load(signatures)?;
+
+        let mut config: Config = match wasmcfgfile {
+            Some(ref path) => {
+                let config = fs::read_to_string(path)
+                    .with_context(|| format!("failed to read config at `{path}`"))?;
+                toml::from_str(&config)
+                    .with_context(|| format!("failed to parse config at `{path}`"))?
+            }
+            None => Config::default(),
+        };
+        config.args.extend(args);
+        config
+            .env_host
+            .extend(ENV_HOST.iter().map(|&pattern| pattern.into()));
+        let encoded = toml::to_vec(&config).context("failed to encode config")?;
+
+        let get_pkg = || {
+            let wasm = File::open(&module)
+                .with_context(|| format!("failed to open WASM module at `{module}`"))?;
+            let conf = anonymous("rundev.toml", &encoded).context("failed to write config")?;
+            // The modules of the services are looked up next to the config.
+            let dir = wasmcfgfile
+                .as_ref()
+                .and_then(|path| path.parent())
+                .map_or_else(|| Path::new(""), |dir| dir.as_std_path());
+            let modules = open_modules(&config, dir)?;
+            let data = open_data(&config, dir, &[module.as_std_path()])?;
+
+            #[cfg(unix)]
+            let pkg = Package::Local {
+                wasm: wasm.into_raw_fd(),
+                conf: Some(conf.into_raw_fd()),
+                modules: modules
+                    .into_iter()
+                    .map(|(name, module)| (name, module.into_raw_fd()))
+                    .collect(),
+                data: data
+                    .into_iter()
+                    .map(|(name, file)| (name, file.into_raw_fd()))
+                    .collect(),
+            };
+
+            #[cfg(windows)]
+            let pkg = Package::Local {
+                wasm,
+                conf: Some(conf),
+                modules,
+                data,
+            };
+
+            Ok(pkg)
+        };
+
+        let options = RunOptions {
+            signatures,
+            #[cfg(feature = "gdb")]
+            gdblisten: Some(gdblisten),
+            faults,
+            env_host: config.env_host.clone(),
+            log_files: log_files(&config),
+            ..Default::default()
+        };
+        let code = run_package(backend, exec, options, get_pkg)?;
+        std::process::exit(code);
+    }
+}
+
+/// Returns an unlinked temporary file containing `contents`, positioned at its start.
+///
+/// Windows cannot remove open files, so the file is removed once it is closed there.
+pub(crate) fn anonymous(name: &str, contents: &[u8]) -> anyhow::Result<File> {
+    let path = std::env::temp_dir().join(format!("enarx-{}-{name}", std::process::id()));
+    let mut options = OpenOptions::new();
+    options.read(true).write(true).create_new(true);
+    #[cfg(windows)]
+    options.custom_flags(FILE_FLAG_DELETE_ON_CLOSE);
+    let mut file = options
+        .open(&path)
+        .with_context(|| format!("failed to create `{}`", path.display()))?;
+    #[cfg(unix)]
+    fs::remove_file(&path).with_context(|| format!("failed to remove `{}`", path.display()))?;
+    file.write_all(contents)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
@@ -0,0 +1,265 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::backend::sev::policy::Policy;
+use crate::backend::sev::snp::launch::IdBlock;
+
+use anyhow::{anyhow, ensure, Context, Result};
+use camino::Utf8Path;
+use serde::Deserialize;
+use sgx::parameters::{Attributes, Features, MiscSelect, Xfrm};
+use sgx::signature::Body;
+
+/// The launch configuration of the signed keeps, read from the file passed to `--config`
+#[derive(Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Fields of the SGX SIGSTRUCT
+    #[serde(default)]
+    pub sgx: Sgx,
+
+    /// Fields of the SEV-SNP ID block
+    #[serde(default)]
+    pub sev: Sev,
+}
+
+impl Config {
+    pub fn load(path: &Utf8Path) -> Result<Self> {
+        let config = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read signing config `{path}`"))?;
+        toml::from_str(&config).with_context(|| format!("invalid signing config `{path}`"))
+    }
+}
+
+/// Fields of the SGX SIGSTRUCT, the ones not specified default to the values required by the shim
+#[derive(Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Sgx {
+    /// `ISVPRODID`
+    pub isv_prod_id: Option<u16>,
+
+    /// `ISVSVN`
+    pub isv_svn: Option<u16>,
+
+    /// `MISCSELECT`
+    pub misc_select: Option<u32>,
+
+    /// `MISCMASK`
+    pub misc_select_mask: Option<u32>,
+
+    /// `ATTRIBUTES.FLAGS`
+    pub features: Option<u64>,
+
+    /// `ATTRIBUTEMASK.FLAGS`
+    pub features_mask: Option<u64>,
+
+    /// `ATTRIBUTES.XFRM`
+    pub xfrm: Option<u64>,
+
+    /// `ATTRIBUTEMASK.XFRM`
+    pub xfrm_mask: Option<u64>,
+}
+
+impl Sgx {
+    /// Returns `body` with the configured fields replaced.
+    pub fn apply(&self, body: Body) -> Result<Body> {
+        let misc = |bits: u32, name| {
+            MiscSelect::from_bits(bits).ok_or_else(|| anyhow!("unknown `{name}` bits {bits:#x}"))
+        };
+        let features = |bits: u64, name| {
+            Features::from_bits(bits).ok_or_else(|| anyhow!("unknown `{name}` bits {bits:#x}"))
+        };
+        let xfrm = |bits: u64, name| {
+            Xfrm::from_bits(bits).ok_or_else(|| anyhow!("unknown `{name}` bits {bits:#x}"))
+        };
+
+        let mut params = body.parameters();
+        if let Some(pid) = self.isv_prod_id {
+            params.pid = pid;
+        }
+        if let Some(svn) = self.isv_svn {
+            params.svn = svn;
+        }
+        if let Some(bits) = self.misc_select {
+            params.misc.data = misc(bits, "misc_select")?;
+        }
+        if let Some(bits) = self.misc_select_mask {
+            params.misc.mask = misc(bits, "misc_select_mask")?;
+        }
+        let (data, mask) = (params.attr.data, params.attr.mask);
+        params.attr.data = Attributes::new(
+            self.features
+                .map_or(Ok(data.features()), |bits| features(bits, "features"))?,
+            self.xfrm
+                .map_or(Ok(data.xfrm()), |bits| xfrm(bits, "xfrm"))?,
+        );
+        params.attr.mask = Attributes::new(
+            self.features_mask
+                .map_or(Ok(mask.features()), |bits| features(bits, "features_mask"))?,
+            self.xfrm_mask
+                .map_or(Ok(mask.xfrm()), |bits| xfrm(bits, "xfrm_mask"))?,
+        );
+        Ok(params.body(body.mrenclave()))
+    }
+}
+
+/// Fields of the SEV-SNP ID block, the ones not specified default to the values of the shim
+#[derive(Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Sev {
+    /// Hex-encoded 16 byte `FAMILY_ID`
+    pub family_id: Option<String>,
+
+    /// Hex-encoded 16 byte `IMAGE_ID`
+    pub image_id: Option<String>,
+
+    /// `GUEST_SVN`
+    pub guest_svn: Option<u32>,
+
+    /// Changes of the guest policy
+    #[serde(default)]
+    pub policy: Policy,
+}
+
+/// Returns the 16 bytes encoded in `hex`.
+fn id(hex: &str, name: &str) -> Result<[u8; 16]> {
+    let invalid = || anyhow!("`{name}` must be 32 hex digits");
+    ensure!(hex.len() == 32, invalid());
+    let mut id = [0; 16];
+    for (i, b) in id.iter_mut().enumerate() {
+        *b = hex
+            .get(2 * i..2 * i + 2)
+            .and_then(|b| u8::from_str_radix(b, 16).ok())
+            .ok_or_else(invalid)?;
+    }
+    Ok(id)
+}
+
+impl Sev {
+    /// Returns `id_block` with the configured fields replaced.
+    pub fn apply(&self, mut id_block: IdBlock) -> Result<IdBlock> {
+        if let Some(ref family_id) = self.family_id {
+            id_block.family_id = id(family_id, "family_id")?;
+        }
+        if let Some(ref image_id) = self.image_id {
+            id_block.image_id = id(image_id, "image_id")?;
+        }
+        if let Some(svn) = self.guest_svn {
+            id_block.guest_svn = svn;
+        }
+        id_block.policy = self.policy.apply(id_block.policy);
+        Ok(id_block)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Config, Sev, Sgx};
+
+    use crate::backend::sev::snp::launch::IdBlock;
+
+    use sgx::parameters::{Attributes, Features, Masked, MiscSelect, Parameters, Xfrm};
+
+    #[test]
+    fn sgx() {
+        let config: Config = toml::from_str(
+            r#"
+            [sgx]
+            isv_prod_id = 7
+            isv_svn = 3
+            misc_select = 0x1
+            features = 0x6
+            xfrm_mask = 0x7
+            "#,
+        )
+        .unwrap();
+
+        let defaults = Parameters {
+            misc: Masked {
+                data: MiscSelect::empty(),
+                mask: MiscSelect::EXINFO,
+            },
+            attr: Masked {
+                data: Attributes::new(Features::MODE64BIT, Xfrm::X87 | Xfrm::SSE),
+                mask: Attributes::new(Features::all(), Xfrm::X87 | Xfrm::SSE),
+            },
+            pid: 0,
+            svn: 0,
+        };
+        let body = config.sgx.apply(defaults.body([1; 32])).unwrap();
+        assert_eq!(body.mrenclave(), [1; 32]);
+        assert_eq!(
+            body.parameters(),
+            Parameters {
+                misc: Masked {
+                    data: MiscSelect::EXINFO,
+                    mask: MiscSelect::EXINFO,
+                },
+                attr: Masked {
+                    data: Attributes::new(
+                        Features::MODE64BIT | Features::DEBUG,
+                        Xfrm::X87 | Xfrm::SSE
+                    ),
+                    mask: Attributes::new(Features::all(), Xfrm::X87 | Xfrm::SSE | Xfrm::AVX),
+                },
+                pid: 7,
+                svn: 3,
+            }
+        );
+
+        let sgx = Sgx {
+            features: Some(1 << 63),
+            ..Default::default()
+        };
+        assert_eq!(
+            sgx.apply(defaults.body([0; 32])).unwrap_err().to_string(),
+            "unknown `features` bits 0x8000000000000000"
+        );
+        assert!(toml::from_str::<Config>("[sgx]\nisv_prodid = 1").is_err());
+    }
+
+    #[test]
+    fn sev() {
+        let config: Config = toml::from_str(
+            r#"
+            [sev]
+            family_id = "000102030405060708090a0b0c0d0e0f"
+            guest_svn = 2
+
+            [sev.policy]
+            smt = false
+            "#,
+        )
+        .unwrap();
+
+        let defaults = IdBlock {
+            launch_digest: [1; 48],
+            image_id: [3; 16],
+            policy: 0x30000,
+            ..Default::default()
+        };
+        let id_block = config.sev.apply(defaults).unwrap();
+        assert_eq!(
+            id_block,
+            IdBlock {
+                family_id: [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+                guest_svn: 2,
+                policy: 0x20000,
+                ..defaults
+            }
+        );
+
+        let sev = Sev {
+            image_id: Some("0011".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            sev.apply(defaults).unwrap_err().to_string(),
+            "`image_id` must be 32 hex digits"
+        );
+        let sev = Sev {
+            image_id: Some("zz".repeat(16)),
+            ..Default::default()
+        };
+        assert!(sev.apply(defaults).is_err());
+    }
+}
@@ -0,0 +1,209 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::backend::sev::snp::launch::{IdAuth, IdBlock};
+use crate::backend::{ByteSized, Signatures};
+use crate::exec::hex;
+
+use anyhow::{anyhow, Result};
+use camino::Utf8PathBuf;
+use clap::Args;
+use ring::digest::{digest, SHA256, SHA384};
+use serde::Serialize;
+use sgx::signature::Signature;
+
+/// Print the contents of a signature file in JSON.
+///
+/// The signatures are not verified, use `enarx sign verify` for that.
+#[derive(Args, Debug)]
+pub struct Options {
+    /// Path of the signature file to inspect
+    #[clap(value_name = "SIGNATURES")]
+    pub signatures: Utf8PathBuf,
+}
+
+/// The contents of a signature file
+#[derive(Serialize, Debug)]
+struct Inspection {
+    version: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sgx: Option<Sgx>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sev: Option<Sev>,
+}
+
+/// The contents of an SGX SIGSTRUCT
+#[derive(Serialize, Debug, PartialEq, Eq)]
+struct Sgx {
+    /// `MRENCLAVE` of the signed keep
+    mrenclave: String,
+
+    /// `MRSIGNER`, the SHA-256 digest of the modulus of the signing key
+    mrsigner: String,
+
+    /// Public exponent of the signing key
+    exponent: u32,
+
+    /// Date of the signature, as `yyyymmdd` in BCD
+    date: String,
+
+    /// `ISVPRODID`
+    isv_prod_id: u16,
+
+    /// `ISVSVN`
+    isv_svn: u16,
+
+    /// `MISCSELECT` and its mask
+    misc_select: Masked,
+
+    /// `ATTRIBUTES.FLAGS` and its mask
+    features: Masked,
+
+    /// `ATTRIBUTES.XFRM` and its mask
+    xfrm: Masked,
+
+    /// Whether the keep may be debugged
+    debug: bool,
+}
+
+/// A value required by an SGX SIGSTRUCT, hex-encoded
+#[derive(Serialize, Debug, PartialEq, Eq)]
+struct Masked {
+    value: String,
+    mask: String,
+}
+
+/// The contents of an SEV-SNP ID block and ID auth
+#[derive(Serialize, Debug, PartialEq, Eq)]
+struct Sev {
+    /// `MEASUREMENT` of the signed keep
+    launch_digest: String,
+
+    /// `FAMILY_ID`
+    family_id: String,
+
+    /// `IMAGE_ID`
+    image_id: String,
+
+    /// `GUEST_SVN`
+    guest_svn: u32,
+
+    /// Guest policy
+    policy: Policy,
+
+    /// `ID_KEY_DIGEST`, the SHA-384 digest of the ID key
+    id_key_digest: String,
+
+    /// `AUTHOR_KEY_DIGEST`, the SHA-384 digest of the author key, if there is one
+    author_key_digest: Option<String>,
+}
+
+/// An SEV-SNP guest policy
+#[derive(Serialize, Debug, PartialEq, Eq)]
+struct Policy {
+    /// The policy, hex-encoded
+    value: String,
+
+    /// Minimum ABI major version
+    abi_major: u8,
+
+    /// Minimum ABI minor version
+    abi_minor: u8,
+
+    /// Whether SMT is allowed
+    smt: bool,
+
+    /// Whether a migration agent may be associated with the guest
+    migrate_ma: bool,
+
+    /// Whether the guest may be debugged
+    debug: bool,
+
+    /// Whether the guest may only run on a single socket
+    single_socket: bool,
+}
+
+impl From<u64> for Policy {
+    fn from(policy: u64) -> Self {
+        Self {
+            value: format!("{policy:#x}"),
+            abi_major: (policy >> 8) as u8,
+            abi_minor: policy as u8,
+            smt: policy & 1 << 16 != 0,
+            migrate_ma: policy & 1 << 18 != 0,
+            debug: policy & 1 << 19 != 0,
+            single_socket: policy & 1 << 20 != 0,
+        }
+    }
+}
+
+fn inspect_sgx(sig: &[u8]) -> Result<Sgx> {
+    use sgx::parameters::Features;
+
+    let sig = Signature::from_bytes(sig).ok_or_else(|| anyhow!("Invalid SGX signature"))?;
+    let params = sig.body().parameters();
+    let (misc, attr) = (params.misc, params.attr);
+    Ok(Sgx {
+        mrenclave: hex(sig.body().mrenclave()),
+        // `MRSIGNER` is the SHA-256 digest of the little-endian modulus of the signing key.
+        mrsigner: hex(digest(&SHA256, &sig.as_bytes()[128..512])),
+        exponent: u32::from_le_bytes(sig.as_bytes()[512..516].try_into()?),
+        date: format!("{:08x}", sig.author().date()),
+        isv_prod_id: params.pid,
+        isv_svn: params.svn,
+        misc_select: Masked {
+            value: format!("{:#x}", misc.data.bits()),
+            mask: format!("{:#x}", misc.mask.bits()),
+        },
+        features: Masked {
+            value: format!("{:#x}", attr.data.features().bits()),
+            mask: format!("{:#x}", attr.mask.features().bits()),
+        },
+        xfrm: Masked {
+            value: format!("{:#x}", attr.data.xfrm().bits()),
+            mask: format!("{:#x}", attr.mask.xfrm().bits()),
+        },
+        debug: attr.data.features().contains(Features::DEBUG),
+    })
+}
+
+fn inspect_sev(id_block: &[u8], id_auth: &[u8]) -> Result<Sev> {
+    let id_block = IdBlock::from_bytes(id_block).ok_or_else(|| anyhow!("Invalid SEV ID block"))?;
+    let id_auth = IdAuth::from_bytes(id_auth).ok_or_else(|| anyhow!("Invalid SEV ID auth"))?;
+    Ok(Sev {
+        launch_digest: hex(id_block.launch_digest),
+        family_id: hex(id_block.family_id),
+        image_id: hex(id_block.image_id),
+        guest_svn: id_block.guest_svn,
+        policy: id_block.policy.into(),
+        id_key_digest: hex(digest(&SHA384, id_auth.id_key.as_bytes())),
+        author_key_digest: Some(&id_auth)
+            .filter(|id_auth| id_auth.has_author_key())
+            .map(|id_auth| hex(digest(&SHA384, id_auth.author_key.as_bytes()))),
+    })
+}
+
+impl Options {
+    pub fn execute(self) -> anyhow::Result<()> {
+        let signatures = Signatures::load(Some(self.signatures))?
+            .ok_or_else(|| anyhow!("Missing signature file"))?;
+
+        let sgx = Some(&signatures.sgx)
+            .filter(|sig| !sig.is_empty())
+            .map(|sig| inspect_sgx(sig))
+            .transpose()?;
+        let sev = Some(&signatures.sev)
+            .filter(|sig| !sig.id_block.is_empty())
+            .map(|sig| inspect_sev(&sig.id_block, &sig.id_auth))
+            .transpose()?;
+
+        let inspection = Inspection {
+            version: signatures.version,
+            sgx,
+            sev,
+        };
+        println!("{}", serde_json::to_string_pretty(&inspection)?);
+        Ok(())
+    }
+}
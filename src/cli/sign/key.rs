@@ -0,0 +1,515 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Signing keys, which are either read from a file or held externally
+
+use crate::exec::hex;
+
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use camino::Utf8PathBuf;
+use p384::ecdsa::signature::{Signer as _, Verifier as _};
+use p384::ecdsa::{Signature as EcdsaSignature, SigningKey, VerifyingKey};
+use p384::pkcs8::{DecodePrivateKey, DecodePublicKey as _};
+use ring::digest::{digest, SHA256, SHA384};
+use rsa::pkcs1::DecodeRsaPublicKey;
+use rsa::pkcs8::DecodePublicKey as _;
+use rsa::{BigUint, PaddingScheme, PublicKey as _, PublicKeyParts, RsaPublicKey};
+use sgx::crypto::{rcrypto::RS256PrivateKey, PrivateKey, SigData};
+
+/// The DER encoded `DigestInfo` prefix of a SHA-256 digest, as signed by `RSA-PKCS`
+const SHA256_DIGEST_INFO: &[u8] = &[
+    0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05,
+    0x00, 0x04, 0x20,
+];
+
+/// The environment variable passing the PIN of a PKCS#11 token to `pkcs11-tool`
+const PIN_VAR: &str = "ENARX_PKCS11_PIN";
+
+/// Reference to a private key, which is one of:
+///
+/// - a path to a PEM file
+/// - a `pkcs11:` URI of a key on a PKCS#11 token, which is used via `pkcs11-tool`
+/// - `cmd:<command>`, an external command holding the key
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Key {
+    File(Utf8PathBuf),
+    External(External),
+}
+
+impl FromStr for Key {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(attrs) = s.strip_prefix("pkcs11:") {
+            Ok(Self::External(External::Pkcs11(Box::new(attrs.parse()?))))
+        } else if let Some(cmd) = s.strip_prefix("cmd:") {
+            if cmd.trim().is_empty() {
+                bail!("missing command in `{s}`");
+            }
+            Ok(Self::External(External::Command(cmd.into())))
+        } else {
+            Ok(Self::File(s.into()))
+        }
+    }
+}
+
+impl Key {
+    /// Loads the RSA key signing SGX SIGSTRUCTs.
+    pub fn load_sgx(&self) -> Result<SgxKey> {
+        match self {
+            Self::File(path) => {
+                let pem = fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read SGX private key file `{path}`"))?;
+                let key = RS256PrivateKey::from_pem(&pem)
+                    .map_err(|e| anyhow!("{e:?}"))
+                    .with_context(|| format!("Failed to parse SGX private key `{path}`"))?;
+                Ok(SgxKey::File(key.into()))
+            }
+            Self::External(ext) => {
+                let der = ext.public_key(Algorithm::Rs256)?;
+                let key = rsa_public_key(&der)
+                    .with_context(|| format!("Failed to parse SGX public key of {ext}"))?;
+                if key.n().bits() > 384 * 8 {
+                    bail!("SGX public key of {ext} is larger than 3072 bits");
+                }
+                Ok(SgxKey::External(ext.clone(), key))
+            }
+        }
+    }
+
+    /// Loads a P-384 key signing SEV ID blocks or ID keys.
+    pub fn load_sev(&self) -> Result<SevKey> {
+        match self {
+            Self::File(path) => {
+                let pem = fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read SEV private key file `{path}`"))?;
+                let key = SigningKey::from_pkcs8_pem(&pem)
+                    .with_context(|| format!("Failed to parse SEV private key `{path}`"))?;
+                Ok(SevKey::File(key))
+            }
+            Self::External(ext) => {
+                let der = ext.public_key(Algorithm::Es384)?;
+                let key = ec_public_key(&der)
+                    .with_context(|| format!("Failed to parse SEV public key of {ext}"))?;
+                Ok(SevKey::External(ext.clone(), key.into()))
+            }
+        }
+    }
+}
+
+/// Signature algorithm requested from an external key
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Algorithm {
+    /// RSASSA-PKCS1-v1_5 with SHA-256
+    Rs256,
+
+    /// ECDSA on P-384 with SHA-384
+    Es384,
+}
+
+impl Display for Algorithm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Rs256 => "rsa-sha256",
+            Self::Es384 => "ecdsa-p384-sha384",
+        })
+    }
+}
+
+/// A private key, which never leaves its holder
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum External {
+    /// A key on a PKCS#11 token
+    Pkcs11(Box<Pkcs11>),
+
+    /// An external command, invoked as `<command> public-key <algorithm>` to
+    /// print the public key and as `<command> sign <algorithm>` to print the
+    /// signature of the message passed on stdin
+    Command(String),
+}
+
+impl Display for External {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pkcs11(key) => write!(f, "PKCS#11 key `{key}`"),
+            Self::Command(cmd) => write!(f, "command `{cmd}`"),
+        }
+    }
+}
+
+impl External {
+    /// Returns the public key in PEM or DER form.
+    fn public_key(&self, alg: Algorithm) -> Result<Vec<u8>> {
+        let cmd = match self {
+            Self::Pkcs11(key) => {
+                let mut cmd = key.command();
+                cmd.args(["--read-object", "--type", "pubkey"]);
+                cmd
+            }
+            Self::Command(cmd) => {
+                let mut cmd = command(cmd);
+                cmd.arg("public-key").arg(alg.to_string());
+                cmd
+            }
+        };
+        execute(cmd, &[]).with_context(|| format!("Failed to query public key of {self}"))
+    }
+
+    /// Signs `msg`, the signature is in raw form or DER encoded in case of ECDSA.
+    fn sign(&self, alg: Algorithm, msg: &[u8]) -> Result<Vec<u8>> {
+        let sig = match self {
+            Self::Pkcs11(key) => {
+                // Tokens are not guaranteed to support hashing, so only the digest is signed.
+                let (mechanism, input) = match alg {
+                    Algorithm::Rs256 => {
+                        let hash = digest(&SHA256, msg);
+                        ("RSA-PKCS", [SHA256_DIGEST_INFO, hash.as_ref()].concat())
+                    }
+                    Algorithm::Es384 => ("ECDSA", digest(&SHA384, msg).as_ref().to_vec()),
+                };
+                execute(key.sign_command(mechanism)?, &input)
+            }
+            Self::Command(cmd) => {
+                let mut cmd = command(cmd);
+                cmd.arg("sign").arg(alg.to_string());
+                execute(cmd, msg)
+            }
+        };
+        sig.with_context(|| format!("Failed to sign with {self}"))
+    }
+}
+
+/// Key on a PKCS#11 token, identified by the attributes of an RFC 7512 URI
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Pkcs11 {
+    uri: String,
+    module: Option<String>,
+    token: Option<String>,
+    object: Option<String>,
+    id: Option<Vec<u8>>,
+    pin_value: Option<String>,
+    pin_source: Option<String>,
+}
+
+impl FromStr for Pkcs11 {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (path, query) = s.split_once('?').unwrap_or((s, ""));
+        // The query is omitted from messages, since it might contain the PIN.
+        let mut key = Self {
+            uri: format!("pkcs11:{path}"),
+            ..Default::default()
+        };
+        let path = path.split(';').map(|attr| (attr, true));
+        let query = query.split('&').map(|attr| (attr, false));
+        for (attr, in_path) in path.chain(query).filter(|(attr, _)| !attr.is_empty()) {
+            let (name, value) = attr
+                .split_once('=')
+                .ok_or_else(|| anyhow!("invalid PKCS#11 URI attribute `{attr}`"))?;
+            let value = percent_decode(value)
+                .with_context(|| format!("invalid PKCS#11 URI attribute `{attr}`"))?;
+            let utf8 = || {
+                String::from_utf8(value.clone())
+                    .with_context(|| format!("invalid PKCS#11 URI attribute `{attr}`"))
+            };
+            match (name, in_path) {
+                ("token", true) => key.token = Some(utf8()?),
+                ("object", true) => key.object = Some(utf8()?),
+                ("id", true) => key.id = Some(value),
+                ("type", true) if value == b"private" => {}
+                ("module-path", false) => key.module = Some(utf8()?),
+                ("pin-value", false) => key.pin_value = Some(utf8()?),
+                ("pin-source", false) => key.pin_source = Some(utf8()?),
+                _ => bail!("unsupported PKCS#11 URI attribute `{attr}`"),
+            }
+        }
+        if key.object.is_none() && key.id.is_none() {
+            bail!(
+                "PKCS#11 URI `{}` has neither an `object` nor an `id`",
+                key.uri
+            );
+        }
+        Ok(key)
+    }
+}
+
+impl Display for Pkcs11 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.uri)
+    }
+}
+
+impl Pkcs11 {
+    /// Returns the `pkcs11-tool` invocation selecting the key.
+    fn command(&self) -> Command {
+        let mut cmd = Command::new("pkcs11-tool");
+        if let Some(module) = &self.module {
+            cmd.args(["--module", module]);
+        }
+        if let Some(token) = &self.token {
+            cmd.args(["--token-label", token]);
+        }
+        if let Some(object) = &self.object {
+            cmd.args(["--label", object]);
+        }
+        if let Some(id) = &self.id {
+            cmd.args(["--id", &hex(id)]);
+        }
+        cmd
+    }
+
+    /// Returns the `pkcs11-tool` invocation signing its input with the key using `mechanism`.
+    fn sign_command(&self, mechanism: &str) -> Result<Command> {
+        let mut cmd = self.command();
+        cmd.args(["--sign", "--login", "--mechanism", mechanism]);
+        // The PIN is passed in the environment, since the arguments are visible to all users of
+        // the host.
+        if let Some(pin) = self.pin()? {
+            cmd.env(PIN_VAR, pin);
+            cmd.args(["--pin", &format!("env:{PIN_VAR}")]);
+        }
+        Ok(cmd)
+    }
+
+    /// Returns the PIN of the token, if any. Otherwise `pkcs11-tool` prompts for it.
+    fn pin(&self) -> Result<Option<String>> {
+        match (&self.pin_value, &self.pin_source) {
+            (Some(pin), _) => Ok(Some(pin.clone())),
+            (None, Some(path)) => {
+                let pin = fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read PKCS#11 PIN from `{path}`"))?;
+                Ok(Some(pin.trim_end_matches(['\r', '\n']).into()))
+            }
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+/// Decodes the percent-encoded octets of `s`.
+fn percent_decode(s: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b != b'%' {
+            out.push(b);
+            continue;
+        }
+        let hex = [bytes.next(), bytes.next()];
+        let hex = match hex {
+            [Some(hi), Some(lo)] => [hi, lo],
+            _ => bail!("truncated percent-encoding"),
+        };
+        let hex = std::str::from_utf8(&hex)?;
+        out.push(u8::from_str_radix(hex, 16).context("invalid percent-encoding")?);
+    }
+    Ok(out)
+}
+
+/// Returns the command running `cmd`, which is split at whitespace.
+fn command(cmd: &str) -> Command {
+    let mut words = cmd.split_whitespace();
+    let mut cmd = Command::new(words.next().unwrap_or_default());
+    cmd.args(words);
+    cmd
+}
+
+/// Executes `cmd` with `input` on stdin and returns its stdout.
+fn execute(mut cmd: Command, input: &[u8]) -> Result<Vec<u8>> {
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to execute `{program}`"))?;
+    // The inputs are small enough to not fill the pipe.
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input)
+        .with_context(|| format!("Failed to write to `{program}`"))?;
+    let output = child
+        .wait_with_output()
+        .with_context(|| format!("Failed to wait for `{program}`"))?;
+    if !output.status.success() {
+        bail!("`{program}` failed with {}", output.status);
+    }
+    Ok(output.stdout)
+}
+
+/// Parses an RSA public key in PEM or DER form, as SubjectPublicKeyInfo or PKCS#1.
+fn rsa_public_key(key: &[u8]) -> Result<RsaPublicKey> {
+    match std::str::from_utf8(key) {
+        Ok(pem) if pem.trim_start().starts_with("-----BEGIN") => {
+            let pem = pem.trim();
+            RsaPublicKey::from_public_key_pem(pem)
+                .or_else(|_| RsaPublicKey::from_pkcs1_pem(pem))
+                .context("invalid PEM encoded RSA public key")
+        }
+        _ => RsaPublicKey::from_public_key_der(key)
+            .or_else(|_| RsaPublicKey::from_pkcs1_der(key))
+            .context("invalid DER encoded RSA public key"),
+    }
+}
+
+/// Parses a P-384 public key in PEM or DER form, as SubjectPublicKeyInfo.
+fn ec_public_key(key: &[u8]) -> Result<VerifyingKey> {
+    let key = match std::str::from_utf8(key) {
+        Ok(pem) if pem.trim_start().starts_with("-----BEGIN") => {
+            p384::PublicKey::from_public_key_pem(pem.trim())
+                .context("invalid PEM encoded P-384 public key")?
+        }
+        _ => p384::PublicKey::from_public_key_der(key)
+            .context("invalid DER encoded P-384 public key")?,
+    };
+    Ok(key.into())
+}
+
+/// Returns `value` as a little-endian array as used by SIGSTRUCT.
+fn arr_from_big(value: &BigUint) -> [u8; 384] {
+    let mut arr = [0u8; 384];
+    let buf = value.to_bytes_le();
+    arr[..buf.len()].copy_from_slice(&buf);
+    arr
+}
+
+/// RSA key signing SGX SIGSTRUCTs
+pub enum SgxKey {
+    File(Box<RS256PrivateKey>),
+    External(External, RsaPublicKey),
+}
+
+impl PrivateKey for SgxKey {
+    type Error = anyhow::Error;
+
+    fn generate(exponent: u8) -> Result<Self> {
+        Ok(Self::File(RS256PrivateKey::generate(exponent)?.into()))
+    }
+
+    fn from_pem(pem: &str) -> Result<Self> {
+        Ok(Self::File(RS256PrivateKey::from_pem(pem)?.into()))
+    }
+
+    fn from_der(der: &[u8]) -> Result<Self> {
+        Ok(Self::File(RS256PrivateKey::from_der(der)?.into()))
+    }
+
+    fn sign(&self, author: &[u8], body: &[u8]) -> Result<SigData> {
+        let (ext, key) = match self {
+            Self::File(key) => return Ok(key.sign(author, body)?),
+            Self::External(ext, key) => (ext, key),
+        };
+
+        let msg = [author, body].concat();
+        let signature = ext.sign(Algorithm::Rs256, &msg)?;
+        let padding = PaddingScheme::new_pkcs1v15_sign(Some(rsa::hash::Hash::SHA2_256));
+        key.verify(padding, digest(&SHA256, &msg).as_ref(), &signature)
+            .with_context(|| format!("Invalid SGX signature from {ext}"))?;
+
+        // Calculate q1 and q2.
+        let s = BigUint::from_bytes_be(&signature);
+        let m = key.n();
+        let (q1, qr) = (&s * &s / m, &s * &s % m);
+        let q2 = &s * qr / m;
+
+        let exponent = key.e().to_bytes_le();
+        if exponent.len() > 4 {
+            bail!("SGX public key exponent of {ext} is larger than 32 bits");
+        }
+        let mut e = [0u8; 4];
+        e[..exponent.len()].copy_from_slice(&exponent);
+
+        Ok(SigData {
+            signature: arr_from_big(&s),
+            modulus: arr_from_big(m),
+            exponent: u32::from_le_bytes(e),
+            q1: arr_from_big(&q1),
+            q2: arr_from_big(&q2),
+        })
+    }
+}
+
+/// P-384 key signing SEV ID blocks or ID keys
+pub enum SevKey {
+    File(SigningKey),
+    External(External, Box<VerifyingKey>),
+}
+
+impl SevKey {
+    /// Signs `msg` with ECDSA and SHA-384.
+    pub fn sign(&self, msg: &[u8]) -> Result<EcdsaSignature> {
+        let (ext, key) = match self {
+            Self::File(key) => return Ok(key.sign(msg)),
+            Self::External(ext, key) => (ext, key),
+        };
+
+        let sig = ext.sign(Algorithm::Es384, msg)?;
+        let sig = EcdsaSignature::try_from(sig.as_slice())
+            .or_else(|_| EcdsaSignature::from_der(&sig))
+            .with_context(|| format!("Invalid SEV signature encoding from {ext}"))?;
+        key.verify(msg, &sig)
+            .with_context(|| format!("Invalid SEV signature from {ext}"))?;
+        Ok(sig)
+    }
+
+    /// Returns the public key.
+    pub fn verifying_key(&self) -> VerifyingKey {
+        match self {
+            Self::File(key) => key.verifying_key(),
+            Self::External(_, key) => **key,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            "keys/sgx.pem".parse::<Key>().unwrap(),
+            Key::File("keys/sgx.pem".into())
+        );
+        assert_eq!(
+            "cmd:kms-sign --key sev".parse::<Key>().unwrap(),
+            Key::External(External::Command("kms-sign --key sev".into()))
+        );
+        assert!("cmd:".parse::<Key>().is_err());
+
+        let uri = "pkcs11:token=Build%20HSM;id=%01%ab;type=private?module-path=/usr/lib/softhsm/libsofthsm2.so&pin-value=1234";
+        let key = Pkcs11 {
+            uri: "pkcs11:token=Build%20HSM;id=%01%ab;type=private".into(),
+            module: Some("/usr/lib/softhsm/libsofthsm2.so".into()),
+            token: Some("Build HSM".into()),
+            id: Some(vec![0x01, 0xab]),
+            pin_value: Some("1234".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            uri.parse::<Key>().unwrap(),
+            Key::External(External::Pkcs11(key.into()))
+        );
+
+        assert!("pkcs11:token=Build%20HSM".parse::<Key>().is_err());
+        assert!("pkcs11:object=sgx;serial=1".parse::<Key>().is_err());
+        assert!("pkcs11:id=%0".parse::<Key>().is_err());
+    }
+
+    #[test]
+    fn pin() {
+        let key: Pkcs11 = "token=HSM;object=sgx?pin-value=1234".parse().unwrap();
+        let cmd = key.sign_command("RSA-PKCS").unwrap();
+
+        // The PIN is passed in the environment, not as an argument.
+        assert!(cmd.get_args().all(|arg| arg != "1234"));
+        assert!(cmd
+            .get_envs()
+            .any(|(var, pin)| var == PIN_VAR && pin == Some("1234".as_ref())));
+    }
+}
@@ -0,0 +1,16 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::backend::signatures::SCHEMA;
+
+use clap::Args;
+
+/// Print the JSON schema of the signature files written by this version of enarx.
+#[derive(Args, Debug)]
+pub struct Options {}
+
+impl Options {
+    pub fn execute(self) -> anyhow::Result<()> {
+        print!("{SCHEMA}");
+        Ok(())
+    }
+}
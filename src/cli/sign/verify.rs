@@ -0,0 +1,212 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::backend::sev::snp::launch::{IdAuth, IdBlock};
+use crate::backend::sev::snp::sign::{PublicKey, Signature as SevSignature};
+use crate::backend::{Backend, ByteSized, SevSignature as SevSignatures, Signatures, BACKENDS};
+use crate::exec::hex;
+use crate::exec::EXECS;
+
+use std::ops::Deref;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use camino::Utf8PathBuf;
+use clap::Args;
+use p384::ecdsa::signature::Verifier as _;
+use p384::ecdsa::{Signature as EcdsaSignature, VerifyingKey};
+use p384::EncodedPoint;
+use ring::digest::{digest, SHA256, SHA384};
+use rsa::{BigUint, PaddingScheme, PublicKey as _, RsaPublicKey};
+use sgx::signature::{Body, Signature};
+
+/// ECDSA P-384 with SHA-384, the only algorithm of SEV-SNP ID keys and author keys
+const SEV_ALGO_ECDSA_P384_SHA384: u32 = 1;
+
+/// Verify the signatures of a signature file.
+///
+/// The signatures are checked against the public keys they carry and against
+/// the measurement of the compiled-in keep payload, i.e. the shim and the exec
+/// of the backend, or of the given binary. The digests of the signing keys,
+/// which the attestation of a keep reports, are printed for every valid
+/// signature.
+#[derive(Args, Debug)]
+pub struct Options {
+    /// Path of the signature file to verify
+    #[clap(value_name = "SIGNATURES")]
+    pub signatures: Utf8PathBuf,
+
+    /// Binary, which would be loaded and run inside the keep
+    #[clap(value_name = "BINARY")]
+    pub binpath: Option<Utf8PathBuf>,
+
+    /// Only verify the signature of the given backend ("sgx", "sev")
+    #[clap(long)]
+    pub backend: Option<String>,
+}
+
+/// Returns the SGX SIGSTRUCT `sig`, if it is signed by the key it carries.
+pub(super) fn verify_sgx(sig: &[u8]) -> Result<Signature> {
+    let parsed = Signature::from_bytes(sig).ok_or_else(|| anyhow!("Invalid SGX signature"))?;
+    let sig = parsed.as_bytes();
+
+    // The signed author and body are the first 128 bytes and the 128 bytes at offset 900, the
+    // modulus, exponent and signature are stored in little-endian order between them.
+    let (author, body) = (&sig[..128], &sig[900..1028]);
+    let modulus = BigUint::from_bytes_le(&sig[128..512]);
+    let exponent = u32::from_le_bytes(sig[512..516].try_into()?);
+    let mut signature = sig[516..900].to_vec();
+    signature.reverse();
+
+    let key = RsaPublicKey::new(modulus, exponent.into()).context("Invalid SGX public key")?;
+    let padding = PaddingScheme::new_pkcs1v15_sign(Some(rsa::hash::Hash::SHA2_256));
+    let msg = [author, body].concat();
+    key.verify(padding, digest(&SHA256, &msg).as_ref(), &signature)
+        .context("Invalid SGX signature")?;
+    Ok(parsed)
+}
+
+/// Returns the SEV public key `key`, whose coordinates are stored in little-endian order.
+fn sev_key(key: &PublicKey) -> Result<VerifyingKey> {
+    let (mut x, mut y) = (key.component.r, key.component.s);
+    x.reverse();
+    y.reverse();
+    // The coordinates are zero extended from 48 to 72 bytes.
+    let point = EncodedPoint::from_affine_coordinates(
+        x[x.len() - 48..].into(),
+        y[y.len() - 48..].into(),
+        false,
+    );
+    VerifyingKey::from_encoded_point(&point).map_err(|_| anyhow!("Invalid SEV public key"))
+}
+
+/// Verifies that `sig`, whose components are stored in little-endian order, signs `msg`
+/// with `key`.
+fn sev_verify(key: &VerifyingKey, msg: &[u8], sig: &SevSignature) -> Result<()> {
+    let (mut r, mut s) = (sig.component.r, sig.component.s);
+    r.reverse();
+    s.reverse();
+    let r: [u8; 48] = r[r.len() - 48..].try_into()?;
+    let s: [u8; 48] = s[s.len() - 48..].try_into()?;
+    let sig = EcdsaSignature::from_scalars(r, s).map_err(|_| anyhow!("Invalid SEV signature"))?;
+    Ok(key.verify(msg, &sig)?)
+}
+
+/// Returns the SEV ID block and ID auth of `sig`, if the ID block is signed by the ID key and
+/// the ID key is signed by the author key, if there is one.
+pub(super) fn verify_sev(sig: &SevSignatures) -> Result<(IdBlock, IdAuth)> {
+    let id_block =
+        IdBlock::from_bytes(&sig.id_block).ok_or_else(|| anyhow!("Invalid SEV ID block"))?;
+    let id_auth = IdAuth::from_bytes(&sig.id_auth).ok_or_else(|| anyhow!("Invalid SEV ID auth"))?;
+    ensure!(
+        id_auth.id_key_algo == SEV_ALGO_ECDSA_P384_SHA384
+            && id_auth.auth_key_algo == SEV_ALGO_ECDSA_P384_SHA384,
+        "Unsupported SEV signature algorithm"
+    );
+
+    let id_key = sev_key(&id_auth.id_key).context("Invalid SEV ID key")?;
+    sev_verify(&id_key, id_block.as_bytes(), &id_auth.id_block_sig)
+        .context("Invalid SEV ID block signature")?;
+
+    if id_auth.has_author_key() {
+        let author_key = sev_key(&id_auth.author_key).context("Invalid SEV author key")?;
+        sev_verify(&author_key, id_auth.id_key.as_bytes(), &id_auth.id_key_sig)
+            .context("Invalid SEV ID key signature")?;
+    }
+    Ok((id_block, id_auth))
+}
+
+impl Options {
+    pub fn execute(self) -> anyhow::Result<()> {
+        use mmarinus::{perms, Map, Private};
+        let binary = if let Some(ref path) = self.binpath {
+            Some(Map::load(&path, Private, perms::Read)?)
+        } else {
+            None
+        };
+        let signatures = Signatures::load(Some(self.signatures))?
+            .ok_or_else(|| anyhow!("Missing signature file"))?;
+
+        if let Some(ref name) = self.backend {
+            if !["sgx", "sev"].contains(&name.as_str()) {
+                bail!("Keep backend identifier {:?} is not signed.", name);
+            }
+        }
+
+        let mut verified = 0;
+        for backend in BACKENDS.deref().iter() {
+            let backend: &dyn Backend = backend.deref();
+
+            let signed = match backend.name() {
+                "sgx" => !signatures.sgx.is_empty(),
+                "sev" => !signatures.sev.id_block.is_empty(),
+                _ => continue,
+            };
+            match self.backend {
+                Some(ref name) if name != backend.name() => continue,
+                Some(_) if !signed => bail!("No {} signature", backend.name()),
+                None if !signed => continue,
+                _ => {}
+            }
+
+            let exec = if let Some(ref e) = binary {
+                e.as_ref()
+            } else if let Some(e) = EXECS.iter().find(|w| w.with_backend(backend)) {
+                e.exec()
+            } else {
+                bail!("No exec for the {} backend", backend.name());
+            };
+            ensure!(
+                !backend.shim().is_empty() && !exec.is_empty(),
+                "The {} keep payload is not compiled in",
+                backend.name()
+            );
+            let blob = backend
+                .hash(backend.shim().as_ref(), exec.as_ref())
+                .with_context(|| format!("Failed to measure the {} keep", backend.name()))?;
+
+            match backend.name() {
+                "sgx" => {
+                    let sig = verify_sgx(&signatures.sgx)?;
+                    let body = Body::from_bytes(&blob)
+                        .ok_or_else(|| anyhow!("Invalid SGX measurement"))?;
+                    // The remaining fields of the body are chosen by the signer.
+                    ensure!(
+                        sig.body().mrenclave() == body.mrenclave(),
+                        "SGX signature does not cover the keep with MRENCLAVE {}",
+                        hex(body.mrenclave())
+                    );
+                    let modulus = &sig.as_bytes()[128..512];
+                    println!(
+                        "sgx: valid signature of MRENCLAVE {} by MRSIGNER {}",
+                        hex(sig.body().mrenclave()),
+                        hex(digest(&SHA256, modulus))
+                    );
+                }
+                _ => {
+                    let (id_block, id_auth) = verify_sev(&signatures.sev)?;
+                    let measured = IdBlock::from_bytes(&blob)
+                        .ok_or_else(|| anyhow!("Invalid SEV measurement"))?;
+                    // The remaining fields of the ID block are chosen by the signer.
+                    ensure!(
+                        id_block.launch_digest == measured.launch_digest,
+                        "SEV signature does not cover the keep with launch digest {}",
+                        hex(measured.launch_digest)
+                    );
+                    let author = match id_auth.has_author_key() {
+                        true => hex(digest(&SHA384, id_auth.author_key.as_bytes())),
+                        false => "none".into(),
+                    };
+                    println!(
+                        "sev: valid signature of launch digest {} by ID key {} and author key {}",
+                        hex(id_block.launch_digest),
+                        hex(digest(&SHA384, id_auth.id_key.as_bytes())),
+                        author
+                    );
+                }
+            }
+            verified += 1;
+        }
+
+        ensure!(verified > 0, "No signatures to verify");
+        Ok(())
+    }
+}
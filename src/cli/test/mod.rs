@@ -0,0 +1,19 @@
+// SPDX-License-Identifier: Apache-2.0
+
+mod wasi;
+
+use clap::Subcommand;
+
+/// Commands for testing what workloads can do inside an Enarx Keep.
+#[derive(Subcommand, Debug)]
+pub enum Subcommands {
+    Wasi(wasi::Options),
+}
+
+impl Subcommands {
+    pub fn dispatch(self) -> anyhow::Result<()> {
+        match self {
+            Self::Wasi(cmd) => cmd.execute(),
+        }
+    }
+}
@@ -0,0 +1,755 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::backend::Signatures;
+use crate::cli::rundev::anonymous;
+use crate::cli::BackendOptions;
+use crate::exec::{log_files, read_config, run_package, RunOptions, EXECS};
+
+use std::fmt::Debug;
+use std::fs::File;
+use std::os::unix::io::IntoRawFd;
+
+use anyhow::{anyhow, Context};
+use camino::Utf8PathBuf;
+use clap::Args;
+use enarx_exec_wasmtime::Package;
+use wasm_encoder::{
+    CodeSection, ConstExpr, DataSection, EntityType, ExportKind, ExportSection, Function,
+    FunctionSection, ImportSection, Instruction, MemArg, MemorySection, MemoryType, Module,
+    TypeSection, ValType,
+};
+
+/// Test which WASI calls work inside an Enarx Keep.
+///
+/// Runs a module calling every WASI function with fixed arguments inside a
+/// keep, which prints a Markdown table of the calls and the errno each of them
+/// returned. The module is generated from the table of calls in this command,
+/// and the table published in `docs/Running/WASI.md` is its output with the
+/// default config, so the two never drift apart.
+///
+/// File descriptors 0, 1 and 2 are the standard streams of the default config,
+/// pass `--wasmcfgfile` to test the calls against the files of a config.
+#[derive(Args, Debug)]
+pub struct Options {
+    #[clap(flatten)]
+    pub backend: BackendOptions,
+
+    /// Path of the Enarx.toml to run the calls under, by default a config with the standard streams is used
+    #[clap(long, env = "ENARX_WASMCFGFILE")]
+    pub wasmcfgfile: Option<Utf8PathBuf>,
+
+    /// Path of the signature file to use.
+    #[clap(long, value_name = "SIGNATURES")]
+    pub signatures: Option<Utf8PathBuf>,
+
+    /// gdb options
+    #[cfg(feature = "gdb")]
+    #[clap(long, default_value = "localhost:23456")]
+    pub gdblisten: String,
+}
+
+impl Options {
+    pub fn execute(self) -> anyhow::Result<()> {
+        let Self {
+            backend,
+            wasmcfgfile,
+            signatures,
+            #[cfg(feature = "gdb")]
+            gdblisten,
+        } = self;
+        let backend = backend.pick()?;
+        let exec = EXECS
+            .iter()
+            .find(|w| w.with_backend(backend))
+            .ok_or_else(|| anyhow!("no supported exec found"))
+            .map(|b| b.exec())?;
+
+        let signatures = Signatures::load(signatures)?;
+
+        let config = wasmcfgfile.as_ref().map(read_config).transpose()?;
+        let env_host = config
+            .as_ref()
+            .map(|config| config.env_host.clone())
+            .unwrap_or_default();
+        let log_files = config.as_ref().map(log_files).unwrap_or_default();
+
+        let get_pkg = || {
+            let wasm = anonymous("wasi.wasm", &module()).context("failed to write module")?;
+            let conf = wasmcfgfile
+                .map(|path| {
+                    File::open(&path)
+                        .with_context(|| format!("failed to open package config at `{path}`"))
+                })
+                .transpose()?;
+            Ok(Package::Local {
+                wasm: wasm.into_raw_fd(),
+                conf: conf.map(|conf| conf.into_raw_fd()),
+                modules: Default::default(),
+                data: Default::default(),
+            })
+        };
+
+        let options = RunOptions {
+            signatures,
+            #[cfg(feature = "gdb")]
+            gdblisten: Some(gdblisten),
+            env_host,
+            log_files,
+            ..Default::default()
+        };
+        let code = run_package(backend, exec, options, get_pkg)?;
+        std::process::exit(code);
+    }
+}
+
+/// Argument of a WASI call
+#[derive(Clone, Copy, Debug)]
+enum Arg {
+    I32(i32),
+    I64(i64),
+}
+
+use Arg::{I32, I64};
+
+/// A WASI call made by the module
+struct Call {
+    /// Name of the function in `wasi_snapshot_preview1`
+    name: &'static str,
+
+    /// Description of the arguments
+    desc: &'static str,
+
+    /// Arguments passed to the function
+    args: &'static [Arg],
+}
+
+/// Results of the calls, which fit into 64 bytes
+const OUT: i32 = 0;
+/// An empty `iovec`
+const IOV: i32 = 64;
+/// A `subscription` to the monotonic clock, which expires immediately
+const SUB: i32 = 128;
+/// The `event` of [`SUB`]
+const EVT: i32 = 192;
+/// A 256 byte buffer
+const BUF: i32 = 256;
+/// The path `probe`
+const PATH: i32 = 512;
+/// The path `probe2`
+const PATH2: i32 = 528;
+/// The `iovec`s of the written rows
+const ROW: i32 = 576;
+/// The number of bytes written
+const WRITTEN: i32 = 608;
+/// The lengths of the errno names
+const LENS: i32 = 1024;
+/// The errno names in 16 byte slots
+const NAMES: i32 = 2048;
+/// The text of the rows
+const TEXT: i32 = 4096;
+/// The page receiving the arguments
+const ARGS: i32 = 65536;
+/// The page receiving the environment variables
+const ENVIRON: i32 = 131072;
+
+/// The names of the WASI errnos, followed by the name of unknown ones
+const ERRNOS: &[&str] = &[
+    "ESUCCESS",
+    "E2BIG",
+    "EACCES",
+    "EADDRINUSE",
+    "EADDRNOTAVAIL",
+    "EAFNOSUPPORT",
+    "EAGAIN",
+    "EALREADY",
+    "EBADF",
+    "EBADMSG",
+    "EBUSY",
+    "ECANCELED",
+    "ECHILD",
+    "ECONNABORTED",
+    "ECONNREFUSED",
+    "ECONNRESET",
+    "EDEADLK",
+    "EDESTADDRREQ",
+    "EDOM",
+    "EDQUOT",
+    "EEXIST",
+    "EFAULT",
+    "EFBIG",
+    "EHOSTUNREACH",
+    "EIDRM",
+    "EILSEQ",
+    "EINPROGRESS",
+    "EINTR",
+    "EINVAL",
+    "EIO",
+    "EISCONN",
+    "EISDIR",
+    "ELOOP",
+    "EMFILE",
+    "EMLINK",
+    "EMSGSIZE",
+    "EMULTIHOP",
+    "ENAMETOOLONG",
+    "ENETDOWN",
+    "ENETRESET",
+    "ENETUNREACH",
+    "ENFILE",
+    "ENOBUFS",
+    "ENODEV",
+    "ENOENT",
+    "ENOEXEC",
+    "ENOLCK",
+    "ENOLINK",
+    "ENOMEM",
+    "ENOMSG",
+    "ENOPROTOOPT",
+    "ENOSPC",
+    "ENOSYS",
+    "ENOTCONN",
+    "ENOTDIR",
+    "ENOTEMPTY",
+    "ENOTRECOVERABLE",
+    "ENOTSOCK",
+    "ENOTSUP",
+    "ENOTTY",
+    "ENXIO",
+    "EOVERFLOW",
+    "EOWNERDEAD",
+    "EPERM",
+    "EPIPE",
+    "EPROTO",
+    "EPROTONOSUPPORT",
+    "EPROTOTYPE",
+    "ERANGE",
+    "EROFS",
+    "ESPIPE",
+    "ESRCH",
+    "ESTALE",
+    "ETIMEDOUT",
+    "ETXTBSY",
+    "EXDEV",
+    "ENOTCAPABLE",
+    "unknown",
+];
+
+/// The calls made by the module in order
+///
+/// Calls with side effects on the standard streams come last, so that the
+/// table is still written to standard output.
+const CALLS: &[Call] = &[
+    Call {
+        name: "args_sizes_get",
+        desc: "",
+        args: &[I32(OUT), I32(OUT + 4)],
+    },
+    Call {
+        name: "args_get",
+        desc: "",
+        args: &[I32(ARGS), I32(ARGS + 4096)],
+    },
+    Call {
+        name: "environ_sizes_get",
+        desc: "",
+        args: &[I32(OUT), I32(OUT + 4)],
+    },
+    Call {
+        name: "environ_get",
+        desc: "",
+        args: &[I32(ENVIRON), I32(ENVIRON + 8192)],
+    },
+    Call {
+        name: "clock_res_get",
+        desc: "realtime clock",
+        args: &[I32(0), I32(OUT)],
+    },
+    Call {
+        name: "clock_res_get",
+        desc: "monotonic clock",
+        args: &[I32(1), I32(OUT)],
+    },
+    Call {
+        name: "clock_res_get",
+        desc: "process CPU time clock",
+        args: &[I32(2), I32(OUT)],
+    },
+    Call {
+        name: "clock_res_get",
+        desc: "thread CPU time clock",
+        args: &[I32(3), I32(OUT)],
+    },
+    Call {
+        name: "clock_time_get",
+        desc: "realtime clock",
+        args: &[I32(0), I64(0), I32(OUT)],
+    },
+    Call {
+        name: "clock_time_get",
+        desc: "monotonic clock",
+        args: &[I32(1), I64(0), I32(OUT)],
+    },
+    Call {
+        name: "clock_time_get",
+        desc: "process CPU time clock",
+        args: &[I32(2), I64(0), I32(OUT)],
+    },
+    Call {
+        name: "clock_time_get",
+        desc: "thread CPU time clock",
+        args: &[I32(3), I64(0), I32(OUT)],
+    },
+    Call {
+        name: "fd_fdstat_get",
+        desc: "fd 0",
+        args: &[I32(0), I32(OUT)],
+    },
+    Call {
+        name: "fd_fdstat_get",
+        desc: "fd 1",
+        args: &[I32(1), I32(OUT)],
+    },
+    Call {
+        name: "fd_fdstat_get",
+        desc: "fd 2",
+        args: &[I32(2), I32(OUT)],
+    },
+    Call {
+        name: "fd_fdstat_get",
+        desc: "fd 3",
+        args: &[I32(3), I32(OUT)],
+    },
+    Call {
+        name: "fd_fdstat_set_flags",
+        desc: "fd 1, no flags",
+        args: &[I32(1), I32(0)],
+    },
+    Call {
+        name: "fd_filestat_get",
+        desc: "fd 0",
+        args: &[I32(0), I32(OUT)],
+    },
+    Call {
+        name: "fd_filestat_get",
+        desc: "fd 1",
+        args: &[I32(1), I32(OUT)],
+    },
+    Call {
+        name: "fd_filestat_get",
+        desc: "fd 2",
+        args: &[I32(2), I32(OUT)],
+    },
+    Call {
+        name: "fd_filestat_get",
+        desc: "fd 3",
+        args: &[I32(3), I32(OUT)],
+    },
+    Call {
+        name: "fd_filestat_set_size",
+        desc: "fd 0, size 0",
+        args: &[I32(0), I64(0)],
+    },
+    Call {
+        name: "fd_filestat_set_times",
+        desc: "fd 0, unchanged times",
+        args: &[I32(0), I64(0), I64(0), I32(0)],
+    },
+    Call {
+        name: "fd_advise",
+        desc: "fd 0, normal",
+        args: &[I32(0), I64(0), I64(0), I32(0)],
+    },
+    Call {
+        name: "fd_allocate",
+        desc: "fd 0, 0 bytes",
+        args: &[I32(0), I64(0), I64(0)],
+    },
+    Call {
+        name: "fd_read",
+        desc: "fd 0, empty buffer",
+        args: &[I32(0), I32(IOV), I32(1), I32(OUT)],
+    },
+    Call {
+        name: "fd_pread",
+        desc: "fd 0, empty buffer at offset 0",
+        args: &[I32(0), I32(IOV), I32(1), I64(0), I32(OUT)],
+    },
+    Call {
+        name: "fd_write",
+        desc: "fd 1, empty buffer",
+        args: &[I32(1), I32(IOV), I32(1), I32(OUT)],
+    },
+    Call {
+        name: "fd_pwrite",
+        desc: "fd 1, empty buffer at offset 0",
+        args: &[I32(1), I32(IOV), I32(1), I64(0), I32(OUT)],
+    },
+    Call {
+        name: "fd_seek",
+        desc: "fd 0, 0 bytes from the current position",
+        args: &[I32(0), I64(0), I32(1), I32(OUT)],
+    },
+    Call {
+        name: "fd_tell",
+        desc: "fd 0",
+        args: &[I32(0), I32(OUT)],
+    },
+    Call {
+        name: "fd_sync",
+        desc: "fd 1",
+        args: &[I32(1)],
+    },
+    Call {
+        name: "fd_datasync",
+        desc: "fd 1",
+        args: &[I32(1)],
+    },
+    Call {
+        name: "fd_prestat_get",
+        desc: "fd 3",
+        args: &[I32(3), I32(OUT)],
+    },
+    Call {
+        name: "fd_prestat_dir_name",
+        desc: "fd 3",
+        args: &[I32(3), I32(BUF), I32(256)],
+    },
+    Call {
+        name: "fd_readdir",
+        desc: "fd 3",
+        args: &[I32(3), I32(BUF), I32(256), I64(0), I32(OUT)],
+    },
+    Call {
+        name: "path_create_directory",
+        desc: "fd 3, `probe`",
+        args: &[I32(3), I32(PATH), I32(5)],
+    },
+    Call {
+        name: "path_filestat_get",
+        desc: "fd 3, `probe`",
+        args: &[I32(3), I32(0), I32(PATH), I32(5), I32(OUT)],
+    },
+    Call {
+        name: "path_filestat_set_times",
+        desc: "fd 3, `probe`, unchanged times",
+        args: &[I32(3), I32(0), I32(PATH), I32(5), I64(0), I64(0), I32(0)],
+    },
+    Call {
+        name: "path_link",
+        desc: "fd 3, `probe` to `probe2`",
+        args: &[
+            I32(3),
+            I32(0),
+            I32(PATH),
+            I32(5),
+            I32(3),
+            I32(PATH2),
+            I32(6),
+        ],
+    },
+    Call {
+        name: "path_open",
+        desc: "fd 3, `probe`",
+        args: &[
+            I32(3),
+            I32(0),
+            I32(PATH),
+            I32(5),
+            I32(0),
+            I64(0),
+            I64(0),
+            I32(0),
+            I32(OUT),
+        ],
+    },
+    Call {
+        name: "path_readlink",
+        desc: "fd 3, `probe`",
+        args: &[I32(3), I32(PATH), I32(5), I32(BUF), I32(256), I32(OUT)],
+    },
+    Call {
+        name: "path_remove_directory",
+        desc: "fd 3, `probe`",
+        args: &[I32(3), I32(PATH), I32(5)],
+    },
+    Call {
+        name: "path_rename",
+        desc: "fd 3, `probe` to `probe2`",
+        args: &[I32(3), I32(PATH), I32(5), I32(3), I32(PATH2), I32(6)],
+    },
+    Call {
+        name: "path_symlink",
+        desc: "fd 3, `probe` to `probe2`",
+        args: &[I32(PATH), I32(5), I32(3), I32(PATH2), I32(6)],
+    },
+    Call {
+        name: "path_unlink_file",
+        desc: "fd 3, `probe`",
+        args: &[I32(3), I32(PATH), I32(5)],
+    },
+    Call {
+        name: "poll_oneoff",
+        desc: "monotonic clock, expired",
+        args: &[I32(SUB), I32(EVT), I32(1), I32(OUT)],
+    },
+    Call {
+        name: "sched_yield",
+        desc: "",
+        args: &[],
+    },
+    Call {
+        name: "random_get",
+        desc: "256 bytes",
+        args: &[I32(BUF), I32(256)],
+    },
+    Call {
+        name: "sock_accept",
+        desc: "fd 0",
+        args: &[I32(0), I32(0), I32(OUT)],
+    },
+    Call {
+        name: "sock_recv",
+        desc: "fd 0, empty buffer",
+        args: &[I32(0), I32(IOV), I32(1), I32(0), I32(OUT), I32(OUT + 4)],
+    },
+    Call {
+        name: "sock_send",
+        desc: "fd 1, empty buffer",
+        args: &[I32(1), I32(IOV), I32(1), I32(0), I32(OUT)],
+    },
+    Call {
+        name: "sock_shutdown",
+        desc: "fd 0, reading",
+        args: &[I32(0), I32(1)],
+    },
+    Call {
+        name: "fd_renumber",
+        desc: "fd 0 to fd 0",
+        args: &[I32(0), I32(0)],
+    },
+    Call {
+        name: "fd_fdstat_set_rights",
+        desc: "fd 0, no rights",
+        args: &[I32(0), I64(0), I64(0)],
+    },
+    Call {
+        name: "fd_close",
+        desc: "fd 0",
+        args: &[I32(0)],
+    },
+];
+
+/// The functions the module does not call, since they do not return
+const UNCALLED: &str = "\
+| `proc_exit` | | exits the module |
+| `proc_raise` | | traps |
+";
+
+/// The header of the table
+const HEADER: &str = "\
+| Function | Arguments | Result |
+| --- | --- | --- |
+";
+
+/// Returns the instructions writing the text at `ptr` with `len` bytes to standard output.
+fn write(fd_write: u32, ptr: i32, len: i32) -> Vec<Instruction<'static>> {
+    vec![
+        Instruction::I32Const(ROW),
+        Instruction::I32Const(ptr),
+        Instruction::I32Store(mem(0)),
+        Instruction::I32Const(ROW),
+        Instruction::I32Const(len),
+        Instruction::I32Store(mem(4)),
+        Instruction::I32Const(1),
+        Instruction::I32Const(ROW),
+        Instruction::I32Const(1),
+        Instruction::I32Const(WRITTEN),
+        Instruction::Call(fd_write),
+        Instruction::Drop,
+    ]
+}
+
+fn mem(offset: u64) -> MemArg {
+    MemArg {
+        offset,
+        align: 2,
+        memory_index: 0,
+    }
+}
+
+/// Returns the module making the [`CALLS`] and printing their results.
+fn module() -> Vec<u8> {
+    let mut names: Vec<&str> = vec![];
+    for name in CALLS.iter().map(|c| c.name).chain(["fd_write"]) {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    let index = |name| names.iter().position(|&n| n == name).unwrap() as u32;
+    let fd_write = index("fd_write");
+
+    let mut types = TypeSection::new();
+    let mut imports = ImportSection::new();
+    for (i, &name) in names.iter().enumerate() {
+        let call = CALLS.iter().find(|c| c.name == name).unwrap();
+        let params = call.args.iter().map(|arg| match arg {
+            I32(..) => ValType::I32,
+            I64(..) => ValType::I64,
+        });
+        types.function(params, [ValType::I32]);
+        imports.import(
+            "wasi_snapshot_preview1",
+            name,
+            EntityType::Function(i as u32),
+        );
+    }
+    let row = names.len() as u32;
+    types.function([ValType::I32; 3], []);
+    let start = row + 1;
+    types.function([], []);
+
+    let mut functions = FunctionSection::new();
+    functions.function(row);
+    functions.function(start);
+
+    let mut memories = MemorySection::new();
+    memories.memory(MemoryType {
+        minimum: 3,
+        maximum: None,
+        memory64: false,
+        shared: false,
+    });
+
+    let mut exports = ExportSection::new();
+    exports.export("memory", ExportKind::Memory, 0);
+    exports.export("_start", ExportKind::Func, start);
+
+    // Write the text of a row at (param 0, param 1) followed by the errno (param 2).
+    let mut row_fn = Function::new([(1, ValType::I32)]);
+    for instruction in [
+        Instruction::I32Const(ROW),
+        Instruction::LocalGet(0),
+        Instruction::I32Store(mem(0)),
+        Instruction::I32Const(ROW),
+        Instruction::LocalGet(1),
+        Instruction::I32Store(mem(4)),
+        // Map unknown errnos to the last name.
+        Instruction::LocalGet(2),
+        Instruction::I32Const(ERRNOS.len() as i32 - 1),
+        Instruction::LocalGet(2),
+        Instruction::I32Const(ERRNOS.len() as i32 - 1),
+        Instruction::I32LtU,
+        Instruction::Select,
+        Instruction::LocalSet(3),
+        Instruction::I32Const(ROW),
+        Instruction::LocalGet(3),
+        Instruction::I32Const(16),
+        Instruction::I32Mul,
+        Instruction::I32Const(NAMES),
+        Instruction::I32Add,
+        Instruction::I32Store(mem(8)),
+        Instruction::I32Const(ROW),
+        Instruction::LocalGet(3),
+        Instruction::I32Const(4),
+        Instruction::I32Mul,
+        Instruction::I32Load(mem(LENS as u64)),
+        Instruction::I32Store(mem(12)),
+        Instruction::I32Const(1),
+        Instruction::I32Const(ROW),
+        Instruction::I32Const(3),
+        Instruction::I32Const(WRITTEN),
+        Instruction::Call(fd_write),
+        Instruction::Drop,
+        Instruction::End,
+    ] {
+        row_fn.instruction(&instruction);
+    }
+
+    // Lay out the text of the rows and make the calls.
+    let mut text = String::new();
+    let mut push = |s: &str| {
+        let ptr = TEXT + text.len() as i32;
+        text.push_str(s);
+        (ptr, s.len() as i32)
+    };
+    let (suffix, _) = push(" |\n");
+    let mut start_fn = Function::new([]);
+    let (ptr, len) = push(HEADER);
+    for instruction in write(fd_write, ptr, len) {
+        start_fn.instruction(&instruction);
+    }
+    for call in CALLS {
+        let (ptr, len) = match call.desc {
+            "" => push(&format!("| `{}` | | ", call.name)),
+            desc => push(&format!("| `{}` | {desc} | ", call.name)),
+        };
+        start_fn.instruction(&Instruction::I32Const(ptr));
+        start_fn.instruction(&Instruction::I32Const(len));
+        for arg in call.args {
+            start_fn.instruction(&match *arg {
+                I32(value) => Instruction::I32Const(value),
+                I64(value) => Instruction::I64Const(value),
+            });
+        }
+        start_fn.instruction(&Instruction::Call(index(call.name)));
+        start_fn.instruction(&Instruction::Call(row));
+    }
+    let (ptr, len) = push(UNCALLED);
+    for instruction in write(fd_write, ptr, len) {
+        start_fn.instruction(&instruction);
+    }
+    start_fn.instruction(&Instruction::End);
+
+    let mut code = CodeSection::new();
+    code.function(&row_fn);
+    code.function(&start_fn);
+
+    let mut data = DataSection::new();
+    let mut lens = vec![];
+    let mut slots = vec![];
+    for name in ERRNOS {
+        lens.extend((name.len() as u32).to_le_bytes());
+        let mut slot = [0; 16];
+        slot[..name.len()].copy_from_slice(name.as_bytes());
+        slots.extend(slot);
+    }
+    data.active(0, &ConstExpr::i32_const(LENS), lens);
+    data.active(0, &ConstExpr::i32_const(NAMES), slots);
+    data.active(0, &ConstExpr::i32_const(TEXT), text.into_bytes());
+    data.active(0, &ConstExpr::i32_const(PATH), *b"probe");
+    data.active(0, &ConstExpr::i32_const(PATH2), *b"probe2");
+    // The monotonic clock of the subscription.
+    data.active(0, &ConstExpr::i32_const(SUB + 16), 1u32.to_le_bytes());
+    // The errno of each row is followed by the suffix.
+    let mut iovec = suffix.to_le_bytes().to_vec();
+    iovec.extend(3u32.to_le_bytes());
+    data.active(0, &ConstExpr::i32_const(ROW + 16), iovec);
+
+    let mut module = Module::new();
+    module.section(&types);
+    module.section(&imports);
+    module.section(&functions);
+    module.section(&memories);
+    module.section(&exports);
+    module.section(&code);
+    module.section(&data);
+    module.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{module, ERRNOS};
+
+    #[test]
+    fn valid() {
+        wasmparser::validate(&module()).unwrap();
+    }
+
+    #[test]
+    fn errnos() {
+        assert_eq!(ERRNOS[8], "EBADF");
+        assert_eq!(ERRNOS[52], "ENOSYS");
+        assert_eq!(ERRNOS[76], "ENOTCAPABLE");
+        assert!(ERRNOS.iter().all(|name| name.len() <= 16));
+    }
+}
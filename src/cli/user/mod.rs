@@ -4,6 +4,7 @@ mod info;
 mod login;
 mod logout;
 mod register;
+mod token;
 
 use clap::Subcommand;
 
@@ -16,6 +17,7 @@ pub enum Subcommands {
     #[clap(hide = true)]
     Logout(logout::Options),
     Register(register::Options),
+    Token(Box<token::Options>),
 }
 
 impl Subcommands {
@@ -25,6 +27,7 @@ impl Subcommands {
             Self::Login(cmd) => cmd.execute(),
             Self::Logout(cmd) => cmd.execute(),
             Self::Register(cmd) => cmd.execute(),
+            Self::Token(cmd) => cmd.execute(),
         }
     }
 }
@@ -0,0 +1,59 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::drawbridge::{client, device_token, RepoSpec};
+
+use std::ffi::OsString;
+
+use anyhow::Context;
+use camino::Utf8PathBuf;
+use clap::Args;
+use oauth2::url::Url;
+
+/// Print an access token of the user to stdout.
+///
+/// The token is obtained by logging in interactively and printed without
+/// saving it locally, such that it can be passed to non-interactive
+/// environments like CI, e.g. to `enarx package publish` via
+/// `ENARX_INSECURE_AUTH_TOKEN`. The token expires like the credentials saved
+/// by `enarx user login`.
+///
+/// The token is not scoped to a repository: it grants the same access as the
+/// user, i.e. to all of their repositories, until it expires, so store it as
+/// a secret.
+#[derive(Args, Debug)]
+pub struct Options {
+    #[clap(long, env = "ENARX_CA_BUNDLE")]
+    ca_bundle: Option<Utf8PathBuf>,
+    #[clap(long, default_value = "https://auth.profian.com/")]
+    oidc_domain: Url,
+    #[clap(long, default_value = "4NuaJxkQv8EZBeJKE56R57gKJbxrTLG2")]
+    oidc_client_id: String,
+    /// Repository to check the token to grant access to before printing it.
+    #[clap(long)]
+    check: Option<RepoSpec>,
+}
+
+impl Options {
+    pub fn execute(self) -> anyhow::Result<()> {
+        let token = device_token(&self.oidc_domain, self.oidc_client_id)?;
+
+        if let Some(spec) = self.check {
+            let cl = client(
+                &spec.host,
+                &self.oidc_domain,
+                &Some(token.clone()),
+                &self.ca_bundle,
+                &None::<OsString>,
+            )?;
+            cl.repository(&spec.ctx)
+                .get()
+                .context("Failed to access repository with the token")?;
+        }
+
+        eprintln!(
+            "Warning: the token grants access to all repositories of the user until it expires."
+        );
+        println!("{token}");
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::exec::hex;
+use crate::verify::{self, certificate, certificates, snp};
+
+use std::fs;
+use std::io::Read;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use camino::Utf8PathBuf;
+use clap::Args;
+use serde::Serialize;
+
+/// Verify the attestation evidence of a keep offline.
+///
+/// The evidence is an SGX quote or the SEV-SNP attestation report with the VCEK certificate, as
+/// yielded by the `attestation` file of a keep. Its signatures are verified up to the trusted root
+/// certificate of the vendor and its measurement is compared with the expected one, as printed by
+/// `enarx measure`. The claims are printed in JSON. The TCB info, QE identity and CRLs of the
+/// vendor are not checked, which is reported as `"verification": "signature_only"`.
+#[derive(Args, Debug)]
+pub struct Options {
+    /// Path of the evidence to verify
+    #[clap(value_name = "EVIDENCE")]
+    pub evidence: Utf8PathBuf,
+
+    /// Expected hex-encoded SGX `MRENCLAVE` or SEV-SNP `MEASUREMENT` of the keep
+    #[clap(long, value_name = "HEX")]
+    pub measurement: String,
+
+    /// Expected hex-encoded SGX `MRSIGNER` or SEV-SNP `ID_KEY_DIGEST` of the keep
+    #[clap(long, value_name = "HEX")]
+    pub signer: Option<String>,
+
+    /// Expected hex-encoded data the evidence is bound to, padded with zeros to 64 bytes
+    #[clap(long, value_name = "HEX")]
+    pub report_data: Option<String>,
+
+    /// PEM-encoded SEV-SNP certificate chain of the ASK and the ARK, fetched from AMD if not specified
+    #[clap(long, value_name = "FILE")]
+    pub chain: Option<Utf8PathBuf>,
+
+    /// Trusted root certificate, the ARK pinned for the product line of the processor by default
+    /// for SEV-SNP and required for SGX
+    #[clap(long, value_name = "FILE")]
+    pub root: Option<Utf8PathBuf>,
+
+    /// Accept evidence of keeps, which can be debugged
+    #[clap(long)]
+    pub allow_debug: bool,
+}
+
+/// The verified claims of the evidence
+#[derive(Serialize, Debug)]
+struct Claims {
+    technology: &'static str,
+    measurement: String,
+    signer: String,
+    report_data: String,
+    debug: bool,
+    /// `"verified"` or `"signature_only"`, if any collateral was not checked
+    verification: &'static str,
+    /// The collateral, which was not checked
+    unchecked: &'static [&'static str],
+}
+
+/// Returns the bytes encoded in `hex`.
+fn unhex(hex: &str) -> Result<Vec<u8>> {
+    ensure!(hex.len() % 2 == 0, "invalid hex string {:?}", hex);
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+                .ok_or_else(|| anyhow!("invalid hex string {:?}", hex))
+        })
+        .collect()
+}
+
+fn read(path: &Utf8PathBuf) -> Result<Vec<u8>> {
+    fs::read(path).with_context(|| format!("Failed to read `{path}`"))
+}
+
+impl Options {
+    /// Returns the certificate chain of the VCEK of a `product` processor, fetching it from AMD if
+    /// not specified.
+    fn snp_chain(&self, product: &str) -> Result<Vec<Vec<u8>>> {
+        let pem = match self.chain {
+            Some(ref chain) => read(chain)?,
+            None => {
+                let url = snp::chain_url(product);
+                let mut pem = Vec::new();
+                ureq::get(&url)
+                    .call()
+                    .with_context(|| {
+                        format!("Failed to fetch the VCEK certificate chain from {url}")
+                    })?
+                    .into_reader()
+                    .read_to_end(&mut pem)?;
+                pem
+            }
+        };
+        certificates(&pem).context("Invalid VCEK certificate chain")
+    }
+
+    pub fn execute(self) -> Result<()> {
+        let evidence = read(&self.evidence)?;
+        let root = self.root.as_ref().map(read).transpose()?;
+        let root = root.as_deref().map(certificate).transpose()?;
+
+        let verified = if evidence.first() == Some(&0x30) {
+            // The chain is supplied by the host or fetched in the clear, so only a pinned ARK or
+            // the given root is trusted.
+            let product = snp::product(&evidence)?;
+            let chain = self.snp_chain(&product)?;
+            let root = match root {
+                Some(root) => root,
+                None => snp::ark(&product)?.ok_or_else(|| {
+                    anyhow!("No ARK of AMD {product} processors is pinned, pass it with `--root`")
+                })?,
+            };
+            verify::verify(&evidence, &chain, &root)?
+        } else {
+            let root = root.ok_or_else(|| {
+                anyhow!("The root CA certificate of Intel is required to verify SGX evidence")
+            })?;
+            verify::verify(&evidence, &[], &root)?
+        };
+
+        if unhex(&self.measurement)? != verified.measurement {
+            bail!(
+                "The measurement {} does not match the expected one",
+                hex(&verified.measurement)
+            );
+        }
+        if let Some(ref signer) = self.signer {
+            if unhex(signer)? != verified.signer {
+                bail!(
+                    "The signer {} does not match the expected one",
+                    hex(&verified.signer)
+                );
+            }
+        }
+        if let Some(ref data) = self.report_data {
+            let mut expected = unhex(data)?;
+            ensure!(expected.len() <= 64, "The report data exceeds 64 bytes");
+            expected.resize(64, 0);
+            if expected != verified.report_data {
+                bail!(
+                    "The report data {} does not match the expected one",
+                    hex(&verified.report_data)
+                );
+            }
+        }
+        if verified.debug && !self.allow_debug {
+            bail!("The keep can be debugged, its memory is not confidential");
+        }
+
+        let claims = Claims {
+            technology: verified.technology,
+            measurement: hex(&verified.measurement),
+            signer: hex(&verified.signer),
+            report_data: hex(&verified.report_data),
+            debug: verified.debug,
+            verification: verified.status(),
+            unchecked: verified.unchecked,
+        };
+        println!("{}", serde_json::to_string_pretty(&claims)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::unhex;
+
+    #[test]
+    fn hex() {
+        assert_eq!(unhex("00ff1A").unwrap(), [0x00, 0xff, 0x1a]);
+        assert!(unhex("0").is_err());
+        assert!(unhex("zz").is_err());
+        assert!(unhex("é0").is_err());
+    }
+}
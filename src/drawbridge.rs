@@ -20,7 +20,7 @@ use oauth2::url::Url;
 use oauth2::{AuthType, AuthUrl, ClientId, DeviceAuthorizationUrl, Scope, TokenResponse, TokenUrl};
 use rustls::{Certificate, RootCertStore};
 
-const DEFAULT_HOST: &str = "store.profian.com";
+pub const DEFAULT_HOST: &str = "store.profian.com";
 
 #[derive(Debug)]
 pub struct UserSpec {
@@ -177,10 +177,12 @@ pub fn client(
     Ok(cl)
 }
 
-pub fn login(
+/// Obtains an access token with the OAuth 2.0 device authorization flow, which the user
+/// completes in a browser. The instructions are printed to stderr, such that stdout only
+/// contains the output of the command.
+pub fn device_token(
     oidc_domain: &impl Borrow<Url>,
     oidc_client_id: String,
-    helper: &Option<impl AsRef<OsStr>>,
 ) -> anyhow::Result<String> {
     let oidc_domain = oidc_domain.borrow();
     let dev_auth_url = DeviceAuthorizationUrl::new(format!("{oidc_domain}oauth/device/code"))
@@ -206,7 +208,7 @@ pub fn login(
         .request(http_client)
         .context("Failed to request device code")?;
 
-    println!(
+    eprintln!(
         "To continue, open the following link in your browser:\n\
          \t{}\n\
          At the prompt, enter the following one-time code:\n\
@@ -220,13 +222,20 @@ pub fn login(
         .exchange_device_access_token(&details)
         .request(http_client, poll_delay, None)
         .context("Failed to exchange device code for a token")?;
-
     // TODO: graceful timeout, so that users are not forced to Ctrl+C if the server errors
+    Ok(res.access_token().secret().clone())
+}
+
+pub fn login(
+    oidc_domain: &impl Borrow<Url>,
+    oidc_client_id: String,
+    helper: &Option<impl AsRef<OsStr>>,
+) -> anyhow::Result<String> {
+    let secret = &device_token(oidc_domain, oidc_client_id)?;
     let oidc_domain = oidc_domain
         .borrow()
         .host_str()
         .ok_or_else(|| anyhow!("invalid OpenID Connect domain"))?;
-    let secret = res.access_token().secret();
     if let Some(helper) = helper {
         let mut helper = Command::new(helper)
             .stdin(Stdio::piped())
@@ -0,0 +1,302 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Tamper-evident audit log of keep launches
+//!
+//! Every launch of a keep appends a line of JSON to the log, which records what ran on the host:
+//! the backend and measurement of the keep, the digest of its workload and the Steward, which
+//! issued its certificate, along with the serial number of the certificate. Every entry carries
+//! the SHA-256 digest of the line of its predecessor, such that modifying, inserting or removing
+//! an entry breaks the chain of all later entries. The removal of the last entries is only
+//! detected by comparing the digest of the last entry to one recorded elsewhere.
+
+use super::events::{self, Events};
+use super::hex;
+use crate::time::rfc3339;
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::Path;
+use std::thread;
+use std::time::SystemTime;
+
+use anyhow::{bail, Context, Result};
+use enarx_exec_wasmtime::Event;
+use log::{error, warn};
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+
+/// Digest the first entry of a log refers to as its predecessor
+pub const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// A launch of a keep
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Launch {
+    /// Time the keep was launched at in RFC 3339 format
+    pub started: String,
+    /// Name of the backend of the keep
+    pub backend: Option<String>,
+    /// Hex-encoded measurement of the keep, if the backend measures keeps
+    pub measurement: Option<String>,
+    /// URL of the package, if it is remote
+    pub package: Option<String>,
+    /// Digest of the WebAssembly module, `sha256:` followed by the hex-encoded digest
+    pub workload: Option<String>,
+    /// Hex-encoded SHA-256 digest of the public key of the keep
+    pub identity: Option<String>,
+    /// Hex-encoded SHA-256 digest of the DER-encoded certificate of the keep
+    pub certificate: Option<String>,
+    /// Hex-encoded serial number of the certificate of the keep
+    pub serial: Option<String>,
+    /// URL of the Steward, which issued the certificate, or `None` if it is self-signed or was
+    /// issued out of band
+    pub steward: Option<String>,
+    /// Time the keep was attested at in RFC 3339 format, `None` if it exited before
+    pub attested: Option<String>,
+    /// Exit code of the keep, if it exited before it was attested
+    pub code: Option<i32>,
+}
+
+/// An entry of the audit log
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    /// Index of the entry in the log, starting at 0
+    pub seq: u64,
+    /// Hex-encoded SHA-256 digest of the line of the previous entry, [`GENESIS`] for the first
+    pub prev: String,
+    /// Time the entry was appended at in RFC 3339 format
+    pub time: String,
+    /// Name of the host, which launched the keep
+    pub host: String,
+    /// The launch of the keep
+    #[serde(flatten)]
+    pub launch: Launch,
+}
+
+/// Returns the hex-encoded SHA-256 digest of `line`.
+fn hash(line: &str) -> String {
+    hex(digest(&SHA256, line.as_bytes()))
+}
+
+/// Returns the name of the host.
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len() - 1) } != 0 {
+        return String::new();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+/// Reads the entries of the log from `reader` with the digests of their lines, verifying the
+/// chain of the entries.
+pub fn read(reader: impl Read) -> Result<Vec<(Entry, String)>> {
+    let mut entries: Vec<(Entry, String)> = vec![];
+    for (n, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line.context("failed to read audit log")?;
+        let entry: Entry = serde_json::from_str(&line)
+            .with_context(|| format!("line {}: invalid entry", n + 1))?;
+        let prev = entries.last().map_or(GENESIS, |(_, hash)| hash);
+        if entry.seq != n as u64 {
+            bail!(
+                "line {}: entry has index `{}`, expected `{n}`",
+                n + 1,
+                entry.seq
+            );
+        }
+        if entry.prev != prev {
+            bail!(
+                "line {}: entry refers to predecessor `{}`, but the previous entry is `{prev}`",
+                n + 1,
+                entry.prev
+            );
+        }
+        entries.push((entry, hash(&line)));
+    }
+    Ok(entries)
+}
+
+/// An audit log opened for appending
+pub struct Log(File);
+
+impl Log {
+    /// Opens the log at `path` at an FD not conflicting with the socket pair to exec-wasmtime,
+    /// creating it, if it does not exist.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .with_context(|| format!("failed to open audit log `{}`", path.display()))?;
+        let fd = events::dup(file.as_raw_fd())?;
+        Ok(Self(unsafe { File::from_raw_fd(fd) }))
+    }
+
+    /// Appends `launch` to the log, which is locked, such that concurrent keeps append to the
+    /// chain one after another.
+    pub fn append(&mut self, launch: Launch) -> Result<Entry> {
+        if unsafe { libc::flock(self.0.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(io::Error::last_os_error()).context("failed to lock audit log");
+        }
+        let res = self.append_locked(launch);
+        unsafe { libc::flock(self.0.as_raw_fd(), libc::LOCK_UN) };
+        res
+    }
+
+    fn append_locked(&mut self, launch: Launch) -> Result<Entry> {
+        self.0.seek(SeekFrom::Start(0))?;
+        let entries = read(&mut self.0).context("refusing to append to invalid audit log")?;
+        let entry = Entry {
+            seq: entries.len() as u64,
+            prev: entries
+                .last()
+                .map_or_else(|| GENESIS.into(), |(_, hash)| hash.clone()),
+            time: rfc3339(SystemTime::now()),
+            host: hostname(),
+            launch,
+        };
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        self.0
+            .write_all(&line)
+            .and_then(|()| self.0.sync_data())
+            .context("failed to append to audit log")?;
+        Ok(entry)
+    }
+}
+
+/// Spawns a thread recording the launch of the keep in `log` according to the events of the
+/// keep read from `reader`, which are forwarded to `events`, until the keep exits.
+///
+/// The launch is recorded once the keep is attested, or if it exits before, once it exited.
+pub fn spawn(
+    mut log: Log,
+    reader: File,
+    mut events: Option<Events>,
+) -> io::Result<thread::JoinHandle<()>> {
+    let mut launch = Launch {
+        started: rfc3339(SystemTime::now()),
+        ..Default::default()
+    };
+    thread::Builder::new().name("audit".into()).spawn(move || {
+        let mut recorded = false;
+        let mut record = |launch: &Launch| {
+            if let Err(e) = log.append(launch.clone()) {
+                error!("failed to record keep launch in audit log: {e:#}");
+            }
+        };
+        for line in BufReader::new(reader).lines() {
+            let event = match line.map(|line| serde_json::from_str::<Event>(&line)) {
+                Ok(Ok(event)) => event,
+                Ok(Err(e)) => {
+                    warn!("failed to decode keep event: {e}");
+                    continue;
+                }
+                Err(e) => {
+                    warn!("failed to read keep events: {e}");
+                    break;
+                }
+            };
+            if let Some(ref mut events) = events {
+                events.emit(event.clone());
+            }
+            match event {
+                Event::BackendSelected { backend, .. } => launch.backend = Some(backend),
+                Event::KeepMeasured { measurement } => launch.measurement = measurement,
+                Event::PackageFetched { url, .. } => launch.package = url.map(String::from),
+                Event::Attested {
+                    identity,
+                    certificate,
+                    steward,
+                    serial,
+                    workload,
+                } if !recorded => {
+                    launch.identity = Some(identity);
+                    launch.certificate = Some(certificate);
+                    launch.serial = Some(serial);
+                    launch.steward = steward.map(String::from);
+                    launch.workload = Some(workload);
+                    launch.attested = Some(rfc3339(SystemTime::now()));
+                    record(&launch);
+                    recorded = true;
+                }
+                Event::Exited { code } => launch.code = Some(code),
+                _ => {}
+            }
+        }
+        if !recorded {
+            record(&launch);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+
+        let mut log = Log::open(&path).unwrap();
+        let first = log
+            .append(Launch {
+                backend: Some("nil".into()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(first.seq, 0);
+        assert_eq!(first.prev, GENESIS);
+        let second = log.append(Launch::default()).unwrap();
+        assert_eq!(second.seq, 1);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let entries = read(content.as_bytes()).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0, first);
+        assert_eq!(entries[1].0.prev, entries[0].1);
+
+        // Tampering with the first entry breaks the chain.
+        let tampered = content.replacen("\"nil\"", "\"sgx\"", 1);
+        assert!(read(tampered.as_bytes()).is_err());
+
+        // Removing the first entry breaks the chain.
+        let removed = content.lines().skip(1).collect::<Vec<_>>().join("\n");
+        assert!(read(removed.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn launch() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let (reader, writer) = events::pipe().unwrap();
+
+        let thread = spawn(Log::open(&path).unwrap(), reader, None).unwrap();
+        let mut writer = Events::from(writer);
+        writer.emit(Event::BackendSelected {
+            backend: "nil".into(),
+            skipped: vec![],
+        });
+        writer.emit(Event::Attested {
+            identity: "08f5".into(),
+            certificate: "ab12".into(),
+            steward: None,
+            serial: "01".into(),
+            workload: "sha256:5647".into(),
+        });
+        writer.emit(Event::Exited { code: 0 });
+        drop(writer);
+        thread.join().unwrap();
+
+        let entries = read(File::open(&path).unwrap()).unwrap();
+        assert_eq!(entries.len(), 1);
+        let launch = &entries[0].0.launch;
+        assert_eq!(launch.backend.as_deref(), Some("nil"));
+        assert_eq!(launch.identity.as_deref(), Some("08f5"));
+        assert_eq!(launch.serial.as_deref(), Some("01"));
+        assert_eq!(launch.workload.as_deref(), Some("sha256:5647"));
+        assert!(launch.attested.is_some());
+        assert_eq!(launch.code, None);
+    }
+}
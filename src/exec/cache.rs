@@ -0,0 +1,384 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Content-addressed cache of the packages deployed by `enarx deploy`
+//!
+//! A package is stored in a directory named after the digest of its tag entry, i.e. of its root
+//! directory or of its main module. The tag and the root directory are fetched on every deploy,
+//! since they are small and bind the digests of all files of the package. A cached file is only
+//! reused if it matches the digest of its directory entry, otherwise it is fetched again.
+
+use super::hex;
+
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context, Result};
+use drawbridge_client::types::digest::ContentDigest;
+use drawbridge_client::types::{Meta, TagEntry, TreeDirectory, TreeEntry, TreeName, TreePath};
+use drawbridge_client::{scope, Client, Entity, Node};
+use enarx_config::{Config, Service};
+use enarx_exec_wasmtime::{
+    is_package_data, mounts_package_data, MAX_PACKAGE_DATA_SIZE, PACKAGE_CONFIG, PACKAGE_ENTRYPOINT,
+};
+use url::Url;
+
+/// Maximum size of a tag entry in bytes
+const MAX_TAG_SIZE: u64 = 1_000_000;
+/// Maximum size of Enarx.toml in bytes
+const MAX_CONF_SIZE: u64 = 1_000_000;
+/// Maximum directory size in bytes
+const MAX_DIR_SIZE: u64 = 1_000_000;
+
+const WASM_MEDIA_TYPE: &str = "application/wasm";
+
+/// Returns the directory of the cache, `$XDG_CACHE_HOME/enarx` or `$HOME/.cache/enarx`.
+pub fn dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .map(|dir| dir.join("enarx"))
+}
+
+/// Returns the name of the cache entry of content with digest `hash`, which is derived from its
+/// strongest algorithm.
+fn name(hash: &ContentDigest) -> Result<String> {
+    let (algo, hash) = hash.iter().next_back().context("digest is empty")?;
+    Ok(format!("{algo}-{}", hex(hash)))
+}
+
+/// Returns whether the file at `path` exists and matches `meta`.
+fn verify(path: &Path, meta: &Meta) -> bool {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return false,
+    };
+    match file.metadata() {
+        Ok(md) if md.len() == meta.size => {}
+        _ => return false,
+    }
+    io::copy(&mut meta.hash.clone().verifier(file), &mut io::sink()).is_ok()
+}
+
+/// Fetches the file at `name` of `tree` to `path`, unless a file matching `meta` is cached there.
+fn get(
+    tree: Entity<'_, scope::Unknown, scope::Node>,
+    name: &TreePath,
+    meta: &Meta,
+    limit: u64,
+    path: &Path,
+) -> Result<()> {
+    ensure!(
+        meta.size <= limit,
+        "size of `{name}` of `{}` exceeds the limit of `{limit}`",
+        meta.size
+    );
+    if verify(path, meta) {
+        return Ok(());
+    }
+
+    // Fetch to a temporary file first, such that the cache never contains partial files.
+    let tmp = path.with_extension("part");
+    let res = File::create(&tmp)
+        .with_context(|| format!("failed to create `{}`", tmp.display()))
+        .and_then(|mut file| {
+            Node::new(tree, name)
+                .get_to(limit, &mut file)
+                .with_context(|| format!("failed to fetch `{name}`"))
+        })
+        .and_then(|fetched| {
+            ensure!(
+                fetched == *meta,
+                "`{name}` metadata does not match directory entry metadata"
+            );
+            fs::rename(&tmp, path).with_context(|| format!("failed to cache `{name}`"))
+        });
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    res
+}
+
+/// Returns the directory entry `name` of `dir`.
+fn dir_entry<'a>(dir: &'a TreeDirectory, name: &TreeName) -> Result<&'a TreeEntry> {
+    dir.get(name)
+        .with_context(|| format!("directory does not contain `{name}`"))
+}
+
+/// Returns the paths of the main module and of the config of the package tagged at `url`,
+/// which are fetched into `cache`, unless they are cached already.
+///
+/// Returns `None` if `url` does not refer to a tag, which is left to exec-wasmtime to fetch.
+pub fn fetch(cache: &Path, url: &Url, limit: u64) -> Result<Option<(PathBuf, Option<PathBuf>)>> {
+    if !url
+        .path_segments()
+        .map_or(false, |mut segments| segments.any(|s| s == "_tag"))
+    {
+        return Ok(None);
+    }
+
+    let cl =
+        Client::<scope::Unknown>::new_scoped(url.clone()).context("failed to construct client")?;
+    let top = Entity::new(&cl);
+    let (_, tag) = top
+        .get_json::<TagEntry>(MAX_TAG_SIZE)
+        .with_context(|| format!("failed to fetch tag `{url}`"))?;
+    let entry: TreeEntry = match tag {
+        TagEntry::Unsigned(e) => e,
+        // TODO: Support signed tags
+        TagEntry::Signed(_jws) => bail!("signed tags are not currently supported"),
+    };
+
+    let path = cache.join(name(&entry.meta.hash)?);
+    fs::create_dir_all(&path)
+        .with_context(|| format!("failed to create cache directory `{}`", path.display()))?;
+    let wasm = path.join(PACKAGE_ENTRYPOINT.as_str());
+    let tree = top.child("tree");
+    match entry.meta.mime.essence_str() {
+        WASM_MEDIA_TYPE => {
+            get(tree, &TreePath::ROOT, &entry.meta, limit, &wasm)?;
+            Ok(Some((wasm, None)))
+        }
+        TreeDirectory::<()>::TYPE => {
+            let (meta, dir) = Node::new(tree.clone(), &TreePath::ROOT)
+                .get_json::<TreeDirectory>(MAX_DIR_SIZE)
+                .context("failed to get root directory")?;
+            ensure!(
+                meta == entry.meta,
+                "directory metadata does not match tag entry metadata"
+            );
+
+            let meta = &dir_entry(&dir, &PACKAGE_ENTRYPOINT)?.meta;
+            get(
+                tree.clone(),
+                &PACKAGE_ENTRYPOINT.clone().into(),
+                meta,
+                limit,
+                &wasm,
+            )?;
+
+            let meta = match dir.get(&PACKAGE_CONFIG) {
+                Some(e) => &e.meta,
+                None => return Ok(Some((wasm, None))),
+            };
+            let conf = path.join(PACKAGE_CONFIG.as_str());
+            get(
+                tree.clone(),
+                &PACKAGE_CONFIG.clone().into(),
+                meta,
+                MAX_CONF_SIZE,
+                &conf,
+            )?;
+            let config: Config = fs::read_to_string(&conf)
+                .context("failed to read config")
+                .and_then(|config| toml::from_str(&config).context("failed to parse config"))?;
+
+            // The modules of the services are part of the same package.
+            for (service, Service { module, .. }) in config.services.iter() {
+                let name: TreeName = module.parse().with_context(|| {
+                    format!("invalid module name `{module}` of service `{service}`")
+                })?;
+                let meta = &dir_entry(&dir, &name)?.meta;
+                get(
+                    tree.clone(),
+                    &name.clone().into(),
+                    meta,
+                    limit,
+                    &path.join(name.as_str()),
+                )
+                .with_context(|| format!("failed to get Wasm of service `{service}`"))?;
+            }
+
+            // The data files are only fetched, if the workload mounts them.
+            if mounts_package_data(&config) {
+                let mut remaining = MAX_PACKAGE_DATA_SIZE;
+                for (name, entry) in dir.iter() {
+                    if !is_package_data(&config, name.as_str())
+                        || entry.meta.mime.essence_str() == TreeDirectory::<()>::TYPE
+                    {
+                        continue;
+                    }
+                    get(
+                        tree.clone(),
+                        &name.clone().into(),
+                        &entry.meta,
+                        remaining,
+                        &path.join(name.as_str()),
+                    )
+                    .with_context(|| format!("failed to get data file `{name}`"))?;
+                    remaining -= entry.meta.size;
+                }
+            }
+            Ok(Some((wasm, Some(conf))))
+        }
+        typ => bail!("unsupported root type `{typ}`"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use drawbridge_client::types::digest::Algorithms;
+
+    #[test]
+    fn names() {
+        let hash: ContentDigest = "sha-256=:47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=:"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            name(&hash).unwrap(),
+            "sha-256-e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[test]
+    fn verification() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("main.wasm");
+        let meta = Meta {
+            hash: "sha-256=:47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=:"
+                .parse()
+                .unwrap(),
+            size: 0,
+            mime: WASM_MEDIA_TYPE.parse().unwrap(),
+        };
+        assert!(!verify(&path, &meta));
+
+        fs::write(&path, b"").unwrap();
+        assert!(verify(&path, &meta));
+
+        fs::write(&path, b"\0").unwrap();
+        assert!(!verify(&path, &meta));
+        assert!(!verify(&path, &Meta { size: 1, ..meta }));
+    }
+
+    /// Serves `files` at the paths relative to the returned tag URL and records the requests.
+    fn serve(
+        files: BTreeMap<&'static str, (&'static str, Vec<u8>)>,
+    ) -> (Url, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/api/v0.2.0/user/repo/_tag/1.0",
+            listener.local_addr().unwrap()
+        );
+        let requests = Arc::new(Mutex::new(vec![]));
+        let log = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut line = String::new();
+                BufReader::new(&stream).read_line(&mut line).unwrap();
+                let path = line.split_whitespace().nth(1).unwrap();
+                let path = path.trim_start_matches("/api/v0.2.0/user/repo/_tag/1.0");
+                log.lock().unwrap().push(path.to_string());
+                let (mime, body) = &files[path];
+                let (_, hash) = Algorithms::default().read_sync(&body[..]).unwrap();
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Type: {mime}\r\nContent-Length: {}\r\nContent-Digest: {hash}\r\n\r\n",
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(body).unwrap();
+            }
+        });
+        (url.parse().unwrap(), requests)
+    }
+
+    /// Returns the entry of `body` of type `mime`.
+    fn entry(mime: &str, body: &[u8]) -> TreeEntry {
+        let (size, hash) = Algorithms::default().read_sync(body).unwrap();
+        TreeEntry {
+            meta: Meta {
+                hash,
+                size,
+                mime: mime.parse().unwrap(),
+            },
+            custom: Default::default(),
+            content: (),
+        }
+    }
+
+    #[test]
+    fn fetching() {
+        let wasm = b"\0asm\x01\0\0\0".to_vec();
+        let dir: TreeDirectory = [(PACKAGE_ENTRYPOINT.clone(), entry(WASM_MEDIA_TYPE, &wasm))]
+            .into_iter()
+            .collect();
+        let dir = serde_json::to_vec(&dir).unwrap();
+        let tag = serde_json::to_vec(&TagEntry::Unsigned(entry(TreeDirectory::<()>::TYPE, &dir)))
+            .unwrap();
+        let (url, requests) = serve(BTreeMap::from([
+            ("", (TreeEntry::<()>::TYPE, tag)),
+            ("/tree", (TreeDirectory::<()>::TYPE, dir)),
+            ("/tree/main.wasm", (WASM_MEDIA_TYPE, wasm.clone())),
+        ]));
+
+        let cache = tempfile::tempdir().unwrap();
+        let (path, conf) = fetch(cache.path(), &url, 1024).unwrap().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), wasm);
+        assert_eq!(conf, None);
+        assert_eq!(*requests.lock().unwrap(), ["", "/tree", "/tree/main.wasm"]);
+
+        // A cached file is reused.
+        requests.lock().unwrap().clear();
+        assert_eq!(fetch(cache.path(), &url, 1024).unwrap().unwrap().0, path);
+        assert_eq!(*requests.lock().unwrap(), ["", "/tree"]);
+
+        // A corrupted file is fetched again.
+        requests.lock().unwrap().clear();
+        fs::write(&path, b"\0asm\x01\0\0\x01").unwrap();
+        fetch(cache.path(), &url, 1024).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), wasm);
+        assert_eq!(*requests.lock().unwrap(), ["", "/tree", "/tree/main.wasm"]);
+
+        assert!(fetch(cache.path(), &url, 4).is_err());
+        let url = "http://127.0.0.1/main.wasm".parse().unwrap();
+        assert!(fetch(cache.path(), &url, 1024).unwrap().is_none());
+    }
+
+    #[test]
+    fn fetching_data() {
+        let wasm = b"\0asm\x01\0\0\0".to_vec();
+        let conf = b"[[files]]\nkind = \"package\"\n".to_vec();
+        let html = b"<html></html>".to_vec();
+        let dir: TreeDirectory = [
+            (PACKAGE_ENTRYPOINT.clone(), entry(WASM_MEDIA_TYPE, &wasm)),
+            (PACKAGE_CONFIG.clone(), entry("application/toml", &conf)),
+            ("index.html".parse().unwrap(), entry("text/html", &html)),
+        ]
+        .into_iter()
+        .collect();
+        let dir = serde_json::to_vec(&dir).unwrap();
+        let tag = serde_json::to_vec(&TagEntry::Unsigned(entry(TreeDirectory::<()>::TYPE, &dir)))
+            .unwrap();
+        let (url, requests) = serve(BTreeMap::from([
+            ("", (TreeEntry::<()>::TYPE, tag)),
+            ("/tree", (TreeDirectory::<()>::TYPE, dir)),
+            ("/tree/main.wasm", (WASM_MEDIA_TYPE, wasm)),
+            ("/tree/Enarx.toml", ("application/toml", conf)),
+            ("/tree/index.html", ("text/html", html.clone())),
+        ]));
+
+        let cache = tempfile::tempdir().unwrap();
+        let (path, conf) = fetch(cache.path(), &url, 1024).unwrap().unwrap();
+        assert!(requests
+            .lock()
+            .unwrap()
+            .contains(&"/tree/index.html".to_string()));
+        let (.., mut data) = crate::exec::open_package(path, conf).unwrap();
+        let mut read = vec![];
+        data.remove("index.html")
+            .unwrap()
+            .read_to_end(&mut read)
+            .unwrap();
+        assert_eq!(read, html);
+        assert!(data.is_empty());
+    }
+}
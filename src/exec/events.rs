@@ -0,0 +1,112 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Lifecycle events of the keep written for wrappers of `enarx`
+
+use std::path::PathBuf;
+
+#[cfg(unix)]
+use std::fs::{File, OpenOptions};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+
+#[cfg(unix)]
+use anyhow::{Context, Result};
+#[cfg(unix)]
+use enarx_exec_wasmtime::Event;
+#[cfg(unix)]
+use log::warn;
+
+/// The lowest FD the events are written to, since FD 3 and 4 are taken by the socket pair to exec-wasmtime
+#[cfg(unix)]
+const MIN_FD: RawFd = 5;
+
+/// Destination of the lifecycle events of the keep
+#[derive(Debug)]
+pub enum Target {
+    /// An open file descriptor inherited from the parent
+    Fd(i32),
+
+    /// A file, which the events are appended to
+    File(PathBuf),
+}
+
+/// Writes the lifecycle events of the keep as JSON lines
+#[cfg(unix)]
+pub struct Events(File);
+
+#[cfg(unix)]
+impl Events {
+    /// Opens `target` at an FD not conflicting with the socket pair to exec-wasmtime.
+    pub fn open(target: Target) -> Result<Self> {
+        let fd = match target {
+            Target::Fd(fd) => inherit(fd)?,
+            Target::File(path) => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .with_context(|| format!("failed to open events file `{}`", path.display()))?;
+                dup(file.as_raw_fd())?
+            }
+        };
+        Ok(Self(unsafe { File::from_raw_fd(fd) }))
+    }
+
+    /// Returns the FD, which exec-wasmtime writes its events to.
+    pub fn fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+
+    /// Returns another handle to the same destination.
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(Self(unsafe { File::from_raw_fd(dup(self.fd())?) }))
+    }
+
+    /// Writes `event`, failures are only logged, since they must not affect the keep.
+    pub fn emit(&mut self, event: Event) {
+        if let Err(e) = event.write_to(&mut self.0) {
+            warn!("failed to write event: {e}");
+        }
+    }
+}
+
+#[cfg(unix)]
+impl From<File> for Events {
+    fn from(file: File) -> Self {
+        Self(file)
+    }
+}
+
+/// Duplicates `fd` to an FD not conflicting with the socket pair to exec-wasmtime.
+#[cfg(unix)]
+pub(super) fn dup(fd: RawFd) -> Result<RawFd> {
+    match unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, MIN_FD) } {
+        -1 => Err(std::io::Error::last_os_error())
+            .with_context(|| format!("failed to duplicate FD `{fd}`")),
+        dup => Ok(dup),
+    }
+}
+
+/// Moves the inherited `fd` to an FD not conflicting with the socket pair to exec-wasmtime.
+#[cfg(unix)]
+pub(super) fn inherit(fd: RawFd) -> Result<RawFd> {
+    let dup = dup(fd)?;
+    // Free FD 3 and 4 for the socket pair, stdio is kept open.
+    if (libc::STDERR_FILENO + 1..MIN_FD).contains(&fd) {
+        unsafe { libc::close(fd) };
+    }
+    Ok(dup)
+}
+
+/// Returns the read and the write end of a pipe at FDs not conflicting with the socket pair to
+/// exec-wasmtime.
+#[cfg(unix)]
+pub(crate) fn pipe() -> Result<(File, File)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let [reader, writer] = fds.map(|fd| unsafe { File::from_raw_fd(fd) });
+    let moved =
+        |file: File| -> Result<File> { Ok(unsafe { File::from_raw_fd(dup(file.as_raw_fd())?) }) };
+    Ok((moved(reader)?, moved(writer)?))
+}
@@ -0,0 +1,328 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Out-of-band issuance of the certificate of a keep
+//!
+//! Instead of sending its certificate signing request to a Steward, a keep can hand it to the
+//! host, which writes it to a file or an FD, such that an operator can take it to an issuer, e.g.
+//! across an air gap. The certificate chain issued for it is read back from a file, once it
+//! exists, or from an FD until EOF, and passed on to the keep.
+
+use std::fmt;
+use std::path::PathBuf;
+
+#[cfg(unix)]
+use super::events;
+
+#[cfg(unix)]
+use std::fs::{self, File};
+#[cfg(unix)]
+use std::io::{self, BufRead, BufReader, Read, Write};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+#[cfg(unix)]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(unix)]
+use std::sync::Arc;
+#[cfg(unix)]
+use std::thread;
+#[cfg(unix)]
+use std::time::Duration;
+
+#[cfg(unix)]
+use anyhow::{bail, Context, Result};
+#[cfg(unix)]
+use log::warn;
+
+/// Interval at which the host checks whether the keep has exited
+#[cfg(unix)]
+const TICK: Duration = Duration::from_millis(100);
+
+/// Last line of the PEM-encoded certificate signing request of the keep
+#[cfg(unix)]
+const CSR_END: &str = "-----END CERTIFICATE REQUEST-----";
+
+/// An open file descriptor inherited from the parent or a file
+#[derive(Debug)]
+pub enum Endpoint {
+    /// An open file descriptor inherited from the parent
+    Fd(i32),
+
+    /// A file
+    File(PathBuf),
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fd(fd) => write!(f, "FD {fd}"),
+            Self::File(path) => write!(f, "`{}`", path.display()),
+        }
+    }
+}
+
+/// Where the certificate signing request of the keep is handed off to and where its certificate
+/// chain is read from
+#[derive(Debug)]
+pub struct Handoff {
+    /// Destination of the PEM-encoded certificate signing request
+    pub csr: Endpoint,
+
+    /// Source of the certificate chain, a file is read once it exists
+    pub crt: Endpoint,
+}
+
+/// The keep ends of the pipes of a keep handing off its certificate signing request
+#[cfg(unix)]
+pub struct HandedOff {
+    csr: File,
+    crt: File,
+    done: Arc<AtomicBool>,
+}
+
+#[cfg(unix)]
+impl HandedOff {
+    /// Returns the FD, which exec-wasmtime writes its certificate signing request to.
+    pub fn csr_fd(&self) -> RawFd {
+        self.csr.as_raw_fd()
+    }
+
+    /// Returns the FD, which exec-wasmtime reads its certificate chain from.
+    pub fn crt_fd(&self) -> RawFd {
+        self.crt.as_raw_fd()
+    }
+}
+
+#[cfg(unix)]
+impl Drop for HandedOff {
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::Relaxed);
+    }
+}
+
+/// The host ends of the pipes of a keep handing off its certificate signing request
+#[cfg(unix)]
+pub struct Handler {
+    csr: File,
+    crt: File,
+    out: Output,
+    input: Input,
+    done: Arc<AtomicBool>,
+}
+
+/// Destination of the certificate signing request opened by the host
+#[cfg(unix)]
+enum Output {
+    Fd(i32, File),
+    File(PathBuf),
+}
+
+/// Source of the certificate chain opened by the host
+#[cfg(unix)]
+enum Input {
+    Fd(i32, File),
+    File(PathBuf),
+}
+
+/// Moves the inherited `fd` out of the way of the socket pair to exec-wasmtime.
+#[cfg(unix)]
+fn inherit(fd: i32) -> Result<File> {
+    Ok(unsafe { File::from_raw_fd(events::inherit(fd)?) })
+}
+
+/// Returns the pipes to hand off the certificate signing request of a keep according to
+/// `handoff` at FDs not conflicting with the socket pair to exec-wasmtime.
+#[cfg(unix)]
+pub fn pipes(handoff: Handoff) -> Result<(HandedOff, Handler)> {
+    let out = match handoff.csr {
+        Endpoint::Fd(fd) => Output::Fd(fd, inherit(fd)?),
+        Endpoint::File(path) => Output::File(path),
+    };
+    let input = match handoff.crt {
+        Endpoint::Fd(fd) => Input::Fd(fd, inherit(fd)?),
+        Endpoint::File(path) => Input::File(path),
+    };
+    let (csr_reader, csr_writer) = events::pipe().context("failed to create CSR pipe")?;
+    let (crt_reader, crt_writer) =
+        events::pipe().context("failed to create certificate chain pipe")?;
+    let done = Arc::new(AtomicBool::new(false));
+    let handed_off = HandedOff {
+        csr: csr_writer,
+        crt: crt_reader,
+        done: done.clone(),
+    };
+    let handler = Handler {
+        csr: csr_reader,
+        crt: crt_writer,
+        out,
+        input,
+        done,
+    };
+    Ok((handed_off, handler))
+}
+
+/// Waits until `file` is readable, returns `false`, if `done` before.
+#[cfg(unix)]
+fn readable(file: &File, done: &AtomicBool) -> Result<bool> {
+    loop {
+        if done.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+        let mut fd = libc::pollfd {
+            fd: file.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        match unsafe { libc::poll(&mut fd, 1, TICK.as_millis() as _) } {
+            0 => continue,
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+            -1 => return Err(io::Error::last_os_error()).context("failed to poll"),
+            _ => return Ok(true),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Handler {
+    /// Spawns a thread, which hands off the certificate signing request of the keep and passes
+    /// the certificate chain issued for it on to the keep.
+    pub fn spawn(self) -> io::Result<thread::JoinHandle<()>> {
+        thread::Builder::new()
+            .name("handoff".into())
+            .spawn(move || {
+                if let Err(e) = self.handoff() {
+                    warn!("failed to obtain certificate issued out of band: {e:#}");
+                }
+            })
+    }
+
+    fn handoff(self) -> Result<()> {
+        let Self {
+            csr,
+            crt,
+            out,
+            input,
+            done,
+        } = self;
+
+        // The keep writes its certificate signing request at once, once it was attested.
+        let mut pem = String::new();
+        for line in BufReader::new(csr).lines() {
+            let line = line.context("failed to read the certificate signing request")?;
+            pem.push_str(&line);
+            pem.push('\n');
+            if line == CSR_END {
+                break;
+            }
+        }
+        if !pem.ends_with(&format!("{CSR_END}\n")) {
+            // The keep exited before it requested a certificate.
+            return Ok(());
+        }
+        let out = match out {
+            // Closing the FD signals the end of the request to the reader.
+            Output::Fd(fd, mut file) => {
+                file.write_all(pem.as_bytes())
+                    .context("failed to write the certificate signing request")?;
+                Endpoint::Fd(fd)
+            }
+            Output::File(path) => {
+                fs::write(&path, &pem).with_context(|| {
+                    format!(
+                        "failed to write the certificate signing request to `{}`",
+                        path.display()
+                    )
+                })?;
+                Endpoint::File(path)
+            }
+        };
+
+        let chain = match input {
+            Input::Fd(fd, mut file) => {
+                eprintln!("Wrote the certificate signing request of the keep to {out}, waiting for its certificate chain on FD {fd}");
+                let mut chain = vec![];
+                let mut chunk = [0; 4096];
+                while readable(&file, &done)? {
+                    match file.read(&mut chunk)? {
+                        0 => break,
+                        n => chain.extend_from_slice(&chunk[..n]),
+                    }
+                }
+                chain
+            }
+            Input::File(path) => {
+                eprintln!(
+                    "Wrote the certificate signing request of the keep to {out}, waiting for its certificate chain at `{}`",
+                    path.display()
+                );
+                // The file is expected to be moved in place, once it is complete.
+                while !path.exists() {
+                    if done.load(Ordering::Relaxed) {
+                        bail!("keep exited before its certificate chain was provided");
+                    }
+                    thread::sleep(TICK);
+                }
+                fs::read(&path).with_context(|| {
+                    format!(
+                        "failed to read the certificate chain at `{}`",
+                        path.display()
+                    )
+                })?
+            }
+        };
+        // Closing the pipe signals the end of the chain to the keep.
+        (&crt)
+            .write_all(&chain)
+            .context("failed to pass the certificate chain to the keep")
+    }
+}
+
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handoff() {
+        let dir = tempfile::tempdir().unwrap();
+        let csr = dir.path().join("keep.csr");
+        let crt = dir.path().join("keep.crt");
+
+        let (handed_off, handler) = pipes(Handoff {
+            csr: Endpoint::File(csr.clone()),
+            crt: Endpoint::File(crt.clone()),
+        })
+        .unwrap();
+        let thread = handler.spawn().unwrap();
+
+        let request =
+            "-----BEGIN CERTIFICATE REQUEST-----\nAAAA\n-----END CERTIFICATE REQUEST-----\n";
+        (&handed_off.csr).write_all(request.as_bytes()).unwrap();
+        while !csr.exists() {
+            thread::sleep(TICK);
+        }
+        // Wait for the whole request, which is written at once.
+        while fs::read_to_string(&csr).unwrap() != request {
+            thread::sleep(TICK);
+        }
+
+        let tmp = dir.path().join("keep.crt.tmp");
+        fs::write(&tmp, b"chain").unwrap();
+        fs::rename(&tmp, &crt).unwrap();
+
+        let mut chain = vec![];
+        (&handed_off.crt).read_to_end(&mut chain).unwrap();
+        assert_eq!(chain, b"chain");
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn exited() {
+        let (handed_off, handler) = pipes(Handoff {
+            csr: Endpoint::File("/nonexistent/keep.csr".into()),
+            crt: Endpoint::File("/nonexistent/keep.crt".into()),
+        })
+        .unwrap();
+        let thread = handler.spawn().unwrap();
+        drop(handed_off);
+        thread.join().unwrap();
+    }
+}
@@ -0,0 +1,235 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Holding a keep after attestation until it is released with `enarx release`
+//!
+//! A held keep waits for a byte on a pipe before compiling its workload. The
+//! host learns the identity of the keep from its `attested` event and serves
+//! a Unix socket named after it, a connection to which releases the keep.
+
+use super::events::{self, Events};
+
+use std::fs::{self, DirBuilder, File};
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use enarx_exec_wasmtime::Event;
+use log::warn;
+
+/// Interval at which a held keep checks whether it has exited
+const TICK: Duration = Duration::from_millis(100);
+
+/// Returns the directory of the release sockets of held keeps.
+pub fn dir() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("enarx"),
+        None => std::env::temp_dir().join(format!("enarx-{}", unsafe { libc::getuid() })),
+    }
+}
+
+/// Returns the path of the release socket of the keep with `identity` in `dir`.
+fn socket(dir: &Path, identity: &str) -> Result<PathBuf> {
+    if identity.is_empty() || !identity.bytes().all(|b| b.is_ascii_hexdigit()) {
+        bail!("invalid keep identity `{identity}`, expected a hex-encoded digest");
+    }
+    Ok(dir.join(format!("{identity}.sock")))
+}
+
+/// Releases the keep with `identity` held in `dir`.
+pub fn release(dir: &Path, identity: &str) -> Result<()> {
+    let path = socket(dir, identity)?;
+    let stream = UnixStream::connect(&path)
+        .with_context(|| format!("no keep with identity `{identity}` is held"))?;
+    let mut line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut line)
+        .context("failed to read the response of the keep")?;
+    if line.trim_end() != "released" {
+        bail!("keep `{identity}` was not released");
+    }
+    Ok(())
+}
+
+/// The ends of the pipes passed to a held keep
+pub struct Held {
+    events: File,
+    release: File,
+    done: Arc<AtomicBool>,
+}
+
+impl Held {
+    /// Returns the FD, which exec-wasmtime writes its events to.
+    pub fn events_fd(&self) -> RawFd {
+        self.events.as_raw_fd()
+    }
+
+    /// Returns the FD, which exec-wasmtime reads its release from.
+    pub fn release_fd(&self) -> RawFd {
+        self.release.as_raw_fd()
+    }
+}
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::Relaxed);
+    }
+}
+
+/// The host ends of the pipes of a held keep
+pub struct Holder {
+    events: File,
+    release: File,
+    done: Arc<AtomicBool>,
+}
+
+/// Returns the pipes to hold a keep at FDs not conflicting with the socket pair to exec-wasmtime.
+pub fn pipes() -> Result<(Held, Holder)> {
+    let (events_reader, events_writer) = events::pipe().context("failed to create events pipe")?;
+    let (release_reader, release_writer) =
+        events::pipe().context("failed to create release pipe")?;
+    let done = Arc::new(AtomicBool::new(false));
+    let held = Held {
+        events: events_writer,
+        release: release_reader,
+        done: done.clone(),
+    };
+    let holder = Holder {
+        events: events_reader,
+        release: release_writer,
+        done,
+    };
+    Ok((held, holder))
+}
+
+impl Holder {
+    /// Spawns a thread forwarding the events of the keep to `events`, which holds the keep in
+    /// `dir` once it reports its identity. `measurement` is the hex-encoded measurement of the keep.
+    pub fn spawn(
+        self,
+        dir: PathBuf,
+        mut events: Option<Events>,
+        measurement: Option<String>,
+    ) -> io::Result<thread::JoinHandle<()>> {
+        let Self {
+            events: reader,
+            release,
+            done,
+        } = self;
+        let mut release = Some(release);
+        thread::Builder::new().name("hold".into()).spawn(move || {
+            for line in BufReader::new(reader).lines() {
+                let event = match line.map(|line| serde_json::from_str::<Event>(&line)) {
+                    Ok(Ok(event)) => event,
+                    Ok(Err(e)) => {
+                        warn!("failed to decode keep event: {e}");
+                        continue;
+                    }
+                    Err(e) => return warn!("failed to read keep events: {e}"),
+                };
+                if let Some(ref mut events) = events {
+                    events.emit(event.clone());
+                }
+                if let Event::Attested {
+                    identity,
+                    certificate,
+                    ..
+                } = event
+                {
+                    let release = match release.take() {
+                        Some(release) => release,
+                        None => continue,
+                    };
+                    eprintln!("Keep is held, release it with `enarx release {identity}`");
+                    eprintln!("  identity:    {identity}");
+                    eprintln!(
+                        "  measurement: {}",
+                        measurement.as_deref().unwrap_or("none")
+                    );
+                    eprintln!("  certificate: {certificate}");
+                    // Dropping the write end of the release pipe aborts the keep.
+                    if let Err(e) = hold(&dir, &identity, &done).and_then(|()| {
+                        (&release)
+                            .write_all(&[1])
+                            .context("failed to release the keep")
+                    }) {
+                        warn!("{e:#}");
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// Waits for a connection to the release socket of the keep with `identity` in `dir`, until `done`.
+fn hold(dir: &Path, identity: &str, done: &AtomicBool) -> Result<()> {
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .with_context(|| format!("failed to create `{}`", dir.display()))?;
+    let path = socket(dir, identity)?;
+    let listener = UnixListener::bind(&path)
+        .with_context(|| format!("failed to bind to `{}`", path.display()))?;
+    listener.set_nonblocking(true)?;
+
+    let res = loop {
+        if done.load(Ordering::Relaxed) {
+            break Err(anyhow::anyhow!("keep exited before it was released"));
+        }
+        match listener.accept() {
+            Ok((mut stream, _)) => break Ok(stream.write_all(b"released\n")?),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(TICK),
+            Err(e) => break Err(e).context("failed to accept release connection"),
+        }
+    };
+    let _ = fs::remove_file(&path);
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+
+    #[test]
+    fn release() {
+        let dir = tempfile::tempdir().unwrap();
+        let (held, holder) = pipes().unwrap();
+        let identity = "08f5";
+
+        let thread = holder
+            .spawn(dir.path().into(), None, Some("6b1c".into()))
+            .unwrap();
+        Event::Attested {
+            identity: identity.into(),
+            certificate: "ab12".into(),
+            steward: None,
+            serial: "01".into(),
+            workload: "sha256:5647".into(),
+        }
+        .write_to(&mut &held.events)
+        .unwrap();
+
+        let path = dir.path().join("08f5.sock");
+        while !path.exists() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(super::release(dir.path(), "../etc").is_err());
+        super::release(dir.path(), identity).unwrap();
+
+        let mut byte = [0];
+        (&held.release).read_exact(&mut byte).unwrap();
+        assert!(!path.exists());
+
+        drop(held);
+        thread.join().unwrap();
+        assert!(super::release(dir.path(), identity).is_err());
+    }
+}
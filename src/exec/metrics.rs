@@ -0,0 +1,171 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Host-side HTTP endpoint exposing the metrics of a keep in the Prometheus text format.
+//!
+//! The exits, the bytes and the syscalls passed through the sallyport are counted by the host as
+//! it handles them, while the other metrics are the last ones reported by the keep, which only
+//! reports them on exit, if it cannot spawn a thread to report them periodically.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use enarx_exec_wasmtime::Metrics;
+use log::warn;
+
+/// Timeout for reading a request from and writing a response to a client.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Maximum size of a request head.
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// Reads the request head from `stream` and returns the method and path of the request.
+pub(super) fn read_request(stream: &TcpStream) -> Result<(String, String)> {
+    // Not even a single line is read past the limit, which is only reached by a line without end.
+    let mut reader = BufReader::new(stream).take(MAX_REQUEST_SIZE as _);
+    let mut line = String::new();
+    reader
+        .read_line(&mut line)
+        .context("failed to read request line")?;
+    if reader.limit() == 0 {
+        bail!("request exceeds {MAX_REQUEST_SIZE} bytes");
+    }
+    let mut parts = line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => bail!("invalid request line"),
+    };
+
+    // Skip the headers.
+    loop {
+        line.clear();
+        if reader
+            .read_line(&mut line)
+            .context("failed to read header")?
+            == 0
+        {
+            break;
+        }
+        if reader.limit() == 0 {
+            bail!("request exceeds {MAX_REQUEST_SIZE} bytes");
+        }
+        if line.trim_end().is_empty() {
+            break;
+        }
+    }
+    Ok((method, path))
+}
+
+/// Answers a single request on `stream`.
+fn respond(mut stream: TcpStream, metrics: &Mutex<Metrics>) -> Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+    let (status, body) = match read_request(&stream)? {
+        (method, _) if method != "GET" => ("405 Method Not Allowed", String::new()),
+        (_, path) if path == "/metrics" => {
+            let mut metrics = metrics.lock().unwrap().clone();
+            #[cfg(enarx_with_shim)]
+            crate::backend::stats::collect(&mut metrics);
+            ("200 OK", metrics.render())
+        }
+        _ => ("404 Not Found", String::new()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+    .context("failed to write response")
+}
+
+/// Serves `metrics` at `/metrics` on `listener` in a separate thread.
+pub fn serve(listener: TcpListener, metrics: Arc<Mutex<Metrics>>) -> Result<()> {
+    thread::Builder::new()
+        .name("metrics".into())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let res = stream
+                    .context("failed to accept connection")
+                    .and_then(|stream| respond(stream, &metrics));
+                if let Err(e) = res {
+                    warn!("failed to serve metrics: {e:#}");
+                }
+            }
+        })
+        .context("failed to spawn metrics thread")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{serve, MAX_REQUEST_SIZE};
+
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+
+    use enarx_exec_wasmtime::{Metrics, Traffic};
+
+    fn metrics() -> Metrics {
+        Metrics {
+            exits: 3,
+            bytes: 4096,
+            syscalls: [(0, 2), (1, 1)].into(),
+            streams: [(
+                "web\"1\"".into(),
+                Traffic {
+                    received: 10,
+                    sent: 20,
+                    connections: 2,
+                },
+            )]
+            .into(),
+            fuel_consumed: Some(100),
+            memory: 65536,
+            tls_handshake_failures: 1,
+        }
+    }
+
+    #[test]
+    fn endpoint() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        serve(listener, Arc::new(Mutex::new(metrics()))).unwrap();
+
+        let get = |request: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+
+        let response = get("GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("enarx_tls_handshake_failures_total 1\n"));
+
+        let response = get("GET / HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        let response = get("POST /metrics HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+
+        // A request without end is not read past the limit, but dropped without a response.
+        let dropped = |request: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let _ = stream.write_all(request.as_bytes());
+            let mut response = String::new();
+            let _ = stream.read_to_string(&mut response);
+            response.is_empty()
+        };
+        assert!(dropped(&"GET /".repeat(MAX_REQUEST_SIZE)));
+        assert!(dropped(&format!(
+            "GET /metrics HTTP/1.1\r\nX: {}\r\n\r\n",
+            "a".repeat(MAX_REQUEST_SIZE)
+        )));
+    }
+}
@@ -21,27 +21,95 @@
 // might need to examine the workload and determine which Exec is
 // the right one to use. But first... we gotta make exec-wasmtime work.
 
+#[cfg(unix)]
+pub mod audit;
+pub mod cache;
+pub mod events;
 #[cfg(enarx_with_shim)]
 pub mod exec_wasmtime;
+pub mod handoff;
+#[cfg(unix)]
+pub mod hold;
+#[cfg(unix)]
+mod metrics;
+pub mod oci;
+#[cfg(unix)]
+mod reload;
+pub mod replicas;
+pub mod sealed;
+#[cfg(unix)]
+mod status;
+#[cfg(unix)]
+pub mod systemd;
 
 use crate::backend::{Backend, Command, Signatures};
 
+use std::collections::BTreeMap;
 use std::convert::Into;
-use std::fs::File;
+use std::ffi::OsStr;
+use std::fs::{self, File};
 #[cfg(unix)]
 use std::os::unix::io::AsRawFd;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::sync::atomic::{AtomicI32, Ordering};
 #[cfg(unix)]
 use std::time::Duration;
 
-use anyhow::{Context, Result};
-use enarx_exec_wasmtime::{Args as ExecArgs, Package};
+use anyhow::{bail, Context, Result};
+use enarx_config::{Config, EnvPattern, Service};
+use enarx_exec_wasmtime::{
+    is_package_data, mounts_package_data, Args as ExecArgs, Faults, Package, MAX_PACKAGE_DATA_SIZE,
+};
 use once_cell::sync::Lazy;
 
 /// Write timeout for writing the arguments to exec-wasmtime.
 #[cfg(unix)]
 const ARG_WRITE_TIMEOUT: Duration = Duration::new(60, 0);
 
+/// The write end of the pipe, which forwards the signals requesting a graceful shutdown to the
+/// keep, if any.
+#[cfg(unix)]
+static SHUTDOWN_FD: AtomicI32 = AtomicI32::new(-1);
+
+#[cfg(unix)]
+extern "C" fn request_shutdown(signal: libc::c_int) {
+    let fd = SHUTDOWN_FD.load(Ordering::SeqCst);
+    if fd >= 0 {
+        let signal = signal as u8;
+        // A failed write cannot be handled here, the repeated signal terminates the process.
+        unsafe { libc::write(fd, &signal as *const u8 as *const libc::c_void, 1) };
+    }
+}
+
+/// Installs `SIGTERM` and `SIGINT` handlers, which forward the signal to the keep over `shutdown`,
+/// such that it shuts down the workload gracefully and exits with the code of a process
+/// terminated by the signal.
+///
+/// The handlers are reset after the first signal, so a repeated signal terminates the process,
+/// e.g. if the keep does not notice the request, since it can neither preempt the workload nor
+/// does the workload wait for any files.
+#[cfg(unix)]
+fn handle_shutdown_signals(shutdown: &File) -> Result<()> {
+    SHUTDOWN_FD.store(shutdown.as_raw_fd(), Ordering::SeqCst);
+    for signal in [libc::SIGTERM, libc::SIGINT] {
+        // SAFETY: `request_shutdown` is async-signal-safe, since it only performs an atomic load
+        // and a `write`.
+        let ret = unsafe {
+            let mut act: libc::sigaction = std::mem::zeroed();
+            act.sa_sigaction = request_shutdown as libc::sighandler_t;
+            act.sa_flags = libc::SA_RESETHAND;
+            libc::sigemptyset(&mut act.sa_mask);
+            libc::sigaction(signal, &act, std::ptr::null_mut())
+        };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("failed to install handler for signal {signal}"));
+        }
+    }
+    Ok(())
+}
+
 /// A trait for the "Exec"
 ///
 /// (as in Backend::keep(shim, exec) [q.v.]) and formerly known as the "code"
@@ -105,23 +173,192 @@ pub fn keep_exec(
     }
 }
 
+/// The main Wasm module, the config, the Wasm modules of the services and the data files of a
+/// package
+pub type PackageFiles = (
+    File,
+    Option<File>,
+    BTreeMap<String, File>,
+    BTreeMap<String, File>,
+);
+
+/// Opens the package with the main Wasm module at `wasm` and the config at `conf`.
 pub fn open_package(
     wasm: impl Into<PathBuf>,
     conf: Option<impl Into<PathBuf>>,
-) -> Result<(File, Option<File>)> {
-    let wasm = wasm.into();
-    let wasm = File::open(&wasm)
-        .with_context(|| format!("failed to open WASM module at `{}`", wasm.display()))?;
+) -> Result<PackageFiles> {
+    let wasm_path = wasm.into();
+    let wasm = File::open(&wasm_path)
+        .with_context(|| format!("failed to open WASM module at `{}`", wasm_path.display()))?;
     if let Some(conf) = conf {
         let conf = conf.into();
-        let conf = File::open(&conf)
+        let file = File::open(&conf)
             .with_context(|| format!("failed to open package config at `{}`", conf.display()))?;
-        Ok((wasm, Some(conf)))
+        let config = read_config(&conf)?;
+        let dir = conf.parent().unwrap_or_else(|| Path::new(""));
+        let modules = open_modules(&config, dir)?;
+        let data = open_data(&config, dir, &[&wasm_path])?;
+        Ok((wasm, Some(file), modules, data))
     } else {
-        Ok((wasm, None))
+        Ok((wasm, None, BTreeMap::new(), BTreeMap::new()))
     }
 }
 
+/// Reads and parses the package config at `path`.
+pub fn read_config(path: impl AsRef<Path>) -> Result<Config> {
+    let path = path.as_ref();
+    let config = fs::read_to_string(path)
+        .with_context(|| format!("failed to read package config at `{}`", path.display()))?;
+    toml::from_str(&config)
+        .with_context(|| format!("failed to parse package config at `{}`", path.display()))
+}
+
+/// Opens the modules of the services in `config`, which are looked up in `dir`.
+pub fn open_modules(config: &Config, dir: &Path) -> Result<BTreeMap<String, File>> {
+    config
+        .services
+        .iter()
+        .map(|(name, Service { module, .. })| {
+            if Path::new(module).file_name() != Some(OsStr::new(module)) {
+                bail!("module `{module}` of service `{name}` must be a file name");
+            }
+            let path = dir.join(module);
+            let file = File::open(&path).with_context(|| {
+                format!(
+                    "failed to open WASM module of service `{name}` at `{}`",
+                    path.display()
+                )
+            })?;
+            Ok((name.clone(), file))
+        })
+        .collect()
+}
+
+/// Opens the data files of the package in `dir`, if `config` mounts them.
+///
+/// Only the regular files at the top level of `dir` are data files, except for the ones named
+/// like one of the `modules`, which are passed to the keep as modules.
+pub fn open_data(config: &Config, dir: &Path, modules: &[&Path]) -> Result<BTreeMap<String, File>> {
+    let mut data = BTreeMap::new();
+    if !mounts_package_data(config) {
+        return Ok(data);
+    }
+    let mut size = 0;
+    let entries = fs::read_dir(if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    })
+    .with_context(|| format!("failed to read package directory `{}`", dir.display()))?;
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        let name = match entry.file_name().into_string() {
+            Ok(name) if is_package_data(config, &name) => name,
+            _ => continue,
+        };
+        if !entry.file_type()?.is_file()
+            || modules.iter().any(|m| m.file_name() == path.file_name())
+        {
+            continue;
+        }
+        size += entry.metadata()?.len();
+        if size > MAX_PACKAGE_DATA_SIZE {
+            bail!(
+                "data files in `{}` exceed the limit of `{MAX_PACKAGE_DATA_SIZE}` bytes",
+                dir.display()
+            );
+        }
+        let file = File::open(&path)
+            .with_context(|| format!("failed to open data file at `{}`", path.display()))?;
+        data.insert(name, file);
+    }
+    Ok(data)
+}
+
+/// Returns whether exec-wasmtime emits debug info for the workload, such that gdb attached to
+/// the keep via `gdblisten` can set breakpoints in its source code.
+///
+/// Only debug builds, whose exec-wasmtime is built in debug mode as well, support it.
+fn debug_workload(gdblisten: &Option<String>) -> bool {
+    cfg!(debug_assertions) && gdblisten.is_some()
+}
+
+/// Returns the hex encoding of `bytes`.
+pub fn hex(bytes: impl AsRef<[u8]>) -> String {
+    bytes.as_ref().iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Returns the environment variables of the host, which are valid Unicode and match one of
+/// `patterns`.
+///
+/// The host filters its environment before passing it to the keep, such that variables not allowed
+/// by `env_host` in `Enarx.toml` never leave it, even though exec-wasmtime filters them as well.
+fn host_env(patterns: &[EnvPattern]) -> BTreeMap<String, String> {
+    std::env::vars_os()
+        .filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?)))
+        .filter(|(name, _)| patterns.iter().any(|p| p.matches(name)))
+        .collect()
+}
+
+/// Returns the paths of the files of the host, which the `log` and `audit` files of the workload
+/// configured by `config` append their records to.
+pub fn log_files(config: &Config) -> Vec<String> {
+    let mut paths: Vec<_> = config
+        .files
+        .iter()
+        .chain(config.services.values().flat_map(|s| s.files.iter()))
+        .filter_map(|file| file.log_path())
+        .map(String::from)
+        .collect();
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+/// The options of a keep run by [`run_package`], which default to a keep without any of them
+#[derive(Default)]
+pub struct RunOptions {
+    /// The signatures of the keep
+    pub signatures: Option<Signatures>,
+    /// The address, on which the GDB server of the keep listens
+    pub gdblisten: Option<String>,
+    /// The address, on which the metrics of the keep are served
+    pub metrics_listen: Option<String>,
+    /// The address, on which the status of the keep is served by the host
+    pub status_listen: Option<String>,
+    /// The target of the events of the keep
+    pub events: Option<events::Target>,
+    /// The path the trace of the keep is written to
+    pub trace_out: Option<PathBuf>,
+    /// The path the execution report of the keep is written to
+    pub report_out: Option<PathBuf>,
+    /// The path of the audit log, which records the launch of the keep
+    pub audit_log: Option<PathBuf>,
+    /// Whether the keep is held before running the workload
+    pub hold: bool,
+    /// Where the certificate signing request of the keep is handed off to
+    pub handoff: Option<handoff::Handoff>,
+    /// The faults injected into the keep
+    pub faults: Option<Faults>,
+    /// The maximum size of the WebAssembly module of the workload
+    pub max_wasm_size: Option<u64>,
+    /// The expected digest of the workload
+    pub digest: Option<String>,
+    /// The cache of the compiled workload
+    pub sealed: Option<sealed::Sealed>,
+    /// Whether the workload may mount files of the host
+    pub mounts: bool,
+    /// The patterns of the host environment variables passed to the keep, i.e. `env_host` of the
+    /// package config, if it is available to the host
+    pub env_host: Vec<EnvPattern>,
+    /// The package config, whose `network` section is delivered to the keep again on `SIGHUP`
+    pub reload: Option<PathBuf>,
+    /// The paths of the files of the host, which the keep appends its log records to, i.e. the
+    /// ones of the package config, if it is available to the host
+    pub log_files: Vec<String>,
+}
+
 /// Runs a package.
 /// SAFETY: Panics if next free FD number is not equal to 3.
 /// In other words, callers must either close all files opened at runtime before calling this
@@ -130,12 +367,74 @@ pub fn open_package(
 pub fn run_package(
     backend: &dyn Backend,
     exec: impl AsRef<[u8]>,
-    _signatures: Option<Signatures>,
-    gdblisten: Option<String>,
+    options: RunOptions,
     package: impl FnOnce() -> Result<Package>,
 ) -> Result<i32> {
+    let RunOptions {
+        signatures: _,
+        gdblisten,
+        metrics_listen,
+        status_listen,
+        events,
+        trace_out,
+        report_out,
+        audit_log,
+        hold,
+        handoff,
+        faults,
+        max_wasm_size,
+        digest,
+        sealed,
+        mounts,
+        env_host,
+        // There is no `SIGHUP` to reload the network policy on.
+        reload: _,
+        log_files,
+    } = options;
+    if metrics_listen.is_some() {
+        anyhow::bail!("`--metrics-listen` is not supported on this platform");
+    }
+    if status_listen.is_some() {
+        anyhow::bail!("`--status-listen` is not supported on this platform");
+    }
+    if events.is_some() {
+        anyhow::bail!("`--events-fd` and `--events-file` are not supported on this platform");
+    }
+    if trace_out.is_some() {
+        anyhow::bail!("`--trace-out` is not supported on this platform");
+    }
+    if report_out.is_some() {
+        anyhow::bail!("`--report-out` is not supported on this platform");
+    }
+    if audit_log.is_some() {
+        anyhow::bail!("`--audit-log` is not supported on this platform");
+    }
+    if hold {
+        anyhow::bail!("`--hold` is not supported on this platform");
+    }
+    if handoff.is_some() {
+        anyhow::bail!("`--csr-out` and `--csr-fd` are not supported on this platform");
+    }
+    if sealed.is_some() {
+        anyhow::bail!("`--cache-compiled` is not supported on this platform");
+    }
+    if mounts && backend.name() != "nil" {
+        anyhow::bail!("`--allow-mounts` is only supported on the `nil` backend");
+    }
+    if !log_files.is_empty() {
+        anyhow::bail!("log files of the host are not supported on this platform");
+    }
     let package = package()?;
-    let args = ExecArgs { package };
+    let args = ExecArgs {
+        metrics: false,
+        debug: debug_workload(&gdblisten),
+        mounts,
+        package,
+        env: host_env(&env_host),
+        faults,
+        max_wasm_size,
+        digest,
+    };
     backend.set_args(args);
     let exit_code = keep_exec(backend, backend.shim(), exec, None, gdblisten)?;
     Ok(exit_code)
@@ -149,15 +448,129 @@ pub fn run_package(
 pub fn run_package(
     backend: &dyn Backend,
     exec: impl AsRef<[u8]>,
-    signatures: Option<Signatures>,
-    gdblisten: Option<String>,
+    options: RunOptions,
     package: impl FnOnce() -> Result<Package>,
 ) -> Result<i32> {
-    use std::io::Write;
-    use std::net::Shutdown;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{Shutdown, TcpListener};
+    use std::os::unix::io::IntoRawFd;
     use std::os::unix::net::UnixStream;
+    use std::sync::{Arc, Mutex};
     use std::thread;
 
+    use enarx_exec_wasmtime::{Event, Metrics};
+    use log::{info, warn};
+
+    let RunOptions {
+        signatures,
+        gdblisten,
+        metrics_listen,
+        status_listen,
+        events,
+        trace_out,
+        report_out,
+        audit_log,
+        hold,
+        handoff,
+        faults,
+        max_wasm_size,
+        digest,
+        sealed,
+        mounts,
+        env_host,
+        reload,
+        log_files,
+    } = options;
+
+    // Only the `nil` backend runs the workload as a process of the host, which can open its files.
+    if mounts && backend.name() != "nil" {
+        anyhow::bail!("`--allow-mounts` is only supported on the `nil` backend");
+    }
+
+    // Take the sockets passed by socket activation first, such that they are moved out of the
+    // way of the socket pair.
+    let listeners = systemd::listeners()?;
+    let notifier = systemd::Notifier::from_env()?;
+
+    // Open the events target first, such that it is moved out of the way of the socket pair.
+    let mut events = events.map(events::Events::open).transpose()?;
+
+    // An audited keep reports its events to the host, which records the launch of the keep in the
+    // audit log and forwards them to the events target.
+    let auditor = match audit_log {
+        Some(path) => {
+            let log = audit::Log::open(&path)?;
+            let (reader, writer) = events::pipe().context("failed to create audit pipe")?;
+            let auditor =
+                audit::spawn(log, reader, events.take()).context("failed to spawn audit thread")?;
+            events = Some(events::Events::from(writer));
+            Some(auditor)
+        }
+        None => None,
+    };
+
+    // The host serves the status of the keep reported in its events, which it forwards to the
+    // events target.
+    let status = match status_listen {
+        Some(addr) => {
+            let listener = TcpListener::bind(&addr)
+                .with_context(|| format!("failed to bind status endpoint to `{addr}`"))?;
+            let (reader, writer) = events::pipe().context("failed to create status pipe")?;
+            let status = status::spawn(listener, reader, events.take())?;
+            events = Some(events::Events::from(writer));
+            info!("serving keep status on `{addr}`");
+            Some(status)
+        }
+        None => None,
+    };
+    if let Some(ref mut events) = events {
+        events.emit(Event::BackendSelected {
+            backend: backend.name().into(),
+            skipped: crate::backend::skipped(),
+        });
+    }
+
+    // A held keep reports its events to the host, which forwards them to the events target.
+    let (held, holder) = if hold {
+        let (held, holder) = hold::pipes()?;
+        (Some(held), Some(holder))
+    } else {
+        (None, None)
+    };
+
+    // A keep handing off its certificate signing request passes it to the host, which passes the
+    // certificate chain issued out of band back to the keep.
+    let (handed_off, handler) = match handoff {
+        Some(handoff) => {
+            let (handed_off, handler) = handoff::pipes(handoff)?;
+            (Some(handed_off), Some(handler))
+        }
+        None => (None, None),
+    };
+
+    // A keep started by a service manager reports its events to the host, which notifies the
+    // service manager and forwards them to the events target.
+    let (mut notified, notifier) = match notifier {
+        Some(notifier) => {
+            let (reader, writer) = events::pipe().context("failed to create notification pipe")?;
+            (Some(events::Events::from(writer)), Some((notifier, reader)))
+        }
+        None => (None, None),
+    };
+
+    // The signals requesting a graceful shutdown are forwarded to the keep over a pipe.
+    let (shutdown, forwarder) = events::pipe().context("failed to create shutdown pipe")?;
+
+    // The network policy is reloaded from the package config on `SIGHUP`.
+    let network = match reload {
+        Some(config) => {
+            let (reloader, network) = reload::Reloader::new(config)?;
+            reloader.spawn()?;
+            Some(network)
+        }
+        None => None,
+    };
+
     let (exec_sock, mut host_sock) =
         UnixStream::pair().context("failed to create a Unix socket pair")?;
 
@@ -167,9 +580,84 @@ pub fn run_package(
         "exec-wasmtime expects the Unix socket to be at FD 3"
     );
 
+    let metrics = match metrics_listen {
+        Some(addr) => {
+            let listener = TcpListener::bind(&addr)
+                .with_context(|| format!("failed to bind metrics endpoint to `{addr}`"))?;
+            let metrics = Arc::new(Mutex::new(Metrics::default()));
+            metrics::serve(listener, metrics.clone())?;
+            info!("serving keep metrics on `{addr}`");
+            Some(metrics)
+        }
+        None => None,
+    };
+
     let package = package()?;
-    let args =
-        toml::to_vec(&ExecArgs { package }).context("failed to encode exec-wasmtime arguments")?;
+    // The trace is written by the keep, so the file is kept open until it exits.
+    let trace = trace_out
+        .map(|path| {
+            File::create(&path)
+                .with_context(|| format!("failed to create trace file `{}`", path.display()))
+        })
+        .transpose()?;
+    // The report is written by the keep on exit, so the file is kept open until then.
+    let report = report_out
+        .map(|path| {
+            File::create(&path)
+                .with_context(|| format!("failed to create report file `{}`", path.display()))
+        })
+        .transpose()?;
+    // The log records are appended by the keep, so the files are kept open until it exits.
+    let logs = log_files
+        .into_iter()
+        .map(|path| {
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .with_context(|| format!("failed to open log file `{path}`"))?;
+            Ok((path, file))
+        })
+        .collect::<Result<BTreeMap<_, _>>>()?;
+    let (cached, cache) = match sealed {
+        Some(ref sealed) => {
+            let (cached, cache) = sealed.open()?;
+            (cached.map(File::into_raw_fd), Some(cache.into_raw_fd()))
+        }
+        None => (None, None),
+    };
+    let args = toml::to_vec(&ExecArgs {
+        metrics: metrics.is_some(),
+        debug: debug_workload(&gdblisten),
+        mounts,
+        events: match held {
+            Some(ref held) => Some(held.events_fd()),
+            None => notified
+                .as_ref()
+                .or(events.as_ref())
+                .map(events::Events::fd),
+        },
+        trace: trace.as_ref().map(File::as_raw_fd),
+        report: report.as_ref().map(File::as_raw_fd),
+        hold: held.as_ref().map(hold::Held::release_fd),
+        shutdown: Some(shutdown.as_raw_fd()),
+        network: network.as_ref().map(File::as_raw_fd),
+        csr: handed_off.as_ref().map(handoff::HandedOff::csr_fd),
+        crt: handed_off.as_ref().map(handoff::HandedOff::crt_fd),
+        cached,
+        cache,
+        listeners,
+        logs: logs
+            .iter()
+            .map(|(path, file)| (path.clone(), file.as_raw_fd()))
+            .collect(),
+        package,
+        env: host_env(&env_host),
+        faults,
+        max_wasm_size,
+        digest,
+    })
+    .context("failed to encode exec-wasmtime arguments")?;
 
     host_sock
         .set_nonblocking(true)
@@ -185,13 +673,119 @@ pub fn run_package(
         host_sock
             .shutdown(Shutdown::Write)
             .context("failed to shutdown write half of host's socket")?;
-        // TODO: Read exec-wasmtime output from the socket
+        // TODO: Read other exec-wasmtime output from the socket
+        let metrics = match metrics {
+            Some(metrics) => metrics,
+            None => {
+                return host_sock
+                    .shutdown(Shutdown::Read)
+                    .context("failed to shutdown read half of host's socket")
+            }
+        };
+
+        // exec-wasmtime reports a snapshot of its metrics per line until it exits.
         host_sock
-            .shutdown(Shutdown::Read)
-            .context("failed to shutdown read half of host's socket")
+            .set_nonblocking(false)
+            .context("failed to set host socket to blocking")?;
+        for line in BufReader::new(host_sock).lines() {
+            let line = line.context("failed to read metrics from `wasmtime-exec`")?;
+            match serde_json::from_str(&line) {
+                Ok(snapshot) => *metrics.lock().unwrap() = snapshot,
+                Err(e) => warn!("failed to decode metrics reported by `wasmtime-exec`: {e}"),
+            }
+        }
+        Ok(())
     });
 
-    let exit_code = keep_exec(backend, backend.shim(), exec, signatures, gdblisten)?;
+    handle_shutdown_signals(&forwarder)?;
+
+    // The measurement is only computed, if events were requested, e.g. by the audit log, or the
+    // keep is held.
+    let measurement = if events.is_some() || hold {
+        let measurement = backend
+            .hash(backend.shim(), exec.as_ref())
+            .context("failed to measure keep")?;
+        (!measurement.is_empty()).then(|| hex(measurement))
+    } else {
+        None
+    };
+    if let Some(ref mut events) = events {
+        events.emit(Event::KeepMeasured {
+            measurement: measurement.clone(),
+        });
+    }
+
+    let notifier = match notifier {
+        Some((notifier, reader)) => {
+            let events = events.as_ref().map(events::Events::try_clone).transpose()?;
+            Some(
+                notifier
+                    .spawn(reader, events)
+                    .context("failed to spawn notification thread")?,
+            )
+        }
+        None => None,
+    };
+
+    let holder = match holder {
+        Some(holder) => {
+            let events = match notified.take() {
+                Some(notified) => Some(notified),
+                None => events.as_ref().map(events::Events::try_clone).transpose()?,
+            };
+            Some(
+                holder
+                    .spawn(hold::dir(), events, measurement)
+                    .context("failed to spawn hold thread")?,
+            )
+        }
+        None => None,
+    };
+
+    let handler = handler
+        .map(handoff::Handler::spawn)
+        .transpose()
+        .context("failed to spawn handoff thread")?;
+
+    let exit_code = keep_exec(backend, backend.shim(), exec, signatures, gdblisten);
+    // Stop forwarding signals, before the pipe is closed.
+    SHUTDOWN_FD.store(-1, Ordering::SeqCst);
+    drop(shutdown);
+    drop(network);
+    if let Some(sealed) = sealed {
+        if let Err(e) = sealed.commit() {
+            warn!("failed to cache compiled module: {e:#}");
+        }
+    }
+    // Close the keep ends of the pipes, such that the hold thread stops waiting for the release.
+    drop(held);
+    if let Some(holder) = holder {
+        holder.join().expect("failed to join hold thread");
+    }
+    drop(handed_off);
+    if let Some(handler) = handler {
+        handler.join().expect("failed to join handoff thread");
+    }
+    drop(notified);
+    if let Some(notifier) = notifier {
+        notifier.join().expect("failed to join notification thread");
+    }
+    if let (Ok(code), Some(events)) = (&exit_code, &mut events) {
+        events.emit(Event::Exited { code: *code });
+    }
+    // Close the events, such that the audit thread records the launch of a keep, which exited
+    // before it was attested.
+    drop(events);
+    if let Some(status) = status {
+        status.join().expect("failed to join status events thread");
+    }
+    if let Some(auditor) = auditor {
+        auditor.join().expect("failed to join audit thread");
+    }
+    let exit_code = exit_code?;
+
+    // Close the exec-wasmtime end of the socket, such that the I/O thread observes EOF.
+    drop(exec_sock);
     exec_io
         .join()
         .expect("failed to join exec-wasmtime I/O thread")?;
@@ -200,7 +794,7 @@ pub fn run_package(
 
 #[cfg(test)]
 mod test {
-    use super::{Exec, NilExec};
+    use super::{host_env, log_files, Config, Exec, NilExec};
 
     #[test]
     fn coverage() {
@@ -208,4 +802,50 @@ mod test {
         assert_eq!(exec.name(), "nil");
         assert!(exec.exec().is_empty());
     }
+
+    #[test]
+    fn env() {
+        std::env::set_var("ENARX_TEST_HOST_ENV", "1");
+        std::env::set_var("ENARX_TEST_HOST_ENV_FOO", "2");
+
+        assert!(host_env(&[]).is_empty());
+
+        let env = host_env(&["ENARX_TEST_HOST_ENV".into()]);
+        assert_eq!(env.len(), 1);
+        assert_eq!(env["ENARX_TEST_HOST_ENV"], "1");
+
+        let env = host_env(&["ENARX_TEST_HOST_ENV_*".into()]);
+        assert_eq!(env.len(), 1);
+        assert_eq!(env["ENARX_TEST_HOST_ENV_FOO"], "2");
+    }
+
+    #[test]
+    fn logs() {
+        const CONFIG: &str = r#"
+        [[files]]
+        kind = "log"
+
+        [[files]]
+        kind = "log"
+        target = "file"
+        path = "/var/log/b.log"
+
+        [services.svc]
+        module = "svc.wasm"
+
+        [[services.svc.files]]
+        kind = "audit"
+        target = "file"
+        path = "/var/log/a.log"
+
+        [[services.svc.files]]
+        kind = "log"
+        target = "file"
+        path = "/var/log/b.log"
+        "#;
+
+        let config: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(log_files(&config), vec!["/var/log/a.log", "/var/log/b.log"]);
+        assert!(log_files(&Config::default()).is_empty());
+    }
 }
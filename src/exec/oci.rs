@@ -0,0 +1,710 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Packages stored as OCI artifacts in container registries
+//!
+//! `enarx deploy oci://registry/repository:tag` pulls the manifest of the artifact and the layers
+//! named by their `org.opencontainers.image.title` annotation into the cache, following the
+//! layout of Wasm OCI artifacts: `main.wasm` is the main module and `Enarx.toml` is the config,
+//! while the other layers are the modules of the services and the data files of the package. Every
+//! blob is verified against its digest in the manifest, and the manifest is verified against the
+//! digest of the reference, if it is pinned with `@sha256:<digest>`.
+//!
+//! If a cosign key is given, the artifact must be signed with it: the manifest tagged
+//! `sha256-<digest>.sig` must have a simple signing layer, the payload of which names the digest
+//! of the manifest and the `dev.cosignproject.cosign/signature` annotation of which is a valid
+//! ECDSA P-256 signature of the payload.
+
+use super::hex;
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use enarx_exec_wasmtime::{MAX_PACKAGE_DATA_SIZE, PACKAGE_CONFIG, PACKAGE_ENTRYPOINT};
+use ring::digest::{digest, SHA256};
+use ring::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_ASN1};
+use serde::Deserialize;
+use url::Url;
+
+/// Maximum size of a manifest in bytes
+const MAX_MANIFEST_SIZE: u64 = 4_000_000;
+/// Maximum size of Enarx.toml in bytes
+const MAX_CONF_SIZE: u64 = 1_000_000;
+/// Maximum size of a signature payload in bytes
+const MAX_PAYLOAD_SIZE: u64 = 1_000_000;
+/// Maximum size of the response of a token endpoint in bytes
+const MAX_TOKEN_SIZE: u64 = 1_000_000;
+
+const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+const WASM_MEDIA_TYPES: [&str; 2] = [
+    "application/wasm",
+    "application/vnd.wasm.content.layer.v1+wasm",
+];
+const TITLE_ANNOTATION: &str = "org.opencontainers.image.title";
+const SIGNATURE_MEDIA_TYPE: &str = "application/vnd.dev.cosign.simplesigning.v1+json";
+const SIGNATURE_ANNOTATION: &str = "dev.cosignproject.cosign/signature";
+
+/// DER-encoded prefix of the `SubjectPublicKeyInfo` of an uncompressed ECDSA P-256 key
+const P256_SPKI_PREFIX: [u8; 26] = [
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a,
+    0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+];
+
+/// Returns the digest of `bytes`, `sha256:` followed by the hex-encoded SHA-256 digest.
+fn sha256(bytes: &[u8]) -> String {
+    format!("sha256:{}", hex(digest(&SHA256, bytes)))
+}
+
+/// Returns whether `digest` is a well-formed SHA-256 digest.
+fn valid_digest(digest: &str) -> bool {
+    digest.strip_prefix("sha256:").map_or(false, |hex| {
+        hex.len() == 64 && hex.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    })
+}
+
+/// A reference to an artifact in a registry, e.g. `oci://ghcr.io/enarx/hello:1.0`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reference {
+    /// Base URL of the registry, plain HTTP is only used for registries on the loopback interface
+    registry: String,
+    repository: String,
+    tag: String,
+    digest: Option<String>,
+}
+
+impl Reference {
+    /// Parses the reference `url` with the `oci` scheme.
+    ///
+    /// The tag defaults to `latest`, a digest given with `@sha256:<digest>` pins the manifest.
+    pub fn from_url(url: &Url) -> Result<Self> {
+        ensure!(
+            url.scheme() == "oci",
+            "`{url}` is not an `oci://` reference"
+        );
+        let host = url
+            .host_str()
+            .with_context(|| format!("`{url}` lacks a registry"))?;
+        let loopback = matches!(host, "localhost" | "127.0.0.1" | "[::1]");
+        let registry = match url.port() {
+            Some(port) => format!("{host}:{port}"),
+            None => host.into(),
+        };
+        let registry = format!("{}://{registry}", if loopback { "http" } else { "https" });
+
+        let path = url.path().trim_start_matches('/');
+        let (path, digest) = match path.split_once('@') {
+            Some((path, digest)) => (path, Some(digest.to_string())),
+            None => (path, None),
+        };
+        let (repository, tag) = match path.rsplit_once(':') {
+            Some((repository, tag)) if !tag.contains('/') => (repository, tag),
+            _ => (path, "latest"),
+        };
+        ensure!(
+            !repository.is_empty()
+                && repository.split('/').all(|c| {
+                    !c.is_empty()
+                        && c.bytes()
+                            .all(|b| matches!(b, b'a'..=b'z' | b'0'..=b'9' | b'.' | b'_' | b'-'))
+                }),
+            "invalid repository `{repository}` in `{url}`"
+        );
+        ensure!(
+            !tag.is_empty()
+                && tag.len() <= 128
+                && !tag.starts_with(['.', '-'])
+                && tag
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-')),
+            "invalid tag `{tag}` in `{url}`"
+        );
+        if let Some(ref digest) = digest {
+            ensure!(valid_digest(digest), "invalid digest `{digest}` in `{url}`");
+        }
+        Ok(Self {
+            registry,
+            repository: repository.into(),
+            tag: tag.into(),
+            digest,
+        })
+    }
+}
+
+/// An ECDSA P-256 public key, which cosign signatures are verified with
+#[derive(Clone, Debug)]
+pub struct Key(Vec<u8>);
+
+impl Key {
+    /// Parses a PEM-encoded `PUBLIC KEY`, as written by `cosign generate-key-pair`.
+    pub fn from_pem(pem: &str) -> Result<Self> {
+        let body = pem
+            .trim()
+            .strip_prefix("-----BEGIN PUBLIC KEY-----")
+            .and_then(|pem| pem.strip_suffix("-----END PUBLIC KEY-----"))
+            .context("expected a PEM-encoded `PUBLIC KEY`")?;
+        let der = base64::decode(body.split_whitespace().collect::<String>())
+            .context("invalid base64 encoding of public key")?;
+        match der.strip_prefix(&P256_SPKI_PREFIX[..]) {
+            Some(point) if point.len() == 65 => Ok(Self(point.to_vec())),
+            _ => bail!("only ECDSA P-256 public keys are supported"),
+        }
+    }
+
+    /// Returns whether `signature` is a valid ASN.1-encoded signature of `payload`.
+    fn verify(&self, payload: &[u8], signature: &[u8]) -> bool {
+        UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, &self.0)
+            .verify(payload, signature)
+            .is_ok()
+    }
+}
+
+/// A content descriptor in a manifest
+#[derive(Debug, Deserialize)]
+struct Descriptor {
+    #[serde(rename = "mediaType")]
+    media_type: String,
+    digest: String,
+    size: u64,
+    #[serde(default)]
+    annotations: BTreeMap<String, String>,
+}
+
+/// An image manifest
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    #[serde(rename = "schemaVersion")]
+    schema_version: u32,
+    #[serde(rename = "mediaType")]
+    media_type: Option<String>,
+    layers: Vec<Descriptor>,
+}
+
+/// The payload of a cosign signature in the simple signing format
+#[derive(Debug, Deserialize)]
+struct Payload {
+    critical: Critical,
+}
+
+#[derive(Debug, Deserialize)]
+struct Critical {
+    image: Image,
+    #[serde(rename = "type")]
+    typ: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Image {
+    #[serde(rename = "docker-manifest-digest")]
+    digest: String,
+}
+
+/// A client of a repository of a registry, which authenticates with anonymous bearer tokens
+struct Registry<'a> {
+    reference: &'a Reference,
+    agent: ureq::Agent,
+    token: Option<String>,
+}
+
+/// Returns the parameters of the `Bearer` challenge in `header`.
+fn challenge(header: &str) -> Option<BTreeMap<String, String>> {
+    let mut params = BTreeMap::new();
+    let mut rest = header.trim().strip_prefix("Bearer ")?.trim_start();
+    while !rest.is_empty() {
+        let (name, tail) = rest.split_once('=')?;
+        let (value, tail) = match tail.strip_prefix('"') {
+            Some(tail) => tail.split_once('"')?,
+            None => tail.split_once(',').unwrap_or((tail, "")),
+        };
+        params.insert(name.trim().to_ascii_lowercase(), value.into());
+        rest = tail.trim_start_matches([',', ' ']);
+    }
+    Some(params)
+}
+
+/// Reads the body of `response`, which must not exceed `limit` bytes.
+fn read(response: ureq::Response, limit: u64) -> Result<Vec<u8>> {
+    let mut body = vec![];
+    response
+        .into_reader()
+        .take(limit + 1)
+        .read_to_end(&mut body)
+        .context("failed to read response")?;
+    ensure!(
+        body.len() as u64 <= limit,
+        "response exceeds the limit of `{limit}` bytes"
+    );
+    Ok(body)
+}
+
+impl<'a> Registry<'a> {
+    fn new(reference: &'a Reference) -> Self {
+        Self {
+            reference,
+            agent: ureq::AgentBuilder::new().build(),
+            token: None,
+        }
+    }
+
+    /// Returns an anonymous token for pulling from the repository, as requested by the
+    /// `WWW-Authenticate` challenge of `response`.
+    fn authenticate(&self, response: &ureq::Response) -> Result<String> {
+        let params = response
+            .header("www-authenticate")
+            .and_then(challenge)
+            .context("registry requires authentication, but sent no bearer challenge")?;
+        let realm = params
+            .get("realm")
+            .context("bearer challenge lacks realm")?;
+        let mut url = Url::parse(realm).with_context(|| format!("invalid realm `{realm}`"))?;
+        let scope = format!("repository:{}:pull", self.reference.repository);
+        url.query_pairs_mut()
+            .append_pair("scope", params.get("scope").unwrap_or(&scope));
+        if let Some(service) = params.get("service") {
+            url.query_pairs_mut().append_pair("service", service);
+        }
+
+        #[derive(Deserialize)]
+        struct Token {
+            token: Option<String>,
+            access_token: Option<String>,
+        }
+        let response = self
+            .agent
+            .request_url("GET", &url)
+            .call()
+            .with_context(|| format!("failed to get token from `{realm}`"))?;
+        let token: Token = serde_json::from_slice(&read(response, MAX_TOKEN_SIZE)?)
+            .context("failed to decode token")?;
+        token
+            .token
+            .or(token.access_token)
+            .context("token response lacks token")
+    }
+
+    /// Gets `path` below the repository, accepting `accept`.
+    fn get(&mut self, path: &str, accept: &str) -> Result<ureq::Response> {
+        let url = format!(
+            "{}/v2/{}/{path}",
+            self.reference.registry, self.reference.repository
+        );
+        let call = |token: &Option<String>| {
+            let request = self.agent.get(&url).set("Accept", accept);
+            match token {
+                Some(token) => request.set("Authorization", &format!("Bearer {token}")),
+                None => request,
+            }
+            .call()
+        };
+        let response = match call(&self.token) {
+            Err(ureq::Error::Status(401, response)) if self.token.is_none() => {
+                self.token = Some(self.authenticate(&response)?);
+                call(&self.token)
+            }
+            res => res,
+        };
+        response.with_context(|| format!("failed to get `{url}`"))
+    }
+
+    /// Returns the manifest tagged or pinned by `reference` and its digest.
+    fn manifest(&mut self, reference: &str) -> Result<(Manifest, String)> {
+        let response = self.get(&format!("manifests/{reference}"), MANIFEST_MEDIA_TYPE)?;
+        let raw = read(response, MAX_MANIFEST_SIZE)?;
+        let manifest: Manifest =
+            serde_json::from_slice(&raw).context("failed to decode manifest")?;
+        ensure!(
+            manifest.schema_version == 2,
+            "unsupported manifest schema version `{}`",
+            manifest.schema_version
+        );
+        if let Some(ref typ) = manifest.media_type {
+            ensure!(
+                typ == MANIFEST_MEDIA_TYPE,
+                "unsupported manifest type `{typ}`"
+            );
+        }
+        Ok((manifest, sha256(&raw)))
+    }
+
+    /// Returns the blob described by `desc`, which must not exceed `limit` bytes.
+    fn blob(&mut self, desc: &Descriptor, limit: u64) -> Result<Vec<u8>> {
+        ensure!(
+            desc.size <= limit,
+            "size of blob `{}` exceeds the limit of `{limit}` bytes",
+            desc.digest
+        );
+        ensure!(
+            valid_digest(&desc.digest),
+            "unsupported digest `{}`",
+            desc.digest
+        );
+        let response = self.get(&format!("blobs/{}", desc.digest), "*/*")?;
+        let blob = read(response, desc.size)?;
+        ensure!(
+            blob.len() as u64 == desc.size && sha256(&blob) == desc.digest,
+            "blob does not match its digest `{}`",
+            desc.digest
+        );
+        Ok(blob)
+    }
+
+    /// Verifies, that the manifest with `digest` is signed with `key`.
+    fn verify(&mut self, digest: &str, key: &Key) -> Result<()> {
+        let tag = format!("{}.sig", digest.replace(':', "-"));
+        let (manifest, _) = self
+            .manifest(&tag)
+            .context("failed to get cosign signatures")?;
+        for layer in manifest
+            .layers
+            .iter()
+            .filter(|l| l.media_type == SIGNATURE_MEDIA_TYPE)
+        {
+            let signature = match layer
+                .annotations
+                .get(SIGNATURE_ANNOTATION)
+                .and_then(|s| base64::decode(s).ok())
+            {
+                Some(signature) => signature,
+                None => continue,
+            };
+            let payload = self.blob(layer, MAX_PAYLOAD_SIZE)?;
+            if !key.verify(&payload, &signature) {
+                continue;
+            }
+            let payload: Payload =
+                serde_json::from_slice(&payload).context("failed to decode signature payload")?;
+            if payload.critical.typ == "cosign container image signature"
+                && payload.critical.image.digest == digest
+            {
+                return Ok(());
+            }
+        }
+        bail!("no valid cosign signature of `{digest}` with the given key")
+    }
+}
+
+/// Writes `blob` to `path`, such that the cache never contains partial files.
+fn store(path: &Path, blob: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("part");
+    let res = fs::write(&tmp, blob)
+        .and_then(|()| fs::rename(&tmp, path))
+        .with_context(|| format!("failed to cache `{}`", path.display()));
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    res
+}
+
+/// Returns whether the file at `path` exists and matches `desc`.
+fn cached(path: &Path, desc: &Descriptor) -> bool {
+    let mut blob = vec![];
+    match File::open(path).and_then(|mut file| file.read_to_end(&mut blob)) {
+        Ok(size) => size as u64 == desc.size && sha256(&blob) == desc.digest,
+        Err(_) => false,
+    }
+}
+
+/// Returns the paths of the main module and of the config of the artifact at `reference`,
+/// which are pulled into `cache`, unless `reuse` allows to use the files cached already.
+///
+/// The main module and the modules of the services must not exceed `limit` bytes. If `key` is
+/// set, the artifact must be signed with it.
+pub fn fetch(
+    cache: &Path,
+    reference: &Reference,
+    limit: u64,
+    key: Option<&Key>,
+    reuse: bool,
+) -> Result<(PathBuf, Option<PathBuf>)> {
+    let mut registry = Registry::new(reference);
+    let (manifest, digest) =
+        registry.manifest(reference.digest.as_deref().unwrap_or(&reference.tag))?;
+    if let Some(ref pinned) = reference.digest {
+        ensure!(
+            digest == *pinned,
+            "manifest digest `{digest}` does not match pinned digest `{pinned}`"
+        );
+    }
+    if let Some(key) = key {
+        registry.verify(&digest, key)?;
+    }
+
+    // An untitled layer is the main module, if it is the only Wasm layer.
+    let wasm_layers = manifest
+        .layers
+        .iter()
+        .filter(|l| WASM_MEDIA_TYPES.contains(&l.media_type.as_str()))
+        .count();
+    let mut files = BTreeMap::new();
+    for layer in manifest.layers.iter() {
+        let name = match layer.annotations.get(TITLE_ANNOTATION) {
+            Some(name) => name.as_str(),
+            None if wasm_layers == 1 && WASM_MEDIA_TYPES.contains(&layer.media_type.as_str()) => {
+                PACKAGE_ENTRYPOINT.as_str()
+            }
+            None => continue,
+        };
+        ensure!(
+            Path::new(name).file_name() == Some(name.as_ref()) && !name.ends_with(".part"),
+            "layer title `{name}` must be a file name"
+        );
+        ensure!(
+            files.insert(name, layer).is_none(),
+            "multiple layers are titled `{name}`"
+        );
+    }
+    if !files.contains_key(PACKAGE_ENTRYPOINT.as_str()) {
+        bail!("artifact has no `{}` layer", PACKAGE_ENTRYPOINT.as_str());
+    }
+
+    let path = cache.join(format!("oci-{}", digest.replace(':', "-")));
+    fs::create_dir_all(&path)
+        .with_context(|| format!("failed to create cache directory `{}`", path.display()))?;
+    let mut remaining = MAX_PACKAGE_DATA_SIZE;
+    for (name, layer) in files.iter() {
+        let limit = if *name == PACKAGE_CONFIG.as_str() {
+            MAX_CONF_SIZE
+        } else if WASM_MEDIA_TYPES.contains(&layer.media_type.as_str()) {
+            limit
+        } else {
+            remaining = remaining.checked_sub(layer.size).ok_or_else(|| {
+                anyhow!("data files exceed the limit of `{MAX_PACKAGE_DATA_SIZE}` bytes")
+            })?;
+            layer.size
+        };
+        let file = path.join(name);
+        if reuse && cached(&file, layer) {
+            continue;
+        }
+        let blob = registry
+            .blob(layer, limit)
+            .with_context(|| format!("failed to get `{name}`"))?;
+        store(&file, &blob)?;
+    }
+
+    let wasm = path.join(PACKAGE_ENTRYPOINT.as_str());
+    let conf = files
+        .contains_key(PACKAGE_CONFIG.as_str())
+        .then(|| path.join(PACKAGE_CONFIG.as_str()));
+    Ok((wasm, conf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+
+    #[test]
+    fn references() {
+        let parse = |s: &str| Reference::from_url(&s.parse().unwrap());
+
+        let reference = parse("oci://ghcr.io/enarx/hello:1.0").unwrap();
+        assert_eq!(reference.registry, "https://ghcr.io");
+        assert_eq!(reference.repository, "enarx/hello");
+        assert_eq!(reference.tag, "1.0");
+        assert_eq!(reference.digest, None);
+
+        let digest = format!("sha256:{}", "ab".repeat(32));
+        let reference = parse(&format!("oci://localhost:5000/hello@{digest}")).unwrap();
+        assert_eq!(reference.registry, "http://localhost:5000");
+        assert_eq!(reference.repository, "hello");
+        assert_eq!(reference.tag, "latest");
+        assert_eq!(reference.digest, Some(digest));
+
+        assert!(parse("oci://ghcr.io/").is_err());
+        assert!(parse("oci://ghcr.io/Enarx/hello").is_err());
+        assert!(parse("oci://ghcr.io/enarx/hello:-1").is_err());
+        assert!(parse("oci://ghcr.io/enarx/hello@sha256:ab").is_err());
+        assert!(parse("https://ghcr.io/enarx/hello").is_err());
+    }
+
+    #[test]
+    fn challenges() {
+        let params = challenge(
+            r#"Bearer realm="https://ghcr.io/token",service="ghcr.io",scope="repository:enarx/hello:pull""#,
+        )
+        .unwrap();
+        assert_eq!(params["realm"], "https://ghcr.io/token");
+        assert_eq!(params["service"], "ghcr.io");
+        assert_eq!(params["scope"], "repository:enarx/hello:pull");
+        assert!(challenge(r#"Basic realm="registry""#).is_none());
+    }
+
+    /// Serves `files` at the paths relative to `/v2/enarx/hello/` of the returned registry,
+    /// which requires a token, and records the requests.
+    fn serve(files: BTreeMap<String, Vec<u8>>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(vec![]));
+        let log = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let path = line.split_whitespace().nth(1).unwrap().to_string();
+                let mut authorized = false;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    authorized |= header
+                        .trim()
+                        .eq_ignore_ascii_case("authorization: Bearer secret");
+                }
+                if path.starts_with("/token?") {
+                    let body = br#"{"token":"secret"}"#;
+                    write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: {}\r\n\r\n",
+                        body.len()
+                    )
+                    .unwrap();
+                    stream.write_all(body).unwrap();
+                    continue;
+                }
+                if !authorized {
+                    write!(stream, "HTTP/1.1 401 Unauthorized\r\nConnection: close\r\nWWW-Authenticate: Bearer realm=\"http://{addr}/token\",service=\"test\"\r\nContent-Length: 0\r\n\r\n").unwrap();
+                    continue;
+                }
+                let path = path.trim_start_matches("/v2/enarx/hello/");
+                log.lock().unwrap().push(path.to_string());
+                match files.get(path) {
+                    Some(body) => {
+                        write!(
+                            stream,
+                            "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: {}\r\n\r\n",
+                            body.len()
+                        )
+                        .unwrap();
+                        stream.write_all(body).unwrap();
+                    }
+                    None => write!(
+                        stream,
+                        "HTTP/1.1 404 Not Found\r\nConnection: close\r\nContent-Length: 0\r\n\r\n"
+                    )
+                    .unwrap(),
+                }
+            }
+        });
+        (
+            format!("oci://localhost:{}/enarx/hello:1.0", addr.port()),
+            requests,
+        )
+    }
+
+    /// Returns the descriptor of `body` of type `media_type` titled `title`.
+    fn layer(media_type: &str, body: &[u8], annotations: &[(&str, &str)]) -> serde_json::Value {
+        serde_json::json!({
+            "mediaType": media_type,
+            "digest": sha256(body),
+            "size": body.len(),
+            "annotations": annotations.iter().cloned().collect::<BTreeMap<_, _>>(),
+        })
+    }
+
+    /// Returns a manifest with `layers`.
+    fn manifest(layers: Vec<serde_json::Value>) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": MANIFEST_MEDIA_TYPE,
+            "config": layer("application/vnd.wasm.config.v0+json", b"{}", &[]),
+            "layers": layers,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn fetching() {
+        let wasm = b"\0asm\x01\0\0\0".to_vec();
+        let conf = b"[[files]]\nkind = \"stdout\"\n".to_vec();
+        let raw = manifest(vec![
+            layer(
+                "application/wasm",
+                &wasm,
+                &[(TITLE_ANNOTATION, "main.wasm")],
+            ),
+            layer(
+                "application/toml",
+                &conf,
+                &[(TITLE_ANNOTATION, "Enarx.toml")],
+            ),
+        ]);
+        let digest = sha256(&raw);
+
+        // Sign the manifest like `cosign sign --key`.
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        let pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref()).unwrap();
+        let spki = [&P256_SPKI_PREFIX[..], pair.public_key().as_ref()].concat();
+        let pem = format!(
+            "-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----\n",
+            base64::encode(spki)
+        );
+        let key = Key::from_pem(&pem).unwrap();
+        let payload = serde_json::to_vec(&serde_json::json!({
+            "critical": {
+                "identity": { "docker-reference": "localhost/enarx/hello" },
+                "image": { "docker-manifest-digest": digest },
+                "type": "cosign container image signature",
+            },
+            "optional": null,
+        }))
+        .unwrap();
+        let signature = base64::encode(pair.sign(&rng, &payload).unwrap());
+        let sig = manifest(vec![layer(
+            SIGNATURE_MEDIA_TYPE,
+            &payload,
+            &[(SIGNATURE_ANNOTATION, &signature)],
+        )]);
+
+        let (url, requests) = serve(BTreeMap::from([
+            ("manifests/1.0".into(), raw.clone()),
+            (format!("manifests/{digest}"), raw),
+            (format!("manifests/{}.sig", digest.replace(':', "-")), sig),
+            (format!("blobs/{}", sha256(&wasm)), wasm.clone()),
+            (format!("blobs/{}", sha256(&conf)), conf.clone()),
+            (format!("blobs/{}", sha256(&payload)), payload),
+        ]));
+        let reference = Reference::from_url(&url.parse().unwrap()).unwrap();
+
+        let cache = tempfile::tempdir().unwrap();
+        let (path, config) = fetch(cache.path(), &reference, 1024, Some(&key), true).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), wasm);
+        assert_eq!(fs::read(config.unwrap()).unwrap(), conf);
+
+        // Cached files are reused.
+        requests.lock().unwrap().clear();
+        fetch(cache.path(), &reference, 1024, None, true).unwrap();
+        assert_eq!(*requests.lock().unwrap(), ["manifests/1.0"]);
+
+        // A pinned digest must match the manifest.
+        let pinned = format!("{url}@{digest}");
+        let reference = Reference::from_url(&pinned.parse().unwrap()).unwrap();
+        fetch(cache.path(), &reference, 1024, None, true).unwrap();
+        let pinned = format!("{url}@sha256:{}", "00".repeat(32));
+        let wrong = Reference::from_url(&pinned.parse().unwrap()).unwrap();
+        assert!(fetch(cache.path(), &wrong, 1024, None, true).is_err());
+
+        // A signature with another key is rejected.
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        let other =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref()).unwrap();
+        let other = Key(other.public_key().as_ref().to_vec());
+        assert!(fetch(cache.path(), &reference, 1024, Some(&other), true).is_err());
+
+        // A module exceeding the limit is rejected.
+        assert!(fetch(cache.path(), &reference, 4, None, false).is_err());
+    }
+}
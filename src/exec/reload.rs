@@ -0,0 +1,139 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Reloading of the network policy of a keep on `SIGHUP`.
+//!
+//! On `SIGHUP`, the host reads the package config again and delivers its `network` section to the
+//! keep as a line of JSON over a pipe. The keep applies it to all requests sent afterwards, unless
+//! it allows more than the config the keep was started with, which the config must allow with
+//! `allow_widening`.
+
+use super::events;
+use super::read_config;
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::thread;
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+
+/// The write end of the pipe, which notifies the reload thread of a `SIGHUP`, if any.
+static TRIGGER_FD: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn request_reload(_signal: libc::c_int) {
+    let fd = TRIGGER_FD.load(Ordering::SeqCst);
+    if fd >= 0 {
+        // A failed write cannot be handled here, the reload is requested again by the next signal.
+        unsafe { libc::write(fd, &0u8 as *const u8 as *const libc::c_void, 1) };
+    }
+}
+
+/// Reloads the network policy of the keep from the package config on `SIGHUP`.
+pub struct Reloader {
+    trigger: File,
+    /// The write end of the pipe of the keep
+    keep: File,
+    config: PathBuf,
+}
+
+impl Reloader {
+    /// Returns the reloader of the package config at `config` and the read end of the pipe to pass
+    /// to the keep.
+    pub fn new(config: PathBuf) -> Result<(Self, File)> {
+        let (reader, keep) = events::pipe().context("failed to create network policy pipe")?;
+        let (trigger, writer) = events::pipe().context("failed to create reload pipe")?;
+        // The write end lives as long as the process, since the signal handler may use it anytime.
+        TRIGGER_FD.store(writer.as_raw_fd(), Ordering::SeqCst);
+        std::mem::forget(writer);
+
+        // SAFETY: `request_reload` is async-signal-safe, since it only performs an atomic load
+        // and a `write`.
+        let ret = unsafe {
+            let mut act: libc::sigaction = std::mem::zeroed();
+            act.sa_sigaction = request_reload as libc::sighandler_t;
+            act.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut act.sa_mask);
+            libc::sigaction(libc::SIGHUP, &act, std::ptr::null_mut())
+        };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error())
+                .context("failed to install handler for SIGHUP");
+        }
+        Ok((
+            Self {
+                trigger,
+                keep,
+                config,
+            },
+            reader,
+        ))
+    }
+
+    /// Delivers the `network` section of the config to the keep.
+    fn reload(&mut self) -> Result<()> {
+        let network = read_config(&self.config)?.network;
+        let mut line = serde_json::to_vec(&network).context("failed to encode network policy")?;
+        line.push(b'\n');
+        self.keep
+            .write_all(&line)
+            .context("failed to deliver network policy to the keep")?;
+        info!(
+            "reloaded network policy from `{}`: {:?}",
+            self.config.display(),
+            network.outgoing
+        );
+        Ok(())
+    }
+
+    /// Spawns a thread, which reloads the network policy on every `SIGHUP` for the rest of the
+    /// lifetime of the process.
+    pub fn spawn(mut self) -> Result<()> {
+        thread::Builder::new()
+            .name("reload".into())
+            .spawn(move || {
+                let mut signal = [0];
+                while let Ok(1) = self.trigger.read(&mut signal) {
+                    if let Err(e) = self.reload() {
+                        warn!("failed to reload network policy: {e:#}");
+                    }
+                }
+            })
+            .context("failed to spawn reload thread")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Reloader;
+
+    use std::fs;
+    use std::io::{BufRead, BufReader};
+
+    use enarx_config::Network;
+
+    #[test]
+    fn sighup() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("Enarx.toml");
+        fs::write(&config, "[network]\noutgoing = [\"api.example.com\"]\n").unwrap();
+
+        let (reloader, keep) = Reloader::new(config.clone()).unwrap();
+        reloader.spawn().unwrap();
+        let mut keep = BufReader::new(keep);
+
+        let mut reloaded = || {
+            assert_eq!(unsafe { libc::raise(libc::SIGHUP) }, 0);
+            let mut line = String::new();
+            keep.read_line(&mut line).unwrap();
+            serde_json::from_str::<Network>(&line).unwrap()
+        };
+        assert_eq!(reloaded().outgoing, vec!["api.example.com"]);
+
+        fs::write(&config, "").unwrap();
+        assert_eq!(reloaded(), Network::default());
+    }
+}
@@ -0,0 +1,178 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Running replicas of a keep from a single `enarx run` invocation
+//!
+//! Every replica is a child `enarx run` process with the arguments of the parent, except for
+//! `--replicas`. The lines written by a replica are prefixed with its index, and the first
+//! replica to fail terminates all others.
+
+use std::ffi::OsString;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::process::{Child, Command, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use log::warn;
+
+/// Interval at which the replicas are checked for having exited
+const TICK: Duration = Duration::from_millis(100);
+
+/// Checks whether `replicas` keeps can be run at once on this host.
+///
+/// Every keep runs on a dedicated thread, so keeps beyond the number of CPUs compete for them,
+/// which is only worth a warning, since most workloads spend their time waiting for I/O.
+pub fn check(replicas: usize) -> Result<()> {
+    if replicas == 0 {
+        bail!("at least one replica is required");
+    }
+    match thread::available_parallelism() {
+        Ok(cpus) if replicas > cpus.get() => {
+            warn!("{replicas} replicas exceed the {cpus} CPUs of this host")
+        }
+        Ok(_) => {}
+        Err(e) => warn!("failed to determine the number of CPUs: {e}"),
+    }
+    Ok(())
+}
+
+/// Returns `args` without `--replicas` and its value.
+fn args(args: impl IntoIterator<Item = OsString>) -> Vec<OsString> {
+    let mut args = args.into_iter();
+    let mut filtered = vec![];
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--replicas") => {
+                args.next();
+            }
+            Some(arg) if arg.starts_with("--replicas=") => {}
+            _ => filtered.push(arg),
+        }
+    }
+    filtered
+}
+
+/// Forwards the lines read from `reader` to `writer`, prefixed with `prefix`.
+fn forward(
+    prefix: String,
+    reader: impl Read + Send + 'static,
+    mut writer: impl Write + Send + 'static,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        for line in BufReader::new(reader).split(b'\n') {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => return,
+            };
+            // Write every line at once, such that the lines of replicas are not interleaved.
+            let line = [prefix.as_bytes(), &line, b"\n"].concat();
+            if writer.write_all(&line).is_err() {
+                return;
+            }
+        }
+    })
+}
+
+/// Terminates all `children`, which have not exited yet.
+fn terminate(children: &mut [Child]) {
+    for child in children {
+        // The replica may have exited already.
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+/// Runs `replicas` replicas of the `enarx run` invocation of this process and returns the exit
+/// code of the first replica to fail, or 0 if all of them succeed.
+///
+/// The replicas do not read stdin, since there is no way to tell which of them input is for.
+pub fn run(replicas: usize) -> Result<i32> {
+    let exe = std::env::current_exe().context("failed to locate the enarx executable")?;
+    let args = args(std::env::args_os().skip(1));
+
+    let mut children = Vec::with_capacity(replicas);
+    let mut forwarders = vec![];
+    for i in 0..replicas {
+        let child = Command::new(&exe)
+            .args(&args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("failed to launch replica {i}"));
+        let mut child = match child {
+            Ok(child) => child,
+            Err(e) => {
+                terminate(&mut children);
+                return Err(e);
+            }
+        };
+        let prefix = format!("[{i}] ");
+        if let Some(stdout) = child.stdout.take() {
+            forwarders.push(forward(prefix.clone(), stdout, io::stdout()));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            forwarders.push(forward(prefix, stderr, io::stderr()));
+        }
+        children.push(child);
+    }
+
+    let mut exited = vec![false; replicas];
+    let code = 'wait: loop {
+        for (i, child) in children.iter_mut().enumerate() {
+            if exited[i] {
+                continue;
+            }
+            let status = match child.try_wait() {
+                Ok(Some(status)) => status,
+                Ok(None) => continue,
+                Err(e) => {
+                    eprintln!("failed to wait for replica {i}: {e}");
+                    break 'wait 1;
+                }
+            };
+            exited[i] = true;
+            if !status.success() {
+                eprintln!("replica {i} failed with {status}, terminating all replicas");
+                break 'wait status.code().unwrap_or(1);
+            }
+        }
+        if exited.iter().all(|exited| *exited) {
+            break 0;
+        }
+        thread::sleep(TICK);
+    };
+
+    terminate(&mut children);
+    for forwarder in forwarders {
+        let _ = forwarder.join();
+    }
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arguments() {
+        let filtered = args(
+            [
+                "run",
+                "--replicas",
+                "3",
+                "--backend=nil",
+                "--replicas=4",
+                "main.wasm",
+            ]
+            .map(OsString::from),
+        );
+        assert_eq!(filtered, ["run", "--backend=nil", "main.wasm"]);
+    }
+
+    #[test]
+    fn checks() {
+        assert!(check(0).is_err());
+        assert!(check(1).is_ok());
+        assert!(check(usize::MAX).is_ok());
+    }
+}
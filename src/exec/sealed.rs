@@ -0,0 +1,129 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Cache of the compiled main modules sealed by keeps to their platform
+//!
+//! The host cannot read sealed modules, so it only stores the artifact written by a keep and
+//! passes it to the next keep running the same module on the same backend, which validates it
+//! against the module and the platform before loading it. The artifacts are stored in the
+//! `compiled` directory of the cache, named after the backend and the digest of the module.
+
+use super::hex;
+
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use ring::digest::{digest, SHA256};
+use url::Url;
+
+/// Returns the directory of the cache of compiled modules.
+fn dir() -> Result<PathBuf> {
+    super::cache::dir()
+        .context("failed to locate the cache, neither `XDG_CACHE_HOME` nor `HOME` is set")
+}
+
+/// Returns the cache entry of the main module at `path` on `backend`.
+pub fn local(backend: &str, path: impl AsRef<Path>) -> Result<Sealed> {
+    let path = path.as_ref();
+    let wasm = fs::read(path)
+        .with_context(|| format!("failed to read WASM module at `{}`", path.display()))?;
+    Ok(Sealed::new(&dir()?, backend, &wasm))
+}
+
+/// Returns the cache entry of the main module fetched by the keep from `url` on `backend`.
+pub fn remote(backend: &str, url: &Url) -> Result<Sealed> {
+    Ok(Sealed::new(&dir()?, backend, url.as_str().as_bytes()))
+}
+
+/// Cache entry of the sealed compiled main module of a keep
+#[derive(Debug)]
+pub struct Sealed {
+    /// Path of the cached artifact
+    path: PathBuf,
+    /// Path of the artifact written by the keep, which replaces the cached one
+    part: PathBuf,
+}
+
+impl Sealed {
+    /// Returns the entry of the module identified by `source` on `backend` in the cache at `dir`.
+    ///
+    /// `source` is the module itself, or its URL for modules fetched by the keep.
+    pub fn new(dir: &Path, backend: &str, source: &[u8]) -> Self {
+        let hex = hex(digest(&SHA256, source));
+        let path = dir
+            .join("compiled")
+            .join(format!("{backend}-sha-256-{hex}"));
+        // Every keep writes to its own file, such that concurrent keeps do not interfere.
+        let part = path.with_extension(format!("{}.part", std::process::id()));
+        Self { path, part }
+    }
+
+    /// Opens the cached artifact, if any, and creates the file to write a new one to.
+    pub fn open(&self) -> Result<(Option<File>, File)> {
+        let cached = match File::open(&self.path) {
+            Ok(file) => Some(file),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to open `{}`", self.path.display()))
+            }
+        };
+        if let Some(dir) = self.part.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create `{}`", dir.display()))?;
+        }
+        let part = File::create(&self.part)
+            .with_context(|| format!("failed to create `{}`", self.part.display()))?;
+        Ok((cached, part))
+    }
+
+    /// Replaces the cached artifact with the one written by the keep, if any.
+    pub fn commit(&self) -> Result<()> {
+        let written = fs::metadata(&self.part)
+            .map(|md| md.len() > 0)
+            .unwrap_or_default();
+        if written {
+            fs::rename(&self.part, &self.path)
+                .with_context(|| format!("failed to write `{}`", self.path.display()))
+        } else {
+            match fs::remove_file(&self.part) {
+                Err(e) if e.kind() != ErrorKind::NotFound => {
+                    Err(e).with_context(|| format!("failed to remove `{}`", self.part.display()))
+                }
+                _ => Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    #[test]
+    fn caching() {
+        let dir = tempfile::tempdir().unwrap();
+        let sealed = Sealed::new(dir.path(), "sgx", b"module");
+        assert_ne!(sealed.path, Sealed::new(dir.path(), "sev", b"module").path);
+        assert_ne!(sealed.path, Sealed::new(dir.path(), "sgx", b"other").path);
+
+        // Nothing is cached, until a keep writes an artifact.
+        let (cached, _) = sealed.open().unwrap();
+        assert!(cached.is_none());
+        sealed.commit().unwrap();
+        assert!(!sealed.path.exists());
+        assert!(!sealed.part.exists());
+
+        let (_, mut part) = sealed.open().unwrap();
+        part.write_all(b"sealed").unwrap();
+        sealed.commit().unwrap();
+        assert_eq!(fs::read(&sealed.path).unwrap(), b"sealed");
+
+        // A keep, which loaded the cached artifact, does not write a new one.
+        let (cached, _) = sealed.open().unwrap();
+        assert!(cached.is_some());
+        sealed.commit().unwrap();
+        assert_eq!(fs::read(&sealed.path).unwrap(), b"sealed");
+    }
+}
@@ -0,0 +1,250 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Host-side HTTP endpoint exposing the status of a keep.
+//!
+//! The status is the one the keep reports in its events, which are relayed to the events target.
+//! Unlike the status server configured by `[status]` in `Enarx.toml`, which runs in a thread of
+//! the keep, the endpoint is served by the host, so it is also available for keeps, which cannot
+//! create threads, but it is served without TLS and only as trustworthy as the host:
+//!
+//! * `GET /health` returns `{"status":"SERVING"}` with `200 OK`, or `{"status":"NOT_SERVING"}`
+//!   with `503 Service Unavailable`, following the statuses of the gRPC health checking protocol.
+//! * `GET /identity` returns the identity of the keep reported once it was attested, its backend
+//!   and its measurement.
+
+use super::events::Events;
+use super::metrics::read_request;
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use enarx_exec_wasmtime::Event;
+use log::warn;
+use serde_json::json;
+
+/// Timeout for reading a request from and writing a response to a client.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The status of a keep reported in its events
+#[derive(Default)]
+struct Status {
+    backend: Option<String>,
+    measurement: Option<String>,
+    /// The identity of the keep, once it was attested
+    identity: Option<serde_json::Value>,
+    /// Whether the workload signals its readiness, once it was started
+    signals_ready: Option<bool>,
+    ready: Option<bool>,
+    healthy: Option<bool>,
+    exited: bool,
+}
+
+impl Status {
+    /// Returns whether the workload is serving, i.e. it was started and neither signalled that it
+    /// is not ready nor that it is not healthy, and if it signals its readiness, signalled that it
+    /// is ready.
+    fn serving(&self) -> bool {
+        match self.signals_ready {
+            Some(signals_ready) if !self.exited => {
+                self.healthy != Some(false) && self.ready.unwrap_or(!signals_ready)
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the status and the body of the response to `GET` of `path`.
+    fn get(&self, path: &str) -> (&'static str, String) {
+        match path.split('?').next().unwrap_or_default() {
+            "/health" if self.serving() => ("200 OK", json!({ "status": "SERVING" }).to_string()),
+            "/health" => (
+                "503 Service Unavailable",
+                json!({ "status": "NOT_SERVING" }).to_string(),
+            ),
+            "/identity" => match self.identity {
+                Some(ref identity) => {
+                    let mut identity = identity.clone();
+                    identity["backend"] = json!(self.backend);
+                    identity["measurement"] = json!(self.measurement);
+                    ("200 OK", identity.to_string())
+                }
+                None => ("503 Service Unavailable", String::new()),
+            },
+            _ => ("404 Not Found", String::new()),
+        }
+    }
+
+    /// Records `event`.
+    fn record(&mut self, event: Event) {
+        match event {
+            Event::BackendSelected { backend, .. } => self.backend = Some(backend),
+            Event::KeepMeasured { measurement } => self.measurement = measurement,
+            Event::Attested {
+                identity,
+                certificate,
+                steward,
+                serial,
+                workload,
+            } => {
+                self.identity = Some(json!({
+                    "identity": identity,
+                    "certificate": certificate,
+                    "steward": steward,
+                    "serial": serial,
+                    "workload": workload,
+                }))
+            }
+            Event::Started { signals_ready } => self.signals_ready = Some(signals_ready),
+            Event::Ready { ready } => self.ready = Some(ready),
+            Event::Healthy { healthy } => self.healthy = Some(healthy),
+            Event::Exited { .. } => self.exited = true,
+            _ => {}
+        }
+    }
+}
+
+/// Answers a single request on `stream`.
+fn respond(mut stream: TcpStream, status: &Mutex<Status>) -> Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+    let (code, body) = match read_request(&stream)? {
+        (method, _) if method != "GET" => ("405 Method Not Allowed", String::new()),
+        (_, path) => status.lock().unwrap().get(&path),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {code}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+    .context("failed to write response")
+}
+
+/// Serves the status of a keep on `listener` in a separate thread and spawns a thread recording
+/// the events of the keep read from `reader`, which are forwarded to `events`, until it exits.
+pub fn spawn(
+    listener: TcpListener,
+    reader: File,
+    mut events: Option<Events>,
+) -> Result<thread::JoinHandle<()>> {
+    let status = Arc::new(Mutex::new(Status::default()));
+    thread::Builder::new()
+        .name("status".into())
+        .spawn({
+            let status = status.clone();
+            move || {
+                for stream in listener.incoming() {
+                    let res = stream
+                        .context("failed to accept connection")
+                        .and_then(|stream| respond(stream, &status));
+                    if let Err(e) = res {
+                        warn!("failed to serve keep status: {e:#}");
+                    }
+                }
+            }
+        })
+        .context("failed to spawn status thread")?;
+
+    thread::Builder::new()
+        .name("status-events".into())
+        .spawn(move || {
+            for line in BufReader::new(reader).lines() {
+                let event = match line.map(|line| serde_json::from_str::<Event>(&line)) {
+                    Ok(Ok(event)) => event,
+                    Ok(Err(e)) => {
+                        warn!("failed to decode keep event: {e}");
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!("failed to read keep events: {e}");
+                        break;
+                    }
+                };
+                if let Some(ref mut events) = events {
+                    events.emit(event.clone());
+                }
+                status.lock().unwrap().record(event);
+            }
+        })
+        .context("failed to spawn status events thread")
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::events;
+    use super::spawn;
+
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::Duration;
+
+    use enarx_exec_wasmtime::Event;
+
+    #[test]
+    fn endpoint() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (reader, writer) = events::pipe().unwrap();
+        let (mut forwarded, target) = events::pipe().unwrap();
+        let relay = spawn(listener, reader, Some(target.into())).unwrap();
+        let mut keep = events::Events::from(writer);
+
+        let get = |path: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        // Waits until the relay recorded the events emitted so far.
+        let get_after = |path: &str, expected: &str| loop {
+            let response = get(path);
+            if response.starts_with(expected) {
+                break response;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+
+        assert!(get("/health").starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(get("/identity").starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(get("/").starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        keep.emit(Event::BackendSelected {
+            backend: "sgx".into(),
+            skipped: vec![],
+        });
+        keep.emit(Event::Attested {
+            identity: "08f5".into(),
+            certificate: "ab12".into(),
+            steward: None,
+            serial: "01".into(),
+            workload: "sha256:00".into(),
+        });
+        let response = get_after("/identity", "HTTP/1.1 200 OK\r\n");
+        let body = response.split("\r\n\r\n").nth(1).unwrap();
+        let identity: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(identity["identity"], "08f5");
+        assert_eq!(identity["backend"], "sgx");
+
+        keep.emit(Event::Started {
+            signals_ready: true,
+        });
+        keep.emit(Event::Ready { ready: true });
+        let response = get_after("/health", "HTTP/1.1 200 OK\r\n");
+        assert!(response.ends_with(r#"{"status":"SERVING"}"#));
+
+        keep.emit(Event::Healthy { healthy: false });
+        get_after("/health", "HTTP/1.1 503 Service Unavailable\r\n");
+
+        drop(keep);
+        relay.join().unwrap();
+        let mut relayed = String::new();
+        forwarded.read_to_string(&mut relayed).unwrap();
+        assert_eq!(relayed.lines().count(), 5);
+    }
+}
@@ -0,0 +1,292 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Running keeps as systemd services
+//!
+//! A keep started by systemd notifies the service manager of its readiness according to the
+//! events of the keep and pings the watchdog of the service, while the workload is healthy, see
+//! `sd_notify(3)`. Listen sockets passed by socket activation, see `sd_listen_fds(3)`, are
+//! handed to the keep, which accepts connections on them instead of binding sockets of its own.
+
+use super::events::{self, Events};
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use enarx_exec_wasmtime::Event;
+use log::warn;
+
+/// The first FD passed by socket activation
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Returns whether the process ID in `pid` is the one of this process.
+fn ours(pid: &str) -> bool {
+    pid.parse() == Ok(process::id())
+}
+
+/// Returns whether `fd` is a stream socket.
+///
+/// The keep cannot query the type of a socket through the shims, so the host checks it.
+fn stream(fd: RawFd) -> io::Result<bool> {
+    let mut typ: libc::c_int = 0;
+    let mut len = mem::size_of_val(&typ) as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_TYPE,
+            &mut typ as *mut _ as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(typ == libc::SOCK_STREAM)
+}
+
+/// Takes the listen sockets passed by socket activation keyed by the names set with
+/// `FileDescriptorName=` in the socket unit.
+///
+/// The sockets are moved to FDs not conflicting with the socket pair to exec-wasmtime. Like the
+/// variables of the notification socket, the variables of socket activation are removed from
+/// the environment, such that they are neither passed to the workload nor to other processes.
+pub fn listeners() -> Result<BTreeMap<String, RawFd>> {
+    let pid = env::var("LISTEN_PID");
+    let fds = env::var("LISTEN_FDS");
+    let names = env::var("LISTEN_FDNAMES");
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(var);
+    }
+
+    let mut listeners = BTreeMap::new();
+    let fds = match (pid, fds) {
+        (Ok(pid), Ok(fds)) if ours(&pid) => fds,
+        _ => return Ok(listeners),
+    };
+    let count: RawFd = fds
+        .parse()
+        .with_context(|| format!("invalid `LISTEN_FDS` `{fds}`"))?;
+    let names = names.unwrap_or_default();
+    let mut names = names.split(':');
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+        let name = names.next().filter(|n| !n.is_empty()).unwrap_or("unknown");
+        if !stream(fd)
+            .with_context(|| format!("FD `{fd}` passed by socket activation is not a socket"))?
+        {
+            bail!("socket `{name}` passed by socket activation is not a stream socket, only TCP sockets are supported");
+        }
+        let moved = events::dup(fd)?;
+        unsafe { libc::close(fd) };
+        if listeners.insert(name.to_string(), moved).is_some() {
+            bail!("socket activation passed multiple sockets named `{name}`, name them with `FileDescriptorName=`");
+        }
+    }
+    Ok(listeners)
+}
+
+/// The notification socket of the service manager, which started the keep
+pub struct Notifier {
+    socket: UnixDatagram,
+    /// Address of the socket, which starts with a NUL byte for abstract sockets
+    addr: Vec<u8>,
+    /// Interval, within which the watchdog of the service must be pinged
+    watchdog: Option<Duration>,
+}
+
+impl Notifier {
+    /// Returns the notification socket, if the keep was started by a service manager.
+    pub fn from_env() -> Result<Option<Self>> {
+        let addr = env::var_os("NOTIFY_SOCKET");
+        let usec = env::var("WATCHDOG_USEC");
+        let pid = env::var("WATCHDOG_PID");
+        for var in ["NOTIFY_SOCKET", "WATCHDOG_USEC", "WATCHDOG_PID"] {
+            env::remove_var(var);
+        }
+
+        let mut addr = match addr {
+            Some(addr) if !addr.is_empty() => addr.as_bytes().to_vec(),
+            _ => return Ok(None),
+        };
+        if addr[0] == b'@' {
+            addr[0] = 0;
+        }
+        let watchdog = match usec {
+            Ok(usec) if pid.map_or(true, |pid| ours(&pid)) => {
+                Some(Duration::from_micros(usec.parse().with_context(|| {
+                    format!("invalid `WATCHDOG_USEC` `{usec}`")
+                })?))
+            }
+            _ => None,
+        };
+        let socket = UnixDatagram::unbound().context("failed to create notification socket")?;
+        // Move the socket out of the way of the socket pair to exec-wasmtime.
+        let socket = unsafe { UnixDatagram::from_raw_fd(events::dup(socket.as_raw_fd())?) };
+        Ok(Some(Self {
+            socket,
+            addr,
+            watchdog,
+        }))
+    }
+
+    /// Sends the newline-separated assignments in `state`, e.g. `READY=1`, to the service manager.
+    fn notify(&self, state: &str) -> io::Result<()> {
+        // `UnixDatagram::send_to` does not support abstract sockets.
+        let mut sun: libc::sockaddr_un = unsafe { mem::zeroed() };
+        if self.addr.len() >= sun.sun_path.len() {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        sun.sun_family = libc::AF_UNIX as _;
+        for (dst, src) in sun.sun_path.iter_mut().zip(&self.addr) {
+            *dst = *src as _;
+        }
+        let len = sun.sun_path.as_ptr() as usize - &sun as *const _ as usize + self.addr.len();
+        let ret = unsafe {
+            libc::sendto(
+                self.socket.as_raw_fd(),
+                state.as_ptr().cast(),
+                state.len(),
+                0,
+                &sun as *const libc::sockaddr_un as *const libc::sockaddr,
+                len as libc::socklen_t,
+            )
+        };
+        match ret {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sends `state` to the service manager, failures are only logged, since they must not
+    /// affect the keep.
+    fn send(&self, state: &str) {
+        if let Err(e) = self.notify(state) {
+            warn!("failed to notify service manager: {e}");
+        }
+    }
+
+    /// Spawns a thread notifying the service manager of the events of the keep read from
+    /// `reader`, which are forwarded to `events`, until the keep exits.
+    ///
+    /// The service is ready once the workload started, or if it has a `kind = "ready"` file, once
+    /// it signalled its readiness. The watchdog is pinged at half its interval, while the workload
+    /// did not signal that it is unhealthy.
+    pub fn spawn(
+        self,
+        reader: File,
+        mut events: Option<Events>,
+    ) -> io::Result<thread::JoinHandle<()>> {
+        let notifier = Arc::new(self);
+        let healthy = Arc::new(AtomicBool::new(true));
+        let done = Arc::new(AtomicBool::new(false));
+
+        if let Some(interval) = notifier.watchdog {
+            let (notifier, healthy, done) = (notifier.clone(), healthy.clone(), done.clone());
+            thread::Builder::new()
+                .name("watchdog".into())
+                .spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        if healthy.load(Ordering::Relaxed) {
+                            notifier.send("WATCHDOG=1");
+                        }
+                        thread::sleep(interval / 2);
+                    }
+                })?;
+        }
+
+        thread::Builder::new().name("notify".into()).spawn(move || {
+            for line in BufReader::new(reader).lines() {
+                let event = match line.map(|line| serde_json::from_str::<Event>(&line)) {
+                    Ok(Ok(event)) => event,
+                    Ok(Err(e)) => {
+                        warn!("failed to decode keep event: {e}");
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!("failed to read keep events: {e}");
+                        break;
+                    }
+                };
+                if let Some(ref mut events) = events {
+                    events.emit(event.clone());
+                }
+                match event {
+                    Event::Attested { identity, .. } => {
+                        notifier.send(&format!("STATUS=Attested as {identity}"))
+                    }
+                    Event::Started {
+                        signals_ready: false,
+                    }
+                    | Event::Ready { ready: true } => notifier.send("READY=1\nSTATUS=Ready"),
+                    Event::Started {
+                        signals_ready: true,
+                    } => notifier.send("STATUS=Waiting for the workload to become ready"),
+                    Event::Ready { ready: false } => notifier.send("STATUS=Not ready"),
+                    Event::Healthy { healthy: status } => {
+                        healthy.store(status, Ordering::Relaxed);
+                        notifier.send(if status {
+                            "STATUS=Healthy"
+                        } else {
+                            "STATUS=Unhealthy"
+                        });
+                    }
+                    _ => {}
+                }
+            }
+            done.store(true, Ordering::Relaxed);
+            notifier.send("STOPPING=1");
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notify() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify.sock");
+        let manager = UnixDatagram::bind(&path).unwrap();
+        let (reader, writer) = events::pipe().unwrap();
+
+        let notifier = Notifier {
+            socket: UnixDatagram::unbound().unwrap(),
+            addr: path.as_os_str().as_bytes().to_vec(),
+            watchdog: None,
+        };
+        let thread = notifier.spawn(reader, None).unwrap();
+        let mut writer = Events::from(writer);
+        writer.emit(Event::Started {
+            signals_ready: true,
+        });
+        writer.emit(Event::Ready { ready: true });
+        drop(writer);
+        thread.join().unwrap();
+
+        let mut received = vec![];
+        let mut buf = [0; 256];
+        for _ in 0..3 {
+            let n = manager.recv(&mut buf).unwrap();
+            received.push(String::from_utf8(buf[..n].to_vec()).unwrap());
+        }
+        assert_eq!(
+            received,
+            [
+                "STATUS=Waiting for the workload to become ready",
+                "READY=1\nSTATUS=Ready",
+                "STOPPING=1",
+            ]
+        );
+    }
+}
@@ -13,10 +13,17 @@ mod drawbridge;
 mod exec;
 #[cfg(enarx_with_shim)]
 mod protobuf;
+mod time;
+#[cfg(enarx_with_shim)]
+mod verify;
 
 use clap::Parser;
 
-fn main() -> anyhow::Result<()> {
+fn main() {
     let app = cli::Options::parse();
-    app.execute()
+    let format = app.error_format();
+    if let Err(e) = app.execute() {
+        format.report(&e);
+        std::process::exit(1);
+    }
 }
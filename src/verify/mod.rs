@@ -0,0 +1,226 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Offline verification of the attestation evidence of keeps
+//!
+//! Keeps attest themselves with evidence of their technology, an SGX quote or an SEV-SNP
+//! attestation report together with the VCEK certificate, which is embedded in their
+//! certificate signing request or yielded by the `attestation` file. The signatures of the
+//! evidence are verified up to a trusted root certificate of the vendor, such that relying parties
+//! can check it without running a Steward.
+//!
+//! The collateral of the vendor, which states whether the TCB of the platform is up to date and
+//! whether its certificates are revoked, is not fetched. The evidence is only verified up to its
+//! signatures then and the collateral left unchecked is reported in [`Verified::unchecked`].
+
+pub mod sgx;
+pub mod snp;
+
+use std::time::SystemTime;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use ring::signature::{
+    UnparsedPublicKey, VerificationAlgorithm, ECDSA_P256_SHA256_ASN1, ECDSA_P384_SHA384_ASN1,
+    RSA_PKCS1_2048_8192_SHA256, RSA_PKCS1_2048_8192_SHA384, RSA_PSS_2048_8192_SHA384,
+};
+use x509_cert::der::asn1::ObjectIdentifier;
+use x509_cert::der::{Decode, Encode};
+use x509_cert::ext::pkix::BasicConstraints;
+use x509_cert::Certificate;
+
+const ECDSA_WITH_SHA_256: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.2");
+const ECDSA_WITH_SHA_384: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.3");
+const SHA_256_WITH_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.11");
+const SHA_384_WITH_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.12");
+const RSASSA_PSS: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.10");
+const BASIC_CONSTRAINTS: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.5.29.19");
+
+/// The claims of verified evidence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verified {
+    /// The technology of the keep, `"sgx"` or `"sev"`
+    pub technology: &'static str,
+    /// SGX `MRENCLAVE` or SEV-SNP `MEASUREMENT`, as printed by `enarx measure`
+    pub measurement: Vec<u8>,
+    /// SGX `MRSIGNER` or SEV-SNP `ID_KEY_DIGEST`
+    pub signer: Vec<u8>,
+    /// The data the evidence is bound to, e.g. the hash of the public key of the keep
+    pub report_data: Vec<u8>,
+    /// Whether the keep can be debugged, in which case its memory is not confidential
+    pub debug: bool,
+    /// The collateral, which was not checked, e.g. `"tcb_info"`, such that only the signatures of
+    /// the evidence are verified, if not empty
+    pub unchecked: &'static [&'static str],
+}
+
+impl Verified {
+    /// Returns `"verified"`, if all collateral was checked, or `"signature_only"` otherwise.
+    pub fn status(&self) -> &'static str {
+        if self.unchecked.is_empty() {
+            "verified"
+        } else {
+            "signature_only"
+        }
+    }
+}
+
+/// Verifies `evidence`, detecting its technology, up to the root certificate `root`.
+///
+/// For SEV-SNP, `chain` is the certificate chain of the VCEK, the ASK and the ARK, the latter of
+/// which must be `root`. For SGX, the certificate chain is part of the quote and `chain` is
+/// ignored.
+pub fn verify(evidence: &[u8], chain: &[Vec<u8>], root: &[u8]) -> Result<Verified> {
+    match evidence.first() {
+        // The SEV-SNP evidence is a DER-encoded sequence.
+        Some(0x30) => snp::verify(evidence, chain, root),
+        Some(_) => sgx::verify(evidence, root),
+        None => bail!("empty evidence"),
+    }
+}
+
+/// Returns the verification algorithm of a signature `algorithm` of a certificate.
+fn algorithm(algorithm: ObjectIdentifier) -> Result<&'static dyn VerificationAlgorithm> {
+    match algorithm {
+        ECDSA_WITH_SHA_256 => Ok(&ECDSA_P256_SHA256_ASN1),
+        ECDSA_WITH_SHA_384 => Ok(&ECDSA_P384_SHA384_ASN1),
+        SHA_256_WITH_RSA => Ok(&RSA_PKCS1_2048_8192_SHA256),
+        SHA_384_WITH_RSA => Ok(&RSA_PKCS1_2048_8192_SHA384),
+        // The only parameters used by AMD, which are part of the signature verification.
+        RSASSA_PSS => Ok(&RSA_PSS_2048_8192_SHA384),
+        oid => bail!("unsupported certificate signature algorithm {oid}"),
+    }
+}
+
+/// Verifies that the DER-encoded certificate `crt` is signed by `issuer`.
+fn verify_signed(crt: &Certificate<'_>, issuer: &Certificate<'_>) -> Result<()> {
+    let algo = algorithm(crt.signature_algorithm.oid)?;
+    let key = issuer
+        .tbs_certificate
+        .subject_public_key_info
+        .subject_public_key;
+    let body = crt.tbs_certificate.to_vec()?;
+    let signature = crt
+        .signature
+        .as_bytes()
+        .ok_or_else(|| anyhow!("invalid certificate signature"))?;
+    UnparsedPublicKey::new(algo, key)
+        .verify(&body, signature)
+        .map_err(|_| anyhow!("invalid certificate signature"))
+}
+
+/// Returns whether the certificate `crt` may issue certificates, i.e. its basic constraints mark it
+/// as a CA.
+fn is_ca(crt: &Certificate<'_>) -> bool {
+    crt.tbs_certificate
+        .extensions
+        .iter()
+        .flatten()
+        .filter(|ext| ext.extn_id == BASIC_CONSTRAINTS)
+        .any(|ext| matches!(BasicConstraints::from_der(ext.extn_value), Ok(bc) if bc.ca))
+}
+
+/// Verifies the DER-encoded certificate `chain`, leaf first, up to the root certificate `root`,
+/// which must be the last one in the chain, at `now`. Every issuer must be a CA.
+fn verify_chain(chain: &[Vec<u8>], root: &[u8], now: SystemTime) -> Result<()> {
+    ensure!(
+        chain.last().map(Vec::as_slice) == Some(root),
+        "the certificate chain does not end in the trusted root certificate"
+    );
+    let crts = chain
+        .iter()
+        .map(|crt| Certificate::from_der(crt))
+        .collect::<Result<Vec<_>, _>>()
+        .context("invalid certificate")?;
+    for (i, crt) in crts.iter().enumerate() {
+        let validity = crt.tbs_certificate.validity;
+        ensure!(
+            validity.not_before.to_system_time() <= now
+                && now <= validity.not_after.to_system_time(),
+            "certificate {i} of the chain is not valid at this time"
+        );
+        // The root certificate is self-signed.
+        let issuer = crts.get(i + 1).unwrap_or(crt);
+        ensure!(
+            is_ca(issuer),
+            "the issuer of certificate {i} of the chain is not a CA"
+        );
+        verify_signed(crt, issuer)
+            .with_context(|| format!("certificate {i} of the chain is not signed by its issuer"))?;
+    }
+    Ok(())
+}
+
+/// Returns the DER-encoded certificates in `pem` in order.
+pub fn certificates(pem: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let crts = rustls_pemfile::certs(&mut &pem[..]).context("invalid PEM certificates")?;
+    ensure!(!crts.is_empty(), "no PEM certificates found");
+    Ok(crts)
+}
+
+/// Returns the DER-encoded certificate `crt`, which may be PEM-encoded.
+pub fn certificate(crt: &[u8]) -> Result<Vec<u8>> {
+    if crt.starts_with(b"-----BEGIN") {
+        let mut crts = certificates(crt)?;
+        ensure!(crts.len() == 1, "expected a single certificate");
+        Ok(crts.remove(0))
+    } else {
+        Ok(crt.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    const CHAIN: &[u8] = include_bytes!("../backend/sev/snp/testdata/chain.pem");
+    const VCEK: &[u8] = include_bytes!("../backend/sev/snp/testdata/vcek.der");
+
+    #[test]
+    fn chain() {
+        let mut chain = vec![VCEK.to_vec()];
+        chain.extend(certificates(CHAIN).unwrap());
+        assert_eq!(chain.len(), 3);
+        let root = chain[2].clone();
+
+        let valid = Certificate::from_der(VCEK)
+            .unwrap()
+            .tbs_certificate
+            .validity
+            .not_before
+            .to_system_time()
+            + Duration::from_secs(60);
+        verify_chain(&chain, &root, valid).unwrap();
+
+        // The root is the trust anchor, not any self-signed certificate.
+        assert!(verify_chain(&chain, &chain[1], valid).is_err());
+        assert!(verify_chain(&chain, &root, SystemTime::UNIX_EPOCH).is_err());
+
+        // The ASK is not signed by the VCEK.
+        let swapped = vec![chain[1].clone(), chain[0].clone(), root.clone()];
+        assert!(verify_chain(&swapped, &root, valid).is_err());
+
+        // Only CAs issue certificates, the VCEK is none.
+        let crts = chain
+            .iter()
+            .map(|crt| Certificate::from_der(crt).unwrap())
+            .collect::<Vec<_>>();
+        assert!(!is_ca(&crts[0]));
+        assert!(is_ca(&crts[1]));
+        assert!(is_ca(&crts[2]));
+    }
+
+    #[test]
+    fn status() {
+        let mut verified = Verified {
+            technology: "sgx",
+            measurement: vec![],
+            signer: vec![],
+            report_data: vec![],
+            debug: false,
+            unchecked: &["tcb_info"],
+        };
+        assert_eq!(verified.status(), "signature_only");
+        verified.unchecked = &[];
+        assert_eq!(verified.status(), "verified");
+    }
+}
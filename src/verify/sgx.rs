@@ -0,0 +1,242 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Verification of SGX ECDSA quotes
+//!
+//! The evidence of an SGX keep is a version 3 quote, in which the report of the keep is signed
+//! by an attestation key of the Quoting Enclave. The Quoting Enclave binds the attestation key
+//! to its own report, which is signed by the PCK of the platform. The PCK certificate chain up to
+//! the root CA of Intel is part of the quote. The Quoting Enclave must be the one of Intel, i.e.
+//! signed by Intel as product 1, and cannot be debugged.
+//!
+//! The collateral of the PCS of Intel is not checked: neither the TCB info, whether the TCB of the
+//! platform is up to date, nor the QE identity, whether the Quoting Enclave is up to date, nor the
+//! CRLs of the PCK certificate chain.
+
+use super::{certificates, verify_chain, Verified};
+
+use std::time::SystemTime;
+
+use anyhow::{anyhow, ensure, Context, Result};
+use ring::digest::{digest, SHA256};
+use ring::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_FIXED};
+use x509_cert::der::Decode;
+use x509_cert::Certificate;
+
+/// The collateral, which is not checked
+const UNCHECKED: &[&str] = &["tcb_info", "qe_identity", "crl"];
+
+/// Size of the quote header
+const HEADER_SIZE: usize = 48;
+/// Size of an enclave report body
+const BODY_SIZE: usize = 384;
+/// Quote version 3
+const VERSION: u16 = 3;
+/// ECDSA P-256 attestation key
+const ATT_KEY_TYPE_ECDSA_P256: u16 = 2;
+/// Certification data consisting of the PEM-encoded PCK certificate chain
+const CERT_DATA_PCK_CHAIN: u16 = 5;
+/// The bit of the enclave attributes allowing to debug the enclave
+const ATTRIBUTES_DEBUG: u8 = 1 << 1;
+/// `MRSIGNER` of the Quoting Enclave of Intel
+const QE_MRSIGNER: [u8; 32] = [
+    0x8c, 0x4f, 0x57, 0x75, 0xd7, 0x96, 0x50, 0x3e, 0x96, 0x13, 0x7f, 0x77, 0xc6, 0x8a, 0x82, 0x9a,
+    0x00, 0x56, 0xac, 0x8d, 0xed, 0x70, 0x14, 0x0b, 0x08, 0x1b, 0x09, 0x44, 0x90, 0xc5, 0x7b, 0xff,
+];
+/// `ISVPRODID` of the Quoting Enclave of Intel
+const QE_ISVPRODID: u16 = 1;
+
+/// Reads `n` bytes at the front of `buf` and advances it.
+fn take<'a>(buf: &mut &'a [u8], n: usize) -> Result<&'a [u8]> {
+    ensure!(buf.len() >= n, "truncated SGX quote");
+    let (head, tail) = buf.split_at(n);
+    *buf = tail;
+    Ok(head)
+}
+
+fn u16_le(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes(bytes.try_into().unwrap())
+}
+
+/// The fields of a quote, which are verified
+struct Quote<'a> {
+    /// The header and the report body of the keep, which are signed with the attestation key
+    signed: &'a [u8],
+    body: &'a [u8],
+    signature: &'a [u8],
+    attestation_key: &'a [u8],
+    qe_body: &'a [u8],
+    qe_signature: &'a [u8],
+    qe_auth: &'a [u8],
+    pck_chain: &'a [u8],
+}
+
+impl<'a> Quote<'a> {
+    fn parse(quote: &'a [u8]) -> Result<Self> {
+        let mut buf = quote;
+        let header = take(&mut buf, HEADER_SIZE)?;
+        let version = u16_le(&header[0..2]);
+        ensure!(
+            version == VERSION,
+            "unsupported SGX quote version {version}"
+        );
+        let key_type = u16_le(&header[2..4]);
+        ensure!(
+            key_type == ATT_KEY_TYPE_ECDSA_P256,
+            "unsupported SGX attestation key type {key_type}"
+        );
+        let body = take(&mut buf, BODY_SIZE)?;
+        let signed = &quote[..HEADER_SIZE + BODY_SIZE];
+
+        let len = u32::from_le_bytes(take(&mut buf, 4)?.try_into()?) as usize;
+        let mut data = take(&mut buf, len)?;
+        let signature = take(&mut data, 64)?;
+        let attestation_key = take(&mut data, 64)?;
+        let qe_body = take(&mut data, BODY_SIZE)?;
+        let qe_signature = take(&mut data, 64)?;
+        let len = u16_le(take(&mut data, 2)?) as usize;
+        let qe_auth = take(&mut data, len)?;
+        let typ = u16_le(take(&mut data, 2)?);
+        ensure!(
+            typ == CERT_DATA_PCK_CHAIN,
+            "unsupported SGX quote certification data type {typ}"
+        );
+        let len = u32::from_le_bytes(take(&mut data, 4)?.try_into()?) as usize;
+        let pck_chain = take(&mut data, len)?;
+
+        Ok(Self {
+            signed,
+            body,
+            signature,
+            attestation_key,
+            qe_body,
+            qe_signature,
+            qe_auth,
+            pck_chain,
+        })
+    }
+
+    /// Verifies the signatures of the quote, the PCK being the DER-encoded P-256 public key `pck`.
+    fn verify(&self, pck: &[u8]) -> Result<()> {
+        UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, pck)
+            .verify(self.qe_body, self.qe_signature)
+            .map_err(|_| anyhow!("Quoting Enclave report is not signed by the PCK"))?;
+
+        // Only the Quoting Enclave of Intel is trusted to sign with the attestation key.
+        ensure!(
+            self.qe_body[128..160] == QE_MRSIGNER,
+            "Quoting Enclave is not signed by Intel"
+        );
+        ensure!(
+            u16_le(&self.qe_body[256..258]) == QE_ISVPRODID,
+            "Quoting Enclave is not the one of Intel"
+        );
+        ensure!(
+            self.qe_body[48] & ATTRIBUTES_DEBUG == 0,
+            "Quoting Enclave can be debugged"
+        );
+
+        // The report data of the Quoting Enclave binds the attestation key.
+        let hash = digest(&SHA256, &[self.attestation_key, self.qe_auth].concat());
+        ensure!(
+            &self.qe_body[320..352] == hash.as_ref(),
+            "attestation key is not bound to the Quoting Enclave report"
+        );
+
+        let key = [&[0x04], self.attestation_key].concat();
+        UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, key)
+            .verify(self.signed, self.signature)
+            .map_err(|_| anyhow!("SGX quote is not signed by the attestation key"))
+    }
+}
+
+/// Verifies the SGX `quote` up to the root CA certificate of Intel, `root`.
+pub fn verify(quote: &[u8], root: &[u8]) -> Result<Verified> {
+    let quote = Quote::parse(quote)?;
+
+    let chain = certificates(quote.pck_chain).context("invalid PCK certificate chain")?;
+    verify_chain(&chain, root, SystemTime::now()).context("invalid PCK certificate chain")?;
+    let pck = Certificate::from_der(&chain[0])?;
+    quote.verify(
+        pck.tbs_certificate
+            .subject_public_key_info
+            .subject_public_key,
+    )?;
+
+    Ok(Verified {
+        technology: "sgx",
+        measurement: quote.body[64..96].to_vec(),
+        signer: quote.body[128..160].to_vec(),
+        report_data: quote.body[320..384].to_vec(),
+        debug: quote.body[48] & ATTRIBUTES_DEBUG != 0,
+        unchecked: UNCHECKED,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+
+    fn key(rng: &SystemRandom) -> EcdsaKeyPair {
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, rng).unwrap();
+        EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref()).unwrap()
+    }
+
+    #[test]
+    fn quote() {
+        let rng = SystemRandom::new();
+        let (pck, att) = (key(&rng), key(&rng));
+        let att_pub = &att.public_key().as_ref()[1..];
+        let auth = b"auth";
+
+        let mut quote = vec![0; HEADER_SIZE + BODY_SIZE];
+        quote[0] = 3;
+        quote[2] = 2;
+        quote[HEADER_SIZE + 48] = ATTRIBUTES_DEBUG;
+        quote[HEADER_SIZE + 64..HEADER_SIZE + 96].copy_from_slice(&[0xBB; 32]);
+        quote[HEADER_SIZE + 320..].copy_from_slice(&[0xAA; 64]);
+
+        let mut qe_body = vec![0; BODY_SIZE];
+        qe_body[128..160].copy_from_slice(&QE_MRSIGNER);
+        qe_body[256..258].copy_from_slice(&QE_ISVPRODID.to_le_bytes());
+        qe_body[320..352].copy_from_slice(digest(&SHA256, &[att_pub, auth].concat()).as_ref());
+
+        let mut data = att.sign(&rng, &quote).unwrap().as_ref().to_vec();
+        data.extend_from_slice(att_pub);
+        data.extend_from_slice(&qe_body);
+        data.extend_from_slice(pck.sign(&rng, &qe_body).unwrap().as_ref());
+        data.extend_from_slice(&(auth.len() as u16).to_le_bytes());
+        data.extend_from_slice(auth);
+        data.extend_from_slice(&CERT_DATA_PCK_CHAIN.to_le_bytes());
+        data.extend_from_slice(&4u32.to_le_bytes());
+        data.extend_from_slice(b"none");
+        quote.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        quote.extend_from_slice(&data);
+
+        let parsed = Quote::parse(&quote).unwrap();
+        assert_eq!(parsed.body[64..96], [0xBB; 32]);
+        assert_eq!(parsed.pck_chain, b"none");
+        parsed.verify(pck.public_key().as_ref()).unwrap();
+        assert!(parsed.verify(att.public_key().as_ref()).is_err());
+
+        // Another enclave cannot act as the Quoting Enclave, even if its report is signed.
+        let qe = HEADER_SIZE + BODY_SIZE + 4 + 128;
+        for (offset, byte) in [(128, 0x8d), (256, 2), (48, ATTRIBUTES_DEBUG)] {
+            let mut forged = quote.clone();
+            forged[qe + offset] = byte;
+            let qe_body = forged[qe..qe + BODY_SIZE].to_vec();
+            let qe_signature = pck.sign(&rng, &qe_body).unwrap();
+            forged[qe + BODY_SIZE..qe + BODY_SIZE + 64].copy_from_slice(qe_signature.as_ref());
+            let forged = Quote::parse(&forged).unwrap();
+            assert!(forged.verify(pck.public_key().as_ref()).is_err());
+        }
+
+        // Any change to the report of the keep invalidates the signature.
+        quote[HEADER_SIZE + 64] ^= 1;
+        let tampered = Quote::parse(&quote).unwrap();
+        assert!(tampered.verify(pck.public_key().as_ref()).is_err());
+
+        assert!(Quote::parse(&quote[..HEADER_SIZE + BODY_SIZE + 100]).is_err());
+    }
+}
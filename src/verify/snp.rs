@@ -0,0 +1,235 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Verification of SEV-SNP attestation reports
+//!
+//! The evidence of an SEV-SNP keep is the DER encoding of
+//! `SEQUENCE { vcek Certificate, report OCTET STRING }`, where `report` is the attestation report
+//! of the keep signed by the VCEK of its platform. The VCEK is issued by the ASK, which is issued
+//! by the ARK of AMD for the product line of the processor, e.g. Milan, which is pinned.
+//!
+//! The VCEK is derived from the TCB of the platform, but the CRL of the ASK is not checked.
+
+use super::{certificate, verify_chain, Verified};
+
+use std::time::SystemTime;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use ring::signature::{UnparsedPublicKey, ECDSA_P384_SHA384_FIXED};
+use x509_cert::der::asn1::{ObjectIdentifier, OctetStringRef};
+use x509_cert::der::{Encode, Reader, SliceReader};
+use x509_cert::Certificate;
+
+/// The ARKs of AMD pinned by product line, the trust anchors of the VCEK certificate chains
+const ARKS: &[(&str, &[u8])] = &[("Milan", include_bytes!("ark/milan.pem"))];
+
+/// The OID of the common name attribute, which names the product line in the issuer of a VCEK
+const COMMON_NAME: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.5.4.3");
+
+/// The collateral, which is not checked
+const UNCHECKED: &[&str] = &["crl"];
+
+/// Size of an attestation report
+const REPORT_SIZE: usize = 0x4A0;
+/// Size of the signed part of an attestation report, which the signature follows
+const SIGNED_SIZE: usize = 0x2A0;
+/// ECDSA P-384 with SHA-384, the only signature algorithm of attestation reports
+const SIG_ALGO_ECDSA_P384_SHA384: u32 = 1;
+/// The bit of the guest policy allowing to debug the keep
+const POLICY_DEBUG: u64 = 1 << 19;
+
+/// The fields of an attestation report, which are verified
+#[derive(Debug)]
+struct Report<'a> {
+    version: u32,
+    policy: u64,
+    sig_algo: u32,
+    report_data: &'a [u8],
+    measurement: &'a [u8],
+    id_key_digest: &'a [u8],
+    signed: &'a [u8],
+    /// The little-endian `R` and `S` components, zero-extended to 72 bytes
+    r: &'a [u8],
+    s: &'a [u8],
+}
+
+impl<'a> Report<'a> {
+    fn parse(report: &'a [u8]) -> Result<Self> {
+        ensure!(
+            report.len() == REPORT_SIZE,
+            "invalid attestation report size {}",
+            report.len()
+        );
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(report[offset..offset + 4].try_into().unwrap());
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(report[offset..offset + 8].try_into().unwrap());
+        Ok(Self {
+            version: u32_at(0x00),
+            policy: u64_at(0x08),
+            sig_algo: u32_at(0x34),
+            report_data: &report[0x50..0x90],
+            measurement: &report[0x90..0xC0],
+            id_key_digest: &report[0xE0..0x110],
+            signed: &report[..SIGNED_SIZE],
+            r: &report[0x2A0..0x2E8],
+            s: &report[0x2E8..0x330],
+        })
+    }
+
+    /// Verifies the signature of the report with the DER-encoded P-384 public key `vcek`.
+    fn verify(&self, vcek: &[u8]) -> Result<()> {
+        ensure!(
+            self.sig_algo == SIG_ALGO_ECDSA_P384_SHA384,
+            "unsupported attestation report signature algorithm {}",
+            self.sig_algo
+        );
+        // The components are little-endian, zero-extended from 48 to 72 bytes.
+        let mut signature = Vec::with_capacity(96);
+        for component in [self.r, self.s] {
+            ensure!(
+                component[48..].iter().all(|b| *b == 0),
+                "invalid attestation report signature"
+            );
+            signature.extend(component[..48].iter().rev());
+        }
+        UnparsedPublicKey::new(&ECDSA_P384_SHA384_FIXED, vcek)
+            .verify(self.signed, &signature)
+            .map_err(|_| anyhow!("attestation report is not signed by the VCEK"))
+    }
+}
+
+/// Returns the VCEK and the attestation report of the SEV-SNP `evidence`.
+fn parse(evidence: &[u8]) -> Result<(Certificate<'_>, OctetStringRef<'_>)> {
+    SliceReader::new(evidence)
+        .and_then(|mut reader| {
+            let decoded = reader.sequence(|reader| {
+                let vcek: Certificate<'_> = reader.decode()?;
+                let report: OctetStringRef<'_> = reader.decode()?;
+                Ok((vcek, report))
+            })?;
+            reader.finish(decoded)
+        })
+        .context("invalid SEV-SNP evidence")
+}
+
+/// Returns the product line of the processor, which signed the SEV-SNP `evidence`, e.g. `Milan`,
+/// as named by the ASK issuing its VCEK.
+pub fn product(evidence: &[u8]) -> Result<String> {
+    let (vcek, _) = parse(evidence)?;
+    vcek.tbs_certificate
+        .issuer
+        .0
+        .iter()
+        .flat_map(|rdn| rdn.0.iter())
+        .find(|atv| atv.oid == COMMON_NAME)
+        .and_then(|atv| {
+            atv.value
+                .printable_string()
+                .map(|cn| cn.as_str())
+                .or_else(|_| atv.value.utf8_string().map(|cn| cn.as_str()))
+                .ok()
+        })
+        .and_then(|cn| cn.strip_prefix("SEV-"))
+        .map(String::from)
+        .ok_or_else(|| anyhow!("the VCEK is not issued by an ASK of AMD"))
+}
+
+/// Returns the URL of the certificate chain of the ASK and the ARK of `product`.
+pub fn chain_url(product: &str) -> String {
+    format!("https://kdsintf.amd.com/vcek/v1/{product}/cert_chain")
+}
+
+/// Returns the DER-encoded ARK of `product` pinned by Enarx, if any.
+pub fn ark(product: &str) -> Result<Option<Vec<u8>>> {
+    ARKS.iter()
+        .find(|(name, _)| *name == product)
+        .map(|(_, pem)| certificate(pem))
+        .transpose()
+}
+
+/// Verifies the SEV-SNP `evidence` with the chain of the ASK and the ARK, `root`.
+pub fn verify(evidence: &[u8], chain: &[Vec<u8>], root: &[u8]) -> Result<Verified> {
+    let (vcek, report) = parse(evidence)?;
+
+    let mut crts = vec![vcek.to_vec()?];
+    crts.extend_from_slice(chain);
+    verify_chain(&crts, root, SystemTime::now()).context("invalid VCEK certificate chain")?;
+
+    let report = Report::parse(report.as_bytes())?;
+    if report.version < 2 {
+        bail!("unsupported attestation report version {}", report.version);
+    }
+    report.verify(
+        vcek.tbs_certificate
+            .subject_public_key_info
+            .subject_public_key,
+    )?;
+
+    Ok(Verified {
+        technology: "sev",
+        measurement: report.measurement.to_vec(),
+        signer: report.id_key_digest.to_vec(),
+        report_data: report.report_data.to_vec(),
+        debug: report.policy & POLICY_DEBUG != 0,
+        unchecked: UNCHECKED,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P384_SHA384_FIXED_SIGNING};
+
+    #[test]
+    fn report() {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P384_SHA384_FIXED_SIGNING, &rng).unwrap();
+        let key =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P384_SHA384_FIXED_SIGNING, pkcs8.as_ref()).unwrap();
+
+        let mut report = vec![0; REPORT_SIZE];
+        report[0x00] = 2;
+        report[0x08..0x10].copy_from_slice(&(POLICY_DEBUG | 0x30000).to_le_bytes());
+        report[0x34] = 1;
+        report[0x50..0x90].copy_from_slice(&[0xAA; 64]);
+        report[0x90..0xC0].copy_from_slice(&[0xBB; 48]);
+        let signature = key.sign(&rng, &report[..SIGNED_SIZE]).unwrap();
+        let (r, s) = signature.as_ref().split_at(48);
+        report[0x2A0..0x2D0].copy_from_slice(&r.iter().rev().copied().collect::<Vec<_>>());
+        report[0x2E8..0x318].copy_from_slice(&s.iter().rev().copied().collect::<Vec<_>>());
+
+        let parsed = Report::parse(&report).unwrap();
+        assert_eq!(parsed.measurement, [0xBB; 48]);
+        assert_eq!(parsed.report_data, [0xAA; 64]);
+        assert_ne!(parsed.policy & POLICY_DEBUG, 0);
+        parsed.verify(key.public_key().as_ref()).unwrap();
+
+        // Any change to the signed part invalidates the signature.
+        report[0x90] ^= 1;
+        let tampered = Report::parse(&report).unwrap();
+        assert!(tampered.verify(key.public_key().as_ref()).is_err());
+
+        assert!(Report::parse(&report[..SIGNED_SIZE]).is_err());
+    }
+
+    #[test]
+    fn pinned() {
+        const CHAIN: &[u8] = include_bytes!("../backend/sev/snp/testdata/chain.pem");
+        const VCEK: &[u8] = include_bytes!("../backend/sev/snp/testdata/vcek.der");
+
+        // The product is named by the issuer of the VCEK, regardless of the report.
+        let content = [VCEK, &[0x04, 0x00]].concat();
+        let len = u16::try_from(content.len()).unwrap().to_be_bytes();
+        let evidence = [&[0x30, 0x82], &len[..], &content].concat();
+        assert_eq!(product(&evidence).unwrap(), "Milan");
+
+        let chain = super::super::certificates(CHAIN).unwrap();
+        assert_eq!(ark("Milan").unwrap().as_ref(), chain.last());
+        assert_eq!(ark("Unknown").unwrap(), None);
+        assert_eq!(
+            chain_url("Genoa"),
+            "https://kdsintf.amd.com/vcek/v1/Genoa/cert_chain"
+        );
+    }
+}
@@ -0,0 +1,46 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#![no_std]
+#![no_main]
+#![feature(naked_functions, asm_sym)]
+
+enarx_syscall_tests::startup!();
+
+use enarx_syscall_tests::*;
+
+use sallyport::item::syscall::{ARCH_GET_FS, ARCH_GET_GS, ARCH_SET_FS, ARCH_SET_GS};
+
+/// Sets the base with `set`, reads it back with `get` and restores the original value.
+fn check_base(set: i32, get: i32) -> Result<()> {
+    let mut orig: u64 = 0;
+    arch_prctl(get, &mut orig as *mut _ as _)?;
+
+    let base = 0x7f00_dead_b000;
+    arch_prctl(set, base)?;
+
+    let mut current: u64 = 0;
+    let res = arch_prctl(get, &mut current as *mut _ as _);
+
+    arch_prctl(set, orig)?;
+    res?;
+
+    if current != base {
+        return Err(1);
+    }
+
+    // Addresses outside of the user address space must be rejected.
+    match arch_prctl(set, 0x8000_0000_0000_0000) {
+        Err(libc::EPERM) => Ok(()),
+        _ => Err(2),
+    }
+}
+
+fn main() -> Result<()> {
+    check_base(ARCH_SET_FS, ARCH_GET_FS)?;
+    check_base(ARCH_SET_GS, ARCH_GET_GS)?;
+
+    match arch_prctl(0xffff, 0) {
+        Err(libc::EINVAL) => Ok(()),
+        _ => Err(3),
+    }
+}
@@ -508,3 +508,21 @@ pub fn recvfrom(
         Err(-ret as i32)
     }
 }
+
+pub fn arch_prctl(code: i32, addr: u64) -> Result<()> {
+    let ret = syscall(
+        libc::SYS_arch_prctl,
+        Args {
+            arg0: code as _,
+            arg1: addr as _,
+            ..Default::default()
+        },
+    )
+    .0 as isize;
+
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(-ret as i32)
+    }
+}
@@ -22,6 +22,13 @@ fn main() -> std::io::Result<()> {
     assert_eq!(fd_names, "stdin:stdout:stderr:LISTEN:CONNECT");
     assert_eq!(fd_count, 5);
 
+    let fd_ports = std::env::var("FD_PORTS").expect("No FD_PORTS");
+    let fd_ports: Vec<_> = fd_ports.split(':').collect();
+    assert_eq!(fd_ports.len(), 5);
+    assert!(fd_ports[3].parse::<u16>().is_ok());
+    assert_eq!(fd_ports[..3], ["", "", ""]);
+    assert_eq!(fd_ports[4], "");
+
     // Set up the environment sockets.
     let connect = unsafe { TcpStream::from_raw_fd(4) };
     let listen = unsafe { TcpListener::from_raw_fd(3) };
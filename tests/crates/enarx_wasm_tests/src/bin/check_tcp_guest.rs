@@ -0,0 +1,46 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io::{BufRead, BufReader, Write};
+
+use enarx_guest::{Files, ABI};
+
+fn main() -> std::io::Result<()> {
+    let fd_names = std::env::var("FD_NAMES").expect("No FD_NAMES");
+    assert_eq!(fd_names, "stdin:stdout:stderr:LISTEN:CONNECT");
+
+    let mut files = Files::from_env().expect("invalid environment");
+    assert_eq!(files.abi(), Some(ABI));
+    assert_eq!(files.fds().len(), 5);
+    let kinds: Vec<_> = files.fds().iter().map(|fd| fd.kind.as_deref()).collect();
+    assert_eq!(
+        kinds,
+        [
+            Some("stdin"),
+            Some("stdout"),
+            Some("stderr"),
+            Some("listen"),
+            Some("connect")
+        ]
+    );
+    let ports: Vec<_> = files.fds().iter().map(|fd| fd.port).collect();
+    assert!(ports[3].is_some());
+    assert_eq!(ports[..3], [None, None, None]);
+    assert_eq!(ports[4], None);
+
+    // Set up the environment sockets.
+    let connect = files.stream("CONNECT")?;
+    let listen = files.listener("LISTEN")?;
+
+    // Accept the incoming connection.
+    let mut socket = listen.accept().unwrap().0;
+
+    // Output all incoming lines to the output.
+    let reader = BufReader::new(connect);
+    for line in reader.lines() {
+        let line = line.unwrap();
+        socket.write_all(line.as_bytes()).unwrap();
+        socket.write_all(b"\n").unwrap();
+    }
+
+    Ok(())
+}
@@ -0,0 +1,18 @@
+;;; SPDX-License-Identifier: Apache-2.0
+
+;;; Exit with the number of command-line arguments
+(module
+  (import "wasi_snapshot_preview1" "args_sizes_get"
+    (func $__wasi_args_sizes_get (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "proc_exit"
+    (func $__wasi_proc_exit (param i32)))
+  (func (export "_start")
+    (i32.store (i32.const 0) (i32.const 0))
+    (i32.store (i32.const 4) (i32.const 0))
+    (call $__wasi_args_sizes_get (i32.const 0) (i32.const 4))
+    drop
+    (call $__wasi_proc_exit (i32.load (i32.const 0)))
+  )
+  (memory 1)
+  (export "memory" (memory 0))
+)
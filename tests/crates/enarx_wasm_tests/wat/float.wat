@@ -0,0 +1,75 @@
+;;; SPDX-License-Identifier: Apache-2.0
+
+;;; Print the bit patterns of floating-point results, which must be identical on every backend
+;;;
+;;; The operations are wrapped in functions taking their operands as parameters, such that they
+;;; cannot be folded at compile time.
+(module
+  (import "wasi_snapshot_preview1" "fd_write"
+    (func $__wasi_fd_write (param i32 i32 i32 i32) (result i32)))
+
+  ;; Length of the output buffer at 1024
+  (global $len (mut i32) (i32.const 0))
+
+  ;; Appends the `digits` lowest hex digits of `v` and a newline to the output buffer.
+  (func $hex (param $v i64) (param $digits i32)
+    (local $i i32)
+    (local.set $i (local.get $digits))
+    (block $done
+      (loop $next
+        (br_if $done (i32.eqz (local.get $i)))
+        (local.set $i (i32.sub (local.get $i) (i32.const 1)))
+        (i32.store8
+          (i32.add (i32.add (i32.const 1024) (global.get $len)) (local.get $i))
+          (i32.load8_u (i32.wrap_i64 (i64.and (local.get $v) (i64.const 15)))))
+        (local.set $v (i64.shr_u (local.get $v) (i64.const 4)))
+        (br $next)))
+    (global.set $len (i32.add (global.get $len) (local.get $digits)))
+    (i32.store8 (i32.add (i32.const 1024) (global.get $len)) (i32.const 10))
+    (global.set $len (i32.add (global.get $len) (i32.const 1))))
+
+  (func $f64 (param $v f64)
+    (call $hex (i64.reinterpret_f64 (local.get $v)) (i32.const 16)))
+  (func $f32 (param $v f32)
+    (call $hex (i64.extend_i32_u (i32.reinterpret_f32 (local.get $v))) (i32.const 8)))
+
+  (func $f64_add (param f64 f64) (result f64) (f64.add (local.get 0) (local.get 1)))
+  (func $f64_mul (param f64 f64) (result f64) (f64.mul (local.get 0) (local.get 1)))
+  (func $f64_div (param f64 f64) (result f64) (f64.div (local.get 0) (local.get 1)))
+  (func $f64_min (param f64 f64) (result f64) (f64.min (local.get 0) (local.get 1)))
+  (func $f64_sqrt (param f64) (result f64) (f64.sqrt (local.get 0)))
+  (func $f64_nearest (param f64) (result f64) (f64.nearest (local.get 0)))
+  (func $f32_mul (param f32 f32) (result f32) (f32.mul (local.get 0) (local.get 1)))
+  (func $f32_div (param f32 f32) (result f32) (f32.div (local.get 0) (local.get 1)))
+  (func $f32_min (param f32 f32) (result f32) (f32.min (local.get 0) (local.get 1)))
+  (func $f32_demote (param f64) (result f32) (f32.demote_f64 (local.get 0)))
+
+  (func (export "_start")
+    ;; NaN results, canonical with `canonicalize_nans = true`
+    (call $f64 (call $f64_div (f64.const 0) (f64.const 0)))
+    (call $f64 (call $f64_sqrt (f64.const -1)))
+    (call $f32 (call $f32_div (f32.const 0) (f32.const 0)))
+    (call $f32 (call $f32_min (f32.const nan:0x200000) (f32.const 1)))
+
+    ;; Subnormal results and operands are not flushed to zero.
+    (call $f64 (call $f64_div (f64.const 0x1p-1022) (f64.const 2)))
+    (call $f64 (call $f64_mul (f64.reinterpret_i64 (i64.const 1)) (f64.const 1)))
+    (call $f32 (call $f32_div (f32.const 0x1p-126) (f32.const 2)))
+    (call $f32 (call $f32_mul (f32.reinterpret_i32 (i32.const 1)) (f32.const 1)))
+
+    ;; Rounding, signed zeros and overflow
+    (call $f64 (call $f64_add (f64.const 0.1) (f64.const 0.2)))
+    (call $f64 (call $f64_nearest (f64.const 2.5)))
+    (call $f64 (call $f64_min (f64.const -0) (f64.const 0)))
+    (call $f64 (call $f64_mul (f64.const 1e308) (f64.const 10)))
+    (call $f32 (call $f32_demote (f64.const 0.1)))
+
+    ;; Write the output buffer to stdout.
+    (i32.store (i32.const 16) (i32.const 1024))
+    (i32.store (i32.const 20) (global.get $len))
+    (drop (call $__wasi_fd_write (i32.const 1) (i32.const 16) (i32.const 1) (i32.const 24))))
+
+  (memory 1)
+  (export "memory" (memory 0))
+  (data (i32.const 0) "0123456789abcdef")
+)
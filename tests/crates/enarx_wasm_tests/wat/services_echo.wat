@@ -0,0 +1,24 @@
+;;; SPDX-License-Identifier: Apache-2.0
+
+;;; Write everything read from the channel on fd 0 back to it.
+(module
+  (import "wasi_snapshot_preview1" "fd_read"
+    (func $__wasi_fd_read (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write"
+    (func $__wasi_fd_write (param i32 i32 i32 i32) (result i32)))
+  (func $_start
+    (loop $echo
+      (i32.store (i32.const 0) (i32.const 16))
+      (i32.store (i32.const 4) (i32.const 64))
+      (br_if 1 (call $__wasi_fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)))
+      (br_if 1 (i32.eqz (i32.load (i32.const 8))))
+
+      (i32.store (i32.const 4) (i32.load (i32.const 8)))
+      (br_if 1 (call $__wasi_fd_write (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)))
+      (br $echo)
+    )
+  )
+  (memory 1)
+  (export "memory" (memory 0))
+  (export "_start" (func $_start))
+)
@@ -0,0 +1,25 @@
+;;; SPDX-License-Identifier: Apache-2.0
+
+;;; Write "Hello, service!\n" to the channel on fd 1 and the reply to stdout on fd 0.
+(module
+  (import "wasi_snapshot_preview1" "fd_read"
+    (func $__wasi_fd_read (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write"
+    (func $__wasi_fd_write (param i32 i32 i32 i32) (result i32)))
+  (func $_start
+    (i32.store (i32.const 16) (i32.const 0))
+    (i32.store (i32.const 20) (i32.const 16))
+    (drop (call $__wasi_fd_write (i32.const 1) (i32.const 16) (i32.const 1) (i32.const 24)))
+
+    (i32.store (i32.const 16) (i32.const 32))
+    (i32.store (i32.const 20) (i32.const 64))
+    (drop (call $__wasi_fd_read (i32.const 1) (i32.const 16) (i32.const 1) (i32.const 24)))
+
+    (i32.store (i32.const 20) (i32.load (i32.const 24)))
+    (drop (call $__wasi_fd_write (i32.const 0) (i32.const 16) (i32.const 1) (i32.const 24)))
+  )
+  (memory 1)
+  (export "memory" (memory 0))
+  (export "_start" (func $_start))
+  (data (i32.const 0) "Hello, service!\0a")
+)
@@ -5,10 +5,10 @@
 #[cfg(not(windows))]
 mod client;
 
-#[cfg(enarx_with_shim)]
+#[cfg(all(enarx_with_shim, not(enarx_backend_nil)))]
 mod exec;
 
-#[cfg(enarx_with_shim)]
+#[cfg(all(enarx_with_shim, not(enarx_backend_nil)))]
 mod syscall;
 
 mod wasm;
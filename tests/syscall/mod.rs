@@ -30,6 +30,13 @@ fn exit_one() {
     run_test(bin, 1, None, None, None);
 }
 
+#[test]
+#[serial]
+fn arch_prctl() {
+    let bin = env!("CARGO_BIN_FILE_ENARX_SYSCALL_TESTS_arch_prctl");
+    run_test(bin, 0, None, None, None);
+}
+
 #[test]
 #[serial]
 fn clock_gettime() {
@@ -2,13 +2,15 @@
 
 use super::{check_output, CRATE, KEEP_BIN, OUT_DIR, TEST_BINS_OUT, TIMEOUT_SECS};
 
-use std::io::{stderr, Read, Write};
+use std::ffi::OsStr;
+use std::io::{stderr, BufRead, BufReader, Read, Write};
 use std::net::{Ipv4Addr, TcpListener, TcpStream};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::time;
 use std::{fs, thread};
 
+use drawbridge_client::types::digest::Algorithms;
 use process_control::{ChildExt, Control, Output};
 use serial_test::serial;
 use tempfile::tempdir;
@@ -68,7 +70,7 @@ fn enarx<'a>(
 
 #[cfg(not(enarx_with_shim))]
 pub fn enarx_run<'a>(
-    wasm: &Path,
+    wasm: impl AsRef<OsStr>,
     conf: Option<&Path>,
     input: impl Into<Option<&'a [u8]>>,
 ) -> Output {
@@ -87,7 +89,7 @@ pub fn enarx_run<'a>(
 
 #[cfg(enarx_with_shim)]
 pub fn enarx_run<'a>(
-    wasm: &Path,
+    wasm: impl AsRef<OsStr>,
     conf: Option<&Path>,
     input: impl Into<Option<&'a [u8]>>,
 ) -> Output {
@@ -143,6 +145,30 @@ pub fn enarx_run<'a>(
 //    enarx(|cmd| cmd.arg("deploy").arg(url.as_str()), input)
 //}
 
+/// Serves `wasm` over HTTP once and returns its URL.
+fn serve(wasm: Vec<u8>) -> String {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let url = format!("http://{}/main.wasm", listener.local_addr().unwrap());
+    let (_, digest) = Algorithms::default().read_sync(&wasm[..]).unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        while line != "\r\n" {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+        }
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/wasm\r\nContent-Length: {}\r\nContent-Digest: {digest}\r\n\r\n",
+            wasm.len()
+        )
+        .unwrap();
+        stream.write_all(&wasm).unwrap();
+    });
+    url
+}
+
 fn wasm_out() -> PathBuf {
     Path::new(CRATE).join(OUT_DIR).join(TEST_BINS_OUT)
 }
@@ -207,6 +233,23 @@ fn wasi_snapshot1() {
     check_output(&enarx_run(&wasm, None, None), 0, None, None);
 }
 
+#[test]
+#[serial]
+fn exit_code() {
+    // This module exits with the number of commandline args, which is the exit code of the keep.
+    let wasm = compile("exit_argc.wasm");
+    check_output(&enarx_run(&wasm, None, None), 1, None, None);
+}
+
+#[test]
+#[serial]
+fn rundev() {
+    // `rundev` passes the arguments following the module, including ones looking like options.
+    let wasm = compile("exit_argc.wasm");
+    let out = enarx(|cmd| cmd.arg("rundev").arg(&wasm).args(["a", "--b"]), None);
+    check_output(&out, 3, None, None);
+}
+
 #[test]
 #[serial]
 fn hello_wasi_snapshot1() {
@@ -217,6 +260,45 @@ fn hello_wasi_snapshot1() {
     check_output(&enarx_run(&wasm, None, None), 0, OUTPUT, None);
 }
 
+#[test]
+#[serial]
+fn float() {
+    // The floating-point results must be bit-identical on every backend, once NaNs are canonical.
+    let wasm = compile("float.wasm");
+    const OUTPUT: &[u8] = br#"7ff8000000000000
+7ff8000000000000
+7fc00000
+7fc00000
+0008000000000000
+0000000000000001
+00400000
+00000001
+3fd3333333333334
+4000000000000000
+8000000000000000
+7ff0000000000000
+3dcccccd
+"#;
+
+    let pkg = tempdir().expect("failed to create temporary package directory");
+    let pkg_wasm = pkg.path().join("main.wasm");
+    let pkg_conf = pkg.path().join("Enarx.toml");
+
+    fs::copy(wasm, &pkg_wasm).expect("failed to copy WASM module");
+    fs::write(
+        &pkg_conf,
+        "[[files]]\nkind = \"stdin\"\n\n[[files]]\nkind = \"stdout\"\n\n[float]\ncanonicalize_nans = true\n",
+    )
+    .expect("failed to write config");
+
+    check_output(
+        &enarx_run(&pkg_wasm, Some(&pkg_conf), None),
+        0,
+        OUTPUT,
+        None,
+    );
+}
+
 #[test]
 #[serial]
 fn no_export() {
@@ -225,6 +307,56 @@ fn no_export() {
     check_output(&enarx_run(&wasm, None, None), 1, None, None);
 }
 
+#[cfg(unix)]
+#[test]
+#[serial]
+fn wasi_conformance() {
+    // The results of the WASI calls inside a keep must match the table published in the docs.
+    // Regenerate the table with `enarx test wasi` after changing the WASI surface.
+    let out = enarx(|cmd| cmd.args(["test", "wasi"]), None);
+    let doc = fs::read(Path::new(CRATE).join("docs/Running/WASI.md")).unwrap();
+    check_output(&out, 0, None, None);
+    assert!(
+        doc.ends_with(&out.stdout),
+        "WASI conformance differs from docs/Running/WASI.md:\n{}",
+        String::from_utf8_lossy(&out.stdout)
+    );
+}
+
+#[cfg(unix)]
+#[test]
+#[serial]
+fn attest() {
+    // The evidence is empty on KVM, and contains the nonce in the report data otherwise.
+    let nonce = b"enarx attest nonce";
+    let hex: String = nonce.iter().map(|b| format!("{b:02x}")).collect();
+    let out = enarx(|cmd| cmd.args(["attest", "--nonce", &hex]), None);
+    check_output(&out, 0, None, None);
+    assert!(out.stdout.is_empty() || out.stdout.windows(nonce.len()).any(|w| w == nonce));
+}
+
+#[cfg(unix)]
+#[test]
+#[serial]
+fn parallel() {
+    let wasm = wasm_path(env!("CARGO_BIN_FILE_ENARX_WASM_TESTS_echo"));
+    let mut harness = enarx_harness::Harness::new(KEEP_BIN).unwrap();
+    if let Ok(backend) = std::env::var("ENARX_BACKEND") {
+        harness = harness.backend(backend);
+    }
+    let keeps = (0..4).map(|i| harness.keep(&wasm).stdin(vec![i; 4096]));
+    for (i, output) in harness.run_all(keeps).into_iter().enumerate() {
+        let output = output.unwrap();
+        assert_eq!(
+            output.code,
+            Some(0),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert_eq!(output.stdout, vec![i as u8; 4096]);
+    }
+}
+
 #[test]
 #[serial]
 fn echo() {
@@ -269,15 +401,28 @@ fn zerooneone() {
     //let url = Url::from_file_path(&wasm).expect("failed to construct a URL from path");
     //check_output(&enarx_deploy(&url, INPUT), 0, OUTPUT, None);
 
-    // TODO: Test execution from a remote HTTP(S) URL
-    // https://github.com/enarx/enarx/issues/1855
+    let url = serve(fs::read(&wasm).expect("failed to read WASM module"));
+    check_output(&enarx_run(&url, None, INPUT), 0, OUTPUT, None);
 }
 
 #[test]
 #[serial]
 fn check_tcp() {
-    let wasm = wasm_path(env!("CARGO_BIN_FILE_ENARX_WASM_TESTS_check_tcp"));
+    tcp(wasm_path(env!("CARGO_BIN_FILE_ENARX_WASM_TESTS_check_tcp")));
+}
 
+/// Like [`check_tcp`], but the module looks the sockets up with the `enarx-guest` crate.
+#[test]
+#[serial]
+fn check_tcp_guest() {
+    tcp(wasm_path(env!(
+        "CARGO_BIN_FILE_ENARX_WASM_TESTS_check_tcp_guest"
+    )));
+}
+
+/// Runs `wasm`, which echoes the lines read from its `CONNECT` socket to the connection accepted
+/// on its `LISTEN` socket.
+fn tcp(wasm: PathBuf) {
     // Create listening sockets (allocate a port).
     let listen = TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
     let lport = listen.local_addr().unwrap().port();
@@ -342,3 +487,45 @@ name = "CONNECT""#
     // TODO: Test execution from a remote HTTP(S) URL
     // https://github.com/enarx/enarx/issues/1855
 }
+
+// Services run on threads, which the shims cannot create yet.
+#[cfg(any(not(enarx_with_shim), enarx_backend_nil))]
+#[test]
+#[serial]
+fn services() {
+    let main = compile("services_main.wasm");
+    let echo = compile("services_echo.wasm");
+
+    let conf = r#"[[files]]
+kind = "stdout"
+
+[[files]]
+name = "ECHO"
+kind = "channel"
+peer = "echo"
+
+[services.echo]
+module = "echo.wasm"
+
+[[services.echo.files]]
+name = "MAIN"
+kind = "channel"
+peer = "main"
+"#;
+
+    let pkg = tempdir().expect("failed to create temporary package directory");
+    let pkg_wasm = pkg.path().join("main.wasm");
+    let pkg_conf = pkg.path().join("Enarx.toml");
+
+    fs::copy(main, &pkg_wasm).expect("failed to copy WASM module");
+    fs::copy(echo, pkg.path().join("echo.wasm")).expect("failed to copy WASM module");
+    fs::write(&pkg_conf, conf).expect("failed to write config");
+
+    const OUTPUT: &[u8] = b"Hello, service!\n";
+    check_output(
+        &enarx_run(&pkg_wasm, Some(&pkg_conf), None),
+        0,
+        OUTPUT,
+        None,
+    );
+}